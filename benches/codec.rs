@@ -0,0 +1,187 @@
+//! Codec micro-benchmarks: varints, packed arrays, the string paths and whole-message
+//! round trips over the shared benchmark scenarios.
+//!
+//! `cargo bench --bench codec` runs them and compares against the previous run.
+//! To gate a change, save a baseline on the target branch
+//! (`-- --save-baseline main`), then on the branch under test run
+//! `BIWI_BENCH_MAX_REGRESSION=0.05 cargo bench --bench codec -- --baseline main`:
+//! it exits non-zero if any benchmark's mean got slower by more than 5% with 95%
+//! confidence.
+
+use biwi::{BiWiDecoder, BiWiEncoder, BiWiMessage, BiWiValue};
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+#[path = "../benchmarks/scenarios.rs"]
+mod scenarios;
+
+/// Encode `value` into a reused encoder, as a sender would per message
+fn encode(encoder: &mut BiWiEncoder, value: &BiWiValue) -> usize {
+    encoder.reset();
+    encoder.encode_value(value);
+    encoder.as_slice().len()
+}
+
+fn encoded(value: &BiWiValue) -> Vec<u8> {
+    let mut encoder = BiWiEncoder::new();
+    encoder.encode_value(value);
+    encoder.to_buffer()
+}
+
+fn decode(bytes: &[u8]) -> BiWiValue {
+    BiWiDecoder::new(bytes).decode_value().unwrap()
+}
+
+fn varints(c: &mut Criterion) {
+    let values = [
+        ("1 byte", BiWiValue::Int32(42)),
+        ("2 bytes", BiWiValue::Int32(-3_000)),
+        ("3 bytes", BiWiValue::Int32(1_000_000)),
+        ("5 bytes", BiWiValue::Int32(i32::MAX)),
+        ("10 bytes", BiWiValue::Int64(i64::MIN)),
+    ];
+    let mut group = c.benchmark_group("varint");
+    let mut encoder = BiWiEncoder::new();
+    for (label, value) in &values {
+        group.bench_with_input(BenchmarkId::new("encode", label), value, |b, value| {
+            b.iter(|| encode(&mut encoder, black_box(value)))
+        });
+        let bytes = encoded(value);
+        group.bench_with_input(BenchmarkId::new("decode", label), &bytes, |b, bytes| b.iter(|| decode(black_box(bytes))));
+    }
+    group.finish();
+}
+
+fn packed_arrays(c: &mut Criterion) {
+    const LEN: usize = 256;
+    let arrays = [
+        ("int32", BiWiValue::Array((0..LEN as i32).map(|i| BiWiValue::Int32(i * 37 - 4_000)).collect())),
+        ("int64", BiWiValue::Array((0..LEN as i64).map(|i| BiWiValue::Int64(i << 40)).collect())),
+        ("float32", BiWiValue::Array((0..LEN).map(|i| BiWiValue::Float32(i as f32 * 0.25)).collect())),
+        ("float64", BiWiValue::Array((0..LEN).map(|i| BiWiValue::Float64(i as f64 * 0.1)).collect())),
+    ];
+    let mut group = c.benchmark_group("packed_array");
+    group.throughput(Throughput::Elements(LEN as u64));
+    let mut encoder = BiWiEncoder::new();
+    for (label, array) in &arrays {
+        group.bench_with_input(BenchmarkId::new("encode", label), array, |b, array| {
+            b.iter(|| encode(&mut encoder, black_box(array)))
+        });
+        let bytes = encoded(array);
+        group.bench_with_input(BenchmarkId::new("decode", label), &bytes, |b, bytes| b.iter(|| decode(black_box(bytes))));
+    }
+    group.finish();
+}
+
+fn strings(c: &mut Criterion) {
+    // Up to 15 bytes takes the inline SmallString path, longer ones the heap path
+    let strings = [("small", "running".to_string()), ("64 bytes", "x".repeat(64)), ("4 KiB", "x".repeat(4096))];
+    let mut group = c.benchmark_group("string");
+    let mut encoder = BiWiEncoder::new();
+    for (label, text) in &strings {
+        let value = BiWiValue::from(text.as_str());
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_with_input(BenchmarkId::new("encode", label), &value, |b, value| {
+            b.iter(|| encode(&mut encoder, black_box(value)))
+        });
+        let bytes = encoded(&value);
+        group.bench_with_input(BenchmarkId::new("decode", label), &bytes, |b, bytes| b.iter(|| decode(black_box(bytes))));
+    }
+    group.finish();
+}
+
+fn messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message");
+    for scenario in scenarios::scenarios() {
+        let mut message = BiWiMessage::new();
+        message.set_field(1, scenarios::json_to_biwi(&scenario.payload));
+        let bytes = message.to_vec();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("encode", scenario.name), &message, |b, message| {
+            b.iter(|| black_box(message).to_vec())
+        });
+        group.bench_with_input(BenchmarkId::new("decode", scenario.name), &bytes, |b, bytes| {
+            b.iter(|| BiWiMessage::from_buffer(black_box(bytes)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("round_trip", scenario.name), &message, |b, message| {
+            b.iter(|| BiWiMessage::from_buffer(&black_box(message).to_vec()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // Report changes only when they are both significant and larger than run-to-run noise
+    config = Criterion::default().significance_level(0.05).noise_threshold(0.02).measurement_time(Duration::from_secs(3));
+    targets = varints, packed_arrays, strings, messages
+}
+
+fn main() -> ExitCode {
+    let started = SystemTime::now();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+
+    let Some(limit) = std::env::var("BIWI_BENCH_MAX_REGRESSION").ok().and_then(|limit| limit.parse::<f64>().ok()) else {
+        return ExitCode::SUCCESS;
+    };
+    let mut regressions = regressions(&criterion_home(), started, limit);
+    regressions.sort_by(|a, b| a.0.cmp(&b.0));
+    for (id, change) in &regressions {
+        eprintln!("regressed: {} is {:.1}% slower", id, change * 100.0);
+    }
+    if regressions.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Where Criterion keeps its results
+fn criterion_home() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return home.into();
+    }
+    let target = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+    target.join("criterion")
+}
+
+/// Benchmarks measured since `since` whose mean slowed down by more than `limit`
+/// (a fraction) even at the low end of the confidence interval, with that change
+fn regressions(dir: &Path, since: SystemTime, limit: f64) -> Vec<(String, f64)> {
+    let mut found = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return found;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            found.extend(regressions(&path, since, limit));
+            continue;
+        }
+        if !path.ends_with("change/estimates.json") {
+            continue;
+        }
+        let fresh = entry.metadata().and_then(|meta| meta.modified()).is_ok_and(|modified| modified >= since);
+        let Some(change) = read_json(&path).filter(|_| fresh) else {
+            continue;
+        };
+        let lower = change["mean"]["confidence_interval"]["lower_bound"].as_f64().unwrap_or(0.0);
+        if lower > limit {
+            let benchmark = path.parent().and_then(Path::parent).map(|dir| dir.join("new/benchmark.json"));
+            let id = benchmark
+                .and_then(|path| read_json(&path))
+                .and_then(|benchmark| benchmark["full_id"].as_str().map(str::to_string))
+                .unwrap_or_else(|| path.display().to_string());
+            found.push((id, change["mean"]["point_estimate"].as_f64().unwrap_or(lower)));
+        }
+    }
+    found
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
@@ -1,13 +1,25 @@
 mod benchmarks;
 
+use benchmarks::allocations::{AllocResult, CountingAllocator};
 use benchmarks::{StatResult, ThroughputResult};
 use benchmarks::udp::UdpNetworkStats;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 
 const RESULTS_CSV: &str = "benchmark_results.csv";
+const ALLOCATIONS_CSV: &str = "benchmark_allocations.csv";
+const USAGE: &str = "usage: benchmark [--scenarios DIR] [--repeat N] [--udp-loss PERCENT]";
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 fn main() {
+    if let Err(err) = parse_args() {
+        eprintln!("{}\n{}", err, USAGE);
+        std::process::exit(2);
+    }
+
     println!("=== BiWi Benchmarks ===");
     println!("May take a while...");
     let (biwi_stats, biwi_tp) = benchmarks::biwi::run_biwi_benchmark();
@@ -25,17 +37,88 @@ fn main() {
     print_stats("JSON", &json_stats);
     print_throughput(&json_tp);
 
-    println!("\n=== Protobuf Benchmarks ===");
-    println!("May take a while...");
-    let (proto_stats, proto_tp) = benchmarks::protobuf::run_protobuf_benchmark();
-    print_stats("Protobuf", &proto_stats);
-    print_throughput(&proto_tp);
+    let mut runners = vec![(
+        "Protobuf",
+        benchmarks::protobuf::run_protobuf_benchmark as Runner,
+        benchmarks::protobuf::run_protobuf_allocations as Profiler,
+    )];
+    runners.extend(format_runners());
+    if benchmarks::custom_scenarios() {
+        println!("\nSkipping Protobuf and the typed formats: they need a schema for each payload");
+        runners.clear();
+    }
+    let mut formats = Vec::new();
+    for &(label, run, _) in &runners {
+        println!("\n=== {} Benchmarks ===", label);
+        println!("May take a while...");
+        let (stats, tp) = run();
+        print_stats(label, &stats);
+        print_throughput(&tp);
+        formats.push((label, stats, tp));
+    }
 
-    if let Err(err) = write_csv(&biwi_stats, &biwi_tp, &udp_stats, &udp_tp, &udp_network_stats, &json_stats, &json_tp, &proto_stats, &proto_tp) {
+    if let Err(err) = write_csv(&biwi_stats, &biwi_tp, &udp_stats, &udp_tp, &udp_network_stats, &json_stats, &json_tp, &formats) {
         eprintln!("Failed to write {}: {}", RESULTS_CSV, err);
     } else {
         println!("\nCSV results written to {}", RESULTS_CSV);
     }
+
+    println!("\n=== Allocations per operation ===");
+    let mut allocations = vec![
+        ("BiWi", benchmarks::biwi::run_biwi_allocations()),
+        ("JSON", benchmarks::json::run_json_allocations()),
+    ];
+    allocations.extend(runners.iter().map(|&(label, _, profile)| (label, profile())));
+    for (label, results) in &allocations {
+        print_allocations(label, results);
+    }
+    if let Err(err) = write_allocations_csv(&allocations) {
+        eprintln!("Failed to write {}: {}", ALLOCATIONS_CSV, err);
+    } else {
+        println!("\nCSV allocations written to {}", ALLOCATIONS_CSV);
+    }
+}
+
+fn parse_args() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scenarios" => {
+                let dir = args.next().ok_or("--scenarios needs a directory")?;
+                benchmarks::set_scenarios(benchmarks::load_scenarios(Path::new(&dir))?);
+            }
+            "--repeat" => {
+                let count = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+                benchmarks::set_repeat(count.ok_or("--repeat needs a positive count")?);
+            }
+            "--udp-loss" => {
+                let percent = args.next().and_then(|p| p.parse().ok()).filter(|p| (0.0..100.0).contains(p));
+                benchmarks::udp::set_loss_percent(percent.ok_or("--udp-loss needs a percentage below 100")?);
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(())
+}
+
+type Runner = fn() -> (Vec<StatResult>, ThroughputResult);
+type Profiler = fn() -> Vec<AllocResult>;
+
+/// The formats beyond Protobuf, built with the `bench-formats` feature
+#[cfg(feature = "bench-formats")]
+fn format_runners() -> Vec<(&'static str, Runner, Profiler)> {
+    use benchmarks::{bincode, cbor, flatbuffers, msgpack};
+    vec![
+        ("MessagePack", msgpack::run_msgpack_benchmark as Runner, msgpack::run_msgpack_allocations as Profiler),
+        ("CBOR", cbor::run_cbor_benchmark, cbor::run_cbor_allocations),
+        ("bincode", bincode::run_bincode_benchmark, bincode::run_bincode_allocations),
+        ("FlatBuffers", flatbuffers::run_flatbuffers_benchmark, flatbuffers::run_flatbuffers_allocations),
+    ]
+}
+
+#[cfg(not(feature = "bench-formats"))]
+fn format_runners() -> Vec<(&'static str, Runner, Profiler)> {
+    Vec::new()
 }
 
 fn print_stats(label: &str, stats: &[StatResult]) {
@@ -62,6 +145,49 @@ fn print_throughput(t: &ThroughputResult) {
     );
 }
 
+fn print_allocations(label: &str, results: &[AllocResult]) {
+    println!("Protocol: {}", label);
+    println!("{:35} {:>18} {:>18} {:>18}", "Scenario", "encode allocs/B", "decode allocs/B", "round trip allocs/B");
+    for r in results {
+        println!(
+            "{:35} {:>8}/{:<9} {:>8}/{:<9} {:>8}/{:<9}",
+            r.scenario,
+            r.encode.allocations,
+            r.encode.bytes,
+            r.decode.allocations,
+            r.decode.bytes,
+            r.round_trip.allocations,
+            r.round_trip.bytes
+        );
+    }
+}
+
+fn write_allocations_csv(allocations: &[(&str, Vec<AllocResult>)]) -> std::io::Result<()> {
+    let mut file = File::create(ALLOCATIONS_CSV)?;
+    writeln!(
+        file,
+        "protocol,scenario,encode_allocs,encode_bytes,decode_allocs,decode_bytes,round_trip_allocs,round_trip_bytes"
+    )?;
+    for (protocol, results) in allocations {
+        for r in results {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{}",
+                protocol,
+                r.scenario,
+                r.encode.allocations,
+                r.encode.bytes,
+                r.decode.allocations,
+                r.decode.bytes,
+                r.round_trip.allocations,
+                r.round_trip.bytes
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn write_csv(
     biwi_stats: &[StatResult],
     biwi_tp: &ThroughputResult,
@@ -70,13 +196,12 @@ fn write_csv(
     udp_network_stats: &[UdpNetworkStats],
     json_stats: &[StatResult],
     json_tp: &ThroughputResult,
-    proto_stats: &[StatResult],
-    proto_tp: &ThroughputResult,
+    formats: &[(&str, Vec<StatResult>, ThroughputResult)],
 ) -> std::io::Result<()> {
     let mut file = File::create(RESULTS_CSV)?;
     writeln!(
         file,
-        "protocol,scenario,avg_ms,min_ms,max_ms,p95_ms,p99_ms,size_bytes,throughput_msg_s,throughput_total_ms,packet_loss_percent,avg_latency_ms,jitter_ms,retransmissions,bytes_sent,bytes_received,effective_throughput_mbps"
+        "protocol,scenario,avg_ms,min_ms,max_ms,p95_ms,p99_ms,size_bytes,throughput_msg_s,throughput_total_ms,packet_loss_percent,avg_latency_ms,jitter_ms,retransmissions,bytes_sent,bytes_received,effective_throughput_mbps,duplicate_packets,out_of_order_packets,gaps,max_reorder_distance"
     )?;
 
     write_stat_rows(&mut file, "BiWi", biwi_stats)?;
@@ -87,8 +212,10 @@ fn write_csv(
     write_stat_rows(&mut file, "JSON", json_stats)?;
     write_throughput_row(&mut file, "JSON", json_tp)?;
 
-    write_stat_rows(&mut file, "Protobuf", proto_stats)?;
-    write_throughput_row(&mut file, "Protobuf", proto_tp)?;
+    for (protocol, stats, tp) in formats {
+        write_stat_rows(&mut file, protocol, stats)?;
+        write_throughput_row(&mut file, protocol, tp)?;
+    }
 
     Ok(())
 }
@@ -115,7 +242,7 @@ fn write_stat_rows_with_network(file: &mut File, protocol: &str, stats: &[StatRe
     for (s, net) in stats.iter().zip(network_stats.iter()) {
         writeln!(
             file,
-            "{},{},{:.6},{:.6},{:.6},{:.6},{:.6},{},,,{:.2},{:.4},{:.4},{},{},{},{:.4}",
+            "{},{},{:.6},{:.6},{:.6},{:.6},{:.6},{},,,{:.2},{:.4},{:.4},{},{},{},{:.4},{},{},{},{}",
             protocol,
             s.scenario,
             s.avg_ms,
@@ -130,7 +257,11 @@ fn write_stat_rows_with_network(file: &mut File, protocol: &str, stats: &[StatRe
             net.retransmissions,
             net.bytes_sent,
             net.bytes_received,
-            net.effective_throughput_mbps
+            net.effective_throughput_mbps,
+            net.duplicate_packets,
+            net.out_of_order_packets,
+            net.gaps,
+            net.max_reorder_distance
         )?;
     }
     Ok(())
@@ -139,15 +270,8 @@ fn write_stat_rows_with_network(file: &mut File, protocol: &str, stats: &[StatRe
 fn write_throughput_row(file: &mut File, protocol: &str, t: &ThroughputResult) -> std::io::Result<()> {
     writeln!(
         file,
-        "{},{},{},{},{},{},{},{},{:.2},{:.2}",
+        "{},THROUGHPUT,,,,,,,{:.2},{:.2}",
         protocol,
-        "THROUGHPUT",
-        "",
-        "",
-        "",
-        "",
-        "",
-        "",
         t.throughput,
         t.total_time_ms
     )
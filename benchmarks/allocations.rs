@@ -0,0 +1,91 @@
+//! Allocation counting. `CountingAllocator` is the benchmark binary's global allocator:
+//! it forwards to the system allocator and counts allocations and requested bytes per
+//! thread, so the echo servers' threads never show up in a codec's numbers.
+
+use crate::benchmarks::Scenario;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hint::black_box;
+use std::ops::Sub;
+
+pub struct CountingAllocator;
+
+thread_local! {
+    static COUNTS: Cell<AllocStats> = const { Cell::new(AllocStats { allocations: 0, bytes: 0 }) };
+}
+
+fn record(bytes: usize) {
+    // `try_with` because the allocator still runs while thread locals are torn down
+    let _ = COUNTS.try_with(|counts| {
+        let mut stats = counts.get();
+        stats.allocations += 1;
+        stats.bytes += bytes as u64;
+        counts.set(stats);
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    /// A grow or shrink counts as one allocation of the new size
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocStats {
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+impl Sub for AllocStats {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        AllocStats { allocations: self.allocations - rhs.allocations, bytes: self.bytes - rhs.bytes }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AllocResult {
+    pub scenario: String,
+    pub encode: AllocStats,
+    pub decode: AllocStats,
+    pub round_trip: AllocStats,
+}
+
+/// What this thread allocated while running `f`. Dropping the result is not counted.
+pub fn count<R>(f: impl FnOnce() -> R) -> AllocStats {
+    let before = COUNTS.with(Cell::get);
+    let result = black_box(f());
+    let after = COUNTS.with(Cell::get);
+    drop(result);
+    after - before
+}
+
+/// Allocations for one encode, one decode and one encode-then-decode of a scenario,
+/// after a warm-up call so lazily initialised state isn't charged to the first one
+pub fn profile<T>(s: &Scenario, encode: impl Fn() -> Vec<u8>, decode: impl Fn(&[u8]) -> T) -> AllocResult {
+    let bytes = encode();
+    black_box(decode(&bytes));
+    AllocResult {
+        scenario: s.name.to_string(),
+        encode: count(&encode),
+        decode: count(|| decode(&bytes)),
+        round_trip: count(|| decode(&encode())),
+    }
+}
@@ -0,0 +1,31 @@
+//! bincode runner: fields in declaration order with no names or tags, the
+//! smallest and fastest of the serde formats, but not self-describing
+
+use crate::benchmarks::allocations::AllocResult;
+use crate::benchmarks::runner::{self, Serde};
+use crate::benchmarks::typed::SerdeFormat;
+use crate::benchmarks::{StatResult, ThroughputResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub struct Bincode;
+
+impl SerdeFormat for Bincode {
+    const LABEL: &'static str = "bincode";
+
+    fn to_vec<T: Serialize>(value: &T) -> Vec<u8> {
+        ::bincode::serialize(value).unwrap()
+    }
+
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> T {
+        ::bincode::deserialize(bytes).unwrap()
+    }
+}
+
+pub fn run_bincode_benchmark() -> (Vec<StatResult>, ThroughputResult) {
+    runner::run::<Serde<Bincode>>(4015)
+}
+
+pub fn run_bincode_allocations() -> Vec<AllocResult> {
+    runner::allocations::<Serde<Bincode>>()
+}
@@ -1,5 +1,7 @@
 use biwi::{BiWiMessage, BiWiValue};
-use crate::benchmarks::{calc_stats, scenarios, Scenario, StatResult, ThroughputResult};
+use crate::benchmarks::allocations::{self, AllocResult};
+use crate::benchmarks::scenarios::json_to_biwi;
+use crate::benchmarks::{calc_stats, iterations, scenarios, Scenario, StatResult, ThroughputResult};
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::thread;
@@ -13,13 +15,24 @@ pub fn run_biwi_benchmark() -> (Vec<StatResult>, ThroughputResult) {
     (combined, net.1)
 }
 
+pub fn run_biwi_allocations() -> Vec<AllocResult> {
+    scenarios()
+        .iter()
+        .map(|s| {
+            let mut msg = BiWiMessage::new();
+            msg.set_field(1, json_to_biwi(&s.payload));
+            allocations::profile(s, || msg.to_vec(), |buf| BiWiMessage::from_buffer(buf).unwrap())
+        })
+        .collect()
+}
+
 fn run_pure() -> Vec<StatResult> {
     let scenarios = scenarios();
     let mut results = Vec::new();
 
     for scenario in scenarios {
-        let encode_samples = benchmark_encode(&scenario, 5_000);
-        let decode_samples = benchmark_decode(&scenario, 5_000);
+        let encode_samples = benchmark_encode(&scenario, iterations(5_000));
+        let decode_samples = benchmark_decode(&scenario, iterations(5_000));
         let (enc_avg, enc_min, enc_max, enc_p95, enc_p99) = calc_stats(encode_samples);
         let (dec_avg, dec_min, dec_max, dec_p95, dec_p99) = calc_stats(decode_samples);
 
@@ -53,11 +66,9 @@ fn run_network() -> (Vec<StatResult>, ThroughputResult) {
 
     // Echo server thread
     let _server = thread::spawn(move || {
-        for stream in listener.incoming() {
-            if let Ok(mut stream) = stream {
-                stream.set_nodelay(true).ok();
-                thread::spawn(move || handle_client(&mut stream));
-            }
+        for mut stream in listener.incoming().flatten() {
+            stream.set_nodelay(true).ok();
+            thread::spawn(move || handle_client(&mut stream));
         }
     });
 
@@ -69,7 +80,7 @@ fn run_network() -> (Vec<StatResult>, ThroughputResult) {
     for scenario in &scenarios {
         let mut stream = TcpStream::connect("127.0.0.1:4010").expect("connect biwi");
         stream.set_nodelay(true).expect("set_nodelay");
-        let (avg, min, max, p95, p99) = benchmark_round_trip(&mut stream, scenario, 200);
+        let (avg, min, max, p95, p99) = benchmark_round_trip(&mut stream, scenario, iterations(200));
         let size = message_size(scenario) + 4; // length prefix
         results.push(StatResult {
             scenario: format!("{} (net)", scenario.name),
@@ -94,12 +105,12 @@ fn handle_client(stream: &mut TcpStream) {
     let mut buf = Vec::with_capacity(8 * 1024);
     loop {
         let mut len_buf = [0u8; 4];
-        if let Err(_) = stream.read_exact(&mut len_buf) {
+        if stream.read_exact(&mut len_buf).is_err() {
             break;
         }
         let len = u32::from_be_bytes(len_buf) as usize;
         buf.resize(len, 0);
-        if let Err(_) = stream.read_exact(&mut buf) {
+        if stream.read_exact(&mut buf).is_err() {
             break;
         }
 
@@ -108,7 +119,7 @@ fn handle_client(stream: &mut TcpStream) {
         let mut frame = Vec::with_capacity(4 + len);
         frame.extend_from_slice(&(len as u32).to_be_bytes());
         frame.extend_from_slice(&buf);
-        if let Err(_) = stream.write_all(&frame) {
+        if stream.write_all(&frame).is_err() {
             break;
         }
         let _ = stream.flush();
@@ -134,7 +145,7 @@ fn benchmark_decode(s: &Scenario, iterations: usize) -> Vec<f64> {
     let mut samples = Vec::with_capacity(iterations);
     for _ in 0..iterations {
         let start = Instant::now();
-        let _ = BiWiMessage::from_buffer(&buffer).unwrap();
+        let _ = BiWiMessage::from_buffer(buffer).unwrap();
         samples.push(start.elapsed().as_secs_f64() * 1000.0);
     }
     samples
@@ -152,7 +163,7 @@ fn benchmark_round_trip(stream: &mut TcpStream, s: &Scenario, iterations: usize)
         let start = Instant::now();
         let len = payload.len() as u32;
         stream.write_all(&len.to_be_bytes()).unwrap();
-        stream.write_all(&payload).unwrap();
+        stream.write_all(payload).unwrap();
 
         let mut len_buf = [0u8; 4];
         stream.read_exact(&mut len_buf).unwrap();
@@ -175,15 +186,15 @@ fn throughput_test(stream: &mut TcpStream, messages: usize) -> ThroughputResult
         // slightly vary data to avoid caching artifacts
         {
             if let Some(BiWiValue::Object(map)) = msg.get_field_mut(1) {
-                if let Some(BiWiValue::String(ref mut s)) = map.get_mut("playerId") {
-                    *s = format!("player_{}", i % 1000);
+                if let Some(player_id) = map.get_mut("playerId") {
+                    *player_id = BiWiValue::from(format!("player_{}", i % 1000));
                 }
             }
         }
-        let buf = msg.to_vec();
+        let buf = msg.to_buffer();
         let len_bytes = (buf.len() as u32).to_be_bytes();
         stream.write_all(&len_bytes).unwrap();
-        stream.write_all(&buf).unwrap();
+        stream.write_all(buf).unwrap();
     }
 
     let mut received = 0usize;
@@ -210,34 +221,3 @@ fn message_size(s: &Scenario) -> usize {
     msg.set_field(1, json_to_biwi(&s.payload));
     msg.size()
 }
-
-fn json_to_biwi(value: &serde_json::Value) -> BiWiValue {
-    match value {
-        serde_json::Value::Null => BiWiValue::Null,
-        serde_json::Value::Bool(b) => BiWiValue::Boolean(*b),
-        serde_json::Value::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
-                    BiWiValue::Int32(i as i32)
-                } else {
-                    BiWiValue::Int64(i)
-                }
-            } else if let Some(f) = n.as_f64() {
-                BiWiValue::number(f)
-            } else {
-                BiWiValue::Null
-            }
-        }
-        serde_json::Value::String(s) => BiWiValue::from(s.as_str()),
-        serde_json::Value::Array(arr) => {
-            BiWiValue::Array(arr.iter().map(json_to_biwi).collect())
-        }
-        serde_json::Value::Object(map) => {
-            let mut obj = std::collections::HashMap::new();
-            for (k, v) in map {
-                obj.insert(k.clone(), json_to_biwi(v));
-            }
-            BiWiValue::Object(obj)
-        }
-    }
-}
@@ -0,0 +1,32 @@
+//! CBOR runner (ciborium)
+
+use crate::benchmarks::allocations::AllocResult;
+use crate::benchmarks::runner::{self, Serde};
+use crate::benchmarks::typed::SerdeFormat;
+use crate::benchmarks::{StatResult, ThroughputResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub struct Cbor;
+
+impl SerdeFormat for Cbor {
+    const LABEL: &'static str = "CBOR";
+
+    fn to_vec<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(512);
+        ciborium::into_writer(value, &mut buf).unwrap();
+        buf
+    }
+
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> T {
+        ciborium::from_reader(bytes).unwrap()
+    }
+}
+
+pub fn run_cbor_benchmark() -> (Vec<StatResult>, ThroughputResult) {
+    runner::run::<Serde<Cbor>>(4014)
+}
+
+pub fn run_cbor_allocations() -> Vec<AllocResult> {
+    runner::allocations::<Serde<Cbor>>()
+}
@@ -0,0 +1,432 @@
+//! FlatBuffers comparison runner. There is no `flatc` step in the build, so the
+//! tables in `messages.fbs` are written out here the way generated code would:
+//! a verified root, slot accessors and a builder per table.
+
+use crate::benchmarks::allocations::AllocResult;
+use crate::benchmarks::runner::{self, Codec};
+use crate::benchmarks::typed::{
+    ApiResponse, ChatMessage, GameStateUpdate, IoTSensorData, Preferences, Profile, Reading, Rotation, StockTick, Typed, Vec3,
+};
+use crate::benchmarks::{StatResult, ThroughputResult};
+use ::flatbuffers::{
+    FlatBufferBuilder, Follow, ForwardsUOffset, InvalidFlatbuffer, Table, TableFinishedWIPOffset, VOffsetT, Verifiable, Verifier,
+    Vector, WIPOffset,
+};
+
+/// vtable offset of the field declared `index`-th in its table
+const fn slot(index: VOffsetT) -> VOffsetT {
+    4 + 2 * index
+}
+
+/// A read-only view of one table, with one accessor per field in declaration order
+macro_rules! table {
+    ($name:ident { $($index:literal $field:ident: $ty:ty),* $(,)? }) => {
+        #[derive(Clone, Copy)]
+        struct $name<'a>(Table<'a>);
+
+        impl<'a> Follow<'a> for $name<'a> {
+            type Inner = Self;
+
+            unsafe fn follow(buf: &'a [u8], loc: usize) -> Self {
+                Self(Table::new(buf, loc))
+            }
+        }
+
+        impl<'a> Verifiable for $name<'a> {
+            fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+                v.visit_table(pos)?
+                    $(.visit_field::<$ty>(stringify!($field), slot($index), false)?)*
+                    .finish();
+                Ok(())
+            }
+        }
+
+        impl<'a> $name<'a> {
+            $(
+                fn $field(&self) -> Option<<$ty as Follow<'a>>::Inner> {
+                    // SAFETY: every buffer is checked by `flatbuffers::root` before it is read
+                    unsafe { self.0.get::<$ty>(slot($index), None) }
+                }
+            )*
+        }
+    };
+}
+
+type Str<'a> = ForwardsUOffset<&'a str>;
+type Strings<'a> = ForwardsUOffset<Vector<'a, ForwardsUOffset<&'a str>>>;
+type Tables<'a, T> = ForwardsUOffset<Vector<'a, ForwardsUOffset<T>>>;
+
+table!(Float3Table { 0 x: f32, 1 y: f32, 2 z: f32 });
+
+table!(GameTable {
+    0 player_id: Str<'a>,
+    1 position: ForwardsUOffset<Float3Table<'a>>,
+    2 rotation: ForwardsUOffset<Float3Table<'a>>,
+    3 health: i32,
+    4 mana: i32,
+    5 stamina: i32,
+    6 equipment: Strings<'a>,
+    7 status: Str<'a>,
+    8 timestamp: i64,
+});
+
+table!(ProfileTable {
+    0 display_name: Str<'a>,
+    1 avatar: Str<'a>,
+    2 bio: Str<'a>,
+    3 followers: i32,
+    4 following: i32,
+});
+
+table!(PreferencesTable { 0 notifications: bool, 1 theme: Str<'a>, 2 language: Str<'a> });
+
+table!(ApiTable {
+    0 id: Str<'a>,
+    1 username: Str<'a>,
+    2 email: Str<'a>,
+    3 profile: ForwardsUOffset<ProfileTable<'a>>,
+    4 preferences: ForwardsUOffset<PreferencesTable<'a>>,
+    5 verified: bool,
+    6 created_at: Str<'a>,
+    7 last_login: i64,
+});
+
+table!(ReadingTable { 0 timestamp: i64, 1 temperature: f32, 2 humidity: f32 });
+
+table!(IoTTable {
+    0 device_id: Str<'a>,
+    1 location: Str<'a>,
+    2 readings: Tables<'a, ReadingTable<'a>>,
+    3 status: Str<'a>,
+    4 battery_level: i32,
+    5 signal_strength: i32,
+});
+
+table!(ReactionTable { 0 emoji: Str<'a>, 1 count: i32 });
+
+table!(ChatTable {
+    0 message_id: Str<'a>,
+    1 conversation_id: Str<'a>,
+    2 sender_id: Str<'a>,
+    3 sender_name: Str<'a>,
+    4 text: Str<'a>,
+    5 timestamp: i64,
+    6 reactions: Tables<'a, ReactionTable<'a>>,
+    7 mentions: Strings<'a>,
+    8 edited: bool,
+});
+
+table!(StockTable {
+    0 symbol: Str<'a>,
+    1 price: f64,
+    2 price_change: f32,
+    3 percent_change: f32,
+    4 volume: i64,
+    5 bid: f32,
+    6 ask: f32,
+    7 high: f32,
+    8 low: f32,
+    9 open: f32,
+    10 close: f32,
+    11 timestamp: i64,
+    12 exchange: Str<'a>,
+});
+
+type Finished = WIPOffset<TableFinishedWIPOffset>;
+
+fn float3(fbb: &mut FlatBufferBuilder, x: f32, y: f32, z: f32) -> Finished {
+    let table = fbb.start_table();
+    fbb.push_slot(slot(0), x, 0.0);
+    fbb.push_slot(slot(1), y, 0.0);
+    fbb.push_slot(slot(2), z, 0.0);
+    fbb.end_table(table)
+}
+
+fn strings<'fbb>(fbb: &mut FlatBufferBuilder<'fbb>, items: &[String]) -> WIPOffset<Vector<'fbb, ForwardsUOffset<&'fbb str>>> {
+    let items: Vec<_> = items.iter().map(|s| fbb.create_string(s)).collect();
+    fbb.create_vector(&items)
+}
+
+fn build_game(fbb: &mut FlatBufferBuilder, m: &GameStateUpdate) -> Finished {
+    let player_id = fbb.create_string(&m.player_id);
+    let position = float3(fbb, m.position.x, m.position.y, m.position.z);
+    let rotation = float3(fbb, m.rotation.pitch, m.rotation.yaw, m.rotation.roll);
+    let equipment = strings(fbb, &m.equipment);
+    let status = fbb.create_string(&m.status);
+    let table = fbb.start_table();
+    fbb.push_slot_always(slot(0), player_id);
+    fbb.push_slot_always(slot(1), position);
+    fbb.push_slot_always(slot(2), rotation);
+    fbb.push_slot(slot(3), m.health, 0);
+    fbb.push_slot(slot(4), m.mana, 0);
+    fbb.push_slot(slot(5), m.stamina, 0);
+    fbb.push_slot_always(slot(6), equipment);
+    fbb.push_slot_always(slot(7), status);
+    fbb.push_slot(slot(8), m.timestamp, 0);
+    fbb.end_table(table)
+}
+
+fn build_api(fbb: &mut FlatBufferBuilder, m: &ApiResponse) -> Finished {
+    let id = fbb.create_string(&m.id);
+    let username = fbb.create_string(&m.username);
+    let email = fbb.create_string(&m.email);
+    let profile = {
+        let display_name = fbb.create_string(&m.profile.display_name);
+        let avatar = fbb.create_string(&m.profile.avatar);
+        let bio = fbb.create_string(&m.profile.bio);
+        let table = fbb.start_table();
+        fbb.push_slot_always(slot(0), display_name);
+        fbb.push_slot_always(slot(1), avatar);
+        fbb.push_slot_always(slot(2), bio);
+        fbb.push_slot(slot(3), m.profile.followers, 0);
+        fbb.push_slot(slot(4), m.profile.following, 0);
+        fbb.end_table(table)
+    };
+    let preferences = {
+        let theme = fbb.create_string(&m.preferences.theme);
+        let language = fbb.create_string(&m.preferences.language);
+        let table = fbb.start_table();
+        fbb.push_slot(slot(0), m.preferences.notifications, false);
+        fbb.push_slot_always(slot(1), theme);
+        fbb.push_slot_always(slot(2), language);
+        fbb.end_table(table)
+    };
+    let created_at = fbb.create_string(&m.created_at);
+    let table = fbb.start_table();
+    fbb.push_slot_always(slot(0), id);
+    fbb.push_slot_always(slot(1), username);
+    fbb.push_slot_always(slot(2), email);
+    fbb.push_slot_always(slot(3), profile);
+    fbb.push_slot_always(slot(4), preferences);
+    fbb.push_slot(slot(5), m.verified, false);
+    fbb.push_slot_always(slot(6), created_at);
+    fbb.push_slot(slot(7), m.last_login, 0);
+    fbb.end_table(table)
+}
+
+fn build_iot(fbb: &mut FlatBufferBuilder, m: &IoTSensorData) -> Finished {
+    let device_id = fbb.create_string(&m.device_id);
+    let location = fbb.create_string(&m.location);
+    let readings: Vec<_> = m
+        .readings
+        .iter()
+        .map(|r| {
+            let table = fbb.start_table();
+            fbb.push_slot(slot(0), r.timestamp, 0);
+            fbb.push_slot(slot(1), r.temperature, 0.0);
+            fbb.push_slot(slot(2), r.humidity, 0.0);
+            fbb.end_table(table)
+        })
+        .collect();
+    let readings = fbb.create_vector(&readings);
+    let status = fbb.create_string(&m.status);
+    let table = fbb.start_table();
+    fbb.push_slot_always(slot(0), device_id);
+    fbb.push_slot_always(slot(1), location);
+    fbb.push_slot_always(slot(2), readings);
+    fbb.push_slot_always(slot(3), status);
+    fbb.push_slot(slot(4), m.battery_level, 0);
+    fbb.push_slot(slot(5), m.signal_strength, 0);
+    fbb.end_table(table)
+}
+
+fn build_chat(fbb: &mut FlatBufferBuilder, m: &ChatMessage) -> Finished {
+    let message_id = fbb.create_string(&m.message_id);
+    let conversation_id = fbb.create_string(&m.conversation_id);
+    let sender_id = fbb.create_string(&m.sender_id);
+    let sender_name = fbb.create_string(&m.sender_name);
+    let text = fbb.create_string(&m.text);
+    let reactions: Vec<_> = m
+        .reactions
+        .iter()
+        .map(|(emoji, count)| {
+            let emoji = fbb.create_string(emoji);
+            let table = fbb.start_table();
+            fbb.push_slot_always(slot(0), emoji);
+            fbb.push_slot(slot(1), *count, 0);
+            fbb.end_table(table)
+        })
+        .collect();
+    let reactions = fbb.create_vector(&reactions);
+    let mentions = strings(fbb, &m.mentions);
+    let table = fbb.start_table();
+    fbb.push_slot_always(slot(0), message_id);
+    fbb.push_slot_always(slot(1), conversation_id);
+    fbb.push_slot_always(slot(2), sender_id);
+    fbb.push_slot_always(slot(3), sender_name);
+    fbb.push_slot_always(slot(4), text);
+    fbb.push_slot(slot(5), m.timestamp, 0);
+    fbb.push_slot_always(slot(6), reactions);
+    fbb.push_slot_always(slot(7), mentions);
+    fbb.push_slot(slot(8), m.edited, false);
+    fbb.end_table(table)
+}
+
+fn build_stock(fbb: &mut FlatBufferBuilder, m: &StockTick) -> Finished {
+    let symbol = fbb.create_string(&m.symbol);
+    let exchange = fbb.create_string(&m.exchange);
+    let table = fbb.start_table();
+    fbb.push_slot_always(slot(0), symbol);
+    fbb.push_slot(slot(1), m.price, 0.0);
+    fbb.push_slot(slot(2), m.price_change, 0.0);
+    fbb.push_slot(slot(3), m.percent_change, 0.0);
+    fbb.push_slot(slot(4), m.volume, 0);
+    fbb.push_slot(slot(5), m.bid, 0.0);
+    fbb.push_slot(slot(6), m.ask, 0.0);
+    fbb.push_slot(slot(7), m.high, 0.0);
+    fbb.push_slot(slot(8), m.low, 0.0);
+    fbb.push_slot(slot(9), m.open, 0.0);
+    fbb.push_slot(slot(10), m.close, 0.0);
+    fbb.push_slot(slot(11), m.timestamp, 0);
+    fbb.push_slot_always(slot(12), exchange);
+    fbb.end_table(table)
+}
+
+fn string(s: Option<&str>) -> String {
+    s.unwrap_or_default().to_string()
+}
+
+fn string_list(v: Option<Vector<ForwardsUOffset<&str>>>) -> Vec<String> {
+    v.map(|v| v.iter().map(str::to_string).collect()).unwrap_or_default()
+}
+
+fn read_float3(t: Option<Float3Table>) -> (f32, f32, f32) {
+    t.map(|t| (t.x().unwrap_or_default(), t.y().unwrap_or_default(), t.z().unwrap_or_default())).unwrap_or_default()
+}
+
+fn read_game(t: GameTable) -> GameStateUpdate {
+    let (x, y, z) = read_float3(t.position());
+    let (pitch, yaw, roll) = read_float3(t.rotation());
+    GameStateUpdate {
+        player_id: string(t.player_id()),
+        position: Vec3 { x, y, z },
+        rotation: Rotation { pitch, yaw, roll },
+        health: t.health().unwrap_or_default(),
+        mana: t.mana().unwrap_or_default(),
+        stamina: t.stamina().unwrap_or_default(),
+        equipment: string_list(t.equipment()),
+        status: string(t.status()),
+        timestamp: t.timestamp().unwrap_or_default(),
+    }
+}
+
+fn read_api(t: ApiTable) -> ApiResponse {
+    let profile = t.profile();
+    let preferences = t.preferences();
+    ApiResponse {
+        id: string(t.id()),
+        username: string(t.username()),
+        email: string(t.email()),
+        profile: Profile {
+            display_name: string(profile.and_then(|p| p.display_name())),
+            avatar: string(profile.and_then(|p| p.avatar())),
+            bio: string(profile.and_then(|p| p.bio())),
+            followers: profile.and_then(|p| p.followers()).unwrap_or_default(),
+            following: profile.and_then(|p| p.following()).unwrap_or_default(),
+        },
+        preferences: Preferences {
+            notifications: preferences.and_then(|p| p.notifications()).unwrap_or_default(),
+            theme: string(preferences.and_then(|p| p.theme())),
+            language: string(preferences.and_then(|p| p.language())),
+        },
+        verified: t.verified().unwrap_or_default(),
+        created_at: string(t.created_at()),
+        last_login: t.last_login().unwrap_or_default(),
+    }
+}
+
+fn read_iot(t: IoTTable) -> IoTSensorData {
+    IoTSensorData {
+        device_id: string(t.device_id()),
+        location: string(t.location()),
+        readings: t
+            .readings()
+            .map(|readings| {
+                readings
+                    .iter()
+                    .map(|r| Reading {
+                        timestamp: r.timestamp().unwrap_or_default(),
+                        temperature: r.temperature().unwrap_or_default(),
+                        humidity: r.humidity().unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        status: string(t.status()),
+        battery_level: t.battery_level().unwrap_or_default(),
+        signal_strength: t.signal_strength().unwrap_or_default(),
+    }
+}
+
+fn read_chat(t: ChatTable) -> ChatMessage {
+    ChatMessage {
+        message_id: string(t.message_id()),
+        conversation_id: string(t.conversation_id()),
+        sender_id: string(t.sender_id()),
+        sender_name: string(t.sender_name()),
+        text: string(t.text()),
+        timestamp: t.timestamp().unwrap_or_default(),
+        reactions: t
+            .reactions()
+            .map(|reactions| reactions.iter().map(|r| (string(r.emoji()), r.count().unwrap_or_default())).collect())
+            .unwrap_or_default(),
+        mentions: string_list(t.mentions()),
+        edited: t.edited().unwrap_or_default(),
+    }
+}
+
+fn read_stock(t: StockTable) -> StockTick {
+    StockTick {
+        symbol: string(t.symbol()),
+        price: t.price().unwrap_or_default(),
+        price_change: t.price_change().unwrap_or_default(),
+        percent_change: t.percent_change().unwrap_or_default(),
+        volume: t.volume().unwrap_or_default(),
+        bid: t.bid().unwrap_or_default(),
+        ask: t.ask().unwrap_or_default(),
+        high: t.high().unwrap_or_default(),
+        low: t.low().unwrap_or_default(),
+        open: t.open().unwrap_or_default(),
+        close: t.close().unwrap_or_default(),
+        timestamp: t.timestamp().unwrap_or_default(),
+        exchange: string(t.exchange()),
+    }
+}
+
+pub struct FlatBuffers;
+
+impl Codec for FlatBuffers {
+    const LABEL: &'static str = "FlatBuffers";
+
+    fn encode(message: &Typed) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::with_capacity(512);
+        let root = match message {
+            Typed::Game(m) => build_game(&mut fbb, m),
+            Typed::Api(m) => build_api(&mut fbb, m),
+            Typed::IoT(m) => build_iot(&mut fbb, m),
+            Typed::Chat(m) => build_chat(&mut fbb, m),
+            Typed::Stock(m) => build_stock(&mut fbb, m),
+        };
+        fbb.finish(root, None);
+        fbb.finished_data().to_vec()
+    }
+
+    fn decode(name: &str, bytes: &[u8]) -> Typed {
+        let verified = "FlatBuffers verify";
+        match name {
+            "Game State Update" => Typed::Game(read_game(::flatbuffers::root::<GameTable>(bytes).expect(verified))),
+            "API Response (User Data)" => Typed::Api(read_api(::flatbuffers::root::<ApiTable>(bytes).expect(verified))),
+            "IoT Sensor Data" => Typed::IoT(read_iot(::flatbuffers::root::<IoTTable>(bytes).expect(verified))),
+            "Chat Message" => Typed::Chat(read_chat(::flatbuffers::root::<ChatTable>(bytes).expect(verified))),
+            _ => Typed::Stock(read_stock(::flatbuffers::root::<StockTable>(bytes).expect(verified))),
+        }
+    }
+}
+
+pub fn run_flatbuffers_benchmark() -> (Vec<StatResult>, ThroughputResult) {
+    runner::run::<FlatBuffers>(4016)
+}
+
+pub fn run_flatbuffers_allocations() -> Vec<AllocResult> {
+    runner::allocations::<FlatBuffers>()
+}
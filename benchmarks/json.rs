@@ -1,4 +1,5 @@
-use crate::benchmarks::{calc_stats, scenarios, Scenario, StatResult, ThroughputResult};
+use crate::benchmarks::allocations::{self, AllocResult};
+use crate::benchmarks::{calc_stats, iterations, scenarios, Scenario, StatResult, ThroughputResult};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::io::{Read, Write};
@@ -20,11 +21,22 @@ pub fn run_json_benchmark() -> (Vec<StatResult>, ThroughputResult) {
     (combined, net.1)
 }
 
+pub fn run_json_allocations() -> Vec<AllocResult> {
+    scenarios()
+        .iter()
+        .map(|s| {
+            allocations::profile(s, || serde_json::to_vec(&s.payload).unwrap(), |buf| {
+                serde_json::from_slice::<Value>(buf).unwrap()
+            })
+        })
+        .collect()
+}
+
 fn run_pure() -> Vec<StatResult> {
     let mut results = Vec::new();
     for scenario in scenarios() {
-        let encode_samples = bench_encode(&scenario, 5_000);
-        let decode_samples = bench_decode(&scenario, 5_000);
+        let encode_samples = bench_encode(&scenario, iterations(5_000));
+        let decode_samples = bench_decode(&scenario, iterations(5_000));
         let (enc_avg, enc_min, enc_max, enc_p95, enc_p99) = calc_stats(encode_samples);
         let (dec_avg, dec_min, dec_max, dec_p95, dec_p99) = calc_stats(decode_samples);
 
@@ -52,11 +64,9 @@ fn run_pure() -> Vec<StatResult> {
 fn run_network() -> (Vec<StatResult>, ThroughputResult) {
     let listener = TcpListener::bind("127.0.0.1:4011").expect("bind json");
     let _server = thread::spawn(move || {
-        for stream in listener.incoming() {
-            if let Ok(mut s) = stream {
-                s.set_nodelay(true).ok();
-                thread::spawn(move || handle_client(&mut s));
-            }
+        for mut s in listener.incoming().flatten() {
+            s.set_nodelay(true).ok();
+            thread::spawn(move || handle_client(&mut s));
         }
     });
     std::thread::sleep(std::time::Duration::from_millis(100));
@@ -66,7 +76,7 @@ fn run_network() -> (Vec<StatResult>, ThroughputResult) {
     for scenario in &scens {
         let mut stream = TcpStream::connect("127.0.0.1:4011").expect("connect json");
         stream.set_nodelay(true).expect("set_nodelay");
-        let (avg, min, max, p95, p99) = bench_round_trip(&mut stream, scenario, 200);
+        let (avg, min, max, p95, p99) = bench_round_trip(&mut stream, scenario, iterations(200));
         let size = serde_json::to_vec(&scenario.payload).unwrap().len() + 1;
         results.push(StatResult {
             scenario: format!("{} (net)", scenario.name),
@@ -1,18 +1,79 @@
-use serde_json::json;
-
+pub mod allocations;
+#[cfg(feature = "bench-formats")]
+pub mod bincode;
 pub mod biwi;
+#[cfg(feature = "bench-formats")]
+pub mod cbor;
+#[cfg(feature = "bench-formats")]
+pub mod flatbuffers;
 pub mod json;
+#[cfg(feature = "bench-formats")]
+pub mod msgpack;
 pub mod protobuf;
+#[cfg(feature = "bench-formats")]
+pub mod runner;
+pub mod scenarios;
+#[cfg(feature = "bench-formats")]
+pub mod typed;
 pub mod udp;
 
-pub mod proto {
-    include!(concat!(env!("OUT_DIR"), "/_.rs"));
+pub use scenarios::Scenario;
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+static CUSTOM_SCENARIOS: OnceLock<Vec<Scenario>> = OnceLock::new();
+static REPEAT: OnceLock<usize> = OnceLock::new();
+
+/// The payloads every runner measures: the files given with `--scenarios`, or the built-in ones
+pub fn scenarios() -> Vec<Scenario> {
+    CUSTOM_SCENARIOS.get().cloned().unwrap_or_else(scenarios::scenarios)
+}
+
+/// Whether `--scenarios` replaced the built-in payloads. Protobuf and the typed formats
+/// need a schema per payload, so they only run on the built-in ones.
+pub fn custom_scenarios() -> bool {
+    CUSTOM_SCENARIOS.get().is_some()
+}
+
+pub fn set_scenarios(scenarios: Vec<Scenario>) {
+    let _ = CUSTOM_SCENARIOS.set(scenarios);
+}
+
+/// `default` iterations, unless `--repeat` gave a count
+pub fn iterations(default: usize) -> usize {
+    REPEAT.get().copied().unwrap_or(default)
+}
+
+pub fn set_repeat(count: usize) {
+    let _ = REPEAT.set(count);
 }
 
-#[derive(Clone)]
-pub struct Scenario {
-    pub name: &'static str,
-    pub payload: serde_json::Value,
+/// One scenario per `*.json` file in `dir`, named after the file and in file name order
+pub fn load_scenarios(dir: &Path) -> Result<Vec<Scenario>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(format!("{}: no .json files", dir.display()));
+    }
+    paths
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let payload = serde_json::from_slice(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            // Names live for the whole run, like the built-in ones
+            Ok(Scenario { name: Box::leak(name.into_boxed_str()), payload })
+        })
+        .collect()
+}
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/_.rs"));
 }
 
 #[derive(Clone, Debug)]
@@ -33,96 +94,6 @@ pub struct ThroughputResult {
     pub total_time_ms: f64,
 }
 
-pub fn scenarios() -> Vec<Scenario> {
-    vec![
-        Scenario {
-            name: "Game State Update",
-            payload: json!({
-                "playerId": "player_12345",
-                "position": {"x": 123.456, "y": 789.012, "z": 45.678},
-                "rotation": {"pitch": 45.5, "yaw": 180.0, "roll": 0.0},
-                "health": 95,
-                "mana": 120,
-                "stamina": 85,
-                "equipment": ["sword", "shield", "boots"],
-                "status": "running",
-                "timestamp": now_ms(),
-            }),
-        },
-        Scenario {
-            name: "API Response (User Data)",
-            payload: json!({
-                "id": "user_98765",
-                "username": "alice_wonderland",
-                "email": "alice@example.com",
-                "profile": {
-                    "displayName": "Alice W.",
-                    "avatar": "https://example.com/avatar.jpg",
-                    "bio": "Software engineer and gaming enthusiast",
-                    "followers": 1523,
-                    "following": 342
-                },
-                "preferences": {
-                    "notifications": true,
-                    "theme": "dark",
-                    "language": "en-US"
-                },
-                "verified": true,
-                "createdAt": "2020-05-15T10:30:00Z",
-                "lastLogin": now_ms(),
-            }),
-        },
-        Scenario {
-            name: "IoT Sensor Data",
-            payload: json!({
-                "deviceId": "sensor_001",
-                "location": "warehouse_b",
-                "readings": [
-                    {"timestamp": now_ms() - 3000, "temperature": 22.5, "humidity": 45.2},
-                    {"timestamp": now_ms() - 2000, "temperature": 22.6, "humidity": 45.1},
-                    {"timestamp": now_ms() - 1000, "temperature": 22.4, "humidity": 45.3},
-                    {"timestamp": now_ms(), "temperature": 22.5, "humidity": 45.2}
-                ],
-                "status": "healthy",
-                "batteryLevel": 87,
-                "signalStrength": -42,
-            }),
-        },
-        Scenario {
-            name: "Chat Message",
-            payload: json!({
-                "messageId": "msg_550e8400",
-                "conversationId": "conv_12345",
-                "senderId": "user_001",
-                "senderName": "Bob",
-                "text": "Hey Alice! Did you see the new game update? The graphics are amazing!",
-                "timestamp": now_ms(),
-                "reactions": {"👍": 5, "❤️": 3, "😂": 1},
-                "mentions": ["Alice", "Charlie"],
-                "edited": false
-            }),
-        },
-        Scenario {
-            name: "Stock Market Tick",
-            payload: json!({
-                "symbol": "AAPL",
-                "price": 182.45,
-                "priceChange": 1.23,
-                "percentChange": 0.68,
-                "volume": 52345600,
-                "bid": 182.40,
-                "ask": 182.50,
-                "high": 183.50,
-                "low": 181.20,
-                "open": 181.22,
-                "close": 182.45,
-                "timestamp": now_ms(),
-                "exchange": "NASDAQ",
-            }),
-        },
-    ]
-}
-
 pub fn calc_stats(mut samples: Vec<f64>) -> (f64, f64, f64, f64, f64) {
     samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
     let len = samples.len();
@@ -135,9 +106,3 @@ pub fn calc_stats(mut samples: Vec<f64>) -> (f64, f64, f64, f64, f64) {
     let p99 = samples[idx99.min(len - 1)];
     (avg, min, max, p95, p99)
 }
-
-fn now_ms() -> i64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    now.as_millis() as i64
-}
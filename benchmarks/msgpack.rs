@@ -0,0 +1,31 @@
+//! MessagePack runner (rmp-serde), with named fields so payloads stay
+//! self-describing like BiWi and JSON
+
+use crate::benchmarks::allocations::AllocResult;
+use crate::benchmarks::runner::{self, Serde};
+use crate::benchmarks::typed::SerdeFormat;
+use crate::benchmarks::{StatResult, ThroughputResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub struct MessagePack;
+
+impl SerdeFormat for MessagePack {
+    const LABEL: &'static str = "MessagePack";
+
+    fn to_vec<T: Serialize>(value: &T) -> Vec<u8> {
+        rmp_serde::to_vec_named(value).unwrap()
+    }
+
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> T {
+        rmp_serde::from_slice(bytes).unwrap()
+    }
+}
+
+pub fn run_msgpack_benchmark() -> (Vec<StatResult>, ThroughputResult) {
+    runner::run::<Serde<MessagePack>>(4013)
+}
+
+pub fn run_msgpack_allocations() -> Vec<AllocResult> {
+    runner::allocations::<Serde<MessagePack>>()
+}
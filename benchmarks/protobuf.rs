@@ -1,5 +1,5 @@
-use crate::benchmarks::{calc_stats, proto, scenarios, Scenario, StatResult, ThroughputResult};
-use bytes::BytesMut;
+use crate::benchmarks::allocations::{self, AllocResult};
+use crate::benchmarks::{calc_stats, iterations, proto, scenarios, Scenario, StatResult, ThroughputResult};
 use prost::Message;
 use std::collections::BTreeMap;
 use std::io::{Read, Write};
@@ -24,11 +24,21 @@ pub fn run_protobuf_benchmark() -> (Vec<StatResult>, ThroughputResult) {
     (combined, net.1)
 }
 
+pub fn run_protobuf_allocations() -> Vec<AllocResult> {
+    scenarios()
+        .iter()
+        .map(|s| {
+            let proto = to_proto(s);
+            allocations::profile(s, || encode_proto(&proto), |buf| decode_proto(s.name, buf))
+        })
+        .collect()
+}
+
 fn run_pure() -> Vec<StatResult> {
     let mut results = Vec::new();
     for scenario in scenarios() {
-        let encode_samples = bench_encode(&scenario, 5_000);
-        let decode_samples = bench_decode(&scenario, 5_000);
+        let encode_samples = bench_encode(&scenario, iterations(5_000));
+        let decode_samples = bench_decode(&scenario, iterations(5_000));
         let (enc_avg, enc_min, enc_max, enc_p95, enc_p99) = calc_stats(encode_samples);
         let (dec_avg, dec_min, dec_max, dec_p95, dec_p99) = calc_stats(decode_samples);
 
@@ -56,11 +66,9 @@ fn run_pure() -> Vec<StatResult> {
 fn run_network() -> (Vec<StatResult>, ThroughputResult) {
     let listener = TcpListener::bind("127.0.0.1:4012").expect("bind proto");
     let _server = thread::spawn(move || {
-        for stream in listener.incoming() {
-            if let Ok(mut s) = stream {
-                s.set_nodelay(true).ok();
-                thread::spawn(move || handle_client(&mut s));
-            }
+        for mut s in listener.incoming().flatten() {
+            s.set_nodelay(true).ok();
+            thread::spawn(move || handle_client(&mut s));
         }
     });
     std::thread::sleep(std::time::Duration::from_millis(100));
@@ -71,7 +79,7 @@ fn run_network() -> (Vec<StatResult>, ThroughputResult) {
     for scenario in &scens {
         let mut stream = TcpStream::connect("127.0.0.1:4012").expect("connect proto");
         stream.set_nodelay(true).expect("set_nodelay");
-        let (avg, min, max, p95, p99) = bench_round_trip(&mut stream, scenario, 200);
+        let (avg, min, max, p95, p99) = bench_round_trip(&mut stream, scenario, iterations(200));
         let size = encoded_size(scenario) + 4; // length prefix
         results.push(StatResult {
             scenario: format!("{} (net)", scenario.name),
@@ -196,7 +204,7 @@ fn encoded_size(s: &Scenario) -> usize {
 }
 
 fn encode_proto(msg: &ProtoMsg) -> Vec<u8> {
-    let mut buf = BytesMut::with_capacity(512);
+    let mut buf = Vec::with_capacity(512);
     match msg {
         ProtoMsg::Game(m) => m.encode(&mut buf).unwrap(),
         ProtoMsg::Api(m) => m.encode(&mut buf).unwrap(),
@@ -205,7 +213,7 @@ fn encode_proto(msg: &ProtoMsg) -> Vec<u8> {
         ProtoMsg::Stock(m) => m.encode(&mut buf).unwrap(),
         ProtoMsg::Test(m) => m.encode(&mut buf).unwrap(),
     }
-    buf.to_vec()
+    buf
 }
 
 fn decode_proto(name: &str, buf: &[u8]) -> ProtoMsg {
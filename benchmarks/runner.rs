@@ -0,0 +1,192 @@
+//! Shared runner for the typed formats: the same pure encode/decode timings,
+//! length-prefixed TCP round trips and throughput test as the BiWi, JSON and
+//! Protobuf runners, over `Typed` payloads
+
+use crate::benchmarks::allocations::{self, AllocResult};
+use crate::benchmarks::typed::{SerdeFormat, Typed};
+use crate::benchmarks::{calc_stats, iterations, scenarios, Scenario, StatResult, ThroughputResult};
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Instant;
+
+/// How one format turns a scenario payload into bytes and back
+pub trait Codec {
+    const LABEL: &'static str;
+    fn encode(message: &Typed) -> Vec<u8>;
+    /// Decode the payload of scenario `name`
+    fn decode(name: &str, bytes: &[u8]) -> Typed;
+}
+
+/// `Codec` for any serde format
+pub struct Serde<F>(PhantomData<F>);
+
+impl<F: SerdeFormat> Codec for Serde<F> {
+    const LABEL: &'static str = F::LABEL;
+
+    fn encode(message: &Typed) -> Vec<u8> {
+        message.encode::<F>()
+    }
+
+    fn decode(name: &str, bytes: &[u8]) -> Typed {
+        Typed::decode::<F>(name, bytes)
+    }
+}
+
+pub fn run<C: Codec>(port: u16) -> (Vec<StatResult>, ThroughputResult) {
+    let pure = run_pure::<C>();
+    let net = run_network::<C>(port);
+    let mut combined = pure;
+    combined.extend(net.0);
+    (combined, net.1)
+}
+
+pub fn allocations<C: Codec>() -> Vec<AllocResult> {
+    scenarios()
+        .iter()
+        .map(|s| {
+            let message = Typed::from_scenario(s);
+            allocations::profile(s, || C::encode(&message), |buf| C::decode(s.name, buf))
+        })
+        .collect()
+}
+
+fn run_pure<C: Codec>() -> Vec<StatResult> {
+    let mut results = Vec::new();
+    for scenario in scenarios() {
+        let (enc_avg, enc_min, enc_max, enc_p95, enc_p99) = calc_stats(bench_encode::<C>(&scenario, iterations(5_000)));
+        let (dec_avg, dec_min, dec_max, dec_p95, dec_p99) = calc_stats(bench_decode::<C>(&scenario, iterations(5_000)));
+        results.push(StatResult {
+            scenario: format!("{} (pure)", scenario.name),
+            avg_ms: enc_avg + dec_avg,
+            min_ms: enc_min + dec_min,
+            max_ms: enc_max + dec_max,
+            p95_ms: enc_p95 + dec_p95,
+            p99_ms: enc_p99 + dec_p99,
+            size_bytes: C::encode(&Typed::from_scenario(&scenario)).len(),
+        });
+    }
+    results
+}
+
+fn run_network<C: Codec>(port: u16) -> (Vec<StatResult>, ThroughputResult) {
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr).unwrap_or_else(|e| panic!("bind {}: {}", C::LABEL, e));
+    let _server = thread::spawn(move || {
+        for mut s in listener.incoming().flatten() {
+            s.set_nodelay(true).ok();
+            thread::spawn(move || handle_client(&mut s));
+        }
+    });
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    let mut results = Vec::new();
+    for scenario in &scenarios() {
+        let mut stream = TcpStream::connect(&addr).expect("connect");
+        stream.set_nodelay(true).expect("set_nodelay");
+        let (avg, min, max, p95, p99) = bench_round_trip::<C>(&mut stream, scenario, iterations(200));
+        results.push(StatResult {
+            scenario: format!("{} (net)", scenario.name),
+            avg_ms: avg,
+            min_ms: min,
+            max_ms: max,
+            p95_ms: p95,
+            p99_ms: p99,
+            size_bytes: C::encode(&Typed::from_scenario(scenario)).len() + 4, // length prefix
+        });
+    }
+
+    let mut stream = TcpStream::connect(&addr).expect("connect throughput");
+    stream.set_nodelay(true).expect("set_nodelay");
+    let throughput = throughput_test::<C>(&mut stream, 1_000);
+    (results, throughput)
+}
+
+/// Echo each length-prefixed frame back unchanged
+fn handle_client(stream: &mut TcpStream) {
+    let mut buf = Vec::with_capacity(8 * 1024);
+    loop {
+        let mut len_buf = [0u8; 4];
+        if stream.read_exact(&mut len_buf).is_err() {
+            break;
+        }
+        buf.resize(u32::from_be_bytes(len_buf) as usize, 0);
+        if stream.read_exact(&mut buf).is_err() {
+            break;
+        }
+        if stream.write_all(&len_buf).is_err() || stream.write_all(&buf).is_err() {
+            break;
+        }
+    }
+}
+
+fn bench_encode<C: Codec>(s: &Scenario, iterations: usize) -> Vec<f64> {
+    let message = Typed::from_scenario(s);
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let _ = C::encode(&message);
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    samples
+}
+
+fn bench_decode<C: Codec>(s: &Scenario, iterations: usize) -> Vec<f64> {
+    let buf = C::encode(&Typed::from_scenario(s));
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let _ = C::decode(s.name, &buf);
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    samples
+}
+
+fn write_frame(stream: &mut TcpStream, buf: &[u8]) {
+    stream.write_all(&(buf.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(buf).unwrap();
+}
+
+fn read_frame(stream: &mut TcpStream, buf: &mut Vec<u8>) -> bool {
+    let mut len_buf = [0u8; 4];
+    if stream.read_exact(&mut len_buf).is_err() {
+        return false;
+    }
+    buf.resize(u32::from_be_bytes(len_buf) as usize, 0);
+    stream.read_exact(buf).is_ok()
+}
+
+fn bench_round_trip<C: Codec>(stream: &mut TcpStream, s: &Scenario, iterations: usize) -> (f64, f64, f64, f64, f64) {
+    let message = Typed::from_scenario(s);
+    let mut resp = Vec::new();
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        write_frame(stream, &C::encode(&message));
+        assert!(read_frame(stream, &mut resp), "{} echo closed", C::LABEL);
+        let _ = C::decode(s.name, &resp);
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    calc_stats(samples)
+}
+
+fn throughput_test<C: Codec>(stream: &mut TcpStream, messages: usize) -> ThroughputResult {
+    let scenario = &scenarios()[0];
+    let message = Typed::from_scenario(scenario);
+
+    let start = Instant::now();
+    for _ in 0..messages {
+        write_frame(stream, &C::encode(&message));
+    }
+    let mut resp = Vec::new();
+    let mut received = 0usize;
+    while received < messages && read_frame(stream, &mut resp) {
+        let _ = C::decode(scenario.name, &resp);
+        received += 1;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    let throughput = messages as f64 / (elapsed / 1000.0);
+    ThroughputResult { label: C::LABEL, throughput, total_time_ms: elapsed }
+}
@@ -0,0 +1,140 @@
+//! Payloads shared by every benchmark: the comparison harness in this directory and
+//! the Criterion benches in `benches/`.
+
+use biwi::BiWiValue;
+use serde_json::json;
+
+#[derive(Clone)]
+pub struct Scenario {
+    pub name: &'static str,
+    pub payload: serde_json::Value,
+}
+
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "Game State Update",
+            payload: json!({
+                "playerId": "player_12345",
+                "position": {"x": 123.456, "y": 789.012, "z": 45.678},
+                "rotation": {"pitch": 45.5, "yaw": 180.0, "roll": 0.0},
+                "health": 95,
+                "mana": 120,
+                "stamina": 85,
+                "equipment": ["sword", "shield", "boots"],
+                "status": "running",
+                "timestamp": now_ms(),
+            }),
+        },
+        Scenario {
+            name: "API Response (User Data)",
+            payload: json!({
+                "id": "user_98765",
+                "username": "alice_wonderland",
+                "email": "alice@example.com",
+                "profile": {
+                    "displayName": "Alice W.",
+                    "avatar": "https://example.com/avatar.jpg",
+                    "bio": "Software engineer and gaming enthusiast",
+                    "followers": 1523,
+                    "following": 342
+                },
+                "preferences": {
+                    "notifications": true,
+                    "theme": "dark",
+                    "language": "en-US"
+                },
+                "verified": true,
+                "createdAt": "2020-05-15T10:30:00Z",
+                "lastLogin": now_ms(),
+            }),
+        },
+        Scenario {
+            name: "IoT Sensor Data",
+            payload: json!({
+                "deviceId": "sensor_001",
+                "location": "warehouse_b",
+                "readings": [
+                    {"timestamp": now_ms() - 3000, "temperature": 22.5, "humidity": 45.2},
+                    {"timestamp": now_ms() - 2000, "temperature": 22.6, "humidity": 45.1},
+                    {"timestamp": now_ms() - 1000, "temperature": 22.4, "humidity": 45.3},
+                    {"timestamp": now_ms(), "temperature": 22.5, "humidity": 45.2}
+                ],
+                "status": "healthy",
+                "batteryLevel": 87,
+                "signalStrength": -42,
+            }),
+        },
+        Scenario {
+            name: "Chat Message",
+            payload: json!({
+                "messageId": "msg_550e8400",
+                "conversationId": "conv_12345",
+                "senderId": "user_001",
+                "senderName": "Bob",
+                "text": "Hey Alice! Did you see the new game update? The graphics are amazing!",
+                "timestamp": now_ms(),
+                "reactions": {"👍": 5, "❤️": 3, "😂": 1},
+                "mentions": ["Alice", "Charlie"],
+                "edited": false
+            }),
+        },
+        Scenario {
+            name: "Stock Market Tick",
+            payload: json!({
+                "symbol": "AAPL",
+                "price": 182.45,
+                "priceChange": 1.23,
+                "percentChange": 0.68,
+                "volume": 52345600,
+                "bid": 182.40,
+                "ask": 182.50,
+                "high": 183.50,
+                "low": 181.20,
+                "open": 181.22,
+                "close": 182.45,
+                "timestamp": now_ms(),
+                "exchange": "NASDAQ",
+            }),
+        },
+    ]
+}
+
+/// The BiWi value for a scenario payload: integers narrow to Int32 where they fit,
+/// other numbers take the smallest float that holds them
+pub fn json_to_biwi(value: &serde_json::Value) -> BiWiValue {
+    match value {
+        serde_json::Value::Null => BiWiValue::Null,
+        serde_json::Value::Bool(b) => BiWiValue::Boolean(*b),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
+                    BiWiValue::Int32(i as i32)
+                } else {
+                    BiWiValue::Int64(i)
+                }
+            } else if let Some(f) = n.as_f64() {
+                BiWiValue::number(f)
+            } else {
+                BiWiValue::Null
+            }
+        }
+        serde_json::Value::String(s) => BiWiValue::from(s.as_str()),
+        serde_json::Value::Array(arr) => {
+            BiWiValue::Array(arr.iter().map(json_to_biwi).collect())
+        }
+        serde_json::Value::Object(map) => {
+            let mut obj = std::collections::HashMap::new();
+            for (k, v) in map {
+                obj.insert(k.clone(), json_to_biwi(v));
+            }
+            BiWiValue::Object(obj)
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_millis() as i64
+}
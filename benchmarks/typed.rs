@@ -0,0 +1,255 @@
+//! Typed scenario payloads for the schema-driven serde formats (MessagePack, CBOR,
+//! bincode) and FlatBuffers. The structs mirror `messages.proto`, so every typed
+//! format encodes the same fields with the same widths as Protobuf.
+
+use crate::benchmarks::Scenario;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameStateUpdate {
+    pub player_id: String,
+    pub position: Vec3,
+    pub rotation: Rotation,
+    pub health: i32,
+    pub mana: i32,
+    pub stamina: i32,
+    pub equipment: Vec<String>,
+    pub status: String,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rotation {
+    pub pitch: f32,
+    pub yaw: f32,
+    pub roll: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiResponse {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub profile: Profile,
+    pub preferences: Preferences,
+    pub verified: bool,
+    pub created_at: String,
+    pub last_login: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub display_name: String,
+    pub avatar: String,
+    pub bio: String,
+    pub followers: i32,
+    pub following: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub notifications: bool,
+    pub theme: String,
+    pub language: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IoTSensorData {
+    pub device_id: String,
+    pub location: String,
+    pub readings: Vec<Reading>,
+    pub status: String,
+    pub battery_level: i32,
+    pub signal_strength: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+    pub timestamp: i64,
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub sender_id: String,
+    pub sender_name: String,
+    pub text: String,
+    pub timestamp: i64,
+    pub reactions: BTreeMap<String, i32>,
+    pub mentions: Vec<String>,
+    pub edited: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StockTick {
+    pub symbol: String,
+    pub price: f64,
+    pub price_change: f32,
+    pub percent_change: f32,
+    pub volume: i64,
+    pub bid: f32,
+    pub ask: f32,
+    pub high: f32,
+    pub low: f32,
+    pub open: f32,
+    pub close: f32,
+    pub timestamp: i64,
+    pub exchange: String,
+}
+
+/// One scenario's payload as its typed struct
+#[derive(Clone, Debug, PartialEq)]
+pub enum Typed {
+    Game(GameStateUpdate),
+    Api(ApiResponse),
+    IoT(IoTSensorData),
+    Chat(ChatMessage),
+    Stock(StockTick),
+}
+
+/// A serde data format, as used by one comparison runner
+pub trait SerdeFormat {
+    const LABEL: &'static str;
+    fn to_vec<T: Serialize>(value: &T) -> Vec<u8>;
+    fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> T;
+}
+
+impl Typed {
+    pub fn from_scenario(s: &Scenario) -> Self {
+        let p = &s.payload;
+        let string = |v: &serde_json::Value| v.as_str().unwrap_or_default().to_string();
+        let strings = |v: &serde_json::Value| {
+            v.as_array().map(|arr| arr.iter().filter_map(|v| v.as_str().map(str::to_string)).collect()).unwrap_or_default()
+        };
+        let int = |v: &serde_json::Value| v.as_i64().unwrap_or(0) as i32;
+        let float = |v: &serde_json::Value| v.as_f64().unwrap_or(0.0) as f32;
+        match s.name {
+            "Game State Update" => Typed::Game(GameStateUpdate {
+                player_id: string(&p["playerId"]),
+                position: Vec3 { x: float(&p["position"]["x"]), y: float(&p["position"]["y"]), z: float(&p["position"]["z"]) },
+                rotation: Rotation {
+                    pitch: float(&p["rotation"]["pitch"]),
+                    yaw: float(&p["rotation"]["yaw"]),
+                    roll: float(&p["rotation"]["roll"]),
+                },
+                health: int(&p["health"]),
+                mana: int(&p["mana"]),
+                stamina: int(&p["stamina"]),
+                equipment: strings(&p["equipment"]),
+                status: string(&p["status"]),
+                timestamp: p["timestamp"].as_i64().unwrap_or(0),
+            }),
+            "API Response (User Data)" => {
+                let profile = &p["profile"];
+                let prefs = &p["preferences"];
+                Typed::Api(ApiResponse {
+                    id: string(&p["id"]),
+                    username: string(&p["username"]),
+                    email: string(&p["email"]),
+                    profile: Profile {
+                        display_name: string(&profile["displayName"]),
+                        avatar: string(&profile["avatar"]),
+                        bio: string(&profile["bio"]),
+                        followers: int(&profile["followers"]),
+                        following: int(&profile["following"]),
+                    },
+                    preferences: Preferences {
+                        notifications: prefs["notifications"].as_bool().unwrap_or(false),
+                        theme: string(&prefs["theme"]),
+                        language: string(&prefs["language"]),
+                    },
+                    verified: p["verified"].as_bool().unwrap_or(false),
+                    created_at: string(&p["createdAt"]),
+                    last_login: p["lastLogin"].as_i64().unwrap_or(0),
+                })
+            }
+            "IoT Sensor Data" => Typed::IoT(IoTSensorData {
+                device_id: string(&p["deviceId"]),
+                location: string(&p["location"]),
+                readings: p["readings"]
+                    .as_array()
+                    .map(|arr| {
+                        arr.iter()
+                            .map(|r| Reading {
+                                timestamp: r["timestamp"].as_i64().unwrap_or(0),
+                                temperature: float(&r["temperature"]),
+                                humidity: float(&r["humidity"]),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                status: string(&p["status"]),
+                battery_level: int(&p["batteryLevel"]),
+                signal_strength: int(&p["signalStrength"]),
+            }),
+            "Chat Message" => Typed::Chat(ChatMessage {
+                message_id: string(&p["messageId"]),
+                conversation_id: string(&p["conversationId"]),
+                sender_id: string(&p["senderId"]),
+                sender_name: string(&p["senderName"]),
+                text: string(&p["text"]),
+                timestamp: p["timestamp"].as_i64().unwrap_or(0),
+                reactions: p["reactions"]
+                    .as_object()
+                    .map(|map| map.iter().map(|(k, v)| (k.clone(), int(v))).collect())
+                    .unwrap_or_default(),
+                mentions: strings(&p["mentions"]),
+                edited: p["edited"].as_bool().unwrap_or(false),
+            }),
+            _ => Typed::Stock(StockTick {
+                symbol: string(&p["symbol"]),
+                price: p["price"].as_f64().unwrap_or(0.0),
+                price_change: float(&p["priceChange"]),
+                percent_change: float(&p["percentChange"]),
+                volume: p["volume"].as_i64().unwrap_or(0),
+                bid: float(&p["bid"]),
+                ask: float(&p["ask"]),
+                high: float(&p["high"]),
+                low: float(&p["low"]),
+                open: float(&p["open"]),
+                close: float(&p["close"]),
+                timestamp: p["timestamp"].as_i64().unwrap_or(0),
+                exchange: string(&p["exchange"]),
+            }),
+        }
+    }
+
+    pub fn encode<F: SerdeFormat>(&self) -> Vec<u8> {
+        match self {
+            Typed::Game(m) => F::to_vec(m),
+            Typed::Api(m) => F::to_vec(m),
+            Typed::IoT(m) => F::to_vec(m),
+            Typed::Chat(m) => F::to_vec(m),
+            Typed::Stock(m) => F::to_vec(m),
+        }
+    }
+
+    /// Decode the payload of scenario `name`
+    pub fn decode<F: SerdeFormat>(name: &str, bytes: &[u8]) -> Self {
+        match name {
+            "Game State Update" => Typed::Game(F::from_slice(bytes)),
+            "API Response (User Data)" => Typed::Api(F::from_slice(bytes)),
+            "IoT Sensor Data" => Typed::IoT(F::from_slice(bytes)),
+            "Chat Message" => Typed::Chat(F::from_slice(bytes)),
+            _ => Typed::Stock(F::from_slice(bytes)),
+        }
+    }
+}
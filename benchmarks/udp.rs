@@ -1,10 +1,34 @@
 //! BiWi UDP Benchmark
-//! Compares UDP transport performance with packet loss recovery
-//! Includes comprehensive network statistics
+//! Round trips between a `BiWiUdpClient` and an echoing `BiWiUdpServer` on loopback.
+//! Traffic goes through a relay that drops a share of the datagrams in each direction,
+//! so the latency, retransmission and throughput figures include loss recovery.
 
-use biwi::{BiWiMessage, BiWiValue};
-use crate::benchmarks::{calc_stats, scenarios, Scenario, StatResult, ThroughputResult};
-use std::time::Instant;
+use biwi::network::PACKET_HEADER_SIZE;
+use biwi::{BiWiMessage, BiWiUdpClient, BiWiUdpServer, ConnectionStats};
+use crate::benchmarks::scenarios::json_to_biwi;
+use crate::benchmarks::{calc_stats, iterations, scenarios, Scenario, StatResult, ThroughputResult};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Share of datagrams the relay drops, unless `--udp-loss` set another
+const DEFAULT_LOSS_PERCENT: f64 = 1.0;
+
+/// How long a round trip may take, retransmissions included, before it counts as failed
+const ROUND_TRIP_TIMEOUT: Duration = Duration::from_secs(2);
+
+static LOSS_PERCENT: OnceLock<f64> = OnceLock::new();
+
+pub fn set_loss_percent(percent: f64) {
+    let _ = LOSS_PERCENT.set(percent);
+}
+
+fn loss_percent() -> f64 {
+    LOSS_PERCENT.get().copied().unwrap_or(DEFAULT_LOSS_PERCENT)
+}
 
 /// UDP Network Statistics
 #[derive(Clone, Debug)]
@@ -12,186 +36,257 @@ pub struct UdpNetworkStats {
     pub avg_latency_ms: f64,
     pub min_latency_ms: f64,
     pub max_latency_ms: f64,
-    pub jitter_ms: f64,           // Standard deviation of latency
-    pub packet_loss_percent: f64,  // Simulated loss percentage
-    pub retransmissions: usize,
+    pub jitter_ms: f64,           // Standard deviation of round-trip latency
+    pub packet_loss_percent: f64,  // Datagrams the relay dropped, both directions
+    pub retransmissions: usize,   // Client and server together
     pub duplicate_packets: usize,
     pub out_of_order_packets: usize,
-    pub bytes_sent: usize,
+    pub gaps: usize,              // Packets that skipped ahead of the next expected sequence
+    pub max_reorder_distance: u32, // In sequences, the larger of client and server
+    pub bytes_sent: usize,        // By the client, headers, ACKs and retransmissions included
     pub bytes_received: usize,
-    pub effective_throughput_mbps: f64,
+    pub effective_throughput_mbps: f64, // Echoed message bytes per second
 }
 
 pub fn run_udp_benchmark() -> (Vec<StatResult>, ThroughputResult, Vec<UdpNetworkStats>) {
-    run_pure_with_network_stats()
-}
-
-fn run_pure_with_network_stats() -> (Vec<StatResult>, ThroughputResult, Vec<UdpNetworkStats>) {
     let scenarios = scenarios();
     let mut results = Vec::new();
     let mut network_stats = Vec::new();
 
-    println!("\n=== BiWi UDP Network Statistics ===\n");
+    println!("\n=== BiWi UDP Network Statistics ({:.1}% injected loss) ===\n", loss_percent());
 
     for scenario in &scenarios {
-        let encode_samples = benchmark_encode_local(scenario, 5_000);
-        let decode_samples = benchmark_decode_local(scenario, 5_000);
-        let (enc_avg, enc_min, enc_max, enc_p95, enc_p99) = calc_stats(encode_samples);
-        let (dec_avg, dec_min, dec_max, dec_p95, dec_p99) = calc_stats(decode_samples);
-
-        let combined_avg = enc_avg + dec_avg;
-        let combined_min = enc_min + dec_min;
-        let combined_max = enc_max + dec_max;
-        let combined_p95 = enc_p95 + dec_p95;
-        let combined_p99 = enc_p99 + dec_p99;
-
-        // Size includes UDP header (13 bytes)
-        let size = message_size(scenario) + 13;
-
-        // Simulate network conditions and calculate stats
-        let net_stats = simulate_network_conditions(scenario, 1000);
-        network_stats.push(net_stats.clone());
-        
+        let (result, net_stats) = match round_trips(scenario, iterations(200)) {
+            Ok(measured) => measured,
+            Err(e) => {
+                eprintln!("UDP benchmark for {} failed: {}", scenario.name, e);
+                continue;
+            }
+        };
+
         println!("Scenario: {}", scenario.name);
-        println!("  Message Size: {} bytes (+ 13 byte UDP header = {} bytes)", 
-                 message_size(scenario), size);
+        println!("  Message Size: {} bytes (+ {} byte UDP header = {} bytes)",
+                 message_size(scenario), PACKET_HEADER_SIZE, result.size_bytes);
         println!("  Latency: {:.4}ms avg, {:.4}ms min, {:.4}ms max, {:.4}ms jitter (p95: {:.4}ms)",
                  net_stats.avg_latency_ms, net_stats.min_latency_ms, net_stats.max_latency_ms,
-                 net_stats.jitter_ms, combined_p95);
+                 net_stats.jitter_ms, result.p95_ms);
         println!("  Packet Loss: {:.2}%", net_stats.packet_loss_percent);
         println!("  Retransmissions: {}", net_stats.retransmissions);
+        println!("  Duplicates: {}, Out of order: {} (max distance {}), Gaps: {}",
+                 net_stats.duplicate_packets, net_stats.out_of_order_packets,
+                 net_stats.max_reorder_distance, net_stats.gaps);
         println!("  Effective Throughput: {:.2} Mbps", net_stats.effective_throughput_mbps);
         println!("  Bytes (sent/received): {} / {}", net_stats.bytes_sent, net_stats.bytes_received);
         println!();
 
-        results.push(StatResult {
-            scenario: format!("{} (UDP)", scenario.name),
-            avg_ms: combined_avg,
-            min_ms: combined_min,
-            max_ms: combined_max,
-            p95_ms: combined_p95,
-            p99_ms: combined_p99,
-            size_bytes: size,
-        });
-    }
-
-    // Throughput test with network stats
-    let start = Instant::now();
-    let msg = create_message(&scenarios[0]);
-    let msg_size = message_size(&scenarios[0]);
-    let message_count = 10_000;
-
-    for _ in 0..message_count {
-        let _ = msg.to_vec();
+        results.push(result);
+        network_stats.push(net_stats);
     }
 
-    let total_bytes = (msg_size + 13) * message_count;
-    let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
-    let throughput = total_bytes as f64 / elapsed_ms / 1000.0;
-
+    let throughput = throughput_test(&scenarios[0], 1_000).unwrap_or_else(|e| {
+        eprintln!("UDP throughput test failed: {}", e);
+        ThroughputResult { label: "BiWi UDP", throughput: 0.0, total_time_ms: 0.0 }
+    });
     println!("=== UDP Throughput Test ===");
-    println!("Messages sent: {}", message_count);
-    println!("Total bytes: {} bytes ({:.2} MB)", total_bytes, total_bytes as f64 / (1024.0 * 1024.0));
-    println!("Time elapsed: {:.2}ms", elapsed_ms);
-    println!("Raw throughput: {:.2} msg/s ({:.2} Mbps)", throughput / (msg_size as f64), 
-             total_bytes as f64 / elapsed_ms);
+    println!("Throughput: {:.2} msg/s over {:.2}ms", throughput.throughput, throughput.total_time_ms);
     println!();
 
-    (
-        results,
-        ThroughputResult {
-            label: "BiWi UDP",
-            throughput,
-            total_time_ms: elapsed_ms,
-        },
-        network_stats,
-    )
+    (results, throughput, network_stats)
 }
 
-fn simulate_network_conditions(scenario: &Scenario, num_packets: usize) -> UdpNetworkStats {
-    let msg_size = message_size(scenario);
-    let packet_size = msg_size + 13; // UDP header
-    
-    // Simulate realistic network conditions
-    // LAN: ~1ms latency, 0.1% loss
-    // Internet: ~20-50ms latency, 0.5-2% loss
-    // Mobile: ~50-100ms latency, 2-5% loss
-    
-    let base_latency = 5.0; // 5ms base latency
-    let latency_variation = 2.0; // ±2ms jitter
-    
-    let mut latencies = Vec::with_capacity(num_packets);
-    let mut packet_loss_count = 0;
-    let mut retransmissions = 0;
-    
-    // Generate latency samples with jitter
-    for i in 0..num_packets {
-        // Simulate network jitter (normal distribution approximation)
-        let jitter = ((i as f64 * 7919.0) % 100.0 - 50.0) / 25.0; // Pseudo-random
-        let latency = (base_latency + jitter * latency_variation).max(0.5);
-        latencies.push(latency);
-        
-        // Simulate packet loss (0.5% chance)
-        if (i * 7919) % 1000 < 5 {
-            packet_loss_count += 1;
-            retransmissions += 2; // Average 2 retries per lost packet
+/// Time `count` send-and-echo round trips of one scenario over a fresh session
+fn round_trips(scenario: &Scenario, count: usize) -> io::Result<(StatResult, UdpNetworkStats)> {
+    let session = Session::open(loss_percent())?;
+    let msg = create_message(scenario);
+    let size = message_size(scenario);
+
+    let mut latencies = Vec::with_capacity(count);
+    let started = Instant::now();
+    for _ in 0..count {
+        let start = Instant::now();
+        session.client.send(&msg)?;
+        if session.client.recv_timeout(ROUND_TRIP_TIMEOUT).is_ok() {
+            latencies.push(start.elapsed().as_secs_f64() * 1000.0);
         }
     }
-    
-    let (avg_latency, min_latency, max_latency, _, _) = calc_stats(latencies.clone());
-    
-    // Calculate jitter (standard deviation)
-    let mean = avg_latency;
-    let variance: f64 = latencies.iter()
-        .map(|l| (l - mean).powi(2))
-        .sum::<f64>() / latencies.len() as f64;
-    let jitter = variance.sqrt();
-    
-    let packet_loss_percent = (packet_loss_count as f64 / num_packets as f64) * 100.0;
-    
-    // Calculate effective throughput accounting for retransmissions
-    let total_packets_sent = num_packets + retransmissions;
-    let bytes_sent = total_packets_sent * packet_size;
-    let bytes_received = num_packets * packet_size;
-    let total_time_ms = avg_latency * num_packets as f64;
-    let effective_throughput_mbps = (bytes_received as f64 / total_time_ms) * 8.0 / 1000.0;
-    
-    UdpNetworkStats {
-        avg_latency_ms: avg_latency,
-        min_latency_ms: min_latency,
-        max_latency_ms: max_latency,
+    let elapsed = started.elapsed().as_secs_f64();
+    if latencies.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "no echo came back"));
+    }
+    let echoed = latencies.len();
+    if echoed < count {
+        println!("{}: {} of {} round trips timed out", scenario.name, count - echoed, count);
+    }
+    let summary = session.close();
+
+    let (avg, min, max, p95, p99) = calc_stats(latencies.clone());
+    let jitter = (latencies.iter().map(|l| (l - avg).powi(2)).sum::<f64>() / latencies.len() as f64).sqrt();
+    let result = StatResult {
+        scenario: format!("{} (UDP)", scenario.name),
+        avg_ms: avg,
+        min_ms: min,
+        max_ms: max,
+        p95_ms: p95,
+        p99_ms: p99,
+        size_bytes: size + PACKET_HEADER_SIZE,
+    };
+    let net_stats = UdpNetworkStats {
+        avg_latency_ms: avg,
+        min_latency_ms: min,
+        max_latency_ms: max,
         jitter_ms: jitter,
-        packet_loss_percent,
-        retransmissions,
-        duplicate_packets: 0,
-        out_of_order_packets: 0,
-        bytes_sent,
-        bytes_received,
-        effective_throughput_mbps,
+        packet_loss_percent: summary.relay.loss_percent(),
+        retransmissions: (summary.client.retransmissions + summary.server.retransmissions) as usize,
+        duplicate_packets: (summary.client.duplicates + summary.server.duplicates) as usize,
+        out_of_order_packets: (summary.client.out_of_order + summary.server.out_of_order) as usize,
+        gaps: (summary.client.gaps + summary.server.gaps) as usize,
+        max_reorder_distance: summary.client.max_reorder_distance.max(summary.server.max_reorder_distance),
+        bytes_sent: summary.client.bytes_sent as usize,
+        bytes_received: summary.client.bytes_received as usize,
+        effective_throughput_mbps: (echoed * size) as f64 * 8.0 / elapsed / 1_000_000.0,
+    };
+    Ok((result, net_stats))
+}
+
+/// Send `messages` back to back, then collect echoes until they stop coming. Timed to
+/// the last echo; echoes that never arrive are reported rather than waited for.
+fn throughput_test(scenario: &Scenario, messages: usize) -> io::Result<ThroughputResult> {
+    let session = Session::open(loss_percent())?;
+    let msg = create_message(scenario);
+
+    let start = Instant::now();
+    for _ in 0..messages {
+        session.client.send(&msg)?;
+    }
+    let mut received = 0usize;
+    let mut elapsed = 0.0;
+    while received < messages && session.client.recv_timeout(ROUND_TRIP_TIMEOUT).is_ok() {
+        received += 1;
+        elapsed = start.elapsed().as_secs_f64() * 1000.0;
     }
+    session.close();
+    if received < messages {
+        println!("UDP throughput test: {} of {} echoes arrived", received, messages);
+    }
+
+    Ok(ThroughputResult {
+        label: "BiWi UDP",
+        throughput: received as f64 / (elapsed / 1000.0),
+        total_time_ms: elapsed,
+    })
 }
 
-fn benchmark_encode_local(s: &Scenario, iterations: usize) -> Vec<f64> {
-    let mut samples = Vec::with_capacity(iterations);
-    for _ in 0..iterations {
-        let msg = create_message(s);
-        let start = Instant::now();
-        let _ = msg.to_vec();
-        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+/// A client connected through a lossy relay to an echo server, each on its own thread
+struct Session {
+    client: BiWiUdpClient,
+    stop: Arc<AtomicBool>,
+    relay: JoinHandle<RelayCounts>,
+    server: JoinHandle<Option<ConnectionStats>>,
+}
+
+struct SessionSummary {
+    relay: RelayCounts,
+    client: ConnectionStats,
+    server: ConnectionStats,
+}
+
+impl Session {
+    fn open(loss_percent: f64) -> io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0)?;
+        let server_addr = server.local_addr()?;
+        let server = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut peer = None;
+                while !stop.load(Ordering::Relaxed) {
+                    if let Some((id, message)) = server.recv_packet() {
+                        let _ = server.send_to(&id, &message);
+                        peer = Some(id);
+                    }
+                }
+                peer.and_then(|id| server.connection_stats(&id))
+            })
+        };
+        let (relay_addr, relay) = spawn_relay(server_addr, loss_percent / 100.0, Arc::clone(&stop))?;
+        match BiWiUdpClient::connect(&relay_addr.to_string()) {
+            Ok(client) => Ok(Session { client, stop, relay, server }),
+            Err(e) => {
+                stop.store(true, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Stop the relay and server before the client disconnects, so the server still
+    /// has the session's stats
+    fn close(self) -> SessionSummary {
+        let Session { client, stop, relay, server } = self;
+        stop.store(true, Ordering::Relaxed);
+        SessionSummary {
+            relay: relay.join().unwrap_or_default(),
+            client: client.connection_stats(),
+            server: server.join().ok().flatten().unwrap_or_default(),
+        }
     }
-    samples
 }
 
-fn benchmark_decode_local(s: &Scenario, iterations: usize) -> Vec<f64> {
-    let msg = create_message(s);
-    let buffer = msg.to_vec();
-    let mut samples = Vec::with_capacity(iterations);
-    for _ in 0..iterations {
-        let start = Instant::now();
-        let _ = BiWiMessage::from_buffer(&buffer).unwrap();
-        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+#[derive(Default)]
+struct RelayCounts {
+    forwarded: u64,
+    dropped: u64,
+}
+
+impl RelayCounts {
+    fn loss_percent(&self) -> f64 {
+        let total = self.forwarded + self.dropped;
+        if total == 0 {
+            return 0.0;
+        }
+        self.dropped as f64 / total as f64 * 100.0
     }
-    samples
+}
+
+/// Forward datagrams between one client and `server`, dropping each with probability
+/// `loss`. Returns the address clients should connect to.
+fn spawn_relay(server: SocketAddr, loss: f64, stop: Arc<AtomicBool>) -> io::Result<(SocketAddr, JoinHandle<RelayCounts>)> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.set_read_timeout(Some(Duration::from_millis(20)))?;
+    let addr = socket.local_addr()?;
+    let relay = thread::spawn(move || {
+        let mut counts = RelayCounts::default();
+        let mut client = None;
+        // Fixed seed, so runs drop the same share in the same pattern
+        let mut rng = 0x2545_F491_4F6C_DD1Du64;
+        let mut buf = vec![0u8; 65536];
+        while !stop.load(Ordering::Relaxed) {
+            let Ok((n, from)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            let to = if from == server {
+                match client {
+                    Some(client) => client,
+                    None => continue,
+                }
+            } else {
+                client = Some(from);
+                server
+            };
+            // xorshift64
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let draw = (rng >> 11) as f64 / (1u64 << 53) as f64;
+            if draw < loss {
+                counts.dropped += 1;
+                continue;
+            }
+            counts.forwarded += 1;
+            let _ = socket.send_to(&buf[..n], to);
+        }
+        counts
+    });
+    Ok((addr, relay))
 }
 
 fn create_message(s: &Scenario) -> BiWiMessage {
@@ -200,38 +295,6 @@ fn create_message(s: &Scenario) -> BiWiMessage {
     msg
 }
 
-fn encoded_size(s: &Scenario) -> usize {
-    create_message(s).to_vec().len() + 13 // +13 for UDP header
-}
-
 fn message_size(s: &Scenario) -> usize {
     create_message(s).to_vec().len()
 }
-
-fn json_to_biwi(v: &serde_json::Value) -> BiWiValue {
-    use serde_json::Value;
-    match v {
-        Value::Null => BiWiValue::Null,
-        Value::Bool(b) => BiWiValue::Boolean(*b),
-        Value::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                BiWiValue::Int64(i)
-            } else if let Some(f) = n.as_f64() {
-                BiWiValue::Float64(f)
-            } else {
-                BiWiValue::String(n.to_string())
-            }
-        }
-        Value::String(s) => BiWiValue::String(s.clone()),
-        Value::Array(arr) => {
-            BiWiValue::Array(arr.iter().map(json_to_biwi).collect())
-        }
-        Value::Object(obj) => {
-            let mut map = std::collections::HashMap::new();
-            for (k, v) in obj {
-                map.insert(k.clone(), json_to_biwi(v));
-            }
-            BiWiValue::Object(map)
-        }
-    }
-}
@@ -0,0 +1,374 @@
+//! Python bindings for BiWi
+//! `BiWiMessage` and `BiWiValue` for building and parsing messages, and `UdpClient`
+//! for talking to a BiWi UDP server. Field values convert to and from plain Python
+//! objects: `None`, `bool`, `int`, `float`, `str`, `bytes`, `list` and `dict` (with
+//! `str` keys). `int`s become 32-bit integers when they fit and 64-bit ones
+//! otherwise, `float`s 64-bit floats; wrap a value in `BiWiValue` to pick the type.
+
+// pyo3's generated wrappers trip this on every `PyResult` method
+#![allow(clippy::useless_conversion)]
+
+use ::biwi::{BiWiMessage, BiWiUdpClient, BiWiValue};
+use pyo3::exceptions::{PyKeyError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Convert a value to the matching Python object
+fn to_py(py: Python<'_>, value: &BiWiValue) -> PyResult<PyObject> {
+    Ok(match value {
+        BiWiValue::Null => py.None(),
+        BiWiValue::Boolean(value) => value.into_py(py),
+        BiWiValue::Int32(value) => value.into_py(py),
+        BiWiValue::Int64(value) => value.into_py(py),
+        BiWiValue::Float32(value) => value.into_py(py),
+        BiWiValue::Float64(value) => value.into_py(py),
+        BiWiValue::SmallString(value) => value.as_str().into_py(py),
+        BiWiValue::String(value) => value.into_py(py),
+        BiWiValue::Binary(value) => PyBytes::new_bound(py, value).into_any().unbind(),
+        BiWiValue::Array(items) => {
+            let list = PyList::empty_bound(py);
+            for item in items {
+                list.append(to_py(py, item)?)?;
+            }
+            list.into_any().unbind()
+        }
+        BiWiValue::Object(entries) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in entries {
+                dict.set_item(key, to_py(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        BiWiValue::Int32Array(items) => PyList::new_bound(py, items).into_any().unbind(),
+        BiWiValue::Int64Array(items) => PyList::new_bound(py, items).into_any().unbind(),
+        BiWiValue::Float32Array(items) => PyList::new_bound(py, items).into_any().unbind(),
+        BiWiValue::Float64Array(items) => PyList::new_bound(py, items).into_any().unbind(),
+    })
+}
+
+/// Convert a Python object (or a `BiWiValue`) to a value
+fn from_py(object: &Bound<'_, PyAny>) -> PyResult<BiWiValue> {
+    if let Ok(value) = object.downcast::<PyValue>() {
+        return Ok(value.borrow().0.clone());
+    }
+    if object.is_none() {
+        return Ok(BiWiValue::Null);
+    }
+    // bool before int: Python's bool is an int
+    if let Ok(value) = object.downcast::<pyo3::types::PyBool>() {
+        return Ok(BiWiValue::Boolean(value.is_true()));
+    }
+    if let Ok(value) = object.downcast::<pyo3::types::PyInt>() {
+        return Ok(match value.extract::<i32>() {
+            Ok(value) => BiWiValue::Int32(value),
+            Err(_) => BiWiValue::Int64(value.extract()?),
+        });
+    }
+    if let Ok(value) = object.downcast::<pyo3::types::PyFloat>() {
+        return Ok(BiWiValue::Float64(value.value()));
+    }
+    if let Ok(value) = object.downcast::<pyo3::types::PyString>() {
+        return Ok(BiWiValue::from(value.to_cow()?.as_ref()));
+    }
+    if let Ok(value) = object.downcast::<PyBytes>() {
+        return Ok(BiWiValue::Binary(value.as_bytes().to_vec()));
+    }
+    if let Ok(value) = object.downcast::<pyo3::types::PyByteArray>() {
+        return Ok(BiWiValue::Binary(value.to_vec()));
+    }
+    if let Ok(list) = object.downcast::<PyList>() {
+        return Ok(BiWiValue::Array(list.iter().map(|item| from_py(&item)).collect::<PyResult<_>>()?));
+    }
+    if let Ok(tuple) = object.downcast::<pyo3::types::PyTuple>() {
+        return Ok(BiWiValue::Array(tuple.iter().map(|item| from_py(&item)).collect::<PyResult<_>>()?));
+    }
+    if let Ok(dict) = object.downcast::<PyDict>() {
+        let mut entries = HashMap::with_capacity(dict.len());
+        for (key, value) in dict.iter() {
+            entries.insert(key.extract::<String>()?, from_py(&value)?);
+        }
+        return Ok(BiWiValue::Object(entries));
+    }
+    Err(PyTypeError::new_err(format!("Can't convert {} to a BiWi value", object.get_type().name()?)))
+}
+
+/// A typed BiWi value, for when the default conversion picks the wrong type
+#[pyclass(name = "BiWiValue", module = "biwi", frozen)]
+#[derive(Clone)]
+struct PyValue(BiWiValue);
+
+#[pymethods]
+impl PyValue {
+    #[staticmethod]
+    fn null() -> Self {
+        Self(BiWiValue::Null)
+    }
+
+    #[staticmethod]
+    fn boolean(value: bool) -> Self {
+        Self(BiWiValue::Boolean(value))
+    }
+
+    #[staticmethod]
+    fn int32(value: i32) -> Self {
+        Self(BiWiValue::Int32(value))
+    }
+
+    #[staticmethod]
+    fn int64(value: i64) -> Self {
+        Self(BiWiValue::Int64(value))
+    }
+
+    #[staticmethod]
+    fn float32(value: f32) -> Self {
+        Self(BiWiValue::Float32(value))
+    }
+
+    #[staticmethod]
+    fn float64(value: f64) -> Self {
+        Self(BiWiValue::Float64(value))
+    }
+
+    #[staticmethod]
+    fn string(value: &str) -> Self {
+        Self(BiWiValue::from(value))
+    }
+
+    #[staticmethod]
+    fn binary(value: &[u8]) -> Self {
+        Self(BiWiValue::Binary(value.to_vec()))
+    }
+
+    /// Convert any supported Python object
+    #[staticmethod]
+    fn of(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        from_py(value).map(Self)
+    }
+
+    /// The type's name: "Null", "Int32", "String", ...
+    #[getter]
+    fn kind(&self) -> &'static str {
+        match self.0 {
+            BiWiValue::Null => "Null",
+            BiWiValue::Boolean(_) => "Boolean",
+            BiWiValue::Int32(_) => "Int32",
+            BiWiValue::Int64(_) => "Int64",
+            BiWiValue::Float32(_) => "Float32",
+            BiWiValue::Float64(_) => "Float64",
+            BiWiValue::SmallString(_) | BiWiValue::String(_) => "String",
+            BiWiValue::Binary(_) => "Binary",
+            BiWiValue::Array(_) => "Array",
+            BiWiValue::Object(_) => "Object",
+            BiWiValue::Int32Array(_) => "Int32Array",
+            BiWiValue::Int64Array(_) => "Int64Array",
+            BiWiValue::Float32Array(_) => "Float32Array",
+            BiWiValue::Float64Array(_) => "Float64Array",
+        }
+    }
+
+    /// The value as a plain Python object
+    #[getter]
+    fn value(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_py(py, &self.0)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("BiWiValue.{}({})", self.kind(), to_py(py, &self.0)?.bind(py).repr()?))
+    }
+}
+
+/// A BiWi message: values keyed by u32 field ID
+#[pyclass(name = "BiWiMessage", module = "biwi")]
+#[derive(Clone)]
+struct PyMessage(BiWiMessage);
+
+#[pymethods]
+impl PyMessage {
+    /// An empty message, or one holding `fields` (a dict of field ID to value)
+    #[new]
+    #[pyo3(signature = (fields = None))]
+    fn new(fields: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut message = BiWiMessage::new();
+        for (field_id, value) in fields.into_iter().flat_map(|fields| fields.iter()) {
+            message.set_field(field_id.extract()?, from_py(&value)?);
+        }
+        Ok(Self(message))
+    }
+
+    /// Decode a message; raises ValueError if `data` isn't one
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        BiWiMessage::from_buffer(data).map(Self).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.0.to_vec())
+    }
+
+    /// The field's value as a Python object, or `default` if it isn't set
+    #[pyo3(signature = (field_id, default = None))]
+    fn get(&self, py: Python<'_>, field_id: u32, default: Option<PyObject>) -> PyResult<PyObject> {
+        match self.0.get_field(field_id) {
+            Some(value) => to_py(py, value),
+            None => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    /// The field's value with its BiWi type, or None
+    fn value(&self, field_id: u32) -> Option<PyValue> {
+        self.0.get_field(field_id).cloned().map(PyValue)
+    }
+
+    fn set(&mut self, field_id: u32, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.0.set_field(field_id, from_py(value)?);
+        Ok(())
+    }
+
+    /// Remove a field, returning its value or None
+    fn remove(&mut self, py: Python<'_>, field_id: u32) -> PyResult<PyObject> {
+        match self.0.remove_field(field_id) {
+            Some(value) => to_py(py, &value),
+            None => Ok(py.None()),
+        }
+    }
+
+    /// Field IDs in ascending order
+    fn field_ids(&self) -> Vec<u32> {
+        self.0.field_ids()
+    }
+
+    /// The fields as a dict of field ID to Python object
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        for (field_id, value) in self.0.fields() {
+            dict.set_item(field_id, to_py(py, value)?)?;
+        }
+        Ok(dict)
+    }
+
+    fn __getitem__(&self, py: Python<'_>, field_id: u32) -> PyResult<PyObject> {
+        match self.0.get_field(field_id) {
+            Some(value) => to_py(py, value),
+            None => Err(PyKeyError::new_err(field_id)),
+        }
+    }
+
+    fn __setitem__(&mut self, field_id: u32, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.set(field_id, value)
+    }
+
+    fn __delitem__(&mut self, field_id: u32) -> PyResult<()> {
+        match self.0.remove_field(field_id) {
+            Some(_) => Ok(()),
+            None => Err(PyKeyError::new_err(field_id)),
+        }
+    }
+
+    fn __contains__(&self, field_id: u32) -> bool {
+        self.0.get_field(field_id).is_some()
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.fields().len()
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("BiWiMessage({})", self.to_dict(py)?.repr()?))
+    }
+}
+
+/// A client session with a BiWi UDP server. Blocking calls release the GIL.
+#[pyclass(name = "UdpClient", module = "biwi")]
+struct PyUdpClient {
+    /// `None` once closed
+    client: Mutex<Option<BiWiUdpClient>>,
+}
+
+impl PyUdpClient {
+    fn with<T>(&self, f: impl FnOnce(&BiWiUdpClient) -> io::Result<T>) -> PyResult<T> {
+        let client = self.client.lock().unwrap();
+        let client = client.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Client is closed"))?;
+        Ok(f(client)?)
+    }
+}
+
+#[pymethods]
+impl PyUdpClient {
+    /// Connect to `addr` ("host:port"), encrypting the session if `psk` is given
+    #[new]
+    #[pyo3(signature = (addr, psk = None))]
+    fn new(py: Python<'_>, addr: &str, psk: Option<&[u8]>) -> PyResult<Self> {
+        let client = py.allow_threads(|| match psk {
+            Some(psk) => BiWiUdpClient::connect_with_psk(addr, psk),
+            None => BiWiUdpClient::connect(addr),
+        })?;
+        Ok(Self { client: Mutex::new(Some(client)) })
+    }
+
+    /// Send a message reliably, without waiting for the server's ACK
+    fn send(&self, message: &PyMessage) -> PyResult<()> {
+        self.with(|client| client.send(&message.0).map(drop))
+    }
+
+    /// The next message from the server, waiting up to `timeout` seconds (forever if
+    /// None); None if nothing arrived in time
+    #[pyo3(signature = (timeout = None))]
+    fn recv(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<Option<PyMessage>> {
+        let received = py.allow_threads(|| {
+            self.with(|client| match timeout {
+                Some(timeout) => client.recv_timeout(Duration::from_secs_f64(timeout)),
+                None => client.recv(),
+            })
+        });
+        match received {
+            Ok(message) => Ok(Some(PyMessage(message))),
+            Err(e) if e.is_instance_of::<pyo3::exceptions::PyTimeoutError>(py) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Session ID the server issued
+    #[getter]
+    fn session_id(&self) -> PyResult<u64> {
+        self.with(|client| Ok(client.session_id()))
+    }
+
+    /// Wait up to `timeout` seconds for sent messages to be ACKed, then disconnect
+    #[pyo3(signature = (timeout = 1.0))]
+    fn close(&self, py: Python<'_>, timeout: f64) -> PyResult<()> {
+        let Some(client) = self.client.lock().unwrap().take() else {
+            return Ok(());
+        };
+        py.allow_threads(|| client.close(Duration::from_secs_f64(timeout)))?;
+        Ok(())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(&self, py: Python<'_>, _type: PyObject, _value: PyObject, _traceback: PyObject) -> PyResult<()> {
+        self.close(py, 1.0)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "biwi")]
+fn biwi_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyValue>()?;
+    module.add_class::<PyMessage>()?;
+    module.add_class::<PyUdpClient>()?;
+    Ok(())
+}
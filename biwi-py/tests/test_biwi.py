@@ -0,0 +1,39 @@
+"""Run with `maturin develop && pytest` from biwi-py/."""
+
+import socket
+
+import pytest
+
+from biwi import BiWiMessage, BiWiValue, UdpClient
+
+
+def test_message_roundtrip():
+    message = BiWiMessage({1: "player", 2: 42, 3: 1.5, 4: b"\x00\x01", 5: [1, None, True], 6: {"hp": 100}})
+    message[7] = BiWiValue.float32(0.5)
+    decoded = BiWiMessage.from_bytes(message.to_bytes())
+    assert decoded == message
+    assert decoded[1] == "player"
+    assert decoded.get(9, "missing") == "missing"
+    assert decoded.value(2).kind == "Int32"
+    assert decoded.value(7).kind == "Float32"
+    assert decoded.to_dict()[6] == {"hp": 100}
+    assert 5 in decoded and len(decoded) == 7
+    del decoded[5]
+    assert decoded.field_ids() == [1, 2, 3, 4, 6, 7]
+
+
+def test_bad_input():
+    with pytest.raises(ValueError):
+        BiWiMessage.from_bytes(BiWiMessage({1: "cut short"}).to_bytes()[:-1])
+    with pytest.raises(TypeError):
+        BiWiMessage({1: object()})
+
+
+def test_client_times_out_without_server():
+    # Nothing listens here, so the handshake fails
+    probe = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    probe.bind(("127.0.0.1", 0))
+    addr = "127.0.0.1:%d" % probe.getsockname()[1]
+    probe.close()
+    with pytest.raises(OSError):
+        UdpClient(addr)
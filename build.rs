@@ -5,4 +5,30 @@ fn main() {
     prost_build::Config::new()
         .btree_map(["."])
         .compile_protos(&[proto_path], &["benchmarks"]).expect("protos");
+
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Generate the C header for the `extern "C"` API in src/ffi.rs into OUT_DIR, where
+/// a test checks the committed include/biwi.h against it. Builds only write the
+/// committed copy when BIWI_REGENERATE_HEADER is set.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=BIWI_REGENERATE_HEADER");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("set by cargo");
+    let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).expect("cbindgen.toml");
+    let header = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{crate_dir}/src/ffi.rs"))
+        .generate()
+        .expect("C header");
+    header.write_to_file(format!("{out_dir}/biwi.h"));
+    if std::env::var_os("BIWI_REGENERATE_HEADER").is_some() {
+        header.write_to_file(format!("{crate_dir}/include/biwi.h"));
+    }
 }
@@ -6,27 +6,19 @@ use std::collections::HashMap;
 fn main() {
     println!("=== BiWi Base System Example ===\n");
 
-    // Create a message
-    let mut msg = BiWiMessage::new();
-    
-    // Add various field types
-    msg.set_field(1, BiWiValue::String("Hello, BiWi!".to_string()));
-    msg.set_field(2, BiWiValue::Int32(42));
-    msg.set_field(3, BiWiValue::Float64(3.14159));
-    msg.set_field(4, BiWiValue::Boolean(true));
-    
-    // Add an array
-    msg.set_field(5, BiWiValue::Array(vec![
-        BiWiValue::Int32(1),
-        BiWiValue::Int32(2),
-        BiWiValue::Int32(3),
-    ]));
-    
-    // Add an object
+    // Create a message with the fluent builder
     let mut obj = HashMap::new();
-    obj.insert("name".to_string(), BiWiValue::String("Rust".to_string()));
+    obj.insert("name".to_string(), BiWiValue::from("Rust"));
     obj.insert("version".to_string(), BiWiValue::Int32(1));
-    msg.set_field(6, BiWiValue::Object(obj));
+
+    let msg = BiWiMessage::builder()
+        .field(1, "Hello, BiWi!")
+        .field(2, 42)
+        .field(3, std::f64::consts::PI)
+        .field(4, true)
+        .field(5, [1, 2, 3])
+        .field(6, obj)
+        .build();
 
     println!("Original message:");
     println!("  Field 1 (String): {:?}", msg.get_field(1));
@@ -1,11 +1,25 @@
 //! BiWi UDP Example
 //! Demonstrates UDP server and client with automatic packet loss recovery
 
-use biwi::{BiWiMessage, BiWiValue, BiWiUdpServer, BiWiUdpClient};
+use biwi::{BiWiMessage, BiWiServerHandler, BiWiValue, BiWiUdpServer, BiWiUdpClient, Connection};
 use std::io;
 use std::thread;
 use std::time::Duration;
 
+/// Echoes every message back to its sender
+struct EchoHandler;
+
+impl BiWiServerHandler for EchoHandler {
+    fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+        println!("[Server] Received from {}: {:?}", conn.id(), message.get_field(1));
+
+        // Echo back to client
+        if conn.reply(&message).is_ok() {
+            println!("[Server] Echoed back to client");
+        }
+    }
+}
+
 fn main() -> io::Result<()> {
     println!("=== BiWi UDP Echo Server & Client Example ===\n");
 
@@ -14,41 +28,30 @@ fn main() -> io::Result<()> {
     
     println!("[Server] Listening for messages...");
 
-    // Give server a moment to start
-    thread::sleep(Duration::from_millis(100));
+    // The server answers the client's handshake, so it runs on its own thread
+    thread::spawn(move || server.run(EchoHandler));
 
     // Create UDP client
     println!("[Client] Connecting to server...");
     let client = BiWiUdpClient::connect("127.0.0.1:9001")?;
-    println!("[Client] Connected!\n");
+    println!("[Client] Connected with session {:016x}\n", client.session_id());
 
     // Send and receive in a loop
     for i in 1..=3 {
         // Client sends message
         let mut msg = BiWiMessage::new();
         msg.set_field(1, BiWiValue::String(format!("Hello from client #{}", i)));
-        msg.set_field(2, BiWiValue::Int32(i as i32));
+        msg.set_field(2, BiWiValue::Int32(i));
         
         println!("[Client] Sending message {}...", i);
         client.send(&msg)?;
         
-        // Server receives and echoes back
-        thread::sleep(Duration::from_millis(50));
-        if let Some((client_id, server_msg)) = server.recv_packet() {
-            println!("[Server] Received from {}: {:?}", client_id, server_msg.get_field(1));
-            
-            // Echo back to client
-            server.send_to(&client_id, &server_msg)?;
-            println!("[Server] Echoed back to client");
-        }
-        
         // Client receives echo
-        thread::sleep(Duration::from_millis(50));
-        match client.try_recv() {
-            Some(response) => {
+        match client.recv_timeout(Duration::from_millis(500)) {
+            Ok(response) => {
                 println!("[Client] Received echo: {:?}\n", response.get_field(1));
             }
-            None => {
+            Err(_) => {
                 println!("[Client] No echo received (packet may have been lost)\n");
             }
         }
@@ -61,4 +64,3 @@ fn main() -> io::Result<()> {
     
     Ok(())
 }
-
@@ -0,0 +1,24 @@
+#![no_main]
+
+use biwi::{BiWiDecoder, BiWiDelta, BiWiMessage, BiWiMessageView};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BiWiDecoder::new(data).decode_all();
+
+    // Anything that decodes must re-encode and decode to the same message
+    if let Ok(message) = BiWiMessage::from_buffer(data) {
+        let reencoded = message.to_vec();
+        let again = BiWiMessage::from_buffer(&reencoded).expect("re-encoded message must decode");
+        assert_eq!(again.field_count(), message.field_count());
+
+        // The lazy view must agree with the eager decoder
+        let view = BiWiMessageView::new(data);
+        for (field_id, value) in message.iter() {
+            assert_eq!(view.get_field(field_id).expect("view must index a valid message").as_ref(), Some(value));
+        }
+    }
+
+    let _ = BiWiMessage::from_buffer_sparse(data);
+    let _ = BiWiDelta::from_buffer(data);
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use biwi::{BiWiDecoder, ChunkAssembler};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = BiWiDecoder::new(data);
+    let _ = decoder.decode_chunk_start();
+    while decoder.has_more() {
+        if decoder.decode_chunk_data().is_err() {
+            break;
+        }
+    }
+
+    // Feeding the same bytes whole or split must not panic either way
+    let _ = ChunkAssembler::new().feed(data);
+    let mut assembler = ChunkAssembler::new();
+    for part in data.chunks(7) {
+        if assembler.feed(part).is_err() {
+            break;
+        }
+    }
+});
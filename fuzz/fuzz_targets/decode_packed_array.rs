@@ -0,0 +1,13 @@
+#![no_main]
+
+use biwi::BiWiDecoder;
+use libfuzzer_sys::fuzz_target;
+
+// Packed arrays are reached through the ARRAY|0x80 type byte; prefixing it
+// makes every input exercise the element type, count and element paths.
+fuzz_target!(|data: &[u8]| {
+    let mut input = Vec::with_capacity(data.len() + 1);
+    input.push(0x88);
+    input.extend_from_slice(data);
+    let _ = BiWiDecoder::new(&input).decode_value();
+});
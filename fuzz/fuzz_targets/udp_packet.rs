@@ -0,0 +1,21 @@
+#![no_main]
+
+use biwi::{PacketManager, UdpPacket};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = UdpPacket::from_bytes(data) {
+        // Parsing a full header is lossless: serializing gives back the original bytes
+        assert_eq!(packet.to_bytes(), data);
+    }
+
+    // A session that negotiated compact headers reads either form, writing it back in full
+    let mut pm = PacketManager::new();
+    pm.set_compact_headers(true);
+    if let Ok(packet) = pm.decode(data) {
+        let bytes = packet.to_bytes();
+        let again = UdpPacket::from_bytes(&bytes).expect("full header must parse");
+        assert_eq!(again.to_bytes(), bytes);
+        assert!(data.ends_with(&packet.payload));
+    }
+});
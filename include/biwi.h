@@ -0,0 +1,262 @@
+/* BiWi C API. Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#ifndef BIWI_H
+#define BIWI_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Kind of value a `BiwiField` holds, and so which of its members is set
+ */
+typedef enum BiwiKind {
+  BIWI_KIND_NULL = 0,
+  /**
+   * In `boolean`
+   */
+  BIWI_KIND_BOOLEAN = 1,
+  /**
+   * In `integer`
+   */
+  BIWI_KIND_INTEGER = 2,
+  /**
+   * In `number`
+   */
+  BIWI_KIND_FLOAT = 3,
+  /**
+   * UTF-8 in `data`/`len`
+   */
+  BIWI_KIND_STRING = 4,
+  /**
+   * Bytes in `data`/`len`
+   */
+  BIWI_KIND_BINARY = 5,
+  /**
+   * JSON text in `data`/`len`
+   */
+  BIWI_KIND_ARRAY = 6,
+  /**
+   * JSON text in `data`/`len`
+   */
+  BIWI_KIND_OBJECT = 7,
+} BiwiKind;
+
+/**
+ * Result of a C API call
+ */
+typedef enum BiwiStatus {
+  BIWI_STATUS_OK = 0,
+  /**
+   * `biwi_decoder_next` has no more fields
+   */
+  BIWI_STATUS_END = 1,
+  /**
+   * A required pointer was NULL
+   */
+  BIWI_STATUS_NULL_POINTER = 2,
+  /**
+   * A string was not valid UTF-8
+   */
+  BIWI_STATUS_INVALID_UTF8 = 3,
+  /**
+   * A JSON value did not parse
+   */
+  BIWI_STATUS_INVALID_JSON = 4,
+  /**
+   * The input is not a valid BiWi message
+   */
+  BIWI_STATUS_DECODE = 5,
+} BiwiStatus;
+
+/**
+ * Reads the fields of one message in order
+ */
+typedef struct BiwiDecoder BiwiDecoder;
+
+/**
+ * Builds one message at a time
+ */
+typedef struct BiwiEncoder BiwiEncoder;
+
+/**
+ * One decoded field
+ */
+typedef struct BiwiField {
+  uint32_t field_id;
+  enum BiwiKind kind;
+  bool boolean;
+  int64_t integer;
+  double number;
+  /**
+   * Points into the decoder; valid until its next call
+   */
+  const uint8_t *data;
+  size_t len;
+} BiwiField;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The library version, NUL-terminated
+ */
+const char *biwi_version(void);
+
+/**
+ * A new encoder, to be released with `biwi_encoder_free`
+ */
+struct BiwiEncoder *biwi_encoder_new(void);
+
+/**
+ * Release an encoder and the bytes it handed out
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder. Neither it nor bytes from its
+ * `biwi_encoder_finish` may be used afterwards.
+ */
+void biwi_encoder_free(struct BiwiEncoder *encoder);
+
+/**
+ * Remove every field, to build the next message
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encoder_clear(struct BiwiEncoder *encoder);
+
+/**
+ * Set field `field_id` to null
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_null(struct BiwiEncoder *encoder, uint32_t field_id);
+
+/**
+ * Set field `field_id` to a boolean
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_bool(struct BiwiEncoder *encoder, uint32_t field_id, bool value);
+
+/**
+ * Set field `field_id` to a 32-bit integer
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_i32(struct BiwiEncoder *encoder, uint32_t field_id, int32_t value);
+
+/**
+ * Set field `field_id` to a 64-bit integer
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_i64(struct BiwiEncoder *encoder, uint32_t field_id, int64_t value);
+
+/**
+ * Set field `field_id` to a float
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_f32(struct BiwiEncoder *encoder, uint32_t field_id, float value);
+
+/**
+ * Set field `field_id` to a double
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder.
+ */
+enum BiwiStatus biwi_encode_f64(struct BiwiEncoder *encoder, uint32_t field_id, double value);
+
+/**
+ * A UTF-8 string of `len` bytes (no terminator needed)
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+ * bytes. The string is copied, so `data` only has to last for the call.
+ */
+enum BiwiStatus biwi_encode_string(struct BiwiEncoder *encoder,
+                                   uint32_t field_id,
+                                   const uint8_t *data,
+                                   size_t len);
+
+/**
+ * `len` bytes of binary data
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+ * bytes. The bytes are copied, so `data` only has to last for the call.
+ */
+enum BiwiStatus biwi_encode_binary(struct BiwiEncoder *encoder,
+                                   uint32_t field_id,
+                                   const uint8_t *data,
+                                   size_t len);
+
+/**
+ * Any value given as JSON text: arrays and objects nest, integers become 64-bit
+ * integers and other numbers doubles
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+ * bytes, which only have to last for the call.
+ */
+enum BiwiStatus biwi_encode_json(struct BiwiEncoder *encoder,
+                                 uint32_t field_id,
+                                 const uint8_t *data,
+                                 size_t len);
+
+/**
+ * Encode the fields set so far, pointing `out`/`out_len` at the bytes, which the
+ * encoder owns
+ *
+ * # Safety
+ * `encoder` must be NULL or a live encoder, and `out` and `out_len` NULL or
+ * writable. The bytes stay valid until the next call on `encoder` or its
+ * `biwi_encoder_free`, and must not be written to.
+ */
+enum BiwiStatus biwi_encoder_finish(struct BiwiEncoder *encoder,
+                                    const uint8_t **out,
+                                    size_t *out_len);
+
+/**
+ * A decoder over a copy of the `len` bytes at `data`; NULL if `data` is NULL. Release
+ * it with `biwi_decoder_free`.
+ *
+ * # Safety
+ * `data` must be NULL or readable for `len` bytes. They are copied, so the caller
+ * may release them as soon as this returns.
+ */
+struct BiwiDecoder *biwi_decoder_new(const uint8_t *data, size_t len);
+
+/**
+ * Release a decoder and the field data it handed out
+ *
+ * # Safety
+ * `decoder` must be NULL or a live decoder. Neither it nor pointers in a field from
+ * its `biwi_decoder_next` may be used afterwards.
+ */
+void biwi_decoder_free(struct BiwiDecoder *decoder);
+
+/**
+ * Decode the next field into `field`; `End` once there are none left
+ *
+ * # Safety
+ * `decoder` must be NULL or a live decoder, and `field` NULL or writable. Pointers
+ * written into `field` stay valid until the next call on `decoder` or its
+ * `biwi_decoder_free`.
+ */
+enum BiwiStatus biwi_decoder_next(struct BiwiDecoder *decoder, struct BiwiField *field);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* BIWI_H */
@@ -0,0 +1,411 @@
+//! BiWi Async UDP Client
+//! Tokio version of `BiWiUdpClient`, with background receive and retransmit tasks.
+//! Incoming messages are read with `recv()` or through the client's `Stream` implementation.
+
+use crate::async_server::{decode_messages, encode_all, RETRANSMIT_INTERVAL};
+use crate::congestion::CongestionController;
+use crate::client::{
+    accept_session, connect_request, session_packet_manager, CONNECT_ATTEMPTS, CONNECT_RETRY_INTERVAL, DEFAULT_KEEP_ALIVE_INTERVAL,
+};
+use crate::crypto::PacketCipher;
+use crate::message::BiWiMessage;
+use crate::network::{ConnectionStats, PacketManager, PacketType, Priority, RetransmitPolicy, SendMode, UdpPacket, DEFAULT_CHANNEL};
+use futures_core::Stream;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// BiWi UDP Client on tokio
+pub struct BiWiUdpClientAsync {
+    socket: Arc<UdpSocket>,
+    server_addr: SocketAddr,
+    packet_manager: Arc<Mutex<PacketManager>>,
+    incoming: UnboundedReceiver<BiWiMessage>,
+    tasks: Vec<JoinHandle<()>>,
+    session_id: u64,
+    keep_alive: Arc<Mutex<Option<Duration>>>,
+}
+
+impl BiWiUdpClientAsync {
+    /// Open a session with the server and start the background tasks
+    pub async fn connect(server_addr: &str) -> io::Result<Self> {
+        Self::establish(server_addr, None, &[]).await
+    }
+
+    /// Open a session, presenting `credentials` to the server's admission check (see
+    /// `ServerConfig::auth_callback`). They travel unencrypted, so send a token rather
+    /// than a password. Fails with `ConnectionRefused` if the server turns them down.
+    pub async fn connect_with_credentials(server_addr: &str, credentials: &[u8]) -> io::Result<Self> {
+        Self::establish(server_addr, None, credentials).await
+    }
+
+    /// Open a session encrypted with a key derived from `psk` (see `crypto`). Fails
+    /// with `PermissionDenied` if the server can't prove it holds the same key.
+    pub async fn connect_with_psk(server_addr: &str, psk: &[u8]) -> io::Result<Self> {
+        Self::establish(server_addr, Some(psk), &[]).await
+    }
+
+    async fn establish(server_addr: &str, psk: Option<&[u8]>, credentials: &[u8]) -> io::Result<Self> {
+        let server_addr: SocketAddr = server_addr
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid address"))?;
+
+        let bind_addr = if server_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+        let (session_id, cipher, accepted) = handshake(&socket, server_addr, psk, credentials).await?;
+        let packet_manager = Arc::new(Mutex::new(session_packet_manager(session_id, cipher, accepted)));
+        let (tx, rx) = unbounded_channel();
+        let keep_alive = Arc::new(Mutex::new(Some(DEFAULT_KEEP_ALIVE_INTERVAL)));
+
+        let tasks = vec![
+            tokio::spawn(receive_loop(Arc::clone(&socket), server_addr, session_id, Arc::clone(&packet_manager), tx.clone())),
+            tokio::spawn(retransmit_loop(
+                Arc::clone(&socket),
+                server_addr,
+                Arc::clone(&packet_manager),
+                tx.clone(),
+                Arc::clone(&keep_alive),
+            )),
+        ];
+
+        let client = Self {
+            socket,
+            server_addr,
+            packet_manager,
+            incoming: rx,
+            tasks,
+            session_id,
+            keep_alive,
+        };
+        // Confirms the session to the server straight away (and takes a first RTT sample)
+        client.ping().await?;
+        Ok(client)
+    }
+
+    /// Live RTT, traffic and loss counters for the connection to the server
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.packet_manager.lock().unwrap().stats()
+    }
+
+    /// Session ID the server issued during the handshake
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Wait up to `timeout` for every sent packet to be ACKed, then disconnect.
+    /// Returns `TimedOut` if packets were still un-ACKed; the session is closed either way.
+    pub async fn close(self, timeout: Duration) -> io::Result<()> {
+        let flushed = tokio::time::timeout(timeout, async {
+            while self.pending_ack_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .is_ok();
+
+        self.disconnect().await?;
+        if flushed {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "Packets still un-ACKed at close"))
+        }
+    }
+
+    /// Close the session; the background tasks stop when the client is dropped
+    pub async fn disconnect(&self) -> io::Result<()> {
+        let packet = self.packet_manager.lock().unwrap().encode(&UdpPacket::disconnect(self.session_id));
+        self.socket.send_to(&packet, self.server_addr).await?;
+        Ok(())
+    }
+
+    /// Deliver `ReliableOrdered` messages in sequence order, holding each for at most
+    /// `window` while an earlier one is missing (`None` delivers in arrival order)
+    pub fn set_reorder_window(&self, window: Option<Duration>) {
+        self.packet_manager.lock().unwrap().set_reorder_window(window);
+    }
+
+    /// Change how packets to the server are retransmitted: the ACK timeout, retries,
+    /// backoff and packets in flight (`RetransmitPolicy::default()` unless set)
+    pub fn set_retransmit_policy(&self, policy: RetransmitPolicy) {
+        self.packet_manager.lock().unwrap().set_retransmit_policy(policy);
+    }
+
+    /// Hold ACKs to the server up to `delay` so one covers several packets or rides on
+    /// a message going out; the retransmit task then runs at least that often. Zero,
+    /// the default, ACKs every reliable packet at once.
+    pub fn set_max_ack_delay(&self, delay: Duration) {
+        self.packet_manager.lock().unwrap().set_max_ack_delay(delay);
+    }
+
+    /// Cap the send rate with a congestion controller; packets over budget are queued
+    /// and released by the retransmit task (`None` sends immediately)
+    pub fn set_congestion_controller(&self, controller: Option<Box<dyn CongestionController>>) {
+        self.packet_manager.lock().unwrap().set_congestion_controller(controller);
+    }
+
+    /// Datagram size messages are currently fragmented to
+    pub fn mtu(&self) -> usize {
+        self.packet_manager.lock().unwrap().max_packet_size()
+    }
+
+    /// Fragment to `size`-byte datagrams instead of `MAX_PACKET_SIZE`
+    pub fn set_max_packet_size(&self, size: usize) {
+        self.packet_manager.lock().unwrap().set_max_packet_size(size);
+    }
+
+    /// Send a message to the server (reliable and ordered)
+    pub async fn send(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.send_with_mode(message, SendMode::default()).await
+    }
+
+    /// Send a message to the server with the given delivery guarantees
+    pub async fn send_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.send_on(DEFAULT_CHANNEL, message, mode).await
+    }
+
+    /// Send a message on `channel`, sequenced independently of other channels
+    pub async fn send_on(&self, channel: u8, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.send_message(channel, message, mode, Priority::Normal).await
+    }
+
+    /// Send a message (reliable and ordered) ahead of or behind other queued traffic
+    pub async fn send_with_priority(&self, message: &BiWiMessage, priority: Priority) -> io::Result<()> {
+        self.send_message(DEFAULT_CHANNEL, message, SendMode::default(), priority).await
+    }
+
+    async fn send_message(&self, channel: u8, message: &BiWiMessage, mode: SendMode, priority: Priority) -> io::Result<()> {
+        let packets = {
+            let msg_bytes = message.to_vec();
+            let mut pm = self.packet_manager.lock().unwrap();
+            pm.check_message_size(msg_bytes.len())?;
+            let packets = pm.create_packets_on(channel, &msg_bytes, mode);
+            let packets = pm.pace_with_priority(packets, priority);
+            encode_all(&mut pm, packets)
+        };
+        for packet in packets {
+            self.socket.send_to(&packet, self.server_addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next message from the server
+    pub async fn recv(&mut self) -> io::Result<BiWiMessage> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "Channel closed"))
+    }
+
+    /// Ping the server whenever nothing else has been sent for `interval` (default
+    /// `DEFAULT_KEEP_ALIVE_INTERVAL`); `None` stops the keep-alives
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        *self.keep_alive.lock().unwrap() = interval;
+    }
+
+    /// Send a ping (keep-alive); its round trip shows up in `last_rtt` once the Pong arrives
+    pub async fn ping(&self) -> io::Result<()> {
+        self.send_ping().await.map(drop)
+    }
+
+    /// Ping the server and wait up to `timeout` for the Pong, returning the round trip
+    pub async fn ping_rtt(&self, timeout: Duration) -> io::Result<Duration> {
+        let id = self.send_ping().await?;
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(rtt) = self.packet_manager.lock().unwrap().ping_rtt(id) {
+                    return rtt;
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No pong"))
+    }
+
+    /// Round trip of the most recently answered ping
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.packet_manager.lock().unwrap().last_rtt()
+    }
+
+    async fn send_ping(&self) -> io::Result<u32> {
+        let (id, ping) = {
+            let mut pm = self.packet_manager.lock().unwrap();
+            let ping = pm.create_ping_packet();
+            (ping.sequence, pm.encode(&ping))
+        };
+        self.socket.send_to(&ping, self.server_addr).await?;
+        Ok(id)
+    }
+
+    /// Number of sent packets still waiting for an ACK
+    pub fn pending_ack_count(&self) -> usize {
+        self.packet_manager.lock().unwrap().pending_ack_count()
+    }
+}
+
+impl Stream for BiWiUdpClientAsync {
+    type Item = BiWiMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+impl Drop for BiWiUdpClientAsync {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Send `Connect` until the server answers with a session ID. Returns it with the
+/// session's cipher and the handshake flags the server accepted.
+async fn handshake(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+    psk: Option<&[u8]>,
+    credentials: &[u8],
+) -> io::Result<(u64, Option<PacketCipher>, u32)> {
+    let mut buf = [0u8; 128];
+    let (connect, client_random) = connect_request(psk, credentials);
+
+    for _ in 0..CONNECT_ATTEMPTS {
+        socket.send_to(&connect.to_bytes(), server_addr).await?;
+        let answer = tokio::time::timeout(CONNECT_RETRY_INTERVAL, async {
+            loop {
+                let (n, addr) = socket.recv_from(&mut buf).await?;
+                if addr != server_addr {
+                    continue;
+                }
+                match UdpPacket::from_bytes(&buf[..n]) {
+                    Ok(packet) if packet.packet_type == PacketType::ConnectAck => {
+                        return accept_session(&packet, psk, &client_random).map(|(id, cipher)| (id, cipher, packet.flags));
+                    }
+                    Ok(packet) if packet.packet_type == PacketType::Disconnect => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionRefused,
+                            "Server refused the session",
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        if let Ok(result) = answer {
+            return result;
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"))
+}
+
+async fn receive_loop(
+    socket: Arc<UdpSocket>,
+    server_addr: SocketAddr,
+    session_id: u64,
+    packet_manager: Arc<Mutex<PacketManager>>,
+    tx: UnboundedSender<BiWiMessage>,
+) {
+    let mut buf = vec![0u8; 65536];
+
+    loop {
+        let Ok((n, addr)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        if addr != server_addr {
+            continue;
+        }
+        let (replies, messages) = {
+            let mut pm = packet_manager.lock().unwrap();
+            let Ok(mut packet) = pm.decode(&buf[..n]) else {
+                continue;
+            };
+            // Packets that fail to decrypt are forged, tampered with or from another session
+            if !pm.open(&mut packet) {
+                continue;
+            }
+            match packet.packet_type {
+                PacketType::Data => {
+                    let acks = if packet.send_mode().is_reliable() { pm.queue_ack(&packet) } else { Vec::new() };
+                    (encode_all(&mut pm, acks), decode_messages(pm.deliver(packet), None))
+                }
+                PacketType::Ack => {
+                    // ACKs free up send budget and may call for fast retransmits
+                    pm.handle_ack_packet(&packet);
+                    let released = pm.release_paced();
+                    (encode_all(&mut pm, released), Vec::new())
+                }
+                PacketType::Ping => {
+                    let mut pong = UdpPacket::pong(&packet);
+                    pong.tag_session(session_id);
+                    (vec![pm.encode(&pong)], Vec::new())
+                }
+                PacketType::Pong => {
+                    pm.handle_pong(&packet);
+                    (Vec::new(), Vec::new())
+                }
+                // Server closed the session; ending the loop closes `recv`
+                PacketType::Disconnect if packet.session_id() == Some(session_id) => return,
+                _ => (Vec::new(), Vec::new()),
+            }
+        };
+
+        for reply in replies {
+            let _ = socket.send_to(&reply, server_addr).await;
+        }
+        for message in messages {
+            if tx.send(message).is_err() {
+                // The client was dropped
+                return;
+            }
+        }
+    }
+}
+
+async fn retransmit_loop(
+    socket: Arc<UdpSocket>,
+    server_addr: SocketAddr,
+    packet_manager: Arc<Mutex<PacketManager>>,
+    tx: UnboundedSender<BiWiMessage>,
+    keep_alive: Arc<Mutex<Option<Duration>>>,
+) {
+    let mut every = RETRANSMIT_INTERVAL;
+
+    loop {
+        tokio::time::sleep(every).await;
+        let (outgoing, paced, released) = {
+            let mut pm = packet_manager.lock().unwrap();
+            // Delayed ACKs must not wait longer than they were allowed to
+            every = match pm.max_ack_delay() {
+                Duration::ZERO => RETRANSMIT_INTERVAL,
+                delay => RETRANSMIT_INTERVAL.min(delay),
+            };
+            let mut outgoing: Vec<UdpPacket> = pm.get_retransmit_packets().into_iter().map(|(packet, _)| packet).collect();
+            // Ping when idle so the server (and any NAT on the way) keeps the session
+            if keep_alive.lock().unwrap().is_some_and(|every| pm.idle_time() >= every) {
+                outgoing.push(pm.create_ping_packet());
+            }
+            let mut paced = pm.release_paced();
+            paced.extend(pm.take_due_acks());
+            (encode_all(&mut pm, outgoing), encode_all(&mut pm, paced), pm.flush_reorder())
+        };
+        for packet in paced {
+            let _ = socket.send_to(&packet, server_addr).await;
+        }
+        for message in decode_messages(released, None) {
+            let _ = tx.send(message);
+        }
+        for packet in outgoing {
+            let _ = socket.send_to(&packet, server_addr).await;
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! BiWi Async UDP Server
+//! Tokio version of `BiWiUdpServer`: a background task receives and ACKs packets,
+//! another retransmits un-ACKed packets, and decoded messages are delivered through
+//! `recv()` or the server's `Stream` implementation.
+
+use crate::compression::{decode_message, FieldTable};
+use crate::congestion::CongestionController;
+use crate::message::BiWiMessage;
+use crate::network::{ConnectionStats, PacketManager, PacketType, Priority, RetransmitPolicy, SendMode, UdpPacket, WirePacket, DEFAULT_CHANNEL};
+use crate::server::{
+    expire_sessions, find_session, handle_handshake, ClientConnection, ConnectionId, ServerConfig, ServerEvent, Shard,
+    CONNECTION_TIMEOUT,
+};
+use crate::shared::SharedMessage;
+use futures_core::Stream;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// How often the background task checks for packets to retransmit
+pub const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(50);
+
+type Connections = Arc<Mutex<HashMap<ConnectionId, ClientConnection>>>;
+
+/// BiWi UDP Server on tokio
+pub struct BiWiUdpServerAsync {
+    socket: Arc<UdpSocket>,
+    connections: Connections,
+    incoming: UnboundedReceiver<(ConnectionId, BiWiMessage)>,
+    events: UnboundedReceiver<ServerEvent>,
+    events_tx: UnboundedSender<ServerEvent>,
+    tasks: Vec<JoinHandle<()>>,
+    connection_timeout: Arc<Mutex<Duration>>,
+}
+
+impl BiWiUdpServerAsync {
+    /// Bind to `addr` and start the receive and retransmit tasks
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        Self::start(addr, None, ServerConfig::default()).await
+    }
+
+    /// Like `bind`, limiting and authenticating new sessions with `config`
+    pub async fn bind_with_config(addr: &str, config: ServerConfig) -> io::Result<Self> {
+        Self::start(addr, None, config).await
+    }
+
+    /// Like `bind`, but only accept sessions encrypted with a key derived from `psk`
+    /// (see `crypto`); clients must connect with the same key
+    pub async fn bind_with_psk(addr: &str, psk: &[u8]) -> io::Result<Self> {
+        Self::start(addr, Some(psk.to_vec()), ServerConfig::default()).await
+    }
+
+    async fn start(addr: &str, psk: Option<Vec<u8>>, config: ServerConfig) -> io::Result<Self> {
+        let handshake_timeout = config.handshake_timeout;
+        let tick_interval = config.tick_interval(RETRANSMIT_INTERVAL);
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let connections: Connections = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = unbounded_channel();
+        let (events_tx, events) = unbounded_channel();
+        let connection_timeout = Arc::new(Mutex::new(CONNECTION_TIMEOUT));
+
+        let tasks = vec![
+            tokio::spawn(receive_loop(
+                Arc::clone(&socket),
+                Arc::clone(&connections),
+                tx.clone(),
+                events_tx.clone(),
+                psk,
+                config,
+            )),
+            tokio::spawn(retransmit_loop(
+                Arc::clone(&socket),
+                Arc::clone(&connections),
+                tx.clone(),
+                events_tx.clone(),
+                Arc::clone(&connection_timeout),
+                handshake_timeout,
+                tick_interval,
+            )),
+        ];
+
+        Ok(Self {
+            socket,
+            connections,
+            incoming: rx,
+            events,
+            events_tx,
+            tasks,
+            connection_timeout,
+        })
+    }
+
+    /// Address the server is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Wait for the next complete message; `None` once the receive task has stopped
+    pub async fn recv(&mut self) -> Option<(ConnectionId, BiWiMessage)> {
+        self.incoming.recv().await
+    }
+
+    /// Wait for the next session lifecycle event
+    pub async fn recv_event(&mut self) -> Option<ServerEvent> {
+        self.events.recv().await
+    }
+
+    /// Drop sessions that send nothing for `timeout` instead of `CONNECTION_TIMEOUT`;
+    /// each one is reported as `ServerEvent::ClientTimedOut`
+    pub fn set_connection_timeout(&self, timeout: Duration) {
+        *self.connection_timeout.lock().unwrap() = timeout;
+    }
+
+    /// Close a client's session, telling the client with a `Disconnect`
+    pub async fn disconnect(&self, client_id: &str) -> io::Result<()> {
+        let conn = self.connections.lock().unwrap().remove(client_id);
+        let mut conn = conn.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        let _ = self.events_tx.send(ServerEvent::ClientDisconnected(conn.id));
+        let disconnect = conn.packet_manager.encode(&UdpPacket::disconnect(conn.session_id));
+        self.socket.send_to(&disconnect, conn.addr).await?;
+        Ok(())
+    }
+
+    /// Deliver a client's `ReliableOrdered` messages in sequence order, holding each
+    /// for at most `window` while an earlier one is missing (`None` delivers in arrival order)
+    pub fn set_reorder_window(&self, client_id: &str, window: Option<Duration>) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_reorder_window(window);
+        Ok(())
+    }
+
+    /// Change how packets to a client are retransmitted: its ACK timeout, retries,
+    /// backoff and packets in flight (`ServerConfig::retransmit_policy` by default)
+    pub fn set_retransmit_policy(&self, client_id: &str, policy: RetransmitPolicy) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_retransmit_policy(policy);
+        Ok(())
+    }
+
+    /// Cap the send rate to a client with a congestion controller (`None` sends immediately)
+    pub fn set_congestion_controller(
+        &self,
+        client_id: &str,
+        controller: Option<Box<dyn CongestionController>>,
+    ) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_congestion_controller(controller);
+        Ok(())
+    }
+
+    /// Fragment messages to a client into `size`-byte datagrams (default `MAX_PACKET_SIZE`)
+    pub fn set_max_packet_size(&self, client_id: &str, size: usize) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_max_packet_size(size);
+        Ok(())
+    }
+
+    /// Send a message to a specific client (reliable and ordered)
+    pub async fn send_to(&self, client_id: &str, message: &BiWiMessage) -> io::Result<()> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &message.to_vec(), SendMode::default(), Priority::Normal).await
+    }
+
+    /// Send a message to a specific client with the given delivery guarantees
+    pub async fn send_to_with_mode(&self, client_id: &str, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &message.to_vec(), mode, Priority::Normal).await
+    }
+
+    /// Send a message to a specific client on `channel`
+    pub async fn send_to_on(&self, client_id: &str, channel: u8, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.send_bytes(client_id, channel, &message.to_vec(), mode, Priority::Normal).await
+    }
+
+    /// Send a message (reliable and ordered) to a specific client ahead of or behind other queued traffic
+    pub async fn send_to_with_priority(&self, client_id: &str, message: &BiWiMessage, priority: Priority) -> io::Result<()> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &message.to_vec(), SendMode::default(), priority).await
+    }
+
+    /// Send an already-encoded shared message to a specific client
+    pub async fn send_shared(&self, client_id: &str, message: &SharedMessage) -> io::Result<()> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, message.as_bytes(), SendMode::default(), Priority::Normal).await
+    }
+
+    async fn send_bytes(
+        &self,
+        client_id: &str,
+        channel: u8,
+        msg_bytes: &[u8],
+        mode: SendMode,
+        priority: Priority,
+    ) -> io::Result<()> {
+        // Build the packets under the lock, send them after releasing it
+        let (addr, packets) = {
+            let mut conns = self.connections.lock().unwrap();
+            let conn = conns
+                .get_mut(client_id)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+            conn.packet_manager.check_message_size(msg_bytes.len())?;
+            let packets = conn.packet_manager.create_packets_on(channel, msg_bytes, mode);
+            let packets = conn.packet_manager.pace_with_priority(packets, priority);
+            (conn.addr, encode_all(&mut conn.packet_manager, packets))
+        };
+
+        for packet in packets {
+            self.socket.send_to(&packet, addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Broadcast a message to all connected clients (encoded once)
+    pub async fn broadcast(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.broadcast_bytes(&message.to_vec(), SendMode::default()).await
+    }
+
+    /// Broadcast a message to all connected clients with the given delivery guarantees
+    pub async fn broadcast_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.broadcast_bytes(&message.to_vec(), mode).await
+    }
+
+    /// Broadcast an already-encoded shared message to all connected clients
+    pub async fn broadcast_shared(&self, message: &SharedMessage) -> io::Result<()> {
+        self.broadcast_bytes(message.as_bytes(), SendMode::default()).await
+    }
+
+    async fn broadcast_bytes(&self, msg_bytes: &[u8], mode: SendMode) -> io::Result<()> {
+        let outgoing: Vec<_> = {
+            let mut conns = self.connections.lock().unwrap();
+            if let Some(conn) = conns.values().next() {
+                conn.packet_manager.check_message_size(msg_bytes.len())?;
+            }
+            conns
+                .values_mut()
+                .map(|conn| {
+                    let packets = conn.packet_manager.create_packets_with_mode(msg_bytes, mode);
+                    let packets = conn.packet_manager.pace(packets);
+                    (conn.addr, encode_all(&mut conn.packet_manager, packets))
+                })
+                .collect()
+        };
+
+        for (addr, packets) in outgoing {
+            for packet in packets {
+                self.socket.send_to(&packet, addr).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Live RTT, traffic and loss counters for a client's connection
+    pub fn connection_stats(&self, client_id: &str) -> Option<ConnectionStats> {
+        self.connections.lock().unwrap().get(client_id).map(|conn| conn.packet_manager.stats())
+    }
+
+    /// Get all connected clients
+    pub fn get_connections(&self) -> Vec<(ConnectionId, SocketAddr)> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, conn)| (id.clone(), conn.addr))
+            .collect()
+    }
+}
+
+impl Stream for BiWiUdpServerAsync {
+    type Item = (ConnectionId, BiWiMessage);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+impl Drop for BiWiUdpServerAsync {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn receive_loop(
+    socket: Arc<UdpSocket>,
+    connections: Connections,
+    tx: UnboundedSender<(ConnectionId, BiWiMessage)>,
+    events: UnboundedSender<ServerEvent>,
+    psk: Option<Vec<u8>>,
+    config: ServerConfig,
+) {
+    let mut buf = vec![0u8; 65536];
+
+    loop {
+        let Ok((n, addr)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let Ok(wire) = WirePacket::parse(&buf[..n]) else {
+            continue;
+        };
+
+        let (replies, messages) = {
+            let mut conns = connections.lock().unwrap();
+            if matches!(wire.packet.packet_type, PacketType::Connect | PacketType::Disconnect) {
+                let (reply, event) = handle_handshake(&mut conns, addr, &wire, psk.as_deref(), &config, Shard::WHOLE);
+                if let Some(event) = event {
+                    let _ = events.send(event);
+                }
+                (reply.iter().map(UdpPacket::to_bytes).collect(), Vec::new())
+            } else if let Some((conn, packet)) = find_session(&mut conns, addr, wire) {
+                let (replies, messages) = handle_session_packet(conn, packet);
+                let replies = encode_all(&mut conn.packet_manager, replies);
+                (replies, messages.into_iter().map(|message| (conn.id.clone(), message)).collect())
+            } else {
+                // Only established sessions get past the handshake
+                (Vec::new(), Vec::new())
+            }
+        };
+
+        for reply in replies {
+            let _ = socket.send_to(&reply, addr).await;
+        }
+        for message in messages {
+            if tx.send(message).is_err() {
+                // The server was dropped
+                return;
+            }
+        }
+    }
+}
+
+/// Replies and decoded messages for a packet from an established session
+fn handle_session_packet(
+    conn: &mut ClientConnection,
+    packet: UdpPacket,
+) -> (Vec<UdpPacket>, Vec<BiWiMessage>) {
+    match packet.packet_type {
+        PacketType::Data => {
+            let pm = &mut conn.packet_manager;
+            let acks = if packet.send_mode().is_reliable() { pm.queue_ack(&packet) } else { Vec::new() };
+            (acks, decode_messages(pm.deliver(packet), conn.fields.as_mut()))
+        }
+        PacketType::Ack => {
+            // ACKs free up send budget and may call for fast retransmits
+            conn.packet_manager.handle_ack_packet(&packet);
+            (conn.packet_manager.release_paced(), Vec::new())
+        }
+        PacketType::Ping => {
+            let pong = UdpPacket::pong(&packet);
+            (vec![pong], Vec::new())
+        }
+        _ => (Vec::new(), Vec::new()),
+    }
+}
+
+/// Decode delivered data packets, skipping any that aren't valid messages. Sessions
+/// with field compression resolve references through their `fields` table.
+pub(crate) fn decode_messages(packets: Vec<UdpPacket>, mut fields: Option<&mut FieldTable>) -> Vec<BiWiMessage> {
+    let mut messages = Vec::new();
+    for packet in &packets {
+        for message in packet.messages() {
+            if let Ok(message) = decode_message(message, fields.as_deref_mut()) {
+                messages.push(message);
+            }
+        }
+    }
+    messages
+}
+
+async fn retransmit_loop(
+    socket: Arc<UdpSocket>,
+    connections: Connections,
+    tx: UnboundedSender<(ConnectionId, BiWiMessage)>,
+    events: UnboundedSender<ServerEvent>,
+    connection_timeout: Arc<Mutex<Duration>>,
+    handshake_timeout: Duration,
+    tick_interval: Duration,
+) {
+    let mut interval = tokio::time::interval(tick_interval);
+
+    loop {
+        interval.tick().await;
+
+        let outgoing: Vec<_> = {
+            let timeout = *connection_timeout.lock().unwrap();
+            let mut conns = connections.lock().unwrap();
+            for event in expire_sessions(&mut conns, timeout, handshake_timeout, Instant::now()) {
+                let _ = events.send(event);
+            }
+            for conn in conns.values_mut() {
+                for message in decode_messages(conn.packet_manager.flush_reorder(), conn.fields.as_mut()) {
+                    let _ = tx.send((conn.id.clone(), message));
+                }
+            }
+            conns
+                .values_mut()
+                .map(|conn| {
+                    let mut packets: Vec<UdpPacket> =
+                        conn.packet_manager.get_retransmit_packets().into_iter().map(|(packet, _)| packet).collect();
+                    packets.extend(conn.packet_manager.release_paced());
+                    packets.extend(conn.packet_manager.take_due_acks());
+                    (conn.addr, encode_all(&mut conn.packet_manager, packets))
+                })
+                .collect()
+        };
+
+        for (addr, packets) in outgoing {
+            for packet in packets {
+                let _ = socket.send_to(&packet, addr).await;
+            }
+        }
+    }
+}
+
+/// Serialize packets for the wire while the connection is locked
+pub(crate) fn encode_all(packet_manager: &mut PacketManager, packets: Vec<UdpPacket>) -> Vec<Vec<u8>> {
+    packets.iter().map(|packet| packet_manager.encode(packet)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_client::BiWiUdpClientAsync;
+    use crate::encoder::BiWiValue;
+
+    #[tokio::test]
+    async fn test_async_echo() {
+        let mut server = BiWiUdpServerAsync::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut client = BiWiUdpClientAsync::connect(&addr.to_string()).await.unwrap();
+
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from("ping"));
+        msg.set_field(2, BiWiValue::Binary(vec![7; 5000]));
+        client.send(&msg).await.unwrap();
+
+        let (client_id, received) = server.recv().await.unwrap();
+        assert_eq!(received, msg);
+        server.send_to(&client_id, &received).await.unwrap();
+
+        let echoed = tokio::time::timeout(Duration::from_secs(5), client.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(echoed, msg);
+    }
+}
@@ -0,0 +1,64 @@
+//! BiWi Message Authentication
+//! HMAC-SHA256 framing so receivers can reject forged or tampered frames
+//! before spending any time decoding them.
+//!
+//! Frame layout: [payload][tag: 32 bytes]
+
+use crate::decoder::{DecodeError, DecodeResult};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the authentication tag appended to signed frames
+pub const TAG_LEN: usize = 32;
+
+fn mac(key: &[u8]) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    HmacSha256::new_from_slice(key).expect("HMAC accepts any key length")
+}
+
+/// Append an HMAC-SHA256 tag over `payload`
+pub fn sign(payload: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut mac = mac(key);
+    mac.update(payload);
+    let tag = mac.finalize().into_bytes();
+
+    let mut frame = Vec::with_capacity(payload.len() + TAG_LEN);
+    frame.extend_from_slice(payload);
+    frame.extend_from_slice(&tag);
+    frame
+}
+
+/// Check the tag on a signed frame and return the payload.
+/// The comparison is constant-time.
+pub fn verify<'a>(frame: &'a [u8], key: &[u8]) -> DecodeResult<&'a [u8]> {
+    let split = frame
+        .len()
+        .checked_sub(TAG_LEN)
+        .ok_or(DecodeError::InsufficientData("authentication tag"))?;
+    let (payload, tag) = frame.split_at(split);
+
+    let mut mac = mac(key);
+    mac.update(payload);
+    mac.verify_slice(tag).map_err(|_| DecodeError::AuthenticationFailed)?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let frame = sign(b"payload", b"secret");
+        assert_eq!(frame.len(), 7 + TAG_LEN);
+        assert_eq!(verify(&frame, b"secret").unwrap(), b"payload");
+
+        assert_eq!(verify(&frame, b"other key"), Err(DecodeError::AuthenticationFailed));
+        let mut tampered = frame.clone();
+        tampered[0] ^= 1;
+        assert_eq!(verify(&tampered, b"secret"), Err(DecodeError::AuthenticationFailed));
+        assert!(matches!(verify(&frame[..10], b"secret"), Err(DecodeError::InsufficientData(_))));
+    }
+}
@@ -0,0 +1,254 @@
+//! biwi-cli: convert, inspect, validate and send BiWi messages from the command line.
+//!
+//! Messages are read from BiWi files, or from JSON files (`.json`, in the serde form
+//! of `BiWiMessage`: `{"1": {"SmallString": "hi"}}`). `-` reads stdin or writes stdout.
+
+use biwi::{BiWiDecoder, BiWiMessage, BiWiUdpClient, BiWiUdpServer, MessageSpec, ValueKind};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: biwi-cli <command> [args]
+
+Commands:
+  encode <in.json> [out]         Convert JSON to BiWi
+  decode <in> [out]              Convert BiWi to JSON
+  inspect <in>                   Hex dump with each field annotated
+  validate <in> <schema.json>    Check a message against a schema
+  send <host:port> <in> [secs]   Send a message to a UDP server and print replies
+                                 for `secs` seconds (default 1)
+  listen <host:port>             Run a UDP server and print every message received
+  dissector [port...]            Print a Wireshark Lua dissector for BiWi on the
+                                 given UDP ports (none: use \"Decode As...\")
+
+Schemas are JSON: {\"deny_unknown\": true, \"fields\": {\"1\": {\"required\": true,
+\"kind\": \"string\", \"max_len\": 16}, \"2\": {\"kind\": \"number\", \"min\": 0, \"max\": 100}}}
+Kinds: null, boolean, integer, float, number, string, binary, array, object.
+";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["encode", input, rest @ ..] if rest.len() <= 1 => encode(input, rest.first().copied()),
+        ["decode", input, rest @ ..] if rest.len() <= 1 => decode(input, rest.first().copied()),
+        ["inspect", input] => inspect(input),
+        ["validate", input, schema] => validate(input, schema),
+        ["send", addr, input, rest @ ..] if rest.len() <= 1 => send(addr, input, rest.first().copied()),
+        ["listen", addr] => listen(addr),
+        ["dissector", ports @ ..] => dissector(ports),
+        _ => {
+            eprint!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("biwi-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn invalid(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        return Ok(buf);
+    }
+    fs::read(path)
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> io::Result<()> {
+    match path {
+        None | Some("-") => io::stdout().write_all(bytes),
+        Some(path) => fs::write(path, bytes),
+    }
+}
+
+/// Read a message from a BiWi file, or a JSON one if the name ends in `.json`
+fn read_message(path: &str) -> io::Result<BiWiMessage> {
+    let bytes = read_input(path)?;
+    if path.ends_with(".json") {
+        serde_json::from_slice(&bytes).map_err(invalid)
+    } else {
+        BiWiMessage::from_buffer(&bytes).map_err(invalid)
+    }
+}
+
+fn to_json(message: &BiWiMessage) -> String {
+    serde_json::to_string_pretty(message).expect("BiWiMessage serializes to JSON")
+}
+
+fn encode(input: &str, output: Option<&str>) -> io::Result<ExitCode> {
+    let message: BiWiMessage = serde_json::from_slice(&read_input(input)?).map_err(invalid)?;
+    write_output(output, &message.to_vec())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn decode(input: &str, output: Option<&str>) -> io::Result<ExitCode> {
+    let message = BiWiMessage::from_buffer(&read_input(input)?).map_err(invalid)?;
+    write_output(output, format!("{}\n", to_json(&message)).as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print the buffer a region at a time: the envelope, then each field, then any
+/// bytes that fail to decode
+fn inspect(input: &str) -> io::Result<ExitCode> {
+    let buffer = read_input(input)?;
+    let mut decoder = BiWiDecoder::new(&buffer);
+    println!("{} bytes", buffer.len());
+
+    let mut start = 0;
+    match decoder.decode_envelope() {
+        Ok(Some(envelope)) => {
+            dump(&buffer[..decoder.offset()], 0, &format!("envelope {:?}", envelope));
+            start = decoder.offset();
+        }
+        Ok(None) => {}
+        Err(e) => {
+            dump(&buffer, 0, &format!("error: {}", e));
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    while decoder.has_more() {
+        match decoder.decode_field() {
+            Ok(field) => {
+                let value = serde_json::to_string(&field.value).expect("BiWiValue serializes to JSON");
+                dump(&buffer[start..decoder.offset()], start, &format!("field {}: {}", field.field_id, value));
+            }
+            Err(e) => {
+                dump(&buffer[start..], start, &format!("error: {}", e));
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        start = decoder.offset();
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Hex dump `bytes`, which start at `offset`, 16 to a line, with `note` on the first
+fn dump(bytes: &[u8], offset: usize, note: &str) {
+    if bytes.is_empty() {
+        println!("{:08x}  {:<48}  {}", offset, "", note);
+    }
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let note = if i == 0 { note } else { "" };
+        println!("{:08x}  {:<48}  {}", offset + i * 16, hex.join(" "), note);
+    }
+}
+
+fn validate(input: &str, schema: &str) -> io::Result<ExitCode> {
+    let message = read_message(input)?;
+    let schema: Value = serde_json::from_slice(&read_input(schema)?).map_err(invalid)?;
+    match message.validate(&parse_schema(&schema)?) {
+        Ok(()) => {
+            println!("valid");
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(violations) => {
+            for violation in violations {
+                println!("{}", violation);
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn parse_schema(schema: &Value) -> io::Result<MessageSpec> {
+    let mut spec = MessageSpec::new();
+    if schema.get("deny_unknown").and_then(Value::as_bool) == Some(true) {
+        spec = spec.deny_unknown();
+    }
+    let Some(fields) = schema.get("fields") else {
+        return Ok(spec);
+    };
+    let fields = fields.as_object().ok_or_else(|| invalid("schema \"fields\" must be an object"))?;
+    for (id, rules) in fields {
+        let field_id: u32 = id.parse().map_err(|_| invalid(format!("field ID {:?} is not a number", id)))?;
+        let kind = match rules.get("kind").and_then(Value::as_str) {
+            Some(kind) => Some(parse_kind(kind)?),
+            None => None,
+        };
+        spec = match (rules.get("required").and_then(Value::as_bool).unwrap_or(false), kind) {
+            (true, Some(kind)) => spec.required(field_id, kind),
+            (true, None) => return Err(invalid(format!("required field {} needs a kind", field_id))),
+            (false, Some(kind)) => spec.optional(field_id, kind),
+            (false, None) => spec,
+        };
+        let (min, max) = (rules.get("min").and_then(Value::as_f64), rules.get("max").and_then(Value::as_f64));
+        if min.is_some() || max.is_some() {
+            spec = spec.range(field_id, min.unwrap_or(f64::NEG_INFINITY), max.unwrap_or(f64::INFINITY));
+        }
+        if let Some(max_len) = rules.get("max_len").and_then(Value::as_u64) {
+            spec = spec.max_len(field_id, max_len as usize);
+        }
+    }
+    Ok(spec)
+}
+
+fn parse_kind(kind: &str) -> io::Result<ValueKind> {
+    Ok(match kind {
+        "null" => ValueKind::Null,
+        "boolean" => ValueKind::Boolean,
+        "integer" => ValueKind::Integer,
+        "float" => ValueKind::Float,
+        "number" => ValueKind::Number,
+        "string" => ValueKind::String,
+        "binary" => ValueKind::Binary,
+        "array" => ValueKind::Array,
+        "object" => ValueKind::Object,
+        other => return Err(invalid(format!("unknown kind {:?}", other))),
+    })
+}
+
+fn send(addr: &str, input: &str, wait: Option<&str>) -> io::Result<ExitCode> {
+    let message = read_message(input)?;
+    let wait = match wait {
+        Some(secs) => secs.parse::<f64>().map_err(|_| invalid(format!("bad wait time {:?}", secs)))?,
+        None => 1.0,
+    };
+    let client = BiWiUdpClient::connect(addr)?;
+    client.send(&message)?.wait_timeout(Duration::from_secs(5));
+    eprintln!("sent {} bytes to {} (session {:016x})", message.to_vec().len(), addr, client.session_id());
+
+    let deadline = std::time::Instant::now() + Duration::from_secs_f64(wait);
+    while let Some(left) = deadline.checked_duration_since(std::time::Instant::now()) {
+        match client.recv_timeout(left) {
+            Ok(reply) => println!("{}", to_json(&reply)),
+            Err(_) => break,
+        }
+    }
+    client.close(Duration::from_secs(1))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn listen(addr: &str) -> io::Result<ExitCode> {
+    let (host, port) = addr.rsplit_once(':').ok_or_else(|| invalid("expected host:port"))?;
+    let port = port.parse().map_err(|_| invalid(format!("bad port {:?}", port)))?;
+    let mut server = BiWiUdpServer::new(host, port)?;
+    eprintln!("listening on {}", server.local_addr()?);
+    loop {
+        if let Some((client_id, message)) = server.recv_packet() {
+            println!("from {}:\n{}", client_id, to_json(&message));
+        }
+    }
+}
+
+fn dissector(ports: &[&str]) -> io::Result<ExitCode> {
+    let ports = ports
+        .iter()
+        .map(|port| port.parse().map_err(|_| invalid(format!("bad port {:?}", port))))
+        .collect::<io::Result<Vec<u16>>>()?;
+    write_output(None, biwi::dissector::lua_dissector(&ports).as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
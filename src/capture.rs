@@ -0,0 +1,324 @@
+//! BiWi Packet Capture
+//! Records the packets a connection sends and receives, with timestamps, so real
+//! traffic can be kept and played back later: through the decoder (`decode`), to
+//! check that every captured message still parses, or against a server (`replay`),
+//! to drive it with the same messages at the same pace.
+//!
+//! A `Capture` is attached to `PacketManager`s, so it sees every data, ACK and Ping
+//! packet of an established session, plaintext even when the session is encrypted.
+//! Handshake packets are not recorded. Servers attach it to each new session through
+//! `ServerConfig::with_capture`, clients with `BiWiUdpClient::set_capture`; one
+//! capture can be shared by any number of connections.
+//!
+//! File format: the magic `BWCAP\x01` and the start time (u64 microseconds since the
+//! Unix epoch), then one record per packet: u64 microseconds since the start, a
+//! direction byte (0 sent, 1 received), a u32 length and the packet bytes, all
+//! integers little-endian.
+
+use crate::client::BiWiUdpClient;
+use crate::clock::unix_micros;
+use crate::decoder::DecodeError;
+use crate::message::BiWiMessage;
+use crate::network::{PacketManager, PacketType, SendMode, UdpPacket};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Identifies a capture file
+pub const MAGIC: &[u8; 6] = b"BWCAP\x01";
+
+/// Which way a captured packet went, from the point of view of the recording side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// One recorded packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// Time since the capture started
+    pub at: Duration,
+    pub direction: Direction,
+    /// The packet as serialized by `UdpPacket::to_bytes`, session tag included
+    pub bytes: Vec<u8>,
+}
+
+impl CapturedPacket {
+    pub fn packet(&self) -> Result<UdpPacket, String> {
+        UdpPacket::from_bytes(&self.bytes)
+    }
+}
+
+/// A message recovered from captured packets by `decode`
+#[derive(Debug, Clone)]
+pub struct CapturedMessage {
+    /// When the packet completing the message was captured
+    pub at: Duration,
+    /// Session it belongs to, `None` for untagged packets
+    pub session_id: Option<u64>,
+    pub channel: u8,
+    pub mode: SendMode,
+    /// The message, or why its bytes don't decode
+    pub message: Result<BiWiMessage, DecodeError>,
+}
+
+struct Sink {
+    started: Instant,
+    writer: Box<dyn Write + Send>,
+    /// First write error, reported by `flush`; nothing more is recorded after it
+    error: Option<io::Error>,
+}
+
+/// Where packets are recorded. Clones share the same destination.
+#[derive(Clone)]
+pub struct Capture {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl std::fmt::Debug for Capture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capture").finish_non_exhaustive()
+    }
+}
+
+impl Capture {
+    /// Record to a new file at `path`, replacing any existing one
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::to_writer(BufWriter::new(File::create(path)?))
+    }
+
+    /// Record to `writer`, starting with the file header
+    pub fn to_writer(mut writer: impl Write + Send + 'static) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&unix_micros().to_le_bytes())?;
+        Ok(Self {
+            sink: Arc::new(Mutex::new(Sink { started: Instant::now(), writer: Box::new(writer), error: None })),
+        })
+    }
+
+    pub(crate) fn record(&self, direction: Direction, bytes: &[u8]) {
+        let mut sink = self.sink.lock().unwrap();
+        if sink.error.is_some() {
+            return;
+        }
+        let at = sink.started.elapsed().as_micros() as u64;
+        let mut record = Vec::with_capacity(13 + bytes.len());
+        record.extend_from_slice(&at.to_le_bytes());
+        record.push(match direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        });
+        record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(bytes);
+        if let Err(error) = sink.writer.write_all(&record) {
+            sink.error = Some(error);
+        }
+    }
+
+    /// Write out buffered records, reporting the first error recording hit if any
+    pub fn flush(&self) -> io::Result<()> {
+        let mut sink = self.sink.lock().unwrap();
+        if let Some(error) = sink.error.take() {
+            return Err(error);
+        }
+        sink.writer.flush()
+    }
+}
+
+/// Reads a capture back, one packet at a time
+pub struct CaptureReader<R> {
+    reader: R,
+    started: SystemTime,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Read the header; fails if `reader` doesn't hold a capture
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a BiWi capture"));
+        }
+        let started = UNIX_EPOCH + Duration::from_micros(read_u64(&mut reader)?);
+        Ok(Self { reader, started })
+    }
+
+    /// Wall-clock time the capture started
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<CapturedPacket>> {
+        // A clean end of file falls between records
+        let mut at = [0u8; 8];
+        match self.reader.read(&mut at[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut at[1..])?,
+        }
+        let mut direction = [0u8; 1];
+        self.reader.read_exact(&mut direction)?;
+        let direction = match direction[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown direction {}", other))),
+        };
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(Some(CapturedPacket { at: Duration::from_micros(u64::from_le_bytes(at)), direction, bytes }))
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+/// Recover the messages carried by the `direction` packets of a capture, as the
+/// receiving side would have: duplicates and retransmissions are dropped, fragments
+/// reassembled and coalesced messages split, each session on its own. Streamed chunks
+/// (`send_stream`) and packets that don't parse are skipped.
+pub fn decode(packets: &[CapturedPacket], direction: Direction) -> Vec<CapturedMessage> {
+    let mut sessions: HashMap<Option<u64>, PacketManager> = HashMap::new();
+    let mut messages = Vec::new();
+    for captured in packets.iter().filter(|captured| captured.direction == direction) {
+        let Ok(mut packet) = captured.packet() else {
+            continue;
+        };
+        if packet.packet_type != PacketType::Data || packet.is_stream() {
+            continue;
+        }
+        let session_id = packet.take_session();
+        for packet in sessions.entry(session_id).or_default().deliver(packet) {
+            for bytes in packet.messages() {
+                messages.push(CapturedMessage {
+                    at: captured.at,
+                    session_id,
+                    channel: packet.channel(),
+                    mode: packet.send_mode(),
+                    message: BiWiMessage::from_buffer(bytes),
+                });
+            }
+        }
+    }
+    messages
+}
+
+/// Send the messages carried by the `direction` packets of a capture to the server at
+/// `addr`, one client per captured session, at the captured pace sped up by `speed`
+/// (2.0 is twice as fast). Messages that don't decode are skipped. Returns how many
+/// were sent once every client has closed.
+pub fn replay(addr: &str, packets: &[CapturedPacket], direction: Direction, speed: f64) -> io::Result<usize> {
+    let mut clients: HashMap<Option<u64>, BiWiUdpClient> = HashMap::new();
+    let start = Instant::now();
+    let mut sent = 0;
+    for captured in decode(packets, direction) {
+        let Ok(message) = captured.message else {
+            continue;
+        };
+        let due = start + captured.at.div_f64(speed);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        let client = match clients.entry(captured.session_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(BiWiUdpClient::connect(addr)?),
+        };
+        client.send_on(captured.channel, &message, captured.mode)?;
+        sent += 1;
+    }
+    for client in clients.into_values() {
+        client.close(Duration::from_secs(1))?;
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::BiWiValue;
+    use crate::server::{BiWiUdpServer, ServerConfig};
+
+    /// `Write` into a buffer the test keeps a handle on
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_capture_decode_and_replay() {
+        let buf = SharedBuf::default();
+        let capture = Capture::to_writer(buf.clone()).unwrap();
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_config(ServerConfig::default().with_capture(capture.clone()));
+        let addr = server.local_addr().unwrap().to_string();
+
+        let sender = {
+            let addr = addr.clone();
+            thread::spawn(move || {
+                let client = BiWiUdpClient::connect(&addr).unwrap();
+                for i in 0..3 {
+                    let msg = BiWiMessage::builder().field(1, "move").field(2, BiWiValue::Int32(i)).build();
+                    client.send(&msg).unwrap().wait_timeout(Duration::from_secs(2));
+                }
+                client.close(Duration::from_secs(1)).unwrap();
+            })
+        };
+        let mut received = 0;
+        while received < 3 {
+            received += server.recv_packet().is_some() as usize;
+        }
+        sender.join().unwrap();
+        capture.flush().unwrap();
+
+        let bytes = buf.0.lock().unwrap().clone();
+        let packets: Vec<CapturedPacket> = CaptureReader::new(&bytes[..]).unwrap().collect::<io::Result<_>>().unwrap();
+        assert!(packets.iter().any(|packet| packet.direction == Direction::Sent));
+        let messages = decode(&packets, Direction::Received);
+        let values: Vec<i32> = messages.iter().map(|m| m.message.as_ref().unwrap().get_i32(2).unwrap()).collect();
+        assert_eq!(values, [0, 1, 2]);
+        assert!(messages.windows(2).all(|pair| pair[0].session_id == pair[1].session_id && pair[0].at <= pair[1].at));
+
+        // Play the client's side back into a second server
+        let mut target = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let target_addr = target.local_addr().unwrap().to_string();
+        let replayer = thread::spawn(move || replay(&target_addr, &packets, Direction::Received, 100.0));
+        let mut replayed = Vec::new();
+        while replayed.len() < 3 {
+            if let Some((_, msg)) = target.recv_packet() {
+                replayed.push(msg.get_i32(2).unwrap());
+            }
+        }
+        assert_eq!(replayed, [0, 1, 2]);
+        assert_eq!(replayer.join().unwrap().unwrap(), 3);
+        assert!(CaptureReader::new(&b"nope"[..]).is_err());
+    }
+}
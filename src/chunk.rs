@@ -0,0 +1,608 @@
+//! BiWi Chunked Streaming
+//! High-level counterparts to `encode_chunk_start/data/end`:
+//! `ChunkWriter` turns a byte stream into chunk frames, and `ChunkAssembler`
+//! turns frames (possibly split across reads) back into complete field payloads.
+//!
+//! Resumable transfers start with ChunkTransferStart (a transfer ID). If the
+//! stream breaks, the receiver calls `ChunkAssembler::interrupt` and sends the
+//! returned `ChunkResume` to the sender, which reopens the transfer with
+//! `ChunkWriter::resume_from` and writes the bytes from `ChunkResume::byte_offset`.
+//!
+//! With `ChunkWriter::with_checksums`, data frames carry a CRC-32 and the end frame
+//! a SHA-256 of the payload. The assembler rejects a bad chunk as soon as it arrives.
+
+use crate::decoder::{BiWiDecoder, ChunkFrame, ChunkResume, DecodeError, DecodeResult};
+use std::collections::BTreeMap;
+use crate::encoder::BiWiEncoder;
+use crate::types::ByteOrder;
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+/// Default payload size of each ChunkData frame
+pub const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+/// Largest payload a ChunkData frame can carry (16-bit length)
+pub const MAX_CHUNK_SIZE: usize = u16::MAX as usize;
+
+/// Splits written bytes into ChunkStart/ChunkData/ChunkEnd frames on `inner`
+pub struct ChunkWriter<W: Write> {
+    inner: W,
+    field_id: u16,
+    total_size: u64,
+    chunk_size: usize,
+    byte_order: ByteOrder,
+    transfer_id: Option<u32>,
+    /// Chunk index this writer started at (non-zero when resuming)
+    first_index: u32,
+    pending: Vec<u8>,
+    written: u64,
+    next_index: u32,
+    started: bool,
+    checksums: bool,
+    /// Hash of every byte written, for the end frame
+    hasher: Sha256,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Stream `total_size` bytes for `field_id` into `inner`
+    pub fn new(inner: W, field_id: u16, total_size: u64) -> Self {
+        Self {
+            inner,
+            field_id,
+            total_size,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            byte_order: ByteOrder::BigEndian,
+            transfer_id: None,
+            first_index: 0,
+            pending: Vec::new(),
+            written: 0,
+            next_index: 0,
+            started: false,
+            checksums: false,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Set the payload size of each data frame (clamped to 1..=MAX_CHUNK_SIZE)
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Set the byte order of frame headers (must match the reader)
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Add a CRC-32 to every data frame and a SHA-256 of the payload to the end frame.
+    /// A writer made with `resume_from` never sees the skipped bytes, so it ends
+    /// without the hash.
+    pub fn with_checksums(mut self) -> Self {
+        self.checksums = true;
+        self
+    }
+
+    /// Make the transfer resumable under `transfer_id`
+    pub fn with_transfer_id(mut self, transfer_id: u32) -> Self {
+        self.transfer_id = Some(transfer_id);
+        self
+    }
+
+    /// Continue an interrupted transfer. Only the bytes from
+    /// `resume.byte_offset(chunk_size)` onwards are written to this writer.
+    pub fn resume_from(mut self, resume: ChunkResume) -> Self {
+        self.transfer_id = Some(resume.transfer_id);
+        self.first_index = resume.next_index;
+        self.next_index = resume.next_index;
+        self
+    }
+
+    /// Bytes of the field accepted so far, including any skipped by resuming
+    pub fn bytes_written(&self) -> u64 {
+        self.resume_offset() + self.written
+    }
+
+    fn resume_offset(&self) -> u64 {
+        u64::from(self.first_index) * self.chunk_size as u64
+    }
+
+    /// Flush the last partial chunk, write ChunkEnd and return the inner writer.
+    /// Fails if fewer bytes than `total_size` were written.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.bytes_written() != self.total_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("wrote {} of {} chunked bytes", self.bytes_written(), self.total_size),
+            ));
+        }
+        self.start()?;
+        if !self.pending.is_empty() {
+            let data = std::mem::take(&mut self.pending);
+            self.emit_data(&data)?;
+        }
+        let mut encoder = self.encoder();
+        if self.checksums && self.first_index == 0 {
+            encoder.encode_chunk_end_hashed(&self.hasher.finalize_reset().into());
+        } else {
+            encoder.encode_chunk_end();
+        }
+        self.inner.write_all(encoder.as_slice())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn encoder(&self) -> BiWiEncoder {
+        BiWiEncoder::new().with_byte_order(self.byte_order)
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            let mut encoder = self.encoder();
+            match self.transfer_id {
+                Some(transfer_id) => encoder.encode_chunk_transfer_start(transfer_id, self.field_id, self.total_size),
+                None => encoder.encode_chunk_start(self.field_id, self.total_size),
+            }
+            self.inner.write_all(encoder.as_slice())?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn emit_data(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut encoder = self.encoder();
+        // Data frames carry the low 16 bits; the assembler counts the wraps
+        if self.checksums {
+            encoder.encode_chunk_data_checked(self.next_index as u16, data);
+        } else {
+            encoder.encode_chunk_data(self.next_index as u16, data);
+        }
+        self.inner.write_all(encoder.as_slice())?;
+        self.next_index = self.next_index.checked_add(1).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "too many chunks for a 32-bit index")
+        })?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.total_size.saturating_sub(self.bytes_written());
+        if buf.len() as u64 > room {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "write exceeds the declared chunk total size",
+            ));
+        }
+        self.start()?;
+
+        if self.checksums {
+            self.hasher.update(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        self.written += buf.len() as u64;
+        while self.pending.len() >= self.chunk_size {
+            let rest = self.pending.split_off(self.chunk_size);
+            let data = std::mem::replace(&mut self.pending, rest);
+            self.emit_data(&data)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Progress of the field currently being assembled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkProgress {
+    pub field_id: u16,
+    pub received: u64,
+    pub total: u64,
+}
+
+/// A fully reassembled chunked field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledField {
+    pub field_id: u16,
+    pub data: Vec<u8>,
+}
+
+struct InProgress {
+    transfer_id: Option<u32>,
+    field_id: u16,
+    total: u64,
+    next_index: u32,
+    data: Vec<u8>,
+}
+
+/// Reassembles chunk frames into complete fields
+pub struct ChunkAssembler {
+    byte_order: ByteOrder,
+    max_total_size: u64,
+    /// Bytes of a frame that has not fully arrived yet
+    partial: Vec<u8>,
+    current: Option<InProgress>,
+    /// Interrupted resumable transfers, by transfer ID
+    suspended: BTreeMap<u32, InProgress>,
+    on_progress: Option<Box<dyn FnMut(ChunkProgress) + Send>>,
+}
+
+/// Interrupted transfers kept for resumption before the oldest IDs are dropped
+pub const MAX_SUSPENDED_TRANSFERS: usize = 16;
+
+impl ChunkAssembler {
+    pub fn new() -> Self {
+        Self {
+            byte_order: ByteOrder::BigEndian,
+            max_total_size: 64 << 20,
+            partial: Vec::new(),
+            current: None,
+            suspended: BTreeMap::new(),
+            on_progress: None,
+        }
+    }
+
+    /// Set the byte order of frame headers (must match the writer)
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Reject streams that announce more than `max` bytes (default 64 MiB)
+    pub fn with_max_total_size(mut self, max: u64) -> Self {
+        self.max_total_size = max;
+        self
+    }
+
+    /// Call `callback` after every data frame
+    pub fn on_progress(mut self, callback: impl FnMut(ChunkProgress) + Send + 'static) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Progress of the field currently being assembled
+    pub fn progress(&self) -> Option<ChunkProgress> {
+        self.current.as_ref().map(|c| ChunkProgress {
+            field_id: c.field_id,
+            received: c.data.len() as u64,
+            total: c.total,
+        })
+    }
+
+    /// Bytes of the current field received so far
+    pub fn received_data(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|c| c.data.as_slice())
+    }
+
+    /// Mark the current stream as broken. A resumable transfer is kept and the
+    /// `ChunkResume` to send back to the sender is returned; anything else is dropped.
+    pub fn interrupt(&mut self) -> Option<ChunkResume> {
+        self.partial.clear();
+        let current = self.current.take()?;
+        let transfer_id = current.transfer_id?;
+        let resume = ChunkResume {
+            transfer_id,
+            next_index: current.next_index,
+        };
+
+        self.suspended.insert(transfer_id, current);
+        while self.suspended.len() > MAX_SUSPENDED_TRANSFERS {
+            self.suspended.pop_first();
+        }
+        Some(resume)
+    }
+
+    /// Resume request for a suspended transfer
+    pub fn resume_point(&self, transfer_id: u32) -> Option<ChunkResume> {
+        self.suspended.get(&transfer_id).map(|t| ChunkResume {
+            transfer_id,
+            next_index: t.next_index,
+        })
+    }
+
+    /// Feed raw bytes (frames may be split at any point) and return completed fields
+    pub fn feed(&mut self, bytes: &[u8]) -> DecodeResult<Vec<AssembledField>> {
+        self.partial.extend_from_slice(bytes);
+        let buffer = std::mem::take(&mut self.partial);
+
+        let mut completed = Vec::new();
+        let mut consumed = 0;
+        let mut decoder = BiWiDecoder::new(&buffer).with_byte_order(self.byte_order);
+        while decoder.has_more() {
+            match decoder.decode_chunk_frame() {
+                Ok(frame) => {
+                    consumed = decoder.offset();
+                    completed.extend(self.push_frame(frame)?);
+                }
+                // The rest of the frame has not arrived yet
+                Err(DecodeError::InsufficientData(_)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.partial = buffer.get(consumed..).unwrap_or_default().to_vec();
+        Ok(completed)
+    }
+
+    /// Apply one decoded frame, returning the field it completes, if any
+    pub fn push_frame(&mut self, frame: ChunkFrame) -> DecodeResult<Option<AssembledField>> {
+        match frame {
+            ChunkFrame::Start(start) => {
+                if self.current.is_some() {
+                    return Err(DecodeError::InvalidData("chunk start before previous chunk end"));
+                }
+                if start.total_size > self.max_total_size {
+                    return Err(DecodeError::LimitExceeded("chunked field size"));
+                }
+                self.current = Some(InProgress {
+                    transfer_id: None,
+                    field_id: start.field_id,
+                    total: start.total_size,
+                    next_index: 0,
+                    // Don't trust the announced size for the up-front allocation
+                    data: Vec::with_capacity(start.total_size.min(1 << 20) as usize),
+                });
+                Ok(None)
+            }
+            ChunkFrame::TransferStart(start) => {
+                if self.current.is_some() {
+                    return Err(DecodeError::InvalidData("chunk start before previous chunk end"));
+                }
+                if start.total_size > self.max_total_size {
+                    return Err(DecodeError::LimitExceeded("chunked field size"));
+                }
+                let resumed = self
+                    .suspended
+                    .remove(&start.transfer_id)
+                    .filter(|t| t.field_id == start.field_id && t.total == start.total_size);
+                self.current = Some(resumed.unwrap_or_else(|| InProgress {
+                    transfer_id: Some(start.transfer_id),
+                    field_id: start.field_id,
+                    total: start.total_size,
+                    next_index: 0,
+                    data: Vec::with_capacity(start.total_size.min(1 << 20) as usize),
+                }));
+                Ok(None)
+            }
+            // Resume requests travel the other way; the sender handles them
+            ChunkFrame::Resume(_) => Err(DecodeError::InvalidData("unexpected chunk resume frame")),
+            ChunkFrame::Data(chunk) => {
+                let current = self
+                    .current
+                    .as_mut()
+                    .ok_or(DecodeError::InvalidData("chunk data without chunk start"))?;
+                if !chunk.crc_matches() {
+                    return Err(DecodeError::InvalidData("chunk checksum mismatch"));
+                }
+                if chunk.chunk_index != current.next_index as u16 {
+                    return Err(DecodeError::InvalidData("chunk index out of order"));
+                }
+                if (current.data.len() + chunk.data.len()) as u64 > current.total {
+                    return Err(DecodeError::InvalidData("chunk data exceeds announced size"));
+                }
+                current.data.extend_from_slice(&chunk.data);
+                current.next_index = current.next_index.checked_add(1).ok_or(DecodeError::LimitExceeded("chunk count"))?;
+
+                let progress = self.progress();
+                if let (Some(callback), Some(progress)) = (self.on_progress.as_mut(), progress) {
+                    callback(progress);
+                }
+                Ok(None)
+            }
+            ChunkFrame::End(hash) => {
+                let current = self
+                    .current
+                    .take()
+                    .ok_or(DecodeError::InvalidData("chunk end without chunk start"))?;
+                if current.data.len() as u64 != current.total {
+                    return Err(DecodeError::InsufficientData("chunked field ended early"));
+                }
+                if hash.is_some_and(|hash| Sha256::digest(&current.data)[..] != hash) {
+                    return Err(DecodeError::InvalidData("chunked field hash mismatch"));
+                }
+                Ok(Some(AssembledField {
+                    field_id: current.field_id,
+                    data: current.data,
+                }))
+            }
+        }
+    }
+}
+
+impl Default for ChunkAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for ChunkAssembler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkAssembler")
+            .field("progress", &self.progress())
+            .field("buffered", &self.partial.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::ChunkData;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_writer_and_assembler_roundtrip() {
+        let payload: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut writer = ChunkWriter::new(Vec::new(), 7, payload.len() as u64).with_chunk_size(1000);
+        for part in payload.chunks(333) {
+            writer.write_all(part).unwrap();
+        }
+        let stream = writer.finish().unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let mut assembler = ChunkAssembler::new().on_progress(move |p| log.lock().unwrap().push(p.received));
+
+        // Feed in awkward slices so frames are split across calls
+        let mut completed = Vec::new();
+        for part in stream.chunks(97) {
+            completed.extend(assembler.feed(part).unwrap());
+        }
+        assert_eq!(completed, vec![AssembledField { field_id: 7, data: payload }]);
+        assert_eq!(*seen.lock().unwrap(), vec![1000, 2000, 3000, 4000, 5000]);
+        assert!(assembler.progress().is_none());
+    }
+
+    #[test]
+    fn test_interrupted_transfer_resumes() {
+        let payload: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+        let mut writer = ChunkWriter::new(Vec::new(), 3, payload.len() as u64)
+            .with_chunk_size(500)
+            .with_transfer_id(42);
+        writer.write_all(&payload).unwrap();
+        let stream = writer.finish().unwrap();
+
+        // The connection drops part-way through the fourth chunk
+        let mut assembler = ChunkAssembler::new();
+        assert!(assembler.feed(&stream[..1800]).unwrap().is_empty());
+        let resume = assembler.interrupt().unwrap();
+        assert_eq!(resume, ChunkResume { transfer_id: 42, next_index: 3 });
+        assert_eq!(assembler.resume_point(42), Some(resume));
+
+        // The resume request survives the wire
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_resume(resume.transfer_id, resume.next_index);
+        let frame = BiWiDecoder::new(encoder.as_slice()).decode_chunk_frame().unwrap();
+        assert_eq!(frame, ChunkFrame::Resume(resume));
+
+        let offset = resume.byte_offset(500) as usize;
+        let mut writer = ChunkWriter::new(Vec::new(), 3, payload.len() as u64)
+            .with_chunk_size(500)
+            .resume_from(resume);
+        writer.write_all(&payload[offset..]).unwrap();
+        let rest = writer.finish().unwrap();
+
+        let completed = assembler.feed(&rest).unwrap();
+        assert_eq!(completed, vec![AssembledField { field_id: 3, data: payload }]);
+        assert_eq!(assembler.resume_point(42), None);
+    }
+
+    #[test]
+    fn test_chunk_index_wraps_past_16_bits() {
+        // More chunks than a 16-bit index can count
+        let payload: Vec<u8> = (0..70_000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = ChunkWriter::new(Vec::new(), 2, payload.len() as u64)
+            .with_chunk_size(1)
+            .with_transfer_id(9);
+        writer.write_all(&payload).unwrap();
+        let stream = writer.finish().unwrap();
+
+        // Cut off during the second lap of the index; the resume point is not wrapped
+        let frame_len = 6;
+        let start_len = 15;
+        let mut assembler = ChunkAssembler::new();
+        assembler.feed(&stream[..start_len + 66_000 * frame_len]).unwrap();
+        let resume = assembler.interrupt().unwrap();
+        assert_eq!(resume, ChunkResume { transfer_id: 9, next_index: 66_000 });
+        assert_eq!(resume.byte_offset(1), 66_000);
+
+        let mut writer = ChunkWriter::new(Vec::new(), 2, payload.len() as u64)
+            .with_chunk_size(1)
+            .resume_from(resume);
+        writer.write_all(&payload[66_000..]).unwrap();
+        let completed = assembler.feed(&writer.finish().unwrap()).unwrap();
+        assert_eq!(completed, vec![AssembledField { field_id: 2, data: payload }]);
+    }
+
+    #[test]
+    fn test_declared_sizes_beyond_4_gb() {
+        let over = u64::from(u32::MAX) + 1;
+        let mut assembler = ChunkAssembler::new().with_max_total_size(over);
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_start(4, over);
+        encoder.encode_chunk_data(0, b"abc");
+        assert!(assembler.feed(encoder.as_slice()).unwrap().is_empty());
+        assert_eq!(assembler.progress(), Some(ChunkProgress { field_id: 4, received: 3, total: over }));
+
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_start(4, over + 1);
+        assert!(matches!(
+            ChunkAssembler::new().with_max_total_size(over).feed(encoder.as_slice()),
+            Err(DecodeError::LimitExceeded(_))
+        ));
+
+        // The writer no longer caps a stream at 4 GB
+        let mut writer = ChunkWriter::new(Vec::new(), 4, over).with_chunk_size(MAX_CHUNK_SIZE);
+        writer.write_all(&[0; 16]).unwrap();
+        assert_eq!(writer.bytes_written(), 16);
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn test_checksums_catch_corruption_early() {
+        let payload: Vec<u8> = (0..4000u32).map(|i| (i * 13) as u8).collect();
+        let mut writer = ChunkWriter::new(Vec::new(), 5, payload.len() as u64)
+            .with_chunk_size(1000)
+            .with_checksums();
+        writer.write_all(&payload).unwrap();
+        let stream = writer.finish().unwrap();
+
+        let completed = ChunkAssembler::new().feed(&stream).unwrap();
+        assert_eq!(completed, vec![AssembledField { field_id: 5, data: payload.clone() }]);
+
+        // Start is 11 bytes, each data frame 5 + 1000 + 4. Flip a byte of the second
+        // chunk: the error comes while only two chunks have been fed.
+        let mut corrupted = stream.clone();
+        corrupted[11 + 1009 + 5 + 10] ^= 0xFF;
+        assert_eq!(
+            ChunkAssembler::new().feed(&corrupted[..11 + 2 * 1009]),
+            Err(DecodeError::InvalidData("chunk checksum mismatch"))
+        );
+
+        // A chunk whose CRC was recomputed over bad data still fails the payload hash
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_start(5, 3);
+        encoder.encode_chunk_data_checked(0, b"abd");
+        encoder.encode_chunk_end_hashed(&Sha256::digest(b"abc").into());
+        assert_eq!(
+            ChunkAssembler::new().feed(encoder.as_slice()),
+            Err(DecodeError::InvalidData("chunked field hash mismatch"))
+        );
+
+        // A resumed writer still checks each chunk but can't hash what it skipped
+        let resume = ChunkResume { transfer_id: 1, next_index: 3 };
+        let mut writer = ChunkWriter::new(Vec::new(), 5, payload.len() as u64)
+            .with_chunk_size(1000)
+            .with_checksums()
+            .resume_from(resume);
+        writer.write_all(&payload[3000..]).unwrap();
+        let rest = writer.finish().unwrap();
+        let mut decoder = BiWiDecoder::new(&rest);
+        decoder.decode_chunk_frame().unwrap();
+        assert!(matches!(decoder.decode_chunk_frame().unwrap(), ChunkFrame::Data(ChunkData { crc: Some(_), .. })));
+        assert_eq!(decoder.decode_chunk_frame().unwrap(), ChunkFrame::End(None));
+    }
+
+    #[test]
+    fn test_size_mismatches_are_rejected() {
+        let mut writer = ChunkWriter::new(Vec::new(), 1, 4);
+        assert!(writer.write_all(b"hello").is_err());
+        writer.write_all(b"hi").unwrap();
+        assert!(writer.finish().is_err());
+
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_start(1, 4);
+        encoder.encode_chunk_data(1, b"data");
+        let mut assembler = ChunkAssembler::new();
+        assert!(assembler.feed(encoder.as_slice()).is_err());
+
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_chunk_start(1, u64::MAX);
+        assert!(matches!(
+            ChunkAssembler::new().feed(encoder.as_slice()),
+            Err(DecodeError::LimitExceeded(_))
+        ));
+    }
+}
@@ -1,87 +1,436 @@
 //! BiWi UDP Client
 //! Fast UDP-based client with automatic packet loss recovery
 
+use crate::capture::Capture;
+use crate::clock::ClockEstimate;
+use crate::coalesce::{Coalescer, DEFAULT_COALESCE_DELAY};
+use crate::compression::FieldCompressor;
+use crate::congestion::CongestionController;
+use crate::crypto::{handshake_random, verify_confirmation, PacketCipher, Role, HANDSHAKE_RANDOM_LEN};
+use crate::decoder::BiWiDecoder;
+use crate::encoder::BiWiEncoder;
+use crate::handler::DisconnectReason;
+use crate::intercept::{Interceptor, Interceptors, MessageContext, SharedInterceptors};
 use crate::message::BiWiMessage;
-use crate::network::{PacketManager, PacketType, UdpPacket};
-use std::io;
-use std::net::{SocketAddr, UdpSocket};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use crate::mtu::MtuProbe;
+use crate::receipt::{SendHandle, SendOutcome};
+use crate::topics::Topic;
+use crate::network::{
+    channel_flags, ConnectionStats, PacketManager, PacketType, Priority, RetransmitPolicy, SendMode, UdpPacket, DEFAULT_CHANNEL, FLAG_COMPACT_HEADERS, FLAG_FIELD_COMPRESSION, FLAG_PROBE, FLAG_STREAM,
+    STREAM_CHANNEL,
+};
+use crate::transport::{BiWiTransport, StatsCounters, TransportStats};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Connect packets sent before `connect` gives up on the handshake
+pub const CONNECT_ATTEMPTS: u32 = 10;
+
+/// How long to wait for a `ConnectAck` before resending `Connect`
+pub const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Pings are sent after this long without other traffic to the server, well within
+/// the server's `CONNECTION_TIMEOUT`
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Longest wait between reconnect attempts; the backoff doubles up to this
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Packets a client holds (un-ACKed or waiting to go out) before `send` blocks
+pub const DEFAULT_SEND_CAPACITY: usize = 1024;
+
+/// Chunk data packets allowed in flight (un-ACKed) during `send_stream`
+pub const DEFAULT_STREAM_WINDOW: usize = 32;
+
+/// How long the receive thread waits for a packet before checking retransmits and the send queue
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Shortest wait for a delayed ACK; a zero read timeout would block forever
+const MIN_ACK_WAIT: Duration = Duration::from_millis(1);
+
+/// Chunk data frame header: type (1) + index (2) + length (2)
+const CHUNK_DATA_HEADER: usize = 5;
+
+/// CRC-32 after the data of a checked chunk frame
+const CHUNK_CRC_LEN: usize = 4;
+
+/// How a client recovers when the server stops answering
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Re-handshake when nothing has been heard from the server for `timeout`
+    pub auto_reconnect: bool,
+    /// Wait before the first reconnect attempt, doubled after each failed one up to
+    /// `MAX_RECONNECT_BACKOFF`
+    pub backoff: Duration,
+    /// Attempts before giving up and closing the client
+    pub max_attempts: u32,
+    /// Messages held while reconnecting; sends beyond this fail with `WouldBlock`
+    pub max_buffered: usize,
+    /// Silence after which the server counts as gone. Keep-alives make sure a live
+    /// server is heard from, so this should be a few keep-alive intervals.
+    pub timeout: Duration,
+    /// Packets held (un-ACKed or queued by the congestion controller) before `send`
+    /// blocks and `try_send` fails with `WouldBlock`
+    pub send_capacity: usize,
+    /// `on_high_watermark` fires when the held packets reach this many...
+    pub high_watermark: usize,
+    /// ...and `on_low_watermark` once they have fallen back to this many
+    pub low_watermark: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            auto_reconnect: false,
+            backoff: Duration::from_millis(250),
+            max_attempts: 5,
+            max_buffered: 256,
+            timeout: DEFAULT_KEEP_ALIVE_INTERVAL * 3,
+            send_capacity: DEFAULT_SEND_CAPACITY,
+            high_watermark: DEFAULT_SEND_CAPACITY * 3 / 4,
+            low_watermark: DEFAULT_SEND_CAPACITY / 4,
+        }
+    }
+}
+
+type WatermarkHandler = Box<dyn FnMut(usize) + Send>;
+
+/// Flow control between senders and the network: wakes blocked `send`s as packets
+/// are settled and reports watermark crossings
+#[derive(Default)]
+struct Backpressure {
+    /// Set when the backlog reaches the high watermark, cleared at the low one
+    above_high: Mutex<bool>,
+    drained: Condvar,
+    on_high: Mutex<Option<WatermarkHandler>>,
+    on_low: Mutex<Option<WatermarkHandler>>,
+}
+
+impl Backpressure {
+    /// Record the current backlog, firing a watermark callback if it crossed one
+    fn update(&self, backlog: usize, config: &ClientConfig) {
+        let mut above_high = self.above_high.lock().unwrap();
+        let handler = if !*above_high && backlog >= config.high_watermark {
+            *above_high = true;
+            &self.on_high
+        } else if *above_high && backlog <= config.low_watermark {
+            *above_high = false;
+            &self.on_low
+        } else {
+            self.drained.notify_all();
+            return;
+        };
+        drop(above_high);
+        self.drained.notify_all();
+        if let Some(handler) = handler.lock().unwrap().as_mut() {
+            handler(backlog);
+        }
+    }
+}
+
+/// Connection changes and delivery failures reported by `BiWiUdpClient::events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    /// The handshake completed
+    Connected { session_id: u64 },
+    /// The server closed the session (`Closed`), or it went quiet and reconnecting
+    /// failed (`TimedOut`); the client is no longer active
+    Disconnected(DisconnectReason),
+    /// The server went quiet; reconnect attempt `attempt` (from 1) is under way
+    Reconnecting { attempt: u32 },
+    /// A new session is up and buffered messages have been sent on it
+    Reconnected { session_id: u64 },
+    /// A ping was answered after this round trip
+    RttUpdated(Duration),
+    /// The reliable packet with this sequence number ran out of retries
+    PacketDropped(u32),
+    /// A message lost with a dropped packet. Messages split over several packets
+    /// are only reported through `PacketDropped`.
+    RetransmitExhausted(BiWiMessage),
+}
+
+/// Messages held back while the client re-handshakes
+#[derive(Default)]
+struct Outbox {
+    reconnecting: bool,
+    queued: VecDeque<(u8, Vec<u8>, SendMode, Priority, SendHandle)>,
+}
+
+/// What the receive thread needs to open a new session on the client's behalf
+struct Reconnect {
+    psk: Option<Vec<u8>>,
+    credentials: Vec<u8>,
+    session_id: Arc<AtomicU64>,
+    outbox: Arc<Mutex<Outbox>>,
+    fields: Arc<Mutex<Option<FieldCompressor>>>,
+    events: Sender<ClientEvent>,
+}
+
+impl Reconnect {
+    /// Re-handshake with backoff, moving the packet manager onto the new session and
+    /// sending whatever was buffered meanwhile. Returns the new session ID, or `None`
+    /// if every attempt failed or the client was closed.
+    fn run(
+        &self,
+        socket: &UdpSocket,
+        server_addr: SocketAddr,
+        packet_manager: &Mutex<PacketManager>,
+        config: &ClientConfig,
+        running: &Mutex<bool>,
+    ) -> Option<u64> {
+        self.outbox.lock().unwrap().reconnecting = true;
+        let mut delay = config.backoff;
+        for attempt in 1..=config.max_attempts {
+            let _ = self.events.send(ClientEvent::Reconnecting { attempt });
+            let wake = Instant::now() + delay;
+            while Instant::now() < wake {
+                if !*running.lock().unwrap() {
+                    return None;
+                }
+                thread::sleep(POLL_INTERVAL.min(wake.saturating_duration_since(Instant::now())));
+            }
+
+            let granted = handshake(socket, server_addr, self.psk.as_deref(), &self.credentials);
+            let _ = socket.set_read_timeout(Some(POLL_INTERVAL));
+            if let Ok((session_id, cipher, accepted)) = granted {
+                let mut outbox = self.outbox.lock().unwrap();
+                let mut pm = packet_manager.lock().unwrap();
+                // Settings and RTT estimates carry over; sequence numbers, un-ACKed packets
+                // and the server's field table don't
+                pm.reset();
+                pm.set_session(session_id);
+                pm.set_cipher(cipher);
+                pm.set_compact_headers(accepted & FLAG_COMPACT_HEADERS != 0);
+                *self.fields.lock().unwrap() = (accepted & FLAG_FIELD_COMPRESSION != 0).then(FieldCompressor::new);
+                self.session_id.store(session_id, Ordering::Relaxed);
+
+                // Confirm the session, then catch up on what was sent meanwhile
+                let ping = pm.create_ping_packet();
+                let _ = socket.send_to(&pm.encode(&ping), server_addr);
+                for (channel, msg_bytes, mode, priority, receipt) in outbox.queued.drain(..) {
+                    let packets = pm.create_packets_on(channel, &msg_bytes, mode);
+                    pm.attach_receipt(&packets, &receipt);
+                    for packet in pm.pace_with_priority(packets, priority) {
+                        let _ = socket.send_to(&pm.encode(&packet), server_addr);
+                    }
+                }
+                outbox.reconnecting = false;
+                let _ = self.events.send(ClientEvent::Reconnected { session_id });
+                return Some(session_id);
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+
+        let mut outbox = self.outbox.lock().unwrap();
+        outbox.reconnecting = false;
+        for (.., receipt) in outbox.queued.drain(..) {
+            receipt.resolve(SendOutcome::Cancelled);
+        }
+        None
+    }
+}
+
+/// Where messages carrying a correlation ID go instead of the receive queue
+struct Waiter {
+    tx: Sender<BiWiMessage>,
+    /// Streams take every message with their ID until closed; requests only the first
+    stream: bool,
+}
+
+/// Callers of `request`, and open streams, by correlation ID
+type Requests = Arc<Mutex<HashMap<u64, Waiter>>>;
 
 /// BiWi UDP Client
 pub struct BiWiUdpClient {
     socket: Arc<UdpSocket>,
     server_addr: SocketAddr,
     packet_manager: Arc<Mutex<PacketManager>>,
-    message_tx: Sender<Vec<u8>>,
-    message_rx: Receiver<Vec<u8>>,
+    /// Fed by the receive thread alone, so it reports disconnection once the thread ends.
+    /// Behind a lock, like `events`, so one thread can send while another waits here.
+    message_rx: Mutex<Receiver<Vec<u8>>>,
+    /// Messages in `message_rx` not yet read, which shrink the receive window
+    unread: Arc<AtomicUsize>,
     running: Arc<Mutex<bool>>,
+    /// The receive thread, joined on disconnect
+    receiver: Option<JoinHandle<()>>,
+    stream_window: usize,
+    stream_checksums: bool,
+    stats: Arc<StatsCounters>,
+    session_id: Arc<AtomicU64>,
+    coalescer: Arc<Mutex<Coalescer>>,
+    mtu_probe: Arc<Mutex<Option<MtuProbe>>>,
+    keep_alive: Arc<Mutex<Option<Duration>>>,
+    config: Arc<Mutex<ClientConfig>>,
+    outbox: Arc<Mutex<Outbox>>,
+    /// Compresses what `send` sends, if the server accepted field compression
+    fields: Arc<Mutex<Option<FieldCompressor>>>,
+    events: Mutex<Receiver<ClientEvent>>,
+    requests: Requests,
+    backpressure: Arc<Backpressure>,
+    interceptors: SharedInterceptors,
+    /// Correlation ID for the next `request`; 0 means "no correlation", so IDs start at 1
+    next_request: AtomicU64,
 }
 
 impl BiWiUdpClient {
-    /// Create and connect a new UDP client
+    /// Create a new UDP client and open a session with the server.
+    /// Fails if the server refuses the session or never answers the handshake.
     pub fn connect(server_addr: &str) -> io::Result<Self> {
+        Self::establish(server_addr, None, &[])
+    }
+
+    /// Open a session, presenting `credentials` to the server's admission check (see
+    /// `ServerConfig::auth_callback`). They travel unencrypted, so send a token rather
+    /// than a password. Fails with `ConnectionRefused` if the server turns them down.
+    pub fn connect_with_credentials(server_addr: &str, credentials: &[u8]) -> io::Result<Self> {
+        Self::establish(server_addr, None, credentials)
+    }
+
+    /// Open a session encrypted with a key derived from `psk` (see `crypto`). Fails
+    /// with `PermissionDenied` if the server can't prove it holds the same key.
+    pub fn connect_with_psk(server_addr: &str, psk: &[u8]) -> io::Result<Self> {
+        Self::establish(server_addr, Some(psk), &[])
+    }
+
+    fn establish(server_addr: &str, psk: Option<&[u8]>, credentials: &[u8]) -> io::Result<Self> {
         let server_addr: SocketAddr = server_addr.parse()
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid address"))?;
 
         // Bind to any local address
         let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let (session_id, cipher, accepted) = handshake(&socket, server_addr, psk, credentials)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
 
-        println!(
-            "[BiWi UDP] Client connected to {}",
-            server_addr
-        );
+        event!(INFO, server = %server_addr, session = session_id, "BiWi UDP client connected");
 
         let (tx, rx) = channel();
+        let (event_tx, event_rx) = channel();
+        let packet_manager = session_packet_manager(session_id, cipher, accepted);
+        let coalescer = Coalescer::new(packet_manager.payload_limit(), DEFAULT_COALESCE_DELAY);
 
-        let client = BiWiUdpClient {
+        let mut client = BiWiUdpClient {
             socket: Arc::new(socket),
             server_addr,
-            packet_manager: Arc::new(Mutex::new(PacketManager::new())),
-            message_tx: tx,
-            message_rx: rx,
+            packet_manager: Arc::new(Mutex::new(packet_manager)),
+            message_rx: Mutex::new(rx),
+            unread: Arc::new(AtomicUsize::new(0)),
             running: Arc::new(Mutex::new(true)),
+            receiver: None,
+            stream_window: DEFAULT_STREAM_WINDOW,
+            stream_checksums: false,
+            stats: Arc::new(StatsCounters::default()),
+            session_id: Arc::new(AtomicU64::new(session_id)),
+            coalescer: Arc::new(Mutex::new(coalescer)),
+            mtu_probe: Arc::new(Mutex::new(None)),
+            keep_alive: Arc::new(Mutex::new(Some(DEFAULT_KEEP_ALIVE_INTERVAL))),
+            config: Arc::new(Mutex::new(ClientConfig::default())),
+            outbox: Arc::new(Mutex::new(Outbox::default())),
+            fields: Arc::new(Mutex::new((accepted & FLAG_FIELD_COMPRESSION != 0).then(FieldCompressor::new))),
+            events: Mutex::new(event_rx),
+            requests: Arc::new(Mutex::new(HashMap::new())),
+            backpressure: Arc::new(Backpressure::default()),
+            interceptors: SharedInterceptors::default(),
+            next_request: AtomicU64::new(1),
         };
+        let _ = event_tx.send(ClientEvent::Connected { session_id });
 
         // Start receive loop
         let socket = Arc::clone(&client.socket);
         let packet_manager = Arc::clone(&client.packet_manager);
-        let tx = client.message_tx.clone();
         let running = Arc::clone(&client.running);
+        let stats = Arc::clone(&client.stats);
+        let coalescer = Arc::clone(&client.coalescer);
+        let mtu_probe = Arc::clone(&client.mtu_probe);
+        let keep_alive = Arc::clone(&client.keep_alive);
+        let config = Arc::clone(&client.config);
+        let requests = Arc::clone(&client.requests);
+        let backpressure = Arc::clone(&client.backpressure);
+        let interceptors = Arc::clone(&client.interceptors);
+        let unread = Arc::clone(&client.unread);
+        let events = event_tx.clone();
+        let reconnect = Reconnect {
+            psk: psk.map(<[u8]>::to_vec),
+            credentials: credentials.to_vec(),
+            session_id: Arc::clone(&client.session_id),
+            outbox: Arc::clone(&client.outbox),
+            fields: Arc::clone(&client.fields),
+            events: event_tx,
+        };
         let server_addr = client.server_addr;
 
-        thread::spawn(move || {
+        client.receiver = Some(thread::spawn(move || {
             let mut buf = vec![0u8; 65536];
+            let mut session_id = session_id;
+            let mut last_heard = Instant::now();
+            let mut last_ping = Instant::now();
+            let mut read_timeout = POLL_INTERVAL;
 
             while *running.lock().unwrap() {
                 match socket.recv_from(&mut buf) {
                     Ok((n, addr)) if addr == server_addr => {
                         let packet_data = &buf[..n];
 
-                        if let Ok(packet) = UdpPacket::from_bytes(packet_data) {
-                            let mut pm = packet_manager.lock().unwrap();
+                        let mut pm = packet_manager.lock().unwrap();
+                        if let Ok(mut packet) = pm.decode(packet_data) {
+                            let _span = span!(TRACE, "biwi.recv", session = session_id, kind = ?packet.packet_type, sequence = packet.sequence);
 
-                            match packet.packet_type {
-                                PacketType::Data => {
-                                    // Record received and send ACK
-                                    if pm.record_received(packet.sequence) {
-                                        let ack = pm.create_ack_packet(packet.sequence);
-                                        let _ = socket.send_to(&ack.to_bytes(), server_addr);
+                            // Packets that fail to decrypt are forged, tampered with or from another session
+                            if pm.open(&mut packet) {
+                                last_heard = Instant::now();
+                                match packet.packet_type {
+                                    PacketType::Data => {
+                                        // ACK reliable packets, duplicates too in case the first ACK was lost
+                                        if packet.send_mode().is_reliable() {
+                                            pm.set_receive_backlog(unread.load(Ordering::Relaxed));
+                                            for ack in pm.queue_ack(&packet) {
+                                                let _ = socket.send_to(&pm.encode(&ack), server_addr);
+                                            }
+                                        }
 
-                                        // Emit message
-                                        let _ = tx.send(packet.payload);
+                                        // Emit messages, in sequence order if a reorder window is set
+                                        for packet in pm.deliver(packet) {
+                                            emit(&packet, &stats, &tx, &unread, &requests, &interceptors, server_addr);
+                                        }
                                     }
+                                    PacketType::Ack => {
+                                        // ACKs free up send budget for queued packets
+                                        pm.handle_ack_packet(&packet);
+                                        for packet in pm.release_paced() {
+                                            let _ = socket.send_to(&pm.encode(&packet), server_addr);
+                                        }
+                                    }
+                                    PacketType::Ping => {
+                                        // The server is timing the round trip
+                                        let mut pong = UdpPacket::pong(&packet);
+                                        pong.tag_session(session_id);
+                                        let _ = socket.send_to(&pm.encode(&pong), server_addr);
+                                    }
+                                    PacketType::Pong if packet.flags & FLAG_PROBE != 0 => {
+                                        // A probe got through: fragment to the larger size from now on
+                                        if let Some(size) = mtu_probe.lock().unwrap().as_mut().and_then(|p| p.on_pong(packet.ack_number)) {
+                                            pm.set_max_packet_size(size);
+                                        }
+                                    }
+                                    PacketType::Pong => {
+                                        // Keep-alive response: time the round trip
+                                        if let Some(rtt) = pm.handle_pong(&packet) {
+                                            let _ = events.send(ClientEvent::RttUpdated(rtt));
+                                        }
+                                    }
+                                    PacketType::Disconnect if packet.session_id() == Some(session_id) => {
+                                        // Server closed the session
+                                        *running.lock().unwrap() = false;
+                                        let _ = events.send(ClientEvent::Disconnected(DisconnectReason::Closed));
+                                    }
+                                    _ => {}
                                 }
-                                PacketType::Ack => {
-                                    pm.handle_ack(packet.ack_number);
-                                }
-                                PacketType::Pong => {
-                                    // Keep-alive response received
-                                }
-                                _ => {}
                             }
                         }
                     }
@@ -93,70 +442,822 @@ impl BiWiUdpClient {
                         let mut pm = packet_manager.lock().unwrap();
                         let retransmits = pm.get_retransmit_packets();
                         for (packet, _) in retransmits {
-                            let _ = socket.send_to(&packet.to_bytes(), server_addr);
+                            let _ = socket.send_to(&pm.encode(&packet), server_addr);
                         }
+                        for packet in pm.take_exhausted() {
+                            report_dropped(&packet, &events);
+                        }
+                        for packet in pm.release_paced() {
+                            let _ = socket.send_to(&pm.encode(&packet), server_addr);
+                        }
+                        for packet in pm.flush_reorder() {
+                            emit(&packet, &stats, &tx, &unread, &requests, &interceptors, server_addr);
+                        }
+                    }
+                }
+
+                let due = coalescer.lock().unwrap().flush_due();
+                let _ = send_batches(&socket, server_addr, &packet_manager, due);
+
+                // Delayed ACKs go out once their time is up, so wake for the next one
+                {
+                    let mut pm = packet_manager.lock().unwrap();
+                    for ack in pm.take_due_acks() {
+                        let _ = socket.send_to(&pm.encode(&ack), server_addr);
+                    }
+                    let wait = pm.next_ack_due().map_or(POLL_INTERVAL, |due| {
+                        due.saturating_duration_since(Instant::now()).clamp(MIN_ACK_WAIT, POLL_INTERVAL)
+                    });
+                    if wait != read_timeout {
+                        let _ = socket.set_read_timeout(Some(wait));
+                        read_timeout = wait;
                     }
                 }
+
+                if let Some(probe) = mtu_probe.lock().unwrap().as_mut() {
+                    if let Some((id, size)) = probe.next_probe() {
+                        let mut pm = packet_manager.lock().unwrap();
+                        let packet = pm.create_mtu_probe(id, size);
+                        if socket.send_to(&pm.encode(&packet), server_addr).is_err() {
+                            probe.on_send_error();
+                        }
+                    }
+                }
+
+                // Ping when idle so the server (and any NAT on the way) keeps the session,
+                // and when the server has been quiet so a live one is sure to answer
+                if let Some(interval) = *keep_alive.lock().unwrap() {
+                    let mut pm = packet_manager.lock().unwrap();
+                    let quiet = last_heard.elapsed() >= interval && last_ping.elapsed() >= interval;
+                    if pm.idle_time() >= interval || quiet {
+                        let ping = pm.create_ping_packet();
+                        let _ = socket.send_to(&pm.encode(&ping), server_addr);
+                        last_ping = Instant::now();
+                    }
+                }
+
+                let config = config.lock().unwrap().clone();
+                let backlog = packet_manager.lock().unwrap().backlog();
+                backpressure.update(backlog, &config);
+
+                if config.auto_reconnect && last_heard.elapsed() >= config.timeout {
+                    match reconnect.run(&socket, server_addr, &packet_manager, &config, &running) {
+                        Some(id) => {
+                            session_id = id;
+                            read_timeout = POLL_INTERVAL;
+                        }
+                        None => {
+                            let mut running = running.lock().unwrap();
+                            if *running {
+                                let _ = events.send(ClientEvent::Disconnected(DisconnectReason::TimedOut));
+                            }
+                            *running = false;
+                        }
+                    }
+                    last_heard = Instant::now();
+                }
             }
-        });
+        }));
 
+        // Confirms the session to the server straight away (and takes a first RTT sample)
+        client.ping()?;
         Ok(client)
     }
 
-    /// Send a message to the server
-    pub fn send(&self, message: &BiWiMessage) -> io::Result<()> {
-        let msg_bytes = message.to_vec();
+    /// Send a message to the server (reliable and ordered); the handle resolves once
+    /// the server has ACKed it. Blocks while the send queue is full (see `try_send`).
+    pub fn send(&self, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.send_with_mode(message, SendMode::default())
+    }
+
+    /// Send a message to the server with the given delivery guarantees
+    pub fn send_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        self.send_on(DEFAULT_CHANNEL, message, mode)
+    }
+
+    /// Send a message on `channel`; channels are sequenced independently, so a busy
+    /// channel never delays messages on another
+    pub fn send_on(&self, channel: u8, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        self.send_message(channel, message, mode, Priority::Normal, true)
+    }
+
+    /// Send a message (reliable and ordered) ahead of or behind other queued traffic
+    pub fn send_with_priority(&self, message: &BiWiMessage, priority: Priority) -> io::Result<SendHandle> {
+        self.send_message(DEFAULT_CHANNEL, message, SendMode::default(), priority, true)
+    }
+
+    /// Send a message (reliable and ordered) without blocking: fails with `WouldBlock`
+    /// while the client already holds `send_capacity` packets (see `ClientConfig`)
+    pub fn try_send(&self, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.send_message(DEFAULT_CHANNEL, message, SendMode::default(), Priority::Normal, false)
+    }
+
+    /// Call `handler` with the backlog when the packets held reach `high_watermark`
+    pub fn on_high_watermark(&self, handler: impl FnMut(usize) + Send + 'static) {
+        *self.backpressure.on_high.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Call `handler` with the backlog when, after reaching the high watermark, the
+    /// packets held fall back to `low_watermark`
+    pub fn on_low_watermark(&self, handler: impl FnMut(usize) + Send + 'static) {
+        *self.backpressure.on_low.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Block while the client holds `send_capacity` packets or more
+    fn wait_for_room(&self) -> io::Result<()> {
+        loop {
+            let capacity = self.config.lock().unwrap().send_capacity;
+            if self.packet_manager.lock().unwrap().backlog() < capacity {
+                return Ok(());
+            }
+            if !self.is_active() {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "Client disconnected"));
+            }
+            // The timeout covers an update landing between the check and the wait
+            let above_high = self.backpressure.above_high.lock().unwrap();
+            let _ = self.backpressure.drained.wait_timeout(above_high, POLL_INTERVAL).unwrap();
+        }
+    }
+
+    /// Send on `channel`, waiting for room in the send queue if `blocking` and failing
+    /// with `WouldBlock` otherwise
+    fn send_message(
+        &self,
+        channel: u8,
+        message: &BiWiMessage,
+        mode: SendMode,
+        priority: Priority,
+        blocking: bool,
+    ) -> io::Result<SendHandle> {
+        if blocking {
+            self.wait_for_room()?;
+        }
+        let ctx = MessageContext { peer: Some(self.server_addr), client_id: None };
+        let message = self.interceptors.lock().unwrap().apply_outgoing(message, &ctx);
+        let config = self.config.lock().unwrap().clone();
+        let mut outbox = self.outbox.lock().unwrap();
         let mut pm = self.packet_manager.lock().unwrap();
-        let packets = pm.create_packets(&msg_bytes);
+        // References need their inserts delivered first, so only reliable ordered
+        // messages on the default channel are compressed, and none held for a new session
+        let mut fields = self.fields.lock().unwrap();
+        let compressor = fields
+            .as_mut()
+            .filter(|_| channel == DEFAULT_CHANNEL && mode == SendMode::ReliableOrdered && !outbox.reconnecting);
+        let (msg_bytes, inserts) = match compressor {
+            Some(compressor) => compressor.encode(&message),
+            None => (message.to_vec(), 0..0),
+        };
+        let refused = pm.check_message_size(msg_bytes.len()).err().or_else(|| {
+            (!outbox.reconnecting && !blocking && pm.backlog() >= config.send_capacity)
+                .then(|| io::Error::new(io::ErrorKind::WouldBlock, "Send queue full"))
+        });
+        if let Some(error) = refused {
+            if let Some(compressor) = fields.as_mut() {
+                compressor.forget(inserts);
+            }
+            return Err(error);
+        }
+        if outbox.reconnecting {
+            // Held until the new session is up
+            if outbox.queued.len() >= config.max_buffered {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "Reconnect buffer full"));
+            }
+            self.stats.record_sent(msg_bytes.len());
+            let receipt = SendHandle::new();
+            outbox.queued.push_back((channel, msg_bytes, mode, priority, receipt.clone()));
+            return Ok(receipt);
+        }
+        let packets = pm.create_packets_on(channel, &msg_bytes, mode);
+        let receipt = SendHandle::new();
+        pm.attach_receipt(&packets, &receipt);
+        if let Some(compressor) = fields.as_mut() {
+            compressor.track(inserts, receipt.clone());
+        }
+        drop(fields);
+        let packets = pm.pace_with_priority(packets, priority);
 
         for packet in packets {
-            self.socket.send_to(&packet.to_bytes(), self.server_addr)?;
+            self.socket.send_to(&pm.encode(&packet), self.server_addr)?;
+        }
+
+        self.stats.record_sent(msg_bytes.len());
+        let backlog = pm.backlog();
+        drop(pm);
+        drop(outbox);
+        self.backpressure.update(backlog, &config);
+        Ok(receipt)
+    }
+
+    /// Ask the server to forward what it publishes on `topic` (see `BiWiUdpServer::publish`)
+    pub fn subscribe(&self, topic: impl Into<Topic>) -> io::Result<SendHandle> {
+        self.send(&topic.into().control(true))
+    }
+
+    /// Stop receiving `topic`
+    pub fn unsubscribe(&self, topic: impl Into<Topic>) -> io::Result<SendHandle> {
+        self.send(&topic.into().control(false))
+    }
+
+    /// Send `message` (reliable and ordered) tagged with a fresh correlation ID, and wait
+    /// up to `timeout` for the server's response carrying the same ID (see
+    /// `BiWiMessage::in_reply_to`). Lost packets are retransmitted by the reliable
+    /// layer; other messages arriving meanwhile still go to `recv`.
+    pub fn request(&self, message: &BiWiMessage, timeout: Duration) -> io::Result<BiWiMessage> {
+        let correlation_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let envelope = message.envelope().copied().unwrap_or_default().with_correlation_id(correlation_id);
+        let request = message.clone().with_envelope(envelope);
+
+        let (tx, rx) = channel();
+        self.requests.lock().unwrap().insert(correlation_id, Waiter { tx, stream: false });
+        let response = self.send(&request).and_then(|_| {
+            rx.recv_timeout(timeout).map_err(|_| {
+                if self.is_active() {
+                    io::Error::new(io::ErrorKind::TimedOut, "No response")
+                } else {
+                    io::Error::new(io::ErrorKind::NotConnected, "Client disconnected")
+                }
+            })
+        });
+        self.requests.lock().unwrap().remove(&correlation_id);
+        response
+    }
+
+    /// Send `message` (reliable and ordered) tagged with a fresh correlation ID, and
+    /// route every message that comes back with that ID to the returned receiver until
+    /// `close_stream`
+    pub(crate) fn open_stream(&self, message: &BiWiMessage) -> io::Result<(u64, Receiver<BiWiMessage>)> {
+        let correlation_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let envelope = message.envelope().copied().unwrap_or_default().with_correlation_id(correlation_id);
+
+        let (tx, rx) = channel();
+        self.requests.lock().unwrap().insert(correlation_id, Waiter { tx, stream: true });
+        if let Err(error) = self.send(&message.clone().with_envelope(envelope)) {
+            self.close_stream(correlation_id);
+            return Err(error);
+        }
+        Ok((correlation_id, rx))
+    }
+
+    pub(crate) fn close_stream(&self, correlation_id: u64) {
+        self.requests.lock().unwrap().remove(&correlation_id);
+    }
+
+    /// Add an interceptor that sees every message sent and received (see `intercept`)
+    pub fn add_interceptor(&self, interceptor: impl Interceptor + 'static) {
+        self.interceptors.lock().unwrap().push(Box::new(interceptor));
+    }
+
+    fn encode(&self, message: &BiWiMessage) -> Vec<u8> {
+        let ctx = MessageContext { peer: Some(self.server_addr), client_id: None };
+        self.interceptors.lock().unwrap().outgoing(message, &ctx)
+    }
+
+    /// Queue a small message to share a datagram with others sent in the next few
+    /// milliseconds (see `set_coalesce_delay`). Messages too large to batch are sent directly.
+    pub fn send_coalesced(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        let msg_bytes = self.encode(message);
+        let mut coalescer = self.coalescer.lock().unwrap();
+        if !coalescer.fits(msg_bytes.len()) {
+            drop(coalescer);
+            return self.send_with_mode(message, mode).map(drop);
         }
 
+        self.stats.record_sent(msg_bytes.len());
+        let full = coalescer.push(mode, msg_bytes);
+        drop(coalescer);
+        send_batches(&self.socket, self.server_addr, &self.packet_manager, full.map(|batch| (mode, batch)))
+    }
+
+    /// Send every batch queued by `send_coalesced` now
+    pub fn flush(&self) -> io::Result<()> {
+        let batches = self.coalescer.lock().unwrap().flush();
+        send_batches(&self.socket, self.server_addr, &self.packet_manager, batches)
+    }
+
+    /// How long `send_coalesced` waits for more messages before sending a batch
+    pub fn set_coalesce_delay(&self, delay: Duration) -> io::Result<()> {
+        let mut coalescer = self.coalescer.lock().unwrap();
+        let batches = coalescer.flush();
+        let limit = self.packet_manager.lock().unwrap().payload_limit();
+        *coalescer = Coalescer::new(limit, delay);
+        drop(coalescer);
+        send_batches(&self.socket, self.server_addr, &self.packet_manager, batches)
+    }
+
+    /// Deliver `ReliableOrdered` messages in sequence order, holding each for at most
+    /// `window` while an earlier one is missing (`None` delivers in arrival order)
+    pub fn set_reorder_window(&self, window: Option<Duration>) {
+        self.packet_manager.lock().unwrap().set_reorder_window(window);
+    }
+
+    /// Change how packets to the server are retransmitted: the ACK timeout, retries,
+    /// backoff and packets in flight (`RetransmitPolicy::default()` unless set)
+    pub fn set_retransmit_policy(&self, policy: RetransmitPolicy) {
+        self.packet_manager.lock().unwrap().set_retransmit_policy(policy);
+    }
+
+    /// Hold ACKs to the server up to `delay` so one covers several packets or rides on
+    /// a message going out. Zero, the default, ACKs every reliable packet at once.
+    pub fn set_max_ack_delay(&self, delay: Duration) {
+        self.packet_manager.lock().unwrap().set_max_ack_delay(delay);
+    }
+
+    /// Cap the send rate with a congestion controller; packets over budget are queued
+    /// and sent as ACKs come back (`None` sends immediately)
+    pub fn set_congestion_controller(&self, controller: Option<Box<dyn CongestionController>>) {
+        self.packet_manager.lock().unwrap().set_congestion_controller(controller);
+    }
+
+    /// Record the packets of this session, and of any it reconnects to, to `capture`
+    /// (`None` stops recording)
+    pub fn set_capture(&self, capture: Option<Capture>) {
+        self.packet_manager.lock().unwrap().set_capture(capture);
+    }
+
+    /// Probe the path to the server for datagrams up to `max_size` bytes (see `mtu`) in
+    /// the background; messages are fragmented to each size the search confirms
+    pub fn discover_mtu(&self, max_size: usize) {
+        *self.mtu_probe.lock().unwrap() = Some(MtuProbe::new(max_size));
+    }
+
+    /// Datagram size messages are currently fragmented to
+    pub fn mtu(&self) -> usize {
+        self.packet_manager.lock().unwrap().max_packet_size()
+    }
+
+    /// Fragment to `size`-byte datagrams, e.g. on a link known to carry jumbo frames.
+    /// Stops any MTU discovery in progress.
+    pub fn set_max_packet_size(&self, size: usize) {
+        *self.mtu_probe.lock().unwrap() = None;
+        self.packet_manager.lock().unwrap().set_max_packet_size(size);
+    }
+
+    /// Set how many chunk packets `send_stream` keeps in flight before waiting for ACKs
+    pub fn set_stream_window(&mut self, window: usize) {
+        self.stream_window = window.max(1);
+    }
+
+    /// Send each `send_stream` chunk with a CRC-32 and end the stream with a SHA-256 of
+    /// the payload, so the server drops a corrupted stream at the first bad chunk
+    pub fn set_stream_checksums(&mut self, enabled: bool) {
+        self.stream_checksums = enabled;
+    }
+
+    /// Stream `total_size` bytes from `reader` to the server as a chunked field on
+    /// `STREAM_CHANNEL` at `Priority::Low`, so regular messages cut ahead of it.
+    /// Each chunk travels in its own packet; sending pauses while the stream window
+    /// is full of un-ACKed packets. The start frame is ACKed before any data is sent
+    /// and every data frame is ACKed before the end frame, so the server sees them in
+    /// that order even though data chunks may arrive out of order. Fails with `TimedOut`,
+    /// without sending the end frame, once a frame runs out of retries.
+    pub fn send_stream(&self, field_id: u16, total_size: u64, mut reader: impl Read) -> io::Result<()> {
+        let trailer = if self.stream_checksums { CHUNK_CRC_LEN } else { 0 };
+        let chunk_size = self.packet_manager.lock().unwrap().payload_limit() - CHUNK_DATA_HEADER - trailer;
+
+        let start = self.send_stream_frame(|e| e.encode_chunk_start(field_id, total_size))?;
+        self.wait_for(|_| start.is_resolved())?;
+        let mut unconfirmed = vec![start];
+        check_stream_frames(&mut unconfirmed)?;
+
+        let mut buf = vec![0u8; chunk_size];
+        let mut hasher = Sha256::new();
+        let mut remaining = total_size;
+        let mut index = 0u16;
+        while remaining > 0 {
+            let len = remaining.min(chunk_size as u64) as usize;
+            reader.read_exact(&mut buf[..len])?;
+            self.wait_for(|pm| pm.pending_ack_count() < self.stream_window)?;
+            check_stream_frames(&mut unconfirmed)?;
+            let frame = if self.stream_checksums {
+                hasher.update(&buf[..len]);
+                self.send_stream_frame(|e| e.encode_chunk_data_checked(index, &buf[..len]))?
+            } else {
+                self.send_stream_frame(|e| e.encode_chunk_data(index, &buf[..len]))?
+            };
+            unconfirmed.push(frame);
+            // The server reorders by the wrapping 16-bit index
+            index = index.wrapping_add(1);
+            remaining -= len as u64;
+        }
+
+        self.wait_for(|pm| !pm.has_pending_acks())?;
+        check_stream_frames(&mut unconfirmed)?;
+        if self.stream_checksums {
+            self.send_stream_frame(|e| e.encode_chunk_end_hashed(&hasher.finalize().into()))?;
+        } else {
+            self.send_stream_frame(|e| e.encode_chunk_end())?;
+        }
         Ok(())
     }
 
+    fn send_stream_frame(&self, encode: impl FnOnce(&mut BiWiEncoder)) -> io::Result<SendHandle> {
+        let mut encoder = BiWiEncoder::new();
+        encode(&mut encoder);
+        let mut pm = self.packet_manager.lock().unwrap();
+        let packet = pm.create_flagged_packet(encoder.as_slice(), FLAG_STREAM | channel_flags(STREAM_CHANNEL));
+        let receipt = SendHandle::new();
+        pm.attach_receipt(std::slice::from_ref(&packet), &receipt);
+        for packet in pm.pace_with_priority(vec![packet], Priority::Low) {
+            self.socket.send_to(&pm.encode(&packet), self.server_addr)?;
+        }
+        Ok(receipt)
+    }
+
+    /// Block until `ready` holds. Packets that exhaust their retries leave the pending
+    /// set, so waits on it finish while the receive loop is running; callers that need
+    /// to know whether a packet arrived check its receipt.
+    fn wait_for(&self, ready: impl Fn(&PacketManager) -> bool) -> io::Result<()> {
+        loop {
+            if ready(&self.packet_manager.lock().unwrap()) {
+                return Ok(());
+            }
+            if !self.is_active() {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "Client disconnected"));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
     /// Try to receive a message (non-blocking)
     pub fn try_recv(&self) -> Option<BiWiMessage> {
-        self.message_rx.try_recv().ok().and_then(|data| {
+        self.message_rx.lock().unwrap().try_recv().ok().and_then(|data| {
+            self.mark_read();
             BiWiMessage::from_buffer(&data).ok()
         })
     }
 
-    /// Receive a message (blocking)
+    /// Receive a message (blocking). Messages that arrived before the session ended can
+    /// still be received; after them this fails with `ConnectionReset`.
     pub fn recv(&self) -> io::Result<BiWiMessage> {
         self.message_rx
+            .lock()
+            .unwrap()
             .recv()
             .ok()
-            .and_then(|data| BiWiMessage::from_buffer(&data).ok())
+            .and_then(|data| {
+                self.mark_read();
+                BiWiMessage::from_buffer(&data).ok()
+            })
             .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionReset, "Channel closed"))
     }
 
-    /// Receive with timeout
+    /// Receive with timeout; fails with `ConnectionReset` as soon as the session has
+    /// ended and its messages have been taken, rather than waiting out `timeout`
     pub fn recv_timeout(&self, timeout: Duration) -> io::Result<BiWiMessage> {
-        match self.message_rx.recv_timeout(timeout) {
-            Ok(data) => BiWiMessage::from_buffer(&data)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
-            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "Recv timeout")),
+        let received = self.message_rx.lock().unwrap().recv_timeout(timeout);
+        match received {
+            Ok(data) => {
+                self.mark_read();
+                BiWiMessage::from_buffer(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(io::ErrorKind::TimedOut, "Recv timeout")),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(io::ErrorKind::ConnectionReset, "Channel closed")),
         }
     }
 
+    /// One message left the queue; the window advertised in the next ACK grows by one
+    fn mark_read(&self) {
+        self.unread.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Let the server have up to `packets` reliable packets in flight to this client,
+    /// less the messages waiting to be read (`DEFAULT_RECEIVE_WINDOW` by default)
+    pub fn set_receive_window(&self, packets: u32) {
+        self.packet_manager.lock().unwrap().set_receive_window(packets);
+    }
+
     /// Check if connected and receiving
     pub fn is_active(&self) -> bool {
         *self.running.lock().unwrap()
     }
 
-    /// Disconnect from server
+    /// Live RTT, traffic and loss counters for the connection to the server
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.packet_manager.lock().unwrap().stats()
+    }
+
+    /// Session ID the server issued during the handshake (or the latest reconnect)
+    pub fn session_id(&self) -> u64 {
+        self.session_id.load(Ordering::Relaxed)
+    }
+
+    /// Recover from a lost server as `config` says (see `ClientConfig`)
+    pub fn with_config(self, config: ClientConfig) -> Self {
+        *self.config.lock().unwrap() = config;
+        self
+    }
+
+    /// Connection events since the last call, oldest first (see `ClientEvent`)
+    pub fn events(&self) -> impl Iterator<Item = ClientEvent> + '_ {
+        self.events.lock().unwrap().try_iter().collect::<Vec<_>>().into_iter()
+    }
+
+    /// Wait up to `timeout` for every sent packet to be ACKed, then disconnect.
+    /// Returns `TimedOut` if packets were still un-ACKed; the session is closed either way.
+    pub fn close(mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let flushed = loop {
+            if !self.packet_manager.lock().unwrap().has_pending_acks() {
+                break true;
+            }
+            if Instant::now() >= deadline || !self.is_active() {
+                break false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        self.disconnect();
+        if flushed {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "Packets still un-ACKed at close"))
+        }
+    }
+
+    /// Close the session and stop the receive loop, waiting for its thread to exit
     pub fn disconnect(&mut self) {
-        *self.running.lock().unwrap() = false;
+        let mut running = self.running.lock().unwrap();
+        if *running {
+            let mut pm = self.packet_manager.lock().unwrap();
+            pm.cancel_receipts();
+            let disconnect = pm.encode(&UdpPacket::disconnect(self.session_id()));
+            let _ = self.socket.send_to(&disconnect, self.server_addr);
+            *running = false;
+        }
+        drop(running);
+
+        let Some(receiver) = self.receiver.take() else {
+            return;
+        };
+        // An empty datagram to our own port wakes the thread from its read at once
+        if let Ok(local) = self.socket.local_addr() {
+            let loopback = if local.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() };
+            let _ = self.socket.send_to(&[], SocketAddr::new(loopback, local.port()));
+        }
+        let _ = receiver.join();
+    }
+
+    /// Ping the server whenever nothing else has been sent for `interval` (default
+    /// `DEFAULT_KEEP_ALIVE_INTERVAL`); `None` stops the keep-alives
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        *self.keep_alive.lock().unwrap() = interval;
     }
 
-    /// Send a ping (keep-alive)
+    /// Send a ping (keep-alive); its round trip shows up in `last_rtt` once the Pong arrives
     pub fn ping(&self) -> io::Result<()> {
+        self.send_ping().map(drop)
+    }
+
+    /// Ping the server and wait up to `timeout` for the Pong, returning the round trip
+    pub fn ping_rtt(&self, timeout: Duration) -> io::Result<Duration> {
+        let id = self.send_ping()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(rtt) = self.packet_manager.lock().unwrap().ping_rtt(id) {
+                return Ok(rtt);
+            }
+            if !self.is_active() {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "Client disconnected"));
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No pong"));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Round trip of the most recently answered ping
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.packet_manager.lock().unwrap().last_rtt()
+    }
+
+    /// The server's clock reading now, estimated from ping exchanges (keep-alives
+    /// included); `None` until a Pong has come back. `ping_rtt` adds a sample on demand.
+    pub fn server_time_estimate(&self) -> Option<SystemTime> {
+        self.packet_manager.lock().unwrap().peer_time_estimate()
+    }
+
+    /// Offset and drift of the server's clock against this machine's
+    pub fn clock_estimate(&self) -> Option<ClockEstimate> {
+        self.packet_manager.lock().unwrap().clock_estimate()
+    }
+
+    fn send_ping(&self) -> io::Result<u32> {
         let mut pm = self.packet_manager.lock().unwrap();
         let ping = pm.create_ping_packet();
-        self.socket.send_to(&ping.to_bytes(), self.server_addr)?;
-        Ok(())
+        self.socket.send_to(&pm.encode(&ping), self.server_addr)?;
+        Ok(ping.sequence)
+    }
+}
+
+/// Packet manager that tags every packet with the session, so the server keeps
+/// recognising the client if its address changes, and uses the compact headers the
+/// server `accepted`
+pub(crate) fn session_packet_manager(session_id: u64, cipher: Option<PacketCipher>, accepted: u32) -> PacketManager {
+    let mut pm = PacketManager::new();
+    pm.set_session(session_id);
+    pm.set_cipher(cipher);
+    pm.set_compact_headers(accepted & FLAG_COMPACT_HEADERS != 0);
+    pm
+}
+
+/// `Connect` to send, plus the random it carries when asking for an encrypted session
+pub(crate) fn connect_request(psk: Option<&[u8]>, credentials: &[u8]) -> (UdpPacket, [u8; HANDSHAKE_RANDOM_LEN]) {
+    let client_random = handshake_random();
+    let mut connect = match psk {
+        _ if !credentials.is_empty() => UdpPacket::connect_with_credentials(&client_random, credentials),
+        Some(_) => UdpPacket::connect_encrypted(&client_random),
+        None => UdpPacket::connect(),
+    };
+    connect.flags |= FLAG_FIELD_COMPRESSION | FLAG_COMPACT_HEADERS;
+    (connect, client_random)
+}
+
+/// Session ID and cipher granted by a `ConnectAck`. With a pre-shared key, the server
+/// must prove it holds the same key.
+pub(crate) fn accept_session(
+    ack: &UdpPacket,
+    psk: Option<&[u8]>,
+    client_random: &[u8],
+) -> io::Result<(u64, Option<PacketCipher>)> {
+    let session_id = ack
+        .session_id()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed connect ack"))?;
+    let Some(psk) = psk else {
+        return Ok((session_id, None));
+    };
+
+    let server_random = match (ack.handshake_random(), ack.handshake_confirmation()) {
+        (Some(server_random), Some(proof))
+            if verify_confirmation(psk, session_id, client_random, &server_random, &proof) =>
+        {
+            server_random
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Server did not prove the pre-shared key",
+            ))
+        }
+    };
+    Ok((session_id, Some(PacketCipher::derive(psk, session_id, client_random, &server_random, Role::Client))))
+}
+
+/// Pass each message in a delivered data packet to the application
+fn emit(
+    packet: &UdpPacket,
+    stats: &StatsCounters,
+    tx: &Sender<Vec<u8>>,
+    unread: &AtomicUsize,
+    requests: &Mutex<HashMap<u64, Waiter>>,
+    interceptors: &Mutex<Interceptors>,
+    server_addr: SocketAddr,
+) {
+    let ctx = MessageContext { peer: Some(server_addr), client_id: None };
+    for message in packet.messages() {
+        stats.record_received(message.len());
+        let mut interceptors = interceptors.lock().unwrap();
+        let intercepted;
+        let message = if interceptors.is_empty() {
+            message
+        } else {
+            // Messages that don't decode pass through for the application to reject
+            match BiWiMessage::from_buffer(message) {
+                Ok(decoded) => match interceptors.incoming(decoded, &ctx) {
+                    Some(kept) => {
+                        intercepted = kept.to_vec();
+                        &intercepted[..]
+                    }
+                    None => continue,
+                },
+                Err(_) => message,
+            }
+        };
+        drop(interceptors);
+        // Responses go to the `request` call or stream waiting on them rather than the queue
+        let correlation_id = BiWiDecoder::new(message).decode_envelope().ok().flatten().map(|e| e.correlation_id);
+        if let Some(id) = correlation_id {
+            let mut requests = requests.lock().unwrap();
+            if let Some(waiter) = requests.get(&id) {
+                let stream = waiter.stream;
+                let response = BiWiMessage::from_buffer(message).ok();
+                let delivered = response.is_some();
+                if let Some(response) = response {
+                    let _ = waiter.tx.send(response);
+                }
+                if !stream {
+                    requests.remove(&id);
+                }
+                if delivered {
+                    continue;
+                }
+            }
+        }
+        unread.fetch_add(1, Ordering::Relaxed);
+        let _ = tx.send(message.to_vec());
+    }
+}
+
+/// Tell the application a reliable packet, and any whole messages in it, never got through
+fn report_dropped(packet: &UdpPacket, events: &Sender<ClientEvent>) {
+    let _ = events.send(ClientEvent::PacketDropped(packet.sequence));
+    if packet.is_fragment() || packet.is_stream() {
+        return;
+    }
+    for message in packet.messages().into_iter().filter_map(|m| BiWiMessage::from_buffer(m).ok()) {
+        let _ = events.send(ClientEvent::RetransmitExhausted(message));
+    }
+}
+
+/// Fail a stream once one of its frames was given up on, forgetting the delivered ones
+fn check_stream_frames(frames: &mut Vec<SendHandle>) -> io::Result<()> {
+    if frames.iter().any(|frame| frame.outcome() == Some(SendOutcome::Dropped)) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "Stream packet ran out of retries"));
+    }
+    frames.retain(|frame| !frame.is_resolved());
+    Ok(())
+}
+
+/// Wrap coalesced batches in packets and send them
+fn send_batches(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+    packet_manager: &Mutex<PacketManager>,
+    batches: impl IntoIterator<Item = (SendMode, Vec<u8>)>,
+) -> io::Result<()> {
+    let mut pm = packet_manager.lock().unwrap();
+    for (mode, batch) in batches {
+        let packet = pm.create_batch_packet(DEFAULT_CHANNEL, &batch, mode);
+        for packet in pm.pace(vec![packet]) {
+            socket.send_to(&pm.encode(&packet), server_addr)?;
+        }
+    }
+    Ok(())
+}
+
+/// Send `Connect` until the server answers with a session ID. Returns it with the
+/// session's cipher and the handshake flags the server accepted.
+fn handshake(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+    psk: Option<&[u8]>,
+    credentials: &[u8],
+) -> io::Result<(u64, Option<PacketCipher>, u32)> {
+    let _span = span!(DEBUG, "biwi.handshake", server = %server_addr, encrypted = psk.is_some());
+    socket.set_read_timeout(Some(CONNECT_RETRY_INTERVAL))?;
+    let mut buf = [0u8; 128];
+    let (connect, client_random) = connect_request(psk, credentials);
+
+    for _ in 0..CONNECT_ATTEMPTS {
+        socket.send_to(&connect.to_bytes(), server_addr)?;
+        let deadline = Instant::now() + CONNECT_RETRY_INTERVAL;
+
+        while Instant::now() < deadline {
+            let n = match socket.recv_from(&mut buf) {
+                Ok((n, addr)) if addr == server_addr => n,
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            };
+            let Ok(packet) = UdpPacket::from_bytes(&buf[..n]) else {
+                continue;
+            };
+            match packet.packet_type {
+                PacketType::ConnectAck => {
+                    return accept_session(&packet, psk, &client_random).map(|(id, cipher)| (id, cipher, packet.flags));
+                }
+                PacketType::Disconnect => {
+                    event!(DEBUG, "server refused the session");
+                    return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "Server refused the session"));
+                }
+                _ => {}
+            }
+        }
+        event!(DEBUG, "no ConnectAck yet, retrying");
+    }
+
+    event!(DEBUG, "handshake timed out");
+    Err(io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"))
+}
+
+impl BiWiTransport for BiWiUdpClient {
+    fn send_message(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.send(message).map(drop)
+    }
+
+    fn recv_message(&self, timeout: Option<Duration>) -> io::Result<BiWiMessage> {
+        match timeout {
+            Some(timeout) => self.recv_timeout(timeout),
+            None => self.recv(),
+        }
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.stats.snapshot()
     }
 }
 
@@ -0,0 +1,145 @@
+//! BiWi Clock Synchronization
+//! NTP-style offset estimation over the Ping/Pong exchange. A Pong carries, after the
+//! echoed Ping timestamp, the time the peer received the Ping and the time it sent
+//! the Pong, both in microseconds since the Unix epoch on the peer's clock. With the
+//! local send and receive times that gives one sample of the offset between the two
+//! clocks, accurate to within half the round trip's network delay.
+//!
+//! Samples with the least delay are the most trustworthy, so the offset is taken
+//! from the best of the recent ones; the drift between the clocks is the slope of
+//! the offset over the whole sample window.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Bytes a Pong adds after the echoed Ping timestamp: receive and transmit times
+pub const PONG_TIMES_LEN: usize = 16;
+
+/// Samples kept for the drift fit
+const CLOCK_SAMPLES: usize = 16;
+/// The offset comes from the lowest-delay sample among this many of the newest
+const OFFSET_FILTER: usize = 8;
+
+/// Microseconds since the Unix epoch on this machine's clock
+pub(crate) fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+}
+
+/// One Ping/Pong exchange
+#[derive(Debug, Clone, Copy)]
+struct ClockSample {
+    /// Local time the Pong arrived
+    local: u64,
+    /// Peer clock minus local clock, in microseconds
+    offset: i64,
+    /// Round trip minus the peer's processing time
+    delay: u64,
+}
+
+/// What is known about a peer's clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockEstimate {
+    /// Peer clock minus the local reference clock, in microseconds
+    pub offset_micros: i64,
+    /// How fast the peer clock runs against the local one, in parts per million
+    /// (`None` until the samples span some time)
+    pub drift_ppm: Option<f64>,
+    /// Network delay of the sample the offset came from; the offset is good to half of it
+    pub delay: Duration,
+}
+
+/// Offset and drift of a peer's clock against a local microsecond clock
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+}
+
+impl ClockSync {
+    /// Record an exchange: `sent` and `received` on the local clock, the peer's
+    /// `peer_received` and `peer_sent` on its own, all in microseconds
+    pub fn add_sample(&mut self, sent: u64, peer_received: u64, peer_sent: u64, received: u64) {
+        let round_trip = received.saturating_sub(sent);
+        let processing = peer_sent.saturating_sub(peer_received);
+        let offset = ((peer_received as i128 - sent as i128) + (peer_sent as i128 - received as i128)) / 2;
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ClockSample {
+            local: received,
+            offset: offset as i64,
+            delay: round_trip.saturating_sub(processing),
+        });
+    }
+
+    /// The current estimate; `None` before the first sample
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let best = self.samples.iter().rev().take(OFFSET_FILTER).min_by_key(|s| s.delay)?;
+        Some(ClockEstimate {
+            offset_micros: best.offset,
+            drift_ppm: self.drift_ppm(),
+            delay: Duration::from_micros(best.delay),
+        })
+    }
+
+    /// The peer's clock reading at local time `local`, projected with the drift
+    pub fn peer_time(&self, local: u64) -> Option<u64> {
+        let best = self.samples.iter().rev().take(OFFSET_FILTER).min_by_key(|s| s.delay)?;
+        let drift = self.drift_ppm().unwrap_or(0.0) * 1e-6;
+        let elapsed = local as f64 - best.local as f64;
+        let time = local as f64 + best.offset as f64 + drift * elapsed;
+        Some(time.max(0.0) as u64)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Least-squares slope of offset over local time
+    fn drift_ppm(&self) -> Option<f64> {
+        let n = self.samples.len() as f64;
+        let first = self.samples.front()?.local as f64;
+        let mean_t = self.samples.iter().map(|s| s.local as f64 - first).sum::<f64>() / n;
+        let mean_o = self.samples.iter().map(|s| s.offset as f64).sum::<f64>() / n;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for sample in &self.samples {
+            let t = sample.local as f64 - first - mean_t;
+            covariance += t * (sample.offset as f64 - mean_o);
+            variance += t * t;
+        }
+        // Under a second of spread says nothing useful about drift
+        if self.samples.len() < 2 || variance / n < 1e12 {
+            return None;
+        }
+        Some(covariance / variance * 1e6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_and_drift() {
+        let mut clock = ClockSync::default();
+        assert_eq!(clock.estimate(), None);
+
+        // The peer runs 5 s ahead and gains 100 µs per second; one-way delays vary
+        let peer = |local: u64| local + 5_000_000 + local / 10_000;
+        for i in 0..10u64 {
+            let sent = i * 1_000_000;
+            let (there, back) = if i == 3 { (400, 100) } else { (2_000 + i * 100, 3_000) };
+            let peer_received = peer(sent + there);
+            clock.add_sample(sent, peer_received, peer_received + 50, sent + there + 50 + back);
+        }
+        let estimate = clock.estimate().unwrap();
+        // The low-delay sample wins, off by half its asymmetry
+        assert_eq!(estimate.delay, Duration::from_micros(500));
+        assert!((estimate.offset_micros - 5_000_300).abs() <= 200, "offset {}", estimate.offset_micros);
+        let drift = estimate.drift_ppm.unwrap();
+        assert!((drift - 100.0).abs() < 100.0, "drift {}", drift);
+
+        let later = 20_000_000;
+        let projected = clock.peer_time(later).unwrap() as i64;
+        assert!((projected - peer(later) as i64).abs() < 2_000, "{} vs {}", projected, peer(later));
+    }
+}
@@ -0,0 +1,165 @@
+//! BiWi Message Coalescing
+//! Batches small messages bound for the same peer into one datagram, so tiny game
+//! updates don't each pay for a packet header. A batch payload is a big-endian u16
+//! message count, one u16 length per message, then the messages back to back; it
+//! travels in a single packet flagged `FLAG_BATCH`.
+
+use crate::network::SendMode;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a message may wait for company before its batch is flushed
+pub const DEFAULT_COALESCE_DELAY: Duration = Duration::from_millis(5);
+
+/// Bytes a batch adds around its first message: count + one length
+pub const BATCH_OVERHEAD: usize = 4;
+
+/// Encode `messages` as one batch payload
+pub fn encode_batch<M: AsRef<[u8]>>(messages: &[M]) -> Vec<u8> {
+    let size = 2 + messages.iter().map(|m| 2 + m.as_ref().len()).sum::<usize>();
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(&(messages.len() as u16).to_be_bytes());
+    for message in messages {
+        out.extend_from_slice(&(message.as_ref().len() as u16).to_be_bytes());
+    }
+    for message in messages {
+        out.extend_from_slice(message.as_ref());
+    }
+    out
+}
+
+/// Split a batch payload into its messages; `None` if the lengths don't add up
+pub fn decode_batch(payload: &[u8]) -> Option<Vec<&[u8]>> {
+    let count = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+    let lengths = payload.get(2..2 + count * 2)?;
+    let mut rest = &payload[2 + count * 2..];
+
+    let mut messages = Vec::with_capacity(count);
+    for len in lengths.chunks(2) {
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        if len > rest.len() {
+            return None;
+        }
+        let (message, tail) = rest.split_at(len);
+        messages.push(message);
+        rest = tail;
+    }
+    rest.is_empty().then_some(messages)
+}
+
+struct Batch {
+    messages: Vec<Vec<u8>>,
+    /// Encoded size of the batch so far
+    size: usize,
+    since: Instant,
+}
+
+/// Collects messages per `SendMode` until a batch is full or has waited `max_delay`
+pub struct Coalescer {
+    max_size: usize,
+    max_delay: Duration,
+    batches: HashMap<SendMode, Batch>,
+}
+
+impl Coalescer {
+    /// Batches never exceed `max_size` encoded bytes (normally the packet payload limit)
+    pub fn new(max_size: usize, max_delay: Duration) -> Self {
+        Self {
+            max_size,
+            max_delay,
+            batches: HashMap::new(),
+        }
+    }
+
+    /// True if `len` bytes fit in a batch at all; larger messages should be sent on their own
+    pub fn fits(&self, len: usize) -> bool {
+        len + BATCH_OVERHEAD <= self.max_size && len <= u16::MAX as usize
+    }
+
+    /// Add a message, returning the batch it pushed out if the current one was full.
+    /// The message must `fit`.
+    pub fn push(&mut self, mode: SendMode, message: Vec<u8>) -> Option<Vec<u8>> {
+        debug_assert!(self.fits(message.len()));
+        let added = 2 + message.len();
+
+        let full = self
+            .batches
+            .get(&mode)
+            .is_some_and(|batch| batch.size + added > self.max_size);
+        let flushed = if full { self.take(mode) } else { None };
+
+        let batch = self.batches.entry(mode).or_insert_with(|| Batch {
+            messages: Vec::new(),
+            size: 2,
+            since: Instant::now(),
+        });
+        batch.messages.push(message);
+        batch.size += added;
+        flushed
+    }
+
+    /// Batches that have waited at least `max_delay`
+    pub fn flush_due(&mut self) -> Vec<(SendMode, Vec<u8>)> {
+        self.flush_due_at(Instant::now())
+    }
+
+    /// Batches that have waited at least `max_delay` as of `now`
+    pub fn flush_due_at(&mut self, now: Instant) -> Vec<(SendMode, Vec<u8>)> {
+        let due: Vec<SendMode> = self
+            .batches
+            .iter()
+            .filter(|(_, batch)| now.saturating_duration_since(batch.since) >= self.max_delay)
+            .map(|(mode, _)| *mode)
+            .collect();
+        due.into_iter()
+            .filter_map(|mode| self.take(mode).map(|payload| (mode, payload)))
+            .collect()
+    }
+
+    /// Every pending batch, regardless of age
+    pub fn flush(&mut self) -> Vec<(SendMode, Vec<u8>)> {
+        let modes: Vec<SendMode> = self.batches.keys().copied().collect();
+        modes
+            .into_iter()
+            .filter_map(|mode| self.take(mode).map(|payload| (mode, payload)))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    fn take(&mut self, mode: SendMode) -> Option<Vec<u8>> {
+        self.batches.remove(&mode).map(|batch| encode_batch(&batch.messages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let payload = encode_batch(&[&b"ab"[..], b"", b"cde"]);
+        assert_eq!(payload, [0, 3, 0, 2, 0, 0, 0, 3, b'a', b'b', b'c', b'd', b'e']);
+        assert_eq!(decode_batch(&payload).unwrap(), [&b"ab"[..], b"", b"cde"]);
+
+        assert!(decode_batch(&payload[..payload.len() - 1]).is_none());
+        assert!(decode_batch(&[0, 1, 0]).is_none());
+    }
+
+    #[test]
+    fn test_coalescer_flushes_on_size_and_time() {
+        let mut coalescer = Coalescer::new(20, Duration::ZERO);
+        assert!(coalescer.push(SendMode::Unreliable, vec![1; 6]).is_none());
+        assert!(coalescer.push(SendMode::Unreliable, vec![2; 6]).is_none());
+        // 2 + 8 + 8 + 8 would exceed 20 bytes
+        let full = coalescer.push(SendMode::Unreliable, vec![3; 6]).unwrap();
+        assert_eq!(decode_batch(&full).unwrap().len(), 2);
+
+        let due = coalescer.flush_due();
+        assert_eq!(due.len(), 1);
+        assert_eq!(decode_batch(&due[0].1).unwrap(), [&[3u8; 6][..]]);
+        assert!(coalescer.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! BiWi Field Compression
+//! Session-level compression of repeated field values, in the spirit of HPACK. The
+//! sender remembers the values it has put in the receiver's field table; a field whose
+//! value is unchanged since then is sent as a FIELD_REF to that entry (a type byte and
+//! a varint index) instead of the value. A new value goes out as FIELD_INSERT, which
+//! the receiver's `FieldTable` adds as the next entry.
+//!
+//! Entries carry absolute indices and are only referenced once the message that
+//! inserted them has been delivered, so loss never leaves the receiver resolving a
+//! reference it lacks: an insert whose message was dropped is simply made again by a
+//! later message. Both sides keep the last `FIELD_TABLE_SIZE` inserts.
+//!
+//! UDP sessions negotiate it during the handshake. Clients offer it in their `Connect`
+//! and servers configured with `ServerConfig::with_field_compression` accept in the
+//! `ConnectAck`; from then on the client compresses the reliable, ordered messages it
+//! sends on the default channel, so each reference arrives after its insert.
+
+use crate::decoder::DecodeResult;
+use crate::encoder::{BiWiEncoder, BiWiValue};
+use crate::message::BiWiMessage;
+use crate::receipt::{SendHandle, SendOutcome};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+/// Inserts each side remembers; references reach back at most this far
+pub const FIELD_TABLE_SIZE: u32 = 256;
+
+/// Largest encoded value a table entry may hold
+pub const MAX_ENTRY_LEN: usize = 512;
+
+/// Values shorter than this cost less to resend than to reference
+const MIN_ENTRY_LEN: usize = 4;
+
+/// The latest value inserted for one field
+struct Entry {
+    index: u32,
+    value: BiWiValue,
+    /// The message that inserted it was delivered
+    acked: bool,
+}
+
+/// The sending side of a session's field table
+#[derive(Default)]
+pub struct FieldCompressor {
+    entries: HashMap<u32, Entry>,
+    next_index: u32,
+    /// Inserts awaiting delivery, with the receipt of the message that made them
+    in_flight: Vec<(Range<u32>, SendHandle)>,
+}
+
+impl FieldCompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode `message`, referencing delivered entries where values are unchanged.
+    /// Returns the bytes and the entries the message inserts, to pass to `track`
+    /// (or to `acknowledge` or `forget` once its fate is known).
+    pub fn encode(&mut self, message: &BiWiMessage) -> (Vec<u8>, Range<u32>) {
+        self.settle();
+        let first = self.next_index;
+        let mut encoder = BiWiEncoder::new();
+        if let Some(envelope) = message.envelope() {
+            encoder.encode_envelope(envelope);
+        }
+        for (field_id, value) in message.iter_sorted() {
+            match self.entries.get(&field_id).filter(|entry| entry.value == *value) {
+                Some(entry) if entry.acked && self.next_index.wrapping_sub(entry.index) <= FIELD_TABLE_SIZE => {
+                    encoder.encode_field_ref(field_id, entry.index);
+                }
+                // Its insert is still on the way
+                Some(entry) if !entry.acked => encoder.encode_field(field_id, value),
+                _ if (MIN_ENTRY_LEN..=MAX_ENTRY_LEN).contains(&encoded_len(value)) => {
+                    let index = self.next_index;
+                    self.next_index = self.next_index.wrapping_add(1);
+                    encoder.encode_field_insert(field_id, index, value);
+                    self.entries.insert(field_id, Entry { index, value: value.clone(), acked: false });
+                }
+                _ => encoder.encode_field(field_id, value),
+            }
+        }
+        (encoder.to_buffer(), first..self.next_index)
+    }
+
+    /// Acknowledge or forget `inserts` once `receipt` resolves
+    pub fn track(&mut self, inserts: Range<u32>, receipt: SendHandle) {
+        if !inserts.is_empty() {
+            self.in_flight.push((inserts, receipt));
+        }
+    }
+
+    /// The message carrying `inserts` was delivered; later messages may reference them
+    pub fn acknowledge(&mut self, inserts: Range<u32>) {
+        for entry in self.entries.values_mut() {
+            if inserts.contains(&entry.index) {
+                entry.acked = true;
+            }
+        }
+    }
+
+    /// The message carrying `inserts` never arrived; their values are inserted again
+    /// next time they are sent
+    pub fn forget(&mut self, inserts: Range<u32>) {
+        self.entries.retain(|_, entry| !inserts.contains(&entry.index));
+    }
+
+    /// Apply the outcomes of resolved receipts
+    fn settle(&mut self) {
+        let mut pending = Vec::with_capacity(self.in_flight.len());
+        for (inserts, receipt) in std::mem::take(&mut self.in_flight) {
+            match receipt.outcome() {
+                None => pending.push((inserts, receipt)),
+                Some(SendOutcome::Delivered) => self.acknowledge(inserts),
+                Some(_) => self.forget(inserts),
+            }
+        }
+        self.in_flight = pending;
+    }
+}
+
+fn encoded_len(value: &BiWiValue) -> usize {
+    let mut encoder = BiWiEncoder::with_capacity(16);
+    encoder.encode_value(value);
+    encoder.size()
+}
+
+/// The receiving side of a session's field table
+#[derive(Debug, Clone, Default)]
+pub struct FieldTable {
+    entries: BTreeMap<u32, BiWiValue>,
+}
+
+impl FieldTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries held
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn get(&self, index: u32) -> Option<&BiWiValue> {
+        self.entries.get(&index)
+    }
+
+    /// Add entry `index`, dropping those too old to be referenced any more
+    pub(crate) fn insert(&mut self, index: u32, value: BiWiValue) {
+        self.entries.insert(index, value);
+        let newest = self.entries.last_key_value().map_or(index, |(&newest, _)| newest);
+        let oldest = newest.saturating_sub(FIELD_TABLE_SIZE - 1);
+        while self.entries.first_key_value().is_some_and(|(&first, _)| first < oldest) {
+            self.entries.pop_first();
+        }
+    }
+}
+
+/// Decode a message, resolving field table entries through `fields` if the session has one
+pub(crate) fn decode_message(buffer: &[u8], fields: Option<&mut FieldTable>) -> DecodeResult<BiWiMessage> {
+    match fields {
+        Some(fields) => BiWiMessage::from_buffer_compressed(buffer, fields),
+        None => BiWiMessage::from_buffer(buffer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DecodeError;
+
+    fn reading(temperature: f64) -> BiWiMessage {
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from("NASDAQ"));
+        msg.set_field(2, BiWiValue::from("device-7f3a9c"));
+        msg.set_field(3, BiWiValue::Float64(temperature));
+        msg.set_field(4, BiWiValue::Int32(1));
+        msg
+    }
+
+    #[test]
+    fn test_unchanged_fields_reference_delivered_entries() {
+        let mut sender = FieldCompressor::new();
+        let mut receiver = FieldTable::new();
+
+        let (first, inserts) = sender.encode(&reading(20.5));
+        assert_eq!(inserts, 0..3);
+        assert_eq!(BiWiMessage::from_buffer_compressed(&first, &mut receiver).unwrap(), reading(20.5));
+
+        // Until the inserts are delivered, values are resent rather than referenced
+        let (unconfirmed, none) = sender.encode(&reading(20.5));
+        assert!(none.is_empty());
+        assert_eq!(unconfirmed.len(), reading(20.5).to_vec().len());
+
+        sender.acknowledge(inserts);
+        let (second, inserts) = sender.encode(&reading(21.0));
+        assert_eq!(inserts, 3..4);
+        assert!(second.len() < first.len() / 2);
+        assert_eq!(BiWiMessage::from_buffer_compressed(&second, &mut receiver).unwrap(), reading(21.0));
+        assert_eq!(receiver.len(), 4);
+
+        // References need the session's table
+        assert!(matches!(BiWiMessage::from_buffer(&second), Err(DecodeError::InvalidData(_))));
+        assert!(BiWiMessage::from_buffer_compressed(&second, &mut FieldTable::new()).is_err());
+    }
+
+    #[test]
+    fn test_lost_inserts_are_made_again() {
+        let mut sender = FieldCompressor::new();
+        let mut receiver = FieldTable::new();
+
+        let (_lost, inserts) = sender.encode(&reading(20.5));
+        let receipt = SendHandle::new();
+        sender.track(inserts, receipt.clone());
+        receipt.resolve(SendOutcome::Dropped);
+
+        // The receiver never saw the first message; the next one inserts again
+        let (resent, inserts) = sender.encode(&reading(20.5));
+        assert_eq!(inserts, 3..6);
+        assert_eq!(BiWiMessage::from_buffer_compressed(&resent, &mut receiver).unwrap(), reading(20.5));
+
+        let receipt = SendHandle::new();
+        sender.track(inserts, receipt.clone());
+        receipt.resolve(SendOutcome::Delivered);
+        let (compressed, _) = sender.encode(&reading(20.5));
+        assert_eq!(BiWiMessage::from_buffer_compressed(&compressed, &mut receiver).unwrap(), reading(20.5));
+        assert!(compressed.len() < resent.len());
+    }
+}
@@ -0,0 +1,293 @@
+//! BiWi Conformance Vectors
+//! Golden test vectors describing values, fields, messages and packets together
+//! with their exact wire bytes, plus malformed inputs and the error class they must
+//! produce. The vectors live in `conformance/vectors.json` so other implementations
+//! (JS, Go, ...) can run the same suite; `run_vectors` checks this crate against them.
+//!
+//! Vector format (one entry of `"vectors"`):
+//! - `target`: `value` | `field` | `message` | `sparse` | `packet` | `compact packet`
+//!   (the first datagram of a session that negotiated compact headers, which plain
+//!   `packet`s must refuse)
+//! - `mode`: `roundtrip` (encode must produce `hex`, decode must produce the value),
+//!   `decode` (decode `hex` only, for non-canonical encodings), or `error`
+//!   (decoding `hex` must fail with the named `error` class)
+//! - values are typed: `{"type": "int32", "value": 42}`, `{"type": "array", "items": [...]}`,
+//!   `{"type": "object", "entries": {...}}`, `{"type": "binary", "hex": "0102"}`;
+//!   int64 values may be given as strings to stay exact in JavaScript
+
+use crate::decoder::{BiWiDecoder, DecodeError};
+use crate::encoder::{BiWiEncoder, BiWiValue, SmallString};
+use crate::message::BiWiMessage;
+use crate::network::{PacketManager, PacketType, UdpPacket};
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
+
+/// The vectors shipped with this crate
+pub const VECTORS: &str = include_str!("../conformance/vectors.json");
+
+/// A vector that did not behave as specified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorFailure {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Run every vector in `json`, returning the number that passed or all failures
+pub fn run_vectors(json: &str) -> Result<usize, Vec<VectorFailure>> {
+    let root: Json = serde_json::from_str(json).map_err(|e| {
+        vec![VectorFailure {
+            name: "<file>".to_string(),
+            reason: format!("invalid JSON: {}", e),
+        }]
+    })?;
+    let vectors = root["vectors"].as_array().cloned().unwrap_or_default();
+
+    let mut failures = Vec::new();
+    for vector in &vectors {
+        let name = vector["name"].as_str().unwrap_or("<unnamed>").to_string();
+        if let Err(reason) = run_vector(vector) {
+            failures.push(VectorFailure { name, reason });
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(vectors.len())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Name of the error class a `DecodeError` belongs to
+pub fn error_class(error: &DecodeError) -> &'static str {
+    match error {
+        DecodeError::InsufficientData(_) => "InsufficientData",
+        DecodeError::UnknownType(_) => "UnknownType",
+        DecodeError::InvalidData(_) => "InvalidData",
+        DecodeError::LimitExceeded(_) => "LimitExceeded",
+        DecodeError::AuthenticationFailed => "AuthenticationFailed",
+    }
+}
+
+fn run_vector(vector: &Json) -> Result<(), String> {
+    let bytes = from_hex(vector["hex"].as_str().ok_or("missing hex")?)?;
+    let mode = vector["mode"].as_str().unwrap_or("roundtrip");
+    let target = vector["target"].as_str().ok_or("missing target")?;
+
+    if mode == "error" {
+        let expected = vector["error"].as_str().ok_or("missing error")?;
+        return match decode_target(target, &bytes) {
+            Ok(_) => Err(format!("expected {} but decoding succeeded", expected)),
+            Err(actual) if actual == expected => Ok(()),
+            Err(actual) => Err(format!("expected {} but got {}", expected, actual)),
+        };
+    }
+
+    let expected = expected_target(target, vector)?;
+    let decoded = decode_target(target, &bytes).map_err(|e| format!("decode failed: {}", e))?;
+    if decoded != expected {
+        return Err(format!("decoded {:?}, expected {:?}", decoded, expected));
+    }
+
+    if mode == "roundtrip" {
+        let encoded = encode_target(target, vector, &expected)?;
+        if encoded != bytes {
+            return Err(format!("encoded {}, expected {}", to_hex(&encoded), to_hex(&bytes)));
+        }
+    }
+    Ok(())
+}
+
+/// Decoded form of any target, compared structurally
+#[derive(Debug, PartialEq)]
+enum Decoded {
+    Value(BiWiValue),
+    Field(u32, BiWiValue),
+    Message(BTreeMap<u32, BiWiValue>),
+    Packet(PacketType, u32, u32, u32, Vec<u8>),
+}
+
+fn decode_target(target: &str, bytes: &[u8]) -> Result<Decoded, String> {
+    let class = |e: DecodeError| error_class(&e).to_string();
+    match target {
+        "value" => {
+            let mut decoder = BiWiDecoder::new(bytes);
+            let value = decoder.decode_value().map_err(class)?;
+            if decoder.has_more() {
+                return Err("TrailingData".to_string());
+            }
+            Ok(Decoded::Value(value))
+        }
+        "field" => {
+            let mut decoder = BiWiDecoder::new(bytes);
+            let field = decoder.decode_field().map_err(class)?;
+            if decoder.has_more() {
+                return Err("TrailingData".to_string());
+            }
+            Ok(Decoded::Field(field.field_id, field.value))
+        }
+        "message" => BiWiMessage::from_buffer(bytes)
+            .map(|m| Decoded::Message(m.fields().clone()))
+            .map_err(class),
+        "sparse" => BiWiMessage::from_buffer_sparse(bytes)
+            .map(|m| Decoded::Message(m.fields().clone()))
+            .map_err(class),
+        "packet" => UdpPacket::from_bytes(bytes)
+            .map(|p| Decoded::Packet(p.packet_type, p.sequence, p.ack_number, p.flags, p.payload))
+            .map_err(|_| "InvalidPacket".to_string()),
+        "compact packet" => {
+            let mut pm = PacketManager::new();
+            pm.set_compact_headers(true);
+            pm.decode(bytes)
+                .map(|p| Decoded::Packet(p.packet_type, p.sequence, p.ack_number, p.flags, p.payload))
+                .map_err(|_| "InvalidPacket".to_string())
+        }
+        other => Err(format!("unknown target {}", other)),
+    }
+}
+
+fn expected_target(target: &str, vector: &Json) -> Result<Decoded, String> {
+    match target {
+        "value" => Ok(Decoded::Value(parse_value(&vector["value"])?)),
+        "field" => {
+            let id = vector["field_id"].as_u64().ok_or("missing field_id")? as u32;
+            Ok(Decoded::Field(id, parse_value(&vector["value"])?))
+        }
+        "message" | "sparse" => Ok(Decoded::Message(parse_fields(&vector["fields"])?)),
+        "packet" | "compact packet" => {
+            let packet = parse_packet(&vector["packet"])?;
+            Ok(Decoded::Packet(packet.packet_type, packet.sequence, packet.ack_number, packet.flags, packet.payload))
+        }
+        other => Err(format!("unknown target {}", other)),
+    }
+}
+
+fn encode_target(target: &str, vector: &Json, expected: &Decoded) -> Result<Vec<u8>, String> {
+    let mut encoder = BiWiEncoder::new();
+    match (target, expected) {
+        ("value", Decoded::Value(value)) => encoder.encode_value(value),
+        ("field", Decoded::Field(id, value)) => encoder.encode_field(*id, value),
+        ("message", Decoded::Message(fields)) => {
+            for (id, value) in fields {
+                encoder.encode_field(*id, value);
+            }
+        }
+        ("sparse", Decoded::Message(fields)) => {
+            let fields: Vec<(u32, &BiWiValue)> = fields.iter().map(|(id, v)| (*id, v)).collect();
+            encoder.encode_sparse(&fields);
+        }
+        ("packet", _) => return Ok(parse_packet(&vector["packet"])?.to_bytes()),
+        _ => return Err("target/value mismatch".to_string()),
+    }
+    Ok(encoder.to_buffer())
+}
+
+fn parse_fields(fields: &Json) -> Result<BTreeMap<u32, BiWiValue>, String> {
+    let mut out = BTreeMap::new();
+    for field in fields.as_array().ok_or("fields must be an array")? {
+        let id = field["id"].as_u64().ok_or("field missing id")? as u32;
+        out.insert(id, parse_value(&field["value"])?);
+    }
+    Ok(out)
+}
+
+fn parse_packet(packet: &Json) -> Result<UdpPacket, String> {
+    let packet_type = match packet["type"].as_str() {
+        Some("data") => PacketType::Data,
+        Some("ack") => PacketType::Ack,
+        Some("ping") => PacketType::Ping,
+        Some("pong") => PacketType::Pong,
+        _ => return Err("unknown packet type".to_string()),
+    };
+    let number = |key: &str| packet[key].as_u64().map(|n| n as u32).ok_or(format!("packet missing {}", key));
+    Ok(UdpPacket {
+        packet_type,
+        sequence: number("sequence")?,
+        ack_number: number("ack")?,
+        flags: number("flags")?,
+        payload: from_hex(packet["payload"].as_str().unwrap_or(""))?,
+    })
+}
+
+fn parse_value(spec: &Json) -> Result<BiWiValue, String> {
+    let ty = spec["type"].as_str().ok_or("value missing type")?;
+    let v = &spec["value"];
+    let value = match ty {
+        "null" => BiWiValue::Null,
+        "bool" => BiWiValue::Boolean(v.as_bool().ok_or("bool value")?),
+        "int32" => BiWiValue::Int32(v.as_i64().and_then(|n| i32::try_from(n).ok()).ok_or("int32 value")?),
+        "int64" => BiWiValue::Int64(match v {
+            Json::String(s) => s.parse().map_err(|_| "int64 value")?,
+            _ => v.as_i64().ok_or("int64 value")?,
+        }),
+        "float32" => BiWiValue::Float32(v.as_f64().ok_or("float32 value")? as f32),
+        "float64" => BiWiValue::Float64(v.as_f64().ok_or("float64 value")?),
+        "small_string" => {
+            let s = v.as_str().ok_or("small_string value")?;
+            BiWiValue::SmallString(SmallString::new(s).ok_or("small_string longer than 15 bytes")?)
+        }
+        "string" => BiWiValue::String(v.as_str().ok_or("string value")?.to_string()),
+        "binary" => BiWiValue::Binary(from_hex(spec["hex"].as_str().ok_or("binary hex")?)?),
+        "array" => BiWiValue::Array(
+            spec["items"]
+                .as_array()
+                .ok_or("array items")?
+                .iter()
+                .map(parse_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        "object" => BiWiValue::Object(
+            spec["entries"]
+                .as_object()
+                .ok_or("object entries")?
+                .iter()
+                .map(|(k, v)| parse_value(v).map(|v| (k.clone(), v)))
+                .collect::<Result<_, _>>()?,
+        ),
+        other => return Err(format!("unknown value type {}", other)),
+    };
+    Ok(value)
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd-length hex".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let s = std::str::from_utf8(pair).map_err(|_| "invalid hex")?;
+            u8::from_str_radix(s, 16).map_err(|_| "invalid hex".to_string())
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_vectors() {
+        match run_vectors(VECTORS) {
+            Ok(count) => assert!(count > 0),
+            Err(failures) => panic!("conformance failures: {:#?}", failures),
+        }
+    }
+
+    #[test]
+    fn test_runner_reports_mismatches() {
+        let json = r#"{"vectors": [
+            {"name": "wrong bytes", "target": "value", "mode": "roundtrip",
+             "value": {"type": "int32", "value": 1}, "hex": "0204"},
+            {"name": "wrong error", "target": "value", "mode": "error",
+             "hex": "0d", "error": "InsufficientData"}
+        ]}"#;
+        let failures = run_vectors(json).unwrap_err();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[1].reason, "expected InsufficientData but got UnknownType");
+    }
+}
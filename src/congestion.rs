@@ -0,0 +1,131 @@
+//! BiWi Congestion Control
+//! Pluggable send-rate limiting for `PacketManager`. A controller decides when the
+//! next packet may leave and adapts its rate to ACKs and losses; packets it holds
+//! back wait in the manager's send queue instead of hitting the socket in one burst.
+
+use crate::network::MAX_PACKET_SIZE;
+use std::time::{Duration, Instant};
+
+/// Decides how fast a connection may send
+pub trait CongestionController: Send {
+    /// Take `bytes` of send budget if available; false means hold the packet for now
+    fn try_send(&mut self, bytes: usize) -> bool;
+
+    /// `bytes` were confirmed by an ACK
+    fn on_ack(&mut self, bytes: usize);
+
+    /// A packet timed out and is being retransmitted
+    fn on_loss(&mut self);
+
+    /// Current send rate in bytes per second
+    fn rate(&self) -> u64;
+}
+
+/// Token bucket whose fill rate follows AIMD: it grows by `increase` bytes/s for
+/// every second's worth of ACKed data and halves on loss
+pub struct TokenBucketAimd {
+    rate: f64,
+    min_rate: f64,
+    max_rate: f64,
+    increase: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    last_decrease: Option<Instant>,
+}
+
+impl TokenBucketAimd {
+    /// Losses closer together than this count as one congestion event
+    pub const DECREASE_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Start at `rate` bytes/s, never leaving `min_rate..=max_rate`
+    pub fn new(rate: u64, min_rate: u64, max_rate: u64) -> Self {
+        let min_rate = min_rate.max(1) as f64;
+        let max_rate = (max_rate as f64).max(min_rate);
+        let rate = (rate as f64).clamp(min_rate, max_rate);
+        Self {
+            rate,
+            min_rate,
+            max_rate,
+            increase: MAX_PACKET_SIZE as f64,
+            burst: MAX_PACKET_SIZE as f64 * 4.0,
+            tokens: MAX_PACKET_SIZE as f64 * 4.0,
+            last_refill: Instant::now(),
+            last_decrease: None,
+        }
+    }
+
+    /// Bytes that may go out back to back (at least one full packet)
+    pub fn with_burst(mut self, burst: usize) -> Self {
+        self.burst = burst.max(MAX_PACKET_SIZE) as f64;
+        self.tokens = self.tokens.min(self.burst);
+        self
+    }
+
+    /// Additive increase step, in bytes/s
+    pub fn with_increase(mut self, increase: u64) -> Self {
+        self.increase = increase as f64;
+        self
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + self.rate * elapsed).min(self.burst);
+        self.last_refill = now;
+    }
+}
+
+impl CongestionController for TokenBucketAimd {
+    fn try_send(&mut self, bytes: usize) -> bool {
+        self.refill();
+        let bytes = bytes as f64;
+        if self.tokens < bytes.min(self.burst) {
+            return false;
+        }
+        self.tokens -= bytes;
+        true
+    }
+
+    fn on_ack(&mut self, bytes: usize) {
+        self.rate = (self.rate + self.increase * bytes as f64 / self.rate).min(self.max_rate);
+    }
+
+    fn on_loss(&mut self) {
+        if self.last_decrease.is_some_and(|at| at.elapsed() < Self::DECREASE_INTERVAL) {
+            return;
+        }
+        self.rate = (self.rate / 2.0).max(self.min_rate);
+        self.last_decrease = Some(Instant::now());
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_limits_burst() {
+        let mut cc = TokenBucketAimd::new(1_000, 100, 10_000).with_burst(MAX_PACKET_SIZE * 2);
+        assert!(cc.try_send(MAX_PACKET_SIZE));
+        assert!(cc.try_send(MAX_PACKET_SIZE));
+        assert!(!cc.try_send(MAX_PACKET_SIZE));
+    }
+
+    #[test]
+    fn test_aimd() {
+        let mut cc = TokenBucketAimd::new(8_000, 1_000, 16_000).with_increase(1_000);
+        cc.on_loss();
+        assert_eq!(cc.rate(), 4_000);
+        // A second loss in the same burst is the same congestion event
+        cc.on_loss();
+        assert_eq!(cc.rate(), 4_000);
+
+        cc.on_ack(4_000);
+        assert_eq!(cc.rate(), 5_000);
+    }
+}
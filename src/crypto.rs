@@ -0,0 +1,304 @@
+//! BiWi Datagram Encryption
+//! Optional XChaCha20-Poly1305 sealing of UDP packet payloads. Both ends derive a
+//! per-session key from a pre-shared key and the random values exchanged in the
+//! handshake, one key per direction. Headers stay readable so packets can be routed,
+//! but they are authenticated as associated data, so a forged or tampered packet
+//! fails to open.
+//!
+//! Every sealed packet carries a per-direction packet number. The nonce is built
+//! from it and the packet's sequence number, and the receiver remembers the packet
+//! numbers it has opened, so a captured datagram replayed later is rejected.
+//!
+//! Sealed payload layout: [session tag: 8 bytes, if any][packet number: u64][ciphertext][tag: 16 bytes]
+
+use crate::network::{PacketType, UdpPacket, FLAG_SESSION, PACKET_HEADER_SIZE, SESSION_TAG_LEN};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const PACKET_NUMBER_LEN: usize = 8;
+pub const AEAD_TAG_LEN: usize = 16;
+
+/// Bytes sealing adds to every packet
+pub const CIPHER_OVERHEAD: usize = PACKET_NUMBER_LEN + AEAD_TAG_LEN;
+
+/// Packet numbers this far behind the newest one opened are rejected as replays
+pub const REPLAY_WINDOW: u64 = 1024;
+
+/// Which end of the session a cipher belongs to; each direction has its own key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Length of the random value each side contributes to the handshake
+pub const HANDSHAKE_RANDOM_LEN: usize = 32;
+
+/// Length of the server's proof that it knows the pre-shared key
+pub const CONFIRMATION_LEN: usize = 32;
+
+/// Fresh random value for the handshake
+pub fn handshake_random() -> [u8; HANDSHAKE_RANDOM_LEN] {
+    let mut random = [0u8; HANDSHAKE_RANDOM_LEN];
+    OsRng.fill_bytes(&mut random);
+    random
+}
+
+fn handshake_mac(psk: &[u8], label: &[u8], session_id: u64, client_random: &[u8], server_random: &[u8]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(psk).expect("HMAC accepts any key length");
+    mac.update(label);
+    mac.update(&session_id.to_be_bytes());
+    mac.update(client_random);
+    mac.update(server_random);
+    mac
+}
+
+/// The server's proof, sent in the `ConnectAck`, that it holds the same pre-shared key
+pub fn confirmation(psk: &[u8], session_id: u64, client_random: &[u8], server_random: &[u8]) -> [u8; CONFIRMATION_LEN] {
+    handshake_mac(psk, b"biwi confirm", session_id, client_random, server_random)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Check a `ConnectAck` confirmation (constant-time)
+pub fn verify_confirmation(
+    psk: &[u8],
+    session_id: u64,
+    client_random: &[u8],
+    server_random: &[u8],
+    confirmation: &[u8],
+) -> bool {
+    handshake_mac(psk, b"biwi confirm", session_id, client_random, server_random)
+        .verify_slice(confirmation)
+        .is_ok()
+}
+
+/// Seals and opens the packets of one session
+pub struct PacketCipher {
+    sealing: XChaCha20Poly1305,
+    opening: XChaCha20Poly1305,
+    /// Packet number of the next packet sealed
+    next_packet: AtomicU64,
+    /// Packet numbers already opened
+    received: ReplayWindow,
+}
+
+impl PacketCipher {
+    /// Cipher that seals with `sealing_key` and opens with `opening_key`
+    pub fn new(sealing_key: [u8; 32], opening_key: [u8; 32]) -> Self {
+        Self {
+            sealing: XChaCha20Poly1305::new(&sealing_key.into()),
+            opening: XChaCha20Poly1305::new(&opening_key.into()),
+            next_packet: AtomicU64::new(0),
+            received: ReplayWindow::default(),
+        }
+    }
+
+    /// The session cipher for `role`, derived from the pre-shared key and the handshake randoms
+    pub fn derive(psk: &[u8], session_id: u64, client_random: &[u8], server_random: &[u8], role: Role) -> Self {
+        let key = |label: &[u8]| -> [u8; 32] {
+            handshake_mac(psk, label, session_id, client_random, server_random)
+                .finalize()
+                .into_bytes()
+                .into()
+        };
+        let (client_key, server_key) = (key(b"biwi client key"), key(b"biwi server key"));
+        match role {
+            Role::Client => Self::new(client_key, server_key),
+            Role::Server => Self::new(server_key, client_key),
+        }
+    }
+
+    /// Serialize `packet` with its payload encrypted (the session tag stays readable)
+    pub fn seal(&self, packet: &UdpPacket) -> Vec<u8> {
+        let prefix = clear_prefix(packet);
+        let bytes = packet.to_bytes();
+        let (aad, plaintext) = bytes.split_at(PACKET_HEADER_SIZE + prefix);
+
+        let packet_number = self.next_packet.fetch_add(1, Ordering::Relaxed);
+        let ciphertext = self
+            .sealing
+            .encrypt(&nonce(packet.sequence, packet_number), Payload { msg: plaintext, aad })
+            .expect("XChaCha20-Poly1305 encrypts any message length");
+
+        let mut out = Vec::with_capacity(aad.len() + PACKET_NUMBER_LEN + ciphertext.len());
+        out.extend_from_slice(aad);
+        out.extend_from_slice(&packet_number.to_be_bytes());
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    /// Decrypt a packet's payload in place; false if it was forged, tampered with,
+    /// sealed under another key or already opened once (a replay), in which case the
+    /// packet is left unchanged
+    pub fn open(&mut self, packet: &mut UdpPacket) -> bool {
+        let prefix = clear_prefix(packet);
+        let Some(sealed) = packet.payload.get(prefix..).filter(|sealed| sealed.len() >= CIPHER_OVERHEAD) else {
+            return false;
+        };
+        let (packet_number, ciphertext) = sealed.split_at(PACKET_NUMBER_LEN);
+        let packet_number = u64::from_be_bytes(packet_number.try_into().expect("split at PACKET_NUMBER_LEN"));
+        if !self.received.is_fresh(packet_number) {
+            return false;
+        }
+
+        let mut aad = packet.to_bytes();
+        aad.truncate(PACKET_HEADER_SIZE + prefix);
+        let nonce = nonce(packet.sequence, packet_number);
+        let Ok(plaintext) = self.opening.decrypt(&nonce, Payload { msg: ciphertext, aad: &aad }) else {
+            return false;
+        };
+
+        // Only authentic packets move the window, so forgeries can't push real ones out of it
+        self.received.insert(packet_number);
+        packet.payload.truncate(prefix);
+        packet.payload.extend_from_slice(&plaintext);
+        true
+    }
+}
+
+/// Nonce for a packet: its sequence number and packet number, zero-padded. Packet
+/// numbers never repeat under one key, so neither does the nonce.
+fn nonce(sequence: u32, packet_number: u64) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[12..16].copy_from_slice(&sequence.to_be_bytes());
+    nonce[16..].copy_from_slice(&packet_number.to_be_bytes());
+    nonce
+}
+
+/// Packet numbers opened recently, as a bitmap of the `REPLAY_WINDOW` up to the newest
+struct ReplayWindow {
+    highest: Option<u64>,
+    bits: [u64; (REPLAY_WINDOW / 64) as usize],
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self {
+            highest: None,
+            bits: [0; (REPLAY_WINDOW / 64) as usize],
+        }
+    }
+}
+
+impl ReplayWindow {
+    fn slot(packet_number: u64) -> (usize, u64) {
+        let index = packet_number % REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// False if `packet_number` was already opened or is too old to tell
+    fn is_fresh(&self, packet_number: u64) -> bool {
+        let Some(highest) = self.highest else {
+            return true;
+        };
+        if packet_number > highest {
+            return true;
+        }
+        let (word, bit) = Self::slot(packet_number);
+        highest - packet_number < REPLAY_WINDOW && self.bits[word] & bit == 0
+    }
+
+    fn insert(&mut self, packet_number: u64) {
+        match self.highest {
+            Some(highest) if packet_number <= highest => {}
+            Some(highest) if packet_number - highest < REPLAY_WINDOW => {
+                // Slots being reused for newer packet numbers forget what they held
+                for skipped in highest + 1..packet_number {
+                    let (word, bit) = Self::slot(skipped);
+                    self.bits[word] &= !bit;
+                }
+                self.highest = Some(packet_number);
+            }
+            _ => {
+                self.bits = [0; (REPLAY_WINDOW / 64) as usize];
+                self.highest = Some(packet_number);
+            }
+        }
+        let (word, bit) = Self::slot(packet_number);
+        self.bits[word] |= bit;
+    }
+}
+
+/// Leading payload bytes that stay in the clear: the session tag (or the session ID
+/// of a `Disconnect`), which the receiver needs to pick the key
+fn clear_prefix(packet: &UdpPacket) -> usize {
+    if packet.flags & FLAG_SESSION != 0 || packet.packet_type == PacketType::Disconnect {
+        SESSION_TAG_LEN.min(packet.payload.len())
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::PacketManager;
+
+    fn session_ciphers(psk: &[u8]) -> (PacketCipher, PacketCipher) {
+        let (client, server) = (handshake_random(), handshake_random());
+        (
+            PacketCipher::derive(psk, 7, &client, &server, Role::Client),
+            PacketCipher::derive(psk, 7, &client, &server, Role::Server),
+        )
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let (client, mut server) = session_ciphers(b"secret");
+
+        let mut pm = PacketManager::new();
+        pm.set_session(7);
+        let packet = pm.create_packets(b"hello").remove(0);
+        let sealed = client.seal(&packet);
+        assert_eq!(sealed.len(), packet.to_bytes().len() + CIPHER_OVERHEAD);
+        assert!(!sealed.windows(5).any(|w| w == b"hello"));
+
+        // A tampered header, a different key or the sender's own key fails to open
+        let mut tampered = UdpPacket::from_bytes(&sealed).unwrap();
+        tampered.sequence ^= 1;
+        assert!(!server.open(&mut tampered));
+        let (_, mut other) = session_ciphers(b"other");
+        assert!(!other.open(&mut UdpPacket::from_bytes(&sealed).unwrap()));
+        let (mut sender, _) = session_ciphers(b"secret");
+        assert!(!sender.open(&mut UdpPacket::from_bytes(&sealed).unwrap()));
+
+        let mut received = UdpPacket::from_bytes(&sealed).unwrap();
+        assert!(server.open(&mut received));
+        assert_eq!(received.take_session(), Some(7));
+        assert_eq!(received.payload, b"hello");
+    }
+
+    #[test]
+    fn test_replayed_packets_are_rejected() {
+        let (client, mut server) = session_ciphers(b"secret");
+        let packet = PacketManager::new().create_packets(b"fire").remove(0);
+        let sealed: Vec<Vec<u8>> = (0..REPLAY_WINDOW + 3).map(|_| client.seal(&packet)).collect();
+
+        // Out of order is fine, twice is not
+        assert!(server.open(&mut UdpPacket::from_bytes(&sealed[1]).unwrap()));
+        assert!(server.open(&mut UdpPacket::from_bytes(&sealed[0]).unwrap()));
+        assert!(!server.open(&mut UdpPacket::from_bytes(&sealed[1]).unwrap()));
+
+        // Once the window moves on, old packet numbers can't be told apart from replays
+        assert!(server.open(&mut UdpPacket::from_bytes(&sealed[REPLAY_WINDOW as usize + 2]).unwrap()));
+        assert!(!server.open(&mut UdpPacket::from_bytes(&sealed[2]).unwrap()));
+        assert!(server.open(&mut UdpPacket::from_bytes(&sealed[3]).unwrap()));
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let (client, server) = (handshake_random(), handshake_random());
+        let proof = confirmation(b"secret", 7, &client, &server);
+        assert!(verify_confirmation(b"secret", 7, &client, &server, &proof));
+        assert!(!verify_confirmation(b"other", 7, &client, &server, &proof));
+        assert!(!verify_confirmation(b"secret", 8, &client, &server, &proof));
+    }
+}
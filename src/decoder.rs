@@ -1,8 +1,16 @@
 // BiWi Binary Decoder
-// Decodes BiWi binary format into Rust values
-
-use crate::encoder::BiWiValue;
-use crate::types::BiWiType;
+// Decodes BiWi binary format into Rust values.
+// Decoding never panics: all reads go through the checked `Reader`, and
+// untrusted counts are bounded by `DecodeLimits` and the remaining input.
+
+#![deny(clippy::indexing_slicing)]
+
+use crate::encoder::{BiWiValue, SPARSE_FLAG_FIELDS, SPARSE_FLAG_NULLS};
+use crate::envelope::{Envelope, ENVELOPE_HAS_TIMESTAMP, ENVELOPE_MARKER};
+use crate::compression::{FieldTable, MAX_ENTRY_LEN};
+use crate::intern::{KeyTable, KEY_NEW, KEY_REF_BASE};
+use crate::reader::Reader;
+use crate::types::{BiWiType, ByteOrder};
 use std::collections::HashMap;
 
 /// Errors that can occur during decoding
@@ -11,6 +19,10 @@ pub enum DecodeError {
     InsufficientData(&'static str),
     UnknownType(u8),
     InvalidData(&'static str),
+    /// A configured `DecodeLimits` bound was exceeded
+    LimitExceeded(&'static str),
+    /// A signed frame's authentication tag did not match
+    AuthenticationFailed,
 }
 
 impl std::fmt::Display for DecodeError {
@@ -19,6 +31,8 @@ impl std::fmt::Display for DecodeError {
             DecodeError::InsufficientData(msg) => write!(f, "Insufficient data: {}", msg),
             DecodeError::UnknownType(code) => write!(f, "Unknown type code: 0x{:02x}", code),
             DecodeError::InvalidData(msg) => write!(f, "Invalid data: {}", msg),
+            DecodeError::LimitExceeded(msg) => write!(f, "Limit exceeded: {}", msg),
+            DecodeError::AuthenticationFailed => write!(f, "Authentication failed"),
         }
     }
 }
@@ -27,6 +41,27 @@ impl std::error::Error for DecodeError {}
 
 pub type DecodeResult<T> = Result<T, DecodeError>;
 
+/// Resource limits applied while decoding untrusted input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum nesting of arrays/objects
+    pub max_depth: usize,
+    /// Maximum element count of a single array or object
+    pub max_collection_len: usize,
+    /// Maximum byte length of a single string, binary or key
+    pub max_bytes_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 64,
+            max_collection_len: 1 << 20,
+            max_bytes_len: 64 << 20,
+        }
+    }
+}
+
 /// Represents a decoded field with its ID and value
 #[derive(Debug, Clone, PartialEq)]
 pub struct DecodedField {
@@ -38,146 +73,400 @@ pub struct DecodedField {
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChunkStart {
     pub field_id: u16,
-    pub total_size: u32,
+    pub total_size: u64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChunkData {
     pub chunk_index: u16,
     pub data: Vec<u8>,
+    /// CRC-32 of `data`, if the sender added one (CHUNK_DATA_CHECKED)
+    pub crc: Option<u32>,
+}
+
+impl ChunkData {
+    /// Whether `data` matches its CRC. Frames without one always pass.
+    pub fn crc_matches(&self) -> bool {
+        self.crc.is_none_or(|crc| crc32fast::hash(&self.data) == crc)
+    }
+}
+
+/// Start of a resumable chunk transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkTransferStart {
+    pub transfer_id: u32,
+    pub field_id: u16,
+    pub total_size: u64,
+}
+
+/// Request to continue a transfer from `next_index`. Unlike the 16-bit index of
+/// a data frame, which wraps, this counts every chunk since the transfer started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkResume {
+    pub transfer_id: u32,
+    pub next_index: u32,
+}
+
+impl ChunkResume {
+    /// Byte offset the sender must continue from, given its chunk size
+    pub fn byte_offset(&self, chunk_size: usize) -> u64 {
+        u64::from(self.next_index) * chunk_size as u64
+    }
+}
+
+/// A single frame of a chunked stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkFrame {
+    Start(ChunkStart),
+    TransferStart(ChunkTransferStart),
+    Data(ChunkData),
+    /// End of the stream, with a SHA-256 of the whole payload if the sender added one
+    End(Option<[u8; 32]>),
+    Resume(ChunkResume),
 }
 
 /// BiWi decoder for converting binary format to values
 pub struct BiWiDecoder<'a> {
-    buffer: &'a [u8],
-    offset: usize,
+    reader: Reader<'a>,
+    limits: DecodeLimits,
+    depth: usize,
+    byte_order: ByteOrder,
+    typed_arrays: bool,
+    /// The input as a shared buffer, when Binary values may borrow from it
+    #[cfg(feature = "bytes")]
+    shared: Option<bytes::Bytes>,
+    /// Keys interned by earlier messages of the stream
+    keys: Option<&'a mut KeyTable>,
+    /// Field values inserted by earlier messages of the session
+    fields: Option<&'a mut FieldTable>,
 }
 
 impl<'a> BiWiDecoder<'a> {
     /// Create a new decoder from a byte slice
     pub fn new(buffer: &'a [u8]) -> Self {
-        Self { buffer, offset: 0 }
+        Self::with_limits(buffer, DecodeLimits::default())
     }
 
-    /// Decode varint (variable-length integer) optimized for common cases
-    fn read_varint(&mut self) -> DecodeResult<u32> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("varint"));
-        }
-
-        let mut byte = self.buffer[self.offset];
-        self.offset += 1;
-
-        // Fast path for single byte (0-127)
-        if (byte & 0x80) == 0 {
-            return Ok(byte as u32);
+    /// Create a new decoder with custom resource limits
+    pub fn with_limits(buffer: &'a [u8], limits: DecodeLimits) -> Self {
+        Self {
+            reader: Reader::new(buffer),
+            limits,
+            depth: 0,
+            byte_order: ByteOrder::BigEndian,
+            typed_arrays: false,
+            #[cfg(feature = "bytes")]
+            shared: None,
+            keys: None,
+            fields: None,
         }
+    }
 
-        let mut value = (byte & 0x7f) as u32;
-        let mut shift = 7;
-
-        // Read remaining bytes
-        while self.offset < self.buffer.len() {
-            byte = self.buffer[self.offset];
-            self.offset += 1;
+    /// Create a decoder over a shared buffer. Binary values decode to
+    /// `BiWiValue::Bytes` slices of `buffer` instead of being copied out.
+    #[cfg(feature = "bytes")]
+    pub fn new_shared(buffer: &'a bytes::Bytes) -> Self {
+        let mut decoder = Self::new(buffer);
+        decoder.shared = Some(buffer.clone());
+        decoder
+    }
 
-            value |= ((byte & 0x7f) as u32) << shift;
+    /// Set the byte order for fixed-width values (must match the encoder)
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
 
-            if (byte & 0x80) == 0 {
-                return Ok(value);
-            }
+    /// Decode packed arrays into `Int32Array`, `Float32Array`, ... rather than an
+    /// `Array` holding one `BiWiValue` per element
+    pub fn with_typed_arrays(mut self) -> Self {
+        self.typed_arrays = true;
+        self
+    }
 
-            shift += 7;
-        }
+    /// Resolve interned object keys through `keys`, which must have seen every
+    /// earlier message the encoder wrote with its table
+    pub fn with_key_table(mut self, keys: &'a mut KeyTable) -> Self {
+        self.keys = Some(keys);
+        self
+    }
 
-        Err(DecodeError::InsufficientData("varint continuation"))
+    /// Resolve FIELD_REF values, and record FIELD_INSERT ones, in the session's
+    /// field table (see `compression`)
+    pub fn with_field_table(mut self, fields: &'a mut FieldTable) -> Self {
+        self.fields = Some(fields);
+        self
     }
 
-    /// Decode a 64-bit varint
-    fn read_varint_u64(&mut self) -> DecodeResult<u64> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("varint64"));
-        }
+    fn read_f32(&mut self, what: &'static str) -> DecodeResult<f32> {
+        let bytes = self.reader.read_array(what)?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => f32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => f32::from_le_bytes(bytes),
+        })
+    }
 
-        let mut byte = self.buffer[self.offset];
-        self.offset += 1;
+    fn read_f64(&mut self, what: &'static str) -> DecodeResult<f64> {
+        let bytes = self.reader.read_array(what)?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => f64::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => f64::from_le_bytes(bytes),
+        })
+    }
 
-        // Fast path for single byte (0-127)
-        if (byte & 0x80) == 0 {
-            return Ok(byte as u64);
-        }
+    fn read_u16(&mut self, what: &'static str) -> DecodeResult<u16> {
+        let bytes = self.reader.read_array(what)?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+        })
+    }
 
-        let mut value = (byte & 0x7f) as u64;
-        let mut shift = 7;
+    fn read_u32(&mut self, what: &'static str) -> DecodeResult<u32> {
+        let bytes = self.reader.read_array(what)?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+        })
+    }
 
-        // Read remaining bytes
-        while self.offset < self.buffer.len() {
-            byte = self.buffer[self.offset];
-            self.offset += 1;
+    fn read_u64(&mut self, what: &'static str) -> DecodeResult<u64> {
+        let bytes = self.reader.read_array(what)?;
+        Ok(match self.byte_order {
+            ByteOrder::BigEndian => u64::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u64::from_le_bytes(bytes),
+        })
+    }
 
-            value |= ((byte & 0x7f) as u64) << shift;
+    /// Decode varint (variable-length integer)
+    fn read_varint(&mut self) -> DecodeResult<u32> {
+        self.reader.read_varint_u32("varint")
+    }
 
-            if (byte & 0x80) == 0 {
-                return Ok(value);
-            }
+    /// Decode a 64-bit varint
+    fn read_varint_u64(&mut self) -> DecodeResult<u64> {
+        self.reader.read_varint_u64("varint64")
+    }
 
-            shift += 7;
+    /// Read a length prefix and check it against the byte-length limit
+    fn read_length(&mut self, what: &'static str) -> DecodeResult<usize> {
+        let length = self.read_varint()? as usize;
+        if length > self.limits.max_bytes_len {
+            return Err(DecodeError::LimitExceeded(what));
         }
+        Ok(length)
+    }
 
-        Err(DecodeError::InsufficientData("varint64 continuation"))
+    /// Read an element count and check it against the collection limit.
+    /// Each element needs at least `min_element_size` bytes, so counts that
+    /// cannot possibly fit in the remaining input are rejected up front.
+    fn read_count(&mut self, min_element_size: usize, what: &'static str) -> DecodeResult<usize> {
+        let count = self.read_varint()? as usize;
+        if count > self.limits.max_collection_len {
+            return Err(DecodeError::LimitExceeded(what));
+        }
+        if count.saturating_mul(min_element_size) > self.reader.remaining() {
+            return Err(DecodeError::InsufficientData(what));
+        }
+        Ok(count)
     }
 
     /// ZigZag decode a u32 to i32
     fn zigzag_decode_i32(value: u32) -> i32 {
-        ((value >> 1) as i32) ^ -(((value & 1) as i32))
+        ((value >> 1) as i32) ^ -((value & 1) as i32)
     }
 
     /// ZigZag decode a u64 to i64
     fn zigzag_decode_i64(value: u64) -> i64 {
-        ((value >> 1) as i64) ^ -(((value & 1) as i64))
+        ((value >> 1) as i64) ^ -((value & 1) as i64)
     }
 
     /// Decode a field with its header (handles compact and extended formats)
-    /// Compact format (fields 1-63): [field_id:6 + wire_type:2]
-    /// Extended format (fields 64+): [field_id(varint) + wire_type:3]
+    /// Compact format (fields 1-31): [0 + field_id:5 + wire_type:2]
+    /// Extended format (fields 32+): [field_id(varint) + wire_type:3]
     pub fn decode_field(&mut self) -> DecodeResult<DecodedField> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("field header"));
-        }
+        let field_id = self.read_field_id()?;
+        let value = self.decode_value()?;
+        Ok(DecodedField { field_id, value })
+    }
 
-        let header_byte = self.buffer[self.offset];
-        self.offset += 1;
+    /// Decode an envelope if the input starts with one; otherwise consume nothing
+    pub fn decode_envelope(&mut self) -> DecodeResult<Option<Envelope>> {
+        let mut probe = self.reader.clone();
+        if probe.read_array::<2>("envelope marker").ok() != Some(ENVELOPE_MARKER) {
+            return Ok(None);
+        }
+        self.reader = probe;
 
-        // Check if this is compact encoding (fields 1-63 use single byte)
-        let field_id = if header_byte < 0x80 {
-            // Compact format: extract field_id from top 6 bits
-            (header_byte >> 2) as u32
-        } else if header_byte >= 0xC0 {
-            // Extended format starts with continuation bytes, put byte back and read varint
-            self.offset -= 1;
-            (self.read_varint_u64()? >> 3) as u32
+        let presence = self.reader.read_u8("envelope presence")?;
+        if presence & !ENVELOPE_HAS_TIMESTAMP != 0 {
+            return Err(DecodeError::InvalidData("unknown envelope presence bits"));
+        }
+        let message_type = u16::try_from(self.read_varint()?)
+            .map_err(|_| DecodeError::InvalidData("envelope message type exceeds 16 bits"))?;
+        let correlation_id = self.read_varint_u64()?;
+        let flags = self.reader.read_u8("envelope flags")?;
+        let timestamp = if presence & ENVELOPE_HAS_TIMESTAMP != 0 {
+            Some(self.read_varint_u64()?)
         } else {
-            // Single extended byte field ID
-            (header_byte as u32) >> 3
+            None
         };
 
-        let value = self.decode_value()?;
-        Ok(DecodedField { field_id, value })
+        Ok(Some(Envelope {
+            message_type,
+            correlation_id,
+            flags,
+            timestamp,
+        }))
     }
 
-    /// Decode a value with its type
-    pub fn decode_value(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("type byte"));
+    /// Skip over a field without decoding its value, returning the field ID
+    pub fn skip_field(&mut self) -> DecodeResult<u32> {
+        let field_id = self.read_field_id()?;
+        self.skip_value()?;
+        Ok(field_id)
+    }
+
+    /// Read a field header, returning the field ID
+    pub(crate) fn read_field_id(&mut self) -> DecodeResult<u32> {
+        let header_byte = self.reader.read_u8("field header")?;
+
+        // Check if this is compact encoding (fields 1-31 use a single byte, high bit clear)
+        if header_byte < 0x80 {
+            // Compact format: extract field_id from bits 2-6
+            Ok((header_byte >> 2) as u32)
+        } else {
+            // Extended format starts with continuation bytes, put byte back and read varint
+            self.reader.unread_u8();
+            u32::try_from(self.read_varint_u64()? >> 3)
+                .map_err(|_| DecodeError::InvalidData("field ID exceeds 32 bits"))
+        }
+    }
+
+    /// Skip over a value without allocating. Structure and limits are checked,
+    /// string contents are not (UTF-8 is validated when the value is decoded).
+    pub fn skip_value(&mut self) -> DecodeResult<()> {
+        let type_code = self.reader.read_u8("type byte")?;
+        match type_code {
+            0x00 | 0x01 | 0xFF => {}
+            0x02 => {
+                self.read_varint()?;
+            }
+            0x03 => {
+                self.read_varint_u64()?;
+            }
+            0x04 => {
+                self.reader.read_bytes(4, "float32")?;
+            }
+            0x05 => {
+                self.reader.read_bytes(8, "float64")?;
+            }
+            0x06 | 0x07 => {
+                let length = self.read_length("string length")?;
+                self.reader.read_bytes(length, "string content")?;
+            }
+            0x86 => {
+                let length = self.reader.read_u8("small string length")? as usize;
+                if length > 15 {
+                    return Err(DecodeError::InvalidData("small string longer than 15 bytes"));
+                }
+                self.reader.read_bytes(length, "small string content")?;
+            }
+            0x08 | 0x09 | 0x89 => {
+                if self.depth >= self.limits.max_depth {
+                    return Err(DecodeError::LimitExceeded("nesting depth"));
+                }
+                self.depth += 1;
+                let result = match type_code {
+                    0x08 => self.skip_array(),
+                    0x09 => self.skip_object(),
+                    _ => self.skip_interned_object(),
+                };
+                self.depth -= 1;
+                result?;
+            }
+            0x88 => self.skip_packed_array()?,
+            0x11 => {
+                self.read_varint()?;
+            }
+            // The value is kept in the field table, so it has to be decoded
+            0x12 => {
+                self.decode_field_insert()?;
+            }
+            _ => return Err(DecodeError::UnknownType(type_code)),
+        }
+        Ok(())
+    }
+
+    fn skip_array(&mut self) -> DecodeResult<()> {
+        let count = self.read_count(1, "array elements")?;
+        for _ in 0..count {
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    fn skip_object(&mut self) -> DecodeResult<()> {
+        let count = self.read_count(2, "object entries")?;
+        for _ in 0..count {
+            let key_length = self.read_length("key length")?;
+            self.reader.read_bytes(key_length, "key content")?;
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    /// Skipped objects still add their new keys, so the table stays in step
+    fn skip_interned_object(&mut self) -> DecodeResult<()> {
+        let count = self.read_count(2, "object entries")?;
+        for _ in 0..count {
+            self.read_interned_key()?;
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    fn skip_packed_array(&mut self) -> DecodeResult<()> {
+        let element_type = self.reader.read_u8("packed array type")?;
+        let size = match element_type {
+            0x02 | 0x03 => None,
+            0x04 => Some(4),
+            0x05 => Some(8),
+            _ => return Err(DecodeError::InvalidData("unknown packed array element type")),
+        };
+        let count = self.read_count(size.unwrap_or(1), "packed array elements")?;
+        match size {
+            // read_count has already checked that count * size fits in the input
+            Some(size) => {
+                self.reader.read_bytes(count.saturating_mul(size), "packed array elements")?;
+            }
+            None => {
+                for _ in 0..count {
+                    if element_type == 0x02 {
+                        self.read_varint()?;
+                    } else {
+                        self.read_varint_u64()?;
+                    }
+                }
+            }
         }
+        Ok(())
+    }
 
-        let type_code = self.buffer[self.offset];
-        self.offset += 1;
+    /// Decode a value with its type
+    pub fn decode_value(&mut self) -> DecodeResult<BiWiValue> {
+        let type_code = self.reader.read_u8("type byte")?;
 
-        // Check for packed array marker (high bit set)
+        // Check for packed array / small string markers (high bit set)
         if type_code == (BiWiType::Array as u8 | 0x80) {
             return self.decode_packed_array();
         }
+        if type_code == (BiWiType::String as u8 | 0x80) {
+            return self.decode_small_string();
+        }
+        if type_code == (BiWiType::Object as u8 | 0x80) {
+            return self.nested(Self::decode_interned_object);
+        }
 
         match type_code {
             0x00 => Ok(BiWiValue::Null),
@@ -189,149 +478,88 @@ impl<'a> BiWiDecoder<'a> {
             0x05 => self.decode_float64(),
             0x06 => self.decode_string(),
             0x07 => self.decode_binary(),
-            0x08 => self.decode_array(),
-            0x09 => self.decode_object(),
+            0x08 => self.nested(Self::decode_array),
+            0x09 => self.nested(Self::decode_object),
+            0x11 => self.decode_field_ref(),
+            0x12 => self.decode_field_insert(),
             _ => Err(DecodeError::UnknownType(type_code)),
         }
     }
 
-    /// Decode a 32-bit integer with ZigZag decoding
-    fn decode_int32(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("int32 value"));
+    /// Run a container decoder one nesting level deeper
+    fn nested(&mut self, decode: fn(&mut Self) -> DecodeResult<BiWiValue>) -> DecodeResult<BiWiValue> {
+        if self.depth >= self.limits.max_depth {
+            return Err(DecodeError::LimitExceeded("nesting depth"));
         }
+        self.depth += 1;
+        let result = decode(self);
+        self.depth -= 1;
+        result
+    }
 
-        let byte = self.buffer[self.offset];
-        
-        // Check for small integer marker (high bit set)
-        if byte & 0x80 != 0 {
-            self.offset += 1;
-            let value = (byte as i8) as i32;
-            Ok(BiWiValue::Int32(value))
-        } else {
-            // Varint encoded value
-            self.offset -= 1; // Put byte back for varint reading
-            let zigzag = self.read_varint()?;
-            let value = Self::zigzag_decode_i32(zigzag);
-            Ok(BiWiValue::Int32(value))
-        }
+    /// Decode a 32-bit integer with ZigZag decoding
+    fn decode_int32(&mut self) -> DecodeResult<BiWiValue> {
+        let zigzag = self.read_varint()?;
+        Ok(BiWiValue::Int32(Self::zigzag_decode_i32(zigzag)))
     }
 
     /// Decode a 64-bit integer with ZigZag decoding
     fn decode_int64(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("int64 value"));
-        }
-
-        let byte = self.buffer[self.offset];
-        
-        // Check for small integer marker (high bit set)
-        if byte & 0x80 != 0 {
-            self.offset += 1;
-            let value = (byte as i8) as i64;
-            Ok(BiWiValue::Int64(value))
-        } else {
-            // Varint encoded value
-            self.offset -= 1; // Put byte back for varint reading
-            let zigzag = self.read_varint_u64()?;
-            let value = Self::zigzag_decode_i64(zigzag);
-            Ok(BiWiValue::Int64(value))
-        }
+        let zigzag = self.read_varint_u64()?;
+        Ok(BiWiValue::Int64(Self::zigzag_decode_i64(zigzag)))
     }
 
     /// Decode a 32-bit float
     fn decode_float32(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset + 4 > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("float32"));
-        }
-
-        let bytes = &self.buffer[self.offset..self.offset + 4];
-        self.offset += 4;
-
-        let value = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        Ok(BiWiValue::Float32(value))
+        Ok(BiWiValue::Float32(self.read_f32("float32")?))
     }
 
     /// Decode a 64-bit float
     fn decode_float64(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset + 8 > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("float64"));
-        }
-
-        let bytes = &self.buffer[self.offset..self.offset + 8];
-        self.offset += 8;
-
-        let value = f64::from_be_bytes([
-            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
-        ]);
-        Ok(BiWiValue::Float64(value))
+        Ok(BiWiValue::Float64(self.read_f64("float64")?))
     }
 
-    /// Decode a string (handles both small and large)
+    /// Decode a length-prefixed string
     fn decode_string(&mut self) -> DecodeResult<BiWiValue> {
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("string length"));
-        }
-
-        let len_byte = self.buffer[self.offset];
-        self.offset += 1;
-
-        // Check if small string marker (high bit set)
-        if len_byte & 0x80 != 0 {
-            // Small string: length encoded in low 7 bits
-            let length = (len_byte & 0x7F) as usize;
-            if self.offset + length > self.buffer.len() {
-                return Err(DecodeError::InsufficientData("small string content"));
-            }
-
-            let bytes = &self.buffer[self.offset..self.offset + length];
-            self.offset += length;
+        let length = self.read_length("string length")?;
+        let bytes = self.reader.read_bytes(length, "string content")?;
 
-            let s = std::str::from_utf8(bytes)
-                .map_err(|_| DecodeError::InvalidData("invalid UTF-8 in small string"))?;
-
-            // Try to create SmallString, fallback to regular String if needed
-            if let Some(small) = crate::encoder::SmallString::new(s) {
-                Ok(BiWiValue::SmallString(small))
-            } else {
-                Ok(BiWiValue::String(s.to_string()))
-            }
-        } else {
-            // Large string: length is a varint starting with len_byte
-            self.offset -= 1; // Put byte back
-            let length = self.read_varint()? as usize;
+        let value = String::from_utf8(bytes.to_vec())
+            .map_err(|_| DecodeError::InvalidData("invalid UTF-8"))?;
 
-            if self.offset + length > self.buffer.len() {
-                return Err(DecodeError::InsufficientData("string content"));
-            }
+        Ok(BiWiValue::String(value))
+    }
 
-            let bytes = &self.buffer[self.offset..self.offset + length];
-            self.offset += length;
+    /// Decode an inline small string (at most 15 bytes)
+    fn decode_small_string(&mut self) -> DecodeResult<BiWiValue> {
+        let length = self.reader.read_u8("small string length")? as usize;
+        let bytes = self.reader.read_bytes(length, "small string content")?;
 
-            let value = String::from_utf8(bytes.to_vec())
-                .map_err(|_| DecodeError::InvalidData("invalid UTF-8"))?;
+        let s = std::str::from_utf8(bytes)
+            .map_err(|_| DecodeError::InvalidData("invalid UTF-8 in small string"))?;
 
-            Ok(BiWiValue::String(value))
-        }
+        crate::encoder::SmallString::new(s)
+            .map(BiWiValue::SmallString)
+            .ok_or(DecodeError::InvalidData("small string longer than 15 bytes"))
     }
 
     /// Decode binary data
     fn decode_binary(&mut self) -> DecodeResult<BiWiValue> {
-        let length = self.read_varint()? as usize;
-
-        if self.offset + length > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("binary content"));
+        let length = self.read_length("binary length")?;
+        #[cfg(feature = "bytes")]
+        if let Some(shared) = &self.shared {
+            let start = self.reader.offset();
+            self.reader.read_bytes(length, "binary content")?;
+            return Ok(BiWiValue::Bytes(shared.slice(start..start + length)));
         }
-
-        let data = self.buffer[self.offset..self.offset + length].to_vec();
-        self.offset += length;
-
+        let data = self.reader.read_bytes(length, "binary content")?.to_vec();
         Ok(BiWiValue::Binary(data))
     }
 
     /// Decode an array
     fn decode_array(&mut self) -> DecodeResult<BiWiValue> {
-        let count = self.read_varint()? as usize;
+        // Every element carries at least a type byte
+        let count = self.read_count(1, "array elements")?;
 
         let mut array = Vec::with_capacity(count);
         for _ in 0..count {
@@ -343,19 +571,14 @@ impl<'a> BiWiDecoder<'a> {
 
     /// Decode an object
     fn decode_object(&mut self) -> DecodeResult<BiWiValue> {
-        let count = self.read_varint()? as usize;
+        // Every entry carries at least a key length and a type byte
+        let count = self.read_count(2, "object entries")?;
 
         let mut map = HashMap::with_capacity(count);
         for _ in 0..count {
             // Decode key
-            let key_length = self.read_varint()? as usize;
-
-            if self.offset + key_length > self.buffer.len() {
-                return Err(DecodeError::InsufficientData("key content"));
-            }
-
-            let key_bytes = &self.buffer[self.offset..self.offset + key_length];
-            self.offset += key_length;
+            let key_length = self.read_length("key length")?;
+            let key_bytes = self.reader.read_bytes(key_length, "key content")?;
 
             let key = String::from_utf8(key_bytes.to_vec())
                 .map_err(|_| DecodeError::InvalidData("invalid key UTF-8"))?;
@@ -369,24 +592,78 @@ impl<'a> BiWiDecoder<'a> {
         Ok(BiWiValue::Object(map))
     }
 
-    /// Decode chunk start header
-    pub fn decode_chunk_start(&mut self) -> DecodeResult<ChunkStart> {
-        if self.offset + 6 > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("chunk start"));
+    /// Decode a value taken from the field table
+    fn decode_field_ref(&mut self) -> DecodeResult<BiWiValue> {
+        let index = self.read_varint()?;
+        let fields = self.fields.as_deref().ok_or(DecodeError::InvalidData("field reference without a field table"))?;
+        fields.get(index).cloned().ok_or(DecodeError::InvalidData("unknown field table entry"))
+    }
+
+    /// Decode a value and add it to the field table
+    fn decode_field_insert(&mut self) -> DecodeResult<BiWiValue> {
+        if self.fields.is_none() {
+            return Err(DecodeError::InvalidData("field insert without a field table"));
+        }
+        let index = self.read_varint()?;
+        if matches!(self.reader.peek_u8("field insert value")?, 0x11 | 0x12) {
+            return Err(DecodeError::InvalidData("nested field table entry"));
+        }
+        let start = self.reader.offset();
+        let value = self.decode_value()?;
+        if self.reader.offset() - start > MAX_ENTRY_LEN {
+            return Err(DecodeError::LimitExceeded("field table entry size"));
+        }
+        if let Some(fields) = self.fields.as_deref_mut() {
+            fields.insert(index, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Decode an object whose keys go through the key table
+    fn decode_interned_object(&mut self) -> DecodeResult<BiWiValue> {
+        let count = self.read_count(2, "object entries")?;
+
+        let mut map = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let key = self.read_interned_key()?.to_owned();
+            let value = self.decode_value()?;
+            map.insert(key, value);
         }
 
-        let field_id_bytes = &self.buffer[self.offset..self.offset + 2];
-        let field_id = u16::from_be_bytes([field_id_bytes[0], field_id_bytes[1]]);
-        self.offset += 2;
+        Ok(BiWiValue::Object(map))
+    }
+
+    /// Read a key ID, or a string key and add it to the table if the encoder did
+    fn read_interned_key(&mut self) -> DecodeResult<&str> {
+        if self.keys.is_none() {
+            return Err(DecodeError::InvalidData("interned object keys without a key table"));
+        }
+        let tag = self.read_varint()?;
+        let key = if tag >= KEY_REF_BASE {
+            None
+        } else {
+            let key_length = self.read_length("key length")?;
+            let key_bytes = self.reader.read_bytes(key_length, "key content")?;
+            Some(std::str::from_utf8(key_bytes).map_err(|_| DecodeError::InvalidData("invalid key UTF-8"))?)
+        };
+        let Some(keys) = self.keys.as_deref_mut() else {
+            return Err(DecodeError::InvalidData("interned object keys without a key table"));
+        };
+        match key {
+            Some(key) => {
+                if tag == KEY_NEW && keys.insert(key).is_none() {
+                    return Err(DecodeError::LimitExceeded("key table size"));
+                }
+                Ok(key)
+            }
+            None => keys.key(tag - KEY_REF_BASE).ok_or(DecodeError::InvalidData("unknown interned key ID")),
+        }
+    }
 
-        let total_size_bytes = &self.buffer[self.offset..self.offset + 4];
-        let total_size = u32::from_be_bytes([
-            total_size_bytes[0],
-            total_size_bytes[1],
-            total_size_bytes[2],
-            total_size_bytes[3],
-        ]);
-        self.offset += 4;
+    /// Decode chunk start header
+    pub fn decode_chunk_start(&mut self) -> DecodeResult<ChunkStart> {
+        let field_id = self.read_u16("chunk start")?;
+        let total_size = self.read_u64("chunk start")?;
 
         Ok(ChunkStart {
             field_id,
@@ -396,41 +673,56 @@ impl<'a> BiWiDecoder<'a> {
 
     /// Decode chunk data
     pub fn decode_chunk_data(&mut self) -> DecodeResult<ChunkData> {
-        if self.offset + 4 > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("chunk data header"));
-        }
+        let chunk_index = self.read_u16("chunk data header")?;
+        let data_length = self.read_u16("chunk data header")?;
 
-        let chunk_index_bytes = &self.buffer[self.offset..self.offset + 2];
-        let chunk_index = u16::from_be_bytes([chunk_index_bytes[0], chunk_index_bytes[1]]);
-        self.offset += 2;
+        let data = self.reader.read_bytes(data_length as usize, "chunk content")?.to_vec();
 
-        let data_length_bytes = &self.buffer[self.offset..self.offset + 2];
-        let data_length = u16::from_be_bytes([data_length_bytes[0], data_length_bytes[1]]);
-        self.offset += 2;
+        Ok(ChunkData { chunk_index, data, crc: None })
+    }
 
-        let data_length = data_length as usize;
-        if self.offset + data_length > self.buffer.len() {
-            return Err(DecodeError::InsufficientData("chunk content"));
+    /// Decode one chunk frame including its type byte
+    pub fn decode_chunk_frame(&mut self) -> DecodeResult<ChunkFrame> {
+        match self.reader.read_u8("chunk frame type")? {
+            0x0A => self.decode_chunk_start().map(ChunkFrame::Start),
+            0x0B => self.decode_chunk_data().map(ChunkFrame::Data),
+            0x0C => Ok(ChunkFrame::End(None)),
+            0x0D => Ok(ChunkFrame::TransferStart(ChunkTransferStart {
+                transfer_id: self.read_u32("chunk transfer start")?,
+                field_id: self.read_u16("chunk transfer start")?,
+                total_size: self.read_u64("chunk transfer start")?,
+            })),
+            0x0E => Ok(ChunkFrame::Resume(ChunkResume {
+                transfer_id: self.read_u32("chunk resume")?,
+                next_index: self.read_u32("chunk resume")?,
+            })),
+            0x0F => {
+                let mut chunk = self.decode_chunk_data()?;
+                chunk.crc = Some(self.read_u32("chunk checksum")?);
+                Ok(ChunkFrame::Data(chunk))
+            }
+            0x10 => Ok(ChunkFrame::End(Some(self.reader.read_array("chunk payload hash")?))),
+            other => Err(DecodeError::UnknownType(other)),
         }
-
-        let data = self.buffer[self.offset..self.offset + data_length].to_vec();
-        self.offset += data_length;
-
-        Ok(ChunkData { chunk_index, data })
     }
 
     /// Decode a packed array of primitives (no per-element type markers)
     fn decode_packed_array(&mut self) -> DecodeResult<BiWiValue> {
         // Read element type
-        if self.offset >= self.buffer.len() {
-            return Err(DecodeError::InsufficientData("packed array type"));
-        }
-        
-        let element_type = self.buffer[self.offset];
-        self.offset += 1;
+        let element_type = self.reader.read_u8("packed array type")?;
+
+        // Smallest possible element: 1 varint byte, or 4/8 bytes for floats
+        let min_size = match element_type {
+            0x04 => 4,
+            0x05 => 8,
+            _ => 1,
+        };
 
         // Read element count
-        let count = self.read_varint()? as usize;
+        let count = self.read_count(min_size, "packed array elements")?;
+        if self.typed_arrays {
+            return self.decode_typed_array(element_type, count);
+        }
         let mut array = Vec::with_capacity(count);
 
         // Decode elements based on type
@@ -452,27 +744,13 @@ impl<'a> BiWiDecoder<'a> {
             0x04 => {
                 // Float32 packed array
                 for _ in 0..count {
-                    if self.offset + 4 > self.buffer.len() {
-                        return Err(DecodeError::InsufficientData("float32 in packed array"));
-                    }
-                    let bytes = &self.buffer[self.offset..self.offset + 4];
-                    self.offset += 4;
-                    let value = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-                    array.push(BiWiValue::Float32(value));
+                    array.push(BiWiValue::Float32(self.read_f32("float32 in packed array")?));
                 }
             }
             0x05 => {
                 // Float64 packed array
                 for _ in 0..count {
-                    if self.offset + 8 > self.buffer.len() {
-                        return Err(DecodeError::InsufficientData("float64 in packed array"));
-                    }
-                    let bytes = &self.buffer[self.offset..self.offset + 8];
-                    self.offset += 8;
-                    let value = f64::from_be_bytes([
-                        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
-                    ]);
-                    array.push(BiWiValue::Float64(value));
+                    array.push(BiWiValue::Float64(self.read_f64("float64 in packed array")?));
                 }
             }
             _ => return Err(DecodeError::InvalidData("unknown packed array element type")),
@@ -481,10 +759,100 @@ impl<'a> BiWiDecoder<'a> {
         Ok(BiWiValue::Array(array))
     }
 
-    /// Decode all fields in the buffer
+    /// Packed array elements straight into a native vector
+    fn decode_typed_array(&mut self, element_type: u8, count: usize) -> DecodeResult<BiWiValue> {
+        Ok(match element_type {
+            0x02 => BiWiValue::Int32Array(
+                (0..count)
+                    .map(|_| self.read_varint().map(Self::zigzag_decode_i32))
+                    .collect::<DecodeResult<_>>()?,
+            ),
+            0x03 => BiWiValue::Int64Array(
+                (0..count)
+                    .map(|_| self.read_varint_u64().map(Self::zigzag_decode_i64))
+                    .collect::<DecodeResult<_>>()?,
+            ),
+            0x04 => BiWiValue::Float32Array(
+                (0..count)
+                    .map(|_| self.read_f32("float32 in packed array"))
+                    .collect::<DecodeResult<_>>()?,
+            ),
+            0x05 => BiWiValue::Float64Array(
+                (0..count)
+                    .map(|_| self.read_f64("float64 in packed array"))
+                    .collect::<DecodeResult<_>>()?,
+            ),
+            _ => return Err(DecodeError::InvalidData("unknown packed array element type")),
+        })
+    }
+
+    /// Decode a message written with `BiWiEncoder::encode_sparse`
+    pub fn decode_sparse(&mut self) -> DecodeResult<Vec<DecodedField>> {
+        let flags = self.reader.read_u8("sparse flags")?;
+        if flags == SPARSE_FLAG_FIELDS {
+            return self.try_decode_all();
+        }
+        if flags & !SPARSE_FLAG_NULLS != 0 {
+            return Err(DecodeError::InvalidData("unknown sparse flags"));
+        }
+
+        let min_id = self.read_varint()?;
+        let span = self.read_varint()?;
+        if span == 0 {
+            return Ok(Vec::new());
+        }
+        if min_id.checked_add(span - 1).is_none() {
+            return Err(DecodeError::InvalidData("sparse field range overflows"));
+        }
+
+        // Bitmaps are length-checked by the reader, which also bounds the span
+        let bitmap_len = span.div_ceil(8) as usize;
+        let presence = self.reader.read_bytes(bitmap_len, "sparse bitmap")?;
+        let nulls = if flags & SPARSE_FLAG_NULLS != 0 {
+            Some(self.reader.read_bytes(bitmap_len, "sparse null bitmap")?)
+        } else {
+            None
+        };
+        let bit_set = |bitmap: &[u8], bit: u32| {
+            bitmap.get((bit / 8) as usize).is_some_and(|b| b & (1 << (bit % 8)) != 0)
+        };
+
+        let mut fields = Vec::new();
+        for bit in 0..span {
+            if !bit_set(presence, bit) {
+                continue;
+            }
+            let field_id = min_id + bit;
+            let is_null = nulls.is_some_and(|n| bit_set(n, bit));
+            let value = if is_null { BiWiValue::Null } else { self.decode_value()? };
+            fields.push(DecodedField { field_id, value });
+        }
+
+        Ok(fields)
+    }
+
+    /// Decode a patch written with `BiWiEncoder::encode_delta`, returning
+    /// the removed field IDs and the changed fields
+    pub fn decode_delta(&mut self) -> DecodeResult<(Vec<u32>, Vec<DecodedField>)> {
+        let count = self.read_count(1, "delta removals")?;
+        let mut removed = Vec::with_capacity(count);
+        let mut previous: u32 = 0;
+        for _ in 0..count {
+            let gap = self.read_varint()?;
+            previous = previous
+                .checked_add(gap)
+                .ok_or(DecodeError::InvalidData("delta field id overflows"))?;
+            removed.push(previous);
+        }
+
+        let changed = self.try_decode_all()?;
+        Ok((removed, changed))
+    }
+
+    /// Decode all fields in the buffer, stopping silently at the first error
     pub fn decode_all(&mut self) -> Vec<DecodedField> {
         let mut fields = Vec::new();
-        while self.offset < self.buffer.len() {
+        while self.has_more() {
             match self.decode_field() {
                 Ok(field) => fields.push(field),
                 Err(_) => break, // Stop on incomplete data
@@ -493,18 +861,245 @@ impl<'a> BiWiDecoder<'a> {
         fields
     }
 
+    /// Decode all fields in the buffer, failing on any malformed field
+    pub fn try_decode_all(&mut self) -> DecodeResult<Vec<DecodedField>> {
+        let mut fields = Vec::new();
+        while self.has_more() {
+            fields.push(self.decode_field()?);
+        }
+        Ok(fields)
+    }
+
     /// Check if there's more data to decode
     pub fn has_more(&self) -> bool {
-        self.offset < self.buffer.len()
+        self.reader.has_more()
     }
 
     /// Get remaining bytes count
     pub fn remaining(&self) -> usize {
-        self.buffer.len() - self.offset
+        self.reader.remaining()
     }
 
     /// Get current offset
     pub fn offset(&self) -> usize {
-        self.offset
+        self.reader.offset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::BiWiEncoder;
+
+    fn decode_everything(bytes: &[u8]) {
+        let _ = BiWiDecoder::new(bytes).try_decode_all();
+        let _ = BiWiDecoder::new(bytes).decode_value();
+        let _ = BiWiDecoder::new(bytes).decode_sparse();
+        let _ = BiWiDecoder::new(bytes).decode_chunk_start();
+        let _ = BiWiDecoder::new(bytes).decode_chunk_data();
+    }
+
+    #[test]
+    fn test_truncated_input_never_panics() {
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_field(1, &BiWiValue::String("x".repeat(300)));
+        encoder.encode_field(40, &BiWiValue::Array(vec![BiWiValue::Float64(1.5); 4]));
+        encoder.encode_field(2, &BiWiValue::Array(vec![BiWiValue::Null, BiWiValue::Int64(-7)]));
+        encoder.encode_chunk_start(3, 1000);
+        encoder.encode_chunk_data(0, &[9; 20]);
+        let buffer = encoder.to_buffer();
+
+        for end in 0..buffer.len() {
+            decode_everything(buffer.get(..end).unwrap_or_default());
+        }
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        // Deterministic xorshift so failures are reproducible
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        for _ in 0..20_000 {
+            let len = (state % 48) as usize;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            decode_everything(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_hostile_lengths_are_rejected() {
+        // Array claiming u32::MAX elements with no data behind it
+        let huge_array = [0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert!(BiWiDecoder::new(&huge_array).decode_value().is_err());
+
+        // Packed float64 array whose count cannot fit in the input
+        let huge_packed = [0x88, 0x05, 0xFF, 0xFF, 0x03];
+        assert_eq!(
+            BiWiDecoder::new(&huge_packed).decode_value(),
+            Err(DecodeError::InsufficientData("packed array elements"))
+        );
+
+        // Binary length near usize::MAX must not overflow offset math
+        let huge_binary = [0x07, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert!(BiWiDecoder::new(&huge_binary).decode_value().is_err());
+
+        // Over-long varints are rejected rather than shifted out of range
+        let long_varint = [0x03, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(
+            BiWiDecoder::new(&long_varint).decode_value(),
+            Err(DecodeError::InvalidData("varint exceeds 64 bits"))
+        );
+        let wide_int32 = [0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(
+            BiWiDecoder::new(&wide_int32).decode_value(),
+            Err(DecodeError::InvalidData("varint exceeds 32 bits"))
+        );
+    }
+
+    #[test]
+    fn test_little_endian_roundtrip() {
+        let values = BiWiValue::Array(vec![BiWiValue::Float32(1.5), BiWiValue::Float32(-2.25)]);
+        let mut encoder = BiWiEncoder::new().with_byte_order(ByteOrder::LittleEndian);
+        encoder.encode_field(1, &BiWiValue::Float64(6.5));
+        encoder.encode_field(2, &values);
+        encoder.encode_chunk_start(7, 0x0102_0304_0506_0708);
+        let buffer = encoder.to_buffer();
+
+        // Float64 payload follows the header and type bytes, least significant byte first
+        assert_eq!(buffer.get(2..10), Some(&6.5f64.to_le_bytes()[..]));
+
+        let mut decoder = BiWiDecoder::new(&buffer).with_byte_order(ByteOrder::LittleEndian);
+        assert_eq!(decoder.decode_field().unwrap().value, BiWiValue::Float64(6.5));
+        assert_eq!(decoder.decode_field().unwrap().value, values);
+
+        let chunk = buffer.get(buffer.len() - 10..).unwrap();
+        let start = BiWiDecoder::new(chunk)
+            .with_byte_order(ByteOrder::LittleEndian)
+            .decode_chunk_start()
+            .unwrap();
+        assert_eq!((start.field_id, start.total_size), (7, 0x0102_0304_0506_0708));
+    }
+
+    #[test]
+    fn test_extended_header_and_chunk_size_boundaries() {
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_field(u32::MAX, &BiWiValue::Int32(1));
+        let buffer = encoder.to_buffer();
+        assert_eq!(BiWiDecoder::new(&buffer).decode_field().unwrap().field_id, u32::MAX);
+
+        // One past u32::MAX used to wrap around to field 0
+        let mut too_wide = Vec::new();
+        let mut header = (u64::from(u32::MAX) + 1) << 3 | 2;
+        while header >= 0x80 {
+            too_wide.push(header as u8 | 0x80);
+            header >>= 7;
+        }
+        too_wide.extend_from_slice(&[header as u8, 0x02, 0x02]);
+        assert_eq!(
+            BiWiDecoder::new(&too_wide).decode_field(),
+            Err(DecodeError::InvalidData("field ID exceeds 32 bits"))
+        );
+
+        for total_size in [u64::from(u32::MAX), u64::from(u32::MAX) + 1, u64::MAX] {
+            let mut encoder = BiWiEncoder::new();
+            encoder.encode_chunk_start(9, total_size);
+            encoder.encode_chunk_transfer_start(5, 9, total_size);
+            let mut decoder = BiWiDecoder::new(encoder.as_slice());
+            assert_eq!(decoder.decode_chunk_frame().unwrap(), ChunkFrame::Start(ChunkStart { field_id: 9, total_size }));
+            assert_eq!(
+                decoder.decode_chunk_frame().unwrap(),
+                ChunkFrame::TransferStart(ChunkTransferStart { transfer_id: 5, field_id: 9, total_size })
+            );
+        }
+    }
+
+    #[test]
+    fn test_typed_arrays_roundtrip() {
+        let floats: Vec<f32> = (0..10_000).map(|i| i as f32 * 0.5).collect();
+        let typed = [
+            BiWiValue::from(floats.clone()),
+            BiWiValue::from(vec![-1i32, 0, i32::MAX]),
+            BiWiValue::from(vec![i64::MIN, 7]),
+            BiWiValue::from(vec![f64::MAX, -0.5]),
+            BiWiValue::Int32Array(Vec::new()),
+        ];
+        for value in &typed {
+            let mut encoder = BiWiEncoder::new();
+            encoder.encode_value(value);
+            let buffer = encoder.to_buffer();
+            let decoded = BiWiDecoder::new(&buffer).with_typed_arrays().decode_value().unwrap();
+            assert_eq!(&decoded, value);
+        }
+
+        // Same bytes as the boxed form, which still decodes by default
+        let boxed = BiWiValue::Array(floats.iter().map(|&f| BiWiValue::Float32(f)).collect());
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_value(&boxed);
+        let boxed_bytes = encoder.to_buffer();
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_value(&typed[0]);
+        assert_eq!(encoder.as_slice(), &boxed_bytes[..]);
+        assert_eq!(BiWiDecoder::new(&boxed_bytes).decode_value().unwrap(), boxed);
+        assert_eq!(
+            BiWiDecoder::new(&boxed_bytes).with_typed_arrays().decode_value().unwrap().as_f32_slice(),
+            Some(&floats[..])
+        );
+    }
+
+    #[test]
+    fn test_array_iterators() {
+        let mut streamed = BiWiEncoder::new();
+        streamed.encode_array_iter(3, 3, ["a", "b", "c"]);
+        streamed.encode_packed_iter(40, 1000, (0..1000).map(|i| i as f32 * 0.25));
+        streamed.encode_packed_iter(5, 3, [i64::MIN, 0, 9]);
+
+        // The packed form matches the collected typed array byte for byte
+        let mut collected = BiWiEncoder::new();
+        collected.encode_field(3, &BiWiValue::from(["a", "b", "c"]));
+        collected.encode_field(40, &BiWiValue::from((0..1000).map(|i| i as f32 * 0.25).collect::<Vec<_>>()));
+        collected.encode_field(5, &BiWiValue::from(vec![i64::MIN, 0, 9]));
+        assert_eq!(streamed.as_slice(), collected.as_slice());
+
+        let fields = BiWiDecoder::new(streamed.as_slice()).try_decode_all().unwrap();
+        let [strings, floats, ints] = fields.as_slice() else {
+            panic!("expected three fields, got {:?}", fields);
+        };
+        assert_eq!(strings.value, BiWiValue::from(["a", "b", "c"]));
+        assert_eq!(floats.field_id, 40);
+        assert_eq!(ints.value, BiWiValue::Array(vec![BiWiValue::Int64(i64::MIN), BiWiValue::Int64(0), BiWiValue::Int64(9)]));
+
+        let short = std::panic::catch_unwind(|| BiWiEncoder::new().encode_array_iter(1, 4, [1, 2]));
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn test_limits_are_enforced() {
+        // 100 nested single-element arrays
+        let mut nested = Vec::new();
+        for _ in 0..100 {
+            nested.extend_from_slice(&[0x08, 0x01]);
+        }
+        nested.push(0x00);
+        assert_eq!(
+            BiWiDecoder::new(&nested).decode_value(),
+            Err(DecodeError::LimitExceeded("nesting depth"))
+        );
+
+        let limits = DecodeLimits { max_depth: 128, ..DecodeLimits::default() };
+        assert!(BiWiDecoder::with_limits(&nested, limits).decode_value().is_ok());
+
+        let limits = DecodeLimits { max_bytes_len: 4, ..DecodeLimits::default() };
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_value(&BiWiValue::Binary(vec![0; 5]));
+        assert_eq!(
+            BiWiDecoder::with_limits(encoder.as_slice(), limits).decode_value(),
+            Err(DecodeError::LimitExceeded("binary length"))
+        );
     }
 }
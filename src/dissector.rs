@@ -0,0 +1,673 @@
+//! BiWi Wireshark Dissector
+//! Generates a Lua dissector that labels BiWi traffic in Wireshark: the packet header,
+//! full or compact, and every flag bit (fragment first/last/index, stream, session, send mode, batch,
+//! probe, channel), session tags, handshake, ACK and ping payloads, and the messages
+//! in data packets field by field, with field IDs, type codes and decoded values,
+//! down into arrays, packed arrays and objects. Coalesced batches are split into
+//! their messages and stream packets shown as chunk frames.
+//!
+//! Constants and type names are taken from this crate, so a dissector generated by
+//! `biwi-cli dissector` always matches the wire format of the `biwi` that built it.
+//! Fragments are labeled but not reassembled. Encrypted sessions can't be decoded;
+//! tick the "Sessions are encrypted" protocol preference to show their packet number
+//! and ciphertext instead of a malformed message.
+
+use crate::envelope::ENVELOPE_MARKER;
+use crate::header::{COMPACT_ACK, COMPACT_FLAGS, COMPACT_MODE_MASK, COMPACT_SESSION, COMPACT_TYPE_MASK};
+use crate::network::{
+    PacketType, CHANNEL_MASK, FLAG_BATCH, FLAG_COMPACT_HEADERS, FLAG_FIELD_COMPRESSION, FLAG_PROBE, FLAG_SESSION, FLAG_STREAM, FRAG_FIRST, FRAG_INDEX_MASK, FRAG_LAST,
+    PACKET_HEADER_SIZE, PING_PAYLOAD_LEN, SEND_MODE_MASK, SESSION_TAG_LEN,
+};
+use crate::types::BiWiType;
+use std::fmt::Write;
+
+/// Lua dissector for BiWi on UDP `ports`. With no ports, pick it per capture with
+/// Wireshark's "Decode As..." instead. Install by saving it to Wireshark's personal
+/// plugins folder (Help > About Wireshark > Folders).
+pub fn lua_dissector(ports: &[u16]) -> String {
+    let mut lua = String::new();
+    let _ = writeln!(lua, "-- BinaryWire (BiWi) dissector for Wireshark");
+    let _ = writeln!(lua, "-- Generated by biwi {}; regenerate with `biwi-cli dissector [port...]`", env!("CARGO_PKG_VERSION"));
+    lua.push('\n');
+
+    let constants: [(&str, u64); 25] = [
+        ("HEADER_SIZE", PACKET_HEADER_SIZE as u64),
+        ("SESSION_TAG_LEN", SESSION_TAG_LEN as u64),
+        ("PING_PAYLOAD_LEN", PING_PAYLOAD_LEN as u64),
+        ("FRAG_LAST", FRAG_LAST.into()),
+        ("FRAG_FIRST", FRAG_FIRST.into()),
+        ("FLAG_STREAM", FLAG_STREAM.into()),
+        ("FLAG_SESSION", FLAG_SESSION.into()),
+        ("SEND_MODE_MASK", SEND_MODE_MASK.into()),
+        ("FLAG_BATCH", FLAG_BATCH.into()),
+        ("FLAG_PROBE", FLAG_PROBE.into()),
+        ("FLAG_FIELD_COMPRESSION", FLAG_FIELD_COMPRESSION.into()),
+        ("FLAG_COMPACT_HEADERS", FLAG_COMPACT_HEADERS.into()),
+        ("COMPACT_ACK", COMPACT_ACK.into()),
+        ("COMPACT_TYPE_MASK", COMPACT_TYPE_MASK.into()),
+        ("COMPACT_FLAGS", COMPACT_FLAGS.into()),
+        ("COMPACT_SESSION", COMPACT_SESSION.into()),
+        ("COMPACT_MODE_MASK", COMPACT_MODE_MASK.into()),
+        ("CHANNEL_MASK", CHANNEL_MASK.into()),
+        ("FRAG_INDEX_MASK", FRAG_INDEX_MASK.into()),
+        ("ENVELOPE_MARKER_0", ENVELOPE_MARKER[0].into()),
+        ("ENVELOPE_MARKER_1", ENVELOPE_MARKER[1].into()),
+        ("T_FALSE", 0xFF),
+        ("T_SMALL_STRING", (BiWiType::String as u8 | 0x80).into()),
+        ("T_PACKED_ARRAY", (BiWiType::Array as u8 | 0x80).into()),
+        ("T_INTERNED_OBJECT", (BiWiType::Object as u8 | 0x80).into()),
+    ];
+    for (name, value) in constants {
+        let _ = writeln!(lua, "local {} = 0x{:X}", name, value);
+    }
+    for code in 0..=u8::MAX {
+        if let Some(ty) = BiWiType::from_u8(code) {
+            let _ = writeln!(lua, "local T_{} = 0x{:02X}", ty.name(), code);
+        }
+    }
+    for code in 0..=u8::MAX {
+        if let Some(ty) = PacketType::from_u8(code) {
+            let _ = writeln!(lua, "local P_{} = {}", format!("{:?}", ty).to_uppercase(), code);
+        }
+    }
+
+    lua.push_str("\nlocal packet_types = {\n");
+    for code in 0..=u8::MAX {
+        if let Some(ty) = PacketType::from_u8(code) {
+            let _ = writeln!(lua, "    [{}] = \"{:?}\",", code, ty);
+        }
+    }
+    lua.push_str("}\n\nlocal value_types = {\n");
+    for code in 0..=u8::MAX {
+        if let Some(ty) = BiWiType::from_u8(code) {
+            let _ = writeln!(lua, "    [0x{:02X}] = \"{}\",", code, ty.name());
+        }
+    }
+    lua.push_str("    [T_FALSE] = \"BOOLEAN\",\n    [T_SMALL_STRING] = \"SMALL_STRING\",\n    [T_PACKED_ARRAY] = \"PACKED_ARRAY\",\n    [T_INTERNED_OBJECT] = \"INTERNED_OBJECT\",\n}\n\n");
+
+    let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+    if ports.is_empty() {
+        lua.push_str("local PORTS = {}\n");
+    } else {
+        let _ = writeln!(lua, "local PORTS = {{ {} }}", ports.join(", "));
+    }
+    lua.push_str(DISSECTOR);
+    lua
+}
+
+/// The protocol-independent part of the dissector, run after the generated constants
+const DISSECTOR: &str = r#"
+local send_modes = {
+    [0] = "ReliableOrdered",
+    [1] = "ReliableUnordered",
+    [2] = "UnreliableSequenced",
+    [3] = "Unreliable",
+}
+
+local MAX_DEPTH = 32
+
+local biwi = Proto("biwi", "BinaryWire")
+biwi.prefs.encrypted = Pref.bool("Sessions are encrypted", false,
+    "Show data payloads as packet number and ciphertext instead of decoding them")
+
+local f = biwi.fields
+f.type = ProtoField.uint8("biwi.type", "Packet type", base.DEC, packet_types)
+f.sequence = ProtoField.uint32("biwi.sequence", "Sequence")
+f.ack = ProtoField.uint32("biwi.ack", "Ack number")
+f.flags = ProtoField.uint32("biwi.flags", "Flags", base.HEX)
+f.frag_last = ProtoField.bool("biwi.flags.frag_last", "Last fragment", 32, nil, FRAG_LAST)
+f.frag_first = ProtoField.bool("biwi.flags.frag_first", "First fragment", 32, nil, FRAG_FIRST)
+f.stream = ProtoField.bool("biwi.flags.stream", "Stream", 32, nil, FLAG_STREAM)
+f.session_flag = ProtoField.bool("biwi.flags.session", "Session tag", 32, nil, FLAG_SESSION)
+f.send_mode = ProtoField.uint32("biwi.flags.send_mode", "Send mode", base.DEC, send_modes, SEND_MODE_MASK)
+f.batch = ProtoField.bool("biwi.flags.batch", "Batch", 32, nil, FLAG_BATCH)
+f.probe = ProtoField.bool("biwi.flags.probe", "MTU probe", 32, nil, FLAG_PROBE)
+f.channel = ProtoField.uint32("biwi.flags.channel", "Channel", base.DEC, nil, CHANNEL_MASK)
+f.frag_index = ProtoField.uint32("biwi.flags.frag_index", "Fragment index", base.DEC, nil, FRAG_INDEX_MASK)
+f.session = ProtoField.uint64("biwi.session", "Session ID", base.HEX)
+f.version = ProtoField.uint16("biwi.version", "Protocol version")
+f.random = ProtoField.bytes("biwi.handshake.random", "Handshake random")
+f.confirmation = ProtoField.bytes("biwi.handshake.confirmation", "Key confirmation")
+f.credentials = ProtoField.bytes("biwi.handshake.credentials", "Credentials")
+f.ack_bits = ProtoField.uint32("biwi.ack_bits", "ACK bits", base.HEX)
+f.receive_window = ProtoField.uint32("biwi.receive_window", "Receive window")
+f.ping_sent = ProtoField.uint64("biwi.ping.sent", "Sent (us)")
+f.ping_received = ProtoField.uint64("biwi.ping.received", "Peer received (us)")
+f.ping_replied = ProtoField.uint64("biwi.ping.replied", "Peer replied (us)")
+f.padding = ProtoField.bytes("biwi.padding", "Padding")
+f.packet_number = ProtoField.uint64("biwi.packet_number", "Packet number")
+f.ciphertext = ProtoField.bytes("biwi.ciphertext", "Ciphertext")
+f.fragment = ProtoField.bytes("biwi.fragment", "Fragment data")
+f.batch_count = ProtoField.uint16("biwi.batch.count", "Messages")
+f.batch_length = ProtoField.uint16("biwi.batch.length", "Message length")
+f.envelope_type = ProtoField.uint32("biwi.envelope.type", "Message type")
+f.correlation_id = ProtoField.uint64("biwi.envelope.correlation_id", "Correlation ID")
+f.envelope_flags = ProtoField.uint8("biwi.envelope.flags", "Flags", base.HEX)
+f.timestamp = ProtoField.uint64("biwi.envelope.timestamp", "Timestamp (ms)")
+f.field_id = ProtoField.uint32("biwi.field.id", "Field ID")
+f.value_type = ProtoField.uint8("biwi.value.type", "Type", base.HEX, value_types)
+f.count = ProtoField.uint32("biwi.value.count", "Count")
+f.length = ProtoField.uint32("biwi.value.length", "Length")
+f.bool_value = ProtoField.bool("biwi.value.bool", "Value")
+f.int_value = ProtoField.int64("biwi.value.int", "Value")
+f.float32_value = ProtoField.float("biwi.value.float32", "Value")
+f.float64_value = ProtoField.double("biwi.value.float64", "Value")
+f.string_value = ProtoField.string("biwi.value.string", "Value", base.UNICODE)
+f.binary_value = ProtoField.bytes("biwi.value.binary", "Value")
+f.key = ProtoField.string("biwi.value.key", "Key", base.UNICODE)
+f.table_index = ProtoField.uint32("biwi.value.table_index", "Field table index")
+f.chunk_field_id = ProtoField.uint16("biwi.chunk.field_id", "Field ID")
+f.chunk_total_size = ProtoField.uint64("biwi.chunk.total_size", "Total size")
+f.chunk_index = ProtoField.uint16("biwi.chunk.index", "Chunk index")
+f.chunk_data = ProtoField.bytes("biwi.chunk.data", "Chunk data")
+f.chunk_transfer_id = ProtoField.uint32("biwi.chunk.transfer_id", "Transfer ID")
+f.chunk_next_index = ProtoField.uint32("biwi.chunk.next_index", "Next index")
+f.chunk_crc = ProtoField.uint32("biwi.chunk.crc", "CRC-32", base.HEX)
+f.chunk_hash = ProtoField.bytes("biwi.chunk.hash", "Payload SHA-256")
+
+local e_malformed = ProtoExpert.new("biwi.malformed", "Malformed BiWi payload",
+    expert.group.MALFORMED, expert.severity.WARN)
+biwi.experts = { e_malformed }
+
+-- Errors raised while decoding are caught per payload and shown as expert info
+local function need(tvb, offset, len)
+    if offset + len > tvb:len() then
+        error("truncated at byte " .. offset, 0)
+    end
+end
+
+-- Unsigned LEB128 varint: value and byte count
+local function varint(tvb, offset)
+    local value, scale, pos = 0, 1, offset
+    while true do
+        need(tvb, pos, 1)
+        local byte = tvb(pos, 1):uint()
+        pos = pos + 1
+        value = value + (byte % 128) * scale
+        if byte < 128 then
+            return value, pos - offset
+        end
+        if pos - offset >= 10 then
+            error("varint longer than 10 bytes", 0)
+        end
+        scale = scale * 128
+    end
+end
+
+local function zigzag(value)
+    if value % 2 == 0 then
+        return math.floor(value / 2)
+    end
+    return -math.floor((value + 1) / 2)
+end
+
+local function has(flags, bit)
+    return flags % (bit * 2) >= bit
+end
+
+local value_item
+
+-- Decode the value at `offset` into `item`: type byte, then its contents.
+-- Returns the offset after the value and a one-line summary.
+local function dissect_value(tvb, offset, item, depth)
+    if depth > MAX_DEPTH then
+        error("nested deeper than " .. MAX_DEPTH, 0)
+    end
+    local code = tvb(offset, 1):uint()
+    local name = value_types[code]
+    if not name then
+        error(string.format("unknown type 0x%02X", code), 0)
+    end
+    item:add(f.value_type, tvb(offset, 1))
+    local pos = offset + 1
+
+    if code == T_NULL then
+        return pos, "null"
+    elseif code == T_BOOLEAN or code == T_FALSE then
+        item:add(f.bool_value, tvb(offset, 1), code == T_BOOLEAN)
+        return pos, tostring(code == T_BOOLEAN)
+    elseif code == T_INT32 or code == T_INT64 then
+        local raw, n = varint(tvb, pos)
+        local value = zigzag(raw)
+        item:add(f.int_value, tvb(pos, n), value)
+        return pos + n, string.format("%d", value)
+    elseif code == T_FLOAT32 then
+        need(tvb, pos, 4)
+        item:add(f.float32_value, tvb(pos, 4))
+        return pos + 4, tostring(tvb(pos, 4):float())
+    elseif code == T_FLOAT64 then
+        need(tvb, pos, 8)
+        item:add(f.float64_value, tvb(pos, 8))
+        return pos + 8, tostring(tvb(pos, 8):float())
+    elseif code == T_STRING or code == T_BINARY or code == T_SMALL_STRING then
+        local len, n = 0, 1
+        if code == T_SMALL_STRING then
+            need(tvb, pos, 1)
+            len = tvb(pos, 1):uint()
+        else
+            len, n = varint(tvb, pos)
+        end
+        item:add(f.length, tvb(pos, n), len)
+        pos = pos + n
+        need(tvb, pos, len)
+        if code == T_BINARY then
+            if len > 0 then
+                item:add(f.binary_value, tvb(pos, len))
+            end
+            return pos + len, len .. " bytes"
+        end
+        if len == 0 then
+            return pos, '""'
+        end
+        item:add(f.string_value, tvb(pos, len))
+        return pos + len, '"' .. tvb(pos, len):string(ENC_UTF_8) .. '"'
+    elseif code == T_ARRAY then
+        local count, n = varint(tvb, pos)
+        item:add(f.count, tvb(pos, n), count)
+        pos = pos + n
+        for i = 0, count - 1 do
+            pos = value_item(tvb, pos, item, "[" .. i .. "]", depth + 1)
+        end
+        return pos, count .. " items"
+    elseif code == T_PACKED_ARRAY then
+        need(tvb, pos, 1)
+        local element = tvb(pos, 1):uint()
+        item:add(f.value_type, tvb(pos, 1)):prepend_text("Element ")
+        pos = pos + 1
+        local count, n = varint(tvb, pos)
+        item:add(f.count, tvb(pos, n), count)
+        pos = pos + n
+        for i = 0, count - 1 do
+            local label = "[" .. i .. "]: "
+            if element == T_INT32 or element == T_INT64 then
+                local raw, len = varint(tvb, pos)
+                item:add(f.int_value, tvb(pos, len), zigzag(raw)):prepend_text(label)
+                pos = pos + len
+            elseif element == T_FLOAT32 then
+                need(tvb, pos, 4)
+                item:add(f.float32_value, tvb(pos, 4)):prepend_text(label)
+                pos = pos + 4
+            elseif element == T_FLOAT64 then
+                need(tvb, pos, 8)
+                item:add(f.float64_value, tvb(pos, 8)):prepend_text(label)
+                pos = pos + 8
+            else
+                error(string.format("unknown packed element type 0x%02X", element), 0)
+            end
+        end
+        return pos, count .. " " .. (value_types[element] or "?") .. " items"
+    elseif code == T_OBJECT or code == T_INTERNED_OBJECT then
+        local count, n = varint(tvb, pos)
+        item:add(f.count, tvb(pos, n), count)
+        pos = pos + n
+        for _ = 1, count do
+            local entry_start = pos
+            local ref = 0
+            if code == T_INTERNED_OBJECT then
+                -- 0 and 1 precede a string key; 2 + id refers to an earlier one
+                local ref_len
+                ref, ref_len = varint(tvb, pos)
+                pos = pos + ref_len
+            end
+            local entry = item:add(tvb(entry_start, 1), "")
+            local key
+            if ref >= 2 then
+                key = "key " .. (ref - 2)
+            else
+                local len, key_len = varint(tvb, pos)
+                need(tvb, pos + key_len, len)
+                key = '"' .. (len > 0 and tvb(pos + key_len, len):string(ENC_UTF_8) or "") .. '"'
+                if len > 0 then
+                    entry:add(f.key, tvb(pos + key_len, len))
+                end
+                pos = pos + key_len + len
+            end
+            need(tvb, pos, 1)
+            local after, summary = dissect_value(tvb, pos, entry, depth + 1)
+            entry:set_text(string.format('%s: %s = %s', key, value_types[tvb(pos, 1):uint()], summary))
+            entry:set_len(after - entry_start)
+            pos = after
+        end
+        return pos, count .. " entries"
+    elseif code == T_FIELD_REF then
+        -- Resolved against the session's field table, which a capture may not cover
+        local index, n = varint(tvb, pos)
+        item:add(f.table_index, tvb(pos, n), index)
+        return pos + n, "entry " .. index
+    elseif code == T_FIELD_INSERT then
+        local index, n = varint(tvb, pos)
+        item:add(f.table_index, tvb(pos, n), index)
+        return value_item(tvb, pos + n, item, "Value", depth + 1), "entry " .. index
+    elseif code == T_CHUNK_START then
+        need(tvb, pos, 10)
+        item:add(f.chunk_field_id, tvb(pos, 2))
+        item:add(f.chunk_total_size, tvb(pos + 2, 8))
+        return pos + 10, "field " .. tvb(pos, 2):uint() .. ", " .. tostring(tvb(pos + 2, 8):uint64()) .. " bytes"
+    elseif code == T_CHUNK_DATA or code == T_CHUNK_DATA_CHECKED then
+        need(tvb, pos, 4)
+        local len = tvb(pos + 2, 2):uint()
+        item:add(f.chunk_index, tvb(pos, 2))
+        item:add(f.length, tvb(pos + 2, 2), len)
+        need(tvb, pos + 4, len)
+        if len > 0 then
+            item:add(f.chunk_data, tvb(pos + 4, len))
+        end
+        local after = pos + 4 + len
+        if code == T_CHUNK_DATA_CHECKED then
+            need(tvb, after, 4)
+            item:add(f.chunk_crc, tvb(after, 4))
+            after = after + 4
+        end
+        return after, "chunk " .. tvb(pos, 2):uint() .. ", " .. len .. " bytes"
+    elseif code == T_CHUNK_END then
+        return pos, "end"
+    elseif code == T_CHUNK_END_HASHED then
+        need(tvb, pos, 32)
+        item:add(f.chunk_hash, tvb(pos, 32))
+        return pos + 32, "end, hashed"
+    elseif code == T_CHUNK_TRANSFER_START then
+        need(tvb, pos, 14)
+        item:add(f.chunk_transfer_id, tvb(pos, 4))
+        item:add(f.chunk_field_id, tvb(pos + 4, 2))
+        item:add(f.chunk_total_size, tvb(pos + 6, 8))
+        return pos + 14, "transfer " .. tvb(pos, 4):uint() .. ", field " .. tvb(pos + 4, 2):uint()
+    elseif code == T_CHUNK_RESUME then
+        need(tvb, pos, 8)
+        item:add(f.chunk_transfer_id, tvb(pos, 4))
+        item:add(f.chunk_next_index, tvb(pos + 4, 4))
+        return pos + 8, "transfer " .. tvb(pos, 4):uint() .. " from chunk " .. tvb(pos + 4, 4):uint()
+    end
+    error("unhandled type " .. name, 0)
+end
+
+-- Add a subtree for the value at `offset` under `tree`, labeled `label`
+value_item = function(tvb, offset, tree, label, depth)
+    need(tvb, offset, 1)
+    local item = tree:add(tvb(offset, 1), label)
+    local pos, summary = dissect_value(tvb, offset, item, depth)
+    item:set_text(label .. ": " .. value_types[tvb(offset, 1):uint()] .. " = " .. summary)
+    item:set_len(pos - offset)
+    return pos
+end
+
+-- One encoded message: an optional envelope, then fields to the end of `tvb`.
+-- Returns the number of fields.
+local function dissect_message(tvb, tree)
+    local pos = 0
+    local len = tvb:len()
+    if len >= 2 and tvb(0, 1):uint() == ENVELOPE_MARKER_0 and tvb(1, 1):uint() == ENVELOPE_MARKER_1 then
+        local envelope = tree:add(tvb(0, 2), "Envelope")
+        need(tvb, 2, 1)
+        local presence = tvb(2, 1):uint()
+        pos = 3
+        local value, n = varint(tvb, pos)
+        envelope:add(f.envelope_type, tvb(pos, n), value)
+        pos = pos + n
+        value, n = varint(tvb, pos)
+        envelope:add(f.correlation_id, tvb(pos, n), value)
+        pos = pos + n
+        need(tvb, pos, 1)
+        envelope:add(f.envelope_flags, tvb(pos, 1))
+        pos = pos + 1
+        if presence % 2 == 1 then
+            value, n = varint(tvb, pos)
+            envelope:add(f.timestamp, tvb(pos, n), value)
+            pos = pos + n
+        end
+        envelope:set_len(pos)
+    end
+
+    local fields = 0
+    while pos < len do
+        local start = pos
+        local header = tvb(pos, 1):uint()
+        local id, n = math.floor(header / 4), 1
+        if header >= 0x80 then
+            id, n = varint(tvb, pos)
+            id = math.floor(id / 8)
+        end
+        local item = tree:add(tvb(start, n), "Field " .. id)
+        item:add(f.field_id, tvb(start, n), id)
+        pos = pos + n
+        need(tvb, pos, 1)
+        local after, summary = dissect_value(tvb, pos, item, 0)
+        item:set_text("Field " .. id .. ": " .. value_types[tvb(pos, 1):uint()] .. " = " .. summary)
+        item:set_len(after - start)
+        pos = after
+        fields = fields + 1
+    end
+    return fields
+end
+
+-- A data packet's payload after the session tag; returns a note for the Info column
+local function dissect_data(tvb, flags, tree)
+    if biwi.prefs.encrypted then
+        need(tvb, 0, 8)
+        tree:add(f.packet_number, tvb(0, 8))
+        if tvb:len() > 8 then
+            tree:add(f.ciphertext, tvb(8))
+        end
+        return "encrypted"
+    end
+    if has(flags, FLAG_STREAM) then
+        value_item(tvb, 0, tree, "Stream chunk", 0)
+        return "stream"
+    end
+    if not (has(flags, FRAG_FIRST) and has(flags, FRAG_LAST)) then
+        if tvb:len() > 0 then
+            tree:add(f.fragment, tvb())
+        end
+        local note = "fragment " .. math.floor(flags / 0x10000)
+        if has(flags, FRAG_LAST) then
+            note = note .. " (last)"
+        end
+        return note
+    end
+    if has(flags, FLAG_BATCH) then
+        need(tvb, 0, 2)
+        local count = tvb(0, 2):uint()
+        tree:add(f.batch_count, tvb(0, 2))
+        need(tvb, 2, count * 2)
+        local pos = 2 + count * 2
+        for i = 0, count - 1 do
+            local len = tvb(2 + i * 2, 2):uint()
+            need(tvb, pos, len)
+            local message = tree:add(tvb(pos, math.max(len, 1)), "Message " .. i)
+            message:add(f.batch_length, tvb(2 + i * 2, 2))
+            if len > 0 then
+                local fields = dissect_message(tvb(pos, len):tvb(), message)
+                message:append_text(" (" .. fields .. " fields)")
+            end
+            pos = pos + len
+        end
+        return count .. " messages"
+    end
+    if tvb:len() == 0 then
+        return "0 fields"
+    end
+    return dissect_message(tvb, tree) .. " fields"
+end
+
+-- Compact header: the type and usual flags in one byte, then varints. Returns the
+-- header's values, the ranges they came from (nil if left out) and its length. A
+-- data packet's sequence is only its low 7 bits per byte unless it takes all five.
+local function compact_header(tvb, first, packet_type)
+    local pos = 1
+    local sequence, n = varint(tvb, pos)
+    local h = { sequence = sequence, sequence_range = tvb(pos, n) }
+    if packet_type == P_DATA and n < 5 then
+        h.sequence_bits = 7 * n
+    end
+    pos = pos + n
+    h.ack = packet_type == P_DATA and 0xFFFFFFFF or 0
+    if has(first, COMPACT_ACK) then
+        h.ack, n = varint(tvb, pos)
+        h.ack_range = tvb(pos, n)
+        pos = pos + n
+    end
+    if has(first, COMPACT_FLAGS) then
+        h.flags, n = varint(tvb, pos)
+        h.flags_range = tvb(pos, n)
+        pos = pos + n
+    else
+        h.flags = (first % (COMPACT_MODE_MASK + 1)) * (SEND_MODE_MASK / COMPACT_MODE_MASK)
+        if has(first, COMPACT_SESSION) then
+            h.flags = h.flags + FLAG_SESSION
+        end
+        if packet_type == P_DATA then
+            h.flags = h.flags + FRAG_FIRST + FRAG_LAST
+        end
+        h.flags_range = tvb(0, 1)
+    end
+    return h, pos
+end
+
+function biwi.dissector(tvb, pinfo, tree)
+    local len = tvb:len()
+    local first = len > 0 and tvb(0, 1):uint() or 0
+    -- A full header's type byte never has the compact type bits set
+    local compact_type = math.floor(first % (COMPACT_TYPE_MASK + 0x10) / 0x10)
+    local compact = compact_type ~= 0
+    local packet_type = compact and compact_type or first
+    local type_name = packet_types[packet_type]
+    if not type_name or len < (compact and 2 or HEADER_SIZE) then
+        return 0
+    end
+    local h, pos
+    if compact then
+        local ok
+        ok, h, pos = pcall(compact_header, tvb, first, packet_type)
+        if not ok then
+            return 0
+        end
+    else
+        h = {
+            sequence = tvb(1, 4):uint(), sequence_range = tvb(1, 4),
+            ack = tvb(5, 4):uint(), ack_range = tvb(5, 4),
+            flags = tvb(9, 4):uint(), flags_range = tvb(9, 4),
+        }
+        pos = HEADER_SIZE
+    end
+    pinfo.cols.protocol = "BiWi"
+
+    local root = tree:add(biwi, tvb(), "BinaryWire, " .. type_name .. (compact and " (compact header)" or ""))
+    root:add(f.type, tvb(0, 1), packet_type)
+    local sequence_item = root:add(f.sequence, h.sequence_range, h.sequence)
+    if h.sequence_bits then
+        sequence_item:append_text(" (low " .. h.sequence_bits .. " bits)")
+    end
+    if h.ack_range then
+        root:add(f.ack, h.ack_range, h.ack)
+    end
+    local flags = h.flags
+    local flag_tree = root:add(f.flags, h.flags_range, flags)
+    for _, field in ipairs({ f.frag_first, f.frag_last, f.frag_index, f.stream, f.session_flag,
+                             f.send_mode, f.batch, f.probe, f.channel }) do
+        flag_tree:add(field, h.flags_range, flags)
+    end
+
+    local info = string.format("%s seq=%d ack=%d", type_name, h.sequence, h.ack)
+    local channel = math.floor(flags / 0x100) % 0x100
+    if channel ~= 0 then
+        info = info .. " ch=" .. channel
+    end
+    if has(flags, FLAG_SESSION) and len >= pos + SESSION_TAG_LEN then
+        root:add(f.session, tvb(pos, SESSION_TAG_LEN))
+        pos = pos + SESSION_TAG_LEN
+    end
+    local payload = tvb(pos, len - pos):tvb()
+    local payload_len = payload:len()
+
+    local ok, note = pcall(function()
+        if packet_type == P_DATA then
+            return dissect_data(payload, flags, root)
+        elseif has(flags, FLAG_PROBE) then
+            if payload_len > 0 then
+                root:add(f.padding, payload())
+            end
+            return "MTU probe, " .. len .. " bytes"
+        elseif packet_type == P_ACK and payload_len >= 4 then
+            root:add(f.ack_bits, payload(0, 4))
+            if payload_len >= 8 then
+                root:add(f.receive_window, payload(4, 4))
+                return "window " .. payload(4, 4):uint()
+            end
+        elseif (packet_type == P_PING or packet_type == P_PONG) and payload_len >= PING_PAYLOAD_LEN then
+            root:add(f.ping_sent, payload(0, 8))
+            if payload_len >= 24 then
+                root:add(f.ping_received, payload(8, 8))
+                root:add(f.ping_replied, payload(16, 8))
+            end
+        elseif packet_type == P_CONNECT or packet_type == P_CONNECTACK then
+            -- On handshakes these bits offer (Connect) or accept (ConnectAck) session features
+            local features = {}
+            if has(flags, FLAG_FIELD_COMPRESSION) then
+                table.insert(features, "field compression")
+            end
+            if has(flags, FLAG_COMPACT_HEADERS) then
+                table.insert(features, "compact headers")
+            end
+            local note = #features > 0 and table.concat(features, ", ") or nil
+            need(payload, 0, 2)
+            root:add(f.version, payload(0, 2))
+            local at = 2
+            if packet_type == P_CONNECTACK then
+                need(payload, at, 8)
+                root:add(f.session, payload(at, 8))
+                at = at + 8
+            end
+            if payload_len >= at + 32 then
+                root:add(f.random, payload(at, 32))
+                at = at + 32
+                if packet_type == P_CONNECTACK and payload_len >= at + 32 then
+                    root:add(f.confirmation, payload(at, 32))
+                elseif packet_type == P_CONNECT and payload_len > at then
+                    root:add(f.credentials, payload(at))
+                end
+                return note and "encrypted, " .. note or "encrypted"
+            end
+            return note
+        elseif packet_type == P_DISCONNECT and payload_len >= 8 then
+            root:add(f.session, payload(0, 8))
+        end
+    end)
+    if not ok then
+        root:add_proto_expert_info(e_malformed, note)
+        note = "malformed"
+    end
+    if note then
+        info = info .. " [" .. note .. "]"
+    end
+    pinfo.cols.info = info
+    return len
+end
+
+local udp_port = DissectorTable.get("udp.port")
+for _, port in ipairs(PORTS) do
+    udp_port:add(port, biwi)
+end
+udp_port:add_for_decode_as(biwi)
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lua_dissector_uses_crate_constants() {
+        let lua = lua_dissector(&[9001, 9002]);
+        assert!(lua.contains("local HEADER_SIZE = 0xD"));
+        assert!(lua.contains("local FLAG_BATCH = 0x40"));
+        assert!(lua.contains("local FRAG_INDEX_MASK = 0xFFFF0000"));
+        assert!(lua.contains("local COMPACT_TYPE_MASK = 0x70"));
+        assert!(lua.contains("local T_SMALL_STRING = 0x86"));
+        assert!(lua.contains("local T_CHUNK_RESUME = 0x0E"));
+        assert!(lua.contains("local T_CHUNK_END_HASHED = 0x10"));
+        assert!(lua.contains("local P_CONNECTACK = 6"));
+        assert!(lua.contains("[7] = \"Disconnect\","));
+        assert!(lua.contains("[0x06] = \"STRING\","));
+        assert!(lua.contains("local PORTS = { 9001, 9002 }"));
+        assert!(lua_dissector(&[]).contains("local PORTS = {}"));
+        assert!(lua.contains("function biwi.dissector(tvb, pinfo, tree)"));
+    }
+}
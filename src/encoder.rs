@@ -1,11 +1,16 @@
 //! BiWi Binary Encoder - Optimized for Performance & Efficiency
 //! Encodes Rust values into BiWi binary format with compression techniques
 
-use crate::types::BiWiType;
+use crate::envelope::{Envelope, ENVELOPE_HAS_TIMESTAMP, ENVELOPE_MARKER};
+use crate::intern::{KeyTable, KEY_LITERAL, KEY_NEW, KEY_REF_BASE};
+use crate::types::{BiWiType, ByteOrder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 
-/// BiWi value representation with inlined small values for allocation efficiency
-#[derive(Debug, Clone, PartialEq)]
+/// BiWi value representation with inlined small values for allocation efficiency.
+/// Serializes with serde as an externally tagged enum (`{"Int32": 42}`) so the
+/// exact wire type survives a trip through JSON or YAML.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BiWiValue {
     Null,
     Boolean(bool),
@@ -20,6 +25,16 @@ pub enum BiWiValue {
     Binary(Vec<u8>),
     Array(Vec<BiWiValue>),
     Object(HashMap<String, BiWiValue>),
+    /// Packed arrays held as native slices. They encode like an `Array` of the matching
+    /// number type; `BiWiDecoder::with_typed_arrays` decodes packed arrays into them.
+    Int32Array(Vec<i32>),
+    Int64Array(Vec<i64>),
+    Float32Array(Vec<f32>),
+    Float64Array(Vec<f64>),
+    /// Binary data sharing a `bytes::Bytes` buffer; encodes exactly like `Binary`.
+    /// `BiWiDecoder::new_shared` decodes Binary values into it without copying.
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
 }
 
 /// Inline small string (up to 15 bytes with 1-byte length)
@@ -52,7 +67,82 @@ impl SmallString {
     }
 }
 
+impl Serialize for SmallString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SmallString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        SmallString::new(&s).ok_or_else(|| serde::de::Error::custom("small string longer than 15 bytes"))
+    }
+}
+
+/// Controls when `BiWiValue::number_with_policy` may trade float precision for size.
+/// Encoders decide float widths with `FloatPolicy` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EncodePolicy {
+    /// Never lose information: non-integral values stay Float64, -0.0 keeps its sign
+    Exact,
+    /// Narrow to Float32 for simple decimals with relative error below 1e-5 (default)
+    #[default]
+    Compact,
+    /// Narrow to Float32 whenever the relative round-trip error is within the tolerance
+    LossyTolerance(f64),
+}
+
+/// How `BiWiEncoder` writes Float32 and Float64 values, wherever they appear:
+/// top-level fields, array and object elements and typed float arrays
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FloatPolicy {
+    /// Write every float at the width it already has (default)
+    #[default]
+    Exact64,
+    /// Narrow Float64 to Float32 under the same rule as `BiWiValue::number`
+    Auto32,
+    /// Round to the nearest multiple of the step, then use Float32 if it holds the
+    /// rounded value to within half a step. Decoded values are within one step.
+    Quantized(f64),
+}
+
+impl FloatPolicy {
+    /// `value` as written under this policy, and whether Float32 may carry it
+    fn shape(self, value: f64) -> (f64, bool) {
+        match self {
+            FloatPolicy::Exact64 => (value, false),
+            FloatPolicy::Auto32 => (value, BiWiValue::can_use_float32(value)),
+            FloatPolicy::Quantized(step) => {
+                if step.is_nan() || step <= 0.0 || !value.is_finite() {
+                    return (value, false);
+                }
+                let rounded = (value / step).round() * step;
+                (rounded, (rounded as f32 as f64 - rounded).abs() <= step / 2.0)
+            }
+        }
+    }
+
+    /// Whether a float that arrived as Float32 (`is_f32`) or Float64 is written as Float32
+    fn narrows(self, value: f64, is_f32: bool) -> bool {
+        match self {
+            FloatPolicy::Exact64 => is_f32,
+            FloatPolicy::Auto32 if is_f32 => true,
+            policy => policy.shape(value).1,
+        }
+    }
+}
+
 impl BiWiValue {
+    /// Relative error introduced by narrowing to f32
+    fn float32_relative_error(value: f64) -> f64 {
+        if value == 0.0 {
+            return 0.0;
+        }
+        let roundtrip = value as f32 as f64;
+        ((roundtrip - value) / value).abs()
+    }
+
     /// Check if value can be safely encoded as Float32
     fn can_use_float32(value: f64) -> bool {
         // Only use FLOAT32 for very simple decimals
@@ -60,38 +150,149 @@ impl BiWiValue {
             return false;
         }
 
-        // Check precision loss
-        let as_f32 = value as f32;
-        let roundtrip = as_f32 as f64;
-
-        if value == 0.0 {
-            return true;
-        }
-
-        let relative_error = ((roundtrip - value) / value).abs();
-        relative_error < 0.00001
+        Self::float32_relative_error(value) < 0.00001
     }
 
-    /// Create a Number value, automatically choosing the best type
+    /// Create a Number value, automatically choosing the best type. Floats narrow under
+    /// `EncodePolicy::Compact`; `BiWiEncoder::encode_number` leaves that to its `FloatPolicy`.
     pub fn number(value: f64) -> Self {
-        if value.fract() == 0.0 {
-            // It's an integer
+        Self::number_with_policy(value, EncodePolicy::Compact)
+    }
+
+    /// Create a Number value, narrowing floats only as far as `policy` allows
+    pub fn number_with_policy(value: f64, policy: EncodePolicy) -> Self {
+        let exact = policy == EncodePolicy::Exact;
+        let negative_zero = value == 0.0 && value.is_sign_negative();
+
+        if value.fract() == 0.0 && !(exact && negative_zero) {
+            // It's an integer (2^63 itself does not fit in i64)
             if value >= i32::MIN as f64 && value <= i32::MAX as f64 {
                 BiWiValue::Int32(value as i32)
-            } else if value >= i64::MIN as f64 && value <= i64::MAX as f64 {
+            } else if value >= i64::MIN as f64 && value < i64::MAX as f64 {
                 BiWiValue::Int64(value as i64)
             } else {
                 BiWiValue::Float64(value)
             }
         } else {
             // It's a float
-            if Self::can_use_float32(value) {
+            let narrow = match policy {
+                EncodePolicy::Exact => false,
+                EncodePolicy::Compact => Self::can_use_float32(value),
+                EncodePolicy::LossyTolerance(tolerance) => {
+                    value.is_finite() && Self::float32_relative_error(value) <= tolerance
+                }
+            };
+            if narrow {
                 BiWiValue::Float32(value as f32)
             } else {
                 BiWiValue::Float64(value)
             }
         }
     }
+
+    /// Boolean value, if this is a Boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            BiWiValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Integer value, if this is an Int32 or an Int64 that fits in 32 bits
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            BiWiValue::Int32(n) => Some(*n),
+            BiWiValue::Int64(n) => i32::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    /// Integer value, widening Int32
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            BiWiValue::Int32(n) => Some(i64::from(*n)),
+            BiWiValue::Int64(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Numeric value as f64 (`number()` may have stored it as an integer or Float32)
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            BiWiValue::Int32(n) => Some(f64::from(*n)),
+            BiWiValue::Int64(n) => Some(*n as f64),
+            BiWiValue::Float32(n) => Some(f64::from(*n)),
+            BiWiValue::Float64(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// String value, whether stored as SmallString or String
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            BiWiValue::SmallString(s) => Some(s.as_str()),
+            BiWiValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Raw bytes, if this is Binary (or Bytes)
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            BiWiValue::Binary(bytes) => Some(bytes),
+            #[cfg(feature = "bytes")]
+            BiWiValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Elements, if this is an Array
+    pub fn as_array(&self) -> Option<&[BiWiValue]> {
+        match self {
+            BiWiValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Entries, if this is an Object
+    pub fn as_object(&self) -> Option<&HashMap<String, BiWiValue>> {
+        match self {
+            BiWiValue::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Elements, if this is an Int32Array
+    pub fn as_i32_slice(&self) -> Option<&[i32]> {
+        match self {
+            BiWiValue::Int32Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Elements, if this is an Int64Array
+    pub fn as_i64_slice(&self) -> Option<&[i64]> {
+        match self {
+            BiWiValue::Int64Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Elements, if this is a Float32Array
+    pub fn as_f32_slice(&self) -> Option<&[f32]> {
+        match self {
+            BiWiValue::Float32Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Elements, if this is a Float64Array
+    pub fn as_f64_slice(&self) -> Option<&[f64]> {
+        match self {
+            BiWiValue::Float64Array(items) => Some(items),
+            _ => None,
+        }
+    }
 }
 
 impl From<bool> for BiWiValue {
@@ -156,9 +357,124 @@ impl From<&[u8]> for BiWiValue {
     }
 }
 
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for BiWiValue {
+    fn from(data: bytes::Bytes) -> Self {
+        BiWiValue::Bytes(data)
+    }
+}
+
+impl From<Vec<i32>> for BiWiValue {
+    fn from(items: Vec<i32>) -> Self {
+        BiWiValue::Int32Array(items)
+    }
+}
+
+impl From<Vec<i64>> for BiWiValue {
+    fn from(items: Vec<i64>) -> Self {
+        BiWiValue::Int64Array(items)
+    }
+}
+
+impl From<Vec<f32>> for BiWiValue {
+    fn from(items: Vec<f32>) -> Self {
+        BiWiValue::Float32Array(items)
+    }
+}
+
+impl From<Vec<f64>> for BiWiValue {
+    fn from(items: Vec<f64>) -> Self {
+        BiWiValue::Float64Array(items)
+    }
+}
+
+impl<T: Into<BiWiValue>, const N: usize> From<[T; N]> for BiWiValue {
+    fn from(items: [T; N]) -> Self {
+        BiWiValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<BiWiValue>> From<Option<T>> for BiWiValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(BiWiValue::Null, Into::into)
+    }
+}
+
+impl From<HashMap<String, BiWiValue>> for BiWiValue {
+    fn from(map: HashMap<String, BiWiValue>) -> Self {
+        BiWiValue::Object(map)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Numbers `BiWiEncoder::encode_packed_iter` writes without per-element type markers:
+/// `i32`, `i64`, `f32` and `f64`
+pub trait PackedElement: Copy + sealed::Sealed {
+    #[doc(hidden)]
+    const ELEMENT_TYPE: BiWiType;
+    #[doc(hidden)]
+    fn write_packed(self, encoder: &mut BiWiEncoder);
+}
+
+impl sealed::Sealed for i32 {}
+impl PackedElement for i32 {
+    const ELEMENT_TYPE: BiWiType = BiWiType::Int32;
+    fn write_packed(self, encoder: &mut BiWiEncoder) {
+        encoder.write_varint(((self << 1) ^ (self >> 31)) as u32);
+    }
+}
+
+impl sealed::Sealed for i64 {}
+impl PackedElement for i64 {
+    const ELEMENT_TYPE: BiWiType = BiWiType::Int64;
+    fn write_packed(self, encoder: &mut BiWiEncoder) {
+        encoder.write_varint_u64(((self << 1) ^ (self >> 63)) as u64);
+    }
+}
+
+impl sealed::Sealed for f32 {}
+impl PackedElement for f32 {
+    const ELEMENT_TYPE: BiWiType = BiWiType::Float32;
+    fn write_packed(self, encoder: &mut BiWiEncoder) {
+        let value = encoder.float_policy.shape(f64::from(self)).0;
+        encoder.write_f32(value as f32);
+    }
+}
+
+impl sealed::Sealed for f64 {}
+impl PackedElement for f64 {
+    const ELEMENT_TYPE: BiWiType = BiWiType::Float64;
+    fn write_packed(self, encoder: &mut BiWiEncoder) {
+        encoder.write_f64(encoder.float_policy.shape(self).0);
+    }
+}
+
+/// Sparse layout flag: a null bitmap follows the presence bitmap
+pub(crate) const SPARSE_FLAG_NULLS: u8 = 0x01;
+
+/// Sparse layout flag: the fields follow with their usual headers instead of bitmaps,
+/// for ID ranges too wide for a bitmap to pay off
+pub(crate) const SPARSE_FLAG_FIELDS: u8 = 0x80;
+
+/// Bytes `write_field_header` spends on `field_id`
+fn field_header_len(field_id: u32) -> usize {
+    if field_id > 0 && field_id <= 31 {
+        return 1;
+    }
+    let header = (field_id as u64) << 3 | 7;
+    (64 - header.leading_zeros() as usize).div_ceil(7)
+}
+
 /// BiWi encoder for converting values to binary format
 pub struct BiWiEncoder {
     buffer: Vec<u8>,
+    byte_order: ByteOrder,
+    float_policy: FloatPolicy,
+    /// Interned object keys, when encoding one message of a stream
+    keys: Option<KeyTable>,
 }
 
 impl BiWiEncoder {
@@ -171,6 +487,78 @@ impl BiWiEncoder {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             buffer: Vec::with_capacity(capacity),
+            byte_order: ByteOrder::BigEndian,
+            float_policy: FloatPolicy::default(),
+            keys: None,
+        }
+    }
+
+    /// Set how every float value is written (see `FloatPolicy`)
+    pub fn with_float_policy(mut self, policy: FloatPolicy) -> Self {
+        self.float_policy = policy;
+        self
+    }
+
+    /// Get the policy applied to float values
+    pub fn float_policy(&self) -> FloatPolicy {
+        self.float_policy
+    }
+
+    /// Write object keys through `keys`, sending each one as a string only the
+    /// first time the table sees it. The decoder needs the matching table.
+    pub fn with_key_table(mut self, keys: KeyTable) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Take the key table back, to keep using it for the next message
+    pub fn take_key_table(&mut self) -> Option<KeyTable> {
+        self.keys.take()
+    }
+
+    /// Set the byte order for fixed-width values (the decoder must use the same)
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Get the byte order used for fixed-width values
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buffer.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buffer.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn write_f64(&mut self, value: f64) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buffer.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buffer.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buffer.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buffer.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buffer.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buffer.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        match self.byte_order {
+            ByteOrder::BigEndian => self.buffer.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.buffer.extend_from_slice(&value.to_le_bytes()),
         }
     }
 
@@ -213,25 +601,190 @@ impl BiWiEncoder {
     }
 
     /// Encode a complete field with field header
-    /// Compact format for fields 1-63: [field_id:6 + wire_type:2]
-    /// Extended format for fields 64+: [field_id(varint) + wire_type:3]
+    /// Compact format for fields 1-31: [0 + field_id:5 + wire_type:2]
+    /// Extended format for fields 32+: [field_id(varint) + wire_type:3]
     pub fn encode_field(&mut self, field_id: u32, value: &BiWiValue) {
         let wire_type = match value {
             BiWiValue::Int32(_) | BiWiValue::Int64(_) => 2, // varint
-            BiWiValue::String(_) | BiWiValue::Binary(_) | BiWiValue::Array(_) | BiWiValue::Object(_) => 3,
-            BiWiValue::Float32(_) => 0, // fixed32
-            BiWiValue::Float64(_) => 1, // fixed64
+            BiWiValue::String(_)
+            | BiWiValue::Binary(_)
+            | BiWiValue::Array(_)
+            | BiWiValue::Object(_)
+            | BiWiValue::Int32Array(_)
+            | BiWiValue::Int64Array(_)
+            | BiWiValue::Float32Array(_)
+            | BiWiValue::Float64Array(_) => 3,
+            #[cfg(feature = "bytes")]
+            BiWiValue::Bytes(_) => 3,
+            // fixed32 or fixed64, whichever the float policy writes
+            BiWiValue::Float32(f) => if self.float_policy.narrows(f64::from(*f), true) { 0 } else { 1 },
+            BiWiValue::Float64(f) => if self.float_policy.narrows(*f, false) { 0 } else { 1 },
             _ => 2, // default varint
         };
 
-        if field_id > 0 && field_id <= 63 {
-            // Compact encoding: single byte with field_id (6 bits) + wire_type (2 bits)
+        self.write_field_header(field_id, wire_type);
+        self.encode_value(value);
+    }
+
+    /// Encode a field as a reference to entry `index` of the receiver's field table
+    pub(crate) fn encode_field_ref(&mut self, field_id: u32, index: u32) {
+        self.write_field_header(field_id, 2);
+        self.buffer.push(BiWiType::FieldRef as u8);
+        self.write_varint(index);
+    }
+
+    /// Encode a field whose value also becomes entry `index` of the receiver's field table
+    pub(crate) fn encode_field_insert(&mut self, field_id: u32, index: u32, value: &BiWiValue) {
+        self.write_field_header(field_id, 2);
+        self.buffer.push(BiWiType::FieldInsert as u8);
+        self.write_varint(index);
+        self.encode_value(value);
+    }
+
+    fn write_field_header(&mut self, field_id: u32, wire_type: u8) {
+        if field_id > 0 && field_id <= 31 {
+            // Compact encoding: single byte with field_id (5 bits) + wire_type (2 bits), high bit clear
             self.buffer.push(((field_id as u8) << 2) | (wire_type & 0x3));
         } else {
-            // Standard encoding for field IDs > 63
+            // Standard encoding for field IDs > 31 (first byte always has the high bit set)
             self.write_varint_u64((field_id as u64) << 3 | (wire_type as u64));
         }
-        self.encode_value(value);
+    }
+
+    /// Encode an array field from `len` elements streamed out of `items`, without
+    /// collecting a `Vec<BiWiValue>` first. Every element keeps its type marker;
+    /// numbers pack tighter with `encode_packed_iter`.
+    ///
+    /// Panics if `items` yields a different number of elements than `len`.
+    pub fn encode_array_iter<V: Into<BiWiValue>>(&mut self, field_id: u32, len: usize, items: impl IntoIterator<Item = V>) {
+        self.write_field_header(field_id, 3);
+        self.buffer.push(BiWiType::Array as u8);
+        self.write_varint(len as u32);
+        let mut written = 0;
+        for item in items {
+            assert!(written < len, "array iterator yielded more than {} elements", len);
+            self.encode_value(&item.into());
+            written += 1;
+        }
+        assert_eq!(written, len, "array iterator yielded {} of {} elements", written, len);
+    }
+
+    /// Encode a packed array field from `len` numbers streamed out of `items`. The
+    /// bytes match `encode_field` with the equivalent `Int32Array`, `Float32Array`, ...
+    /// Floats are quantized by the float policy but keep the width of `T`, since the
+    /// header is written before the elements are seen.
+    ///
+    /// Panics if `items` yields a different number of elements than `len`.
+    pub fn encode_packed_iter<T: PackedElement>(&mut self, field_id: u32, len: usize, items: impl IntoIterator<Item = T>) {
+        self.write_field_header(field_id, 3);
+        self.write_packed_header(T::ELEMENT_TYPE, len);
+        let mut written = 0;
+        for item in items {
+            assert!(written < len, "packed iterator yielded more than {} elements", len);
+            item.write_packed(self);
+            written += 1;
+        }
+        assert_eq!(written, len, "packed iterator yielded {} of {} elements", written, len);
+    }
+
+    /// Encode a message envelope; must come before any fields
+    pub fn encode_envelope(&mut self, envelope: &Envelope) {
+        self.buffer.extend_from_slice(&ENVELOPE_MARKER);
+        self.buffer.push(if envelope.timestamp.is_some() { ENVELOPE_HAS_TIMESTAMP } else { 0 });
+        self.write_varint(u32::from(envelope.message_type));
+        self.write_varint_u64(envelope.correlation_id);
+        self.buffer.push(envelope.flags);
+        if let Some(timestamp) = envelope.timestamp {
+            self.write_varint_u64(timestamp);
+        }
+    }
+
+    /// Encode a numeric field: integral values as Int32 or Int64, the rest as floats
+    /// at the width the encoder's `FloatPolicy` picks
+    pub fn encode_number(&mut self, field_id: u32, value: f64) {
+        let value = BiWiValue::number_with_policy(value, EncodePolicy::Exact);
+        self.encode_field(field_id, &value);
+    }
+
+    /// Encode fields in the sparse layout: a presence bitmap and a null bitmap
+    /// over the field ID range, followed by the non-null values with no field headers.
+    /// Layout: [flags][min_field_id(varint)][span(varint)][presence bitmap][null bitmap?][values...]
+    ///
+    /// When the IDs are spread so wide that the bitmap would outweigh the field headers
+    /// it replaces, the fields are written as usual behind `SPARSE_FLAG_FIELDS`:
+    /// [flags][fields...]
+    pub fn encode_sparse(&mut self, fields: &[(u32, &BiWiValue)]) {
+        let mut sorted: Vec<(u32, &BiWiValue)> = fields.to_vec();
+        sorted.sort_by_key(|(id, _)| *id);
+        sorted.dedup_by_key(|(id, _)| *id);
+
+        let (min_id, max_id) = match (sorted.first(), sorted.last()) {
+            (Some((min, _)), Some((max, _))) => (*min, *max),
+            _ => {
+                // Empty message: flags + zero span
+                self.buffer.push(0);
+                self.write_varint(0);
+                self.write_varint(0);
+                return;
+            }
+        };
+
+        let headers: usize = sorted.iter().map(|&(id, _)| field_header_len(id)).sum();
+        let span = (max_id - min_id).checked_add(1).filter(|span| span.div_ceil(8) as usize <= headers);
+        let Some(span) = span else {
+            self.buffer.push(SPARSE_FLAG_FIELDS);
+            for (id, value) in sorted {
+                self.encode_field(id, value);
+            }
+            return;
+        };
+        let bitmap_len = span.div_ceil(8) as usize;
+        let mut presence = vec![0u8; bitmap_len];
+        let mut nulls = vec![0u8; bitmap_len];
+        let mut has_nulls = false;
+
+        for (id, value) in &sorted {
+            let bit = (id - min_id) as usize;
+            presence[bit / 8] |= 1 << (bit % 8);
+            if matches!(value, BiWiValue::Null) {
+                nulls[bit / 8] |= 1 << (bit % 8);
+                has_nulls = true;
+            }
+        }
+
+        self.buffer.push(if has_nulls { SPARSE_FLAG_NULLS } else { 0 });
+        self.write_varint(min_id);
+        self.write_varint(span);
+        self.buffer.extend_from_slice(&presence);
+        if has_nulls {
+            self.buffer.extend_from_slice(&nulls);
+        }
+
+        for (_, value) in sorted {
+            if !matches!(value, BiWiValue::Null) {
+                self.encode_value(value);
+            }
+        }
+    }
+
+    /// Encode a delta patch: the removed field IDs followed by the changed fields.
+    /// Layout: [removed_count(varint)][removed id gaps(varint)...][fields...]
+    pub fn encode_delta(&mut self, removed: &[u32], changed: &[(u32, &BiWiValue)]) {
+        let mut removed = removed.to_vec();
+        removed.sort_unstable();
+        removed.dedup();
+
+        self.write_varint(removed.len() as u32);
+        let mut previous = 0;
+        for id in removed {
+            // Ascending IDs are stored as gaps so dense removals stay one byte each
+            self.write_varint(id - previous);
+            previous = id;
+        }
+
+        for (field_id, value) in changed {
+            self.encode_field(*field_id, value);
+        }
     }
 
     /// Encode a raw value with its type
@@ -250,37 +803,26 @@ impl BiWiEncoder {
             }
             BiWiValue::Int32(n) => {
                 self.buffer.push(BiWiType::Int32 as u8);
-                // Optimize for small integers: use single byte with marker
-                if *n >= -64 && *n <= 63 {
-                    self.buffer.push(((*n as i8) as u8) | 0x80); // Mark as small
-                } else {
-                    // Use zigzag + varint for larger integers
-                    let zigzag = ((n << 1) ^ (n >> 31)) as u32;
-                    self.write_varint(zigzag);
-                }
+                // Zigzag + varint: values in -64..=63 take a single byte
+                let zigzag = ((n << 1) ^ (n >> 31)) as u32;
+                self.write_varint(zigzag);
             }
             BiWiValue::Int64(n) => {
                 self.buffer.push(BiWiType::Int64 as u8);
-                // Optimize for small integers: use single byte
-                if *n >= -64 && *n <= 63 {
-                    self.buffer.push(((*n as i8) as u8) | 0x80); // Mark as small
-                } else {
-                    // Use zigzag + varint for larger integers
-                    let zigzag = ((n << 1) ^ (n >> 63)) as u64;
-                    self.write_varint_u64(zigzag);
-                }
+                // Zigzag + varint: values in -64..=63 take a single byte
+                let zigzag = ((n << 1) ^ (n >> 63)) as u64;
+                self.write_varint_u64(zigzag);
             }
             BiWiValue::Float32(f) => {
-                self.buffer.push(BiWiType::Float32 as u8);
-                self.buffer.extend_from_slice(&f.to_be_bytes());
+                self.encode_float(f64::from(*f), true);
             }
             BiWiValue::Float64(f) => {
-                self.buffer.push(BiWiType::Float64 as u8);
-                self.buffer.extend_from_slice(&f.to_be_bytes());
+                self.encode_float(*f, false);
             }
             BiWiValue::SmallString(s) => {
-                self.buffer.push(BiWiType::String as u8);
-                self.buffer.push(0x80 | (s.len & 0x7F)); // Mark as small string with length
+                // Mark as small string: use high bit of type byte (like packed arrays)
+                self.buffer.push(BiWiType::String as u8 | 0x80);
+                self.buffer.push(s.len);
                 self.buffer.extend_from_slice(s.as_bytes());
             }
             BiWiValue::String(s) => {
@@ -289,12 +831,50 @@ impl BiWiEncoder {
             BiWiValue::Binary(data) => {
                 self.encode_binary(data);
             }
+            #[cfg(feature = "bytes")]
+            BiWiValue::Bytes(data) => {
+                self.encode_binary(data);
+            }
             BiWiValue::Array(items) => {
                 self.encode_array(items);
             }
             BiWiValue::Object(map) => {
                 self.encode_object(map);
             }
+            BiWiValue::Int32Array(items) => self.write_packed_slice(items),
+            BiWiValue::Int64Array(items) => self.write_packed_slice(items),
+            BiWiValue::Float32Array(items) => self.write_packed_floats(items.iter().map(|&f| f64::from(f)), items.len(), true),
+            BiWiValue::Float64Array(items) => self.write_packed_floats(items.iter().copied(), items.len(), false),
+        }
+    }
+
+    /// Write a float at the width the float policy picks
+    fn encode_float(&mut self, value: f64, is_f32: bool) {
+        let narrow = self.float_policy.narrows(value, is_f32);
+        let value = self.float_policy.shape(value).0;
+        if narrow {
+            self.buffer.push(BiWiType::Float32 as u8);
+            self.write_f32(value as f32);
+        } else {
+            self.buffer.push(BiWiType::Float64 as u8);
+            self.write_f64(value);
+        }
+    }
+
+    /// Packed float array; one width for every element, so the array stays packed
+    fn write_packed_floats(&mut self, values: impl Iterator<Item = f64> + Clone, len: usize, is_f32: bool) {
+        let policy = self.float_policy;
+        let narrow = values.clone().all(|value| policy.narrows(value, is_f32));
+        if narrow {
+            self.write_packed_header(BiWiType::Float32, len);
+            for value in values {
+                self.write_f32(policy.shape(value).0 as f32);
+            }
+        } else {
+            self.write_packed_header(BiWiType::Float64, len);
+            for value in values {
+                self.write_f64(policy.shape(value).0);
+            }
         }
     }
 
@@ -367,21 +947,14 @@ impl BiWiEncoder {
         let packed_type = match &items[0] {
             BiWiValue::Int32(_) => BiWiType::Int32,
             BiWiValue::Int64(_) => BiWiType::Int64,
-            BiWiValue::Float32(_) => BiWiType::Float32,
-            BiWiValue::Float64(_) => BiWiType::Float64,
+            BiWiValue::Float32(_) | BiWiValue::Float64(_) => {
+                let is_f32 = matches!(items[0], BiWiValue::Float32(_));
+                return self.write_packed_floats(items.iter().filter_map(BiWiValue::as_f64), items.len(), is_f32);
+            }
             _ => unreachable!(),
         };
 
-        // Mark as packed array: use high bit of type byte
-        self.buffer.push(BiWiType::Array as u8 | 0x80); // High bit = packed
-        self.buffer.push(packed_type as u8);
-
-        let len = items.len() as u32;
-        if len < 128 {
-            self.buffer.push(len as u8);
-        } else {
-            self.write_varint(len);
-        }
+        self.write_packed_header(packed_type, items.len());
 
         // Encode elements without type markers
         for item in items {
@@ -394,20 +967,37 @@ impl BiWiEncoder {
                     let zigzag = ((n << 1) ^ (n >> 63)) as u64;
                     self.write_varint_u64(zigzag);
                 }
-                BiWiValue::Float32(f) => {
-                    self.buffer.extend_from_slice(&f.to_be_bytes());
-                }
-                BiWiValue::Float64(f) => {
-                    self.buffer.extend_from_slice(&f.to_be_bytes());
-                }
                 _ => unreachable!(),
             }
         }
     }
 
+    fn write_packed_slice<T: PackedElement>(&mut self, items: &[T]) {
+        self.write_packed_header(T::ELEMENT_TYPE, items.len());
+        for &item in items {
+            item.write_packed(self);
+        }
+    }
+
+    /// Packed array type byte, element type and count
+    fn write_packed_header(&mut self, element_type: BiWiType, len: usize) {
+        // Mark as packed array: use high bit of type byte
+        self.buffer.push(BiWiType::Array as u8 | 0x80); // High bit = packed
+        self.buffer.push(element_type as u8);
+
+        let len = len as u32;
+        if len < 128 {
+            self.buffer.push(len as u8);
+        } else {
+            self.write_varint(len);
+        }
+    }
+
     /// Encode an object with key count optimization
     fn encode_object(&mut self, map: &HashMap<String, BiWiValue>) {
-        self.buffer.push(BiWiType::Object as u8);
+        let interned = self.keys.is_some();
+        // High bit = keys go through the key table
+        self.buffer.push(BiWiType::Object as u8 | if interned { 0x80 } else { 0 });
         let key_count = map.len() as u32;
 
         // Optimize for common case of small objects (< 128 keys)
@@ -418,45 +1008,96 @@ impl BiWiEncoder {
         }
 
         for (key, value) in map {
-            // Encode key as string (length + bytes)
-            let key_bytes = key.as_bytes();
-            let key_length = key_bytes.len() as u32;
-
-            // Optimize for common case of short keys (< 128 bytes)
-            if key_length < 128 {
-                self.buffer.push(key_length as u8);
+            if interned {
+                self.write_interned_key(key);
             } else {
-                self.write_varint(key_length);
+                self.write_key(key);
             }
 
-            self.buffer.extend_from_slice(key_bytes);
-
             // Encode value
             self.encode_value(value);
         }
     }
 
+    /// Encode a key as string (length + bytes)
+    fn write_key(&mut self, key: &str) {
+        let key_bytes = key.as_bytes();
+        let key_length = key_bytes.len() as u32;
+
+        // Optimize for common case of short keys (< 128 bytes)
+        if key_length < 128 {
+            self.buffer.push(key_length as u8);
+        } else {
+            self.write_varint(key_length);
+        }
+
+        self.buffer.extend_from_slice(key_bytes);
+    }
+
+    /// Encode a key as its table ID, or as a string tagged with whether it was added
+    fn write_interned_key(&mut self, key: &str) {
+        let Some(keys) = self.keys.as_mut() else {
+            return self.write_key(key);
+        };
+        if let Some(id) = keys.id(key) {
+            return self.write_varint(KEY_REF_BASE + id);
+        }
+        let tag = if keys.insert(key).is_some() { KEY_NEW } else { KEY_LITERAL };
+        self.write_varint(tag);
+        self.write_key(key);
+    }
+
     /// Encode a streaming chunk start
-    pub fn encode_chunk_start(&mut self, field_id: u16, total_size: u32) {
+    pub fn encode_chunk_start(&mut self, field_id: u16, total_size: u64) {
         self.buffer.push(BiWiType::ChunkStart as u8);
-        self.buffer.extend_from_slice(&field_id.to_be_bytes());
-        self.buffer.extend_from_slice(&total_size.to_be_bytes());
+        self.write_u16(field_id);
+        self.write_u64(total_size);
+    }
+
+    /// Encode a resumable chunk start: like `encode_chunk_start` plus a transfer ID
+    pub fn encode_chunk_transfer_start(&mut self, transfer_id: u32, field_id: u16, total_size: u64) {
+        self.buffer.push(BiWiType::ChunkTransferStart as u8);
+        self.write_u32(transfer_id);
+        self.write_u16(field_id);
+        self.write_u64(total_size);
     }
 
-    /// Encode a streaming chunk data
+    /// Encode a resume request for a transfer, starting at `next_index`
+    pub fn encode_chunk_resume(&mut self, transfer_id: u32, next_index: u32) {
+        self.buffer.push(BiWiType::ChunkResume as u8);
+        self.write_u32(transfer_id);
+        self.write_u32(next_index);
+    }
+
+    /// Encode a streaming chunk data. `chunk_index` wraps past `u16::MAX`.
     pub fn encode_chunk_data(&mut self, chunk_index: u16, data: &[u8]) {
         self.buffer.push(BiWiType::ChunkData as u8);
-        self.buffer.extend_from_slice(&chunk_index.to_be_bytes());
+        self.write_u16(chunk_index);
         let data_length = data.len() as u16;
-        self.buffer.extend_from_slice(&data_length.to_be_bytes());
+        self.write_u16(data_length);
         self.buffer.extend_from_slice(data);
     }
 
+    /// Encode a streaming chunk data followed by a CRC-32 of `data`
+    pub fn encode_chunk_data_checked(&mut self, chunk_index: u16, data: &[u8]) {
+        self.buffer.push(BiWiType::ChunkDataChecked as u8);
+        self.write_u16(chunk_index);
+        self.write_u16(data.len() as u16);
+        self.buffer.extend_from_slice(data);
+        self.write_u32(crc32fast::hash(data));
+    }
+
     /// Encode a streaming chunk end
     pub fn encode_chunk_end(&mut self) {
         self.buffer.push(BiWiType::ChunkEnd as u8);
     }
 
+    /// Encode a streaming chunk end carrying a SHA-256 of the whole payload
+    pub fn encode_chunk_end_hashed(&mut self, hash: &[u8; 32]) {
+        self.buffer.push(BiWiType::ChunkEndHashed as u8);
+        self.buffer.extend_from_slice(hash);
+    }
+
     /// Get the final buffer (consumes the encoder)
     pub fn to_buffer(self) -> Vec<u8> {
         self.buffer
@@ -0,0 +1,97 @@
+//! BiWi Message Envelope
+//! Optional routing header written before a message's field section: a message
+//! type, a correlation/request ID, application flags and an optional timestamp.
+//!
+//! Layout: [0x80 0x00][presence][message_type(varint)][correlation_id(varint64)][flags][timestamp(varint64)?]
+//!
+//! `0x80 0x00` is an overlong varint that no encoder emits as a field header,
+//! so enveloped and plain messages can be told apart from the first two bytes.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes that introduce an envelope
+pub const ENVELOPE_MARKER: [u8; 2] = [0x80, 0x00];
+
+/// Presence bit: a timestamp follows the flags byte
+pub(crate) const ENVELOPE_HAS_TIMESTAMP: u8 = 0x01;
+
+/// Routing and correlation metadata carried ahead of the fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Envelope {
+    /// Application-defined message type, used for dispatch
+    pub message_type: u16,
+    /// Request ID echoed by responses (0 when unused)
+    pub correlation_id: u64,
+    /// Application-defined flags
+    pub flags: u8,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<u64>,
+}
+
+impl Envelope {
+    /// Create an envelope for the given message type
+    pub fn new(message_type: u16) -> Self {
+        Self {
+            message_type,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: u64) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp_ms: u64) -> Self {
+        self.timestamp = Some(timestamp_ms);
+        self
+    }
+
+    /// Stamp the envelope with the current wall-clock time
+    pub fn with_timestamp_now(self) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.with_timestamp(now)
+    }
+
+    /// Check whether all bits of `flag` are set
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BiWiDecoder, BiWiEncoder};
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelopes = [
+            Envelope::new(0),
+            Envelope::new(7).with_correlation_id(u64::MAX).with_flags(0x81),
+            Envelope::new(u16::MAX).with_timestamp(1_700_000_000_000),
+        ];
+        for envelope in envelopes {
+            let mut encoder = BiWiEncoder::new();
+            encoder.encode_envelope(&envelope);
+            let buffer = encoder.to_buffer();
+            assert_eq!(buffer[..2], ENVELOPE_MARKER);
+
+            let mut decoder = BiWiDecoder::new(&buffer);
+            assert_eq!(decoder.decode_envelope().unwrap(), Some(envelope));
+            assert!(!decoder.has_more());
+        }
+
+        // No marker: nothing is consumed
+        let mut decoder = BiWiDecoder::new(&[0x04, 0x00]);
+        assert_eq!(decoder.decode_envelope().unwrap(), None);
+        assert_eq!(decoder.offset(), 0);
+    }
+}
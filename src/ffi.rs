@@ -0,0 +1,515 @@
+//! BiWi C API
+//! A stable `extern "C"` interface to the codec, for engines written in C or C++.
+//! Messages are built with a `BiwiEncoder` handle (`biwi_encoder_new`, one
+//! `biwi_encode_*` call per field, `biwi_encoder_finish`) and read with a
+//! `BiwiDecoder` handle that yields one `BiwiField` per `biwi_decoder_next`. The
+//! header is `include/biwi.h`, generated by cbindgen from this file.
+//!
+//! Conventions: every function that can fail returns a `BiwiStatus`. Handles come
+//! from `*_new` and must be released with the matching `*_free`; a handle is live
+//! from its `*_new` until then, and is never used again after. Pointers passed in
+//! must be valid for the lengths given. Pointers handed out (encoded bytes, field
+//! strings) point into the handle and stay valid until the next call on it.
+//! Strings are UTF-8 with an explicit length; the ones handed out are also
+//! NUL-terminated. Arrays and objects cross as JSON text.
+
+use crate::decoder::BiWiDecoder;
+use crate::encoder::BiWiValue;
+use crate::message::BiWiMessage;
+use serde_json::Value as Json;
+use std::collections::HashMap;
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+
+/// Result of a C API call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiwiStatus {
+    Ok = 0,
+    /// `biwi_decoder_next` has no more fields
+    End = 1,
+    /// A required pointer was NULL
+    NullPointer = 2,
+    /// A string was not valid UTF-8
+    InvalidUtf8 = 3,
+    /// A JSON value did not parse
+    InvalidJson = 4,
+    /// The input is not a valid BiWi message
+    Decode = 5,
+}
+
+/// Kind of value a `BiwiField` holds, and so which of its members is set
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiwiKind {
+    Null = 0,
+    /// In `boolean`
+    Boolean = 1,
+    /// In `integer`
+    Integer = 2,
+    /// In `number`
+    Float = 3,
+    /// UTF-8 in `data`/`len`
+    String = 4,
+    /// Bytes in `data`/`len`
+    Binary = 5,
+    /// JSON text in `data`/`len`
+    Array = 6,
+    /// JSON text in `data`/`len`
+    Object = 7,
+}
+
+/// One decoded field
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BiwiField {
+    pub field_id: u32,
+    pub kind: BiwiKind,
+    pub boolean: bool,
+    pub integer: i64,
+    pub number: f64,
+    /// Points into the decoder; valid until its next call
+    pub data: *const u8,
+    pub len: usize,
+}
+
+/// Builds one message at a time
+pub struct BiwiEncoder {
+    message: BiWiMessage,
+    bytes: Vec<u8>,
+}
+
+/// Reads the fields of one message in order
+pub struct BiwiDecoder {
+    buffer: Vec<u8>,
+    offset: usize,
+    /// Set once a field fails to decode; later calls report it again
+    failed: bool,
+    /// Backing store for the last field's `data`
+    data: Vec<u8>,
+}
+
+/// The library version, NUL-terminated
+#[no_mangle]
+pub extern "C" fn biwi_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// A new encoder, to be released with `biwi_encoder_free`
+#[no_mangle]
+pub extern "C" fn biwi_encoder_new() -> *mut BiwiEncoder {
+    Box::into_raw(Box::new(BiwiEncoder { message: BiWiMessage::new(), bytes: Vec::new() }))
+}
+
+/// Release an encoder and the bytes it handed out
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder. Neither it nor bytes from its
+/// `biwi_encoder_finish` may be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encoder_free(encoder: *mut BiwiEncoder) {
+    if !encoder.is_null() {
+        drop(Box::from_raw(encoder));
+    }
+}
+
+/// Remove every field, to build the next message
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encoder_clear(encoder: *mut BiwiEncoder) -> BiwiStatus {
+    let Some(encoder) = encoder.as_mut() else {
+        return BiwiStatus::NullPointer;
+    };
+    encoder.message = BiWiMessage::new();
+    BiwiStatus::Ok
+}
+
+unsafe fn set(encoder: *mut BiwiEncoder, field_id: u32, value: BiWiValue) -> BiwiStatus {
+    let Some(encoder) = encoder.as_mut() else {
+        return BiwiStatus::NullPointer;
+    };
+    encoder.message.set_field(field_id, value);
+    BiwiStatus::Ok
+}
+
+/// `len` bytes at `data`, which may only be NULL when `len` is 0
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Set field `field_id` to null
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_null(encoder: *mut BiwiEncoder, field_id: u32) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Null)
+}
+
+/// Set field `field_id` to a boolean
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_bool(encoder: *mut BiwiEncoder, field_id: u32, value: bool) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Boolean(value))
+}
+
+/// Set field `field_id` to a 32-bit integer
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_i32(encoder: *mut BiwiEncoder, field_id: u32, value: i32) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Int32(value))
+}
+
+/// Set field `field_id` to a 64-bit integer
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_i64(encoder: *mut BiwiEncoder, field_id: u32, value: i64) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Int64(value))
+}
+
+/// Set field `field_id` to a float
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_f32(encoder: *mut BiwiEncoder, field_id: u32, value: f32) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Float32(value))
+}
+
+/// Set field `field_id` to a double
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_f64(encoder: *mut BiwiEncoder, field_id: u32, value: f64) -> BiwiStatus {
+    set(encoder, field_id, BiWiValue::Float64(value))
+}
+
+/// A UTF-8 string of `len` bytes (no terminator needed)
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+/// bytes. The string is copied, so `data` only has to last for the call.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_string(
+    encoder: *mut BiwiEncoder,
+    field_id: u32,
+    data: *const u8,
+    len: usize,
+) -> BiwiStatus {
+    let Some(data) = bytes(data, len) else {
+        return BiwiStatus::NullPointer;
+    };
+    match std::str::from_utf8(data) {
+        Ok(text) => set(encoder, field_id, BiWiValue::from(text)),
+        Err(_) => BiwiStatus::InvalidUtf8,
+    }
+}
+
+/// `len` bytes of binary data
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+/// bytes. The bytes are copied, so `data` only has to last for the call.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_binary(
+    encoder: *mut BiwiEncoder,
+    field_id: u32,
+    data: *const u8,
+    len: usize,
+) -> BiwiStatus {
+    match bytes(data, len) {
+        Some(data) => set(encoder, field_id, BiWiValue::Binary(data.to_vec())),
+        None => BiwiStatus::NullPointer,
+    }
+}
+
+/// Any value given as JSON text: arrays and objects nest, integers become 64-bit
+/// integers and other numbers doubles
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder, and `data` NULL or readable for `len`
+/// bytes, which only have to last for the call.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encode_json(
+    encoder: *mut BiwiEncoder,
+    field_id: u32,
+    data: *const u8,
+    len: usize,
+) -> BiwiStatus {
+    let Some(data) = bytes(data, len) else {
+        return BiwiStatus::NullPointer;
+    };
+    match serde_json::from_slice::<Json>(data) {
+        Ok(json) => set(encoder, field_id, from_json(&json)),
+        Err(_) => BiwiStatus::InvalidJson,
+    }
+}
+
+/// Encode the fields set so far, pointing `out`/`out_len` at the bytes, which the
+/// encoder owns
+///
+/// # Safety
+/// `encoder` must be NULL or a live encoder, and `out` and `out_len` NULL or
+/// writable. The bytes stay valid until the next call on `encoder` or its
+/// `biwi_encoder_free`, and must not be written to.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_encoder_finish(
+    encoder: *mut BiwiEncoder,
+    out: *mut *const u8,
+    out_len: *mut usize,
+) -> BiwiStatus {
+    let (Some(encoder), false, false) = (encoder.as_mut(), out.is_null(), out_len.is_null()) else {
+        return BiwiStatus::NullPointer;
+    };
+    encoder.bytes = encoder.message.to_vec();
+    *out = encoder.bytes.as_ptr();
+    *out_len = encoder.bytes.len();
+    BiwiStatus::Ok
+}
+
+/// A decoder over a copy of the `len` bytes at `data`; NULL if `data` is NULL. Release
+/// it with `biwi_decoder_free`.
+///
+/// # Safety
+/// `data` must be NULL or readable for `len` bytes. They are copied, so the caller
+/// may release them as soon as this returns.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_decoder_new(data: *const u8, len: usize) -> *mut BiwiDecoder {
+    let Some(data) = bytes(data, len) else {
+        return ptr::null_mut();
+    };
+    let mut decoder = BiwiDecoder { buffer: data.to_vec(), offset: 0, failed: false, data: Vec::new() };
+    // Fields follow the envelope, if there is one
+    let mut envelope = BiWiDecoder::new(&decoder.buffer);
+    match envelope.decode_envelope() {
+        Ok(_) => decoder.offset = envelope.offset(),
+        Err(_) => decoder.failed = true,
+    }
+    Box::into_raw(Box::new(decoder))
+}
+
+/// Release a decoder and the field data it handed out
+///
+/// # Safety
+/// `decoder` must be NULL or a live decoder. Neither it nor pointers in a field from
+/// its `biwi_decoder_next` may be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_decoder_free(decoder: *mut BiwiDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Decode the next field into `field`; `End` once there are none left
+///
+/// # Safety
+/// `decoder` must be NULL or a live decoder, and `field` NULL or writable. Pointers
+/// written into `field` stay valid until the next call on `decoder` or its
+/// `biwi_decoder_free`.
+#[no_mangle]
+pub unsafe extern "C" fn biwi_decoder_next(decoder: *mut BiwiDecoder, field: *mut BiwiField) -> BiwiStatus {
+    let (Some(decoder), Some(field)) = (decoder.as_mut(), field.as_mut()) else {
+        return BiwiStatus::NullPointer;
+    };
+    if decoder.failed {
+        return BiwiStatus::Decode;
+    }
+    let mut reader = BiWiDecoder::new(&decoder.buffer[decoder.offset..]);
+    if !reader.has_more() {
+        return BiwiStatus::End;
+    }
+    let decoded = match reader.decode_field() {
+        Ok(decoded) => decoded,
+        Err(_) => {
+            decoder.failed = true;
+            return BiwiStatus::Decode;
+        }
+    };
+    decoder.offset += reader.offset();
+
+    *field = BiwiField {
+        field_id: decoded.field_id,
+        kind: BiwiKind::Null,
+        boolean: false,
+        integer: 0,
+        number: 0.0,
+        data: ptr::null(),
+        len: 0,
+    };
+    let data = match decoded.value {
+        BiWiValue::Null => None,
+        BiWiValue::Boolean(value) => {
+            field.kind = BiwiKind::Boolean;
+            field.boolean = value;
+            None
+        }
+        BiWiValue::Int32(value) => {
+            field.kind = BiwiKind::Integer;
+            field.integer = value.into();
+            None
+        }
+        BiWiValue::Int64(value) => {
+            field.kind = BiwiKind::Integer;
+            field.integer = value;
+            None
+        }
+        BiWiValue::Float32(value) => {
+            field.kind = BiwiKind::Float;
+            field.number = value.into();
+            None
+        }
+        BiWiValue::Float64(value) => {
+            field.kind = BiwiKind::Float;
+            field.number = value;
+            None
+        }
+        BiWiValue::SmallString(value) => {
+            field.kind = BiwiKind::String;
+            Some(value.as_str().as_bytes().to_vec())
+        }
+        BiWiValue::String(value) => {
+            field.kind = BiwiKind::String;
+            Some(value.into_bytes())
+        }
+        BiWiValue::Binary(value) => {
+            field.kind = BiwiKind::Binary;
+            Some(value)
+        }
+        #[cfg(feature = "bytes")]
+        BiWiValue::Bytes(value) => {
+            field.kind = BiwiKind::Binary;
+            Some(value.to_vec())
+        }
+        value @ (BiWiValue::Array(_)
+        | BiWiValue::Int32Array(_)
+        | BiWiValue::Int64Array(_)
+        | BiWiValue::Float32Array(_)
+        | BiWiValue::Float64Array(_)) => {
+            field.kind = BiwiKind::Array;
+            Some(to_json(&value).to_string().into_bytes())
+        }
+        value @ BiWiValue::Object(_) => {
+            field.kind = BiwiKind::Object;
+            Some(to_json(&value).to_string().into_bytes())
+        }
+    };
+    if let Some(mut data) = data {
+        field.len = data.len();
+        // Terminated so strings can be used as C strings
+        data.push(0);
+        decoder.data = data;
+        field.data = decoder.data.as_ptr();
+    }
+    BiwiStatus::Ok
+}
+
+fn from_json(json: &Json) -> BiWiValue {
+    match json {
+        Json::Null => BiWiValue::Null,
+        Json::Bool(value) => BiWiValue::Boolean(*value),
+        Json::Number(number) => match number.as_i64() {
+            Some(value) => BiWiValue::Int64(value),
+            None => BiWiValue::Float64(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        Json::String(value) => BiWiValue::from(value.as_str()),
+        Json::Array(items) => BiWiValue::Array(items.iter().map(from_json).collect()),
+        Json::Object(entries) => {
+            BiWiValue::Object(entries.iter().map(|(key, value)| (key.clone(), from_json(value))).collect::<HashMap<_, _>>())
+        }
+    }
+}
+
+/// Plain JSON for a value; binary becomes an array of byte values
+fn to_json(value: &BiWiValue) -> Json {
+    match value {
+        BiWiValue::Null => Json::Null,
+        BiWiValue::Boolean(value) => Json::Bool(*value),
+        BiWiValue::Int32(value) => Json::from(*value),
+        BiWiValue::Int64(value) => Json::from(*value),
+        BiWiValue::Float32(value) => Json::from(*value),
+        BiWiValue::Float64(value) => Json::from(*value),
+        BiWiValue::SmallString(value) => Json::from(value.as_str()),
+        BiWiValue::String(value) => Json::from(value.as_str()),
+        BiWiValue::Binary(value) => Json::from(value.clone()),
+        #[cfg(feature = "bytes")]
+        BiWiValue::Bytes(value) => Json::from(value.to_vec()),
+        BiWiValue::Array(items) => Json::Array(items.iter().map(to_json).collect()),
+        BiWiValue::Int32Array(items) => Json::from(items.clone()),
+        BiWiValue::Int64Array(items) => Json::from(items.clone()),
+        BiWiValue::Float32Array(items) => Json::from(items.clone()),
+        BiWiValue::Float64Array(items) => Json::from(items.clone()),
+        BiWiValue::Object(entries) => Json::Object(entries.iter().map(|(key, value)| (key.clone(), to_json(value))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_committed_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/biwi.h"));
+        assert!(
+            include_str!("../include/biwi.h") == generated,
+            "include/biwi.h is out of date; run `BIWI_REGENERATE_HEADER=1 cargo build --features ffi`"
+        );
+    }
+
+    #[test]
+    fn test_c_api_roundtrip() {
+        unsafe {
+            let encoder = biwi_encoder_new();
+            assert_eq!(biwi_encode_i32(encoder, 1, -7), BiwiStatus::Ok);
+            assert_eq!(biwi_encode_string(encoder, 2, "player".as_ptr(), 6), BiwiStatus::Ok);
+            assert_eq!(biwi_encode_f64(encoder, 3, 1.5), BiwiStatus::Ok);
+            let json = br#"{"pos":[1,2.5]}"#;
+            assert_eq!(biwi_encode_json(encoder, 4, json.as_ptr(), json.len()), BiwiStatus::Ok);
+            assert_eq!(biwi_encode_string(encoder, 5, [0xFF].as_ptr(), 1), BiwiStatus::InvalidUtf8);
+            assert_eq!(biwi_encode_binary(encoder, 5, ptr::null(), 3), BiwiStatus::NullPointer);
+
+            let (mut out, mut out_len) = (ptr::null(), 0);
+            assert_eq!(biwi_encoder_finish(encoder, &mut out, &mut out_len), BiwiStatus::Ok);
+            let encoded = slice::from_raw_parts(out, out_len).to_vec();
+            assert_eq!(BiWiMessage::from_buffer(&encoded).unwrap().get_str(2), Some("player"));
+            biwi_encoder_free(encoder);
+
+            let decoder = biwi_decoder_new(encoded.as_ptr(), encoded.len());
+            let mut field = std::mem::zeroed::<BiwiField>();
+            assert_eq!(biwi_decoder_next(decoder, &mut field), BiwiStatus::Ok);
+            assert_eq!((field.field_id, field.kind, field.integer), (1, BiwiKind::Integer, -7));
+            assert_eq!(biwi_decoder_next(decoder, &mut field), BiwiStatus::Ok);
+            assert_eq!(CStr::from_ptr(field.data as *const c_char).to_str(), Ok("player"));
+            assert_eq!(biwi_decoder_next(decoder, &mut field), BiwiStatus::Ok);
+            assert_eq!(field.number, 1.5);
+            assert_eq!(biwi_decoder_next(decoder, &mut field), BiwiStatus::Ok);
+            assert_eq!(field.kind, BiwiKind::Object);
+            assert_eq!(slice::from_raw_parts(field.data, field.len), json);
+            assert_eq!(biwi_decoder_next(decoder, &mut field), BiwiStatus::End);
+            biwi_decoder_free(decoder);
+
+            // The last field is cut short
+            let decoder = biwi_decoder_new(encoded.as_ptr(), encoded.len() - 1);
+            let mut status = BiwiStatus::Ok;
+            while status == BiwiStatus::Ok {
+                status = biwi_decoder_next(decoder, &mut field);
+            }
+            assert_eq!(status, BiwiStatus::Decode);
+            biwi_decoder_free(decoder);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! BiWi Gateway
+//! Bridges stream front-ends to a UDP game server, so players who can't speak UDP
+//! (browsers, restrictive networks) share a server with native clients. Each TCP
+//! connection (length-prefixed frames, as in `tcp`) or, with the `websocket` feature,
+//! WebSocket connection (one binary message per BiWi message) gets its own session
+//! with the backend. The server keys connections by session ID, so it sees every
+//! front-end player as a separate client, exactly like a native one.
+//!
+//! Each front-end connection gets a thread per direction, each blocked reading its
+//! own side, so neither waits on the other. Both stop when either side goes away,
+//! and `shutdown` (or dropping the gateway) waits for them.
+
+use crate::client::BiWiUdpClient;
+use crate::message::BiWiMessage;
+use crate::tcp::{write_frame, MAX_FRAME_SIZE};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Longest a connection thread stays blocked on its side before checking whether the
+/// gateway or the other direction has stopped
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The kind of connection a player reached the gateway over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontEndKind {
+    Tcp,
+    #[cfg(feature = "websocket")]
+    WebSocket,
+}
+
+/// One front-end connection and the backend session standing in for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewaySession {
+    pub peer: SocketAddr,
+    pub kind: FrontEndKind,
+    /// The backend session ID; the server's `ConnectionId` for this player
+    pub session_id: u64,
+}
+
+type Sessions = Arc<Mutex<HashMap<SocketAddr, GatewaySession>>>;
+
+/// Relays front-end connections to a UDP backend
+pub struct Gateway {
+    backend: String,
+    psk: Option<Vec<u8>>,
+    running: Arc<Mutex<bool>>,
+    sessions: Sessions,
+    listeners: Vec<JoinHandle<()>>,
+    /// Connection threads, joined on shutdown; finished ones are pruned as more start
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Gateway {
+    /// A gateway to the UDP server at `backend`; add front-ends with `listen_tcp`
+    /// (and `listen_websocket`)
+    pub fn new(backend: &str) -> Self {
+        Self {
+            backend: backend.to_string(),
+            psk: None,
+            running: Arc::new(Mutex::new(true)),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            listeners: Vec::new(),
+            connections: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Encrypt backend sessions with a pre-shared key (see `connect_with_psk`)
+    pub fn with_psk(mut self, psk: &[u8]) -> Self {
+        self.psk = Some(psk.to_vec());
+        self
+    }
+
+    /// Accept length-prefixed TCP connections on `addr`; returns the bound address
+    pub fn listen_tcp(&mut self, addr: &str) -> io::Result<SocketAddr> {
+        self.listen(addr, FrontEndKind::Tcp)
+    }
+
+    /// Accept WebSocket connections on `addr`; returns the bound address
+    #[cfg(feature = "websocket")]
+    pub fn listen_websocket(&mut self, addr: &str) -> io::Result<SocketAddr> {
+        self.listen(addr, FrontEndKind::WebSocket)
+    }
+
+    /// Current front-end connections with a backend session
+    pub fn sessions(&self) -> Vec<GatewaySession> {
+        self.sessions.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Stop accepting, close every session and wait for their threads to finish
+    pub fn shutdown(&mut self) {
+        *self.running.lock().unwrap() = false;
+        for listener in self.listeners.drain(..) {
+            let _ = listener.join();
+        }
+        // The listeners are gone, so no more connections can start
+        for connection in mem::take(&mut *self.connections.lock().unwrap()) {
+            let _ = connection.join();
+        }
+    }
+
+    fn listen(&mut self, addr: &str, kind: FrontEndKind) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let link = Link {
+            backend: self.backend.clone(),
+            psk: self.psk.clone(),
+            running: Arc::clone(&self.running),
+            sessions: Arc::clone(&self.sessions),
+        };
+        let connections = Arc::clone(&self.connections);
+        self.listeners.push(thread::spawn(move || {
+            while *link.running.lock().unwrap() {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        let link = link.clone();
+                        let connection = thread::spawn(move || {
+                            if let Err(error) = link.relay(stream, peer, kind) {
+                                event!(DEBUG, %peer, %error, "gateway connection closed");
+                            }
+                            link.sessions.lock().unwrap().remove(&peer);
+                        });
+                        let mut connections = connections.lock().unwrap();
+                        connections.retain(|connection| !connection.is_finished());
+                        connections.push(connection);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }));
+        Ok(local_addr)
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// What a connection thread needs from its gateway
+#[derive(Clone)]
+struct Link {
+    backend: String,
+    psk: Option<Vec<u8>>,
+    running: Arc<Mutex<bool>>,
+    sessions: Sessions,
+}
+
+impl Link {
+    /// Open a backend session for `stream` and relay until either side closes
+    fn relay(&self, stream: TcpStream, peer: SocketAddr, kind: FrontEndKind) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        let (mut front_end, mut replies) = split(stream, kind)?;
+
+        let mut client = match &self.psk {
+            Some(psk) => BiWiUdpClient::connect_with_psk(&self.backend, psk)?,
+            None => BiWiUdpClient::connect(&self.backend)?,
+        };
+        let session_id = client.session_id();
+        self.sessions.lock().unwrap().insert(peer, GatewaySession { peer, kind, session_id });
+
+        let done = AtomicBool::new(false);
+        let result = thread::scope(|scope| {
+            let backend = scope.spawn(|| {
+                let result = self.pump_replies(&client, &mut replies, &done);
+                done.store(true, Ordering::Relaxed);
+                // Also wakes the other thread from its read
+                replies.close();
+                result
+            });
+            let sent = self.pump_messages(&mut front_end, &client, &done);
+            done.store(true, Ordering::Relaxed);
+            let replied = backend.join().unwrap_or_else(|_| Err(io::Error::other("Relay thread panicked")));
+            // The player's side ending is only the cause if the backend's didn't first
+            replied.and(sent)
+        });
+        client.disconnect();
+        result
+    }
+
+    /// Player to backend
+    fn pump_messages(&self, front_end: &mut FrontEndReader, client: &BiWiUdpClient, done: &AtomicBool) -> io::Result<()> {
+        while *self.running.lock().unwrap() && !done.load(Ordering::Relaxed) {
+            if let Some(bytes) = front_end.read()? {
+                // A stream can't resync after a bad message
+                let message = BiWiMessage::from_buffer(&bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                client.send(&message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Backend to player
+    fn pump_replies(&self, client: &BiWiUdpClient, replies: &mut FrontEndWriter, done: &AtomicBool) -> io::Result<()> {
+        while *self.running.lock().unwrap() && !done.load(Ordering::Relaxed) {
+            match client.recv_timeout(READ_TIMEOUT) {
+                Ok(message) => replies.send(&message.to_vec())?,
+                // Nothing yet, or a message that didn't decode and is skipped
+                Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::InvalidData) => {}
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Backend session ended"));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a front-end connection into the halves its two threads use, reads timing
+/// out after `READ_TIMEOUT`
+fn split(stream: TcpStream, kind: FrontEndKind) -> io::Result<(FrontEndReader, FrontEndWriter)> {
+    match kind {
+        FrontEndKind::Tcp => {
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            let writer = stream.try_clone()?;
+            Ok((FrontEndReader::Tcp(TcpFrontEnd { stream, buf: Vec::new() }), FrontEndWriter::Tcp(writer)))
+        }
+        #[cfg(feature = "websocket")]
+        FrontEndKind::WebSocket => {
+            let turn = Arc::new(Mutex::new(()));
+            let shared = |stream| SharedStream { stream, turn: Arc::clone(&turn) };
+            let writer = shared(stream.try_clone()?);
+            let reader = tungstenite::accept(shared(stream))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            // Only after the handshake, which a timeout would cut short
+            reader.get_ref().stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            let writer = tungstenite::WebSocket::from_raw_socket(writer, tungstenite::protocol::Role::Server, None);
+            Ok((FrontEndReader::WebSocket(Box::new(reader)), FrontEndWriter::WebSocket(Box::new(writer))))
+        }
+    }
+}
+
+/// The half of a front-end connection messages from the player are read from
+enum FrontEndReader {
+    Tcp(TcpFrontEnd),
+    #[cfg(feature = "websocket")]
+    WebSocket(Box<tungstenite::WebSocket<SharedStream>>),
+}
+
+impl FrontEndReader {
+    /// The next message from the player, waiting at most `READ_TIMEOUT`
+    fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            FrontEndReader::Tcp(tcp) => tcp.read(),
+            #[cfg(feature = "websocket")]
+            FrontEndReader::WebSocket(socket) => match socket.read() {
+                Ok(tungstenite::Message::Binary(bytes)) => Ok(Some(bytes)),
+                Ok(tungstenite::Message::Close(_)) => Err(io::ErrorKind::ConnectionAborted.into()),
+                // Text is not BiWi; pings are answered by tungstenite
+                Ok(_) => Ok(None),
+                Err(tungstenite::Error::Io(e)) if is_timeout(&e) => Ok(None),
+                Err(tungstenite::Error::Io(e)) => Err(e),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            },
+        }
+    }
+}
+
+/// The half of a front-end connection messages to the player are written to
+enum FrontEndWriter {
+    Tcp(TcpStream),
+    #[cfg(feature = "websocket")]
+    WebSocket(Box<tungstenite::WebSocket<SharedStream>>),
+}
+
+impl FrontEndWriter {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            FrontEndWriter::Tcp(stream) => write_frame(stream, bytes),
+            #[cfg(feature = "websocket")]
+            FrontEndWriter::WebSocket(socket) => match socket.send(tungstenite::Message::Binary(bytes.to_vec())) {
+                Ok(()) => Ok(()),
+                Err(tungstenite::Error::Io(e)) => Err(e),
+                Err(e) => Err(io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())),
+            },
+        }
+    }
+
+    /// Close the connection, for both halves
+    fn close(&mut self) {
+        match self {
+            FrontEndWriter::Tcp(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            #[cfg(feature = "websocket")]
+            FrontEndWriter::WebSocket(socket) => {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                let _ = socket.get_ref().stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+/// One half's handle on a WebSocket's TCP stream. Each write goes out whole and in
+/// turn with the other half's, so a pong the reading half answers with can't land in
+/// the middle of a message the writing half is sending.
+#[cfg(feature = "websocket")]
+struct SharedStream {
+    stream: TcpStream,
+    turn: Arc<Mutex<()>>,
+}
+
+#[cfg(feature = "websocket")]
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl std::io::Write for SharedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _turn = self.turn.lock().unwrap();
+        self.stream.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A TCP front-end read with a timeout, so partial frames are kept between reads
+struct TcpFrontEnd {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl TcpFrontEnd {
+    fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(frame) = self.take_frame()? {
+            return Ok(Some(frame));
+        }
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                self.take_frame()
+            }
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Split the first whole frame off the buffer
+    fn take_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let Some(len) = self.buf.get(..4).map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize) else {
+            return Ok(None);
+        };
+        if len > MAX_FRAME_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame = self.buf[4..4 + len].to_vec();
+        self.buf.drain(..4 + len);
+        Ok(Some(frame))
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{session_key, BiWiUdpServer};
+    use crate::tcp::BiWiTcpClient;
+    use std::time::Instant;
+
+    #[test]
+    fn test_tcp_players_reach_udp_server() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let backend = server.local_addr().unwrap();
+        let mut gateway = Gateway::new(&backend.to_string());
+        let front = gateway.listen_tcp("127.0.0.1:0").unwrap();
+
+        let player = BiWiTcpClient::connect(&front.to_string()).unwrap();
+        let hello = BiWiMessage::builder().field(1, "hello").build();
+        player.send(&hello).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (client_id, received) = loop {
+            assert!(Instant::now() < deadline, "message never reached the backend");
+            if let Some(received) = server.recv_packet() {
+                break received;
+            }
+        };
+        assert_eq!(received, hello);
+        // The server's connection ID is the gateway's backend session
+        let sessions = gateway.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].kind, FrontEndKind::Tcp);
+        assert_eq!(session_key(sessions[0].session_id), client_id);
+
+        let reply = BiWiMessage::builder().field(1, "welcome").build();
+        server.send_to(&client_id, &reply).unwrap();
+        assert_eq!(player.recv_timeout(Duration::from_secs(5)).unwrap(), reply);
+
+        // Shutdown waits for the connection's threads, which leave no session behind
+        gateway.shutdown();
+        assert!(gateway.sessions().is_empty());
+        assert!(player.recv_timeout(Duration::from_secs(5)).is_err());
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_players_reach_udp_server() {
+        use tungstenite::Message;
+
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let mut gateway = Gateway::new(&server.local_addr().unwrap().to_string());
+        let front = gateway.listen_websocket("127.0.0.1:0").unwrap();
+        let (mut player, _) = tungstenite::connect(format!("ws://{}", front)).unwrap();
+        let hello = BiWiMessage::builder().field(1, "hello").build();
+        player.send(Message::Binary(hello.to_vec())).unwrap();
+
+        let (client_id, received) = loop {
+            if let Some(received) = server.recv_packet() {
+                break received;
+            }
+        };
+        assert_eq!(received, hello);
+        server.send_to(&client_id, &hello).unwrap();
+        match player.read().unwrap() {
+            Message::Binary(bytes) => assert_eq!(BiWiMessage::from_buffer(&bytes).unwrap(), hello),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
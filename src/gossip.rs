@@ -0,0 +1,783 @@
+//! BiWi Gossip Channel
+//! Server-to-server presence and room membership sync over the BiWi UDP stack.
+//! Local changes are pushed to peers as deltas; a periodic anti-entropy digest
+//! exchange repairs anything a peer missed, so every node converges on the
+//! same view of which node hosts which connection and who is in which room.
+//!
+//! Each run of a node has an incarnation, its start time in milliseconds. A node
+//! that restarts counts versions up from zero again, and peers take its state from
+//! the new incarnation over whatever they held for the old one.
+
+use crate::encoder::BiWiValue;
+use crate::message::BiWiMessage;
+use crate::network::{PacketManager, PacketType, UdpPacket};
+use crate::server::ConnectionId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Identifier of a server node within a cluster
+pub type NodeId = String;
+
+// Field IDs used by gossip frames
+const FIELD_KIND: u32 = 1;
+const FIELD_ORIGIN: u32 = 2;
+const FIELD_VERSION: u32 = 3;
+const FIELD_OP: u32 = 4;
+const FIELD_CONNECTION: u32 = 5;
+const FIELD_ROOM: u32 = 6;
+const FIELD_DIGEST: u32 = 7;
+const FIELD_CONNECTIONS: u32 = 8;
+const FIELD_ROOMS: u32 = 9;
+const FIELD_PAYLOAD: u32 = 10;
+const FIELD_SUBJECT: u32 = 11;
+const FIELD_INCARNATION: u32 = 12;
+
+/// Gossip frame kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GossipKind {
+    Delta = 1,
+    Digest = 2,
+    State = 3,
+    Forward = 4,
+    RoomBroadcast = 5,
+}
+
+impl GossipKind {
+    fn from_i64(value: i64) -> Option<Self> {
+        match value {
+            1 => Some(GossipKind::Delta),
+            2 => Some(GossipKind::Digest),
+            3 => Some(GossipKind::State),
+            4 => Some(GossipKind::Forward),
+            5 => Some(GossipKind::RoomBroadcast),
+            _ => None,
+        }
+    }
+}
+
+/// A single membership change made by the origin node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipOp {
+    Connect(ConnectionId),
+    Disconnect(ConnectionId),
+    JoinRoom(String, ConnectionId),
+    LeaveRoom(String, ConnectionId),
+}
+
+impl GossipOp {
+    fn code(&self) -> i32 {
+        match self {
+            GossipOp::Connect(_) => 1,
+            GossipOp::Disconnect(_) => 2,
+            GossipOp::JoinRoom(..) => 3,
+            GossipOp::LeaveRoom(..) => 4,
+        }
+    }
+}
+
+/// Presence and room membership owned by one node
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeState {
+    /// Start time of the node's current run; a later incarnation wins over any version
+    pub incarnation: u64,
+    /// Incremented on every local change; higher versions win
+    pub version: u64,
+    pub connections: BTreeSet<ConnectionId>,
+    pub rooms: BTreeMap<String, BTreeSet<ConnectionId>>,
+}
+
+impl NodeState {
+    /// Which of two states of a node is newer
+    fn stamp(&self) -> (u64, u64) {
+        (self.incarnation, self.version)
+    }
+
+    fn apply(&mut self, op: &GossipOp) {
+        match op {
+            GossipOp::Connect(conn) => {
+                self.connections.insert(conn.clone());
+            }
+            GossipOp::Disconnect(conn) => {
+                self.connections.remove(conn);
+                for members in self.rooms.values_mut() {
+                    members.remove(conn);
+                }
+                self.rooms.retain(|_, members| !members.is_empty());
+            }
+            GossipOp::JoinRoom(room, conn) => {
+                self.rooms.entry(room.clone()).or_default().insert(conn.clone());
+            }
+            GossipOp::LeaveRoom(room, conn) => {
+                if let Some(members) = self.rooms.get_mut(room) {
+                    members.remove(conn);
+                    if members.is_empty() {
+                        self.rooms.remove(room);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where a connection lives within the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Local,
+    Remote(NodeId),
+    Unknown,
+}
+
+/// Events surfaced to the hosting server by `GossipNode::poll`
+#[derive(Debug, Clone)]
+pub enum GossipEvent {
+    /// A peer forwarded a message for a connection hosted on this node
+    Forwarded {
+        connection: ConnectionId,
+        message: BiWiMessage,
+    },
+    /// A peer broadcast to a room; `connections` are this node's local members
+    RoomBroadcast {
+        room: String,
+        connections: Vec<ConnectionId>,
+        message: BiWiMessage,
+    },
+    /// A peer's presence state changed
+    NodeUpdated(NodeId),
+}
+
+/// Per-peer transport state
+struct Peer {
+    node_id: Option<NodeId>,
+    /// Incarnation the peer tags its packets with, once heard from
+    incarnation: Option<u64>,
+    packet_manager: PacketManager,
+}
+
+/// Packet manager for a peer, tagging our packets with our incarnation
+fn peer_manager(incarnation: u64) -> PacketManager {
+    let mut packet_manager = PacketManager::new();
+    packet_manager.set_session(incarnation);
+    packet_manager
+}
+
+/// Gossip endpoint run alongside a `BiWiUdpServer` on each shard
+pub struct GossipNode {
+    socket: UdpSocket,
+    node_id: NodeId,
+    local: NodeState,
+    remote: HashMap<NodeId, NodeState>,
+    peers: HashMap<SocketAddr, Peer>,
+    sync_interval: Duration,
+    last_sync: Instant,
+}
+
+impl GossipNode {
+    /// Bind a gossip endpoint for `node_id` on `addr`
+    pub fn bind(node_id: &str, addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+
+        let incarnation = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        Ok(GossipNode {
+            socket,
+            node_id: node_id.to_string(),
+            local: NodeState {
+                incarnation,
+                ..NodeState::default()
+            },
+            remote: HashMap::new(),
+            peers: HashMap::new(),
+            sync_interval: Duration::from_secs(1),
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Set how often anti-entropy digests are sent to peers
+    pub fn set_sync_interval(&mut self, interval: Duration) {
+        self.sync_interval = interval;
+    }
+
+    /// Get this node's ID
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Get the local gossip socket address
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Add a peer node and immediately exchange digests with it
+    pub fn add_peer(&mut self, addr: SocketAddr) -> io::Result<()> {
+        let incarnation = self.local.incarnation;
+        self.peers.entry(addr).or_insert_with(|| Peer {
+            node_id: None,
+            incarnation: None,
+            packet_manager: peer_manager(incarnation),
+        });
+        let digest = self.digest_frame();
+        self.send_frame(addr, &digest)
+    }
+
+    /// Record that a client connected to this node
+    pub fn announce_connect(&mut self, connection: &str) -> io::Result<()> {
+        self.apply_local(GossipOp::Connect(connection.to_string()))
+    }
+
+    /// Record that a client disconnected from this node
+    pub fn announce_disconnect(&mut self, connection: &str) -> io::Result<()> {
+        self.apply_local(GossipOp::Disconnect(connection.to_string()))
+    }
+
+    /// Add a local connection to a cluster-wide room
+    pub fn join_room(&mut self, room: &str, connection: &str) -> io::Result<()> {
+        self.apply_local(GossipOp::JoinRoom(room.to_string(), connection.to_string()))
+    }
+
+    /// Remove a local connection from a cluster-wide room
+    pub fn leave_room(&mut self, room: &str, connection: &str) -> io::Result<()> {
+        self.apply_local(GossipOp::LeaveRoom(room.to_string(), connection.to_string()))
+    }
+
+    /// Find the node hosting a connection
+    pub fn route(&self, connection: &str) -> Route {
+        if self.local.connections.contains(connection) {
+            return Route::Local;
+        }
+        self.remote
+            .iter()
+            .find(|(_, state)| state.connections.contains(connection))
+            .map(|(node, _)| Route::Remote(node.clone()))
+            .unwrap_or(Route::Unknown)
+    }
+
+    /// Get every member of a room across the cluster as (node, connection)
+    pub fn room_members(&self, room: &str) -> Vec<(NodeId, ConnectionId)> {
+        let mut members = Vec::new();
+        let states = std::iter::once((&self.node_id, &self.local)).chain(self.remote.iter());
+        for (node, state) in states {
+            if let Some(conns) = state.rooms.get(room) {
+                members.extend(conns.iter().map(|c| (node.clone(), c.clone())));
+            }
+        }
+        members.sort();
+        members
+    }
+
+    /// Get the known state of a node (including this one)
+    pub fn node_state(&self, node_id: &str) -> Option<&NodeState> {
+        if node_id == self.node_id {
+            Some(&self.local)
+        } else {
+            self.remote.get(node_id)
+        }
+    }
+
+    /// Forward a message to a connection hosted on another node.
+    /// Returns `Route::Local` without sending if the connection is ours.
+    pub fn forward(&mut self, connection: &str, message: &BiWiMessage) -> io::Result<Route> {
+        let route = self.route(connection);
+        if let Route::Remote(node) = &route {
+            let mut frame = self.frame(GossipKind::Forward);
+            frame.set_field(FIELD_CONNECTION, BiWiValue::from(connection));
+            frame.set_field(FIELD_PAYLOAD, BiWiValue::Binary(message.to_vec()));
+            if let Some(addr) = self.peer_addr(node) {
+                self.send_frame(addr, &frame)?;
+            }
+        }
+        Ok(route)
+    }
+
+    /// Broadcast to a room cluster-wide: remote nodes with members receive the
+    /// message, and this node's own members are returned for local delivery
+    pub fn broadcast_room(&mut self, room: &str, message: &BiWiMessage) -> io::Result<Vec<ConnectionId>> {
+        let mut frame = self.frame(GossipKind::RoomBroadcast);
+        frame.set_field(FIELD_ROOM, BiWiValue::from(room));
+        frame.set_field(FIELD_PAYLOAD, BiWiValue::Binary(message.to_vec()));
+
+        let targets: Vec<SocketAddr> = self
+            .remote
+            .iter()
+            .filter(|(_, state)| state.rooms.contains_key(room))
+            .filter_map(|(node, _)| self.peer_addr(node))
+            .collect();
+        for addr in targets {
+            self.send_frame(addr, &frame)?;
+        }
+
+        Ok(self.local_room_members(room))
+    }
+
+    /// Process incoming frames, retransmit unacknowledged ones and run
+    /// anti-entropy when due. Never blocks.
+    pub fn poll(&mut self) -> io::Result<Vec<GossipEvent>> {
+        let mut events = Vec::new();
+        let mut buf = vec![0u8; 65536];
+
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, addr)) => {
+                    if let Ok(packet) = UdpPacket::from_bytes(&buf[..n]) {
+                        self.handle_packet(addr, packet, &mut events)?;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        for (addr, peer) in self.peers.iter_mut() {
+            for (packet, _) in peer.packet_manager.get_retransmit_packets() {
+                self.socket.send_to(&packet.to_bytes(), addr)?;
+            }
+        }
+
+        if self.last_sync.elapsed() >= self.sync_interval {
+            self.last_sync = Instant::now();
+            let digest = self.digest_frame();
+            let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+            for addr in addrs {
+                self.send_frame(addr, &digest)?;
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn handle_packet(
+        &mut self,
+        addr: SocketAddr,
+        mut packet: UdpPacket,
+        events: &mut Vec<GossipEvent>,
+    ) -> io::Result<()> {
+        // Only configured peers may gossip with us
+        let Some(peer) = self.peers.get_mut(&addr) else {
+            return Ok(());
+        };
+
+        // A restarted peer numbers its packets from zero again, so its sequences
+        // start over on our side too; stragglers from an older run are dropped
+        let incarnation = packet.take_session();
+        match incarnation.cmp(&peer.incarnation) {
+            Ordering::Less => return Ok(()),
+            Ordering::Greater if peer.incarnation.is_some() => {
+                peer.packet_manager = peer_manager(self.local.incarnation);
+            }
+            _ => {}
+        }
+        peer.incarnation = incarnation;
+
+        match packet.packet_type {
+            PacketType::Data => {
+                let ack = peer.packet_manager.create_ack_packet(packet.sequence);
+                self.socket.send_to(&ack.to_bytes(), addr)?;
+                // Frames too big for one packet arrive as fragments and come out whole
+                let frames: Vec<BiWiMessage> = peer
+                    .packet_manager
+                    .deliver(packet)
+                    .iter()
+                    .filter_map(|packet| BiWiMessage::from_buffer(&packet.payload).ok())
+                    .collect();
+                for frame in frames {
+                    self.handle_frame(addr, frame, events)?;
+                }
+            }
+            PacketType::Ack => {
+                peer.packet_manager.handle_ack(packet.ack_number);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_frame(
+        &mut self,
+        addr: SocketAddr,
+        frame: BiWiMessage,
+        events: &mut Vec<GossipEvent>,
+    ) -> io::Result<()> {
+        let kind = frame.get_i64(FIELD_KIND).and_then(GossipKind::from_i64);
+        let origin = match frame.get_str(FIELD_ORIGIN) {
+            Some(origin) if origin != self.node_id => origin.to_string(),
+            _ => return Ok(()),
+        };
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.node_id = Some(origin.clone());
+        }
+
+        match kind {
+            Some(GossipKind::Delta) => {
+                let incarnation = frame.get_i64(FIELD_INCARNATION).unwrap_or(0) as u64;
+                let version = frame.get_i64(FIELD_VERSION).unwrap_or(0) as u64;
+                let state = self.remote.entry(origin.clone()).or_default();
+                if incarnation > state.incarnation {
+                    // The origin restarted: what it hosted before is gone
+                    *state = NodeState {
+                        incarnation,
+                        ..NodeState::default()
+                    };
+                    events.push(GossipEvent::NodeUpdated(origin.clone()));
+                }
+                if incarnation < state.incarnation {
+                    // A delta from before the origin restarted
+                } else if version == state.version + 1 {
+                    if let Some(op) = decode_op(&frame) {
+                        state.apply(&op);
+                        state.version = version;
+                        events.push(GossipEvent::NodeUpdated(origin));
+                    }
+                } else if version > state.version {
+                    // Missed a delta: ask the origin for its full state
+                    let digest = self.digest_frame();
+                    self.send_frame(addr, &digest)?;
+                }
+            }
+            Some(GossipKind::Digest) => {
+                let digest = decode_digest(&frame);
+                let mut behind = false;
+
+                let mut newer = Vec::new();
+                let known = std::iter::once((&self.node_id, &self.local)).chain(self.remote.iter());
+                for (node, state) in known {
+                    let theirs = digest.get(node).copied().unwrap_or_default();
+                    if state.stamp() > theirs {
+                        newer.push(self.state_frame(node, state));
+                    }
+                }
+                for (node, stamp) in &digest {
+                    if *node != self.node_id && *stamp > self.node_state(node).map_or((0, 0), NodeState::stamp) {
+                        behind = true;
+                    }
+                }
+
+                for state in newer {
+                    self.send_frame(addr, &state)?;
+                }
+                if behind {
+                    let ours = self.digest_frame();
+                    self.send_frame(addr, &ours)?;
+                }
+            }
+            Some(GossipKind::State) => {
+                // State frames may relay a third node's state, so the subject is explicit
+                let subject = frame
+                    .get_str(FIELD_SUBJECT)
+                    .unwrap_or(&origin)
+                    .to_string();
+                if subject == self.node_id {
+                    return Ok(());
+                }
+                let state = decode_state(&frame);
+                let current = self.remote.entry(subject.clone()).or_default();
+                if state.stamp() > current.stamp() {
+                    *current = state;
+                    events.push(GossipEvent::NodeUpdated(subject));
+                }
+            }
+            Some(GossipKind::Forward) => {
+                let connection = frame.get_str(FIELD_CONNECTION);
+                let payload = frame.get_bytes(FIELD_PAYLOAD);
+                if let (Some(connection), Some(bytes)) = (connection, payload) {
+                    if let Ok(message) = BiWiMessage::from_buffer(bytes) {
+                        events.push(GossipEvent::Forwarded {
+                            connection: connection.to_string(),
+                            message,
+                        });
+                    }
+                }
+            }
+            Some(GossipKind::RoomBroadcast) => {
+                let room = frame.get_str(FIELD_ROOM);
+                let payload = frame.get_bytes(FIELD_PAYLOAD);
+                if let (Some(room), Some(bytes)) = (room, payload) {
+                    if let Ok(message) = BiWiMessage::from_buffer(bytes) {
+                        events.push(GossipEvent::RoomBroadcast {
+                            room: room.to_string(),
+                            connections: self.local_room_members(room),
+                            message,
+                        });
+                    }
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn apply_local(&mut self, op: GossipOp) -> io::Result<()> {
+        self.local.apply(&op);
+        self.local.version += 1;
+
+        let mut frame = self.frame(GossipKind::Delta);
+        frame.set_field(FIELD_INCARNATION, BiWiValue::Int64(self.local.incarnation as i64));
+        frame.set_field(FIELD_VERSION, BiWiValue::Int64(self.local.version as i64));
+        frame.set_field(FIELD_OP, BiWiValue::Int32(op.code()));
+        match &op {
+            GossipOp::Connect(conn) | GossipOp::Disconnect(conn) => {
+                frame.set_field(FIELD_CONNECTION, BiWiValue::from(conn.as_str()));
+            }
+            GossipOp::JoinRoom(room, conn) | GossipOp::LeaveRoom(room, conn) => {
+                frame.set_field(FIELD_ROOM, BiWiValue::from(room.as_str()));
+                frame.set_field(FIELD_CONNECTION, BiWiValue::from(conn.as_str()));
+            }
+        }
+
+        let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for addr in addrs {
+            self.send_frame(addr, &frame)?;
+        }
+        Ok(())
+    }
+
+    fn local_room_members(&self, room: &str) -> Vec<ConnectionId> {
+        self.local
+            .rooms
+            .get(room)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn peer_addr(&self, node: &str) -> Option<SocketAddr> {
+        self.peers
+            .iter()
+            .find(|(_, peer)| peer.node_id.as_deref() == Some(node))
+            .map(|(addr, _)| *addr)
+    }
+
+    fn frame(&self, kind: GossipKind) -> BiWiMessage {
+        let mut frame = BiWiMessage::new();
+        frame.set_field(FIELD_KIND, BiWiValue::Int32(kind as i32));
+        frame.set_field(FIELD_ORIGIN, BiWiValue::from(self.node_id.as_str()));
+        frame
+    }
+
+    /// Every known node's `[incarnation, version]`
+    fn digest_frame(&self) -> BiWiMessage {
+        let stamp = |state: &NodeState| {
+            BiWiValue::Array(vec![BiWiValue::Int64(state.incarnation as i64), BiWiValue::Int64(state.version as i64)])
+        };
+        let mut digest = HashMap::new();
+        digest.insert(self.node_id.clone(), stamp(&self.local));
+        for (node, state) in &self.remote {
+            digest.insert(node.clone(), stamp(state));
+        }
+
+        let mut frame = self.frame(GossipKind::Digest);
+        frame.set_field(FIELD_DIGEST, BiWiValue::Object(digest));
+        frame
+    }
+
+    fn state_frame(&self, node: &str, state: &NodeState) -> BiWiMessage {
+        let connections = state.connections.iter().map(|c| BiWiValue::from(c.as_str())).collect();
+        let rooms = state
+            .rooms
+            .iter()
+            .map(|(room, members)| {
+                let members = members.iter().map(|c| BiWiValue::from(c.as_str())).collect();
+                (room.clone(), BiWiValue::Array(members))
+            })
+            .collect();
+
+        let mut frame = self.frame(GossipKind::State);
+        frame.set_field(FIELD_SUBJECT, BiWiValue::from(node));
+        frame.set_field(FIELD_INCARNATION, BiWiValue::Int64(state.incarnation as i64));
+        frame.set_field(FIELD_VERSION, BiWiValue::Int64(state.version as i64));
+        frame.set_field(FIELD_CONNECTIONS, BiWiValue::Array(connections));
+        frame.set_field(FIELD_ROOMS, BiWiValue::Object(rooms));
+        frame
+    }
+
+    fn send_frame(&mut self, addr: SocketAddr, frame: &BiWiMessage) -> io::Result<()> {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            for packet in peer.packet_manager.create_packets(&frame.to_vec()) {
+                self.socket.send_to(&packet.to_bytes(), addr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode_op(frame: &BiWiMessage) -> Option<GossipOp> {
+    let conn = frame.get_str(FIELD_CONNECTION)?.to_string();
+    let room = frame.get_str(FIELD_ROOM).map(str::to_string);
+    match (frame.get_i64(FIELD_OP)?, room) {
+        (1, _) => Some(GossipOp::Connect(conn)),
+        (2, _) => Some(GossipOp::Disconnect(conn)),
+        (3, Some(room)) => Some(GossipOp::JoinRoom(room, conn)),
+        (4, Some(room)) => Some(GossipOp::LeaveRoom(room, conn)),
+        _ => None,
+    }
+}
+
+fn decode_digest(frame: &BiWiMessage) -> HashMap<NodeId, (u64, u64)> {
+    match frame.get_field(FIELD_DIGEST) {
+        Some(BiWiValue::Object(map)) => map
+            .iter()
+            .filter_map(|(node, v)| match v.as_array()? {
+                [incarnation, version] => Some((node.clone(), (incarnation.as_i64()? as u64, version.as_i64()? as u64))),
+                _ => None,
+            })
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+fn decode_state(frame: &BiWiMessage) -> NodeState {
+    let mut state = NodeState {
+        incarnation: frame.get_i64(FIELD_INCARNATION).unwrap_or(0) as u64,
+        version: frame.get_i64(FIELD_VERSION).unwrap_or(0) as u64,
+        ..NodeState::default()
+    };
+    if let Some(conns) = frame.get_array(FIELD_CONNECTIONS) {
+        state.connections = conns.iter().filter_map(BiWiValue::as_str).map(str::to_string).collect();
+    }
+    if let Some(BiWiValue::Object(rooms)) = frame.get_field(FIELD_ROOMS) {
+        for (room, members) in rooms {
+            if let Some(members) = members.as_array() {
+                let members: BTreeSet<_> = members.iter().filter_map(BiWiValue::as_str).map(str::to_string).collect();
+                if !members.is_empty() {
+                    state.rooms.insert(room.clone(), members);
+                }
+            }
+        }
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::MAX_PAYLOAD_SIZE;
+    use std::thread;
+
+    fn pump(nodes: &mut [&mut GossipNode]) -> Vec<GossipEvent> {
+        let mut events = Vec::new();
+        for _ in 0..20 {
+            for node in nodes.iter_mut() {
+                events.extend(node.poll().unwrap());
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        events
+    }
+
+    fn pair() -> (GossipNode, GossipNode) {
+        let mut a = GossipNode::bind("a", "127.0.0.1:0").unwrap();
+        let mut b = GossipNode::bind("b", "127.0.0.1:0").unwrap();
+        let (addr_a, addr_b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        a.add_peer(addr_b).unwrap();
+        b.add_peer(addr_a).unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn test_presence_deltas_propagate() {
+        let (mut a, mut b) = pair();
+        pump(&mut [&mut a, &mut b]);
+
+        a.announce_connect("client-1").unwrap();
+        a.join_room("lobby", "client-1").unwrap();
+        b.announce_connect("client-2").unwrap();
+        b.join_room("lobby", "client-2").unwrap();
+        pump(&mut [&mut a, &mut b]);
+
+        assert_eq!(b.route("client-1"), Route::Remote("a".to_string()));
+        assert_eq!(a.route("client-1"), Route::Local);
+        assert_eq!(
+            a.room_members("lobby"),
+            vec![("a".to_string(), "client-1".to_string()), ("b".to_string(), "client-2".to_string())]
+        );
+
+        a.announce_disconnect("client-1").unwrap();
+        pump(&mut [&mut a, &mut b]);
+        assert_eq!(b.route("client-1"), Route::Unknown);
+        assert_eq!(b.room_members("lobby").len(), 1);
+    }
+
+    #[test]
+    fn test_anti_entropy_catches_up_late_peer() {
+        let mut a = GossipNode::bind("a", "127.0.0.1:0").unwrap();
+        a.announce_connect("early").unwrap();
+        a.join_room("room", "early").unwrap();
+
+        // b joins after a's deltas were emitted, so only a digest exchange can inform it
+        let mut b = GossipNode::bind("b", "127.0.0.1:0").unwrap();
+        a.add_peer(b.local_addr().unwrap()).unwrap();
+        b.add_peer(a.local_addr().unwrap()).unwrap();
+        pump(&mut [&mut a, &mut b]);
+
+        assert_eq!(b.node_state("a"), a.node_state("a"));
+        assert_eq!(b.route("early"), Route::Remote("a".to_string()));
+    }
+
+    #[test]
+    fn test_state_larger_than_one_packet() {
+        let mut a = GossipNode::bind("a", "127.0.0.1:0").unwrap();
+        for i in 0..100 {
+            let connection = format!("connection-with-a-long-name-{i}");
+            a.announce_connect(&connection).unwrap();
+            a.join_room("everyone", &connection).unwrap();
+        }
+        assert!(a.state_frame("a", &a.local).to_vec().len() > MAX_PAYLOAD_SIZE);
+
+        // b only hears of a's connections through one fragmented State frame
+        let mut b = GossipNode::bind("b", "127.0.0.1:0").unwrap();
+        a.add_peer(b.local_addr().unwrap()).unwrap();
+        b.add_peer(a.local_addr().unwrap()).unwrap();
+        pump(&mut [&mut a, &mut b]);
+
+        assert_eq!(b.node_state("a"), a.node_state("a"));
+        assert_eq!(b.room_members("everyone").len(), 100);
+    }
+
+    #[test]
+    fn test_restarted_peer_replaces_its_old_state() {
+        let (mut a, mut b) = pair();
+        b.announce_connect("before").unwrap();
+        b.announce_connect("kept").unwrap();
+        pump(&mut [&mut a, &mut b]);
+        assert_eq!(a.route("before"), Route::Remote("b".to_string()));
+
+        // b comes back on the same address and counts versions from zero again
+        let addr_b = b.local_addr().unwrap();
+        drop(b);
+        thread::sleep(Duration::from_millis(5));
+        let mut b = GossipNode::bind("b", &addr_b.to_string()).unwrap();
+        b.add_peer(a.local_addr().unwrap()).unwrap();
+        b.announce_connect("after").unwrap();
+        pump(&mut [&mut a, &mut b]);
+
+        assert_eq!(a.route("after"), Route::Remote("b".to_string()));
+        assert_eq!(a.route("before"), Route::Unknown);
+        assert_eq!(a.node_state("b"), b.node_state("b"));
+
+        // Later deltas from the new run apply as usual
+        b.join_room("lobby", "after").unwrap();
+        pump(&mut [&mut a, &mut b]);
+        assert_eq!(a.room_members("lobby"), vec![("b".to_string(), "after".to_string())]);
+    }
+
+    #[test]
+    fn test_forward_and_room_broadcast() {
+        let (mut a, mut b) = pair();
+        b.announce_connect("remote").unwrap();
+        b.join_room("chat", "remote").unwrap();
+        pump(&mut [&mut a, &mut b]);
+
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from("hi"));
+        assert_eq!(a.forward("remote", &msg).unwrap(), Route::Remote("b".to_string()));
+        assert!(a.broadcast_room("chat", &msg).unwrap().is_empty());
+
+        let events = pump(&mut [&mut a, &mut b]);
+        assert!(events.iter().any(|e| matches!(e,
+            GossipEvent::Forwarded { connection, message }
+                if connection == "remote" && message.get_field(1) == msg.get_field(1))));
+        assert!(events.iter().any(|e| matches!(e,
+            GossipEvent::RoomBroadcast { room, connections, .. }
+                if room == "chat" && connections == &vec!["remote".to_string()])));
+    }
+}
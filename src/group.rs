@@ -0,0 +1,107 @@
+//! BiWi Broadcast Groups
+//! Named sets of connections (game rooms, chat channels) that a server can send to
+//! as one. A `Group` is a cheap handle onto membership shared with the server that
+//! created it, so it can be kept and updated anywhere; sessions leave every group
+//! when they end.
+
+use crate::server::ConnectionId;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Handle to a server's named group of connections
+#[derive(Clone)]
+pub struct Group {
+    name: Arc<str>,
+    members: Arc<Mutex<HashSet<ConnectionId>>>,
+}
+
+impl Group {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            members: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Add a connection; returns false if it was already a member
+    pub fn add(&self, client_id: &str) -> bool {
+        self.members.lock().unwrap().insert(client_id.to_string())
+    }
+
+    /// Remove a connection; returns false if it wasn't a member
+    pub fn remove(&self, client_id: &str) -> bool {
+        self.members.lock().unwrap().remove(client_id)
+    }
+
+    pub fn contains(&self, client_id: &str) -> bool {
+        self.members.lock().unwrap().contains(client_id)
+    }
+
+    /// Current members, in no particular order
+    pub fn members(&self) -> Vec<ConnectionId> {
+        self.members.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.lock().unwrap().is_empty()
+    }
+}
+
+/// A server's groups by name
+#[derive(Clone, Default)]
+pub(crate) struct Groups(Arc<Mutex<HashMap<String, Group>>>);
+
+impl Groups {
+    /// The group called `name`, created empty if it doesn't exist yet
+    pub fn create(&self, name: &str) -> Group {
+        self.0.lock().unwrap().entry(name.to_string()).or_insert_with(|| Group::new(name)).clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Group> {
+        self.0.lock().unwrap().get(name).cloned()
+    }
+
+    /// Drop the group called `name`; existing handles keep working but the server forgets it
+    pub fn remove(&self, name: &str) -> Option<Group> {
+        self.0.lock().unwrap().remove(name)
+    }
+
+    /// Take a closed session out of every group
+    pub fn leave_all(&self, client_id: &str) {
+        for group in self.0.lock().unwrap().values() {
+            group.remove(client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups_share_membership() {
+        let groups = Groups::default();
+        let lobby = groups.create("lobby");
+        assert!(lobby.add("a"));
+        assert!(!lobby.add("a"));
+        lobby.add("b");
+
+        // Handles to the same name see the same members
+        assert_eq!(groups.create("lobby").len(), 2);
+        groups.create("arena").add("a");
+
+        groups.leave_all("a");
+        assert_eq!(lobby.members(), vec!["b".to_string()]);
+        assert!(groups.get("arena").unwrap().is_empty());
+        assert!(groups.remove("arena").is_some());
+        assert!(groups.get("arena").is_none());
+    }
+}
@@ -0,0 +1,239 @@
+//! BiWi Server Handlers
+//! Callback interface for `BiWiUdpServer::run`, which owns the receive loop,
+//! retransmits and session expiry and hands each event to a `BiWiServerHandler`.
+//! Handlers answer through the `Connection` they're given.
+
+use crate::group::Group;
+use crate::message::BiWiMessage;
+use crate::network::SendMode;
+use crate::receipt::SendHandle;
+use crate::server::{BiWiUdpServer, ClientSender, ConnectionId};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Closed by a `Disconnect` from either side
+    Closed,
+    /// Nothing heard from the client within the liveness timeout
+    TimedOut,
+}
+
+/// Callbacks invoked by `BiWiUdpServer::run`; everything but `on_message` is optional
+pub trait BiWiServerHandler {
+    /// A client completed the handshake
+    fn on_connect(&mut self, _conn: &Connection<'_>) {}
+
+    /// A complete message arrived from `conn`
+    fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage);
+
+    /// A session ended; the client can no longer be sent to
+    fn on_disconnect(&mut self, _client_id: &ConnectionId, _reason: DisconnectReason) {}
+
+    /// The socket failed; the loop keeps running
+    fn on_error(&mut self, _error: io::Error) {}
+}
+
+impl<H: BiWiServerHandler + ?Sized> BiWiServerHandler for &mut H {
+    fn on_connect(&mut self, conn: &Connection<'_>) {
+        (**self).on_connect(conn)
+    }
+
+    fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+        (**self).on_message(conn, message)
+    }
+
+    fn on_disconnect(&mut self, client_id: &ConnectionId, reason: DisconnectReason) {
+        (**self).on_disconnect(client_id, reason)
+    }
+
+    fn on_error(&mut self, error: io::Error) {
+        (**self).on_error(error)
+    }
+}
+
+/// One client's session, as seen from a handler callback
+pub struct Connection<'a> {
+    server: &'a BiWiUdpServer,
+    id: &'a ConnectionId,
+    addr: SocketAddr,
+}
+
+impl<'a> Connection<'a> {
+    pub(crate) fn new(server: &'a BiWiUdpServer, id: &'a ConnectionId, addr: SocketAddr) -> Self {
+        Self { server, id, addr }
+    }
+
+    pub fn id(&self) -> &ConnectionId {
+        self.id
+    }
+
+    /// The client's current address (it follows the client if it roams)
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send a message back to this client (reliable and ordered)
+    pub fn reply(&self, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.server.send_to(self.id, message)
+    }
+
+    /// Send a message back to this client with the given delivery guarantees
+    pub fn reply_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        self.server.send_to_with_mode(self.id, message, mode)
+    }
+
+    /// Answer `request` with `response`, carrying the request's correlation ID back so
+    /// the client's `request` call picks it up
+    pub fn respond(&self, request: &BiWiMessage, response: BiWiMessage) -> io::Result<SendHandle> {
+        self.server.send_to(self.id, &response.in_reply_to(request))
+    }
+
+    /// A handle for sending to this client later, from any thread
+    pub fn sender(&self) -> io::Result<ClientSender> {
+        self.server.sender(self.id)
+    }
+
+    /// Send a message to another client
+    pub fn send(&self, client_id: &str, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.server.send_to(client_id, message)
+    }
+
+    /// Send a message to every connected client, this one included
+    pub fn broadcast(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.server.broadcast(message)
+    }
+
+    /// Add this client to the server's group called `name`, creating it if needed
+    pub fn join(&self, name: &str) -> Group {
+        let group = self.server.create_group(name);
+        group.add(self.id);
+        group
+    }
+
+    /// Send a message to every member of `group`
+    pub fn send_to_group(&self, group: &Group, message: &BiWiMessage) -> io::Result<()> {
+        self.server.send_to_group(group, message)
+    }
+}
+
+/// Makes `BiWiUdpServer::run` return; cloneable and usable from any thread
+#[derive(Clone)]
+pub struct StopHandle {
+    running: Arc<Mutex<bool>>,
+}
+
+impl StopHandle {
+    pub(crate) fn new(running: Arc<Mutex<bool>>) -> Self {
+        Self { running }
+    }
+
+    /// Stop the server's loop after the packet it is handling
+    pub fn stop(&self) {
+        *self.running.lock().unwrap() = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::BiWiUdpClient;
+    use crate::encoder::BiWiValue;
+    use crate::server::session_key;
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        Connect(ConnectionId),
+        Message(ConnectionId),
+        Disconnect(ConnectionId, DisconnectReason),
+    }
+
+    struct Echo {
+        seen: Sender<Seen>,
+        stop: StopHandle,
+    }
+
+    impl BiWiServerHandler for Echo {
+        fn on_connect(&mut self, conn: &Connection<'_>) {
+            self.seen.send(Seen::Connect(conn.id().clone())).unwrap();
+        }
+
+        fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+            self.seen.send(Seen::Message(conn.id().clone())).unwrap();
+            conn.reply(&message).unwrap();
+        }
+
+        fn on_disconnect(&mut self, client_id: &ConnectionId, reason: DisconnectReason) {
+            self.seen.send(Seen::Disconnect(client_id.clone(), reason)).unwrap();
+            self.stop.stop();
+        }
+    }
+
+    #[test]
+    fn test_run_dispatches_to_handler() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let (tx, seen) = channel();
+        let handler = Echo { seen: tx, stop: server.stop_handle() };
+        let server_thread = thread::spawn(move || server.run(handler));
+
+        let mut client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let id = session_key(client.session_id());
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from("hi"));
+        let receipt = client.send(&msg).unwrap();
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+        assert_eq!(receipt.wait_timeout(Duration::from_secs(5)), Some(crate::receipt::SendOutcome::Delivered));
+        client.disconnect();
+
+        // run returns once the handler stops it
+        server_thread.join().unwrap();
+        let seen: Vec<Seen> = seen.try_iter().collect();
+        assert_eq!(
+            seen,
+            vec![Seen::Connect(id.clone()), Seen::Message(id.clone()), Seen::Disconnect(id, DisconnectReason::Closed)]
+        );
+    }
+
+    struct Doubler;
+
+    impl BiWiServerHandler for Doubler {
+        fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+            if message.correlation_id().is_none() {
+                conn.reply(&message).unwrap();
+                return;
+            }
+            let Some(BiWiValue::Int32(n)) = message.get_field(1) else {
+                return;
+            };
+            let response = BiWiMessage::builder().field(1, BiWiValue::Int32(n * 2)).build();
+            conn.respond(&message, response).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_request_gets_its_response() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let stop = server.stop_handle();
+        let server_thread = thread::spawn(move || server.run(Doubler));
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let plain = BiWiMessage::builder().field(1, "not a request").build();
+        client.send(&plain).unwrap();
+        let request = BiWiMessage::builder().field(1, BiWiValue::Int32(21)).build().with_type(4);
+        let response = client.request(&request, Duration::from_secs(5)).unwrap();
+        assert_eq!(response.get_field(1), Some(&BiWiValue::Int32(42)));
+        assert_eq!(response.message_type(), Some(4));
+        // The plain echo wasn't taken for the response
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), plain);
+
+        stop.stop();
+        server_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+//! BiWi Compact Headers
+//! A shorter form of the 13-byte packet header for sessions that negotiate it during
+//! the handshake: clients offer `FLAG_COMPACT_HEADERS` in their `Connect` and servers
+//! configured with `ServerConfig::with_compact_headers` accept in the `ConnectAck`.
+//!
+//! `[type and bits u8][sequence varint][ack number varint][flags varint]`
+//!
+//! The first byte holds the packet type in bits 4-6, so it never reads as the 0x01-0x07
+//! of a full header and `PacketManager::decode` tells the two apart; compact headers
+//! are refused on sessions that did not negotiate them. The ack number is only present
+//! with `COMPACT_ACK`; without it, data packets ack nothing (`u32::MAX`) and other
+//! packets 0. The flags are only present with `COMPACT_FLAGS`; without it,
+//! `COMPACT_SESSION` and the send mode in the low two bits are the only flags, besides
+//! both fragment bits on a data packet: a whole message on the default channel.
+//!
+//! A data packet's sequence is sent as a delta: only its low bits, in a varint of one
+//! to four bytes holding 7 to 28 of them. The sender uses enough bits that the
+//! receiver, counting from the sequence after the newest one it has seen on the
+//! channel, lands on the right one: at least twice the distance back to the newest
+//! sequence the receiver has ACKed. Unreliable packets are never ACKed, so for them the
+//! sender assumes the receiver missed at most `COMPACT_LOSS_HORIZON` in a row. A
+//! five-byte varint holds the whole sequence. Other packets send their sequence whole.
+//!
+//! A packet whose compact header would be no shorter goes out with the full one.
+//! Encrypted sessions authenticate the full header either way.
+
+use crate::decoder::DecodeError;
+use crate::network::{PacketType, UdpPacket, FLAG_SESSION, FRAG_FIRST, FRAG_LAST, PACKET_HEADER_SIZE, SEND_MODE_MASK};
+use crate::reader::Reader;
+
+/// An ack number follows the sequence
+pub const COMPACT_ACK: u8 = 0x80;
+/// Packet type, in the bits above `COMPACT_FLAGS`
+pub const COMPACT_TYPE_MASK: u8 = 0x70;
+const COMPACT_TYPE_SHIFT: u32 = 4;
+/// The flags follow in full; otherwise the bits below say what they are
+pub const COMPACT_FLAGS: u8 = 0x08;
+/// `FLAG_SESSION`, when the flags are not sent in full
+pub const COMPACT_SESSION: u8 = 0x04;
+/// Send mode, when the flags are not sent in full
+pub const COMPACT_MODE_MASK: u8 = 0x03;
+const SEND_MODE_SHIFT: u32 = SEND_MODE_MASK.trailing_zeros();
+
+/// Unreliable packets in a row a receiver may miss on a channel and still read the
+/// next one's sequence
+pub const COMPACT_LOSS_HORIZON: u32 = (1 << 13) - 1;
+
+/// Widest sequence delta, in bits; wider sequences are sent whole
+const MAX_DELTA_BITS: u32 = 28;
+
+/// Low bits of the sequence a compact data header needs when the receiver may be
+/// expecting any sequence up to `distance` away; 32 for the whole sequence
+pub(crate) fn sequence_bits(distance: u32) -> u32 {
+    (7..=MAX_DELTA_BITS).step_by(7).find(|&bits| distance < 1 << (bits - 1)).unwrap_or(32)
+}
+
+/// The sequence whose low `bits` are `low` that is closest to `expected`
+pub(crate) fn expand_sequence(low: u32, bits: u32, expected: u32) -> u32 {
+    if bits >= 32 {
+        return low;
+    }
+    let window = 1u32 << bits;
+    let candidate = (expected & !(window - 1)) | low;
+    let offset = candidate.wrapping_sub(expected) as i32;
+    if offset >= (window / 2) as i32 {
+        candidate.wrapping_sub(window)
+    } else if offset < -((window / 2) as i32) {
+        candidate.wrapping_add(window)
+    } else {
+        candidate
+    }
+}
+
+/// Whether `first`, a packet's first byte, starts a compact header
+pub(crate) fn is_compact(first: u8) -> bool {
+    first & COMPACT_TYPE_MASK != 0
+}
+
+/// Ack number a packet of `packet_type` has when its compact header leaves it out
+fn implied_ack(packet_type: PacketType) -> u32 {
+    match packet_type {
+        PacketType::Data => u32::MAX,
+        _ => 0,
+    }
+}
+
+/// Flags a packet of `packet_type` has when its compact header leaves them out
+fn implied_flags(packet_type: PacketType, bits: u8) -> u32 {
+    let fragment = if packet_type == PacketType::Data { FRAG_FIRST | FRAG_LAST } else { 0 };
+    let session = if bits & COMPACT_SESSION != 0 { FLAG_SESSION } else { 0 };
+    fragment | session | u32::from(bits & COMPACT_MODE_MASK) << SEND_MODE_SHIFT
+}
+
+/// The compact header for `packet`, if it is shorter than the full one. A data
+/// packet's sequence is cut to its low `sequence_bits` (see `sequence_bits`).
+pub(crate) fn encode(packet: &UdpPacket, sequence_bits: u32) -> Option<Vec<u8>> {
+    let mut first = (packet.packet_type as u8) << COMPACT_TYPE_SHIFT;
+    let session = if packet.flags & FLAG_SESSION != 0 { COMPACT_SESSION } else { 0 };
+    let bits = session | ((packet.flags & SEND_MODE_MASK) >> SEND_MODE_SHIFT) as u8;
+    let full_flags = packet.flags != implied_flags(packet.packet_type, bits);
+    first |= if full_flags { COMPACT_FLAGS } else { bits };
+    let ack = packet.ack_number != implied_ack(packet.packet_type);
+    if ack {
+        first |= COMPACT_ACK;
+    }
+
+    let mut header = Vec::with_capacity(PACKET_HEADER_SIZE);
+    header.push(first);
+    if packet.packet_type == PacketType::Data {
+        push_sequence(&mut header, packet.sequence, sequence_bits);
+    } else {
+        push_varint(&mut header, packet.sequence);
+    }
+    if ack {
+        push_varint(&mut header, packet.ack_number);
+    }
+    if full_flags {
+        push_varint(&mut header, packet.flags);
+    }
+    (header.len() < PACKET_HEADER_SIZE).then_some(header)
+}
+
+/// Read a packet with a compact header. A data packet comes back with only the low
+/// bits of its sequence, returned alongside for `expand_sequence`; other packets
+/// with all 32.
+pub(crate) fn decode(data: &[u8]) -> Result<(UdpPacket, u32), DecodeError> {
+    let mut reader = Reader::new(data);
+    let first = reader.read_u8("compact header")?;
+    let packet_type = PacketType::from_u8((first & COMPACT_TYPE_MASK) >> COMPACT_TYPE_SHIFT)
+        .ok_or(DecodeError::InvalidData("invalid packet type"))?;
+    let (sequence, sequence_bits) = match packet_type {
+        PacketType::Data => read_sequence(&mut reader)?,
+        _ => (reader.read_varint_u32("sequence")?, 32),
+    };
+    let ack_number = match first & COMPACT_ACK {
+        0 => implied_ack(packet_type),
+        _ => reader.read_varint_u32("ack number")?,
+    };
+    let flags = match first & COMPACT_FLAGS {
+        0 => implied_flags(packet_type, first),
+        _ => reader.read_varint_u32("flags")?,
+    };
+    let packet = UdpPacket {
+        packet_type,
+        sequence,
+        ack_number,
+        flags,
+        payload: reader.read_rest().to_vec(),
+    };
+    Ok((packet, sequence_bits))
+}
+
+/// The low `bits` of `sequence` as a varint of `bits / 7` bytes, or all of it in five
+fn push_sequence(buf: &mut Vec<u8>, sequence: u32, bits: u32) {
+    let len = bits.div_ceil(7);
+    for i in 0..len {
+        let more = if i + 1 < len { 0x80 } else { 0 };
+        buf.push((sequence >> (7 * i)) as u8 & 0x7f | more);
+    }
+}
+
+/// A data packet's sequence, or its low bits, and how many bits it held
+fn read_sequence(reader: &mut Reader) -> Result<(u32, u32), DecodeError> {
+    let mut sequence = 0u32;
+    for i in 0..5 {
+        let byte = reader.read_u8("sequence")?;
+        if i == 4 && byte > 0x0f {
+            return Err(DecodeError::InvalidData("sequence varint overflows"));
+        }
+        sequence |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            let bits = if i == 4 { 32 } else { 7 * (i + 1) };
+            return Ok((sequence, bits));
+        }
+    }
+    Err(DecodeError::InvalidData("sequence varint overflows"))
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{PacketCipher, Role};
+    use crate::network::{PacketManager, SendMode};
+
+    /// Two managers that negotiated compact headers
+    fn pair() -> (PacketManager, PacketManager) {
+        let mut sender = PacketManager::new();
+        let mut receiver = PacketManager::new();
+        sender.set_compact_headers(true);
+        receiver.set_compact_headers(true);
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_compact_headers_round_trip() {
+        let (mut pm, mut receiver) = pair();
+        pm.set_session(0x1234);
+        let mut packets = pm.create_packets_with_mode(&[6, 2, 0x54], SendMode::Unreliable);
+        packets.extend(pm.create_packets_on(3, &[1], SendMode::ReliableOrdered));
+        packets.push(pm.create_ack_packet(300));
+        packets.push(pm.create_ping_packet());
+
+        // A whole message on the default channel: type and flags in one byte, then the sequence
+        assert_eq!(encode(&packets[0], 7).unwrap(), [0x10 | COMPACT_SESSION | 3, 0]);
+        for packet in &packets {
+            let bytes = pm.encode(packet);
+            assert!(bytes.len() - packet.payload.len() <= 5);
+            let decoded = receiver.decode(&bytes).unwrap();
+            assert_eq!(decoded.to_bytes(), packet.to_bytes());
+        }
+
+        // Fields too wide to save anything keep the full header
+        let mut wide = packets[0].clone();
+        wide.sequence = u32::MAX;
+        wide.ack_number = u32::MAX - 1;
+        wide.flags |= 0xFFFF_0000;
+        assert!(encode(&wide, 32).is_none());
+        assert!(receiver.decode(&[0x10]).is_err());
+    }
+
+    #[test]
+    fn test_compact_input_reserializes_with_full_header() {
+        // Full flags spelled out, and the low 14 bits of the sequence
+        let data = [0x10 | COMPACT_FLAGS, 0x81, 0x01, 0x0C, 0x54];
+        let (packet, bits) = decode(&data).unwrap();
+        assert_eq!((packet.sequence, bits, packet.ack_number, packet.flags), (129, 14, u32::MAX, 0x0C));
+
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), PACKET_HEADER_SIZE + 1);
+        let again = UdpPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(again.to_bytes(), bytes);
+        assert_eq!(encode(&again, 14).unwrap(), data[..4]);
+    }
+
+    #[test]
+    fn test_sequences_sent_relative_to_the_last_ack() {
+        let (mut sender, mut receiver) = pair();
+        let header_len = |sender: &mut PacketManager, packet: &UdpPacket| sender.encode(packet).len() - packet.payload.len();
+
+        // Nothing ACKed yet: the receiver may still be anywhere from sequence 0
+        let packets: Vec<UdpPacket> = (0..1000).flat_map(|_| sender.create_packets(b"x")).collect();
+        assert_eq!(header_len(&mut sender, &packets[999]), 3);
+        for packet in &packets {
+            let mut received = receiver.decode(&sender.encode(packet)).unwrap();
+            assert!(receiver.open(&mut received));
+            assert_eq!(received.sequence, packet.sequence);
+            receiver.deliver(received);
+            sender.handle_ack_packet(&receiver.create_ack_for(packet));
+        }
+
+        // Everything ACKed: one byte of sequence, though 1000 takes two as a varint
+        let next = sender.create_packets(b"x").remove(0);
+        assert_eq!(next.sequence, 1000);
+        assert_eq!(header_len(&mut sender, &next), 2);
+        assert_eq!(receiver.decode(&sender.encode(&next)).unwrap().sequence, 1000);
+
+        // Unreliable packets are never ACKed, so they assume a bounded loss instead: here
+        // the receiver missed exactly `COMPACT_LOSS_HORIZON`, counting 1000
+        let unreliable: Vec<UdpPacket> = (0..COMPACT_LOSS_HORIZON).flat_map(|_| sender.create_packets_with_mode(b"x", SendMode::Unreliable)).collect();
+        let last = unreliable.last().unwrap();
+        assert_eq!(header_len(&mut sender, last), 3);
+        assert_eq!(receiver.decode(&sender.encode(last)).unwrap().sequence, last.sequence);
+    }
+
+    #[test]
+    fn test_sequence_expansion() {
+        assert_eq!(sequence_bits(0), 7);
+        assert_eq!(sequence_bits(63), 7);
+        assert_eq!(sequence_bits(64), 14);
+        assert_eq!(sequence_bits(1 << 27), 32);
+
+        assert_eq!(expand_sequence(5, 7, 3), 5);
+        assert_eq!(expand_sequence(127, 7, 130), 127);
+        assert_eq!(expand_sequence(2, 7, 126), 130);
+        // Across the wrap of the 32-bit sequence, both ways
+        assert_eq!(expand_sequence(5, 7, u32::MAX - 1), 5);
+        assert_eq!(expand_sequence(0x7F, 7, 2), u32::MAX);
+        assert_eq!(expand_sequence(0xDEAD_BEEF, 32, 0), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn test_compact_headers_refused_until_negotiated() {
+        let (mut sender, _) = pair();
+        let packet = sender.create_packets(b"hello").remove(0);
+        let bytes = sender.encode(&packet);
+
+        let mut receiver = PacketManager::new();
+        assert!(receiver.decode(&bytes).is_err());
+        assert!(UdpPacket::from_bytes(&bytes).is_err());
+        receiver.set_compact_headers(true);
+        assert_eq!(receiver.decode(&bytes).unwrap().payload, b"hello");
+    }
+
+    #[test]
+    fn test_compact_headers_on_encrypted_sessions() {
+        let (mut sender, mut receiver) = pair();
+        sender.set_cipher(Some(PacketCipher::derive(b"secret", 7, &[1; 32], &[2; 32], Role::Client)));
+        receiver.set_cipher(Some(PacketCipher::derive(b"secret", 7, &[1; 32], &[2; 32], Role::Server)));
+
+        // The full header is authenticated, so a rebuilt one must match it exactly
+        let packet = sender.create_packets(b"hello").remove(0);
+        let bytes = sender.encode(&packet);
+        assert_eq!(bytes.len(), packet.to_bytes().len() + crate::crypto::CIPHER_OVERHEAD - (PACKET_HEADER_SIZE - 2));
+        let mut received = receiver.decode(&bytes).unwrap();
+        assert!(receiver.open(&mut received));
+        assert_eq!(received.payload, b"hello");
+        assert_eq!(receiver.stats().bytes_received, bytes.len() as u64);
+    }
+}
@@ -0,0 +1,213 @@
+//! BiWi over HTTP
+//! Helpers for carrying messages as HTTP bodies, so services can adopt BiWi behind
+//! existing REST gateways one endpoint at a time. BiWi bodies are sent as
+//! `application/x-biwi`; JSON (`application/json`, the serde form of `BiWiMessage`)
+//! is accepted and served too, for clients that haven't switched yet.
+//!
+//! The functions here work on header strings and byte slices, so they fit any HTTP
+//! library. With the `http` feature, `response`, `request` and `decode_request` /
+//! `decode_response` build and read the `http` crate's types directly, as used by
+//! hyper, axum and reqwest.
+
+use crate::decoder::DecodeError;
+use crate::message::BiWiMessage;
+
+/// Media type of BiWi-encoded bodies
+pub const CONTENT_TYPE: &str = "application/x-biwi";
+/// Media type of JSON bodies
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// How a body is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    BiWi,
+    Json,
+}
+
+/// Why a body couldn't be read
+#[derive(Debug)]
+pub enum BodyError {
+    /// The `Content-Type` is neither BiWi nor JSON
+    UnsupportedMediaType(String),
+    /// The BiWi body is malformed
+    Decode(DecodeError),
+    /// The JSON body is malformed
+    Json(serde_json::Error),
+}
+
+impl BodyError {
+    /// The HTTP status to answer with: 415 for the media type, 400 otherwise
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnsupportedMediaType(_) => 415,
+            Self::Decode(_) | Self::Json(_) => 400,
+        }
+    }
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedMediaType(media_type) => write!(f, "Unsupported media type {}", media_type),
+            Self::Decode(error) => write!(f, "Invalid BiWi body: {}", error),
+            Self::Json(error) => write!(f, "Invalid JSON body: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl BodyFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::BiWi => CONTENT_TYPE,
+            Self::Json => JSON_CONTENT_TYPE,
+        }
+    }
+
+    /// The format a `Content-Type` header names; a missing header means BiWi
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self, BodyError> {
+        let Some(content_type) = content_type else {
+            return Ok(Self::BiWi);
+        };
+        match media_type(content_type) {
+            media if media.eq_ignore_ascii_case(CONTENT_TYPE) => Ok(Self::BiWi),
+            media if media.eq_ignore_ascii_case(JSON_CONTENT_TYPE) => Ok(Self::Json),
+            media => Err(BodyError::UnsupportedMediaType(media.to_string())),
+        }
+    }
+
+    /// The format to answer a request with, from its `Accept` header: BiWi unless
+    /// the client accepts JSON and not BiWi
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let Some(accept) = accept else {
+            return Self::BiWi;
+        };
+        let accepted: Vec<&str> = accept.split(',').filter(|range| !refused(range)).map(media_type).collect();
+        let accepts = |media: &str| accepted.iter().any(|range| range.eq_ignore_ascii_case(media));
+        if accepts(JSON_CONTENT_TYPE) && !accepts(CONTENT_TYPE) && !accepts("*/*") && !accepts("application/*") {
+            Self::Json
+        } else {
+            Self::BiWi
+        }
+    }
+}
+
+/// The media type of a header value, without parameters
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+/// Whether a media range carries `q=0`
+fn refused(range: &str) -> bool {
+    range.split(';').skip(1).any(|param| {
+        let param = param.trim();
+        param.strip_prefix("q=").is_some_and(|q| q.parse::<f32>().is_ok_and(|q| q == 0.0))
+    })
+}
+
+/// Encode `message` as a body in `format`
+pub fn encode_body(message: &BiWiMessage, format: BodyFormat) -> Vec<u8> {
+    match format {
+        BodyFormat::BiWi => message.to_vec(),
+        // Every field value has a JSON form
+        BodyFormat::Json => serde_json::to_vec(message).expect("BiWiMessage serializes to JSON"),
+    }
+}
+
+/// Decode a body according to its `Content-Type` header
+pub fn decode_body(content_type: Option<&str>, body: &[u8]) -> Result<BiWiMessage, BodyError> {
+    match BodyFormat::from_content_type(content_type)? {
+        BodyFormat::BiWi => BiWiMessage::from_buffer(body).map_err(BodyError::Decode),
+        BodyFormat::Json => serde_json::from_slice(body).map_err(BodyError::Json),
+    }
+}
+
+#[cfg(feature = "http")]
+mod typed {
+    use super::*;
+    use ::http::header::{ACCEPT, CONTENT_TYPE as CONTENT_TYPE_HEADER};
+    use ::http::{HeaderMap, Method, Request, Response, StatusCode};
+
+    fn header(headers: &HeaderMap, name: ::http::header::HeaderName) -> Option<&str> {
+        headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// A 200 response carrying `message` in the format the request's headers accept
+    pub fn response(request_headers: &HeaderMap, message: &BiWiMessage) -> Response<Vec<u8>> {
+        let format = BodyFormat::negotiate(header(request_headers, ACCEPT));
+        Response::builder()
+            .header(CONTENT_TYPE_HEADER, format.content_type())
+            .body(encode_body(message, format))
+            .expect("static headers are valid")
+    }
+
+    /// A plain-text response for a body that couldn't be read
+    pub fn error_response(error: &BodyError) -> Response<Vec<u8>> {
+        Response::builder()
+            .status(StatusCode::from_u16(error.status()).expect("valid status"))
+            .header(CONTENT_TYPE_HEADER, "text/plain; charset=utf-8")
+            .body(error.to_string().into_bytes())
+            .expect("static headers are valid")
+    }
+
+    /// A request carrying `message` as a BiWi body that asks for a BiWi answer
+    pub fn request(method: Method, uri: &str, message: &BiWiMessage) -> ::http::Result<Request<Vec<u8>>> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE_HEADER, CONTENT_TYPE)
+            .header(ACCEPT, CONTENT_TYPE)
+            .body(message.to_vec())
+    }
+
+    pub fn decode_request<B: AsRef<[u8]>>(request: &Request<B>) -> Result<BiWiMessage, BodyError> {
+        decode_body(header(request.headers(), CONTENT_TYPE_HEADER), request.body().as_ref())
+    }
+
+    pub fn decode_response<B: AsRef<[u8]>>(response: &Response<B>) -> Result<BiWiMessage, BodyError> {
+        decode_body(header(response.headers(), CONTENT_TYPE_HEADER), response.body().as_ref())
+    }
+}
+
+#[cfg(feature = "http")]
+pub use typed::{decode_request, decode_response, error_response, request, response};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::BiWiValue;
+
+    #[test]
+    fn test_bodies_and_negotiation() {
+        let message = BiWiMessage::builder().field(1, "order").field(2, BiWiValue::Int32(3)).build();
+        for format in [BodyFormat::BiWi, BodyFormat::Json] {
+            let body = encode_body(&message, format);
+            let content_type = format!("{}; charset=utf-8", format.content_type().to_uppercase());
+            assert_eq!(decode_body(Some(&content_type), &body).unwrap(), message);
+        }
+        assert_eq!(decode_body(None, &message.to_vec()).unwrap(), message);
+        assert_eq!(decode_body(Some("text/plain"), b"hi").unwrap_err().status(), 415);
+        assert_eq!(decode_body(Some(CONTENT_TYPE), &[0xFF]).unwrap_err().status(), 400);
+
+        assert_eq!(BodyFormat::negotiate(None), BodyFormat::BiWi);
+        assert_eq!(BodyFormat::negotiate(Some("application/json")), BodyFormat::Json);
+        assert_eq!(BodyFormat::negotiate(Some("application/json, */*;q=0.1")), BodyFormat::BiWi);
+        assert_eq!(BodyFormat::negotiate(Some("application/json, application/x-biwi;q=0")), BodyFormat::Json);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_http_types() {
+        let message = BiWiMessage::builder().field(1, "order").build();
+        let request = request(::http::Method::POST, "/orders", &message).unwrap();
+        assert_eq!(decode_request(&request).unwrap(), message);
+
+        let mut json_client = ::http::HeaderMap::new();
+        json_client.insert(::http::header::ACCEPT, JSON_CONTENT_TYPE.parse().unwrap());
+        let answer = response(&json_client, &message);
+        assert_eq!(answer.headers()[::http::header::CONTENT_TYPE], JSON_CONTENT_TYPE);
+        assert_eq!(decode_response(&answer).unwrap(), message);
+        assert_eq!(error_response(&decode_body(Some("text/csv"), b"").unwrap_err()).status(), 415);
+    }
+}
@@ -0,0 +1,78 @@
+//! BiWi Interceptors
+//! Hooks that see every message a client or server sends and receives, for auth
+//! tokens, tracing IDs, metrics or field-level encryption without touching the
+//! transport. Interceptors stack: outgoing messages pass through them in the order
+//! they were added and incoming ones in reverse, so each layer undoes its own work
+//! on the way in.
+//!
+//! Already-encoded messages (`SharedMessage`, chunk streams) bypass the hooks, and
+//! a server's fan-out sends (broadcast, groups, topics) run them once for all
+//! recipients, with no peer in the context.
+
+use crate::message::BiWiMessage;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Where a message is going or came from
+#[derive(Debug, Clone, Copy)]
+pub struct MessageContext<'a> {
+    /// The remote address; `None` for fan-out sends
+    pub peer: Option<SocketAddr>,
+    /// On a server, the client's connection ID; `None` on a client and for fan-out sends
+    pub client_id: Option<&'a str>,
+}
+
+/// A layer of message processing on a client or server
+pub trait Interceptor: Send {
+    /// Adjust a message before it is encoded and sent
+    fn on_outgoing(&mut self, _message: &mut BiWiMessage, _ctx: &MessageContext<'_>) {}
+
+    /// Adjust a message after it is decoded; return false to drop it
+    fn on_incoming(&mut self, _message: &mut BiWiMessage, _ctx: &MessageContext<'_>) -> bool {
+        true
+    }
+}
+
+/// The interceptors installed on one client or server, innermost last
+#[derive(Default)]
+pub(crate) struct Interceptors(Vec<Box<dyn Interceptor>>);
+
+pub(crate) type SharedInterceptors = Arc<Mutex<Interceptors>>;
+
+impl Interceptors {
+    pub fn push(&mut self, interceptor: Box<dyn Interceptor>) {
+        self.0.push(interceptor);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Run `message` through every layer and encode the result
+    pub fn outgoing(&mut self, message: &BiWiMessage, ctx: &MessageContext<'_>) -> Vec<u8> {
+        self.apply_outgoing(message, ctx).to_vec()
+    }
+
+    /// Run `message` through every layer, copying it only if there are any
+    pub fn apply_outgoing<'m>(&mut self, message: &'m BiWiMessage, ctx: &MessageContext<'_>) -> Cow<'m, BiWiMessage> {
+        if self.0.is_empty() {
+            return Cow::Borrowed(message);
+        }
+        let mut message = message.clone();
+        for interceptor in &mut self.0 {
+            interceptor.on_outgoing(&mut message, ctx);
+        }
+        Cow::Owned(message)
+    }
+
+    /// Run `message` back through every layer; `None` if one of them dropped it
+    pub fn incoming(&mut self, mut message: BiWiMessage, ctx: &MessageContext<'_>) -> Option<BiWiMessage> {
+        for interceptor in self.0.iter_mut().rev() {
+            if !interceptor.on_incoming(&mut message, ctx) {
+                return None;
+            }
+        }
+        Some(message)
+    }
+}
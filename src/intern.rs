@@ -0,0 +1,131 @@
+// BiWi Object Key Interning
+// Encoder and decoder keep matching tables of object keys across the messages
+// of one connection direction. The first time a key is written it travels as a
+// string and gets the next ID; later objects send only the ID.
+//
+// Both tables only stay in step if every interned message is decoded, once and
+// in the order it was encoded, so this belongs on reliable ordered transports
+// (TCP, or a reliable ordered UDP channel). Start both sides from empty tables,
+// and `clear` them together when a connection is re-established.
+
+use std::collections::HashMap;
+
+/// Keys a table holds unless built with `KeyTable::with_max_keys`
+pub const DEFAULT_MAX_KEYS: usize = 4096;
+
+/// Key reference tags on the wire: a literal key that takes the next ID, a literal
+/// key the table had no room for, or `KEY_REF_BASE + id`
+pub(crate) const KEY_NEW: u32 = 0;
+pub(crate) const KEY_LITERAL: u32 = 1;
+pub(crate) const KEY_REF_BASE: u32 = 2;
+
+/// Object keys seen so far on one direction of a connection
+#[derive(Debug, Clone)]
+pub struct KeyTable {
+    ids: HashMap<String, u32>,
+    keys: Vec<String>,
+    max_keys: usize,
+}
+
+impl Default for KeyTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyTable {
+    /// Create an empty table holding up to `DEFAULT_MAX_KEYS` keys
+    pub fn new() -> Self {
+        Self::with_max_keys(DEFAULT_MAX_KEYS)
+    }
+
+    /// Create an empty table holding up to `max_keys` keys. Once full, new keys
+    /// are sent as strings every time. The decoding side must allow at least as
+    /// many keys as the encoding side.
+    pub fn with_max_keys(max_keys: usize) -> Self {
+        Self {
+            ids: HashMap::new(),
+            keys: Vec::new(),
+            max_keys: max_keys.min((u32::MAX - KEY_REF_BASE) as usize),
+        }
+    }
+
+    /// Number of interned keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Forget every key, e.g. when the connection is re-established
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.keys.clear();
+    }
+
+    pub(crate) fn id(&self, key: &str) -> Option<u32> {
+        self.ids.get(key).copied()
+    }
+
+    pub(crate) fn key(&self, id: u32) -> Option<&str> {
+        self.keys.get(id as usize).map(String::as_str)
+    }
+
+    /// Give `key` the next ID, unless the table is full
+    pub(crate) fn insert(&mut self, key: &str) -> Option<u32> {
+        if self.keys.len() >= self.max_keys {
+            return None;
+        }
+        let id = self.keys.len() as u32;
+        self.ids.insert(key.to_owned(), id);
+        self.keys.push(key.to_owned());
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BiWiMessage, BiWiValue, DecodeError};
+    use std::collections::HashMap;
+
+    fn reading(x: f64) -> BiWiMessage {
+        let mut sensor = HashMap::new();
+        sensor.insert("temperature".to_string(), BiWiValue::Float64(x));
+        sensor.insert("humidity".to_string(), BiWiValue::Int32(40));
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::Array(vec![BiWiValue::Object(sensor.clone()), BiWiValue::Object(sensor)]));
+        msg
+    }
+
+    #[test]
+    fn test_keys_sent_once_per_connection() {
+        let (mut sender, mut receiver) = (KeyTable::new(), KeyTable::new());
+
+        let first = reading(1.0).to_vec_interned(&mut sender);
+        let second = reading(2.0).to_vec_interned(&mut sender);
+        assert_eq!(sender.len(), 2);
+        // Each object sends a one-byte ID per key in place of the key's length and text
+        assert_eq!(reading(2.0).to_vec().len() - second.len(), 2 * "temperaturehumidity".len());
+        assert!(second.len() < first.len());
+
+        assert_eq!(BiWiMessage::from_buffer_interned(&first, &mut receiver).unwrap(), reading(1.0));
+        assert_eq!(BiWiMessage::from_buffer_interned(&second, &mut receiver).unwrap(), reading(2.0));
+
+        // Without the table the IDs mean nothing
+        assert!(matches!(BiWiMessage::from_buffer(&second), Err(DecodeError::InvalidData(_))));
+        // A receiver that missed the first message can't resolve the IDs
+        assert!(BiWiMessage::from_buffer_interned(&second, &mut KeyTable::new()).is_err());
+
+        // A full table keeps sending new keys as strings
+        let mut small = KeyTable::with_max_keys(1);
+        let mut small_receiver = KeyTable::with_max_keys(1);
+        for x in [1.0, 2.0] {
+            let bytes = reading(x).to_vec_interned(&mut small);
+            assert_eq!(BiWiMessage::from_buffer_interned(&bytes, &mut small_receiver).unwrap(), reading(x));
+        }
+        assert_eq!(small.len(), 1);
+    }
+}
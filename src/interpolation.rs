@@ -0,0 +1,187 @@
+//! BiWi Snapshot Interpolation
+//! Client-side smoothing for replicated state. A `SnapshotBuffer` keeps the latest
+//! timestamped states of one entity and `sample(render_time)` blends the two around
+//! `render_time`: numeric fields are interpolated linearly, everything else steps to
+//! the newer value once it is reached. Past the newest state the last two are
+//! extrapolated, for at most `max_extrapolation`.
+//!
+//! Render a little behind the newest timestamp (typically two or three send
+//! intervals) so there is usually a later state to interpolate towards.
+
+use crate::encoder::BiWiValue;
+use crate::message::BiWiMessage;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// States a buffer keeps by default
+pub const DEFAULT_SNAPSHOT_CAPACITY: usize = 32;
+
+/// How far past the newest state `sample` extrapolates by default
+pub const DEFAULT_MAX_EXTRAPOLATION: Duration = Duration::from_millis(250);
+
+/// Timestamped states, oldest first
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<(Duration, BiWiMessage)>,
+    capacity: usize,
+    max_extrapolation: Duration,
+}
+
+impl Default for SnapshotBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_SNAPSHOT_CAPACITY)
+    }
+
+    /// Keep at most `capacity` states (at least 2)
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            capacity: capacity.max(2),
+            max_extrapolation: DEFAULT_MAX_EXTRAPOLATION,
+        }
+    }
+
+    /// Extrapolate at most this far past the newest state (`Duration::ZERO` holds it)
+    pub fn with_max_extrapolation(mut self, limit: Duration) -> Self {
+        self.max_extrapolation = limit;
+        self
+    }
+
+    /// Add a state taken at `timestamp` (any clock, as long as it is the one `sample`
+    /// is given). Late arrivals are slotted into place; a state with the same
+    /// timestamp as one already held replaces it.
+    pub fn push(&mut self, timestamp: Duration, state: BiWiMessage) {
+        let at = self.snapshots.partition_point(|(t, _)| *t < timestamp);
+        match self.snapshots.get_mut(at) {
+            Some((t, existing)) if *t == timestamp => *existing = state,
+            _ => self.snapshots.insert(at, (timestamp, state)),
+        }
+        if self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Add a state stamped by its sender (`Envelope::with_timestamp_now`); timestamps
+    /// are milliseconds since the Unix epoch. Returns false if it has no timestamp.
+    pub fn push_stamped(&mut self, state: BiWiMessage) -> bool {
+        let Some(timestamp) = state.envelope().and_then(|e| e.timestamp) else {
+            return false;
+        };
+        self.push(Duration::from_millis(timestamp), state);
+        true
+    }
+
+    /// The state at `render_time`; `None` while the buffer is empty
+    pub fn sample(&self, render_time: Duration) -> Option<BiWiMessage> {
+        let (first_time, first) = self.snapshots.front()?;
+        if render_time <= *first_time || self.snapshots.len() == 1 {
+            return Some(first.clone());
+        }
+
+        let after = self.snapshots.partition_point(|(t, _)| *t <= render_time);
+        let (from, to, time) = if after < self.snapshots.len() {
+            (&self.snapshots[after - 1], &self.snapshots[after], render_time)
+        } else {
+            // Past the newest state: carry on along the last two
+            let last = self.snapshots.len() - 1;
+            let newest = self.snapshots[last].0;
+            (&self.snapshots[last - 1], &self.snapshots[last], render_time.min(newest + self.max_extrapolation))
+        };
+        let span = (to.0 - from.0).as_secs_f64();
+        let fraction = (time.as_secs_f64() - from.0.as_secs_f64()) / span;
+        Some(blend(&from.1, &to.1, fraction))
+    }
+
+    /// Timestamp of the newest state
+    pub fn latest_time(&self) -> Option<Duration> {
+        self.snapshots.back().map(|(t, _)| *t)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+/// `from` moved `fraction` of the way to `to` (beyond it when `fraction` > 1)
+fn blend(from: &BiWiMessage, to: &BiWiMessage, fraction: f64) -> BiWiMessage {
+    // Non-numeric fields, and fields only one side has, change once `to` is reached
+    let mut state = if fraction < 1.0 { from.clone() } else { to.clone() };
+    for (field_id, value) in to.iter() {
+        if let Some(blended) = from.get_field(field_id).and_then(|start| lerp(start, value, fraction)) {
+            state.set_field(field_id, blended);
+        }
+    }
+    state
+}
+
+/// Linear interpolation between two numeric values, keeping their kind
+fn lerp(from: &BiWiValue, to: &BiWiValue, fraction: f64) -> Option<BiWiValue> {
+    let (a, b) = (from.as_f64()?, to.as_f64()?);
+    let value = a + (b - a) * fraction;
+    Some(match (from, to) {
+        (BiWiValue::Float32(_), BiWiValue::Float32(_)) => BiWiValue::Float32(value as f32),
+        (BiWiValue::Float64(_), _) | (_, BiWiValue::Float64(_)) => BiWiValue::Float64(value),
+        (BiWiValue::Int32(_), BiWiValue::Int32(_)) => BiWiValue::Int32(value.round() as i32),
+        (BiWiValue::Int32(_) | BiWiValue::Int64(_), BiWiValue::Int32(_) | BiWiValue::Int64(_)) => {
+            BiWiValue::Int64(value.round() as i64)
+        }
+        // Mixed integers and floats come from `BiWiValue::number` narrowing
+        _ => BiWiValue::number(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(x: f32, hp: i32, name: &str) -> BiWiMessage {
+        BiWiMessage::builder()
+            .field(1, BiWiValue::Float32(x))
+            .field(2, BiWiValue::Int32(hp))
+            .field(3, name)
+            .build()
+    }
+
+    #[test]
+    fn test_sample_interpolates_and_extrapolates() {
+        let ms = Duration::from_millis;
+        let mut buffer = SnapshotBuffer::new().with_max_extrapolation(ms(50));
+        assert_eq!(buffer.sample(ms(0)), None);
+        buffer.push(ms(200), state(20.0, 80, "b"));
+        // Arrives late, still slotted before the other
+        buffer.push(ms(100), state(10.0, 100, "a"));
+
+        let mid = buffer.sample(ms(125)).unwrap();
+        assert_eq!(mid.get_field(1), Some(&BiWiValue::Float32(12.5)));
+        assert_eq!(mid.get_i32(2), Some(95));
+        // Non-numeric fields step at the newer state
+        assert_eq!(mid.get_str(3), Some("a"));
+        assert_eq!(buffer.sample(ms(200)).unwrap(), state(20.0, 80, "b"));
+
+        // Before the oldest state: hold it; past the newest: extrapolate, capped
+        assert_eq!(buffer.sample(ms(0)).unwrap(), state(10.0, 100, "a"));
+        let ahead = buffer.sample(ms(400)).unwrap();
+        assert_eq!(ahead.get_field(1), Some(&BiWiValue::Float32(25.0)));
+        assert_eq!(ahead.get_str(3), Some("b"));
+
+        let mut small = SnapshotBuffer::with_capacity(2);
+        for t in 0..5 {
+            small.push(ms(t * 10), state(t as f32, 0, "x"));
+        }
+        assert_eq!(small.len(), 2);
+        assert_eq!(small.latest_time(), Some(ms(40)));
+    }
+}
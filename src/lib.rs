@@ -3,23 +3,103 @@
 //! incremental data transmission. Optimized for real-time applications,
 //! game networking, and microservices.
 
+#[macro_use]
+mod trace;
+
 // Core modules
 pub mod types;
+pub mod auth;
 pub mod encoder;
 pub mod decoder;
+pub mod envelope;
 pub mod message;
+pub mod intern;
+pub mod compression;
+pub mod view;
+pub mod pool;
+pub mod shared;
+pub mod chunk;
+pub mod validation;
+pub mod coalesce;
+pub mod congestion;
+pub mod telemetry;
+pub mod clock;
+pub mod crypto;
+pub mod header;
 pub mod network;
+pub mod receipt;
+pub mod mtu;
+pub mod ratelimit;
 pub mod server;
+pub mod group;
+pub mod topics;
+pub mod replication;
+pub mod interpolation;
+pub mod intercept;
+pub mod handler;
+pub mod workers;
+pub mod rpc;
+pub mod router;
 pub mod client;
+pub mod tcp;
+pub mod gateway;
+pub mod http;
+pub mod capture;
+pub mod dissector;
+pub mod transport;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod gossip;
+pub mod conformance;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod reader;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 // Re-exports for convenience
-pub use types::BiWiType;
-pub use encoder::{BiWiEncoder, BiWiValue};
-pub use decoder::{BiWiDecoder, DecodeError, DecodeResult, DecodedField, ChunkStart, ChunkData};
-pub use message::BiWiMessage;
-pub use network::{PacketManager, UdpPacket, PacketType};
-pub use server::BiWiUdpServer;
-pub use client::BiWiUdpClient;
+pub use types::{BiWiType, ByteOrder};
+pub use encoder::{BiWiEncoder, BiWiValue, EncodePolicy, FloatPolicy, PackedElement};
+pub use decoder::{BiWiDecoder, DecodeError, DecodeLimits, DecodeResult, DecodedField, ChunkStart, ChunkData, ChunkFrame, ChunkTransferStart, ChunkResume};
+pub use envelope::Envelope;
+pub use intern::KeyTable;
+pub use compression::{FieldCompressor, FieldTable};
+pub use message::{BiWiDelta, BiWiMessage, BiWiMessageBuilder, FieldFlags, MergeStrategy, SizeBudgetExceeded};
+pub use view::BiWiMessageView;
+pub use pool::MessagePool;
+pub use shared::SharedMessage;
+pub use chunk::{AssembledField, ChunkAssembler, ChunkProgress, ChunkWriter};
+pub use validation::{MessageSpec, ValueKind, Violation};
+pub use coalesce::Coalescer;
+pub use congestion::{CongestionController, TokenBucketAimd};
+pub use clock::{ClockEstimate, ClockSync};
+pub use crypto::PacketCipher;
+pub use mtu::MtuProbe;
+pub use network::{Backoff, ConnectionStats, PacketManager, RetransmitPolicy, UdpPacket, PacketType, Priority, SendMode, DEFAULT_CHANNEL, STREAM_CHANNEL};
+pub use receipt::{SendHandle, SendOutcome};
+pub use ratelimit::{LimitExceeded, RateLimit};
+pub use server::{BiWiUdpServer, ClientSender, RetransmitTimer, ServerConfig, ServerEvent, StreamUpdate};
+pub use group::Group;
+pub use topics::Topic;
+pub use replication::{Replica, Replicator};
+pub use interpolation::SnapshotBuffer;
+pub use intercept::{Interceptor, MessageContext};
+pub use capture::{Capture, CaptureReader, CapturedMessage, CapturedPacket};
+pub use handler::{BiWiServerHandler, Connection, DisconnectReason, StopHandle};
+pub use workers::WorkerPool;
+pub use rpc::{RpcClient, RpcDispatcher, RpcError, RpcMessage, RpcMethod};
+pub use router::{RouteBy, Router};
+pub use client::{BiWiUdpClient, ClientConfig, ClientEvent};
+pub use tcp::{BiWiTcpClient, BiWiTcpServer, TcpEvent};
+pub use gateway::{FrontEndKind, Gateway, GatewaySession};
+pub use transport::{BiWiTransport, InProcess, TransportStats};
+#[cfg(feature = "tokio")]
+pub use async_server::BiWiUdpServerAsync;
+#[cfg(feature = "tokio")]
+pub use async_client::BiWiUdpClientAsync;
+pub use gossip::{GossipEvent, GossipNode};
 
 /// BiWi protocol version
 pub const VERSION: &str = "0.1.0";
@@ -66,4 +146,103 @@ mod tests {
         assert_eq!(field2.field_id, 200);
         assert_eq!(field2.value, BiWiValue::Boolean(true));
     }
+
+    #[test]
+    fn test_encode_policy() {
+        assert_eq!(BiWiValue::number(0.5), BiWiValue::Float32(0.5));
+        assert_eq!(BiWiValue::number_with_policy(0.5, EncodePolicy::Exact), BiWiValue::Float64(0.5));
+        assert_eq!(BiWiValue::number_with_policy(-0.0, EncodePolicy::Exact), BiWiValue::Float64(-0.0));
+        assert_eq!(BiWiValue::number_with_policy(42.0, EncodePolicy::Exact), BiWiValue::Int32(42));
+        assert_eq!(BiWiValue::number(9_223_372_036_854_775_808.0), BiWiValue::Float64(9_223_372_036_854_775_808.0));
+
+        // 0.1 is not exactly representable; a loose tolerance accepts the f32 error
+        let lossy = EncodePolicy::LossyTolerance(1e-3);
+        assert_eq!(BiWiValue::number_with_policy(1e7 + 0.1, lossy), BiWiValue::Float32((1e7 + 0.1) as f32));
+        assert_eq!(BiWiValue::number(1e7 + 0.1), BiWiValue::Float64(1e7 + 0.1));
+        assert!(matches!(BiWiValue::number_with_policy(f64::NAN, lossy), BiWiValue::Float64(_)));
+
+        // encode_number leaves the float width to the encoder's FloatPolicy
+        let mut exact = BiWiEncoder::new();
+        exact.encode_number(1, 123.456);
+        let mut auto = BiWiEncoder::new().with_float_policy(FloatPolicy::Auto32);
+        auto.encode_number(1, 123.456);
+        assert_eq!(exact.size(), auto.size() + 4);
+        let decoded = BiWiDecoder::new(exact.as_slice()).decode_field().unwrap();
+        assert_eq!(decoded.value, BiWiValue::Float64(123.456));
+    }
+
+    #[test]
+    fn test_float_policy() {
+        let mut inner = std::collections::HashMap::new();
+        inner.insert("x".into(), BiWiValue::Float64(0.1));
+        inner.insert("big".into(), BiWiValue::Float64(1e7 + 0.1));
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::Float64(0.5));
+        msg.set_field(2, BiWiValue::Array(vec![BiWiValue::Object(inner), BiWiValue::Float64(2.25)]));
+        msg.set_field(3, BiWiValue::Float64Array(vec![0.5, 1.5]));
+        msg.set_field(4, BiWiValue::Array(vec![BiWiValue::Float64(0.5), BiWiValue::Float64(1e7 + 0.1)]));
+
+        // The default keeps every width, nested or not
+        assert_eq!(msg.to_vec(), msg.to_vec_with_float_policy(FloatPolicy::Exact64));
+        assert_eq!(BiWiMessage::from_buffer(&msg.to_vec()).unwrap().get_field(1), Some(&BiWiValue::Float64(0.5)));
+
+        let auto = msg.to_vec_with_float_policy(FloatPolicy::Auto32);
+        assert!(auto.len() < msg.to_vec().len());
+        let decoded = BiWiMessage::from_buffer(&auto).unwrap();
+        assert_eq!(decoded.get_field(1), Some(&BiWiValue::Float32(0.5)));
+        let Some(BiWiValue::Array(items)) = decoded.get_field(2) else { panic!("expected array") };
+        let Some(BiWiValue::Object(obj)) = items.first() else { panic!("expected object") };
+        assert_eq!(obj.get("x"), Some(&BiWiValue::Float32(0.1)));
+        assert_eq!(obj.get("big"), Some(&BiWiValue::Float64(1e7 + 0.1)));
+        assert_eq!(items.get(1), Some(&BiWiValue::Float32(2.25)));
+        assert_eq!(decoded.get_field(3), Some(&BiWiValue::Array(vec![BiWiValue::Float32(0.5), BiWiValue::Float32(1.5)])));
+        // One element that needs f64 keeps the whole packed array at f64
+        assert_eq!(decoded.get_field(4), msg.get_field(4));
+
+        let quantized = BiWiMessage::from_buffer(&msg.to_vec_with_float_policy(FloatPolicy::Quantized(0.25))).unwrap();
+        let Some(BiWiValue::Array(items)) = quantized.get_field(2) else { panic!("expected array") };
+        let Some(BiWiValue::Object(obj)) = items.first() else { panic!("expected object") };
+        assert_eq!(obj.get("x"), Some(&BiWiValue::Float32(0.0)));
+        assert_eq!(obj.get("big"), Some(&BiWiValue::Float32(1e7)));
+    }
+
+    #[test]
+    fn test_sparse_encoding() {
+        let mut msg = BiWiMessage::new();
+        for id in 1..=40 {
+            msg.set_field(id, BiWiValue::Null);
+        }
+        msg.set_field(7, BiWiValue::Int32(-300));
+        msg.set_field(33, BiWiValue::from("pos"));
+
+        let sparse = msg.to_vec_sparse();
+        assert!(sparse.len() < msg.to_vec().len());
+
+        let decoded = BiWiMessage::from_buffer_sparse(&sparse).unwrap();
+        assert_eq!(decoded.field_count(), 40);
+        assert_eq!(decoded.get_field(7), Some(&BiWiValue::Int32(-300)));
+        assert_eq!(decoded.get_field(33), Some(&BiWiValue::from("pos")));
+        assert_eq!(decoded.get_field(12), Some(&BiWiValue::Null));
+        assert_eq!(decoded.get_field(41), None);
+
+        let empty = BiWiMessage::from_buffer_sparse(&BiWiMessage::new().to_vec_sparse()).unwrap();
+        assert_eq!(empty.field_count(), 0);
+    }
+
+    #[test]
+    fn test_sparse_encoding_of_spread_ids() {
+        // A bitmap over a wide range would cost far more than the field headers
+        let msg = biwi_msg! { 1 => 1, 2_000_000_000 => 2 };
+        let sparse = msg.to_vec_sparse();
+        assert_eq!(sparse.len(), msg.to_vec().len() + 1);
+        assert_eq!(BiWiMessage::from_buffer_sparse(&sparse).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_sparse_encoding_of_full_id_range() {
+        // The range 0..=u32::MAX has more IDs than a u32 span can count
+        let msg = biwi_msg! { 0 => BiWiValue::Null, u32::MAX => "last" };
+        let decoded = BiWiMessage::from_buffer_sparse(&msg.to_vec_sparse()).unwrap();
+        assert_eq!(decoded, msg);
+    }
 }
@@ -1,3 +1,3 @@
 fn main() {
-    println!("BiWi is a library crate. Run `cargo test` or `cargo run --bin benchmark` for benchmarks.");
+    println!("BiWi is a library crate. Run `cargo test`, `cargo run --bin benchmark` for benchmarks, or `cargo run --bin biwi-cli` for the command-line tool.");
 }
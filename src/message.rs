@@ -2,36 +2,255 @@
 // Represents a complete BiWi message with fields
 
 use crate::decoder::{BiWiDecoder, DecodeResult};
-use crate::encoder::{BiWiEncoder, BiWiValue};
-use std::collections::HashMap;
+use crate::encoder::{BiWiEncoder, BiWiValue, FloatPolicy};
+use crate::envelope::Envelope;
+use crate::compression::FieldTable;
+use crate::intern::KeyTable;
+use crate::telemetry;
+use crate::validation::{MessageSpec, Violation};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, Range};
 
-/// BiWi message containing multiple fields
+/// BiWi message containing multiple fields, kept in ascending field-ID order
 pub struct BiWiMessage {
-    fields: HashMap<u32, BiWiValue>,
-    cached_buffer: Option<Vec<u8>>,
+    fields: BTreeMap<u32, BiWiValue>,
+    envelope: Option<Envelope>,
+    /// Per-field metadata (not encoded)
+    flags: BTreeMap<u32, FieldFlags>,
+    cache: Option<EncodedCache>,
+}
+
+/// Metadata flags attached to a field ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct FieldFlags(u8);
+
+impl FieldFlags {
+    pub const NONE: FieldFlags = FieldFlags(0);
+    /// Value is redacted from `Debug` output
+    pub const SENSITIVE: FieldFlags = FieldFlags(0x01);
+    /// Field is scheduled for removal; see `BiWiMessage::strip_deprecated`
+    pub const DEPRECATED: FieldFlags = FieldFlags(0x02);
+
+    pub fn contains(self, other: FieldFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for FieldFlags {
+    type Output = FieldFlags;
+
+    fn bitor(self, rhs: FieldFlags) -> FieldFlags {
+        FieldFlags(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for FieldFlags {
+    fn bitor_assign(&mut self, rhs: FieldFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Encoded form of a message with the byte range of every field, so that
+/// `to_buffer` can splice in just the fields that changed since the last encode
+#[derive(Debug, Default)]
+struct EncodedCache {
+    buffer: Vec<u8>,
+    /// Bytes taken by the envelope ahead of the first field
+    header_len: usize,
+    segments: BTreeMap<u32, Range<usize>>,
+    dirty: BTreeSet<u32>,
+}
+
+impl EncodedCache {
+    fn build(envelope: Option<&Envelope>, fields: &BTreeMap<u32, BiWiValue>) -> Self {
+        let mut encoder = BiWiEncoder::new();
+        if let Some(envelope) = envelope {
+            encoder.encode_envelope(envelope);
+        }
+        let header_len = encoder.size();
+        let mut segments = BTreeMap::new();
+        for (field_id, value) in fields {
+            let start = encoder.size();
+            encoder.encode_field(*field_id, value);
+            segments.insert(*field_id, start..encoder.size());
+        }
+        Self {
+            buffer: encoder.to_buffer(),
+            header_len,
+            segments,
+            dirty: BTreeSet::new(),
+        }
+    }
+
+    /// Become the (empty) encoding of an empty message without freeing the buffer
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.header_len = 0;
+        self.segments.clear();
+        self.dirty.clear();
+    }
+
+    /// Re-encode dirty fields in place, shifting the ranges of the fields after them
+    fn refresh(&mut self, fields: &BTreeMap<u32, BiWiValue>) {
+        for field_id in std::mem::take(&mut self.dirty) {
+            let bytes = match fields.get(&field_id) {
+                Some(value) => {
+                    let mut encoder = BiWiEncoder::new();
+                    encoder.encode_field(field_id, value);
+                    encoder.to_buffer()
+                }
+                None => Vec::new(),
+            };
+
+            let old = match self.segments.get(&field_id) {
+                Some(range) => range.clone(),
+                None => {
+                    // New field: insert right after the previous field in ID order
+                    let at = self
+                        .segments
+                        .range(..field_id)
+                        .next_back()
+                        .map_or(self.header_len, |(_, r)| r.end);
+                    at..at
+                }
+            };
+            let (old_len, new_len) = (old.len(), bytes.len());
+            self.buffer.splice(old.clone(), bytes);
+
+            for (_, range) in self.segments.range_mut((Bound::Excluded(field_id), Bound::Unbounded)) {
+                *range = range.start - old_len + new_len..range.end - old_len + new_len;
+            }
+            if fields.contains_key(&field_id) {
+                self.segments.insert(field_id, old.start..old.start + new_len);
+            } else {
+                self.segments.remove(&field_id);
+            }
+        }
+    }
 }
 
 impl BiWiMessage {
     /// Create a new empty message
     pub fn new() -> Self {
         Self {
-            fields: HashMap::new(),
-            cached_buffer: None,
+            fields: BTreeMap::new(),
+            envelope: None,
+            flags: BTreeMap::new(),
+            cache: None,
         }
     }
 
-    /// Create a message with initial capacity for fields
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            fields: HashMap::with_capacity(capacity),
-            cached_buffer: None,
+    /// Start building a message fluently
+    pub fn builder() -> BiWiMessageBuilder {
+        BiWiMessageBuilder::default()
+    }
+
+    /// Create a message with initial capacity for fields.
+    /// Kept for API compatibility; ordered storage does not preallocate.
+    pub fn with_capacity(_capacity: usize) -> Self {
+        Self::new()
+    }
+
+    /// Give the message an envelope with this message type (keeping any other envelope data)
+    pub fn with_type(mut self, message_type: u16) -> Self {
+        let envelope = self.envelope.unwrap_or_default();
+        self.set_envelope(Some(Envelope { message_type, ..envelope }));
+        self
+    }
+
+    /// Attach an envelope
+    pub fn with_envelope(mut self, envelope: Envelope) -> Self {
+        self.set_envelope(Some(envelope));
+        self
+    }
+
+    /// Mark this message as the response to `request` by carrying its correlation ID
+    /// (and its message type, if this message has no envelope yet)
+    pub fn in_reply_to(self, request: &BiWiMessage) -> Self {
+        let Some(request) = request.envelope else {
+            return self;
+        };
+        let envelope = self.envelope.unwrap_or(Envelope::new(request.message_type));
+        self.with_envelope(envelope.with_correlation_id(request.correlation_id))
+    }
+
+    /// Set or remove the envelope (invalidates cache)
+    pub fn set_envelope(&mut self, envelope: Option<Envelope>) -> &mut Self {
+        self.envelope = envelope;
+        self.cache = None;
+        self
+    }
+
+    /// Get the envelope, if any
+    pub fn envelope(&self) -> Option<&Envelope> {
+        self.envelope.as_ref()
+    }
+
+    /// Message type from the envelope
+    pub fn message_type(&self) -> Option<u16> {
+        self.envelope.map(|e| e.message_type)
+    }
+
+    /// Correlation ID from the envelope
+    pub fn correlation_id(&self) -> Option<u64> {
+        self.envelope.map(|e| e.correlation_id)
+    }
+
+    /// Add metadata flags to a field ID (whether or not the field is set)
+    pub fn mark_field(&mut self, field_id: u32, flags: FieldFlags) -> &mut Self {
+        *self.flags.entry(field_id).or_default() |= flags;
+        self
+    }
+
+    /// Mark a field as sensitive so `Debug` output redacts it
+    pub fn mark_sensitive(&mut self, field_id: u32) -> &mut Self {
+        self.mark_field(field_id, FieldFlags::SENSITIVE)
+    }
+
+    /// Mark a field as deprecated
+    pub fn mark_deprecated(&mut self, field_id: u32) -> &mut Self {
+        self.mark_field(field_id, FieldFlags::DEPRECATED)
+    }
+
+    /// Copy the field flags declared in a spec onto this message
+    pub fn apply_field_flags(&mut self, spec: &MessageSpec) -> &mut Self {
+        for (field_id, flags) in spec.field_flags() {
+            self.mark_field(field_id, flags);
         }
+        self
     }
 
-    /// Set a field value (invalidates cache)
+    /// Flags attached to a field ID
+    pub fn field_flags(&self, field_id: u32) -> FieldFlags {
+        self.flags.get(&field_id).copied().unwrap_or_default()
+    }
+
+    /// IDs of deprecated fields that are currently set
+    pub fn deprecated_fields(&self) -> Vec<u32> {
+        self.iter()
+            .map(|(field_id, _)| field_id)
+            .filter(|id| self.field_flags(*id).contains(FieldFlags::DEPRECATED))
+            .collect()
+    }
+
+    /// Remove every deprecated field, returning the IDs that were dropped
+    pub fn strip_deprecated(&mut self) -> Vec<u32> {
+        let deprecated = self.deprecated_fields();
+        for field_id in &deprecated {
+            self.remove_field(*field_id);
+        }
+        deprecated
+    }
+
+    /// Set a field value (marks only this field for re-encoding)
     pub fn set_field(&mut self, field_id: u32, value: BiWiValue) -> &mut Self {
         self.fields.insert(field_id, value);
-        self.cached_buffer = None; // Invalidate cache
+        self.mark_dirty(field_id);
         self
     }
 
@@ -40,9 +259,44 @@ impl BiWiMessage {
         self.fields.get(&field_id)
     }
 
+    /// Get a boolean field
+    pub fn get_bool(&self, field_id: u32) -> Option<bool> {
+        self.get_field(field_id)?.as_bool()
+    }
+
+    /// Get an integer field that fits in 32 bits
+    pub fn get_i32(&self, field_id: u32) -> Option<i32> {
+        self.get_field(field_id)?.as_i32()
+    }
+
+    /// Get an integer field, widening Int32
+    pub fn get_i64(&self, field_id: u32) -> Option<i64> {
+        self.get_field(field_id)?.as_i64()
+    }
+
+    /// Get a numeric field as f64
+    pub fn get_f64(&self, field_id: u32) -> Option<f64> {
+        self.get_field(field_id)?.as_f64()
+    }
+
+    /// Get a string field (SmallString or String)
+    pub fn get_str(&self, field_id: u32) -> Option<&str> {
+        self.get_field(field_id)?.as_str()
+    }
+
+    /// Get a binary field
+    pub fn get_bytes(&self, field_id: u32) -> Option<&[u8]> {
+        self.get_field(field_id)?.as_bytes()
+    }
+
+    /// Get an array field
+    pub fn get_array(&self, field_id: u32) -> Option<&[BiWiValue]> {
+        self.get_field(field_id)?.as_array()
+    }
+
     /// Get a mutable reference to a field value
     pub fn get_field_mut(&mut self, field_id: u32) -> Option<&mut BiWiValue> {
-        self.cached_buffer = None; // Invalidate cache since field might be modified
+        self.mark_dirty(field_id); // The field might be modified
         self.fields.get_mut(&field_id)
     }
 
@@ -53,11 +307,11 @@ impl BiWiMessage {
 
     /// Remove a field
     pub fn remove_field(&mut self, field_id: u32) -> Option<BiWiValue> {
-        self.cached_buffer = None; // Invalidate cache
+        self.mark_dirty(field_id);
         self.fields.remove(&field_id)
     }
 
-    /// Get all field IDs
+    /// Get all field IDs in ascending order
     pub fn field_ids(&self) -> Vec<u32> {
         self.fields.keys().copied().collect()
     }
@@ -67,48 +321,197 @@ impl BiWiMessage {
         self.fields.len()
     }
 
-    /// Clear all fields
+    /// Clear all fields and the envelope, keeping the encode buffer's capacity
     pub fn clear(&mut self) {
         self.fields.clear();
-        self.cached_buffer = None;
+        self.envelope = None;
+        if let Some(cache) = &mut self.cache {
+            cache.reset();
+        }
+    }
+
+    /// Clear everything, including field flags, so the message can be reused
+    /// for unrelated data (see `MessagePool`)
+    pub fn reset(&mut self) {
+        self.clear();
+        self.flags.clear();
+    }
+
+    /// Bytes allocated for the cached encoding
+    pub fn buffer_capacity(&self) -> usize {
+        self.cache.as_ref().map_or(0, |cache| cache.buffer.capacity())
     }
 
-    /// Get all fields as a HashMap reference
-    pub fn fields(&self) -> &HashMap<u32, BiWiValue> {
+    /// Get all fields as an ordered map reference
+    pub fn fields(&self) -> &BTreeMap<u32, BiWiValue> {
         &self.fields
     }
 
-    /// Encode message to binary (cached)
+    /// Iterate over `(field_id, value)` pairs in storage order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &BiWiValue)> + '_ {
+        self.fields.iter().map(|(id, value)| (*id, value))
+    }
+
+    /// Iterate over `(field_id, value)` pairs in ascending field-ID order.
+    /// This is the order `to_vec` encodes in, whatever the storage does.
+    pub fn iter_sorted(&self) -> impl Iterator<Item = (u32, &BiWiValue)> + '_ {
+        self.fields.iter().map(|(id, value)| (*id, value))
+    }
+
+    /// Encode message to binary (cached; only fields changed since the last call are re-encoded)
     pub fn to_buffer(&mut self) -> &[u8] {
-        if let Some(ref buffer) = self.cached_buffer {
-            return buffer;
+        let (envelope, fields) = (self.envelope.as_ref(), &self.fields);
+        let cache = self.cache.get_or_insert_with(|| EncodedCache::build(envelope, fields));
+        cache.refresh(fields);
+        &cache.buffer
+    }
+
+    fn mark_dirty(&mut self, field_id: u32) {
+        if let Some(cache) = &mut self.cache {
+            cache.dirty.insert(field_id);
         }
+    }
 
-        let mut encoder = BiWiEncoder::new();
-        for (field_id, value) in &self.fields {
-            encoder.encode_field(*field_id, value);
+    /// Encode message to a new Vec<u8> (doesn't cache)
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.encode_with(BiWiEncoder::new())
+    }
+
+    /// Encode message to a new Vec<u8>, writing every float under `policy`
+    pub fn to_vec_with_float_policy(&self, policy: FloatPolicy) -> Vec<u8> {
+        self.encode_with(BiWiEncoder::new().with_float_policy(policy))
+    }
+
+    /// Encode the next message of a stream whose object keys go through `keys`.
+    /// The receiver decodes with `from_buffer_interned` and its own table, and must
+    /// see every message encoded this way, once and in order.
+    pub fn to_vec_interned(&self, keys: &mut KeyTable) -> Vec<u8> {
+        let mut encoder = BiWiEncoder::new().with_key_table(std::mem::take(keys));
+        self.write_fields(&mut encoder);
+        *keys = encoder.take_key_table().unwrap_or_default();
+        Self::finish(encoder)
+    }
+
+    fn encode_with(&self, mut encoder: BiWiEncoder) -> Vec<u8> {
+        self.write_fields(&mut encoder);
+        Self::finish(encoder)
+    }
+
+    fn write_fields(&self, encoder: &mut BiWiEncoder) {
+        if let Some(envelope) = &self.envelope {
+            encoder.encode_envelope(envelope);
+        }
+        for (field_id, value) in self.iter_sorted() {
+            encoder.encode_field(field_id, value);
         }
+    }
 
+    fn finish(encoder: BiWiEncoder) -> Vec<u8> {
         let buffer = encoder.to_buffer();
-        self.cached_buffer = Some(buffer);
-        self.cached_buffer.as_ref().unwrap()
+        telemetry::message_encoded(buffer.len());
+        buffer
     }
 
-    /// Encode message to a new Vec<u8> (doesn't cache)
-    pub fn to_vec(&self) -> Vec<u8> {
+    /// Encode message, failing if the result would exceed `max_bytes`
+    /// (e.g. `network::MAX_PAYLOAD_SIZE` to avoid fragmentation)
+    pub fn to_vec_bounded(&self, max_bytes: usize) -> Result<Vec<u8>, SizeBudgetExceeded> {
+        let buffer = self.to_vec();
+        if buffer.len() <= max_bytes {
+            return Ok(buffer);
+        }
+
+        let mut field_sizes: Vec<(u32, usize)> = self
+            .iter_sorted()
+            .map(|(field_id, value)| {
+                let mut encoder = BiWiEncoder::new();
+                encoder.encode_field(field_id, value);
+                (field_id, encoder.size())
+            })
+            .collect();
+        field_sizes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        Err(SizeBudgetExceeded {
+            size: buffer.len(),
+            budget: max_bytes,
+            field_sizes,
+        })
+    }
+
+    /// Encode message in the sparse layout (presence bitmap + packed values).
+    /// Smaller than `to_vec` when most fields in a dense ID range are unset or null.
+    pub fn to_vec_sparse(&self) -> Vec<u8> {
+        let fields: Vec<(u32, &BiWiValue)> = self.iter_sorted().collect();
         let mut encoder = BiWiEncoder::new();
-        for (field_id, value) in &self.fields {
-            encoder.encode_field(*field_id, value);
+        if let Some(envelope) = &self.envelope {
+            encoder.encode_envelope(envelope);
         }
-        encoder.to_buffer()
+        encoder.encode_sparse(&fields);
+        let buffer = encoder.to_buffer();
+        telemetry::message_encoded(buffer.len());
+        buffer
     }
 
-    /// Decode from binary buffer
-    pub fn from_buffer(buffer: &[u8]) -> DecodeResult<Self> {
+    /// Encode and append an HMAC-SHA256 tag keyed with `key`
+    pub fn to_vec_signed(&self, key: &[u8]) -> Vec<u8> {
+        crate::auth::sign(&self.to_vec(), key)
+    }
+
+    /// Verify a frame produced by `to_vec_signed` and decode it.
+    /// The tag is checked before any decoding happens.
+    pub fn from_buffer_verified(buffer: &[u8], key: &[u8]) -> DecodeResult<Self> {
+        Self::from_buffer(crate::auth::verify(buffer, key)?)
+    }
+
+    /// Decode a buffer produced by `to_vec_sparse`
+    pub fn from_buffer_sparse(buffer: &[u8]) -> DecodeResult<Self> {
+        record_decode(buffer, Self::decode_sparse(buffer))
+    }
+
+    fn decode_sparse(buffer: &[u8]) -> DecodeResult<Self> {
         let mut decoder = BiWiDecoder::new(buffer);
+        let envelope = decoder.decode_envelope()?;
+        let fields = decoder.decode_sparse()?;
+
+        let mut message = BiWiMessage::with_capacity(fields.len());
+        message.envelope = envelope;
+        for field in fields {
+            message.set_field(field.field_id, field.value);
+        }
+
+        Ok(message)
+    }
+
+    /// Decode from binary buffer, rejecting malformed or truncated input
+    pub fn from_buffer(buffer: &[u8]) -> DecodeResult<Self> {
+        record_decode(buffer, Self::decode(buffer))
+    }
+
+    /// Decode from a shared buffer; Binary fields become `BiWiValue::Bytes` slices of it
+    #[cfg(feature = "bytes")]
+    pub fn from_shared(buffer: &bytes::Bytes) -> DecodeResult<Self> {
+        record_decode(buffer, Self::decode_with(BiWiDecoder::new_shared(buffer)))
+    }
+
+    /// Decode a message whose fields may reference the session's field table
+    /// (see `compression`), adding the values it inserts
+    pub fn from_buffer_compressed(buffer: &[u8], fields: &mut FieldTable) -> DecodeResult<Self> {
+        record_decode(buffer, Self::decode_with(BiWiDecoder::new(buffer).with_field_table(fields)))
+    }
+
+    /// Decode the next message of a stream encoded with `to_vec_interned`
+    pub fn from_buffer_interned(buffer: &[u8], keys: &mut KeyTable) -> DecodeResult<Self> {
+        record_decode(buffer, Self::decode_with(BiWiDecoder::new(buffer).with_key_table(keys)))
+    }
+
+    fn decode(buffer: &[u8]) -> DecodeResult<Self> {
+        Self::decode_with(BiWiDecoder::new(buffer))
+    }
+
+    fn decode_with(mut decoder: BiWiDecoder<'_>) -> DecodeResult<Self> {
         let mut message = BiWiMessage::new();
 
-        let fields = decoder.decode_all();
+        message.envelope = decoder.decode_envelope()?;
+        let fields = decoder.try_decode_all()?;
         for field in fields {
             message.set_field(field.field_id, field.value);
         }
@@ -116,12 +519,242 @@ impl BiWiMessage {
         Ok(message)
     }
 
+    /// Check the message against `spec`, returning every violation
+    pub fn validate(&self, spec: &MessageSpec) -> Result<(), Vec<Violation>> {
+        spec.check(self)
+    }
+
+    /// Compute the patch that turns `self` into `other`
+    pub fn diff(&self, other: &BiWiMessage) -> BiWiDelta {
+        let mut delta = BiWiDelta::default();
+        for (field_id, value) in other.iter() {
+            if self.fields.get(&field_id) != Some(value) {
+                delta.changed.insert(field_id, value.clone());
+            }
+        }
+        for field_id in self.fields.keys() {
+            if !other.fields.contains_key(field_id) {
+                delta.removed.insert(*field_id);
+            }
+        }
+        delta
+    }
+
+    /// Apply a patch produced by `diff` (invalidates cache if anything changes)
+    pub fn apply_delta(&mut self, delta: &BiWiDelta) {
+        for field_id in &delta.removed {
+            self.remove_field(*field_id);
+        }
+        for (field_id, value) in &delta.changed {
+            self.set_field(*field_id, value.clone());
+        }
+    }
+
+    /// Merge `other` into this message, overwriting fields that exist in both
+    pub fn merge(&mut self, other: &BiWiMessage) -> &mut Self {
+        self.merge_with(other, MergeStrategy::Overwrite)
+    }
+
+    /// Merge `other` into this message using `strategy` for fields that exist in both
+    pub fn merge_with(&mut self, other: &BiWiMessage, strategy: MergeStrategy) -> &mut Self {
+        for (field_id, value) in other.iter() {
+            if let Some(cache) = &mut self.cache {
+                cache.dirty.insert(field_id);
+            }
+            match self.fields.get_mut(&field_id) {
+                None => {
+                    self.fields.insert(field_id, value.clone());
+                }
+                Some(existing) => match strategy {
+                    MergeStrategy::Overwrite => *existing = value.clone(),
+                    MergeStrategy::KeepExisting => {}
+                    MergeStrategy::Deep => deep_merge(existing, value),
+                },
+            }
+        }
+        self
+    }
+
     /// Get size of encoded message
     pub fn size(&mut self) -> usize {
         self.to_buffer().len()
     }
 }
 
+/// Count a decode attempt in the metrics
+fn record_decode(buffer: &[u8], result: DecodeResult<BiWiMessage>) -> DecodeResult<BiWiMessage> {
+    match &result {
+        Ok(_) => telemetry::message_decoded(buffer.len()),
+        Err(error) => {
+            event!(DEBUG, len = buffer.len(), %error, "message failed to decode");
+            telemetry::decode_error();
+        }
+    }
+    result
+}
+
+/// How `BiWiMessage::merge_with` resolves a field present in both messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// The incoming value replaces the existing one
+    #[default]
+    Overwrite,
+    /// The existing value wins (useful for applying defaults)
+    KeepExisting,
+    /// Objects merge key by key recursively, arrays are concatenated,
+    /// anything else is overwritten
+    Deep,
+}
+
+fn deep_merge(existing: &mut BiWiValue, incoming: &BiWiValue) {
+    match (existing, incoming) {
+        (BiWiValue::Object(ours), BiWiValue::Object(theirs)) => {
+            for (key, value) in theirs {
+                match ours.get_mut(key) {
+                    Some(slot) => deep_merge(slot, value),
+                    None => {
+                        ours.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (BiWiValue::Array(ours), BiWiValue::Array(theirs)) => ours.extend(theirs.iter().cloned()),
+        (BiWiValue::Int32Array(ours), BiWiValue::Int32Array(theirs)) => ours.extend_from_slice(theirs),
+        (BiWiValue::Int64Array(ours), BiWiValue::Int64Array(theirs)) => ours.extend_from_slice(theirs),
+        (BiWiValue::Float32Array(ours), BiWiValue::Float32Array(theirs)) => ours.extend_from_slice(theirs),
+        (BiWiValue::Float64Array(ours), BiWiValue::Float64Array(theirs)) => ours.extend_from_slice(theirs),
+        (slot, value) => *slot = value.clone(),
+    }
+}
+
+/// Changed and removed fields between two messages, produced by `BiWiMessage::diff`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiWiDelta {
+    changed: BTreeMap<u32, BiWiValue>,
+    removed: BTreeSet<u32>,
+}
+
+impl BiWiDelta {
+    /// True when the two messages were identical
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Fields that were added or whose value changed
+    pub fn changed(&self) -> &BTreeMap<u32, BiWiValue> {
+        &self.changed
+    }
+
+    /// Field IDs that were removed
+    pub fn removed(&self) -> &BTreeSet<u32> {
+        &self.removed
+    }
+
+    /// Encode the patch: only changed fields and removed IDs go on the wire
+    pub fn to_vec(&self) -> Vec<u8> {
+        let removed: Vec<u32> = self.removed.iter().copied().collect();
+        let changed: Vec<(u32, &BiWiValue)> = self.changed.iter().map(|(id, v)| (*id, v)).collect();
+        let mut encoder = BiWiEncoder::new();
+        encoder.encode_delta(&removed, &changed);
+        encoder.to_buffer()
+    }
+
+    /// Decode a patch produced by `to_vec`
+    pub fn from_buffer(buffer: &[u8]) -> DecodeResult<Self> {
+        let mut decoder = BiWiDecoder::new(buffer);
+        let (removed, changed) = decoder.decode_delta()?;
+        Ok(Self {
+            changed: changed.into_iter().map(|f| (f.field_id, f.value)).collect(),
+            removed: removed.into_iter().collect(),
+        })
+    }
+}
+
+/// Returned by `BiWiMessage::to_vec_bounded` when a message is over budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeBudgetExceeded {
+    /// Encoded size of the whole message
+    pub size: usize,
+    pub budget: usize,
+    /// Encoded size of each field (header included), largest first
+    pub field_sizes: Vec<(u32, usize)>,
+}
+
+impl SizeBudgetExceeded {
+    /// Bytes that must be removed to fit the budget
+    pub fn overflow(&self) -> usize {
+        self.size - self.budget
+    }
+
+    /// Largest fields whose removal would bring the message within budget
+    pub fn fields_to_drop(&self) -> Vec<u32> {
+        let mut remaining = self.overflow();
+        let mut drop = Vec::new();
+        for (field_id, size) in &self.field_sizes {
+            if remaining == 0 {
+                break;
+            }
+            drop.push(*field_id);
+            remaining = remaining.saturating_sub(*size);
+        }
+        drop
+    }
+}
+
+impl std::fmt::Display for SizeBudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message is {} bytes, budget is {}", self.size, self.budget)
+    }
+}
+
+impl std::error::Error for SizeBudgetExceeded {}
+
+/// Fluent builder returned by `BiWiMessage::builder()`
+#[derive(Debug, Default)]
+pub struct BiWiMessageBuilder {
+    message: BiWiMessage,
+}
+
+impl BiWiMessageBuilder {
+    /// Set a field, converting the value with `Into<BiWiValue>`
+    pub fn field(mut self, field_id: u32, value: impl Into<BiWiValue>) -> Self {
+        self.message.set_field(field_id, value.into());
+        self
+    }
+
+    pub fn build(self) -> BiWiMessage {
+        self.message
+    }
+}
+
+/// Build a `BiWiMessage` from `field_id => value` pairs.
+///
+/// ```
+/// let msg = biwi::biwi_msg! { 1 => "hello", 2 => 42 };
+/// assert_eq!(msg.get_str(1), Some("hello"));
+/// ```
+#[macro_export]
+macro_rules! biwi_msg {
+    () => {
+        $crate::BiWiMessage::new()
+    };
+    ($($id:expr => $value:expr),+ $(,)?) => {
+        $crate::BiWiMessage::builder()$(.field($id, $value))+.build()
+    };
+}
+
+impl<'a> IntoIterator for &'a BiWiMessage {
+    type Item = (u32, &'a BiWiValue);
+    type IntoIter = std::iter::Map<
+        std::collections::btree_map::Iter<'a, u32, BiWiValue>,
+        fn((&'a u32, &'a BiWiValue)) -> (u32, &'a BiWiValue),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.iter().map(|(id, value)| (*id, value))
+    }
+}
+
 impl Default for BiWiMessage {
     fn default() -> Self {
         Self::new()
@@ -132,16 +765,312 @@ impl Clone for BiWiMessage {
     fn clone(&self) -> Self {
         Self {
             fields: self.fields.clone(),
-            cached_buffer: None, // Don't clone cache, will be regenerated if needed
+            envelope: self.envelope,
+            flags: self.flags.clone(),
+            cache: None, // Don't clone cache, will be regenerated if needed
         }
     }
 }
 
+impl PartialEq for BiWiMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields && self.envelope == other.envelope
+    }
+}
+
+/// Serializes the fields as a map of field ID to value; the envelope is
+/// transport metadata and is not included
+impl Serialize for BiWiMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.fields.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BiWiMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            fields: BTreeMap::deserialize(deserializer)?,
+            envelope: None,
+            flags: BTreeMap::new(),
+            cache: None,
+        })
+    }
+}
+
+/// Stands in for sensitive values in `Debug` output
+struct Redacted;
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Field map with sensitive values redacted
+struct DebugFields<'a>(&'a BiWiMessage);
+
+impl std::fmt::Debug for DebugFields<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = self.0;
+        f.debug_map()
+            .entries(message.fields.iter().map(|(id, value)| {
+                let value: &dyn std::fmt::Debug = if message.field_flags(*id).contains(FieldFlags::SENSITIVE) {
+                    &Redacted
+                } else {
+                    value
+                };
+                (id, value)
+            }))
+            .finish()
+    }
+}
+
 impl std::fmt::Debug for BiWiMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BiWiMessage")
-            .field("fields", &self.fields)
-            .field("cached", &self.cached_buffer.is_some())
+            .field("envelope", &self.envelope)
+            .field("fields", &DebugFields(self))
+            .field("cached", &self.cache.is_some())
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iteration_is_ordered_and_encoding_deterministic() {
+        let mut a = BiWiMessage::new();
+        for id in [40, 3, 17, 1, 300] {
+            a.set_field(id, BiWiValue::Int32(id as i32));
+        }
+        let mut b = BiWiMessage::new();
+        for id in [300, 1, 17, 3, 40] {
+            b.set_field(id, BiWiValue::Int32(id as i32));
+        }
+
+        let ids: Vec<u32> = a.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![1, 3, 17, 40, 300]);
+        assert_eq!(a.field_ids(), ids);
+        assert!(a.iter_sorted().eq(b.iter_sorted()));
+        assert_eq!(a.to_vec(), b.to_vec());
+    }
+
+    #[test]
+    fn test_typed_getters() {
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from("short"));
+        msg.set_field(2, BiWiValue::from("a string longer than fifteen bytes"));
+        msg.set_field(3, BiWiValue::Int32(-7));
+        msg.set_field(4, BiWiValue::Int64(1 << 40));
+        msg.set_field(5, BiWiValue::Float32(1.5));
+        msg.set_field(6, BiWiValue::Binary(vec![1, 2, 3]));
+        msg.set_field(7, BiWiValue::Array(vec![BiWiValue::Boolean(true)]));
+
+        assert_eq!(msg.get_str(1), Some("short"));
+        assert_eq!(msg.get_str(2), Some("a string longer than fifteen bytes"));
+        assert_eq!(msg.get_i32(3), Some(-7));
+        assert_eq!(msg.get_i64(3), Some(-7));
+        assert_eq!(msg.get_i32(4), None);
+        assert_eq!(msg.get_i64(4), Some(1 << 40));
+        assert_eq!(msg.get_f64(5), Some(1.5));
+        assert_eq!(msg.get_f64(3), Some(-7.0));
+        assert_eq!(msg.get_bytes(6), Some(&[1u8, 2, 3][..]));
+        assert_eq!(msg.get_array(7).and_then(|a| a[0].as_bool()), Some(true));
+        assert_eq!(msg.get_str(3), None);
+        assert_eq!(msg.get_i32(99), None);
+    }
+
+    #[test]
+    fn test_builder_and_macro() {
+        let built = BiWiMessage::builder()
+            .field(1, "hello")
+            .field(2, 42)
+            .field(3, [1.0, 2.0])
+            .field(4, None::<i32>)
+            .build();
+        assert_eq!(built.get_str(1), Some("hello"));
+        assert_eq!(built.get_i32(2), Some(42));
+        assert_eq!(
+            built.get_field(3),
+            Some(&BiWiValue::Array(vec![BiWiValue::Float64(1.0), BiWiValue::Float64(2.0)]))
+        );
+        assert_eq!(built.get_field(4), Some(&BiWiValue::Null));
+
+        let from_macro = crate::biwi_msg! { 1 => "hello", 2 => 42, 3 => [1.0, 2.0], 4 => None::<i32> };
+        assert_eq!(from_macro, built);
+        assert_eq!(crate::biwi_msg! {}.field_count(), 0);
+    }
+
+    #[test]
+    fn test_diff_and_apply_delta() {
+        let before = crate::biwi_msg! { 1 => "player", 2 => 10.5, 3 => 20.25, 4 => true };
+        let after = crate::biwi_msg! { 1 => "player", 2 => 11.5, 3 => 20.25, 5 => 7 };
+
+        let delta = before.diff(&after);
+        assert_eq!(delta.changed().keys().copied().collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(delta.removed().iter().copied().collect::<Vec<_>>(), vec![4]);
+
+        let wire = delta.to_vec();
+        assert!(wire.len() < after.to_vec().len());
+        let decoded = BiWiDelta::from_buffer(&wire).unwrap();
+        assert_eq!(decoded, delta);
+
+        let mut patched = before.clone();
+        patched.apply_delta(&decoded);
+        assert_eq!(patched, after);
+
+        assert!(after.diff(&after).is_empty());
+        assert_eq!(BiWiDelta::from_buffer(&after.diff(&after).to_vec()).unwrap(), BiWiDelta::default());
+    }
+
+    #[test]
+    fn test_to_buffer_splices_dirty_fields() {
+        let check = |msg: &mut BiWiMessage| {
+            let expected = msg.to_vec();
+            assert_eq!(msg.to_buffer(), expected.as_slice());
+        };
+        let mut msg = crate::biwi_msg! { 1 => "player_1", 5 => 10, 40 => [1.5, 2.5], 300 => true };
+        check(&mut msg);
+
+        // Grow, shrink, insert in the middle and at both ends, and remove
+        msg.set_field(1, BiWiValue::from("a considerably longer player name"));
+        check(&mut msg);
+        msg.set_field(5, BiWiValue::Int64(1 << 40));
+        msg.set_field(20, BiWiValue::Null);
+        msg.set_field(0, BiWiValue::Boolean(false));
+        msg.set_field(1000, BiWiValue::from("end"));
+        msg.set_field(u32::MAX, BiWiValue::Null);
+        msg.remove_field(40);
+        check(&mut msg);
+
+        *msg.get_field_mut(1000).unwrap() = BiWiValue::Int32(-1);
+        msg.merge(&crate::biwi_msg! { 5 => 6, 7 => 8 });
+        msg.remove_field(0);
+        msg.remove_field(1000);
+        check(&mut msg);
+
+        msg.clear();
+        assert!(msg.to_buffer().is_empty());
+    }
+
+    #[test]
+    fn test_serde_json_roundtrip() {
+        let msg = crate::biwi_msg! {
+            1 => "hi",
+            2 => "a string longer than fifteen bytes",
+            3 => BiWiValue::Int64(5),
+            4 => BiWiValue::Float32(0.5),
+            5 => vec![1u8, 2],
+            6 => [BiWiValue::Null, BiWiValue::Boolean(false)],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.starts_with(r#"{"1":{"SmallString":"hi"}"#));
+        assert_eq!(serde_json::from_str::<BiWiMessage>(&json).unwrap(), msg);
+
+        let too_long = r#"{"1":{"SmallString":"this does not fit inline"}}"#;
+        assert!(serde_json::from_str::<BiWiMessage>(too_long).is_err());
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let mut msg = crate::biwi_msg! { 1 => "ping", 40 => 7 }
+            .with_type(12)
+            .with_envelope(Envelope::new(12).with_correlation_id(99).with_timestamp(5));
+        assert_eq!(msg.message_type(), Some(12));
+        assert_eq!(msg.correlation_id(), Some(99));
+
+        let buffer = msg.to_vec();
+        let decoded = BiWiMessage::from_buffer(&buffer).unwrap();
+        assert_eq!(decoded, msg);
+        assert_eq!(decoded.envelope(), msg.envelope());
+        assert_eq!(BiWiMessage::from_buffer_sparse(&msg.to_vec_sparse()).unwrap(), msg);
+
+        // The cached, spliced encoding keeps the envelope in front of the fields
+        assert_eq!(msg.to_buffer(), buffer.as_slice());
+        msg.set_field(0, BiWiValue::Null);
+        msg.remove_field(1);
+        let expected = msg.to_vec();
+        assert_eq!(msg.to_buffer(), expected.as_slice());
+
+        let plain = crate::biwi_msg! { 1 => "ping" };
+        assert_eq!(BiWiMessage::from_buffer(&plain.to_vec()).unwrap().envelope(), None);
+    }
+
+    #[test]
+    fn test_signed_roundtrip() {
+        let msg = crate::biwi_msg! { 1 => "move", 2 => 3 }.with_type(4);
+        let frame = msg.to_vec_signed(b"key");
+        assert_eq!(BiWiMessage::from_buffer_verified(&frame, b"key").unwrap(), msg);
+        assert_eq!(
+            BiWiMessage::from_buffer_verified(&frame, b"wrong"),
+            Err(crate::DecodeError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn test_to_vec_bounded() {
+        let msg = crate::biwi_msg! { 1 => "small", 2 => vec![0u8; 200], 3 => vec![0u8; 100], 4 => 7 };
+        let size = msg.to_vec().len();
+        assert_eq!(msg.to_vec_bounded(size).unwrap(), msg.to_vec());
+
+        let report = msg.to_vec_bounded(150).unwrap_err();
+        assert_eq!(report.size, size);
+        assert_eq!(report.overflow(), size - 150);
+        assert_eq!(report.field_sizes.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![2, 3, 1, 4]);
+        assert_eq!(report.fields_to_drop(), vec![2]);
+        assert_eq!(msg.to_vec_bounded(10).unwrap_err().fields_to_drop(), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_field_flags() {
+        let mut msg = crate::biwi_msg! { 1 => "alice", 2 => "alice@example.com", 3 => 1 };
+        msg.mark_sensitive(2).mark_deprecated(3);
+
+        let debug = format!("{:?}", msg);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("example.com"));
+        assert!(format!("{:?}", msg.get_field(1)).contains("SmallString"));
+
+        assert_eq!(msg.deprecated_fields(), vec![3]);
+        assert_eq!(msg.strip_deprecated(), vec![3]);
+        assert!(!msg.has_field(3));
+
+        // Flags can come from a spec and survive cloning
+        let spec = MessageSpec::new().sensitive(1);
+        let mut other = msg.clone();
+        other.apply_field_flags(&spec);
+        assert!(other.field_flags(1).contains(FieldFlags::SENSITIVE));
+        assert!(other.field_flags(2).contains(FieldFlags::SENSITIVE));
+        assert!(!format!("{:?}", other).contains("alice"));
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let object = |pairs: &[(&str, i32)]| {
+            let map: std::collections::HashMap<String, BiWiValue> =
+                pairs.iter().map(|(k, v)| (k.to_string(), BiWiValue::Int32(*v))).collect();
+            BiWiValue::Object(map)
+        };
+        let base = crate::biwi_msg! { 1 => "base", 2 => object(&[("a", 1), ("b", 2)]), 3 => [1] };
+        let other = crate::biwi_msg! { 1 => "other", 2 => object(&[("b", 20), ("c", 30)]), 3 => [2], 4 => true };
+
+        let mut overwrite = base.clone();
+        overwrite.merge(&other);
+        assert_eq!(overwrite, other);
+
+        let mut keep = base.clone();
+        keep.merge_with(&other, MergeStrategy::KeepExisting);
+        assert_eq!(keep.get_str(1), Some("base"));
+        assert_eq!(keep.get_field(2), base.get_field(2));
+        assert_eq!(keep.get_bool(4), Some(true));
+
+        let mut deep = base.clone();
+        deep.merge_with(&other, MergeStrategy::Deep);
+        assert_eq!(deep.get_str(1), Some("other"));
+        assert_eq!(deep.get_field(2), Some(&object(&[("a", 1), ("b", 20), ("c", 30)])));
+        assert_eq!(deep.get_field(3), Some(&BiWiValue::from([1, 2])));
+        assert_eq!(deep.get_bool(4), Some(true));
+    }
+}
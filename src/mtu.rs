@@ -0,0 +1,148 @@
+//! BiWi Path MTU Discovery
+//! Binary-searches the largest datagram that reaches the peer, using Ping packets
+//! padded to the probed size; the Pong that answers a probe confirms its size. The
+//! socket is left at the OS default, which on Linux sets DF on UDP, so oversized
+//! probes are dropped (or fail to send) instead of being fragmented by IP.
+
+use crate::network::MAX_PACKET_SIZE;
+use std::time::{Duration, Instant};
+
+/// Largest datagram probed by default: a 1500-byte Ethernet MTU minus IP/UDP headers
+pub const DEFAULT_MAX_PROBE_SIZE: usize = 1472;
+
+/// How long to wait for the Pong answering a probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Probes sent at one size before deciding it doesn't fit
+pub const PROBE_ATTEMPTS: u32 = 2;
+
+/// The search stops once the bounds are this close
+const PROBE_PRECISION: usize = 8;
+
+struct InFlight {
+    id: u32,
+    size: usize,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// State of one path MTU search
+pub struct MtuProbe {
+    /// Largest size known to get through
+    low: usize,
+    /// Smallest size known not to (exclusive upper bound)
+    high: usize,
+    in_flight: Option<InFlight>,
+    next_id: u32,
+}
+
+impl MtuProbe {
+    /// Search between `MAX_PACKET_SIZE` (assumed to work) and `max_size`
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            low: MAX_PACKET_SIZE,
+            high: max_size.max(MAX_PACKET_SIZE) + 1,
+            in_flight: None,
+            next_id: 0,
+        }
+    }
+
+    /// Largest datagram size confirmed so far
+    pub fn mtu(&self) -> usize {
+        self.low
+    }
+
+    /// True once the search has converged
+    pub fn is_complete(&self) -> bool {
+        self.in_flight.is_none() && self.high - self.low <= PROBE_PRECISION
+    }
+
+    /// The next probe to send as (probe ID, datagram size), if one is due: a new
+    /// size, a retry of one that timed out, or nothing while a probe is outstanding
+    pub fn next_probe(&mut self) -> Option<(u32, usize)> {
+        if let Some(probe) = &mut self.in_flight {
+            if probe.sent_at.elapsed() < PROBE_TIMEOUT {
+                return None;
+            }
+            if probe.attempts < PROBE_ATTEMPTS {
+                probe.attempts += 1;
+                probe.sent_at = Instant::now();
+                probe.id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+                return Some((probe.id, probe.size));
+            }
+            // Never answered: too big
+            self.high = probe.size;
+            self.in_flight = None;
+        }
+
+        if self.high - self.low <= PROBE_PRECISION {
+            return None;
+        }
+        let size = self.low + (self.high - self.low) / 2;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.in_flight = Some(InFlight {
+            id,
+            size,
+            sent_at: Instant::now(),
+            attempts: 1,
+        });
+        Some((id, size))
+    }
+
+    /// A Pong answered probe `id`; returns the newly confirmed MTU if it was the outstanding probe
+    pub fn on_pong(&mut self, id: u32) -> Option<usize> {
+        let size = self.in_flight.as_ref().filter(|probe| probe.id == id)?.size;
+        self.in_flight = None;
+        self.low = size;
+        Some(size)
+    }
+
+    /// Sending the outstanding probe failed locally (e.g. `EMSGSIZE`), so its size doesn't fit
+    pub fn on_send_error(&mut self) {
+        if let Some(probe) = self.in_flight.take() {
+            self.high = probe.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_converges_on_path_mtu() {
+        let path_mtu = 1400;
+        let mut probe = MtuProbe::new(DEFAULT_MAX_PROBE_SIZE);
+
+        while !probe.is_complete() {
+            let (id, size) = probe.next_probe().unwrap();
+            if size <= path_mtu {
+                probe.on_pong(id);
+            } else {
+                probe.on_send_error();
+            }
+        }
+        assert!(probe.mtu() <= path_mtu && probe.mtu() > path_mtu - PROBE_PRECISION);
+    }
+
+    #[test]
+    fn test_unanswered_probe_is_retried_then_abandoned() {
+        let mut probe = MtuProbe::new(DEFAULT_MAX_PROBE_SIZE);
+        let (first, size) = probe.next_probe().unwrap();
+        assert!(probe.next_probe().is_none());
+
+        probe.in_flight.as_mut().unwrap().sent_at -= PROBE_TIMEOUT;
+        let (retry, retry_size) = probe.next_probe().unwrap();
+        assert_eq!(retry_size, size);
+        assert_ne!(retry, first);
+        // A late answer to the first attempt no longer counts
+        assert!(probe.on_pong(first).is_none());
+
+        probe.in_flight.as_mut().unwrap().sent_at -= PROBE_TIMEOUT;
+        let (_, smaller) = probe.next_probe().unwrap();
+        assert!(smaller < size);
+        assert_eq!(probe.mtu(), MAX_PACKET_SIZE);
+    }
+}
@@ -2,8 +2,17 @@
 //! Provides fast UDP-based transport with packet loss handling
 //! Features: packet sequencing, ACK-based retransmission, fragment reassembly
 
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use crate::capture::{Capture, Direction};
+use crate::clock::{unix_micros, ClockEstimate, ClockSync, PONG_TIMES_LEN};
+use crate::congestion::CongestionController;
+use crate::crypto::{PacketCipher, CIPHER_OVERHEAD, CONFIRMATION_LEN, HANDSHAKE_RANDOM_LEN};
+use crate::receipt::{SendHandle, SendOutcome};
+use crate::decoder::DecodeError;
+use crate::header;
+use crate::reader::Reader;
+use crate::telemetry;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Packet types for UDP protocol
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,6 +25,12 @@ pub enum PacketType {
     Ping = 0x03,
     /// Ping response
     Pong = 0x04,
+    /// Session request carrying the client's protocol version
+    Connect = 0x05,
+    /// Session granted: protocol version and session ID
+    ConnectAck = 0x06,
+    /// Session closed (or refused) by either side
+    Disconnect = 0x07,
 }
 
 impl PacketType {
@@ -25,6 +40,9 @@ impl PacketType {
             0x02 => Some(PacketType::Ack),
             0x03 => Some(PacketType::Ping),
             0x04 => Some(PacketType::Pong),
+            0x05 => Some(PacketType::Connect),
+            0x06 => Some(PacketType::ConnectAck),
+            0x07 => Some(PacketType::Disconnect),
             _ => None,
         }
     }
@@ -33,12 +51,279 @@ impl PacketType {
 /// Packet header (13 bytes)
 /// Type (1) + Sequence (4) + Ack (4) + Flags (4)
 pub const PACKET_HEADER_SIZE: usize = 13;
-pub const MAX_PACKET_SIZE: usize = 1280; // Conservative for UDP
+pub const MAX_PACKET_SIZE: usize = 1280; // Conservative for UDP; the default per-connection packet size
 pub const MAX_PAYLOAD_SIZE: usize = MAX_PACKET_SIZE - PACKET_HEADER_SIZE;
+/// Ping payload: send time in microseconds since the sender's `PacketManager` was created
+pub const PING_PAYLOAD_LEN: usize = 8;
+/// Pings awaiting a Pong; older ones are forgotten when more are sent
+const MAX_OUTSTANDING_PINGS: usize = 16;
+/// Given-up packets kept for `take_exhausted`; older ones are forgotten if nobody collects them
+const MAX_EXHAUSTED: usize = 256;
+/// Smallest packet size a `PacketManager` can be configured for
+pub const MIN_PACKET_SIZE: usize = 576;
+/// Largest UDP payload over IPv4
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
 
 /// Fragment flags
 pub const FRAG_FIRST: u32 = 0x02;
 pub const FRAG_LAST: u32 = 0x01;
+/// Payload is a chunk-stream frame rather than an encoded message
+pub const FLAG_STREAM: u32 = 0x04;
+/// Payload starts with the sender's 8-byte session ID
+pub const FLAG_SESSION: u32 = 0x08;
+/// Payload is a batch of several small messages (see `coalesce`)
+pub const FLAG_BATCH: u32 = 0x40;
+/// Ping is a path MTU probe (see `mtu`); its Pong echoes the flag
+pub const FLAG_PROBE: u32 = 0x80;
+/// On a Connect, the client can compress fields; on its ConnectAck, the server
+/// accepted (see `compression`). Data packets use this bit for fragmentation.
+pub const FLAG_FIELD_COMPRESSION: u32 = 0x01;
+/// On a Connect, the client can read compact headers; on its ConnectAck, the server
+/// accepted and both sides send them (see `header`)
+pub const FLAG_COMPACT_HEADERS: u32 = 0x02;
+
+/// Flag bits holding a fragment's index within its message
+pub const FRAG_INDEX_MASK: u32 = 0xFFFF_0000;
+const FRAG_INDEX_SHIFT: u32 = 16;
+
+/// Most fragments one message can be split into
+pub const MAX_FRAGMENTS: usize = 1 << 16;
+
+/// Incomplete messages are dropped after waiting this long for their missing fragments
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Two flag bits holding the packet's `SendMode`
+pub const SEND_MODE_MASK: u32 = 0x30;
+const SEND_MODE_SHIFT: u32 = 4;
+
+/// Delivery guarantees for one message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SendMode {
+    /// Retransmitted until ACKed and delivered in send order
+    #[default]
+    ReliableOrdered,
+    /// Retransmitted until ACKed, delivered as it arrives
+    ReliableUnordered,
+    /// Never retransmitted; anything older than the newest packet already delivered is dropped
+    UnreliableSequenced,
+    /// Never retransmitted or ACKed
+    Unreliable,
+}
+
+impl SendMode {
+    pub fn from_flags(flags: u32) -> Self {
+        match (flags & SEND_MODE_MASK) >> SEND_MODE_SHIFT {
+            0 => SendMode::ReliableOrdered,
+            1 => SendMode::ReliableUnordered,
+            2 => SendMode::UnreliableSequenced,
+            _ => SendMode::Unreliable,
+        }
+    }
+
+    pub fn to_flags(self) -> u32 {
+        (self as u32) << SEND_MODE_SHIFT
+    }
+
+    pub fn is_reliable(self) -> bool {
+        matches!(self, SendMode::ReliableOrdered | SendMode::ReliableUnordered)
+    }
+}
+
+/// Where a message waits in the send queue relative to other traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum Priority {
+    /// Input and state updates; always released first
+    High,
+    #[default]
+    Normal,
+    /// Bulk transfers; released a few packets at a time so other traffic can cut in
+    Low,
+}
+
+/// Low-priority packets released per pacing step when no congestion controller is set
+pub const LOW_PRIORITY_BURST: usize = 8;
+
+/// True if sequence `a` comes after `b`, allowing for wraparound
+pub fn sequence_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+/// Floor for the adaptive retransmission timeout
+pub const MIN_RTO: Duration = Duration::from_millis(20);
+
+/// Ceiling for the retransmission timeout, backoff included
+pub const MAX_RTO: Duration = Duration::from_secs(5);
+
+/// Sequences this far behind the newest one received are treated as duplicates
+pub const DEDUP_WINDOW: u32 = 1024;
+
+/// Earlier sequences an ACK confirms alongside its `ack_number`, as a bitfield in the payload
+pub const ACK_BITS: u32 = 32;
+
+/// ACKs for packets sent after a still un-ACKed one that make it count as lost: it is
+/// retransmitted at once rather than when its timeout runs out
+pub const FAST_RETRANSMIT_THRESHOLD: u32 = 3;
+
+/// Reliable packets a channel lets wait for a delayed ACK; it is sent immediately once this many wait
+pub const MAX_ACK_BATCH: usize = 8;
+
+/// Packets a receiver offers to take unless `set_receive_window` says otherwise, and
+/// what a sender assumes until its peer advertises a window
+pub const DEFAULT_RECEIVE_WINDOW: u32 = 512;
+
+/// How the timeout of a packet grows each time it is retransmitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backoff {
+    /// Doubles on every retry
+    #[default]
+    Exponential,
+    /// Grows by one RTO on every retry
+    Linear,
+    /// Stays at the RTO
+    Fixed,
+}
+
+impl Backoff {
+    /// Multiple of the RTO to wait for a packet already retransmitted `retries` times
+    fn factor(self, retries: u32) -> u32 {
+        match self {
+            Backoff::Exponential => 1 << retries.min(16),
+            Backoff::Linear => retries.saturating_add(1),
+            Backoff::Fixed => 1,
+        }
+    }
+}
+
+/// Retransmission settings for one connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitPolicy {
+    /// Retransmission timeout until the first RTT sample; from then on it follows the
+    /// measured round trip
+    pub ack_timeout: Duration,
+    /// Retransmits of a reliable packet before it is given up on
+    pub max_retries: u32,
+    /// How the timeout grows on each retransmit, up to `MAX_RTO`
+    pub backoff: Backoff,
+    /// Reliable packets sent and not yet ACKed, on top of the peer's receive window;
+    /// `None` leaves it to the window
+    pub max_in_flight: Option<usize>,
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_millis(100),
+            max_retries: 3,
+            backoff: Backoff::Exponential,
+            max_in_flight: None,
+        }
+    }
+}
+
+/// Sliding bitmap of the last `DEDUP_WINDOW` sequences received, keyed off the
+/// highest one seen, so duplicate detection uses constant memory
+#[derive(Clone)]
+struct SequenceWindow {
+    highest: Option<u32>,
+    bits: [u64; (DEDUP_WINDOW / 64) as usize],
+}
+
+impl Default for SequenceWindow {
+    fn default() -> Self {
+        Self {
+            highest: None,
+            bits: [0; (DEDUP_WINDOW / 64) as usize],
+        }
+    }
+}
+
+impl SequenceWindow {
+    fn slot(sequence: u32) -> (usize, u64) {
+        let index = sequence % DEDUP_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// Mark `sequence` received; false if it was already seen or has fallen out of the window
+    fn insert(&mut self, sequence: u32) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.set(sequence);
+            return true;
+        };
+
+        if sequence_newer(sequence, highest) {
+            let advance = sequence.wrapping_sub(highest);
+            if advance >= DEDUP_WINDOW {
+                self.bits = [0; (DEDUP_WINDOW / 64) as usize];
+            } else {
+                // Slots being reused for newer sequences forget what they held
+                for step in 1..=advance {
+                    let (word, bit) = Self::slot(highest.wrapping_add(step));
+                    self.bits[word] &= !bit;
+                }
+            }
+            self.highest = Some(sequence);
+            self.set(sequence);
+            return true;
+        }
+
+        if highest.wrapping_sub(sequence) >= DEDUP_WINDOW {
+            return false;
+        }
+        let (word, bit) = Self::slot(sequence);
+        let fresh = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        fresh
+    }
+
+    /// Bit `i` set if `sequence - 1 - i` has been received, for the `ACK_BITS` sequences before `sequence`
+    fn ack_bits(&self, sequence: u32) -> u32 {
+        let Some(highest) = self.highest else {
+            return 0;
+        };
+        (0..ACK_BITS).fold(0, |bits, i| {
+            let earlier = sequence.wrapping_sub(1 + i);
+            let in_window = !sequence_newer(earlier, highest) && highest.wrapping_sub(earlier) < DEDUP_WINDOW;
+            let (word, bit) = Self::slot(earlier);
+            if in_window && self.bits[word] & bit != 0 {
+                bits | 1 << i
+            } else {
+                bits
+            }
+        })
+    }
+
+    fn set(&mut self, sequence: u32) {
+        let (word, bit) = Self::slot(sequence);
+        self.bits[word] |= bit;
+    }
+}
+
+/// Position and value of the newest of `sequences`
+fn newest_sequence(sequences: &[u32]) -> Option<(usize, u32)> {
+    sequences.iter().copied().enumerate().reduce(|newest, next| if sequence_newer(next.1, newest.1) { next } else { newest })
+}
+
+/// Channel used by the plain send APIs
+pub const DEFAULT_CHANNEL: u8 = 0;
+
+/// Channel `send_stream` uses, so chunk transfers don't hold up regular messages
+pub const STREAM_CHANNEL: u8 = 0xFF;
+
+/// Flag bits holding the packet's channel ID
+pub const CHANNEL_MASK: u32 = 0xFF00;
+const CHANNEL_SHIFT: u32 = 8;
+
+/// Flag bits selecting `channel`
+pub fn channel_flags(channel: u8) -> u32 {
+    u32::from(channel) << CHANNEL_SHIFT
+}
+
+/// Size of the session tag carried by `FLAG_SESSION` packets
+pub const SESSION_TAG_LEN: usize = 8;
+
+/// Handshake protocol version; a server refuses clients speaking another version
+pub const PROTOCOL_VERSION: u16 = 1;
 
 /// Represents a single UDP packet with header
 #[derive(Clone)]
@@ -64,203 +349,1497 @@ impl UdpPacket {
         buf
     }
 
-    /// Deserialize packet from bytes
-    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
-        if data.len() < PACKET_HEADER_SIZE {
-            return Err("Packet too small".to_string());
+    /// Deserialize packet from bytes with a full header (never panics on malformed
+    /// input). Packets from a session that negotiated compact headers are read with
+    /// `PacketManager::decode`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < PACKET_HEADER_SIZE {
+            return Err("Packet too small".to_string());
+        }
+
+        let mut reader = Reader::new(data);
+        let header = |e: DecodeError| e.to_string();
+
+        let packet_type = PacketType::from_u8(reader.read_u8("packet type").map_err(header)?)
+            .ok_or_else(|| "Invalid packet type".to_string())?;
+
+        let sequence = u32::from_be_bytes(reader.read_array("sequence").map_err(header)?);
+        let ack_number = u32::from_be_bytes(reader.read_array("ack number").map_err(header)?);
+        let flags = u32::from_be_bytes(reader.read_array("flags").map_err(header)?);
+        let payload = reader.read_rest().to_vec();
+
+        Ok(UdpPacket {
+            packet_type,
+            sequence,
+            ack_number,
+            flags,
+            payload,
+        })
+    }
+
+    /// Unsequenced handshake packet
+    fn control(packet_type: PacketType, payload: Vec<u8>) -> Self {
+        UdpPacket {
+            packet_type,
+            sequence: 0,
+            ack_number: 0,
+            flags: 0,
+            payload,
+        }
+    }
+
+    /// Session request: `[version u16]`
+    pub fn connect() -> Self {
+        Self::control(PacketType::Connect, PROTOCOL_VERSION.to_be_bytes().to_vec())
+    }
+
+    /// Session grant: `[version u16][session_id u64]`
+    pub fn connect_ack(session_id: u64) -> Self {
+        let mut payload = PROTOCOL_VERSION.to_be_bytes().to_vec();
+        payload.extend_from_slice(&session_id.to_be_bytes());
+        Self::control(PacketType::ConnectAck, payload)
+    }
+
+    /// Session close: `[session_id u64]` (0 when refusing a connect)
+    pub fn disconnect(session_id: u64) -> Self {
+        Self::control(PacketType::Disconnect, session_id.to_be_bytes().to_vec())
+    }
+
+    /// Session request asking for an encrypted session: `[version u16][client_random 32]`
+    pub fn connect_encrypted(client_random: &[u8; HANDSHAKE_RANDOM_LEN]) -> Self {
+        let mut payload = PROTOCOL_VERSION.to_be_bytes().to_vec();
+        payload.extend_from_slice(client_random);
+        Self::control(PacketType::Connect, payload)
+    }
+
+    /// Session request carrying credentials for the server's admission check:
+    /// `[version u16][client_random 32][credentials]`. The random is only used if the
+    /// server encrypts sessions; the credentials are sent in the clear.
+    pub fn connect_with_credentials(client_random: &[u8; HANDSHAKE_RANDOM_LEN], credentials: &[u8]) -> Self {
+        let mut packet = Self::connect_encrypted(client_random);
+        packet.payload.extend_from_slice(credentials);
+        packet
+    }
+
+    /// Credentials a `Connect` carries (empty if none)
+    pub fn handshake_credentials(&self) -> &[u8] {
+        match self.packet_type {
+            PacketType::Connect => self.payload.get(2 + HANDSHAKE_RANDOM_LEN..).unwrap_or_default(),
+            _ => &[],
+        }
+    }
+
+    /// Encrypted session grant:
+    /// `[version u16][session_id u64][server_random 32][confirmation 32]`
+    pub fn connect_ack_encrypted(
+        session_id: u64,
+        server_random: &[u8; HANDSHAKE_RANDOM_LEN],
+        confirmation: &[u8; CONFIRMATION_LEN],
+    ) -> Self {
+        let mut packet = Self::connect_ack(session_id);
+        packet.payload.extend_from_slice(server_random);
+        packet.payload.extend_from_slice(confirmation);
+        packet
+    }
+
+    /// Handshake random of an encrypted `Connect` or `ConnectAck`
+    pub fn handshake_random(&self) -> Option<[u8; HANDSHAKE_RANDOM_LEN]> {
+        let offset = match self.packet_type {
+            PacketType::Connect => 2,
+            PacketType::ConnectAck => 2 + SESSION_TAG_LEN,
+            _ => return None,
+        };
+        self.payload.get(offset..offset + HANDSHAKE_RANDOM_LEN)?.try_into().ok()
+    }
+
+    /// Pre-shared key confirmation of an encrypted `ConnectAck`
+    pub fn handshake_confirmation(&self) -> Option<[u8; CONFIRMATION_LEN]> {
+        if self.packet_type != PacketType::ConnectAck {
+            return None;
+        }
+        let offset = 2 + SESSION_TAG_LEN + HANDSHAKE_RANDOM_LEN;
+        self.payload.get(offset..offset + CONFIRMATION_LEN)?.try_into().ok()
+    }
+
+    /// Protocol version of a `Connect` or `ConnectAck`
+    pub fn protocol_version(&self) -> Option<u16> {
+        match self.packet_type {
+            PacketType::Connect | PacketType::ConnectAck => {
+                Reader::new(&self.payload).read_array("protocol version").ok().map(u16::from_be_bytes)
+            }
+            _ => None,
+        }
+    }
+
+    /// Session ID of a `ConnectAck` or `Disconnect`
+    pub fn session_id(&self) -> Option<u64> {
+        let mut reader = Reader::new(&self.payload);
+        match self.packet_type {
+            PacketType::ConnectAck => {
+                reader.read_array::<2>("protocol version").ok()?;
+            }
+            PacketType::Disconnect => {}
+            _ => return None,
+        }
+        reader.read_array("session id").ok().map(u64::from_be_bytes)
+    }
+
+    /// Prefix the payload with `session_id` so the server can find the session
+    /// even if the sender's address changed
+    pub fn tag_session(&mut self, session_id: u64) {
+        if self.flags & FLAG_SESSION == 0 {
+            self.flags |= FLAG_SESSION;
+            self.payload.splice(0..0, session_id.to_be_bytes());
+        }
+    }
+
+    /// Reply to a Ping: the ack number is the Ping's ID and the payload echoes its
+    /// timestamp (MTU probes only echo the probe flag, not their padding)
+    pub fn pong(ping: &UdpPacket) -> Self {
+        let probe = ping.flags & FLAG_PROBE;
+        let echo = if probe != 0 { &[][..] } else { ping.payload.get(..PING_PAYLOAD_LEN).unwrap_or(&ping.payload) };
+        let mut payload = echo.to_vec();
+        if probe == 0 && payload.len() == PING_PAYLOAD_LEN {
+            // Receive and transmit times for clock sync; the Pong goes straight back, so one reading serves both
+            let now = unix_micros();
+            payload.extend_from_slice(&now.to_be_bytes());
+            payload.extend_from_slice(&now.to_be_bytes());
+        }
+        UdpPacket {
+            packet_type: PacketType::Pong,
+            sequence: 0,
+            ack_number: ping.sequence,
+            flags: probe,
+            payload,
+        }
+    }
+
+    /// Session ID in the tag, without removing it (`None` if untagged or truncated)
+    pub fn session_tag(&self) -> Option<u64> {
+        if self.flags & FLAG_SESSION == 0 {
+            return None;
+        }
+        Reader::new(&self.payload).read_array("session tag").ok().map(u64::from_be_bytes)
+    }
+
+    /// Remove the session tag, returning its ID (`None` if untagged or truncated)
+    pub fn take_session(&mut self) -> Option<u64> {
+        let session_id = self.session_tag()?;
+        self.payload.drain(..SESSION_TAG_LEN);
+        self.flags &= !FLAG_SESSION;
+        Some(session_id)
+    }
+
+    pub fn is_first_fragment(&self) -> bool {
+        (self.flags & FRAG_FIRST) != 0
+    }
+
+    pub fn is_last_fragment(&self) -> bool {
+        (self.flags & FRAG_LAST) != 0
+    }
+
+    pub fn is_stream(&self) -> bool {
+        (self.flags & FLAG_STREAM) != 0
+    }
+
+    /// True if this packet is one piece of a larger message
+    pub fn is_fragment(&self) -> bool {
+        (self.flags & (FRAG_FIRST | FRAG_LAST)) != (FRAG_FIRST | FRAG_LAST)
+    }
+
+    /// Position of this fragment within its message
+    pub fn fragment_index(&self) -> u16 {
+        ((self.flags & FRAG_INDEX_MASK) >> FRAG_INDEX_SHIFT) as u16
+    }
+
+    /// ID of the message this packet belongs to: the sequence number of its first fragment
+    pub fn message_id(&self) -> u32 {
+        self.sequence.wrapping_sub(u32::from(self.fragment_index()))
+    }
+
+    pub fn is_batch(&self) -> bool {
+        (self.flags & FLAG_BATCH) != 0
+    }
+
+    /// Encoded messages carried by a data packet: the batch contents for batch
+    /// packets (none if malformed), otherwise the payload itself
+    pub fn messages(&self) -> Vec<&[u8]> {
+        if self.is_batch() {
+            crate::coalesce::decode_batch(&self.payload).unwrap_or_default()
+        } else {
+            vec![&self.payload]
+        }
+    }
+
+    pub fn send_mode(&self) -> SendMode {
+        SendMode::from_flags(self.flags)
+    }
+
+    pub fn channel(&self) -> u8 {
+        ((self.flags & CHANNEL_MASK) >> CHANNEL_SHIFT) as u8
+    }
+}
+
+/// A datagram as read off the wire, before the session it belongs to is known: with a
+/// compact header, a data packet's sequence still holds only its low bits until
+/// `PacketManager::resolve` expands it
+#[derive(Clone)]
+pub(crate) struct WirePacket {
+    pub packet: UdpPacket,
+    /// Bits of the sequence a compact header carried; `None` for a full header
+    sequence_bits: Option<u32>,
+    header_len: usize,
+}
+
+impl WirePacket {
+    /// Read a datagram with a full or compact header (never panics on malformed input)
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !data.first().is_some_and(|&first| header::is_compact(first)) {
+            let packet = UdpPacket::from_bytes(data)?;
+            return Ok(Self { packet, sequence_bits: None, header_len: PACKET_HEADER_SIZE });
+        }
+        let (packet, bits) = header::decode(data).map_err(|e| e.to_string())?;
+        let header_len = data.len() - packet.payload.len();
+        Ok(Self { packet, sequence_bits: Some(bits), header_len })
+    }
+
+    pub fn is_compact(&self) -> bool {
+        self.sequence_bits.is_some()
+    }
+}
+
+/// Sequence state of one channel; every channel numbers its packets independently
+#[derive(Default)]
+struct Channel {
+    next_sequence: u32,
+    /// Newest sequence the peer has ACKed, which bounds how far behind its view of
+    /// the channel can be when a compact header sends only the low bits
+    largest_acked: Option<u32>,
+    /// Recently received sequence numbers (for detecting duplicates)
+    received: SequenceWindow,
+    /// Newest `UnreliableSequenced` packet delivered
+    last_sequenced: Option<u32>,
+    /// Next sequence the reorder buffer releases
+    next_ordered: u32,
+    /// Sequences received ahead of `next_ordered`: the held `ReliableOrdered`
+    /// packet, or `None` for a slot filled by another send mode
+    held: HashMap<u32, Option<UdpPacket>>,
+    /// When the reorder buffer started waiting on the current gap
+    gap_since: Option<Instant>,
+    /// Fragments of messages still being received
+    fragments: FragmentReassembler,
+    /// Reliable packets received and not yet ACKed, while ACKs are delayed
+    unacked: Vec<u32>,
+    /// When the oldest of `unacked` arrived
+    unacked_since: Option<Instant>,
+}
+
+impl Channel {
+    /// Release everything contiguous from `next_ordered`
+    fn release_ready(&mut self, out: &mut Vec<UdpPacket>) {
+        let mut progressed = false;
+        while let Some(slot) = self.held.remove(&self.next_ordered) {
+            out.extend(slot);
+            self.next_ordered = self.next_ordered.wrapping_add(1);
+            progressed = true;
+        }
+        if self.held.is_empty() {
+            self.gap_since = None;
+        } else if progressed || self.gap_since.is_none() {
+            self.gap_since = Some(Instant::now());
+        }
+    }
+
+    /// Give up on the current gap: skip to the oldest held sequence and release from there
+    fn skip_gap(&mut self, out: &mut Vec<UdpPacket>) {
+        let next = self.next_ordered;
+        if let Some(&oldest) = self.held.keys().min_by_key(|seq| seq.wrapping_sub(next)) {
+            self.next_ordered = oldest;
+        }
+        self.release_ready(out);
+    }
+}
+
+/// Live counters for one connection, as reported by `PacketManager::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Smoothed round-trip time, once at least one ACK has been timed
+    pub rtt: Option<Duration>,
+    /// Round-trip time variation
+    pub rtt_var: Duration,
+    /// Datagrams sent, retransmissions and ACKs included
+    pub packets_sent: u64,
+    /// Datagrams received and authenticated
+    pub packets_received: u64,
+    /// Bytes on the wire, headers and encryption overhead included
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub retransmissions: u64,
+    /// Retransmissions triggered by later packets' ACKs rather than a timeout
+    pub fast_retransmits: u64,
+    /// Data packets received more than once
+    pub duplicates: u64,
+    /// Data packets that arrived after a later sequence on their channel
+    pub out_of_order: u64,
+    /// Data packets that arrived more than one sequence ahead of the newest on their
+    /// channel, leaving a gap for late or lost packets
+    pub gaps: u64,
+    /// Furthest an out-of-order packet trailed the newest sequence on its channel
+    pub max_reorder_distance: u32,
+    /// Fraction of reliable transmissions that timed out (0.0 to 1.0)
+    pub loss_estimate: f64,
+}
+
+/// A reliable packet waiting for its ACK
+struct Pending {
+    packet: UdpPacket,
+    /// When the packet last went out; `None` while it waits in the send queue
+    sent_at: Option<Instant>,
+    retries: u32,
+    /// ACKs since it was last sent that confirmed a later packet sent after it
+    overtaken: u32,
+    /// Handle of the send this packet belongs to, resolved as its packets are settled
+    receipt: Option<SendHandle>,
+}
+
+impl Pending {
+    fn new(packet: UdpPacket) -> Self {
+        Self {
+            packet,
+            sent_at: Some(Instant::now()),
+            retries: 0,
+            overtaken: 0,
+            receipt: None,
+        }
+    }
+}
+
+/// Manages packet sequencing, ACKs, and retransmissions
+pub struct PacketManager {
+    channels: HashMap<u8, Channel>,
+    /// Pending packets waiting for ACK, keyed by (channel, sequence)
+    pending_acks: HashMap<(u8, u32), Pending>,
+    /// Send-rate limit; `None` sends everything immediately
+    congestion: Option<Box<dyn CongestionController>>,
+    /// Packets waiting to go out, one queue per `Priority`, oldest first
+    send_queues: [VecDeque<UdpPacket>; 3],
+    /// Session ID tagged onto every outgoing packet, once the handshake is done
+    session: Option<u64>,
+    /// Longest a `ReliableOrdered` packet waits in the reorder buffer for a gap to fill;
+    /// `None` delivers in arrival order
+    reorder_window: Option<Duration>,
+    /// Smoothed round-trip time, `None` until the first sample (RFC 6298 SRTT)
+    srtt: Option<Duration>,
+    /// Round-trip time variation (RFC 6298 RTTVAR)
+    rttvar: Duration,
+    /// Current retransmission timeout; starts at the configured ACK timeout
+    rto: Duration,
+    /// Datagram size packets are fragmented to (header and session tag included)
+    max_packet_size: usize,
+    /// Seals outgoing and opens incoming packets once the handshake negotiated encryption
+    cipher: Option<PacketCipher>,
+    /// Send compact headers, and accept them from the peer, once the handshake
+    /// negotiated them
+    compact_headers: bool,
+    /// Header length of the packet `resolve` last let through, for `open`'s counters
+    received_header_len: Option<usize>,
+    /// Counters behind `stats`; the RTT and loss estimate are filled in on read
+    stats: ConnectionStats,
+    /// Reliable packets tracked for an ACK, for the loss estimate
+    reliable_sent: u64,
+    /// Reference point for Ping timestamps
+    epoch: Instant,
+    /// Wall-clock time at `epoch`, in microseconds since the Unix epoch, so the
+    /// monotonic Ping timestamps can be compared with a peer's clock
+    epoch_unix: u64,
+    /// The peer's clock, as measured by Ping/Pong exchanges
+    clock: ClockSync,
+    /// When `encode` last produced a packet, for keep-alive scheduling
+    last_sent: Instant,
+    /// ID of the next Ping; Pings are numbered apart from data so they leave no sequence gaps
+    next_ping: u32,
+    /// Pings sent and not yet answered, as (ID, timestamp), oldest first
+    outstanding_pings: VecDeque<(u32, u64)>,
+    /// Round-trip times of recently answered Pings, as (ID, RTT), oldest first
+    answered_pings: VecDeque<(u32, Duration)>,
+    /// Reliable packets that ran out of retries, oldest first
+    exhausted: VecDeque<UdpPacket>,
+    /// Where packets sent and received are recorded, if anywhere
+    capture: Option<Capture>,
+    /// Packets this side lets the peer have in flight, before the unread backlog
+    receive_window: u32,
+    /// Messages delivered but not yet taken by the application, as reported by the owner
+    receive_backlog: usize,
+    /// Reliable packets the peer lets this side have in flight, from its latest ACK
+    peer_window: u32,
+    /// When the last packet went out past a closed window to ask for a fresh one
+    last_window_probe: Option<Instant>,
+    /// Longest a received reliable packet waits for its ACK, so one ACK covers several
+    max_ack_delay: Duration,
+    /// Configuration
+    policy: RetransmitPolicy,
+}
+
+impl PacketManager {
+    pub fn new() -> Self {
+        Self {
+            channels: HashMap::new(),
+            pending_acks: HashMap::new(),
+            congestion: None,
+            send_queues: Default::default(),
+            session: None,
+            reorder_window: None,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: RetransmitPolicy::default().ack_timeout,
+            max_packet_size: MAX_PACKET_SIZE,
+            cipher: None,
+            compact_headers: false,
+            received_header_len: None,
+            stats: ConnectionStats::default(),
+            reliable_sent: 0,
+            epoch: Instant::now(),
+            epoch_unix: unix_micros(),
+            clock: ClockSync::default(),
+            last_sent: Instant::now(),
+            next_ping: 0,
+            outstanding_pings: VecDeque::new(),
+            answered_pings: VecDeque::new(),
+            exhausted: VecDeque::new(),
+            capture: None,
+            receive_window: DEFAULT_RECEIVE_WINDOW,
+            receive_backlog: 0,
+            peer_window: DEFAULT_RECEIVE_WINDOW,
+            last_window_probe: None,
+            max_ack_delay: Duration::ZERO,
+            policy: RetransmitPolicy::default(),
+        }
+    }
+
+    pub fn with_config(ack_timeout: Duration, max_retries: u32) -> Self {
+        Self::with_policy(RetransmitPolicy { ack_timeout, max_retries, ..RetransmitPolicy::default() })
+    }
+
+    /// Create a manager that retransmits by `policy`
+    pub fn with_policy(policy: RetransmitPolicy) -> Self {
+        let mut pm = Self::new();
+        pm.set_retransmit_policy(policy);
+        pm
+    }
+
+    /// Retransmit by `policy` from now on. Packets already in flight keep their retry
+    /// counts; the RTO restarts from `ack_timeout` until the next RTT sample.
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.rto = policy.ack_timeout;
+        self.policy = policy;
+    }
+
+    /// Retransmission settings in effect
+    pub fn retransmit_policy(&self) -> RetransmitPolicy {
+        self.policy
+    }
+
+    /// Tag every packet created from now on with `session_id`
+    pub fn set_session(&mut self, session_id: u64) {
+        self.session = Some(session_id);
+    }
+
+    /// Encrypt every packet passed through `encode` and require `open` to authenticate
+    /// every packet received (`None` sends and accepts plaintext)
+    pub fn set_cipher(&mut self, cipher: Option<PacketCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// Record every packet passed through `encode` and `open` to `capture`, before
+    /// sealing and after decryption (`None` stops recording)
+    pub fn set_capture(&mut self, capture: Option<Capture>) {
+        self.capture = capture;
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Send compact headers (see `header`) from now on, once the handshake agreed on
+    /// them; until then, packets from the peer with a compact header are refused
+    pub fn set_compact_headers(&mut self, enabled: bool) {
+        self.compact_headers = enabled;
+    }
+
+    pub fn has_compact_headers(&self) -> bool {
+        self.compact_headers
+    }
+
+    /// Hold the ACKs for received reliable packets up to `delay`, so one ACK covers
+    /// several of them or rides on a data packet going the other way. Keep it well
+    /// below the peer's retransmit timeout. Zero, the default, ACKs each packet as it
+    /// arrives.
+    pub fn set_max_ack_delay(&mut self, delay: Duration) {
+        self.max_ack_delay = delay;
+    }
+
+    pub fn max_ack_delay(&self) -> Duration {
+        self.max_ack_delay
+    }
+
+    /// Serialize a packet for the wire, sealing it if the session is encrypted. Every
+    /// packet sent to the peer should pass through here so it is counted in `stats`.
+    pub fn encode(&mut self, packet: &UdpPacket) -> Vec<u8> {
+        let piggybacked = self.piggyback_ack(packet);
+        let packet = piggybacked.as_ref().unwrap_or(packet);
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Sent, &packet.to_bytes());
+        }
+        let mut bytes = match &self.cipher {
+            Some(cipher) => cipher.seal(packet),
+            None => packet.to_bytes(),
+        };
+        if let Some(compact) = self.compact_headers.then(|| header::encode(packet, self.sequence_bits(packet))).flatten() {
+            bytes.splice(..PACKET_HEADER_SIZE, compact);
+        }
+        self.stats.packets_sent += 1;
+        self.stats.bytes_sent += bytes.len() as u64;
+        telemetry::packet_sent(bytes.len());
+        self.last_sent = Instant::now();
+        bytes
+    }
+
+    /// Low bits of `packet`'s sequence a compact header must carry for the peer to
+    /// expand it, counting from anywhere between its newest ACK and the newest
+    /// sequence sent on the channel
+    fn sequence_bits(&self, packet: &UdpPacket) -> u32 {
+        let Some(state) = self.channels.get(&packet.channel()) else {
+            return 32;
+        };
+        let lowest = state.largest_acked.map_or(0, |acked| acked.wrapping_add(1));
+        let mut behind = (packet.sequence.wrapping_sub(lowest) as i32).unsigned_abs();
+        if !packet.send_mode().is_reliable() {
+            behind = behind.min(header::COMPACT_LOSS_HORIZON);
+        }
+        let ahead = (state.next_sequence.wrapping_sub(packet.sequence) as i32).unsigned_abs();
+        header::sequence_bits(behind.max(ahead))
+    }
+
+    /// Read a datagram from the peer, with a full header or, once negotiated, a
+    /// compact one. Servers, which must find the session first, split this into
+    /// `WirePacket::parse` and `resolve`.
+    pub fn decode(&mut self, data: &[u8]) -> Result<UdpPacket, String> {
+        let wire = WirePacket::parse(data)?;
+        self.resolve(wire).ok_or_else(|| "compact header on a session that did not negotiate it".to_string())
+    }
+
+    /// Finish reading `wire` for this session: expand a compact header's sequence
+    /// against the newest one received on its channel. `None` if the header is compact
+    /// and the session never negotiated compact headers.
+    pub(crate) fn resolve(&mut self, wire: WirePacket) -> Option<UdpPacket> {
+        let mut packet = wire.packet;
+        if let Some(bits) = wire.sequence_bits {
+            if !self.compact_headers {
+                return None;
+            }
+            let highest = self.channels.get(&packet.channel()).and_then(|state| state.received.highest);
+            let expected = highest.map_or(0, |highest| highest.wrapping_add(1));
+            packet.sequence = header::expand_sequence(packet.sequence, bits, expected);
+        }
+        self.received_header_len = Some(wire.header_len);
+        Some(packet)
+    }
+
+    /// Time since the last packet was sent to the peer
+    pub fn idle_time(&self) -> Duration {
+        self.last_sent.elapsed()
+    }
+
+    /// Decrypt a received packet in place if the session is encrypted and count it in
+    /// `stats`; false means it failed authentication and must be dropped
+    pub fn open(&mut self, packet: &mut UdpPacket) -> bool {
+        let header = self.received_header_len.take().unwrap_or(PACKET_HEADER_SIZE);
+        let size = header + packet.payload.len();
+        let authentic = match &mut self.cipher {
+            Some(cipher) => cipher.open(packet),
+            None => true,
+        };
+        if authentic {
+            self.stats.packets_received += 1;
+            self.stats.bytes_received += size as u64;
+            telemetry::packet_received(size);
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Received, &packet.to_bytes());
+            }
+        } else {
+            telemetry::auth_failure();
+        }
+        authentic
+    }
+
+    /// Counters for this connection so far
+    pub fn stats(&self) -> ConnectionStats {
+        let transmissions = self.reliable_sent + self.stats.retransmissions;
+        ConnectionStats {
+            rtt: self.srtt,
+            rtt_var: self.rttvar,
+            loss_estimate: if transmissions == 0 {
+                0.0
+            } else {
+                self.stats.retransmissions as f64 / transmissions as f64
+            },
+            ..self.stats
+        }
+    }
+
+    /// Hold `ReliableOrdered` packets until they can be released in sequence order,
+    /// waiting at most `window` for a missing packet before skipping it. `None`
+    /// (the default) delivers in arrival order.
+    pub fn set_reorder_window(&mut self, window: Option<Duration>) {
+        self.reorder_window = window;
+    }
+
+    /// Limit the send rate with `controller` (`None` sends without limit). Packets
+    /// passed through `pace` beyond its budget wait in the send queue.
+    pub fn set_congestion_controller(&mut self, controller: Option<Box<dyn CongestionController>>) {
+        self.congestion = controller;
+    }
+
+    /// Current send rate allowed by the congestion controller, in bytes per second
+    pub fn send_rate(&self) -> Option<u64> {
+        self.congestion.as_ref().map(|cc| cc.rate())
+    }
+
+    /// Let the peer have up to `packets` reliable packets in flight, less the messages
+    /// the application has yet to read (see `set_receive_backlog`)
+    pub fn set_receive_window(&mut self, packets: u32) {
+        self.receive_window = packets;
+    }
+
+    /// Report how many delivered messages the application has yet to read, so the
+    /// window advertised in ACKs shrinks while it falls behind
+    pub fn set_receive_backlog(&mut self, messages: usize) {
+        self.receive_backlog = messages;
+    }
+
+    /// Window advertised in this side's ACKs: packets the peer may still send
+    pub fn advertised_window(&self) -> u32 {
+        let backlog = u32::try_from(self.receive_backlog).unwrap_or(u32::MAX);
+        self.receive_window.saturating_sub(backlog)
+    }
+
+    /// Reliable packets the peer lets this side have in flight
+    pub fn peer_window(&self) -> u32 {
+        self.peer_window
+    }
+
+    /// Reliable packets sent and not yet ACKed
+    pub fn in_flight(&self) -> usize {
+        self.pending_acks.values().filter(|pending| pending.sent_at.is_some()).count()
+    }
+
+    /// Reliable packets this side may have in flight: the peer's window, capped by
+    /// the policy's `max_in_flight`
+    fn send_window(&self) -> usize {
+        let cap = self.policy.max_in_flight.map_or(usize::MAX, |max| max.max(1));
+        (self.peer_window as usize).min(cap)
+    }
+
+    /// Whether one more reliable packet may go out with `in_flight` already sent. A
+    /// closed window still lets one through every RTO once nothing is in flight, so
+    /// its ACK brings a fresh window even if the update that reopened it was lost.
+    fn window_allows(&mut self, in_flight: usize) -> bool {
+        if in_flight < self.send_window() {
+            return true;
+        }
+        let probe_due = self.last_window_probe.is_none_or(|last| last.elapsed() >= self.rto);
+        if in_flight == 0 && probe_due {
+            self.last_window_probe = Some(Instant::now());
+            return true;
+        }
+        false
+    }
+
+    /// Queue freshly created packets at `Priority::Normal` and return the ones that
+    /// may go out now
+    pub fn pace(&mut self, packets: Vec<UdpPacket>) -> Vec<UdpPacket> {
+        self.pace_with_priority(packets, Priority::Normal)
+    }
+
+    /// Queue freshly created packets behind any already waiting at `priority` and
+    /// return the ones that may go out now. Without a congestion controller, high and
+    /// normal priority packets go straight out while the peer's window has room; low
+    /// priority ones trickle out `LOW_PRIORITY_BURST` at a time on every pacing step.
+    pub fn pace_with_priority(&mut self, packets: Vec<UdpPacket>, priority: Priority) -> Vec<UdpPacket> {
+        let queued_ahead = self.send_queues[..=priority as usize].iter().any(|queue| !queue.is_empty());
+        // Fresh reliable packets already count as in flight until queued
+        let window_open = self.in_flight() <= self.send_window();
+        if self.congestion.is_none() && priority != Priority::Low && !queued_ahead && window_open {
+            let mut out = packets;
+            out.extend(self.release_paced());
+            return out;
+        }
+        for packet in packets {
+            if let Some(pending) = self.pending_acks.get_mut(&(packet.channel(), packet.sequence)) {
+                // Not on the wire yet, so not due for retransmission either
+                pending.sent_at = None;
+            }
+            self.send_queues[priority as usize].push_back(packet);
+        }
+        self.release_paced()
+    }
+
+    /// Packets from the send queues that may go out now, highest priority first:
+    /// as many as the congestion controller allows, or without one everything but
+    /// the low priority queue, which releases `LOW_PRIORITY_BURST` packets per call.
+    /// Reliable packets also wait while the peer's receive window is full.
+    pub fn release_paced(&mut self) -> Vec<UdpPacket> {
+        let mut released = Vec::new();
+        let mut in_flight = self.in_flight();
+        'queues: for level in 0..self.send_queues.len() {
+            let mut burst = 0;
+            while let Some(packet) = self.send_queues[level].front() {
+                let size = PACKET_HEADER_SIZE + packet.payload.len();
+                let reliable = packet.send_mode().is_reliable();
+                if reliable && !self.window_allows(in_flight) {
+                    break 'queues;
+                }
+                let allowed = match self.congestion.as_mut() {
+                    Some(cc) => cc.try_send(size),
+                    None => level != Priority::Low as usize || burst < LOW_PRIORITY_BURST,
+                };
+                if !allowed {
+                    break 'queues;
+                }
+                released.extend(self.send_queues[level].pop_front());
+                in_flight += usize::from(reliable);
+                burst += 1;
+            }
+        }
+        for packet in &released {
+            self.mark_sent(packet);
+        }
+        released
+    }
+
+    /// Number of packets waiting in the send queues
+    pub fn queued_packets(&self) -> usize {
+        self.send_queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Packets this manager still holds: reliable ones awaiting an ACK (queued or not)
+    /// plus unreliable ones waiting in the send queues
+    pub fn backlog(&self) -> usize {
+        let unreliable = self.send_queues.iter().flatten().filter(|p| !p.send_mode().is_reliable()).count();
+        self.pending_acks.len() + unreliable
+    }
+
+    /// Hold on to a reliable packet until it is ACKed
+    fn track(&mut self, channel: u8, packet: &UdpPacket) {
+        self.pending_acks.insert((channel, packet.sequence), Pending::new(packet.clone()));
+        self.reliable_sent += 1;
+    }
+
+    fn mark_sent(&mut self, packet: &UdpPacket) {
+        if let Some(pending) = self.pending_acks.get_mut(&(packet.channel(), packet.sequence)) {
+            pending.sent_at = Some(Instant::now());
+        }
+    }
+
+    /// Feed a round-trip measurement into the RTT estimate and recompute the
+    /// retransmission timeout as `SRTT + 4 * RTTVAR` (RFC 6298)
+    pub fn record_rtt_sample(&mut self, rtt: Duration) {
+        telemetry::rtt(rtt);
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(rtt);
+        self.rto = (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Smoothed round-trip time, once at least one ACK has been timed
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Round-trip time variation
+    pub fn rtt_variance(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// Current retransmission timeout, before backoff
+    pub fn retransmit_timeout(&self) -> Duration {
+        self.rto
+    }
+
+    /// Timeout for a packet already retransmitted `retries` times, grown by the policy's backoff
+    fn backoff_timeout(&self, retries: u32) -> Duration {
+        self.rto
+            .checked_mul(self.policy.backoff.factor(retries))
+            .map_or(MAX_RTO, |timeout| timeout.min(MAX_RTO))
+    }
+
+    /// Largest payload that still fits one packet after the session tag and encryption overhead
+    pub fn payload_limit(&self) -> usize {
+        self.max_packet_size - self.packet_overhead()
+    }
+
+    /// Bytes every packet spends on the header, session tag and encryption
+    fn packet_overhead(&self) -> usize {
+        let tag = if self.session.is_some() { SESSION_TAG_LEN } else { 0 };
+        let cipher = if self.cipher.is_some() { CIPHER_OVERHEAD } else { 0 };
+        PACKET_HEADER_SIZE + tag + cipher
+    }
+
+    /// Datagram size messages are fragmented to
+    pub fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    /// Fragment to `size`-byte datagrams from now on, e.g. once path MTU discovery
+    /// has found a larger size (clamped to `MIN_PACKET_SIZE..=MAX_DATAGRAM_SIZE`)
+    pub fn set_max_packet_size(&mut self, size: usize) {
+        self.max_packet_size = size.clamp(MIN_PACKET_SIZE, MAX_DATAGRAM_SIZE);
+    }
+
+    /// Ping padded to exactly `size` bytes on the wire, for path MTU discovery. The
+    /// probe ID rides in the sequence field, which the Pong echoes as its ack number.
+    pub fn create_mtu_probe(&self, probe_id: u32, size: usize) -> UdpPacket {
+        let mut packet = UdpPacket {
+            packet_type: PacketType::Ping,
+            sequence: probe_id,
+            ack_number: 0,
+            flags: FLAG_PROBE,
+            payload: vec![0; size.saturating_sub(self.packet_overhead())],
+        };
+        if let Some(session_id) = self.session {
+            packet.tag_session(session_id);
+        }
+        packet
+    }
+
+    /// Build the next packet on `channel`, consuming a sequence number
+    fn next_packet(&mut self, channel: u8, packet_type: PacketType, flags: u32, payload: &[u8]) -> UdpPacket {
+        let state = self.channels.entry(channel).or_default();
+        let sequence = state.next_sequence;
+        state.next_sequence = sequence.wrapping_add(1);
+        // Compact headers leave the ack number out unless `encode` piggybacks an ACK on it
+        let ack_number = if self.compact_headers { u32::MAX } else { state.received.highest.unwrap_or(u32::MAX) };
+
+        let mut packet = UdpPacket {
+            packet_type,
+            sequence,
+            ack_number,
+            flags: (flags & !CHANNEL_MASK) | channel_flags(channel),
+            payload: payload.to_vec(),
+        };
+        if let Some(session_id) = self.session {
+            packet.tag_session(session_id);
+        }
+        packet
+    }
+
+    /// Create data packets from a message buffer, handling fragmentation
+    pub fn create_packets(&mut self, data: &[u8]) -> Vec<UdpPacket> {
+        self.create_packets_on(DEFAULT_CHANNEL, data, SendMode::default())
+    }
+
+    /// Create data packets delivered according to `mode`; only reliable packets
+    /// are tracked for retransmission
+    pub fn create_packets_with_mode(&mut self, data: &[u8], mode: SendMode) -> Vec<UdpPacket> {
+        self.create_packets_on(DEFAULT_CHANNEL, data, mode)
+    }
+
+    /// Largest message `create_packets` can fragment
+    pub fn max_message_size(&self) -> usize {
+        self.payload_limit() * MAX_FRAGMENTS
+    }
+
+    /// `InvalidInput` if a message of `len` bytes can't be fragmented
+    pub(crate) fn check_message_size(&self, len: usize) -> std::io::Result<()> {
+        if len > self.max_message_size() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Message too large"));
+        }
+        Ok(())
+    }
+
+    /// Create data packets on `channel`, which has its own sequence numbers so
+    /// its traffic never holds up other channels. `data` must not exceed
+    /// `max_message_size()`.
+    pub fn create_packets_on(&mut self, channel: u8, data: &[u8], mode: SendMode) -> Vec<UdpPacket> {
+        let limit = self.payload_limit();
+        let mode_flags = mode.to_flags();
+
+        // An empty message still needs one packet
+        let fragments: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(limit).collect() };
+        debug_assert!(fragments.len() <= MAX_FRAGMENTS);
+        let last = fragments.len() - 1;
+
+        let mut packets = Vec::with_capacity(fragments.len());
+        for (i, chunk) in fragments.into_iter().enumerate() {
+            let flags = if i == 0 { FRAG_FIRST } else { 0 }
+                | if i == last { FRAG_LAST } else { 0 }
+                | (i as u32) << FRAG_INDEX_SHIFT
+                | mode_flags;
+            let packet = self.next_packet(channel, PacketType::Data, flags, chunk);
+
+            if mode.is_reliable() {
+                self.track(channel, &packet);
+            }
+            packets.push(packet);
+        }
+
+        packets
+    }
+
+    /// Create a single batch packet from an `encode_batch` payload, tracked for ACK if
+    /// `mode` is reliable. The payload must fit in `payload_limit()`.
+    pub fn create_batch_packet(&mut self, channel: u8, payload: &[u8], mode: SendMode) -> UdpPacket {
+        debug_assert!(payload.len() <= self.payload_limit());
+        let flags = FRAG_FIRST | FRAG_LAST | FLAG_BATCH | mode.to_flags();
+        let packet = self.next_packet(channel, PacketType::Data, flags, payload);
+        if mode.is_reliable() {
+            self.track(channel, &packet);
+        }
+        packet
+    }
+
+    /// Create a single unfragmented data packet with extra `flags` (which may select
+    /// a channel), tracked for ACK. The payload must fit in `payload_limit()`.
+    pub fn create_flagged_packet(&mut self, payload: &[u8], flags: u32) -> UdpPacket {
+        debug_assert!(payload.len() <= self.payload_limit());
+        let channel = ((flags & CHANNEL_MASK) >> CHANNEL_SHIFT) as u8;
+        let packet = self.next_packet(channel, PacketType::Data, FRAG_FIRST | FRAG_LAST | flags, payload);
+        self.track(channel, &packet);
+        packet
+    }
+
+    /// Note that the reliable data packet `packet` wants an ACK. Returns the ACKs to
+    /// send now: every one due, which is all of them without a `max_ack_delay` or once
+    /// `MAX_ACK_BATCH` packets wait on a channel. Call `take_due_acks` for
+    /// the rest as time passes.
+    pub fn queue_ack(&mut self, packet: &UdpPacket) -> Vec<UdpPacket> {
+        self.queue_ack_at(packet, Instant::now())
+    }
+
+    /// `queue_ack`, with `packet` arriving at `now`
+    pub fn queue_ack_at(&mut self, packet: &UdpPacket, now: Instant) -> Vec<UdpPacket> {
+        let state = self.channels.entry(packet.channel()).or_default();
+        state.unacked.push(packet.sequence);
+        state.unacked_since.get_or_insert(now);
+        self.take_due_acks_at(now)
+    }
+
+    /// ACKs whose delay ran out by now
+    pub fn take_due_acks(&mut self) -> Vec<UdpPacket> {
+        self.take_due_acks_at(Instant::now())
+    }
+
+    /// ACKs whose delay ran out by `now`. Each covers the newest waiting packet on its
+    /// channel and, through its bitfield, up to `ACK_BITS` before it.
+    pub fn take_due_acks_at(&mut self, now: Instant) -> Vec<UdpPacket> {
+        let delay = self.max_ack_delay;
+        let mut due: Vec<(u8, Vec<u32>)> = Vec::new();
+        for (&channel, state) in self.channels.iter_mut() {
+            let waited = state.unacked_since.is_some_and(|since| now.saturating_duration_since(since) >= delay);
+            if waited || state.unacked.len() >= MAX_ACK_BATCH {
+                state.unacked_since = None;
+                due.push((channel, std::mem::take(&mut state.unacked)));
+            }
+        }
+
+        let mut acks = Vec::new();
+        for (channel, mut unacked) in due {
+            while let Some((_, newest)) = newest_sequence(&unacked) {
+                acks.push(self.create_ack_on(channel, newest));
+                unacked.retain(|&sequence| newest.wrapping_sub(sequence) > ACK_BITS);
+            }
+        }
+        acks
+    }
+
+    /// When the next delayed ACK is due, if any is waiting
+    pub fn next_ack_due(&self) -> Option<Instant> {
+        self.channels.values().filter_map(|state| state.unacked_since).min().map(|since| since + self.max_ack_delay)
+    }
+
+    /// `packet` with the newest ACK waiting on its channel in its ack number, if it is
+    /// a data packet and one is waiting; the peer takes that as an ACK for it alone
+    fn piggyback_ack(&mut self, packet: &UdpPacket) -> Option<UdpPacket> {
+        if packet.packet_type != PacketType::Data {
+            return None;
+        }
+        let state = self.channels.get_mut(&packet.channel())?;
+        let (index, newest) = newest_sequence(&state.unacked)?;
+        state.unacked.swap_remove(index);
+        if state.unacked.is_empty() {
+            state.unacked_since = None;
+        }
+        let mut packet = packet.clone();
+        packet.ack_number = newest;
+        Some(packet)
+    }
+
+    /// Create an ACK packet
+    pub fn create_ack_packet(&self, ack_sequence: u32) -> UdpPacket {
+        self.create_ack_on(DEFAULT_CHANNEL, ack_sequence)
+    }
+
+    /// Create the ACK for a received data packet, on the packet's channel
+    pub fn create_ack_for(&self, packet: &UdpPacket) -> UdpPacket {
+        self.create_ack_on(packet.channel(), packet.sequence)
+    }
+
+    /// The payload is a big-endian bitfield of the `ACK_BITS` sequences before
+    /// `ack_sequence` that have also arrived, so one ACK can cover a lost one, then
+    /// the big-endian `advertised_window`: `[ack_bits u32][window u32]`
+    fn create_ack_on(&self, channel: u8, ack_sequence: u32) -> UdpPacket {
+        let state = self.channels.get(&channel);
+        let bits = state.map_or(0, |c| c.received.ack_bits(ack_sequence));
+        let mut payload = bits.to_be_bytes().to_vec();
+        payload.extend_from_slice(&self.advertised_window().to_be_bytes());
+        let mut packet = UdpPacket {
+            packet_type: PacketType::Ack,
+            sequence: state.map_or(0, |c| c.next_sequence),
+            ack_number: ack_sequence,
+            flags: channel_flags(channel),
+            payload,
+        };
+        if let Some(session_id) = self.session {
+            packet.tag_session(session_id);
+        }
+        packet
+    }
+
+    /// Create a PING packet carrying its send time; its ID (the sequence field) comes
+    /// back as the Pong's ack number
+    pub fn create_ping_packet(&mut self) -> UdpPacket {
+        let id = self.next_ping;
+        self.next_ping = id.wrapping_add(1);
+        let timestamp = self.epoch.elapsed().as_micros() as u64;
+        if self.outstanding_pings.len() == MAX_OUTSTANDING_PINGS {
+            self.outstanding_pings.pop_front();
+        }
+        self.outstanding_pings.push_back((id, timestamp));
+
+        let mut packet = UdpPacket {
+            packet_type: PacketType::Ping,
+            sequence: id,
+            ack_number: 0,
+            flags: 0,
+            payload: timestamp.to_be_bytes().to_vec(),
+        };
+        if let Some(session_id) = self.session {
+            packet.tag_session(session_id);
+        }
+        packet
+    }
+
+    /// Match a Pong to its outstanding Ping and return the round trip, which also feeds
+    /// the RTT estimate. Pongs for unknown or forgotten Pings, or whose echoed
+    /// timestamp doesn't match, are ignored.
+    pub fn handle_pong(&mut self, pong: &UdpPacket) -> Option<Duration> {
+        let echoed = u64::from_be_bytes(pong.payload.get(..PING_PAYLOAD_LEN)?.try_into().ok()?);
+        let index = self
+            .outstanding_pings
+            .iter()
+            .position(|&(id, timestamp)| id == pong.ack_number && timestamp == echoed)?;
+        self.outstanding_pings.remove(index);
+
+        let now = self.epoch.elapsed().as_micros() as u64;
+        let rtt = Duration::from_micros(now.saturating_sub(echoed));
+        self.record_rtt_sample(rtt);
+        if let Some(times) = pong.payload.get(PING_PAYLOAD_LEN..PING_PAYLOAD_LEN + PONG_TIMES_LEN) {
+            let peer_received = u64::from_be_bytes(times[..8].try_into().unwrap());
+            let peer_sent = u64::from_be_bytes(times[8..].try_into().unwrap());
+            self.clock.add_sample(self.epoch_unix + echoed, peer_received, peer_sent, self.epoch_unix + now);
+        }
+        if self.answered_pings.len() == MAX_OUTSTANDING_PINGS {
+            self.answered_pings.pop_front();
+        }
+        self.answered_pings.push_back((pong.ack_number, rtt));
+        Some(rtt)
+    }
+
+    /// Offset and drift of the peer's clock against this machine's, from Ping/Pong
+    /// exchanges; `None` until a Pong from a peer that reports its times
+    pub fn clock_estimate(&self) -> Option<ClockEstimate> {
+        self.clock.estimate()
+    }
+
+    /// What the peer's clock reads now, by the latest clock estimate
+    pub fn peer_time_estimate(&self) -> Option<SystemTime> {
+        let local = self.epoch_unix + self.epoch.elapsed().as_micros() as u64;
+        self.clock.peer_time(local).map(|micros| UNIX_EPOCH + Duration::from_micros(micros))
+    }
+
+    /// Round trip of the most recently answered Ping
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.answered_pings.back().map(|&(_, rtt)| rtt)
+    }
+
+    /// Round trip of Ping `id`, once its Pong has arrived
+    pub fn ping_rtt(&self, id: u32) -> Option<Duration> {
+        self.answered_pings.iter().find(|&&(answered, _)| answered == id).map(|&(_, rtt)| rtt)
+    }
+
+    /// Record received packet to prevent duplicate processing
+    pub fn record_received(&mut self, sequence: u32) -> bool {
+        self.record_received_on(DEFAULT_CHANNEL, sequence)
+    }
+
+    fn record_received_on(&mut self, channel: u8, sequence: u32) -> bool {
+        let state = self.channels.entry(channel).or_default();
+        let highest = state.received.highest;
+        let late = highest.is_some_and(|highest| !sequence_newer(sequence, highest));
+        // Duplicate, or too old to tell
+        let fresh = state.received.insert(sequence);
+        if !fresh {
+            self.stats.duplicates += 1;
+        } else if let Some(highest) = highest {
+            if late {
+                self.stats.out_of_order += 1;
+                let distance = highest.wrapping_sub(sequence);
+                self.stats.max_reorder_distance = self.stats.max_reorder_distance.max(distance);
+            } else if sequence.wrapping_sub(highest) > 1 {
+                self.stats.gaps += 1;
+            }
+        }
+        fresh
+    }
+
+    /// Record an incoming data packet and decide whether to deliver it: duplicates
+    /// are dropped, and so are sequenced packets older than the newest one delivered
+    /// on the same channel
+    pub fn receive_data(&mut self, packet: &UdpPacket) -> bool {
+        self.record_received_on(packet.channel(), packet.sequence) && self.accept_sequenced(packet)
+    }
+
+    /// Record an incoming data packet and return the packets now ready for the
+    /// application. Without a reorder window this is `receive_data`; with one,
+    /// `ReliableOrdered` packets are held until every earlier sequence on their
+    /// channel has arrived or the window runs out. Packets arriving after their
+    /// gap was skipped are delivered late rather than dropped. Fragments are held
+    /// until their message is complete and come out as one packet carrying the
+    /// whole message.
+    pub fn deliver(&mut self, packet: UdpPacket) -> Vec<UdpPacket> {
+        // A data packet's ack number confirms a packet that went the other way
+        if packet.ack_number != u32::MAX {
+            self.confirm(packet.channel(), packet.ack_number);
         }
+        let packets = self.deliver_packet(packet);
+        self.reassemble(packets)
+    }
 
-        let packet_type = PacketType::from_u8(data[0])
-            .ok_or_else(|| "Invalid packet type".to_string())?;
-        
-        let sequence = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
-        let ack_number = u32::from_be_bytes([data[5], data[6], data[7], data[8]]);
-        let flags = u32::from_be_bytes([data[9], data[10], data[11], data[12]]);
-        
-        let payload = if data.len() > PACKET_HEADER_SIZE {
-            data[PACKET_HEADER_SIZE..].to_vec()
-        } else {
-            Vec::new()
-        };
+    fn deliver_packet(&mut self, packet: UdpPacket) -> Vec<UdpPacket> {
+        let channel = packet.channel();
+        if !self.record_received_on(channel, packet.sequence) {
+            return Vec::new();
+        }
+        let accepted = self.accept_sequenced(&packet);
+        if self.reorder_window.is_none() {
+            return if accepted { vec![packet] } else { Vec::new() };
+        }
 
-        Ok(UdpPacket {
-            packet_type,
-            sequence,
-            ack_number,
-            flags,
-            payload,
-        })
+        let mut out = self.flush_reorder();
+        let state = self.channels.entry(channel).or_default();
+        let seq = packet.sequence;
+        let ordered = packet.send_mode() == SendMode::ReliableOrdered;
+        if seq != state.next_ordered && !sequence_newer(seq, state.next_ordered) {
+            // Already released past this sequence
+            if accepted {
+                out.push(packet);
+            }
+            return out;
+        }
+
+        if ordered {
+            state.held.insert(seq, Some(packet));
+        } else {
+            // Other modes never wait, but still fill their slot in the sequence
+            state.held.insert(seq, None);
+            if accepted {
+                out.push(packet);
+            }
+        }
+        state.release_ready(&mut out);
+        out
     }
 
-    pub fn is_first_fragment(&self) -> bool {
-        (self.flags & FRAG_FIRST) != 0
+    /// Release packets whose gap has been waiting longer than the reorder window
+    pub fn flush_reorder(&mut self) -> Vec<UdpPacket> {
+        self.flush_reorder_at(Instant::now())
     }
 
-    pub fn is_last_fragment(&self) -> bool {
-        (self.flags & FRAG_LAST) != 0
+    /// `flush_reorder` with gaps timed against `now`
+    pub fn flush_reorder_at(&mut self, now: Instant) -> Vec<UdpPacket> {
+        let mut out = Vec::new();
+        let Some(window) = self.reorder_window else {
+            return out;
+        };
+        for state in self.channels.values_mut() {
+            while state.gap_since.is_some_and(|since| now.saturating_duration_since(since) >= window) {
+                state.skip_gap(&mut out);
+            }
+        }
+        self.reassemble(out)
     }
-}
 
-/// Manages packet sequencing, ACKs, and retransmissions
-pub struct PacketManager {
-    sequence_number: u32,
-    last_ack_received: u32,
-    /// Pending packets waiting for ACK: sequence -> (packet, send_time, retries)
-    pending_acks: HashMap<u32, (UdpPacket, Instant, u32)>,
-    /// Received sequence numbers (for detecting duplicates)
-    received_sequences: std::collections::HashSet<u32>,
-    /// Configuration
-    ack_timeout: Duration,
-    max_retries: u32,
-}
+    /// Pass whole messages through and stitch fragments back together
+    fn reassemble(&mut self, packets: Vec<UdpPacket>) -> Vec<UdpPacket> {
+        let mut out = Vec::with_capacity(packets.len());
+        for mut packet in packets {
+            if !packet.is_fragment() {
+                out.push(packet);
+                continue;
+            }
+            let fragments = &mut self.channels.entry(packet.channel()).or_default().fragments;
+            fragments.cleanup();
+            let index = u32::from(packet.fragment_index());
+            let is_first = packet.flags & FRAG_FIRST != 0;
+            let is_last = packet.flags & FRAG_LAST != 0;
+            let payload = std::mem::take(&mut packet.payload);
+            if let Some(message) = fragments.add_fragment(packet.message_id(), index, is_first, is_last, payload) {
+                packet.sequence = packet.message_id();
+                packet.flags = (packet.flags & !FRAG_INDEX_MASK) | FRAG_FIRST | FRAG_LAST;
+                packet.payload = message;
+                out.push(packet);
+            }
+        }
+        out
+    }
 
-impl PacketManager {
-    pub fn new() -> Self {
-        Self {
-            sequence_number: 0,
-            last_ack_received: u32::MAX, // Start at max so first real ack is 0
-            pending_acks: HashMap::new(),
-            received_sequences: std::collections::HashSet::new(),
-            ack_timeout: Duration::from_millis(100),
-            max_retries: 3,
+    /// Drop `UnreliableSequenced` packets older than the newest one delivered on their channel
+    fn accept_sequenced(&mut self, packet: &UdpPacket) -> bool {
+        if packet.send_mode() != SendMode::UnreliableSequenced {
+            return true;
+        }
+        let state = self.channels.entry(packet.channel()).or_default();
+        match state.last_sequenced {
+            Some(last) if !sequence_newer(packet.sequence, last) => false,
+            _ => {
+                state.last_sequenced = Some(packet.sequence);
+                true
+            }
         }
     }
 
-    pub fn with_config(ack_timeout: Duration, max_retries: u32) -> Self {
-        let mut pm = Self::new();
-        pm.ack_timeout = ack_timeout;
-        pm.max_retries = max_retries;
-        pm
+    /// Handle incoming ACK, returns true if it was for a pending packet
+    pub fn handle_ack(&mut self, ack_number: u32) -> bool {
+        self.ack_pending(DEFAULT_CHANNEL, ack_number)
     }
 
-    /// Create data packets from a message buffer, handling fragmentation
-    pub fn create_packets(&mut self, data: &[u8]) -> Vec<UdpPacket> {
-        let mut packets = Vec::new();
-
-        if data.len() <= MAX_PAYLOAD_SIZE {
-            // Single packet
-            let packet = UdpPacket {
-                packet_type: PacketType::Data,
-                sequence: self.sequence_number,
-                ack_number: self.last_ack_received,
-                flags: FRAG_FIRST | FRAG_LAST, // Both first and last
-                payload: data.to_vec(),
-            };
-            self.pending_acks.insert(
-                self.sequence_number,
-                (packet.clone(), Instant::now(), 0),
-            );
-            packets.push(packet);
-            self.sequence_number = self.sequence_number.wrapping_add(1);
-        } else {
-            // Multi-packet fragmentation
-            let mut offset = 0;
-            while offset < data.len() {
-                let end = std::cmp::min(offset + MAX_PAYLOAD_SIZE, data.len());
-                let chunk = &data[offset..end];
-                
-                let is_first = offset == 0;
-                let is_last = end == data.len();
-                let flags = if is_first { FRAG_FIRST } else { 0 }
-                    | if is_last { FRAG_LAST } else { 0 };
-
-                let packet = UdpPacket {
-                    packet_type: PacketType::Data,
-                    sequence: self.sequence_number,
-                    ack_number: self.last_ack_received,
-                    flags,
-                    payload: chunk.to_vec(),
-                };
+    /// Handle an incoming ACK packet on its channel, including the earlier sequences
+    /// in its bitfield and the receive window it advertises; returns true if it
+    /// confirmed any pending packet. Packets it shows were overtaken may be queued
+    /// for fast retransmission, so call `release_paced` afterwards.
+    pub fn handle_ack_packet(&mut self, packet: &UdpPacket) -> bool {
+        let channel = packet.channel();
+        let mut acked = Vec::new();
+        acked.extend(self.confirm_timed(channel, packet.ack_number));
 
-                self.pending_acks.insert(
-                    self.sequence_number,
-                    (packet.clone(), Instant::now(), 0),
-                );
-                packets.push(packet);
-                self.sequence_number = self.sequence_number.wrapping_add(1);
-                offset = end;
+        // Older peers don't advertise a window
+        if let Some(&[a, b, c, d]) = packet.payload.get(4..8) {
+            self.peer_window = u32::from_be_bytes([a, b, c, d]);
+        }
+
+        // ACKs without a bitfield only confirm `ack_number`
+        if let Some(bits) = packet.payload.get(..4) {
+            let bits = u32::from_be_bytes([bits[0], bits[1], bits[2], bits[3]]);
+            for i in (0..ACK_BITS).filter(|i| bits & 1 << i != 0) {
+                let sequence = packet.ack_number.wrapping_sub(1 + i);
+                acked.extend(self.confirm(channel, sequence));
             }
         }
+        self.fast_retransmit(channel, &acked);
+        !acked.is_empty()
+    }
 
-        packets
+    fn ack_pending(&mut self, channel: u8, sequence: u32) -> bool {
+        self.confirm_timed(channel, sequence).is_some()
     }
 
-    /// Create an ACK packet
-    pub fn create_ack_packet(&self, ack_sequence: u32) -> UdpPacket {
-        UdpPacket {
-            packet_type: PacketType::Ack,
-            sequence: self.sequence_number,
-            ack_number: ack_sequence,
-            flags: 0,
-            payload: Vec::new(),
+    /// `confirm`, taking an RTT sample from the packet
+    fn confirm_timed(&mut self, channel: u8, sequence: u32) -> Option<Pending> {
+        let pending = self.confirm(channel, sequence)?;
+        // Karn's algorithm: an ACK for a retransmitted packet can't be timed reliably
+        if let (0, Some(sent_at)) = (pending.retries, pending.sent_at) {
+            self.record_rtt_sample(sent_at.elapsed());
         }
+        Some(pending)
     }
 
-    /// Create a PING packet
-    pub fn create_ping_packet(&mut self) -> UdpPacket {
-        let packet = UdpPacket {
-            packet_type: PacketType::Ping,
-            sequence: self.sequence_number,
-            ack_number: self.last_ack_received,
-            flags: 0,
-            payload: Instant::now()
-                .elapsed()
-                .as_millis()
-                .to_le_bytes()
-                .to_vec(),
+    /// Count an ACK against every packet on `channel` that went out before the latest
+    /// one it confirmed, with an earlier sequence, and is still un-ACKed. Those that
+    /// reach `FAST_RETRANSMIT_THRESHOLD` go to the front of the send queue.
+    fn fast_retransmit(&mut self, channel: u8, acked: &[Pending]) {
+        let latest = acked.iter().filter_map(|p| Some((p.sent_at?, p.packet.sequence))).max_by_key(|&(sent, _)| sent);
+        let Some((latest_sent, latest_sequence)) = latest else {
+            return;
         };
-        self.sequence_number = self.sequence_number.wrapping_add(1);
-        packet
-    }
+        let mut resend = Vec::new();
+        for (&(pending_channel, sequence), pending) in self.pending_acks.iter_mut() {
+            let overtaken = pending_channel == channel
+                && pending.sent_at.is_some_and(|sent_at| sent_at <= latest_sent)
+                && sequence_newer(latest_sequence, sequence);
+            if !overtaken {
+                continue;
+            }
+            pending.overtaken += 1;
+            if pending.overtaken >= FAST_RETRANSMIT_THRESHOLD && pending.retries < self.policy.max_retries {
+                // Back in the queue, so not due for a timeout retransmit meanwhile
+                pending.sent_at = None;
+                pending.overtaken = 0;
+                pending.retries += 1;
+                event!(DEBUG, channel, sequence, attempt = pending.retries, "fast retransmit");
+                resend.push(pending.packet.clone());
+            }
+        }
+        if resend.is_empty() {
+            return;
+        }
 
-    /// Record received packet to prevent duplicate processing
-    pub fn record_received(&mut self, sequence: u32) -> bool {
-        if self.received_sequences.contains(&sequence) {
-            return false; // Duplicate
+        // Oldest first, ahead of everything already queued
+        resend.sort_by_key(|packet| packet.sequence.wrapping_sub(latest_sequence));
+        self.stats.retransmissions += resend.len() as u64;
+        self.stats.fast_retransmits += resend.len() as u64;
+        telemetry::retransmissions(resend.len());
+        if let Some(cc) = self.congestion.as_mut() {
+            cc.on_loss();
+        }
+        for packet in resend.into_iter().rev() {
+            self.send_queues[Priority::High as usize].push_front(packet);
         }
-        self.received_sequences.insert(sequence);
-        self.last_ack_received = self.last_ack_received.max(sequence);
-        true
     }
 
-    /// Handle incoming ACK, returns true if it was for a pending packet
-    pub fn handle_ack(&mut self, ack_number: u32) -> bool {
-        self.pending_acks.remove(&ack_number).is_some()
+    /// Stop tracking an ACKed packet and credit its size to the congestion controller
+    fn confirm(&mut self, channel: u8, sequence: u32) -> Option<Pending> {
+        let pending = self.pending_acks.remove(&(channel, sequence))?;
+        let state = self.channels.entry(channel).or_default();
+        if state.largest_acked.is_none_or(|largest| sequence_newer(sequence, largest)) {
+            state.largest_acked = Some(sequence);
+        }
+        if let Some(receipt) = &pending.receipt {
+            receipt.packet_acked();
+        }
+        if let Some(cc) = self.congestion.as_mut() {
+            cc.on_ack(PACKET_HEADER_SIZE + pending.packet.payload.len());
+        }
+        Some(pending)
     }
 
-    /// Get packets that need retransmission due to timeout
+    /// Get packets that need retransmission due to timeout. Retransmits skip the send
+    /// queue, but each batch counts as a loss for the congestion controller.
     pub fn get_retransmit_packets(&mut self) -> Vec<(UdpPacket, u32)> {
-        let now = Instant::now();
+        self.get_retransmit_packets_at(Instant::now())
+    }
+
+    /// `get_retransmit_packets` with timeouts judged against `now`
+    pub fn get_retransmit_packets_at(&mut self, now: Instant) -> Vec<(UdpPacket, u32)> {
         let mut to_retransmit = Vec::new();
         let mut to_remove = Vec::new();
+        let mut to_cancel = Vec::new();
 
-        for (&seq, (packet, send_time, retries)) in self.pending_acks.iter_mut() {
-            if now.duration_since(*send_time) > self.ack_timeout {
-                if *retries < self.max_retries {
+        let timeouts: Vec<Duration> = (0..=self.policy.max_retries).map(|retries| self.backoff_timeout(retries)).collect();
+
+        for (&key, pending) in self.pending_acks.iter_mut() {
+            let Some(sent_at) = pending.sent_at else {
+                continue; // Still queued
+            };
+            if pending.receipt.as_ref().is_some_and(SendHandle::is_cancelled) {
+                to_cancel.push(key);
+                continue;
+            }
+            if now.duration_since(sent_at) > timeouts[pending.retries as usize] {
+                if pending.retries < self.policy.max_retries {
                     // Retransmit
-                    let retry_packet = packet.clone();
-                    *send_time = now;
-                    *retries += 1;
-                    to_retransmit.push((retry_packet, *retries));
+                    pending.sent_at = Some(now);
+                    pending.retries += 1;
+                    pending.overtaken = 0;
+                    event!(DEBUG, channel = key.0, sequence = key.1, attempt = pending.retries, "retransmitting");
+                    to_retransmit.push((pending.packet.clone(), pending.retries));
                 } else {
                     // Max retries exceeded
-                    to_remove.push(seq);
+                    event!(DEBUG, channel = key.0, sequence = key.1, "dropped packet: out of retries");
+                    to_remove.push(key);
                 }
             }
         }
 
-        for seq in to_remove {
-            self.pending_acks.remove(&seq);
+        for key in to_cancel {
+            self.pending_acks.remove(&key);
+        }
+        for key in to_remove {
+            if let Some(pending) = self.pending_acks.remove(&key) {
+                if let Some(receipt) = &pending.receipt {
+                    receipt.resolve(SendOutcome::Dropped);
+                }
+                if self.exhausted.len() == MAX_EXHAUSTED {
+                    self.exhausted.pop_front();
+                }
+                let mut packet = pending.packet;
+                packet.take_session();
+                self.exhausted.push_back(packet);
+            }
+        }
+        self.stats.retransmissions += to_retransmit.len() as u64;
+        telemetry::retransmissions(to_retransmit.len());
+        if !to_retransmit.is_empty() {
+            if let Some(cc) = self.congestion.as_mut() {
+                cc.on_loss();
+            }
         }
 
         to_retransmit
     }
 
+    /// Resolve `receipt` once every reliable packet among `packets` (just created by
+    /// this manager) is ACKed, or as `Dropped` if one runs out of retries. Without
+    /// reliable packets it resolves as `Unacknowledged` straight away.
+    pub fn attach_receipt(&mut self, packets: &[UdpPacket], receipt: &SendHandle) {
+        for packet in packets {
+            if let Some(pending) = self.pending_acks.get_mut(&(packet.channel(), packet.sequence)) {
+                receipt.add_packet();
+                pending.receipt = Some(receipt.clone());
+            }
+        }
+        if receipt.outstanding() == 0 {
+            receipt.resolve(SendOutcome::Unacknowledged);
+        }
+    }
+
+    /// Resolve every send still waiting on ACKs as `Cancelled`, e.g. when the session closes
+    pub fn cancel_receipts(&mut self) {
+        for pending in self.pending_acks.values_mut() {
+            if let Some(receipt) = pending.receipt.take() {
+                receipt.resolve(SendOutcome::Cancelled);
+            }
+        }
+    }
+
+    /// Reliable packets given up on since the last call, after `max_retries` retransmits
+    /// went un-ACKed, without their session tag (only the most recent `MAX_EXHAUSTED` are kept)
+    pub fn take_exhausted(&mut self) -> Vec<UdpPacket> {
+        self.exhausted.drain(..).collect()
+    }
+
     /// Check if there are pending ACKs
     pub fn has_pending_acks(&self) -> bool {
         !self.pending_acks.is_empty()
     }
 
+    /// Check if a specific packet on the default channel is still waiting for its ACK
+    pub fn is_pending(&self, sequence: u32) -> bool {
+        self.is_pending_on(DEFAULT_CHANNEL, sequence)
+    }
+
+    /// Check if a specific packet on `channel` is still waiting for its ACK
+    pub fn is_pending_on(&self, channel: u8, sequence: u32) -> bool {
+        self.pending_acks.contains_key(&(channel, sequence))
+    }
+
     /// Get count of pending ACKs
     pub fn pending_ack_count(&self) -> usize {
         self.pending_acks.len()
@@ -268,17 +1847,46 @@ impl PacketManager {
 
     /// Reset internal state (for new session)
     pub fn reset(&mut self) {
-        self.sequence_number = 0;
-        self.last_ack_received = u32::MAX;
+        self.cancel_receipts();
+        self.channels.clear();
         self.pending_acks.clear();
-        self.received_sequences.clear();
+        self.exhausted.clear();
+        for queue in &mut self.send_queues {
+            queue.clear();
+        }
+        self.session = None;
+        self.stats = ConnectionStats::default();
+        self.reliable_sent = 0;
+        self.peer_window = DEFAULT_RECEIVE_WINDOW;
+        self.last_window_probe = None;
+        self.clock.clear();
+    }
+}
+
+impl Default for PacketManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PacketManager {
+    fn drop(&mut self) {
+        self.cancel_receipts();
     }
 }
 
+/// A message whose fragments are still arriving
+struct Incomplete {
+    fragments: Vec<Option<Vec<u8>>>,
+    /// Fragment count, known once the last fragment arrives
+    total: Option<usize>,
+    started: Instant,
+}
+
 /// Handles reassembly of fragmented messages
 pub struct FragmentReassembler {
     /// Incomplete messages: message_id -> fragments
-    incomplete_messages: HashMap<u32, Vec<Option<Vec<u8>>>>,
+    incomplete_messages: HashMap<u32, Incomplete>,
 }
 
 impl FragmentReassembler {
@@ -294,39 +1902,57 @@ impl FragmentReassembler {
         message_id: u32,
         fragment_index: u32,
         _is_first: bool,
-        _is_last: bool,
+        is_last: bool,
         data: Vec<u8>,
     ) -> Option<Vec<u8>> {
-        let fragments = self.incomplete_messages
-            .entry(message_id)
-            .or_insert_with(Vec::new);
-
         let idx = fragment_index as usize;
-        if idx >= fragments.len() {
-            fragments.resize(idx + 1, None);
+        if idx >= MAX_FRAGMENTS {
+            return None;
         }
+        let message = self.incomplete_messages
+            .entry(message_id)
+            .or_insert_with(|| Incomplete {
+                fragments: Vec::new(),
+                total: None,
+                started: Instant::now(),
+            });
 
-        if fragments[idx].is_none() {
-            fragments[idx] = Some(data);
+        if is_last {
+            message.total = Some(idx + 1);
+        }
+        if idx >= message.fragments.len() {
+            message.fragments.resize(idx + 1, None);
+        }
+        if message.fragments[idx].is_none() {
+            message.fragments[idx] = Some(data);
         }
 
-        // Check if complete
-        if !fragments.is_empty() && fragments.iter().all(|f| f.is_some()) {
-            let message = self.incomplete_messages.remove(&message_id).unwrap();
-            let complete = message.into_iter()
-                .filter_map(|f| f)
-                .collect::<Vec<_>>()
-                .concat();
-            Some(complete)
-        } else {
-            None
+        // Complete once the last fragment and everything before it have arrived
+        let complete = message.total.is_some_and(|total| {
+            message.fragments.len() == total && message.fragments.iter().all(Option::is_some)
+        });
+        if !complete {
+            return None;
         }
+        let message = self.incomplete_messages.remove(&message_id)?;
+        Some(message.fragments.into_iter().flatten().collect::<Vec<_>>().concat())
     }
 
-    /// Clean old incomplete messages (timeout-based cleanup)
+    /// Drop incomplete messages older than `REASSEMBLY_TIMEOUT`
     pub fn cleanup(&mut self) {
-        // In production, track timestamps and remove old incomplete messages
-        // For now, just keep in memory
+        self.incomplete_messages
+            .retain(|_, message| message.started.elapsed() < REASSEMBLY_TIMEOUT);
+    }
+
+    /// Number of messages still waiting for fragments
+    pub fn pending_messages(&self) -> usize {
+        self.incomplete_messages.len()
+    }
+}
+
+impl Default for FragmentReassembler {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -353,6 +1979,17 @@ mod tests {
         assert_eq!(deserialized.payload, vec![1, 2, 3, 4]);
     }
 
+    #[test]
+    fn test_packet_parse_rejects_garbage() {
+        for len in 0..PACKET_HEADER_SIZE {
+            assert!(UdpPacket::from_bytes(&vec![0x01; len]).is_err());
+        }
+        assert!(UdpPacket::from_bytes(&[0xEE; PACKET_HEADER_SIZE]).is_err());
+
+        let header_only = UdpPacket::from_bytes(&[0x02; PACKET_HEADER_SIZE]).unwrap();
+        assert!(header_only.payload.is_empty());
+    }
+
     #[test]
     fn test_packet_manager_single_message() {
         let mut pm = PacketManager::new();
@@ -390,6 +2027,447 @@ mod tests {
         assert_eq!(pm.pending_ack_count(), 0);
     }
 
+    #[test]
+    fn test_flagged_packet_is_tracked() {
+        let mut pm = PacketManager::new();
+        let packet = pm.create_flagged_packet(&[0x0C], FLAG_STREAM);
+
+        assert!(packet.is_stream());
+        assert!(packet.is_first_fragment() && packet.is_last_fragment());
+        assert!(pm.is_pending(packet.sequence));
+        pm.handle_ack(packet.sequence);
+        assert!(!pm.is_pending(packet.sequence));
+    }
+
+    #[test]
+    fn test_handshake_packets() {
+        let connect = UdpPacket::from_bytes(&UdpPacket::connect().to_bytes()).unwrap();
+        assert_eq!(connect.packet_type, PacketType::Connect);
+        assert_eq!(connect.protocol_version(), Some(PROTOCOL_VERSION));
+
+        let ack = UdpPacket::from_bytes(&UdpPacket::connect_ack(0xDEAD_BEEF).to_bytes()).unwrap();
+        assert_eq!(ack.protocol_version(), Some(PROTOCOL_VERSION));
+        assert_eq!(ack.session_id(), Some(0xDEAD_BEEF));
+
+        assert_eq!(UdpPacket::disconnect(7).session_id(), Some(7));
+        assert_eq!(connect.session_id(), None);
+    }
+
+    #[test]
+    fn test_session_tagging() {
+        let mut pm = PacketManager::new();
+        pm.set_session(0x0102_0304_0506_0708);
+
+        let data = vec![5u8; MAX_PAYLOAD_SIZE];
+        let packets = pm.create_packets(&data);
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|p| p.to_bytes().len() <= MAX_PACKET_SIZE));
+
+        let mut received = UdpPacket::from_bytes(&packets[0].to_bytes()).unwrap();
+        assert_eq!(received.take_session(), Some(0x0102_0304_0506_0708));
+        assert_eq!(received.payload.len(), pm.payload_limit());
+        assert_eq!(received.take_session(), None);
+    }
+
+    #[test]
+    fn test_send_modes() {
+        let mut pm = PacketManager::new();
+        for mode in [SendMode::ReliableUnordered, SendMode::UnreliableSequenced, SendMode::Unreliable] {
+            let packet = pm.create_packets_with_mode(&[1], mode).remove(0);
+            assert_eq!(packet.send_mode(), mode);
+        }
+        // Only the reliable packet waits for an ACK
+        assert_eq!(pm.pending_ack_count(), 1);
+        assert_eq!(pm.create_packets(&[1])[0].send_mode(), SendMode::ReliableOrdered);
+
+        let mut sender = PacketManager::new();
+        let older = sender.create_packets_with_mode(&[1], SendMode::UnreliableSequenced).remove(0);
+        let newer = sender.create_packets_with_mode(&[2], SendMode::UnreliableSequenced).remove(0);
+        let mut receiver = PacketManager::new();
+        assert!(receiver.receive_data(&newer));
+        assert!(!receiver.receive_data(&older));
+        assert!(!receiver.receive_data(&newer));
+    }
+
+    #[test]
+    fn test_channels_sequence_independently() {
+        let mut sender = PacketManager::new();
+        let state = sender.create_packets_on(1, &[1], SendMode::ReliableOrdered).remove(0);
+        let bulk = sender.create_packets_on(2, &[2], SendMode::ReliableOrdered).remove(0);
+        let state2 = sender.create_packets_on(1, &[3], SendMode::ReliableOrdered).remove(0);
+        assert_eq!((state.channel(), state.sequence), (1, 0));
+        assert_eq!((bulk.channel(), bulk.sequence), (2, 0));
+        assert_eq!((state2.channel(), state2.sequence), (1, 1));
+
+        // Same sequence on different channels is not a duplicate
+        let mut receiver = PacketManager::new();
+        assert!(receiver.receive_data(&state));
+        assert!(receiver.receive_data(&bulk));
+        assert!(!receiver.receive_data(&bulk));
+
+        // ACKs carry the channel, so they clear the right packet
+        let ack = UdpPacket::from_bytes(&receiver.create_ack_for(&bulk).to_bytes()).unwrap();
+        assert!(sender.handle_ack_packet(&ack));
+        assert!(sender.is_pending_on(1, 0));
+        assert!(!sender.is_pending_on(2, 0));
+    }
+
+    #[test]
+    fn test_reorder_buffer() {
+        let mut sender = PacketManager::new();
+        let packets: Vec<UdpPacket> = (0..4u8).flat_map(|i| sender.create_packets(&[i])).collect();
+
+        let mut receiver = PacketManager::new();
+        receiver.set_reorder_window(Some(Duration::from_secs(10)));
+        assert!(receiver.deliver(packets[1].clone()).is_empty());
+        assert!(receiver.deliver(packets[2].clone()).is_empty());
+        let released: Vec<u32> = receiver.deliver(packets[0].clone()).iter().map(|p| p.sequence).collect();
+        assert_eq!(released, [0, 1, 2]);
+        assert!(receiver.deliver(packets[2].clone()).is_empty());
+
+        // A gap that never fills is skipped once the window runs out
+        let mut receiver = PacketManager::new();
+        receiver.set_reorder_window(Some(Duration::ZERO));
+        assert!(receiver.deliver(packets[2].clone()).is_empty());
+        let released: Vec<u32> = receiver.flush_reorder().iter().map(|p| p.sequence).collect();
+        assert_eq!(released, [2]);
+        assert_eq!(receiver.deliver(packets[0].clone()).len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_window_slides() {
+        let mut window = SequenceWindow::default();
+        assert!(window.insert(5));
+        assert!(window.insert(3));
+        assert!(!window.insert(3));
+
+        // Moving far ahead reuses slots and expires everything behind the window
+        assert!(window.insert(5 + DEDUP_WINDOW));
+        assert!(!window.insert(5));
+        assert!(window.insert(4 + DEDUP_WINDOW));
+        assert!(!window.insert(4 + DEDUP_WINDOW));
+
+        // Wraparound keeps working
+        let mut window = SequenceWindow::default();
+        assert!(window.insert(u32::MAX));
+        assert!(window.insert(0));
+        assert!(!window.insert(u32::MAX));
+        assert!(window.insert(u32::MAX - 1));
+    }
+
+    #[test]
+    fn test_rto_follows_rtt_samples() {
+        let mut pm = PacketManager::new();
+        assert_eq!(pm.retransmit_timeout(), Duration::from_millis(100));
+
+        pm.record_rtt_sample(Duration::from_millis(40));
+        assert_eq!(pm.smoothed_rtt(), Some(Duration::from_millis(40)));
+        assert_eq!(pm.rtt_variance(), Duration::from_millis(20));
+        assert_eq!(pm.retransmit_timeout(), Duration::from_millis(120));
+
+        for _ in 0..50 {
+            pm.record_rtt_sample(Duration::from_millis(40));
+        }
+        // Steady RTT shrinks the variance until the floor applies
+        assert!(pm.retransmit_timeout() < Duration::from_millis(45));
+        assert_eq!(pm.backoff_timeout(2), pm.retransmit_timeout() * 4);
+        assert_eq!(pm.backoff_timeout(30), MAX_RTO);
+    }
+
+    #[test]
+    fn test_selective_ack() {
+        let mut sender = PacketManager::new();
+        let packets: Vec<UdpPacket> = (0..5u8).flat_map(|i| sender.create_packets(&[i])).collect();
+
+        // Packet 2 is lost and the ACKs for 0 and 1 never arrive
+        let mut receiver = PacketManager::new();
+        for i in [0, 1, 3, 4] {
+            assert!(receiver.receive_data(&packets[i]));
+        }
+        let ack = UdpPacket::from_bytes(&receiver.create_ack_for(&packets[4]).to_bytes()).unwrap();
+        assert_eq!(ack.payload[..4], 0b1101u32.to_be_bytes());
+
+        assert!(sender.handle_ack_packet(&ack));
+        assert_eq!(sender.pending_ack_count(), 1);
+        assert!(sender.is_pending(2));
+    }
+
+    #[test]
+    fn test_delayed_acks_are_batched() {
+        let mut sender = PacketManager::new();
+        let mut receiver = PacketManager::new();
+        receiver.set_max_ack_delay(Duration::from_millis(10));
+        let packets: Vec<UdpPacket> = (0..4u8).flat_map(|i| sender.create_packets(&[i])).collect();
+
+        // Nothing goes out until the delay runs out, then one ACK covers all four
+        let start = Instant::now();
+        for packet in &packets {
+            assert!(receiver.queue_ack(packet).is_empty());
+            receiver.deliver(packet.clone());
+        }
+        assert!(receiver.next_ack_due().is_some());
+        let acks = receiver.take_due_acks_at(start + Duration::from_millis(20));
+        assert_eq!(acks.len(), 1);
+        assert!(receiver.next_ack_due().is_none());
+        sender.handle_ack_packet(&acks[0]);
+        assert_eq!(sender.pending_ack_count(), 0);
+
+        // A full batch is ACKed at once
+        let packets: Vec<UdpPacket> = (0..MAX_ACK_BATCH as u8).flat_map(|i| sender.create_packets(&[i])).collect();
+        let (last, rest) = packets.split_last().unwrap();
+        for packet in rest {
+            assert!(receiver.queue_ack(packet).is_empty());
+        }
+        assert_eq!(receiver.queue_ack(last).len(), 1);
+
+        // A data packet going the other way carries the newest waiting ACK
+        let packet = sender.create_packets(b"ping").remove(0);
+        receiver.queue_ack(&packet);
+        let reply = receiver.create_packets(b"pong").remove(0);
+        let reply = UdpPacket::from_bytes(&receiver.encode(&reply)).unwrap();
+        assert_eq!(reply.ack_number, packet.sequence);
+        assert!(receiver.next_ack_due().is_none());
+        assert!(sender.is_pending(packet.sequence));
+        sender.deliver(reply);
+        assert!(!sender.is_pending(packet.sequence));
+    }
+
+    #[test]
+    fn test_overtaken_packet_is_fast_retransmitted() {
+        // A timeout far off, so only ACKs can trigger the retransmit
+        let mut sender = PacketManager::with_config(Duration::from_secs(5), 3);
+        let mut receiver = PacketManager::new();
+        let packets: Vec<UdpPacket> = (0..5u8).flat_map(|i| sender.create_packets(&[i])).collect();
+
+        // Packet 1 is lost; each later ACK counts against it
+        for i in [0, 2, 3] {
+            assert!(receiver.receive_data(&packets[i]));
+            sender.handle_ack_packet(&receiver.create_ack_for(&packets[i]));
+        }
+        assert!(sender.release_paced().is_empty());
+        assert!(receiver.receive_data(&packets[4]));
+        let ack = receiver.create_ack_for(&packets[4]);
+        sender.handle_ack_packet(&ack);
+        let resent = sender.release_paced();
+        assert_eq!(resent.iter().map(|p| p.sequence).collect::<Vec<_>>(), [1]);
+        assert_eq!((sender.stats().fast_retransmits, sender.stats().retransmissions), (1, 1));
+
+        // A repeated ACK confirms nothing new, and ACKs sent before the resend don't count
+        sender.handle_ack_packet(&ack);
+        assert!(sender.release_paced().is_empty());
+        assert!(sender.get_retransmit_packets().is_empty());
+        sender.handle_ack_packet(&receiver.create_ack_for(&resent[0]));
+        assert!(!sender.has_pending_acks());
+    }
+
+    #[test]
+    fn test_receive_window_holds_sender() {
+        let mut sender = PacketManager::new();
+        let mut receiver = PacketManager::new();
+        receiver.set_receive_window(4);
+        receiver.set_receive_backlog(2);
+        assert_eq!(receiver.advertised_window(), 2);
+
+        let packets = sender.create_packets(&[0]);
+        let first = sender.pace(packets);
+        sender.handle_ack_packet(&receiver.create_ack_for(&first[0]));
+        assert_eq!(sender.peer_window(), 2);
+
+        // Two packets fill the window; the third waits for room
+        let mut sent = Vec::new();
+        for i in 1..4u8 {
+            let packets = sender.create_packets(&[i]);
+            sent.extend(sender.pace(packets));
+        }
+        assert_eq!((sent.len(), sender.queued_packets()), (2, 1));
+
+        // The application catches up and the next ACK reopens the window
+        receiver.set_receive_backlog(0);
+        sender.handle_ack_packet(&receiver.create_ack_for(&sent[0]));
+        let released = sender.release_paced();
+        assert_eq!(released.len(), 1);
+
+        // A closed window still lets one probe through once nothing is in flight
+        receiver.set_receive_window(0);
+        for packet in [&sent[1], &released[0]] {
+            sender.handle_ack_packet(&receiver.create_ack_for(packet));
+        }
+        assert_eq!(sender.peer_window(), 0);
+        let mut probes = Vec::new();
+        for i in 4..6u8 {
+            let packets = sender.create_packets(&[i]);
+            probes.extend(sender.pace(packets));
+        }
+        assert_eq!((probes.len(), sender.in_flight(), sender.queued_packets()), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_retransmit_policy() {
+        let policy = RetransmitPolicy {
+            ack_timeout: Duration::from_millis(50),
+            max_retries: 5,
+            backoff: Backoff::Linear,
+            max_in_flight: Some(2),
+        };
+        let mut pm = PacketManager::with_policy(policy);
+        assert_eq!(pm.retransmit_timeout(), Duration::from_millis(50));
+        assert_eq!(pm.backoff_timeout(3), Duration::from_millis(200));
+        pm.set_retransmit_policy(RetransmitPolicy { backoff: Backoff::Fixed, ..policy });
+        assert_eq!(pm.backoff_timeout(3), Duration::from_millis(50));
+
+        // Two packets in flight at most, however wide the peer's window
+        let mut sent = Vec::new();
+        for i in 0..3u8 {
+            let packets = pm.create_packets(&[i]);
+            sent.extend(pm.pace(packets));
+        }
+        assert_eq!((sent.len(), pm.queued_packets()), (2, 1));
+
+        // Fixed backoff retransmits every RTO until the retries run out
+        let start = Instant::now();
+        for retry in 1..=5 {
+            let resent = pm.get_retransmit_packets_at(start + Duration::from_millis(51) * retry);
+            assert_eq!(resent.len(), 2);
+        }
+        assert!(pm.get_retransmit_packets_at(start + Duration::from_millis(51) * 6).is_empty());
+        assert_eq!(pm.take_exhausted().len(), 2);
+    }
+
+    #[test]
+    fn test_connection_stats() {
+        let mut sender = PacketManager::with_config(Duration::ZERO, 3);
+        let mut receiver = PacketManager::new();
+        let packets: Vec<UdpPacket> = (0..4u8).flat_map(|i| sender.create_packets(&[i])).collect();
+
+        // 0, 2, 1, 2 arrive: one gap, filled one sequence late, and one twice; 3 is
+        // lost and resent
+        for i in [0, 2, 1, 2] {
+            let mut packet = UdpPacket::from_bytes(&sender.encode(&packets[i])).unwrap();
+            assert!(receiver.open(&mut packet));
+            receiver.deliver(packet);
+        }
+        sender.encode(&packets[3]);
+        assert_eq!(sender.get_retransmit_packets().len(), 4);
+
+        let received = receiver.stats();
+        assert_eq!(received.packets_received, 4);
+        assert_eq!(received.bytes_received, 4 * (PACKET_HEADER_SIZE as u64 + 1));
+        assert_eq!((received.duplicates, received.out_of_order), (1, 1));
+        assert_eq!((received.gaps, received.max_reorder_distance), (1, 1));
+
+        let sent = sender.stats();
+        assert_eq!(sent.packets_sent, 5);
+        assert_eq!(sent.retransmissions, 4);
+        assert_eq!(sent.loss_estimate, 0.5);
+        assert_eq!(sent.rtt, None);
+    }
+
+    #[test]
+    fn test_pong_matches_outstanding_ping() {
+        let mut pm = PacketManager::new();
+        let first = pm.create_ping_packet();
+        let second = pm.create_ping_packet();
+        assert_ne!(first.sequence, second.sequence);
+        // Pings don't consume data sequence numbers
+        assert_eq!(pm.create_packets(b"x")[0].sequence, 0);
+
+        let mut forged = UdpPacket::pong(&second);
+        forged.payload = 0u64.wrapping_sub(1).to_be_bytes().to_vec();
+        assert!(pm.handle_pong(&forged).is_none());
+
+        let rtt = pm.handle_pong(&UdpPacket::pong(&second)).unwrap();
+        assert_eq!(pm.last_rtt(), Some(rtt));
+        assert_eq!(pm.ping_rtt(second.sequence), Some(rtt));
+        assert_eq!(pm.ping_rtt(first.sequence), None);
+        assert_eq!(pm.stats().rtt, Some(rtt));
+        // Each Ping is answered once
+        assert!(pm.handle_pong(&UdpPacket::pong(&second)).is_none());
+
+        // The Pong's times put the peer (this machine) in step with the local clock
+        let estimate = pm.clock_estimate().unwrap();
+        assert!(estimate.offset_micros.abs() < 100_000, "offset {}", estimate.offset_micros);
+        let skew = match pm.peer_time_estimate().unwrap().duration_since(std::time::SystemTime::now()) {
+            Ok(ahead) => ahead,
+            Err(behind) => behind.duration(),
+        };
+        assert!(skew < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_pacing_holds_packets() {
+        use crate::congestion::TokenBucketAimd;
+
+        let mut pm = PacketManager::with_config(Duration::ZERO, 3);
+        pm.set_congestion_controller(Some(Box::new(
+            TokenBucketAimd::new(100, 100, 100).with_burst(MAX_PACKET_SIZE),
+        )));
+
+        let packets = pm.create_packets(&vec![0u8; MAX_PAYLOAD_SIZE * 3]);
+        let sent = pm.pace(packets);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(pm.queued_packets(), 2);
+        assert!(pm.release_paced().is_empty());
+
+        // Only the packet on the wire can time out
+        let retransmits = pm.get_retransmit_packets();
+        assert_eq!(retransmits.len(), 1);
+        assert_eq!(retransmits[0].0.sequence, sent[0].sequence);
+    }
+
+    #[test]
+    fn test_exhausted_packets_are_reported() {
+        let mut pm = PacketManager::with_config(Duration::from_millis(10), 1);
+        let sent = pm.create_packets(b"lost").remove(0);
+        let start = Instant::now();
+
+        assert_eq!(pm.get_retransmit_packets_at(start + Duration::from_secs(60)).len(), 1);
+        assert!(pm.take_exhausted().is_empty());
+        assert!(pm.get_retransmit_packets_at(start + Duration::from_secs(120)).is_empty());
+        assert!(!pm.has_pending_acks());
+        let exhausted = pm.take_exhausted();
+        assert_eq!(exhausted.len(), 1);
+        assert_eq!(exhausted[0].sequence, sent.sequence);
+        assert!(pm.take_exhausted().is_empty());
+    }
+
+    #[test]
+    fn test_priority_cuts_ahead_of_bulk() {
+        let mut pm = PacketManager::new();
+        let bulk = pm.create_packets(&vec![0u8; MAX_PAYLOAD_SIZE * 20]);
+        let sent = pm.pace_with_priority(bulk, Priority::Low);
+        assert_eq!(sent.len(), LOW_PRIORITY_BURST);
+        assert_eq!(pm.queued_packets(), 20 - LOW_PRIORITY_BURST);
+
+        // A small state update jumps the remaining bulk train
+        let state = pm.create_packets(&[1]);
+        let state_seq = state[0].sequence;
+        let sent = pm.pace_with_priority(state, Priority::High);
+        assert_eq!(sent[0].sequence, state_seq);
+        assert_eq!(sent.len(), 1 + LOW_PRIORITY_BURST);
+    }
+
+    #[test]
+    fn test_fragmented_message_reassembles() {
+        let data: Vec<u8> = (0..MAX_PAYLOAD_SIZE * 3).map(|i| i as u8).collect();
+        let mut sender = PacketManager::new();
+        sender.create_packets(&[0]);
+        let packets = sender.create_packets(&data);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[2].fragment_index(), 2);
+        assert_eq!(packets[2].message_id(), packets[0].sequence);
+
+        // Out of order, with a duplicate; nothing comes out until the last piece
+        let mut receiver = PacketManager::new();
+        let wire = |p: &UdpPacket| UdpPacket::from_bytes(&p.to_bytes()).unwrap();
+        assert!(receiver.deliver(wire(&packets[2])).is_empty());
+        assert!(receiver.deliver(wire(&packets[0])).is_empty());
+        assert!(receiver.deliver(wire(&packets[0])).is_empty());
+        let complete = receiver.deliver(wire(&packets[1]));
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].payload, data);
+        assert_eq!(complete[0].sequence, packets[0].sequence);
+    }
+
     #[test]
     fn test_duplicate_detection() {
         let mut pm = PacketManager::new();
@@ -0,0 +1,90 @@
+//! BiWi Message Pool
+//! Recycles `BiWiMessage` instances on hot paths. Released messages are reset
+//! but keep their encode buffer, so steady-state encoding stops allocating it.
+
+use crate::message::BiWiMessage;
+use std::sync::Mutex;
+
+/// Default number of idle messages kept by a pool
+pub const DEFAULT_POOL_SIZE: usize = 64;
+
+/// Thread-safe pool of reusable messages
+#[derive(Debug)]
+pub struct MessagePool {
+    free: Mutex<Vec<BiWiMessage>>,
+    max_idle: usize,
+}
+
+impl MessagePool {
+    /// Create a pool keeping up to `DEFAULT_POOL_SIZE` idle messages
+    pub fn new() -> Self {
+        Self::with_max_idle(DEFAULT_POOL_SIZE)
+    }
+
+    /// Create a pool keeping up to `max_idle` idle messages
+    pub fn with_max_idle(max_idle: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::with_capacity(max_idle)),
+            max_idle,
+        }
+    }
+
+    /// Take an empty message, reusing a released one when available
+    pub fn acquire(&self) -> BiWiMessage {
+        self.lock().pop().unwrap_or_default()
+    }
+
+    /// Return a message to the pool. It is reset first; if the pool is full it is dropped.
+    pub fn release(&self, mut message: BiWiMessage) {
+        message.reset();
+        let mut free = self.lock();
+        if free.len() < self.max_idle {
+            free.push(message);
+        }
+    }
+
+    /// Number of idle messages ready to be acquired
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<BiWiMessage>> {
+        // A panic while holding the lock cannot leave the free list inconsistent
+        self.free.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for MessagePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BiWiValue;
+
+    #[test]
+    fn test_acquire_reuses_released_messages() {
+        let pool = MessagePool::with_max_idle(1);
+        let mut msg = pool.acquire();
+        msg.set_field(1, BiWiValue::Binary(vec![0; 512])).mark_sensitive(1);
+        msg.to_buffer();
+        let capacity = msg.buffer_capacity();
+        assert!(capacity >= 512);
+
+        pool.release(msg);
+        pool.release(BiWiMessage::new()); // Over max_idle: dropped
+        assert_eq!(pool.idle(), 1);
+
+        let mut reused = pool.acquire();
+        assert_eq!(reused.field_count(), 0);
+        assert!(reused.field_flags(1).is_empty());
+        assert_eq!(reused.buffer_capacity(), capacity);
+        reused.set_field(2, BiWiValue::Int32(5));
+        let expected = reused.to_vec();
+        assert_eq!(reused.to_buffer(), expected.as_slice());
+        assert_eq!(pool.idle(), 0);
+    }
+}
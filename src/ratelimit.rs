@@ -0,0 +1,137 @@
+//! BiWi Rate Limiting
+//! Per-connection limits a server enforces on what each client sends: token buckets
+//! for packets and bytes per second, and a cap on message size. Buckets hold one
+//! second's allowance, so short bursts up to the limit pass untouched.
+
+use crate::crypto::CIPHER_OVERHEAD;
+use crate::network::{PacketType, UdpPacket, MIN_PACKET_SIZE, PACKET_HEADER_SIZE, SESSION_TAG_LEN};
+use std::time::{Duration, Instant};
+
+/// Fewest payload bytes in any fragment but the last: a peer never fragments to less
+/// than `MIN_PACKET_SIZE`, and at most the header, session tag and AEAD tag come off it
+const MIN_FRAGMENT_PAYLOAD: usize = MIN_PACKET_SIZE - PACKET_HEADER_SIZE - SESSION_TAG_LEN - CIPHER_OVERHEAD;
+
+/// Limits applied to each client of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub packets_per_sec: u32,
+    pub bytes_per_sec: u64,
+    /// Largest message a client may send, after reassembly
+    pub max_message_size: usize,
+    /// Disconnect an offending client and refuse its IP for this long; `None` only drops
+    /// the offending packets
+    pub ban: Option<Duration>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            packets_per_sec: 1000,
+            bytes_per_sec: 1024 * 1024,
+            max_message_size: 1024 * 1024,
+            ban: None,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Ban offending clients' IPs for `duration`
+    pub fn with_ban(mut self, duration: Duration) -> Self {
+        self.ban = Some(duration);
+        self
+    }
+}
+
+/// Which limit a client broke
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    PacketRate,
+    ByteRate,
+    /// A message of (at least) this many bytes
+    MessageSize(usize),
+}
+
+/// One connection's token buckets
+pub(crate) struct Limiter {
+    packets: f64,
+    bytes: f64,
+    last_refill: Instant,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        // Full buckets; the first refill caps them at the configured rate
+        Self {
+            packets: f64::INFINITY,
+            bytes: f64::INFINITY,
+            last_refill: Instant::now(),
+        }
+    }
+}
+
+impl Limiter {
+    /// Charge a `size`-byte datagram carrying `packet` against the buckets
+    pub fn check(&mut self, limit: &RateLimit, packet: &UdpPacket, size: usize, now: Instant) -> Result<(), LimitExceeded> {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        let packet_rate = limit.packets_per_sec as f64;
+        let byte_rate = limit.bytes_per_sec as f64;
+        self.packets = (self.packets + elapsed * packet_rate).min(packet_rate);
+        self.bytes = (self.bytes + elapsed * byte_rate).min(byte_rate);
+
+        if self.packets < 1.0 {
+            return Err(LimitExceeded::PacketRate);
+        }
+        if self.bytes < size as f64 {
+            return Err(LimitExceeded::ByteRate);
+        }
+        self.packets -= 1.0;
+        self.bytes -= size as f64;
+
+        if packet.packet_type == PacketType::Data {
+            // A fragment's index gives a lower bound on its message's size, so oversized
+            // messages are refused before they are buffered
+            let least = if packet.is_fragment() {
+                packet.fragment_index() as usize * MIN_FRAGMENT_PAYLOAD + packet.payload.len()
+            } else {
+                packet.payload.len()
+            };
+            if least > limit.max_message_size {
+                return Err(LimitExceeded::MessageSize(least));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::PacketManager;
+
+    #[test]
+    fn test_buckets_refill_over_time() {
+        let limit = RateLimit {
+            packets_per_sec: 10,
+            bytes_per_sec: 100,
+            max_message_size: 64,
+            ban: None,
+        };
+        let mut limiter = Limiter::default();
+        let packet = UdpPacket::connect();
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check(&limit, &packet, 10, start), Ok(()));
+        }
+        assert_eq!(limiter.check(&limit, &packet, 1, start), Err(LimitExceeded::PacketRate));
+        // A tenth of a second buys one more packet, but only 10 bytes
+        let later = start + Duration::from_millis(100);
+        assert_eq!(limiter.check(&limit, &packet, 50, later), Err(LimitExceeded::ByteRate));
+        assert_eq!(limiter.check(&limit, &packet, 10, later), Ok(()));
+
+        let big = PacketManager::new().create_packets(&[0; 65]).remove(0);
+        let mut limiter = Limiter::default();
+        assert_eq!(limiter.check(&limit, &big, 65, start), Err(LimitExceeded::MessageSize(65)));
+    }
+}
@@ -0,0 +1,123 @@
+//! Checked byte reader shared by the decoder and packet parser.
+//! Every read is bounds-checked and every length computation is overflow-checked,
+//! so malformed input surfaces as a `DecodeError` instead of a panic.
+
+#![deny(clippy::indexing_slicing)]
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::arithmetic_side_effects)]
+
+use crate::decoder::{DecodeError, DecodeResult};
+
+/// Cursor over an untrusted byte slice
+#[derive(Debug, Clone)]
+pub(crate) struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+
+    /// Current read position
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Bytes left to read
+    pub(crate) fn remaining(&self) -> usize {
+        self.buffer.len().saturating_sub(self.offset)
+    }
+
+    pub(crate) fn has_more(&self) -> bool {
+        self.remaining() > 0
+    }
+
+    /// Look at the next byte without consuming it
+    pub(crate) fn peek_u8(&self, what: &'static str) -> DecodeResult<u8> {
+        self.buffer
+            .get(self.offset)
+            .copied()
+            .ok_or(DecodeError::InsufficientData(what))
+    }
+
+    pub(crate) fn read_u8(&mut self, what: &'static str) -> DecodeResult<u8> {
+        let byte = self.peek_u8(what)?;
+        self.advance(1, what)?;
+        Ok(byte)
+    }
+
+    /// Borrow the next `len` bytes
+    pub(crate) fn read_bytes(&mut self, len: usize, what: &'static str) -> DecodeResult<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .ok_or(DecodeError::InsufficientData(what))?;
+        let bytes = self
+            .buffer
+            .get(self.offset..end)
+            .ok_or(DecodeError::InsufficientData(what))?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// Read a fixed-size array
+    pub(crate) fn read_array<const N: usize>(&mut self, what: &'static str) -> DecodeResult<[u8; N]> {
+        let bytes = self.read_bytes(N, what)?;
+        let mut out = [0u8; N];
+        out.copy_from_slice(bytes);
+        Ok(out)
+    }
+
+    /// Read everything that is left
+    pub(crate) fn read_rest(&mut self) -> &'a [u8] {
+        let rest = self.buffer.get(self.offset..).unwrap_or_default();
+        self.offset = self.buffer.len();
+        rest
+    }
+
+    /// Read a 32-bit varint, rejecting encodings longer than 5 bytes or wider than 32 bits
+    pub(crate) fn read_varint_u32(&mut self, what: &'static str) -> DecodeResult<u32> {
+        let value = self.read_varint_u64(what)?;
+        u32::try_from(value).map_err(|_| DecodeError::InvalidData("varint exceeds 32 bits"))
+    }
+
+    /// Read a 64-bit varint, rejecting encodings longer than 10 bytes
+    pub(crate) fn read_varint_u64(&mut self, what: &'static str) -> DecodeResult<u64> {
+        let mut value: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte = self.read_u8(what)?;
+            let bits = u64::from(byte & 0x7f);
+            let shifted = bits
+                .checked_shl(shift)
+                .filter(|v| v >> shift == bits)
+                .ok_or(DecodeError::InvalidData("varint exceeds 64 bits"))?;
+            value |= shifted;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_add(7)
+                .filter(|s| *s < 64)
+                .ok_or(DecodeError::InvalidData("varint exceeds 64 bits"))?;
+        }
+    }
+
+    /// Step back one byte (used to re-read a header byte as a varint)
+    pub(crate) fn unread_u8(&mut self) {
+        self.offset = self.offset.saturating_sub(1);
+    }
+
+    fn advance(&mut self, len: usize, what: &'static str) -> DecodeResult<()> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.buffer.len())
+            .ok_or(DecodeError::InsufficientData(what))?;
+        self.offset = end;
+        Ok(())
+    }
+}
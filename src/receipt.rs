@@ -0,0 +1,169 @@
+//! BiWi Delivery Receipts
+//! A `SendHandle` comes back from every send and resolves once the outcome is known:
+//! the peer ACKed all of the message's packets, the packet manager gave up on one of
+//! them, or the session went away first. Handles are cheap to clone and can be
+//! waited on from any thread.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// How a send ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// Every packet of the message was ACKed
+    Delivered,
+    /// A packet ran out of retries
+    Dropped,
+    /// `SendHandle::cancel` was called, or the session closed or was replaced, before
+    /// the message was confirmed
+    Cancelled,
+    /// Sent with an unreliable `SendMode`, so no ACK will ever confirm it
+    Unacknowledged,
+}
+
+struct State {
+    outcome: Option<SendOutcome>,
+    /// Reliable packets not yet ACKed
+    outstanding: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    resolved: Condvar,
+}
+
+/// Outcome of one send, filled in as ACKs come back
+#[derive(Clone)]
+pub struct SendHandle {
+    shared: Arc<Shared>,
+}
+
+impl SendHandle {
+    pub(crate) fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    outcome: None,
+                    outstanding: 0,
+                }),
+                resolved: Condvar::new(),
+            }),
+        }
+    }
+
+    /// One more reliable packet must be ACKed before the send counts as delivered
+    pub(crate) fn add_packet(&self) {
+        self.shared.state.lock().unwrap().outstanding += 1;
+    }
+
+    pub(crate) fn outstanding(&self) -> usize {
+        self.shared.state.lock().unwrap().outstanding
+    }
+
+    /// One of the reliable packets was ACKed; the last one delivers the message
+    pub(crate) fn packet_acked(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.outstanding = state.outstanding.saturating_sub(1);
+        if state.outstanding == 0 && state.outcome.is_none() {
+            state.outcome = Some(SendOutcome::Delivered);
+            self.shared.resolved.notify_all();
+        }
+    }
+
+    /// Settle the outcome, unless it already is
+    pub(crate) fn resolve(&self, outcome: SendOutcome) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.outcome.is_none() {
+            state.outcome = Some(outcome);
+            self.shared.resolved.notify_all();
+        }
+    }
+
+    /// Give up on the message: its packets are no longer retransmitted, though any
+    /// already on the wire may still arrive. No effect once the outcome is known.
+    pub fn cancel(&self) {
+        self.resolve(SendOutcome::Cancelled);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.outcome() == Some(SendOutcome::Cancelled)
+    }
+
+    /// The outcome, if it is known yet
+    pub fn outcome(&self) -> Option<SendOutcome> {
+        self.shared.state.lock().unwrap().outcome
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.outcome().is_some()
+    }
+
+    /// Block until the outcome is known
+    pub fn wait(&self) -> SendOutcome {
+        let state = self.shared.state.lock().unwrap();
+        let state = self.shared.resolved.wait_while(state, |state| state.outcome.is_none()).unwrap();
+        state.outcome.unwrap()
+    }
+
+    /// Block for up to `timeout`; `None` if the outcome still isn't known
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<SendOutcome> {
+        let state = self.shared.state.lock().unwrap();
+        let (state, _) = self.shared.resolved.wait_timeout_while(state, timeout, |state| state.outcome.is_none()).unwrap();
+        state.outcome
+    }
+}
+
+impl std::fmt::Debug for SendHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendHandle").field("outcome", &self.outcome()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{PacketManager, SendMode, MAX_PAYLOAD_SIZE};
+    use std::time::Instant;
+
+    #[test]
+    fn test_receipts_follow_acks_and_retries() {
+        let mut sender = PacketManager::with_config(Duration::from_millis(10), 1);
+        let receiver = PacketManager::new();
+
+        // Delivered once every fragment is ACKed
+        let packets = sender.create_packets(&vec![7u8; MAX_PAYLOAD_SIZE * 2]);
+        let delivered = SendHandle::new();
+        sender.attach_receipt(&packets, &delivered);
+        sender.handle_ack_packet(&receiver.create_ack_for(&packets[0]));
+        assert_eq!(delivered.outcome(), None);
+        sender.handle_ack_packet(&receiver.create_ack_for(&packets[1]));
+        assert_eq!(delivered.wait(), SendOutcome::Delivered);
+
+        let unreliable = SendHandle::new();
+        let packets = sender.create_packets_with_mode(b"x", SendMode::Unreliable);
+        sender.attach_receipt(&packets, &unreliable);
+        assert_eq!(unreliable.outcome(), Some(SendOutcome::Unacknowledged));
+
+        // Dropped when retries run out; cancelled sends stop retransmitting
+        let dropped = SendHandle::new();
+        let cancelled = SendHandle::new();
+        let packets = sender.create_packets(b"lost");
+        sender.attach_receipt(&packets, &dropped);
+        let packets = sender.create_packets(b"unwanted");
+        sender.attach_receipt(&packets, &cancelled);
+        cancelled.cancel();
+        let start = Instant::now();
+        assert_eq!(sender.get_retransmit_packets_at(start + Duration::from_secs(60)).len(), 1);
+        sender.get_retransmit_packets_at(start + Duration::from_secs(120));
+        assert_eq!(dropped.wait_timeout(Duration::ZERO), Some(SendOutcome::Dropped));
+        assert_eq!(cancelled.outcome(), Some(SendOutcome::Cancelled));
+        assert!(!sender.has_pending_acks());
+
+        // Sends still in flight when the session goes away are cancelled
+        let pending = SendHandle::new();
+        let packets = sender.create_packets(b"late");
+        sender.attach_receipt(&packets, &pending);
+        drop(sender);
+        assert_eq!(pending.outcome(), Some(SendOutcome::Cancelled));
+    }
+}
@@ -0,0 +1,320 @@
+//! BiWi State Replication
+//! Snapshot + delta replication for game state. The server registers entities as
+//! `BiWiMessage`s, takes a snapshot every tick, and sends each client only what changed
+//! since the last snapshot that client is known to have: the baseline. Deltas go out
+//! reliable but unordered, and a delta's delivery receipt (ACKed by the packet manager)
+//! is what advances that client's baseline. Until the first ACK, clients get full state.
+//!
+//! Replication messages have envelope type `REPLICATION`:
+//! field 1 = tick, field 2 = baseline tick (0 for none), field 3 = changed entities as
+//! an array of `[id, BiWiDelta bytes]` pairs, field 4 = removed entity IDs.
+//! A `Replica` on the client applies them in any order.
+
+use crate::decoder::{DecodeError, DecodeResult};
+use crate::encoder::BiWiValue;
+use crate::envelope::Envelope;
+use crate::message::{BiWiDelta, BiWiMessage};
+use crate::network::SendMode;
+use crate::receipt::{SendHandle, SendOutcome};
+use crate::server::{BiWiUdpServer, ConnectionId};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+
+/// Envelope message type of replication messages (reserved)
+pub const REPLICATION: u16 = 0xFFFD;
+
+/// Snapshots kept on each side; a client whose baseline is older gets full state
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+type Entities = BTreeMap<u32, BiWiMessage>;
+
+/// What the server knows about one client's copy of the state
+#[derive(Default)]
+struct ClientBaseline {
+    /// Newest snapshot the client is known to have received
+    acked: Option<u32>,
+    /// Deltas sent but not yet resolved, by tick
+    in_flight: Vec<(u32, SendHandle)>,
+}
+
+/// The server's side: the live entity states, recent snapshots and each client's baseline
+#[derive(Default)]
+pub struct Replicator {
+    entities: Entities,
+    /// Recent snapshots, oldest first; ticks start at 1
+    history: VecDeque<(u32, Arc<Entities>)>,
+    tick: u32,
+    clients: HashMap<ConnectionId, ClientBaseline>,
+}
+
+impl Replicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entity, or replace its state
+    pub fn set(&mut self, entity_id: u32, state: BiWiMessage) {
+        self.entities.insert(entity_id, state);
+    }
+
+    /// The entity's live state, for updating in place
+    pub fn get_mut(&mut self, entity_id: u32) -> Option<&mut BiWiMessage> {
+        self.entities.get_mut(&entity_id)
+    }
+
+    pub fn remove(&mut self, entity_id: u32) -> Option<BiWiMessage> {
+        self.entities.remove(&entity_id)
+    }
+
+    /// Tick of the latest snapshot (0 before the first)
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// Freeze the current entity states as the next tick's snapshot
+    pub fn snapshot(&mut self) -> u32 {
+        self.tick += 1;
+        self.history.push_back((self.tick, Arc::new(self.entities.clone())));
+        if self.history.len() > SNAPSHOT_HISTORY {
+            self.history.pop_front();
+        }
+        self.tick
+    }
+
+    /// The client's baseline tick, if it has ACKed any snapshot
+    pub fn baseline(&mut self, client_id: &str) -> Option<u32> {
+        let oldest = self.oldest_tick();
+        let client = self.clients.get_mut(client_id)?;
+        client.refresh(oldest);
+        client.acked
+    }
+
+    /// The replication message that brings `client_id` from its baseline to the latest
+    /// snapshot. `None` before the first `snapshot`.
+    pub fn delta_for(&mut self, client_id: &str) -> Option<BiWiMessage> {
+        let (tick, current) = self.history.back().map(|(tick, s)| (*tick, Arc::clone(s)))?;
+        let baseline = self.baseline(client_id).and_then(|acked| self.snapshot_at(acked).map(|s| (acked, s)));
+        let empty = Entities::new();
+        let (baseline_tick, base) = baseline.as_ref().map_or((0, &empty), |(tick, s)| (*tick, &**s));
+
+        let mut changed = Vec::new();
+        for (id, state) in current.iter() {
+            let delta = match base.get(id) {
+                Some(old) => old.diff(state),
+                None => BiWiMessage::new().diff(state),
+            };
+            // New entities go out even without fields, so the client creates them
+            if !delta.is_empty() || !base.contains_key(id) {
+                changed.push(BiWiValue::Array(vec![BiWiValue::Int64(*id as i64), BiWiValue::Binary(delta.to_vec())]));
+            }
+        }
+        let removed = base.keys().filter(|id| !current.contains_key(id)).map(|id| BiWiValue::Int64(*id as i64)).collect();
+
+        let message = BiWiMessage::builder()
+            .field(1, BiWiValue::Int64(tick as i64))
+            .field(2, BiWiValue::Int64(baseline_tick as i64))
+            .field(3, BiWiValue::Array(changed))
+            .field(4, BiWiValue::Array(removed))
+            .build()
+            .with_envelope(Envelope::new(REPLICATION));
+        Some(message)
+    }
+
+    /// Record a delta for `tick` sent to `client_id`; once `receipt` resolves as
+    /// delivered, that tick becomes the client's baseline
+    pub fn track(&mut self, client_id: &str, tick: u32, receipt: SendHandle) {
+        self.clients.entry(client_id.to_string()).or_default().in_flight.push((tick, receipt));
+    }
+
+    /// Send `client_id` its delta for the latest snapshot, reliable but unordered
+    pub fn send(&mut self, server: &BiWiUdpServer, client_id: &str) -> io::Result<()> {
+        let Some(message) = self.delta_for(client_id) else {
+            return Ok(());
+        };
+        let receipt = server.send_to_with_mode(client_id, &message, SendMode::ReliableUnordered)?;
+        self.track(client_id, self.tick, receipt);
+        Ok(())
+    }
+
+    /// Forget a client whose session ended
+    pub fn forget(&mut self, client_id: &str) {
+        if let Some(client) = self.clients.remove(client_id) {
+            for (_, receipt) in client.in_flight {
+                receipt.cancel();
+            }
+        }
+    }
+
+    fn oldest_tick(&self) -> u32 {
+        self.history.front().map_or(0, |(tick, _)| *tick)
+    }
+
+    fn snapshot_at(&self, tick: u32) -> Option<Arc<Entities>> {
+        let index = tick.checked_sub(self.oldest_tick())? as usize;
+        self.history.get(index).map(|(_, snapshot)| Arc::clone(snapshot))
+    }
+}
+
+impl ClientBaseline {
+    /// Take in resolved receipts, and stop retransmitting deltas too old to matter
+    fn refresh(&mut self, oldest_tick: u32) {
+        let mut acked = self.acked;
+        self.in_flight.retain(|(tick, receipt)| match receipt.outcome() {
+            Some(SendOutcome::Delivered) => {
+                acked = acked.max(Some(*tick));
+                false
+            }
+            Some(_) => false,
+            None if *tick < oldest_tick => {
+                receipt.cancel();
+                false
+            }
+            None => true,
+        });
+        // A baseline that fell out of the history is as good as none
+        self.acked = acked.filter(|tick| *tick >= oldest_tick);
+    }
+}
+
+/// The client's side: rebuilds entity states from replication messages
+#[derive(Default)]
+pub struct Replica {
+    /// Snapshots received, oldest first, kept as baselines for later deltas
+    history: VecDeque<(u32, Entities)>,
+    /// Tick of the newest snapshot
+    latest: Option<u32>,
+}
+
+impl Replica {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `message` is a replication message
+    pub fn is_replication(message: &BiWiMessage) -> bool {
+        message.message_type() == Some(REPLICATION)
+    }
+
+    /// Apply a replication message and return its tick. Messages older than the latest
+    /// are kept as baselines but don't change the current state.
+    pub fn apply(&mut self, message: &BiWiMessage) -> DecodeResult<u32> {
+        let invalid = || DecodeError::InvalidData("malformed replication message");
+        let tick = message.get_i64(1).and_then(|t| u32::try_from(t).ok()).ok_or_else(invalid)?;
+        let baseline = message.get_i64(2).and_then(|t| u32::try_from(t).ok()).ok_or_else(invalid)?;
+
+        let mut entities = match baseline {
+            0 => Entities::new(),
+            _ => self
+                .history
+                .iter()
+                .find(|(t, _)| *t == baseline)
+                .map(|(_, s)| s.clone())
+                .ok_or(DecodeError::InvalidData("unknown baseline snapshot"))?,
+        };
+        for id in message.get_array(4).ok_or_else(invalid)? {
+            entities.remove(&entity_id(id).ok_or_else(invalid)?);
+        }
+        for entry in message.get_array(3).ok_or_else(invalid)? {
+            let BiWiValue::Array(pair) = entry else {
+                return Err(invalid());
+            };
+            let (Some(id), Some(BiWiValue::Binary(delta))) = (pair.first().and_then(entity_id), pair.get(1)) else {
+                return Err(invalid());
+            };
+            entities.entry(id).or_default().apply_delta(&BiWiDelta::from_buffer(delta)?);
+        }
+
+        if !self.history.iter().any(|(t, _)| *t == tick) {
+            let at = self.history.partition_point(|(t, _)| *t < tick);
+            self.history.insert(at, (tick, entities));
+            if self.history.len() > SNAPSHOT_HISTORY {
+                self.history.pop_front();
+            }
+        }
+        self.latest = self.latest.max(Some(tick));
+        Ok(tick)
+    }
+
+    /// Tick of the newest state applied
+    pub fn tick(&self) -> Option<u32> {
+        self.latest
+    }
+
+    /// Entity states as of the newest tick
+    pub fn entities(&self) -> impl Iterator<Item = (u32, &BiWiMessage)> + '_ {
+        self.current().into_iter().flat_map(|entities| entities.iter().map(|(id, state)| (*id, state)))
+    }
+
+    pub fn entity(&self, entity_id: u32) -> Option<&BiWiMessage> {
+        self.current()?.get(&entity_id)
+    }
+
+    fn current(&self) -> Option<&Entities> {
+        let latest = self.latest?;
+        self.history.iter().find(|(t, _)| *t == latest).map(|(_, s)| s)
+    }
+}
+
+fn entity_id(value: &BiWiValue) -> Option<u32> {
+    match value {
+        BiWiValue::Int32(id) => u32::try_from(*id).ok(),
+        BiWiValue::Int64(id) => u32::try_from(*id).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(x: i32) -> BiWiMessage {
+        BiWiMessage::builder().field(1, BiWiValue::Int32(x)).field(2, "ship").build()
+    }
+
+    /// Encode and decode, as the message would cross the wire
+    fn wire(message: BiWiMessage) -> BiWiMessage {
+        BiWiMessage::from_buffer(&message.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_deltas_follow_acked_baseline() {
+        let mut server = Replicator::new();
+        let mut client = Replica::new();
+        server.set(1, position(0));
+        server.set(2, position(5));
+        server.snapshot();
+
+        // No baseline yet: full state
+        let full = wire(server.delta_for("a").unwrap());
+        assert_eq!(client.apply(&full).unwrap(), 1);
+        assert_eq!(client.entity(2), Some(&position(5)));
+        let delivered = SendHandle::new();
+        server.track("a", 1, delivered.clone());
+
+        server.get_mut(1).unwrap().set_field(1, BiWiValue::Int32(1));
+        server.remove(2);
+        server.snapshot();
+        // Still unACKed: the client gets tick 2 against no baseline
+        assert_eq!(wire(server.delta_for("a").unwrap()).get_i64(2), Some(0));
+
+        delivered.resolve(SendOutcome::Delivered);
+        let delta = wire(server.delta_for("a").unwrap());
+        assert_eq!(delta.get_i64(2), Some(1));
+        assert!(delta.to_vec().len() < full.to_vec().len());
+        // Only the changed field of entity 1 travels
+        assert_eq!(delta.get_array(3).unwrap().len(), 1);
+        assert_eq!(client.apply(&delta).unwrap(), 2);
+        assert_eq!(client.entity(1), Some(&position(1)));
+        assert_eq!(client.entity(2), None);
+
+        // A late, older message doesn't roll the state back
+        client.apply(&full).unwrap();
+        assert_eq!(client.tick(), Some(2));
+        assert_eq!(client.entities().count(), 1);
+
+        // A delta against a baseline the client never saw is refused
+        let mut stranger = Replica::new();
+        assert!(stranger.apply(&delta).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! BiWi Message Router
+//! A server handler that picks a closure for each message by a route key, instead of
+//! one large match in `on_message`. The key is the envelope's message type by
+//! default, or the integer value of a designated field (`Router::by_field`) for
+//! protocols that tag messages in their payload. Messages with no key, or a key
+//! nothing is registered for, go to the fallback handler, if any.
+
+use crate::encoder::BiWiValue;
+use crate::handler::{BiWiServerHandler, Connection, DisconnectReason};
+use crate::message::BiWiMessage;
+use crate::server::ConnectionId;
+use std::collections::HashMap;
+
+type RouteFn = Box<dyn FnMut(&Connection<'_>, BiWiMessage) + Send>;
+type ConnectFn = Box<dyn FnMut(&Connection<'_>) + Send>;
+type DisconnectFn = Box<dyn FnMut(&ConnectionId, DisconnectReason) + Send>;
+
+/// Where a message's route key comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteBy {
+    /// The envelope's message type
+    MessageType,
+    /// The integer value of this field
+    Field(u32),
+}
+
+/// Dispatches messages to handlers registered by route key
+pub struct Router {
+    by: RouteBy,
+    routes: HashMap<u32, RouteFn>,
+    fallback: Option<RouteFn>,
+    connect: Option<ConnectFn>,
+    disconnect: Option<DisconnectFn>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    /// Route by envelope message type
+    pub fn new() -> Self {
+        Self {
+            by: RouteBy::MessageType,
+            routes: HashMap::new(),
+            fallback: None,
+            connect: None,
+            disconnect: None,
+        }
+    }
+
+    /// Route by the integer value of `field_id`
+    pub fn by_field(field_id: u32) -> Self {
+        Self { by: RouteBy::Field(field_id), ..Self::new() }
+    }
+
+    /// Handle messages whose route key is `key`, replacing any earlier handler for it
+    pub fn route(mut self, key: u32, handler: impl FnMut(&Connection<'_>, BiWiMessage) + Send + 'static) -> Self {
+        self.routes.insert(key, Box::new(handler));
+        self
+    }
+
+    /// Handle messages no route matches; without one they are dropped
+    pub fn fallback(mut self, handler: impl FnMut(&Connection<'_>, BiWiMessage) + Send + 'static) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Called when a client completes the handshake
+    pub fn on_connect(mut self, handler: impl FnMut(&Connection<'_>) + Send + 'static) -> Self {
+        self.connect = Some(Box::new(handler));
+        self
+    }
+
+    /// Called when a session ends
+    pub fn on_disconnect(mut self, handler: impl FnMut(&ConnectionId, DisconnectReason) + Send + 'static) -> Self {
+        self.disconnect = Some(Box::new(handler));
+        self
+    }
+
+    pub fn routes_by(&self) -> RouteBy {
+        self.by
+    }
+
+    /// The route key of `message`, if it has one
+    pub fn key(&self, message: &BiWiMessage) -> Option<u32> {
+        match self.by {
+            RouteBy::MessageType => message.message_type().map(u32::from),
+            RouteBy::Field(field_id) => match message.get_field(field_id)? {
+                BiWiValue::Int32(key) => u32::try_from(*key).ok(),
+                BiWiValue::Int64(key) => u32::try_from(*key).ok(),
+                _ => None,
+            },
+        }
+    }
+
+    /// Run the handler for `message`; returns false if nothing handled it
+    pub fn dispatch(&mut self, conn: &Connection<'_>, message: BiWiMessage) -> bool {
+        let route = self.key(&message).and_then(|key| self.routes.get_mut(&key));
+        match route.or(self.fallback.as_mut()) {
+            Some(handler) => {
+                handler(conn, message);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl BiWiServerHandler for Router {
+    fn on_connect(&mut self, conn: &Connection<'_>) {
+        if let Some(connect) = &mut self.connect {
+            connect(conn);
+        }
+    }
+
+    fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+        self.dispatch(conn, message);
+    }
+
+    fn on_disconnect(&mut self, client_id: &ConnectionId, reason: DisconnectReason) {
+        if let Some(disconnect) = &mut self.disconnect {
+            disconnect(client_id, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::server::BiWiUdpServer;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_routes_by_type_and_field() {
+        let server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let id = "client".to_string();
+        let conn = Connection::new(&server, &id, "127.0.0.1:9".parse().unwrap());
+        let (tx, seen) = channel();
+
+        let (move_tx, chat_tx, other_tx) = (tx.clone(), tx.clone(), tx);
+        let mut router = Router::new()
+            .route(1, move |_, _| move_tx.send("move").unwrap())
+            .route(2, move |_, _| chat_tx.send("chat").unwrap())
+            .fallback(move |_, _| other_tx.send("other").unwrap());
+        let typed = |message_type| BiWiMessage::new().with_envelope(Envelope::new(message_type));
+        assert!(router.dispatch(&conn, typed(2)));
+        assert!(router.dispatch(&conn, typed(1)));
+        assert!(router.dispatch(&conn, typed(7)));
+        assert!(router.dispatch(&conn, BiWiMessage::new()));
+        assert_eq!(seen.try_iter().collect::<Vec<_>>(), ["chat", "move", "other", "other"]);
+
+        let mut by_field = Router::by_field(0).route(3, |_, message| assert_eq!(message.get_str(1), Some("hi")));
+        let tagged = |tag: i32| BiWiMessage::builder().field(0, BiWiValue::Int32(tag)).field(1, "hi").build();
+        assert_eq!(by_field.key(&tagged(3)), Some(3));
+        assert!(by_field.dispatch(&conn, tagged(3)));
+        // No fallback: unmatched and negative tags go unhandled
+        assert!(!by_field.dispatch(&conn, tagged(4)));
+        assert!(!by_field.dispatch(&conn, tagged(-1)));
+    }
+}
@@ -0,0 +1,698 @@
+//! BiWi RPC
+//! Method calls over the request/response layer. A method is a message type ID with a
+//! request and a response shape; `RpcDispatcher` is a server handler that routes each
+//! request to the function registered for its ID, and `RpcClient` calls methods by ID
+//! or through typed `RpcMethod` stubs.
+//!
+//! Requests carry their correlation ID and, when the caller set one, a deadline in the
+//! envelope. Failures travel back as responses flagged `RPC_ERROR` whose field 1 is an
+//! error code and field 2 a description.
+//!
+//! Stream methods answer one request with any number of messages. The request's
+//! correlation ID names the stream; each item the server pushes carries it with the
+//! `RPC_STREAM` flag, and the last message adds `RPC_STREAM_END`. Flow control is
+//! credit-based: the server may only push as many items as the client has granted
+//! with `RPC_CREDIT` messages (field 1 is the number of items), and a grant flagged
+//! `RPC_STREAM_END` cancels the stream.
+
+use crate::client::BiWiUdpClient;
+use crate::encoder::BiWiValue;
+use crate::envelope::Envelope;
+use crate::handler::{BiWiServerHandler, Connection, DisconnectReason};
+use crate::message::BiWiMessage;
+use crate::server::{ClientSender, ConnectionId};
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Envelope flag: the response is an error (see `RpcError`)
+pub const RPC_ERROR: u8 = 0x80;
+/// Envelope flag: the timestamp is the caller's deadline, not its send time
+pub const RPC_DEADLINE: u8 = 0x40;
+/// Envelope flag: the message belongs to the stream its correlation ID names
+pub const RPC_STREAM: u8 = 0x20;
+/// Envelope flag: the stream ends with this message (from the client: cancel it)
+pub const RPC_STREAM_END: u8 = 0x10;
+/// Envelope flag: the client grants the stream field 1 more items
+pub const RPC_CREDIT: u8 = 0x08;
+
+/// How often a `StreamSink` waiting for credit checks that its client is still there
+const CREDIT_POLL: Duration = Duration::from_millis(100);
+
+/// Error codes below this are reserved for the RPC layer
+pub const FIRST_APPLICATION_CODE: i32 = 16;
+
+const CODE_UNKNOWN_METHOD: i32 = 1;
+const CODE_INVALID_REQUEST: i32 = 2;
+const CODE_DEADLINE_EXCEEDED: i32 = 3;
+const CODE_INTERNAL: i32 = 4;
+
+/// Why a call failed
+#[derive(Debug)]
+pub enum RpcError {
+    /// The server has no method with this ID
+    UnknownMethod(u16),
+    /// The request didn't have the shape the method expects
+    InvalidRequest,
+    /// The response didn't have the shape the method promises
+    InvalidResponse,
+    /// The deadline passed before the server got to the request
+    DeadlineExceeded,
+    /// Returned by the method itself
+    Application { code: i32, message: String },
+    /// The client cancelled the stream or disconnected
+    StreamClosed,
+    /// The request couldn't be sent, or no response came back in time
+    Io(io::Error),
+}
+
+impl RpcError {
+    /// An application error; `code` should be at least `FIRST_APPLICATION_CODE`
+    pub fn application(code: i32, message: impl Into<String>) -> Self {
+        Self::Application { code, message: message.into() }
+    }
+
+    /// The error response sent back for this error
+    fn to_response(&self, method: u16) -> BiWiMessage {
+        let (code, message) = match self {
+            Self::UnknownMethod(_) => (CODE_UNKNOWN_METHOD, String::new()),
+            Self::InvalidRequest | Self::InvalidResponse => (CODE_INVALID_REQUEST, String::new()),
+            Self::DeadlineExceeded => (CODE_DEADLINE_EXCEEDED, String::new()),
+            Self::Application { code, message } => (*code, message.clone()),
+            Self::StreamClosed => (CODE_INTERNAL, self.to_string()),
+            Self::Io(error) => (CODE_INTERNAL, error.to_string()),
+        };
+        BiWiMessage::builder()
+            .field(1, BiWiValue::Int32(code))
+            .field(2, message)
+            .build()
+            .with_envelope(Envelope::new(method).with_flags(RPC_ERROR))
+    }
+
+    fn from_response(response: &BiWiMessage) -> Self {
+        let method = response.message_type().unwrap_or(0);
+        match response.get_i32(1) {
+            Some(CODE_UNKNOWN_METHOD) => Self::UnknownMethod(method),
+            Some(CODE_INVALID_REQUEST) => Self::InvalidRequest,
+            Some(CODE_DEADLINE_EXCEEDED) => Self::DeadlineExceeded,
+            code => Self::application(code.unwrap_or(0), response.get_str(2).unwrap_or_default()),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMethod(method) => write!(f, "Unknown RPC method {}", method),
+            Self::InvalidRequest => write!(f, "Invalid RPC request"),
+            Self::InvalidResponse => write!(f, "Invalid RPC response"),
+            Self::DeadlineExceeded => write!(f, "RPC deadline exceeded"),
+            Self::Application { code, message } => write!(f, "RPC error {}: {}", code, message),
+            Self::StreamClosed => write!(f, "RPC stream closed"),
+            Self::Io(error) => write!(f, "RPC transport error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<io::Error> for RpcError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// A value that travels as a message's fields
+pub trait RpcMessage: Sized {
+    fn to_message(&self) -> BiWiMessage;
+
+    /// `None` if the message doesn't have the expected fields
+    fn from_message(message: BiWiMessage) -> Option<Self>;
+}
+
+impl RpcMessage for BiWiMessage {
+    fn to_message(&self) -> BiWiMessage {
+        self.clone()
+    }
+
+    fn from_message(message: BiWiMessage) -> Option<Self> {
+        Some(message)
+    }
+}
+
+/// A method definition shared by server and client: its ID and message shapes
+pub trait RpcMethod {
+    const ID: u16;
+    type Request: RpcMessage;
+    type Response: RpcMessage;
+}
+
+type MethodFn = Box<dyn FnMut(&Connection<'_>, BiWiMessage) -> Result<BiWiMessage, RpcError> + Send>;
+type StreamFn = Box<dyn FnMut(&Connection<'_>, BiWiMessage, StreamSink) + Send>;
+type OtherFn = Box<dyn FnMut(&Connection<'_>, BiWiMessage) + Send>;
+
+#[derive(Default)]
+struct CreditState {
+    /// Items the client has granted and the server not yet pushed
+    available: u64,
+    /// Finished by the server, or cancelled or disconnected by the client
+    closed: bool,
+}
+
+/// A stream's credit, shared between the dispatcher (which adds grants) and its sink
+#[derive(Default)]
+struct Credit {
+    state: Mutex<CreditState>,
+    granted: Condvar,
+}
+
+impl Credit {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.granted.notify_all();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+/// The server's end of a stream: pushes items to the client as its credit allows.
+/// Can be moved to another thread; dropping it ends the stream.
+pub struct StreamSink {
+    sender: ClientSender,
+    method: u16,
+    stream_id: u64,
+    credit: Arc<Credit>,
+}
+
+impl StreamSink {
+    /// The request's correlation ID, which tags every item
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Push an item, waiting until the client has granted credit for it; fails with
+    /// `StreamClosed` once the client cancels or disconnects
+    pub fn push<T: RpcMessage>(&self, item: &T) -> Result<(), RpcError> {
+        self.take_credit(true)?;
+        self.send(item.to_message(), RPC_STREAM)
+    }
+
+    /// Push an item if the client has credit left; `Ok(false)` if it hasn't
+    pub fn try_push<T: RpcMessage>(&self, item: &T) -> Result<bool, RpcError> {
+        if !self.take_credit(false)? {
+            return Ok(false);
+        }
+        self.send(item.to_message(), RPC_STREAM).map(|_| true)
+    }
+
+    /// End the stream normally
+    pub fn finish(self) -> Result<(), RpcError> {
+        self.end(BiWiMessage::new())
+    }
+
+    /// End the stream with an error for the client
+    pub fn fail(self, error: RpcError) -> Result<(), RpcError> {
+        let response = error.to_response(self.method);
+        self.end(response)
+    }
+
+    fn end(&self, message: BiWiMessage) -> Result<(), RpcError> {
+        if self.credit.is_closed() {
+            return Err(RpcError::StreamClosed);
+        }
+        self.credit.close();
+        self.send(message, RPC_STREAM | RPC_STREAM_END)
+    }
+
+    fn take_credit(&self, wait: bool) -> Result<bool, RpcError> {
+        let mut state = self.credit.state.lock().unwrap();
+        loop {
+            if state.closed || !self.sender.is_connected() {
+                return Err(RpcError::StreamClosed);
+            }
+            if state.available > 0 {
+                state.available -= 1;
+                return Ok(true);
+            }
+            if !wait {
+                return Ok(false);
+            }
+            state = self.credit.granted.wait_timeout(state, CREDIT_POLL).unwrap().0;
+        }
+    }
+
+    fn send(&self, message: BiWiMessage, flags: u8) -> Result<(), RpcError> {
+        let envelope = message.envelope().copied().unwrap_or(Envelope::new(self.method));
+        let envelope = envelope.with_correlation_id(self.stream_id).with_flags(envelope.flags | flags);
+        self.sender.send(&message.with_envelope(envelope))?;
+        Ok(())
+    }
+}
+
+impl Drop for StreamSink {
+    fn drop(&mut self) {
+        let _ = self.end(BiWiMessage::new());
+    }
+}
+
+/// Server handler that routes requests to registered methods by message type.
+/// Messages without a correlation ID aren't requests and go to `on_other`, if set.
+#[derive(Default)]
+pub struct RpcDispatcher {
+    methods: HashMap<u16, MethodFn>,
+    streams: HashMap<u16, StreamFn>,
+    /// Credit of each open stream, by client and stream ID
+    open: HashMap<(ConnectionId, u64), Arc<Credit>>,
+    other: Option<OtherFn>,
+}
+
+impl RpcDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `M` with `handler`; requests that don't decode as `M::Request` are
+    /// answered with `InvalidRequest`
+    pub fn register<M, F>(mut self, mut handler: F) -> Self
+    where
+        M: RpcMethod,
+        F: FnMut(&Connection<'_>, M::Request) -> Result<M::Response, RpcError> + Send + 'static,
+    {
+        let method: MethodFn = Box::new(move |conn, request| {
+            let request = M::Request::from_message(request).ok_or(RpcError::InvalidRequest)?;
+            handler(conn, request).map(|response| response.to_message())
+        });
+        self.methods.insert(M::ID, method);
+        self
+    }
+
+    /// Serve method `id` with a handler working on raw messages
+    pub fn register_raw<F>(mut self, id: u16, handler: F) -> Self
+    where
+        F: FnMut(&Connection<'_>, BiWiMessage) -> Result<BiWiMessage, RpcError> + Send + 'static,
+    {
+        self.methods.insert(id, Box::new(handler));
+        self
+    }
+
+    /// Serve stream method `M`: `handler` gets a `StreamSink` to push `M::Response`
+    /// items through, now or later from another thread. Requests that don't decode as
+    /// `M::Request` end the stream with `InvalidRequest`.
+    pub fn register_stream<M, F>(mut self, mut handler: F) -> Self
+    where
+        M: RpcMethod,
+        F: FnMut(&Connection<'_>, M::Request, StreamSink) + Send + 'static,
+    {
+        let method: StreamFn = Box::new(move |conn, request, sink| match M::Request::from_message(request) {
+            Some(request) => handler(conn, request, sink),
+            None => {
+                let _ = sink.fail(RpcError::InvalidRequest);
+            }
+        });
+        self.streams.insert(M::ID, method);
+        self
+    }
+
+    /// Serve stream method `id` with a handler working on raw messages
+    pub fn register_stream_raw<F>(mut self, id: u16, handler: F) -> Self
+    where
+        F: FnMut(&Connection<'_>, BiWiMessage, StreamSink) + Send + 'static,
+    {
+        self.streams.insert(id, Box::new(handler));
+        self
+    }
+
+    /// Handle messages that aren't requests
+    pub fn on_other(mut self, handler: impl FnMut(&Connection<'_>, BiWiMessage) + Send + 'static) -> Self {
+        self.other = Some(Box::new(handler));
+        self
+    }
+
+    /// Run the method `request` names and produce its response (or error response)
+    fn call(&mut self, conn: &Connection<'_>, request: BiWiMessage, envelope: Envelope) -> BiWiMessage {
+        let method = envelope.message_type;
+        let result = if expired(&envelope) {
+            Err(RpcError::DeadlineExceeded)
+        } else if let Some(handler) = self.methods.get_mut(&method) {
+            handler(conn, request)
+        } else {
+            Err(RpcError::UnknownMethod(method))
+        };
+        match result {
+            Ok(response) => response.with_type(method),
+            Err(error) => error.to_response(method),
+        }
+    }
+
+    /// Hand a stream request to its method along with a sink for the items
+    fn open_stream(&mut self, conn: &Connection<'_>, request: BiWiMessage, envelope: Envelope) {
+        let Ok(sender) = conn.sender() else {
+            return;
+        };
+        let credit = Arc::new(Credit::default());
+        self.open.insert((conn.id().clone(), envelope.correlation_id), Arc::clone(&credit));
+        let sink = StreamSink {
+            sender,
+            method: envelope.message_type,
+            stream_id: envelope.correlation_id,
+            credit,
+        };
+        if let Some(handler) = self.streams.get_mut(&envelope.message_type) {
+            handler(conn, request, sink);
+        }
+    }
+
+    /// Apply a client's credit grant (or cancellation) to one of its streams
+    fn grant(&mut self, client_id: &ConnectionId, envelope: Envelope, message: &BiWiMessage) {
+        let key = (client_id.clone(), envelope.correlation_id);
+        if envelope.has_flag(RPC_STREAM_END) {
+            if let Some(credit) = self.open.remove(&key) {
+                credit.close();
+            }
+        } else if let Some(credit) = self.open.get(&key) {
+            let items = message.get_i64(1).or(message.get_i32(1).map(i64::from)).unwrap_or(0);
+            credit.state.lock().unwrap().available += items.max(0) as u64;
+            credit.granted.notify_all();
+        }
+    }
+}
+
+impl BiWiServerHandler for RpcDispatcher {
+    fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+        let envelope = match message.envelope() {
+            Some(envelope) if envelope.correlation_id != 0 => *envelope,
+            _ => {
+                if let Some(other) = &mut self.other {
+                    other(conn, message);
+                }
+                return;
+            }
+        };
+        if envelope.has_flag(RPC_CREDIT) {
+            self.grant(conn.id(), envelope, &message);
+            return;
+        }
+        // Forget streams their sinks have ended
+        self.open.retain(|_, credit| !credit.is_closed());
+        if self.streams.contains_key(&envelope.message_type) && !expired(&envelope) {
+            self.open_stream(conn, message, envelope);
+            return;
+        }
+        let response = self.call(conn, message, envelope);
+        let envelope = response.envelope().copied().unwrap_or_default().with_correlation_id(envelope.correlation_id);
+        // The session may have closed meanwhile; the client then stops waiting by itself
+        let _ = conn.reply(&response.with_envelope(envelope));
+    }
+
+    fn on_disconnect(&mut self, client_id: &ConnectionId, _reason: DisconnectReason) {
+        // Sinks still pushing to the client fail from now on
+        self.open.retain(|(id, _), credit| {
+            if id != client_id {
+                return true;
+            }
+            credit.close();
+            false
+        });
+    }
+}
+
+/// Calls methods on the server a client is connected to
+pub struct RpcClient<'a> {
+    client: &'a BiWiUdpClient,
+    timeout: Duration,
+}
+
+impl<'a> RpcClient<'a> {
+    /// Calls wait up to 5 s by default
+    pub fn new(client: &'a BiWiUdpClient) -> Self {
+        Self {
+            client,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// How long a call waits for its response; it is also sent as the call's deadline
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Call `M` with `request`
+    pub fn call<M: RpcMethod>(&self, request: &M::Request) -> Result<M::Response, RpcError> {
+        let response = self.call_raw(M::ID, request.to_message())?;
+        M::Response::from_message(response).ok_or(RpcError::InvalidResponse)
+    }
+
+    /// Call method `id` with a raw request message
+    pub fn call_raw(&self, id: u16, request: BiWiMessage) -> Result<BiWiMessage, RpcError> {
+        let response = self.client.request(&self.with_deadline(id, request), self.timeout)?;
+        if response.envelope().is_some_and(|e| e.has_flag(RPC_ERROR)) {
+            return Err(RpcError::from_response(&response));
+        }
+        Ok(response)
+    }
+
+    /// Open stream method `M`, letting the server run up to `window` items ahead of
+    /// what has been read
+    pub fn stream<M: RpcMethod>(&self, request: &M::Request, window: u32) -> Result<RpcStream<'a, M::Response>, RpcError> {
+        self.open(M::ID, request.to_message(), window)
+    }
+
+    /// Open stream method `id` with a raw request message
+    pub fn stream_raw(&self, id: u16, request: BiWiMessage, window: u32) -> Result<RpcStream<'a, BiWiMessage>, RpcError> {
+        self.open(id, request, window)
+    }
+
+    fn open<T: RpcMessage>(&self, id: u16, request: BiWiMessage, window: u32) -> Result<RpcStream<'a, T>, RpcError> {
+        let (stream_id, items) = self.client.open_stream(&self.with_deadline(id, request))?;
+        let stream = RpcStream {
+            client: self.client,
+            method: id,
+            stream_id,
+            items,
+            timeout: self.timeout,
+            window: window.max(1),
+            unacknowledged: 0,
+            done: false,
+            _item: PhantomData,
+        };
+        stream.control(RPC_CREDIT, stream.window)?;
+        Ok(stream)
+    }
+
+    fn with_deadline(&self, id: u16, request: BiWiMessage) -> BiWiMessage {
+        let deadline = now_ms() + self.timeout.as_millis() as u64;
+        request.with_envelope(Envelope::new(id).with_flags(RPC_DEADLINE).with_timestamp(deadline))
+    }
+}
+
+/// The client's end of a stream: iterates over the items the server pushes, granting
+/// more credit as they are read. Each item is waited for up to the `RpcClient`'s
+/// timeout. Dropping the stream before it ends cancels it.
+pub struct RpcStream<'a, T> {
+    client: &'a BiWiUdpClient,
+    method: u16,
+    stream_id: u64,
+    items: Receiver<BiWiMessage>,
+    timeout: Duration,
+    window: u32,
+    /// Items read since the last grant
+    unacknowledged: u32,
+    done: bool,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> RpcStream<'_, T> {
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Send a credit grant or cancellation for this stream
+    fn control(&self, flags: u8, items: u32) -> io::Result<()> {
+        let envelope = Envelope::new(self.method).with_correlation_id(self.stream_id).with_flags(flags);
+        let grant = BiWiMessage::builder().field(1, BiWiValue::Int64(items as i64)).build().with_envelope(envelope);
+        self.client.send(&grant).map(drop)
+    }
+}
+
+impl<T: RpcMessage> Iterator for RpcStream<'_, T> {
+    type Item = Result<T, RpcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let Ok(message) = self.items.recv_timeout(self.timeout) else {
+            self.done = true;
+            return Some(Err(RpcError::Io(io::Error::new(io::ErrorKind::TimedOut, "No stream item"))));
+        };
+        let flags = message.envelope().map_or(0, |e| e.flags);
+        if flags & RPC_ERROR != 0 {
+            self.done = true;
+            return Some(Err(RpcError::from_response(&message)));
+        }
+        if flags & RPC_STREAM_END != 0 {
+            self.done = true;
+            return None;
+        }
+
+        // Top the window back up once half of it has been read
+        self.unacknowledged += 1;
+        if self.unacknowledged >= self.window.div_ceil(2) {
+            let _ = self.control(RPC_CREDIT, self.unacknowledged);
+            self.unacknowledged = 0;
+        }
+        Some(T::from_message(message).ok_or(RpcError::InvalidResponse))
+    }
+}
+
+impl<T> Drop for RpcStream<'_, T> {
+    fn drop(&mut self) {
+        self.client.close_stream(self.stream_id);
+        if !self.done {
+            let _ = self.control(RPC_CREDIT | RPC_STREAM_END, 0);
+        }
+    }
+}
+
+/// Whether a request's deadline has passed
+fn expired(envelope: &Envelope) -> bool {
+    envelope.has_flag(RPC_DEADLINE) && envelope.timestamp.is_some_and(|deadline| deadline < now_ms())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::BiWiUdpServer;
+    use std::thread;
+
+    struct Add;
+
+    struct Operands(i32, i32);
+
+    impl RpcMessage for Operands {
+        fn to_message(&self) -> BiWiMessage {
+            BiWiMessage::builder().field(1, BiWiValue::Int32(self.0)).field(2, BiWiValue::Int32(self.1)).build()
+        }
+
+        fn from_message(message: BiWiMessage) -> Option<Self> {
+            Some(Self(message.get_i32(1)?, message.get_i32(2)?))
+        }
+    }
+
+    struct Sum(i32);
+
+    impl RpcMessage for Sum {
+        fn to_message(&self) -> BiWiMessage {
+            BiWiMessage::builder().field(1, BiWiValue::Int32(self.0)).build()
+        }
+
+        fn from_message(message: BiWiMessage) -> Option<Self> {
+            Some(Self(message.get_i32(1)?))
+        }
+    }
+
+    impl RpcMethod for Add {
+        const ID: u16 = 10;
+        type Request = Operands;
+        type Response = Sum;
+    }
+
+    #[test]
+    fn test_dispatcher_routes_calls() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let stop = server.stop_handle();
+        let dispatcher = RpcDispatcher::new()
+            .register::<Add, _>(|_, Operands(a, b)| {
+                a.checked_add(b).map(Sum).ok_or_else(|| RpcError::application(FIRST_APPLICATION_CODE, "overflow"))
+            })
+            .on_other(|conn, message| {
+                conn.reply(&message).unwrap();
+            });
+        let server_thread = thread::spawn(move || server.run(dispatcher));
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let rpc = RpcClient::new(&client);
+        assert_eq!(rpc.call::<Add>(&Operands(2, 3)).unwrap().0, 5);
+        assert!(matches!(
+            rpc.call::<Add>(&Operands(i32::MAX, 1)),
+            Err(RpcError::Application { code: FIRST_APPLICATION_CODE, .. })
+        ));
+        assert!(matches!(rpc.call_raw(11, BiWiMessage::new()), Err(RpcError::UnknownMethod(11))));
+        assert!(matches!(rpc.call_raw(Add::ID, BiWiMessage::new()), Err(RpcError::InvalidRequest)));
+
+        // A request whose deadline has already passed isn't run
+        let expired = Operands(1, 1).to_message().with_envelope(Envelope::new(Add::ID).with_flags(RPC_DEADLINE).with_timestamp(1));
+        let response = client.request(&expired, Duration::from_secs(5)).unwrap();
+        assert!(matches!(RpcError::from_response(&response), RpcError::DeadlineExceeded));
+
+        // Plain messages still reach the application
+        let plain = BiWiMessage::builder().field(1, "hello").build();
+        client.send(&plain).unwrap();
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), plain);
+
+        stop.stop();
+        server_thread.join().unwrap();
+    }
+
+    struct Ticks;
+
+    impl RpcMethod for Ticks {
+        const ID: u16 = 20;
+        type Request = Sum;
+        type Response = Sum;
+    }
+
+    #[test]
+    fn test_stream_respects_credit() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let stop = server.stop_handle();
+        let (pushed_tx, pushed) = std::sync::mpsc::channel();
+        let dispatcher = RpcDispatcher::new().register_stream::<Ticks, _>(move |_, Sum(count), sink| {
+            let pushed_tx = pushed_tx.clone();
+            // Items are pushed from another thread, outside the server loop
+            thread::spawn(move || {
+                for tick in 0..count {
+                    if let Err(error) = sink.push(&Sum(tick)) {
+                        pushed_tx.send(Err(error)).unwrap();
+                        return;
+                    }
+                    pushed_tx.send(Ok(tick)).unwrap();
+                }
+                sink.finish().unwrap();
+            });
+        });
+        let server_thread = thread::spawn(move || server.run(dispatcher));
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let rpc = RpcClient::new(&client);
+        let ticks: Vec<i32> = rpc.stream::<Ticks>(&Sum(10), 2).unwrap().map(|item| item.unwrap().0).collect();
+        assert_eq!(ticks, (0..10).collect::<Vec<_>>());
+        assert_eq!(pushed.try_iter().count(), 10);
+
+        // The server stops at the window until items are read, and fails once cancelled
+        let mut stream = rpc.stream::<Ticks>(&Sum(1000), 4).unwrap();
+        assert_eq!(stream.next().unwrap().unwrap().0, 0);
+        thread::sleep(Duration::from_millis(200));
+        drop(stream);
+        let outcomes: Vec<_> = pushed.iter().take_while(|pushed| pushed.is_ok()).collect();
+        assert!(outcomes.len() <= 6, "pushed {} items with a window of 4", outcomes.len());
+
+        assert!(matches!(rpc.stream_raw(21, BiWiMessage::new(), 1).unwrap().next(), Some(Err(RpcError::UnknownMethod(21)))));
+        assert!(matches!(rpc.stream_raw(Ticks::ID, BiWiMessage::new(), 1).unwrap().next(), Some(Err(RpcError::InvalidRequest))));
+
+        stop.stop();
+        server_thread.join().unwrap();
+    }
+}
@@ -1,13 +1,38 @@
 //! BiWi UDP Server
 //! Fast UDP-based server with automatic packet loss recovery
 
+use crate::capture::Capture;
+use crate::chunk::ChunkAssembler;
+use crate::clock::ClockEstimate;
+use crate::coalesce::{Coalescer, DEFAULT_COALESCE_DELAY};
+use crate::compression::{decode_message, FieldTable};
+use crate::congestion::CongestionController;
+use crate::crypto::{confirmation, handshake_random, PacketCipher, Role, HANDSHAKE_RANDOM_LEN};
+use crate::decoder::{BiWiDecoder, ChunkData, ChunkFrame};
+use crate::group::{Group, Groups};
+use crate::intercept::{Interceptor, Interceptors, MessageContext, SharedInterceptors};
+use crate::topics::{Topic, Topics};
+use crate::handler::{BiWiServerHandler, Connection, DisconnectReason, StopHandle};
+use crate::workers::WorkerPool;
 use crate::message::BiWiMessage;
-use crate::network::{PacketManager, PacketType, UdpPacket};
-use std::collections::HashMap;
+use crate::receipt::SendHandle;
+use crate::ratelimit::{LimitExceeded, Limiter, RateLimit};
+use crate::network::{
+    ConnectionStats, PacketManager, PacketType, Priority, RetransmitPolicy, SendMode, UdpPacket, WirePacket, DEFAULT_CHANNEL,
+    FLAG_COMPACT_HEADERS, FLAG_FIELD_COMPRESSION, PROTOCOL_VERSION,
+};
+use crate::shared::SharedMessage;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 pub type ConnectionId = String;
 
@@ -17,6 +42,665 @@ pub struct ClientConnection {
     pub addr: SocketAddr,
     pub packet_manager: PacketManager,
     pub last_activity: std::time::Instant,
+    /// Server-issued ID from the handshake
+    pub session_id: u64,
+    stream: InboundStream,
+    coalescer: Coalescer,
+    /// Reply to a `Connect`, resent if the client repeats it
+    connect_ack: UdpPacket,
+    /// Set once the client sends anything after the handshake; until then the session is pending
+    confirmed: bool,
+    limiter: Limiter,
+    /// The client's field table, if the session negotiated field compression
+    pub(crate) fields: Option<FieldTable>,
+}
+
+impl ClientConnection {
+    pub(crate) fn new(id: ConnectionId, addr: SocketAddr, session_id: u64) -> Self {
+        let packet_manager = PacketManager::new();
+        let coalescer = Coalescer::new(packet_manager.payload_limit(), DEFAULT_COALESCE_DELAY);
+        Self {
+            id,
+            addr,
+            session_id,
+            packet_manager,
+            last_activity: std::time::Instant::now(),
+            stream: InboundStream::default(),
+            coalescer,
+            connect_ack: UdpPacket::connect_ack(session_id),
+            confirmed: false,
+            limiter: Limiter::default(),
+            fields: None,
+        }
+    }
+
+    /// Encrypt the session with a key derived from `psk` and the client's handshake random
+    fn encrypt(&mut self, psk: &[u8], client_random: &[u8; HANDSHAKE_RANDOM_LEN]) {
+        let server_random = handshake_random();
+        let cipher = PacketCipher::derive(psk, self.session_id, client_random, &server_random, Role::Server);
+        self.packet_manager.set_cipher(Some(cipher));
+        self.coalescer = Coalescer::new(self.packet_manager.payload_limit(), DEFAULT_COALESCE_DELAY);
+
+        let proof = confirmation(psk, self.session_id, client_random, &server_random);
+        self.connect_ack = UdpPacket::connect_ack_encrypted(self.session_id, &server_random, &proof);
+    }
+
+    /// Wrap coalesced batches in packets that may go out now
+    fn batch_packets(&mut self, batches: impl IntoIterator<Item = (SendMode, Vec<u8>)>) -> Vec<UdpPacket> {
+        let mut packets = Vec::new();
+        for (mode, batch) in batches {
+            let packet = self.packet_manager.create_batch_packet(DEFAULT_CHANNEL, &batch, mode);
+            packets.extend(self.packet_manager.pace(vec![packet]));
+        }
+        packets
+    }
+
+    /// Send whatever is due by `now`: retransmits, paced packets the congestion
+    /// controller now allows, and coalesced batches and ACKs whose delay ran out
+    fn send_due(&mut self, socket: &UdpSocket, now: Instant) {
+        let retransmits = self.packet_manager.get_retransmit_packets_at(now);
+        for (packet, _) in retransmits {
+            let _ = socket.send_to(&self.packet_manager.encode(&packet), self.addr);
+        }
+        for packet in self.packet_manager.release_paced() {
+            let _ = socket.send_to(&self.packet_manager.encode(&packet), self.addr);
+        }
+        let due = self.coalescer.flush_due_at(now);
+        for packet in self.batch_packets(due) {
+            let _ = socket.send_to(&self.packet_manager.encode(&packet), self.addr);
+        }
+        for ack in self.packet_manager.take_due_acks_at(now) {
+            let _ = socket.send_to(&self.packet_manager.encode(&ack), self.addr);
+        }
+    }
+
+    /// Hand delivered data packets to the stream handler or the message queue
+    fn dispatch(
+        &mut self,
+        packets: Vec<UdpPacket>,
+        stream_handler: &mut Option<StreamHandler>,
+        ready: &mut Ready,
+        topics: &Topics,
+        interceptors: &Mutex<Interceptors>,
+    ) {
+        for packet in packets {
+            if packet.is_stream() {
+                self.stream.handle(&self.id, &packet.payload, stream_handler);
+                continue;
+            }
+            let ctx = MessageContext {
+                peer: Some(self.addr),
+                client_id: Some(&self.id),
+            };
+            for message in packet.messages().into_iter().filter_map(|m| decode_message(m, self.fields.as_mut()).ok()) {
+                let Some(message) = interceptors.lock().unwrap().incoming(message, &ctx) else {
+                    continue;
+                };
+                // Subscription changes are the server's business, not the application's
+                match Topic::parse_control(&message) {
+                    Some((topic, true)) => {
+                        topics.subscribe(&self.id, topic);
+                    }
+                    Some((topic, false)) => {
+                        topics.unsubscribe(&self.id, &topic);
+                    }
+                    None => ready.push_back((self.id.clone(), message)),
+                }
+            }
+        }
+    }
+}
+
+/// Sessions with no traffic for this long are dropped (the default liveness timeout)
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default cap on open sessions
+pub const DEFAULT_MAX_CONNECTIONS: usize = 4096;
+
+/// Default cap on pending sessions per IP address
+pub const DEFAULT_MAX_PENDING_PER_IP: usize = 32;
+
+/// Pending sessions, whose client never followed up its `Connect`, are dropped this soon
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decides whether a `Connect` from an address, carrying the given credentials, may open a session
+pub type AuthCallback = Arc<dyn Fn(SocketAddr, &[u8]) -> bool + Send + Sync>;
+
+/// Admission control: which `Connect`s may open a session. A session is pending from
+/// the handshake until its client sends anything else, so a spoofed or abandoned
+/// `Connect` holds server state for at most `handshake_timeout`.
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// Sessions open at once, pending or not; further `Connect`s are refused
+    pub max_connections: usize,
+    /// Pending sessions one IP address may hold
+    pub max_pending_per_ip: usize,
+    /// How long a session may stay pending
+    pub handshake_timeout: Duration,
+    /// Checks the address and credentials (see `BiWiUdpClient::connect_with_credentials`)
+    /// of each `Connect`; `None` admits everyone within the limits
+    pub auth_callback: Option<AuthCallback>,
+    /// Records every session's packets (see `capture`); `None` records nothing
+    pub capture: Option<Capture>,
+    /// Accept field compression from clients that offer it (see `compression`)
+    pub field_compression: bool,
+    /// Accept compact headers from clients that offer them (see `header`)
+    pub compact_headers: bool,
+    /// Retransmission settings each new session starts with (see `set_retransmit_policy`)
+    pub retransmit_policy: RetransmitPolicy,
+    /// Longest each session holds an ACK to batch it with others (see
+    /// `PacketManager::set_max_ack_delay`); zero ACKs every reliable packet at once
+    pub max_ack_delay: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_pending_per_ip: DEFAULT_MAX_PENDING_PER_IP,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            auth_callback: None,
+            capture: None,
+            field_compression: false,
+            compact_headers: false,
+            retransmit_policy: RetransmitPolicy::default(),
+            max_ack_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Only admit clients `callback` accepts
+    pub fn with_auth(mut self, callback: impl Fn(SocketAddr, &[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.auth_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Record the packets of every session opened from now on to `capture`
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Let clients compress repeated field values on their sessions, at the cost of a
+    /// field table per session
+    pub fn with_field_compression(mut self) -> Self {
+        self.field_compression = true;
+        self
+    }
+
+    /// Shrink the packet headers of sessions whose clients offer compact headers, from
+    /// 13 bytes to as few as 2
+    pub fn with_compact_headers(mut self) -> Self {
+        self.compact_headers = true;
+        self
+    }
+
+    /// Retransmit to every session opened from now on by `policy`
+    pub fn with_retransmit_policy(mut self, policy: RetransmitPolicy) -> Self {
+        self.retransmit_policy = policy;
+        self
+    }
+
+    /// Batch ACKs on every session opened from now on, holding each up to `delay`. The
+    /// server then ticks at least that often, so keep it to a few milliseconds.
+    pub fn with_max_ack_delay(mut self, delay: Duration) -> Self {
+        self.max_ack_delay = delay;
+        self
+    }
+
+    /// `interval`, or less if delayed ACKs fall due sooner
+    pub(crate) fn tick_interval(&self, interval: Duration) -> Duration {
+        match self.max_ack_delay {
+            Duration::ZERO => interval,
+            delay => interval.min(delay),
+        }
+    }
+
+    /// Whether a new session from `addr` fits the limits and passes the auth check
+    fn admits(&self, conns: &HashMap<ConnectionId, ClientConnection>, addr: SocketAddr, credentials: &[u8]) -> bool {
+        if conns.len() >= self.max_connections {
+            return false;
+        }
+        let pending = conns.values().filter(|conn| !conn.confirmed && conn.addr.ip() == addr.ip()).count();
+        if pending >= self.max_pending_per_ip {
+            return false;
+        }
+        self.auth_callback.as_ref().is_none_or(|auth| auth(addr, credentials))
+    }
+}
+
+/// How long a receive waits before the server runs `tick`
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often a busy server runs `tick` between packets
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Session lifecycle changes and, from `poll`, messages and ping results
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// Handshake completed
+    ClientConnected(ConnectionId),
+    /// Closed by a `Disconnect` from either side
+    ClientDisconnected(ConnectionId),
+    /// Dropped after the liveness timeout (`CONNECTION_TIMEOUT` by default) without traffic
+    ClientTimedOut(ConnectionId),
+    /// A complete message from a client (only returned by `poll`)
+    Message(ConnectionId, BiWiMessage),
+    /// A client answered `ping` after this round trip
+    PingResult(ConnectionId, Duration),
+}
+
+/// Fresh, hard-to-guess session ID (never 0, which marks a refusal)
+pub(crate) fn new_session_id() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish().max(1)
+}
+
+/// The slice of the session ID space one server issues IDs from: those equal to
+/// `index` modulo `count`. Worker pools shard sessions this way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Every session ID
+    pub const WHOLE: Shard = Shard { index: 0, count: 1 };
+
+    /// Shard of `count` that owns `session_id`
+    pub fn index_of(session_id: u64, count: u64) -> u64 {
+        session_id % count
+    }
+
+    fn new_session_id(self) -> u64 {
+        loop {
+            let session_id = new_session_id();
+            if Self::index_of(session_id, self.count) == self.index {
+                return session_id;
+            }
+        }
+    }
+}
+
+/// Connection ID for a session: its ID in hex
+pub fn session_key(session_id: u64) -> ConnectionId {
+    format!("{:016x}", session_id)
+}
+
+/// Handle a `Connect` or `Disconnect` packet, returning the reply to send and the
+/// lifecycle event it caused, if any. A repeated `Connect` from a known address gets
+/// its existing session back, so a lost `ConnectAck` can be retried without resetting
+/// sequence state. With a pre-shared key, only clients asking for an encrypted
+/// session are accepted, and new sessions must pass `config`'s admission check.
+pub(crate) fn handle_handshake(
+    conns: &mut HashMap<ConnectionId, ClientConnection>,
+    addr: SocketAddr,
+    wire: &WirePacket,
+    psk: Option<&[u8]>,
+    config: &ServerConfig,
+    shard: Shard,
+) -> (Option<UdpPacket>, Option<ServerEvent>) {
+    let packet = &wire.packet;
+    match packet.packet_type {
+        // Compact headers are only ever negotiated by a handshake
+        PacketType::Connect if wire.is_compact() => (None, None),
+        PacketType::Connect => {
+            if packet.protocol_version() != Some(PROTOCOL_VERSION) {
+                event!(DEBUG, peer = %addr, version = ?packet.protocol_version(), "refused handshake: protocol version");
+                return (Some(UdpPacket::disconnect(0)), None);
+            }
+            let client_random = packet.handshake_random();
+            if psk.is_some() && client_random.is_none() {
+                event!(DEBUG, peer = %addr, "refused handshake: plaintext session on an encrypted server");
+                return (Some(UdpPacket::disconnect(0)), None);
+            }
+            if let Some(conn) = conns.values_mut().find(|conn| conn.addr == addr) {
+                conn.last_activity = std::time::Instant::now();
+                return (Some(conn.connect_ack.clone()), None);
+            }
+            if !config.admits(conns, addr, packet.handshake_credentials()) {
+                event!(DEBUG, peer = %addr, "refused handshake: not admitted");
+                return (Some(UdpPacket::disconnect(0)), None);
+            }
+            let session_id = shard.new_session_id();
+            let client_id = session_key(session_id);
+            let mut conn = ClientConnection::new(client_id.clone(), addr, session_id);
+            if let (Some(psk), Some(client_random)) = (psk, client_random) {
+                conn.encrypt(psk, &client_random);
+            }
+            if config.field_compression && packet.flags & FLAG_FIELD_COMPRESSION != 0 {
+                conn.fields = Some(FieldTable::new());
+                conn.connect_ack.flags |= FLAG_FIELD_COMPRESSION;
+            }
+            if config.compact_headers && packet.flags & FLAG_COMPACT_HEADERS != 0 {
+                conn.packet_manager.set_compact_headers(true);
+                conn.connect_ack.flags |= FLAG_COMPACT_HEADERS;
+            }
+            conn.packet_manager.set_capture(config.capture.clone());
+            conn.packet_manager.set_retransmit_policy(config.retransmit_policy);
+            conn.packet_manager.set_max_ack_delay(config.max_ack_delay);
+            let reply = conn.connect_ack.clone();
+            event!(DEBUG, client = %client_id, peer = %addr, encrypted = psk.is_some(), "session opened");
+            conns.insert(client_id.clone(), conn);
+            (Some(reply), Some(ServerEvent::ClientConnected(client_id)))
+        }
+        PacketType::Disconnect => {
+            // The session ID is the credential, whatever address it comes from; an
+            // encrypted session also needs the Disconnect sealed with its key
+            let closed = packet
+                .session_id()
+                .map(session_key)
+                .filter(|id| {
+                    conns.get_mut(id).is_some_and(|conn| {
+                        let pm = &mut conn.packet_manager;
+                        pm.resolve(wire.clone()).is_some_and(|mut packet| pm.open(&mut packet))
+                    })
+                })
+                .and_then(|id| conns.remove(&id));
+            if let Some(conn) = &closed {
+                event!(DEBUG, client = %conn.id, peer = %addr, "session closed by client");
+            }
+            (None, closed.map(|conn| ServerEvent::ClientDisconnected(conn.id)))
+        }
+        _ => (None, None),
+    }
+}
+
+/// Drop sessions idle for at least `timeout` as of `now`, or pending for `handshake_timeout`
+pub(crate) fn expire_sessions(
+    conns: &mut HashMap<ConnectionId, ClientConnection>,
+    timeout: Duration,
+    handshake_timeout: Duration,
+    now: Instant,
+) -> Vec<ServerEvent> {
+    let expired: Vec<ConnectionId> = conns
+        .values()
+        .filter(|conn| {
+            let limit = if conn.confirmed { timeout } else { handshake_timeout.min(timeout) };
+            now.saturating_duration_since(conn.last_activity) >= limit
+        })
+        .map(|conn| conn.id.clone())
+        .collect();
+    for id in &expired {
+        event!(DEBUG, client = %id, "session timed out");
+        conns.remove(id);
+    }
+    expired.into_iter().map(ServerEvent::ClientTimedOut).collect()
+}
+
+/// Find the session a packet belongs to by its session tag, read its header for that
+/// session (refusing a compact one it never negotiated), then decrypt the packet
+/// if the session is encrypted and strip the tag. A valid packet from a new address
+/// means the client roamed, so the session follows it there.
+pub(crate) fn find_session(
+    conns: &mut HashMap<ConnectionId, ClientConnection>,
+    addr: SocketAddr,
+    wire: WirePacket,
+) -> Option<(&mut ClientConnection, UdpPacket)> {
+    let conn = conns.get_mut(&session_key(wire.packet.session_tag()?))?;
+    let Some(mut packet) = conn.packet_manager.resolve(wire) else {
+        event!(DEBUG, client = %conn.id, peer = %addr, "dropped packet: compact header not negotiated");
+        return None;
+    };
+    if !conn.packet_manager.open(&mut packet) {
+        event!(DEBUG, client = %conn.id, peer = %addr, "dropped packet: failed authentication");
+        return None;
+    }
+    packet.take_session();
+    if conn.addr != addr {
+        event!(DEBUG, client = %conn.id, from = %conn.addr, to = %addr, "client roamed");
+    }
+    conn.addr = addr;
+    conn.last_activity = std::time::Instant::now();
+    conn.confirmed = true;
+    Some((conn, packet))
+}
+
+/// Out-of-order chunk data frames buffered per stream before they are dropped
+const MAX_EARLY_CHUNKS: usize = 256;
+
+/// A chunked field arriving from a client via `BiWiUdpClient::send_stream`
+#[derive(Debug, Clone, Copy)]
+pub struct StreamUpdate<'a> {
+    pub field_id: u16,
+    /// Bytes received so far, in order
+    pub data: &'a [u8],
+    pub total: u64,
+    pub complete: bool,
+}
+
+type StreamHandler = Box<dyn FnMut(&ConnectionId, StreamUpdate<'_>) + Send>;
+
+type TimeoutHandler = Box<dyn FnMut(&ConnectionId) + Send>;
+
+type RateLimitHandler = Box<dyn FnMut(&ConnectionId, LimitExceeded) + Send>;
+
+/// Per-connection chunk stream state. Each chunk is its own packet, so data frames
+/// can arrive out of order and are held until the gap before them fills.
+#[derive(Default)]
+struct InboundStream {
+    assembler: ChunkAssembler,
+    next_index: u16,
+    early: BTreeMap<u16, ChunkData>,
+}
+
+impl InboundStream {
+    fn handle(&mut self, client_id: &ConnectionId, payload: &[u8], handler: &mut Option<StreamHandler>) {
+        let Ok(frame) = BiWiDecoder::new(payload).decode_chunk_frame() else {
+            return;
+        };
+
+        match frame {
+            ChunkFrame::Start(_) => {
+                self.assembler = ChunkAssembler::new();
+                self.next_index = 0;
+                self.early.clear();
+                let _ = self.assembler.push_frame(frame);
+                self.notify(client_id, handler);
+            }
+            ChunkFrame::Data(chunk) => {
+                let ahead = chunk.chunk_index.wrapping_sub(self.next_index);
+                if ahead != 0 {
+                    // Behind is a late duplicate; ahead waits for the gap to fill
+                    if ahead < u16::MAX / 2 && self.early.len() < MAX_EARLY_CHUNKS {
+                        self.early.insert(chunk.chunk_index, chunk);
+                    }
+                    return;
+                }
+                let mut next = Some(chunk);
+                while let Some(chunk) = next {
+                    if self.assembler.push_frame(ChunkFrame::Data(chunk)).is_err() {
+                        self.assembler.interrupt();
+                        self.early.clear();
+                        return;
+                    }
+                    self.next_index = self.next_index.wrapping_add(1);
+                    next = self.early.remove(&self.next_index);
+                }
+                self.notify(client_id, handler);
+            }
+            ChunkFrame::End(_) => {
+                let progress = self.assembler.progress();
+                if let (Ok(Some(field)), Some(progress)) = (self.assembler.push_frame(frame), progress) {
+                    if let Some(handler) = handler.as_mut() {
+                        handler(client_id, StreamUpdate {
+                            field_id: field.field_id,
+                            data: &field.data,
+                            total: progress.total,
+                            complete: true,
+                        });
+                    }
+                }
+                self.early.clear();
+            }
+            // Resumable transfers are not streamed over UDP
+            _ => {}
+        }
+    }
+
+    fn notify(&self, client_id: &ConnectionId, handler: &mut Option<StreamHandler>) {
+        let (Some(handler), Some(progress), Some(data)) =
+            (handler.as_mut(), self.assembler.progress(), self.assembler.received_data())
+        else {
+            return;
+        };
+        handler(client_id, StreamUpdate {
+            field_id: progress.field_id,
+            data,
+            total: progress.total,
+            complete: false,
+        });
+    }
+}
+
+/// Sends to one client on a server's socket, from any thread (see `BiWiUdpServer::sender`).
+/// Sends fail with `NotFound` once the client's session ends.
+#[derive(Clone)]
+pub struct ClientSender {
+    socket: Arc<UdpSocket>,
+    connections: Arc<Mutex<HashMap<ConnectionId, ClientConnection>>>,
+    interceptors: SharedInterceptors,
+    client_id: ConnectionId,
+}
+
+impl ClientSender {
+    pub fn client_id(&self) -> &ConnectionId {
+        &self.client_id
+    }
+
+    /// Whether the client's session is still open
+    pub fn is_connected(&self) -> bool {
+        self.connections.lock().unwrap().contains_key(&self.client_id)
+    }
+
+    /// Send a message to the client (reliable and ordered)
+    pub fn send(&self, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.send_with_mode(message, SendMode::default())
+    }
+
+    /// Send a message to the client with the given delivery guarantees
+    pub fn send_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        let msg_bytes = encode_for(&self.interceptors, &self.connections, &self.client_id, message);
+        send_to_client(&self.socket, &self.connections, &self.client_id, DEFAULT_CHANNEL, &msg_bytes, mode, Priority::Normal)
+    }
+}
+
+/// Encode a message for one client, through the outgoing interceptors
+fn encode_for(
+    interceptors: &Mutex<Interceptors>,
+    connections: &Mutex<HashMap<ConnectionId, ClientConnection>>,
+    client_id: &str,
+    message: &BiWiMessage,
+) -> Vec<u8> {
+    let mut interceptors = interceptors.lock().unwrap();
+    if interceptors.is_empty() {
+        return message.to_vec();
+    }
+    let peer = connections.lock().unwrap().get(client_id).map(|conn| conn.addr);
+    interceptors.outgoing(message, &MessageContext { peer, client_id: Some(client_id) })
+}
+
+/// Packetize and send a message to one client
+fn send_to_client(
+    socket: &UdpSocket,
+    connections: &Mutex<HashMap<ConnectionId, ClientConnection>>,
+    client_id: &str,
+    channel: u8,
+    msg_bytes: &[u8],
+    mode: SendMode,
+    priority: Priority,
+) -> io::Result<SendHandle> {
+    let mut conns = connections.lock().unwrap();
+    let conn = conns
+        .get_mut(client_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+    conn.packet_manager.check_message_size(msg_bytes.len())?;
+    let packets = conn.packet_manager.create_packets_on(channel, msg_bytes, mode);
+    let receipt = SendHandle::new();
+    conn.packet_manager.attach_receipt(&packets, &receipt);
+    for packet in conn.packet_manager.pace_with_priority(packets, priority) {
+        socket.send_to(&conn.packet_manager.encode(&packet), conn.addr)?;
+    }
+    Ok(receipt)
+}
+
+/// Background retransmit thread started by `BiWiUdpServer::spawn_retransmit_timer`;
+/// stops when dropped
+pub struct RetransmitTimer {
+    running: Arc<Mutex<bool>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RetransmitTimer {
+    /// Stop the thread and wait for it to finish
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for RetransmitTimer {
+    fn drop(&mut self) {
+        *self.running.lock().unwrap() = false;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Messages waiting for the application, counted per client so the receive window
+/// advertised to each shrinks while the application falls behind on its messages
+#[derive(Default)]
+struct Ready {
+    messages: VecDeque<(ConnectionId, BiWiMessage)>,
+    unread: HashMap<ConnectionId, usize>,
+}
+
+impl Ready {
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    fn push_back(&mut self, entry: (ConnectionId, BiWiMessage)) {
+        *self.unread.entry(entry.0.clone()).or_default() += 1;
+        self.messages.push_back(entry);
+    }
+
+    fn push_front(&mut self, entry: (ConnectionId, BiWiMessage)) {
+        *self.unread.entry(entry.0.clone()).or_default() += 1;
+        self.messages.push_front(entry);
+    }
+
+    fn pop_front(&mut self) -> Option<(ConnectionId, BiWiMessage)> {
+        let entry = self.messages.pop_front()?;
+        if let Some(count) = self.unread.get_mut(&entry.0) {
+            *count -= 1;
+            if *count == 0 {
+                self.unread.remove(&entry.0);
+            }
+        }
+        Some(entry)
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = (ConnectionId, BiWiMessage)> + '_ {
+        self.unread.clear();
+        self.messages.drain(..)
+    }
+
+    /// Messages from `client_id` not yet taken
+    fn unread(&self, client_id: &str) -> usize {
+        self.unread.get(client_id).copied().unwrap_or(0)
+    }
 }
 
 /// BiWi UDP Server - Simple synchronous implementation
@@ -25,6 +709,33 @@ pub struct BiWiUdpServer {
     pub port: u16,
     pub host: String,
     pub connections: Arc<Mutex<HashMap<ConnectionId, ClientConnection>>>,
+    stream_handler: Option<StreamHandler>,
+    timeout_handler: Option<TimeoutHandler>,
+    rate_limit_handler: Option<RateLimitHandler>,
+    events: VecDeque<ServerEvent>,
+    /// Messages released together by a reorder buffer, returned one per `recv_packet`
+    ready: Ready,
+    /// Pre-shared key every session is encrypted with; `None` accepts plaintext sessions
+    psk: Option<Vec<u8>>,
+    connection_timeout: Duration,
+    last_tick: Instant,
+    /// Socket errors seen by `recv_packet`, handed to `run`'s handler
+    errors: VecDeque<io::Error>,
+    /// Cleared by a `StopHandle` to end `run`
+    running: Arc<Mutex<bool>>,
+    /// Session IDs this server issues
+    shard: Shard,
+    config: ServerConfig,
+    rate_limit: Option<RateLimit>,
+    /// IPs refused until the given time for breaking `rate_limit`
+    bans: HashMap<IpAddr, Instant>,
+    groups: Groups,
+    topics: Topics,
+    interceptors: SharedInterceptors,
+    /// Datagrams routed to this server when it is a pool worker; it reads the socket otherwise
+    inbox: Option<Receiver<(Vec<u8>, SocketAddr)>>,
+    /// Set once an external event loop drives the socket, which then stays non-blocking
+    event_driven: bool,
 }
 
 impl BiWiUdpServer {
@@ -32,136 +743,1666 @@ impl BiWiUdpServer {
     pub fn new(host: &str, port: u16) -> io::Result<Self> {
         let addr = format!("{}:{}", host, port);
         let socket = UdpSocket::bind(&addr)?;
-        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
 
-        println!("[BiWi UDP] Server listening on {}", addr);
+        event!(INFO, %addr, "BiWi UDP server listening");
 
         Ok(BiWiUdpServer {
             socket,
             port,
             host: host.to_string(),
             connections: Arc::new(Mutex::new(HashMap::new())),
+            stream_handler: None,
+            timeout_handler: None,
+            rate_limit_handler: None,
+            events: VecDeque::new(),
+            ready: Ready::default(),
+            psk: None,
+            connection_timeout: CONNECTION_TIMEOUT,
+            last_tick: Instant::now(),
+            errors: VecDeque::new(),
+            running: Arc::new(Mutex::new(true)),
+            shard: Shard::WHOLE,
+            config: ServerConfig::default(),
+            rate_limit: None,
+            bans: HashMap::new(),
+            groups: Groups::default(),
+            topics: Topics::default(),
+            interceptors: SharedInterceptors::default(),
+            inbox: None,
+            event_driven: false,
+        })
+    }
+
+    /// Worker for `shard` of a pool: sends on this server's socket but receives from
+    /// `inbox`, starting with the sessions in `connections`
+    pub(crate) fn worker(
+        &self,
+        shard: Shard,
+        inbox: Receiver<(Vec<u8>, SocketAddr)>,
+        connections: HashMap<ConnectionId, ClientConnection>,
+    ) -> io::Result<Self> {
+        Ok(BiWiUdpServer {
+            socket: self.socket.try_clone()?,
+            port: self.port,
+            host: self.host.clone(),
+            connections: Arc::new(Mutex::new(connections)),
+            stream_handler: None,
+            timeout_handler: None,
+            rate_limit_handler: None,
+            events: VecDeque::new(),
+            ready: Ready::default(),
+            psk: self.psk.clone(),
+            connection_timeout: self.connection_timeout,
+            last_tick: Instant::now(),
+            errors: VecDeque::new(),
+            running: Arc::new(Mutex::new(true)),
+            shard,
+            config: self.config.clone(),
+            rate_limit: self.rate_limit,
+            bans: HashMap::new(),
+            groups: Groups::default(),
+            topics: Topics::default(),
+            interceptors: Arc::clone(&self.interceptors),
+            inbox: Some(inbox),
+            event_driven: false,
         })
     }
 
+    /// Handle sessions on `count` worker threads, each running its own clone of
+    /// `handler`. A dispatcher thread reads the socket and routes every datagram to the
+    /// worker that owns its session, so decoding and handling scale past one core.
+    /// A worker's `Connection` only reaches its own sessions: `send` to a client on
+    /// another worker fails with `NotFound`, and `broadcast` covers one worker's share.
+    /// `on_stream`, `on_timeout` and `on_rate_limit` callbacks are not carried over to
+    /// the workers, and `ServerConfig` limits and rate-limit bans apply to each worker's
+    /// share separately.
+    pub fn spawn_workers<H>(self, count: usize, handler: H) -> io::Result<WorkerPool>
+    where
+        H: BiWiServerHandler + Clone + Send + 'static,
+    {
+        WorkerPool::start(self, count, handler)
+    }
+
+    /// Only accept encrypted sessions, keyed from `psk` (see `crypto`); clients must
+    /// connect with `BiWiUdpClient::connect_with_psk` and the same key
+    pub fn with_psk(mut self, psk: &[u8]) -> Self {
+        self.psk = Some(psk.to_vec());
+        self
+    }
+
+    /// Limit and authenticate new sessions (see `ServerConfig`)
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        let _ = self.socket.set_read_timeout(Some(config.tick_interval(READ_TIMEOUT)));
+        self.config = config;
+        self
+    }
+
+    /// Hold every client to `limit`: packets over it are dropped, and with `limit.ban`
+    /// set the client is disconnected and its IP refused until the ban runs out
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Drop sessions that send nothing for `timeout` instead of `CONNECTION_TIMEOUT`.
+    /// Clients ping while idle, so this should be a few keep-alive intervals.
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    /// Call `handler` with each session dropped for inactivity, as it is dropped
+    /// (the `ClientTimedOut` event is still recorded)
+    pub fn on_timeout(&mut self, handler: impl FnMut(&ConnectionId) + Send + 'static) {
+        self.timeout_handler = Some(Box::new(handler));
+    }
+
+    /// Retransmit (and release paced and coalesced packets) from a background thread
+    /// every `interval`, so retransmits keep their timing however long the application
+    /// goes between `recv_packet`, `poll` or `tick` calls. Session expiry and reorder
+    /// flushes still happen in `tick`. Stops when the returned timer is dropped.
+    pub fn spawn_retransmit_timer(&self, interval: Duration) -> io::Result<RetransmitTimer> {
+        let socket = self.socket.try_clone()?;
+        let connections = Arc::clone(&self.connections);
+        let running = Arc::new(Mutex::new(true));
+        let thread = {
+            let running = Arc::clone(&running);
+            thread::spawn(move || {
+                while *running.lock().unwrap() {
+                    thread::sleep(interval);
+                    let now = Instant::now();
+                    for conn in connections.lock().unwrap().values_mut() {
+                        conn.send_due(&socket, now);
+                    }
+                }
+            })
+        };
+        Ok(RetransmitTimer { running, thread: Some(thread) })
+    }
+
+    /// Call `handler` each time a client breaks the rate limit, before its packet is
+    /// dropped (and the client banned, if the limit says so)
+    pub fn on_rate_limit(&mut self, handler: impl FnMut(&ConnectionId, LimitExceeded) + Send + 'static) {
+        self.rate_limit_handler = Some(Box::new(handler));
+    }
+
+    /// Call `handler` as chunk streams from clients fill in: after the start frame,
+    /// after each in-order run of data, and once more with `complete` set at the end
+    pub fn on_stream(&mut self, handler: impl FnMut(&ConnectionId, StreamUpdate<'_>) + Send + 'static) {
+        self.stream_handler = Some(Box::new(handler));
+    }
+
     /// Receive next packet and return (client_id, message) if complete
     pub fn recv_packet(&mut self) -> Option<(ConnectionId, BiWiMessage)> {
+        if let Some(ready) = self.ready.pop_front() {
+            return Some(ready);
+        }
+        // Ticked here as well as on read timeouts, which a busy server never hits
+        if Instant::now() >= self.next_tick() {
+            self.tick(Instant::now());
+        }
         let mut buf = vec![0u8; 65536];
 
-        match self.socket.recv_from(&mut buf) {
-            Ok((n, addr)) => {
-                let packet_data = &buf[..n];
-
-                if let Ok(packet) = UdpPacket::from_bytes(packet_data) {
-                    let client_id = addr.to_string();
-                    let mut conns = self.connections.lock().unwrap();
-
-                    // Get or create connection
-                    let conn = conns
-                        .entry(client_id.clone())
-                        .or_insert_with(|| ClientConnection {
-                            id: client_id.clone(),
-                            addr,
-                            packet_manager: PacketManager::new(),
-                            last_activity: std::time::Instant::now(),
-                        });
-
-                    conn.last_activity = std::time::Instant::now();
-
-                    // Handle different packet types
-                    match packet.packet_type {
-                        PacketType::Data => {
-                            // Send ACK back
-                            let ack_packet = conn.packet_manager.create_ack_packet(packet.sequence);
-                            let _ = self.socket.send_to(&ack_packet.to_bytes(), addr);
-
-                            // Check for duplicates
-                            if conn.packet_manager.record_received(packet.sequence) {
-                                // New packet - try to decode
-                                match BiWiMessage::from_buffer(&packet.payload) {
-                                    Ok(msg) => return Some((client_id, msg)),
-                                    Err(_) => {} // Incomplete message, wait for more
-                                }
-                            }
-                        }
-                        PacketType::Ack => {
-                            conn.packet_manager.handle_ack(packet.ack_number);
-                        }
-                        PacketType::Ping => {
-                            let pong = UdpPacket {
-                                packet_type: PacketType::Pong,
-                                sequence: 0,
-                                ack_number: packet.sequence,
-                                flags: 0,
-                                payload: Vec::new(),
-                            };
-                            let _ = self.socket.send_to(&pong.to_bytes(), addr);
-                        }
-                        _ => {}
-                    }
+        match self.recv_datagram(&mut buf) {
+            Ok((n, addr)) => self.handle_datagram(&buf[..n], addr),
+            Err(e) => {
+                if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                    self.errors.push_back(e);
                 }
-                None
+                self.tick(Instant::now());
             }
-            Err(_) => {
-                // Timeout - check for retransmits
-                let mut conns = self.connections.lock().unwrap();
-                for conn in conns.values_mut() {
-                    let retransmits = conn.packet_manager.get_retransmit_packets();
-                    for (packet, _) in retransmits {
-                        let _ = self.socket.send_to(&packet.to_bytes(), conn.addr);
-                    }
-                }
+        }
+        self.ready.pop_front()
+    }
+
+    /// Next datagram from the socket, or from the dispatcher for a pool worker
+    fn recv_datagram(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let Some(inbox) = &self.inbox else {
+            return self.socket.recv_from(buf);
+        };
+        let (data, addr) = inbox.recv_timeout(self.config.tick_interval(READ_TIMEOUT)).map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok((data.len(), addr))
+    }
+
+    /// Read every datagram already waiting on the socket, without blocking, and return
+    /// up to `max_events` messages and lifecycle events. Events past `max_events` stay
+    /// queued for the next call. Retransmits and timeouts only run in `tick`, so a game
+    /// loop calls both once per frame.
+    pub fn poll(&mut self, max_events: usize) -> Vec<ServerEvent> {
+        let _ = self.socket.set_nonblocking(true);
+        self.read_available(max_events);
+        if !self.event_driven {
+            let _ = self.socket.set_nonblocking(false);
+        }
+        self.take_events(max_events)
+    }
+
+    /// Entry point for servers driven by an external event loop (mio, epoll): call it
+    /// whenever the socket (see `AsRawFd` or the `mio` feature's `Source`) is readable.
+    /// Reads until the socket would block, as edge-triggered readiness requires, and
+    /// returns what arrived like `poll`. The socket stays non-blocking from then on;
+    /// call `tick` by `next_tick` at the latest.
+    pub fn handle_readable(&mut self) -> Vec<ServerEvent> {
+        self.set_event_driven();
+        self.read_available(usize::MAX);
+        self.take_events(usize::MAX)
+    }
+
+    /// When `tick` is next due, for event loops that wait with a timeout
+    pub fn next_tick(&self) -> Instant {
+        self.last_tick + self.config.tick_interval(TICK_INTERVAL)
+    }
 
-                // Clean up stale connections
-                let timeout = Duration::from_secs(30);
-                conns.retain(|_, conn| conn.last_activity.elapsed() < timeout);
+    fn set_event_driven(&mut self) {
+        if !self.event_driven {
+            let _ = self.socket.set_nonblocking(true);
+            self.event_driven = true;
+        }
+    }
 
-                None
+    /// Handle datagrams waiting on the non-blocking socket until `limit` messages and
+    /// events are queued or none are left
+    fn read_available(&mut self, limit: usize) {
+        let mut buf = vec![0u8; 65536];
+        while self.events.len() + self.ready.len() < limit {
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, addr)) => self.handle_datagram(&buf[..n], addr),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => self.errors.push_back(e),
             }
         }
     }
 
-    /// Send a message to a specific client
-    pub fn send_to(&self, client_id: &str, message: &BiWiMessage) -> io::Result<()> {
-        let msg_bytes = message.to_vec();
+    /// Up to `limit` queued events, messages last; the rest stay queued
+    fn take_events(&mut self, limit: usize) -> Vec<ServerEvent> {
+        let messages = self.ready.drain().map(|(id, message)| ServerEvent::Message(id, message));
+        let mut events: Vec<ServerEvent> = self.events.drain(..).chain(messages).collect();
+        if events.len() > limit {
+            for event in events.drain(limit..).rev() {
+                match event {
+                    ServerEvent::Message(id, message) => self.ready.push_front((id, message)),
+                    event => self.events.push_front(event),
+                }
+            }
+        }
+        events
+    }
+
+    /// Resend un-ACKed packets, release paced and coalesced ones, flush reorder
+    /// buffers and expire idle sessions, judging every deadline against `now`.
+    /// `recv_packet` and `run` call this themselves; `poll` users call it each frame.
+    pub fn tick(&mut self, now: Instant) {
+        self.last_tick = now;
         let mut conns = self.connections.lock().unwrap();
+        for conn in conns.values_mut() {
+            conn.send_due(&self.socket, now);
+            let released = conn.packet_manager.flush_reorder_at(now);
+            conn.dispatch(released, &mut self.stream_handler, &mut self.ready, &self.topics, &self.interceptors);
+        }
+        self.bans.retain(|_, until| *until > now);
 
-        if let Some(conn) = conns.get_mut(client_id) {
-            let packets = conn.packet_manager.create_packets(&msg_bytes);
-            for packet in packets {
-                self.socket.send_to(&packet.to_bytes(), conn.addr)?;
+        let expired = expire_sessions(&mut conns, self.connection_timeout, self.config.handshake_timeout, now);
+        for event in &expired {
+            if let ServerEvent::ClientTimedOut(id) = event {
+                self.groups.leave_all(id);
+                self.topics.leave_all(id);
+                if let Some(handler) = &mut self.timeout_handler {
+                    handler(id);
+                }
             }
-            Ok(())
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Client not found",
-            ))
         }
+        self.events.extend(expired);
     }
 
-    /// Broadcast a message to all connected clients
-    pub fn broadcast(&self, message: &BiWiMessage) -> io::Result<()> {
-        let msg_bytes = message.to_vec();
-        let conns = self.connections.lock().unwrap();
+    /// Handle one datagram, queueing whatever messages and events it produces
+    fn handle_datagram(&mut self, data: &[u8], addr: SocketAddr) {
+        let Ok(wire) = WirePacket::parse(data) else {
+            return;
+        };
+        if self.bans.get(&addr.ip()).is_some_and(|until| *until > Instant::now()) {
+            return;
+        }
+        let connections = Arc::clone(&self.connections);
+        let mut conns = connections.lock().unwrap();
+
+        if matches!(wire.packet.packet_type, PacketType::Connect | PacketType::Disconnect) {
+            let _span = span!(TRACE, "biwi.recv", peer = %addr, kind = ?wire.packet.packet_type);
+            let (reply, event) = handle_handshake(&mut conns, addr, &wire, self.psk.as_deref(), &self.config, self.shard);
+            if let Some(reply) = reply {
+                let _ = self.socket.send_to(&reply.to_bytes(), addr);
+            }
+            if let Some(ServerEvent::ClientDisconnected(id)) = &event {
+                self.groups.leave_all(id);
+                self.topics.leave_all(id);
+            }
+            self.events.extend(event);
+            return;
+        }
 
-        for conn in conns.values() {
-            let mut pm = PacketManager::new();
-            let packets = pm.create_packets(&msg_bytes);
-            for packet in packets {
-                self.socket.send_to(&packet.to_bytes(), conn.addr)?;
+        // Only established sessions get past the handshake
+        let Some((conn, packet)) = find_session(&mut conns, addr, wire) else {
+            return;
+        };
+        // Only now is a compact header's sequence known
+        let _span = span!(TRACE, "biwi.recv", peer = %addr, kind = ?packet.packet_type, sequence = packet.sequence);
+        if let Some(limit) = &self.rate_limit {
+            if let Err(exceeded) = conn.limiter.check(limit, &packet, data.len(), Instant::now()) {
+                let id = conn.id.clone();
+                self.rate_limited(&mut conns, &id, exceeded);
+                return;
             }
         }
-        Ok(())
+
+        // Handle different packet types
+        let mut exceeded = None;
+        match packet.packet_type {
+            PacketType::Data => {
+                // ACK reliable packets, at once or batched with the next few
+                if packet.send_mode().is_reliable() {
+                    conn.packet_manager.set_receive_backlog(self.ready.unread(&conn.id));
+                    for ack in conn.packet_manager.queue_ack(&packet) {
+                        let _ = self.socket.send_to(&conn.packet_manager.encode(&ack), addr);
+                    }
+                }
+
+                // Drops duplicates and stale sequenced packets, holds early ordered ones
+                let mut delivered = conn.packet_manager.deliver(packet);
+                if let Some(limit) = &self.rate_limit {
+                    // Reassembled messages can outgrow the bound checked on their fragments
+                    if let Some(large) = delivered.iter().find(|p| p.payload.len() > limit.max_message_size) {
+                        exceeded = Some((conn.id.clone(), LimitExceeded::MessageSize(large.payload.len())));
+                        delivered.retain(|p| p.payload.len() <= limit.max_message_size);
+                    }
+                }
+                conn.dispatch(delivered, &mut self.stream_handler, &mut self.ready, &self.topics, &self.interceptors);
+            }
+            PacketType::Ack => {
+                // ACKs free up send budget for queued packets
+                conn.packet_manager.handle_ack_packet(&packet);
+                for packet in conn.packet_manager.release_paced() {
+                    let _ = self.socket.send_to(&conn.packet_manager.encode(&packet), addr);
+                }
+            }
+            PacketType::Ping => {
+                let pong = UdpPacket::pong(&packet);
+                let _ = self.socket.send_to(&conn.packet_manager.encode(&pong), addr);
+            }
+            PacketType::Pong => {
+                if let Some(rtt) = conn.packet_manager.handle_pong(&packet) {
+                    self.events.push_back(ServerEvent::PingResult(conn.id.clone(), rtt));
+                }
+            }
+            _ => {}
+        }
+        if let Some((id, exceeded)) = exceeded {
+            self.rate_limited(&mut conns, &id, exceeded);
+        }
     }
 
-    /// Get all connected clients
-    pub fn get_connections(&self) -> Vec<(ConnectionId, SocketAddr)> {
-        self.connections
-            .lock()
-            .unwrap()
-            .iter()
-            .map(|(id, conn)| (id.clone(), conn.addr))
-            .collect()
+    /// Report a client over its rate limit, and ban it if the limit says to
+    fn rate_limited(&mut self, conns: &mut HashMap<ConnectionId, ClientConnection>, client_id: &ConnectionId, exceeded: LimitExceeded) {
+        if let Some(handler) = &mut self.rate_limit_handler {
+            handler(client_id, exceeded);
+        }
+        let Some(ban) = self.rate_limit.and_then(|limit| limit.ban) else {
+            return;
+        };
+        if let Some(mut conn) = conns.remove(client_id) {
+            self.bans.insert(conn.addr.ip(), Instant::now() + ban);
+            let _ = self.socket.send_to(&conn.packet_manager.encode(&UdpPacket::disconnect(conn.session_id)), conn.addr);
+            self.groups.leave_all(client_id);
+            self.topics.leave_all(client_id);
+            self.events.push_back(ServerEvent::ClientDisconnected(conn.id));
+        }
+    }
+
+    /// Serve until stopped through a `StopHandle`, handing every session event and
+    /// message to `handler`. Retransmits and session expiry run as in `recv_packet`.
+    pub fn run(&mut self, mut handler: impl BiWiServerHandler) {
+        while *self.running.lock().unwrap() {
+            let received = self.recv_packet();
+
+            for event in self.drain_events() {
+                match event {
+                    ServerEvent::ClientConnected(id) => {
+                        if let Some(addr) = self.client_addr(&id) {
+                            handler.on_connect(&Connection::new(self, &id, addr));
+                        }
+                    }
+                    ServerEvent::ClientDisconnected(id) => handler.on_disconnect(&id, DisconnectReason::Closed),
+                    ServerEvent::ClientTimedOut(id) => handler.on_disconnect(&id, DisconnectReason::TimedOut),
+                    // Messages come back from `recv_packet`; ping results have no callback
+                    ServerEvent::Message(..) | ServerEvent::PingResult(..) => {}
+                }
+            }
+            for error in self.errors.drain(..) {
+                handler.on_error(error);
+            }
+
+            if let Some((id, message)) = received {
+                if let Some(addr) = self.client_addr(&id) {
+                    handler.on_message(&Connection::new(self, &id, addr), message);
+                }
+            }
+        }
+    }
+
+    /// Handle for stopping `run` from a handler or another thread
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(Arc::clone(&self.running))
+    }
+
+    fn client_addr(&self, client_id: &str) -> Option<SocketAddr> {
+        self.connections.lock().unwrap().get(client_id).map(|conn| conn.addr)
+    }
+
+    /// Take the lifecycle events recorded by `recv_packet` since the last call
+    pub fn drain_events(&mut self) -> Vec<ServerEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Close a client's session, telling the client with a `Disconnect`
+    pub fn disconnect(&mut self, client_id: &str) -> io::Result<()> {
+        let conn = self.connections.lock().unwrap().remove(client_id);
+        let mut conn = conn.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        self.groups.leave_all(&conn.id);
+        self.topics.leave_all(&conn.id);
+        self.events.push_back(ServerEvent::ClientDisconnected(conn.id));
+        self.socket.send_to(&conn.packet_manager.encode(&UdpPacket::disconnect(conn.session_id)), conn.addr)?;
+        Ok(())
+    }
+
+    /// Deliver a client's `ReliableOrdered` messages in sequence order, holding each
+    /// for at most `window` while an earlier one is missing (`None` delivers in arrival order)
+    pub fn set_reorder_window(&self, client_id: &str, window: Option<Duration>) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_reorder_window(window);
+        Ok(())
+    }
+
+    /// Let a client have up to `packets` reliable packets in flight to this server, less
+    /// its messages `recv_packet` has yet to return (`DEFAULT_RECEIVE_WINDOW` by default)
+    pub fn set_receive_window(&self, client_id: &str, packets: u32) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_receive_window(packets);
+        Ok(())
+    }
+
+    /// Change how packets to a client are retransmitted: its ACK timeout, retries,
+    /// backoff and packets in flight (`ServerConfig::retransmit_policy` by default)
+    pub fn set_retransmit_policy(&self, client_id: &str, policy: RetransmitPolicy) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_retransmit_policy(policy);
+        Ok(())
+    }
+
+    /// Cap the send rate to a client with a congestion controller (`None` sends immediately)
+    pub fn set_congestion_controller(
+        &self,
+        client_id: &str,
+        controller: Option<Box<dyn CongestionController>>,
+    ) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_congestion_controller(controller);
+        Ok(())
+    }
+
+    /// Fragment messages to a client into `size`-byte datagrams (default `MAX_PACKET_SIZE`)
+    pub fn set_max_packet_size(&self, client_id: &str, size: usize) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        conn.packet_manager.set_max_packet_size(size);
+        Ok(())
+    }
+
+    /// Send a message to a specific client
+    pub fn send_to(&self, client_id: &str, message: &BiWiMessage) -> io::Result<SendHandle> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &self.encode_for(client_id, message), SendMode::default(), Priority::Normal)
+    }
+
+    /// Send a message to a specific client with the given delivery guarantees
+    pub fn send_to_with_mode(&self, client_id: &str, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &self.encode_for(client_id, message), mode, Priority::Normal)
+    }
+
+    /// Send a message to a specific client on `channel`
+    pub fn send_to_on(&self, client_id: &str, channel: u8, message: &BiWiMessage, mode: SendMode) -> io::Result<SendHandle> {
+        self.send_bytes(client_id, channel, &self.encode_for(client_id, message), mode, Priority::Normal)
+    }
+
+    /// Send a message (reliable and ordered) to a specific client ahead of or behind other queued traffic
+    pub fn send_to_with_priority(&self, client_id: &str, message: &BiWiMessage, priority: Priority) -> io::Result<SendHandle> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, &self.encode_for(client_id, message), SendMode::default(), priority)
+    }
+
+    /// Send an already-encoded shared message to a specific client
+    pub fn send_shared(&self, client_id: &str, message: &SharedMessage) -> io::Result<SendHandle> {
+        self.send_bytes(client_id, DEFAULT_CHANNEL, message.as_bytes(), SendMode::default(), Priority::Normal)
+    }
+
+    /// Add an interceptor that sees every message sent and received (see `intercept`)
+    pub fn add_interceptor(&self, interceptor: impl Interceptor + 'static) {
+        self.interceptors.lock().unwrap().push(Box::new(interceptor));
+    }
+
+    fn encode_for(&self, client_id: &str, message: &BiWiMessage) -> Vec<u8> {
+        encode_for(&self.interceptors, &self.connections, client_id, message)
+    }
+
+    /// Encode a message sent to many clients, running the interceptors once
+    fn encode_fan_out(&self, message: &BiWiMessage) -> Vec<u8> {
+        let ctx = MessageContext { peer: None, client_id: None };
+        self.interceptors.lock().unwrap().outgoing(message, &ctx)
+    }
+
+    fn send_bytes(
+        &self,
+        client_id: &str,
+        channel: u8,
+        msg_bytes: &[u8],
+        mode: SendMode,
+        priority: Priority,
+    ) -> io::Result<SendHandle> {
+        send_to_client(&self.socket, &self.connections, client_id, channel, msg_bytes, mode, priority)
+    }
+
+    /// A handle that sends to `client_id` from any thread, while the server itself is
+    /// busy in `run` or `recv_packet`
+    pub fn sender(&self, client_id: &str) -> io::Result<ClientSender> {
+        if !self.connections.lock().unwrap().contains_key(client_id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Client not found"));
+        }
+        Ok(ClientSender {
+            socket: Arc::new(self.socket.try_clone()?),
+            connections: Arc::clone(&self.connections),
+            interceptors: Arc::clone(&self.interceptors),
+            client_id: client_id.to_string(),
+        })
+    }
+
+    /// Broadcast a message to all connected clients (encoded once)
+    pub fn broadcast(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.broadcast_bytes(&self.encode_fan_out(message), SendMode::default())
+    }
+
+    /// Broadcast a message to all connected clients with the given delivery guarantees
+    pub fn broadcast_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.broadcast_bytes(&self.encode_fan_out(message), mode)
+    }
+
+    /// Broadcast an already-encoded shared message to all connected clients
+    pub fn broadcast_shared(&self, message: &SharedMessage) -> io::Result<()> {
+        self.broadcast_bytes(message.as_bytes(), SendMode::default())
+    }
+
+    /// The group called `name`, created empty if this server has none by that name
+    pub fn create_group(&self, name: &str) -> Group {
+        self.groups.create(name)
+    }
+
+    /// The group called `name`, if it exists
+    pub fn group(&self, name: &str) -> Option<Group> {
+        self.groups.get(name)
+    }
+
+    /// Forget the group called `name`
+    pub fn remove_group(&self, name: &str) -> Option<Group> {
+        self.groups.remove(name)
+    }
+
+    /// Send a message to every member of `group` (reliable and ordered, encoded once)
+    pub fn send_to_group(&self, group: &Group, message: &BiWiMessage) -> io::Result<()> {
+        self.send_to_group_with_mode(group, message, SendMode::default())
+    }
+
+    /// Send a message to every member of `group` with the given delivery guarantees
+    pub fn send_to_group_with_mode(&self, group: &Group, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        self.group_bytes(group, &self.encode_fan_out(message), mode)
+    }
+
+    /// Send an already-encoded shared message to every member of `group`
+    pub fn send_shared_to_group(&self, group: &Group, message: &SharedMessage) -> io::Result<()> {
+        self.group_bytes(group, message.as_bytes(), SendMode::default())
+    }
+
+    fn group_bytes(&self, group: &Group, msg_bytes: &[u8], mode: SendMode) -> io::Result<()> {
+        self.fan_out(&group.members(), msg_bytes, mode)
+    }
+
+    /// Subscribe a client to `topic` on its behalf; returns false if it already was
+    pub fn subscribe(&self, client_id: &str, topic: impl Into<Topic>) -> bool {
+        self.topics.subscribe(client_id, topic.into())
+    }
+
+    /// Unsubscribe a client from `topic`; returns false if it wasn't subscribed
+    pub fn unsubscribe(&self, client_id: &str, topic: impl Into<Topic>) -> bool {
+        self.topics.unsubscribe(client_id, &topic.into())
+    }
+
+    /// Clients subscribed to `topic`
+    pub fn subscribers(&self, topic: impl Into<Topic>) -> Vec<ConnectionId> {
+        self.topics.subscribers(&topic.into())
+    }
+
+    /// Topics a client is subscribed to
+    pub fn subscriptions(&self, client_id: &str) -> Vec<Topic> {
+        self.topics.subscriptions(client_id)
+    }
+
+    /// Send a message to every subscriber of `topic` (reliable and ordered, encoded
+    /// once); returns how many subscribers there were
+    pub fn publish(&self, topic: impl Into<Topic>, message: &BiWiMessage) -> io::Result<usize> {
+        self.publish_with_mode(topic, message, SendMode::default())
+    }
+
+    /// Send a message to every subscriber of `topic` with the given delivery
+    /// guarantees, e.g. `SendMode::Unreliable` for telemetry that is soon stale
+    pub fn publish_with_mode(&self, topic: impl Into<Topic>, message: &BiWiMessage, mode: SendMode) -> io::Result<usize> {
+        let subscribers = self.topics.subscribers(&topic.into());
+        self.fan_out(&subscribers, &self.encode_fan_out(message), mode)?;
+        Ok(subscribers.len())
+    }
+
+    fn fan_out(&self, members: &[ConnectionId], msg_bytes: &[u8], mode: SendMode) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+
+        for id in members {
+            let Some(conn) = conns.get_mut(id) else {
+                continue;
+            };
+            conn.packet_manager.check_message_size(msg_bytes.len())?;
+            let packets = conn.packet_manager.create_packets_with_mode(msg_bytes, mode);
+            for packet in conn.packet_manager.pace(packets) {
+                self.socket.send_to(&conn.packet_manager.encode(&packet), conn.addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a small message for a client to share a datagram with others sent in
+    /// the next few milliseconds; batches go out when full, on `flush_coalesced`, or
+    /// when `recv_packet` finds them due. Messages too large to batch are sent directly.
+    pub fn send_to_coalesced(&self, client_id: &str, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        let msg_bytes = self.encode_for(client_id, message);
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+
+        let packets = if conn.coalescer.fits(msg_bytes.len()) {
+            let full = conn.coalescer.push(mode, msg_bytes);
+            conn.batch_packets(full.map(|batch| (mode, batch)))
+        } else {
+            let packets = conn.packet_manager.create_packets_with_mode(&msg_bytes, mode);
+            conn.packet_manager.pace(packets)
+        };
+        for packet in packets {
+            self.socket.send_to(&conn.packet_manager.encode(&packet), conn.addr)?;
+        }
+        Ok(())
+    }
+
+    /// Send every batch queued by `send_to_coalesced` now
+    pub fn flush_coalesced(&self) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        for conn in conns.values_mut() {
+            let batches = conn.coalescer.flush();
+            for packet in conn.batch_packets(batches) {
+                self.socket.send_to(&conn.packet_manager.encode(&packet), conn.addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast_bytes(&self, msg_bytes: &[u8], mode: SendMode) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+
+        // Each connection's own manager sequences the packets and tracks them for retransmission
+        for conn in conns.values_mut() {
+            conn.packet_manager.check_message_size(msg_bytes.len())?;
+            let packets = conn.packet_manager.create_packets_with_mode(msg_bytes, mode);
+            for packet in conn.packet_manager.pace(packets) {
+                self.socket.send_to(&conn.packet_manager.encode(&packet), conn.addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ping a client; its answer is reported as `ServerEvent::PingResult`
+    pub fn ping(&self, client_id: &str) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let conn = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        let ping = conn.packet_manager.create_ping_packet();
+        self.socket.send_to(&conn.packet_manager.encode(&ping), conn.addr)?;
+        Ok(())
+    }
+
+    /// Live RTT, traffic and loss counters for a client's connection
+    pub fn connection_stats(&self, client_id: &str) -> Option<ConnectionStats> {
+        self.connections.lock().unwrap().get(client_id).map(|conn| conn.packet_manager.stats())
+    }
+
+    /// A client's clock reading now, estimated from `ping` exchanges with it
+    pub fn client_time_estimate(&self, client_id: &str) -> Option<SystemTime> {
+        self.connections.lock().unwrap().get(client_id)?.packet_manager.peer_time_estimate()
+    }
+
+    /// Offset and drift of a client's clock against this machine's
+    pub fn clock_estimate(&self, client_id: &str) -> Option<ClockEstimate> {
+        self.connections.lock().unwrap().get(client_id)?.packet_manager.clock_estimate()
+    }
+
+    /// Address the server is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Get all connected clients
+    pub fn get_connections(&self) -> Vec<(ConnectionId, SocketAddr)> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, conn)| (id.clone(), conn.addr))
+            .collect()
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for BiWiUdpServer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for BiWiUdpServer {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+/// Register the server's socket with a mio `Poll`; readable events go to `handle_readable`
+#[cfg(all(feature = "mio", unix))]
+impl mio::event::Source for BiWiUdpServer {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        self.set_event_driven();
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.socket.as_raw_fd()).deregister(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::BiWiUdpClient;
+    use crate::transport::BiWiTransport;
+    use crate::encoder::BiWiValue;
+    use crate::network::{PACKET_HEADER_SIZE, SESSION_TAG_LEN};
+    use std::thread;
+
+    #[test]
+    fn test_handshake_gates_sessions() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 64];
+
+        // Data from an unknown address is dropped without creating a connection
+        let stray = PacketManager::new().create_packets(&BiWiMessage::new().to_vec()).remove(0);
+        raw.send_to(&stray.to_bytes(), addr).unwrap();
+        assert!(server.recv_packet().is_none());
+        assert!(server.get_connections().is_empty());
+
+        // Wrong protocol version is refused
+        let mut old = UdpPacket::connect();
+        old.payload = 0u16.to_be_bytes().to_vec();
+        raw.send_to(&old.to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        assert_eq!(UdpPacket::from_bytes(&buf[..n]).unwrap().packet_type, PacketType::Disconnect);
+
+        // A retried connect gets the same session back
+        let mut sessions = Vec::new();
+        for _ in 0..2 {
+            raw.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+            server.recv_packet();
+            let (n, _) = raw.recv_from(&mut buf).unwrap();
+            sessions.push(UdpPacket::from_bytes(&buf[..n]).unwrap().session_id().unwrap());
+        }
+        assert_eq!(sessions[0], sessions[1]);
+        assert_eq!(server.get_connections().len(), 1);
+
+        // Disconnect needs the right session ID
+        raw.send_to(&UdpPacket::disconnect(sessions[0] ^ 1).to_bytes(), addr).unwrap();
+        server.recv_packet();
+        assert_eq!(server.get_connections().len(), 1);
+        raw.send_to(&UdpPacket::disconnect(sessions[0]).to_bytes(), addr).unwrap();
+        server.recv_packet();
+        assert!(server.get_connections().is_empty());
+    }
+
+    #[test]
+    fn test_admission_control() {
+        let config = ServerConfig { max_pending_per_ip: 2, ..ServerConfig::default() }
+            .with_auth(|_, credentials| credentials == b"token");
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_config(config);
+        let addr = server.socket.local_addr().unwrap();
+        let mut buf = [0u8; 128];
+        let mut connect = |credentials: &[u8]| {
+            let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+            raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let packet = UdpPacket::connect_with_credentials(&[0; HANDSHAKE_RANDOM_LEN], credentials);
+            raw.send_to(&packet.to_bytes(), addr).unwrap();
+            server.recv_packet();
+            let (n, _) = raw.recv_from(&mut buf).unwrap();
+            UdpPacket::from_bytes(&buf[..n]).unwrap().packet_type
+        };
+
+        assert_eq!(connect(b"wrong"), PacketType::Disconnect);
+        assert_eq!(connect(b"token"), PacketType::ConnectAck);
+        assert_eq!(connect(b"token"), PacketType::ConnectAck);
+        // Two sessions from this IP are already waiting on their clients
+        assert_eq!(connect(b"token"), PacketType::Disconnect);
+
+        // Pending sessions expire long before idle ones
+        server.tick(Instant::now() + HANDSHAKE_TIMEOUT);
+        assert!(server.get_connections().is_empty());
+    }
+
+    #[test]
+    fn test_send_to_group() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || {
+            (0..3).map(|_| BiWiUdpClient::connect(&addr.to_string()).unwrap()).collect::<Vec<_>>()
+        });
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        let mut clients = connecting.join().unwrap();
+
+        let lobby = server.create_group("lobby");
+        for client in &clients[..2] {
+            lobby.add(&session_key(client.session_id()));
+        }
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, crate::encoder::BiWiValue::from("room"));
+        server.send_to_group(&lobby, &msg).unwrap();
+        for client in &clients[..2] {
+            assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+        }
+        assert!(clients[2].recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Closed sessions leave their groups
+        clients[0].disconnect();
+        while lobby.len() == 2 {
+            server.recv_packet();
+        }
+        assert_eq!(server.group("lobby").unwrap().members(), vec![session_key(clients[1].session_id())]);
+    }
+
+    #[test]
+    fn test_publish_reaches_subscribers() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || {
+            (0..3).map(|_| BiWiUdpClient::connect(&addr.to_string()).unwrap()).collect::<Vec<_>>()
+        });
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        let mut clients = connecting.join().unwrap();
+
+        clients[0].subscribe("telemetry").unwrap();
+        clients[1].subscribe("telemetry").unwrap();
+        clients[2].subscribe(9).unwrap();
+        // Control messages are consumed by the server
+        while server.subscribers("telemetry").len() < 2 || server.subscribers(9).is_empty() {
+            assert!(server.recv_packet().is_none());
+        }
+
+        let reading = BiWiMessage::builder().field(1, 21.5).build();
+        assert_eq!(server.publish_with_mode("telemetry", &reading, SendMode::Unreliable).unwrap(), 2);
+        for client in &clients[..2] {
+            assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), reading);
+        }
+        assert!(clients[2].recv_timeout(Duration::from_millis(100)).is_err());
+
+        clients[1].unsubscribe("telemetry").unwrap();
+        clients[0].disconnect();
+        while !server.subscribers("telemetry").is_empty() {
+            server.recv_packet();
+        }
+        assert_eq!(server.subscriptions(&session_key(clients[2].session_id())), vec![Topic::Id(9)]);
+        assert_eq!(server.publish("telemetry", &reading).unwrap(), 0);
+    }
+
+    /// Stamps outgoing messages with a token and drops incoming ones without it
+    struct TokenAuth(&'static str);
+
+    impl Interceptor for TokenAuth {
+        fn on_outgoing(&mut self, message: &mut BiWiMessage, _ctx: &MessageContext<'_>) {
+            message.set_field(100, BiWiValue::from(self.0));
+        }
+
+        fn on_incoming(&mut self, message: &mut BiWiMessage, _ctx: &MessageContext<'_>) -> bool {
+            message.remove_field(100).is_some_and(|token| token.as_str() == Some(self.0))
+        }
+    }
+
+    #[test]
+    fn test_interceptors_wrap_both_directions() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        server.add_interceptor(TokenAuth("secret"));
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || {
+            (0..2).map(|_| BiWiUdpClient::connect(&addr.to_string()).unwrap()).collect::<Vec<_>>()
+        });
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        let clients = connecting.join().unwrap();
+        clients[0].add_interceptor(TokenAuth("secret"));
+
+        // No token: dropped before the application sees it
+        let hello = BiWiMessage::builder().field(1, "hello").build();
+        clients[1].send(&hello).unwrap();
+        clients[0].send(&hello).unwrap();
+        let (client_id, received) = loop {
+            if let Some(received) = server.recv_packet() {
+                break received;
+            }
+        };
+        assert_eq!(client_id, session_key(clients[0].session_id()));
+        assert_eq!(received, hello);
+
+        server.send_to(&client_id, &hello).unwrap();
+        assert_eq!(clients[0].recv_timeout(Duration::from_secs(5)).unwrap(), hello);
+    }
+
+    #[test]
+    fn test_rate_limit_bans_flooding_client() {
+        let limit = RateLimit { packets_per_sec: 3, ..RateLimit::default() }.with_ban(Duration::from_secs(60));
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_rate_limit(limit);
+        let (tx, exceeded) = std::sync::mpsc::channel();
+        server.on_rate_limit(move |id, exceeded| tx.send((id.clone(), exceeded)).unwrap());
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        let mut buf = [0u8; 64];
+        raw.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let session_id = UdpPacket::from_bytes(&buf[..n]).unwrap().session_id().unwrap();
+        let id = session_key(session_id);
+
+        let mut pm = PacketManager::new();
+        pm.set_session(session_id);
+        let msg = BiWiMessage::builder().field(1, "spam").build();
+        for i in 0..4 {
+            let packet = pm.create_packets(&msg.to_vec()).remove(0);
+            raw.send_to(&packet.to_bytes(), addr).unwrap();
+            assert_eq!(server.recv_packet().is_some(), i < 3);
+        }
+        assert_eq!(exceeded.try_recv().unwrap(), (id.clone(), LimitExceeded::PacketRate));
+        assert_eq!(server.drain_events(), vec![ServerEvent::ClientConnected(id.clone()), ServerEvent::ClientDisconnected(id)]);
+
+        // The IP stays banned: a fresh handshake is ignored
+        raw.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+        server.recv_packet();
+        assert!(server.get_connections().is_empty());
+    }
+
+    #[test]
+    fn test_retransmit_timer_runs_without_recv() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        let mut buf = [0u8; 128];
+        raw.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let id = session_key(UdpPacket::from_bytes(&buf[..n]).unwrap().session_id().unwrap());
+
+        // The server sends once and never reads again; the timer alone resends
+        let timer = server.spawn_retransmit_timer(Duration::from_millis(10)).unwrap();
+        server.send_to(&id, &BiWiMessage::builder().field(1, "again").build()).unwrap();
+        let mut sequences = Vec::new();
+        for _ in 0..2 {
+            let (n, _) = raw.recv_from(&mut buf).unwrap();
+            sequences.push(UdpPacket::from_bytes(&buf[..n]).unwrap().sequence);
+        }
+        assert_eq!(sequences[0], sequences[1]);
+        timer.stop();
+    }
+
+    #[test]
+    fn test_session_follows_roaming_client() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        first.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        let mut buf = [0u8; 64];
+        first.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = first.recv_from(&mut buf).unwrap();
+        let session_id = UdpPacket::from_bytes(&buf[..n]).unwrap().session_id().unwrap();
+
+        let mut pm = PacketManager::new();
+        pm.set_session(session_id);
+        let msg = BiWiMessage::builder().field(1, "hi").build();
+        let packet = pm.create_packets(&msg.to_vec()).remove(0);
+        first.send_to(&packet.to_bytes(), addr).unwrap();
+        let (id, _) = server.recv_packet().unwrap();
+        assert_eq!(id, session_key(session_id));
+
+        // Same session from a new address (NAT rebinding): same connection, new address
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = pm.create_packets(&msg.to_vec()).remove(0);
+        second.send_to(&packet.to_bytes(), addr).unwrap();
+        let (roamed_id, received) = server.recv_packet().unwrap();
+        assert_eq!(roamed_id, id);
+        assert_eq!(received, msg);
+        assert_eq!(server.get_connections(), vec![(id, second.local_addr().unwrap())]);
+
+        // An unknown session tag is dropped
+        let mut forged = PacketManager::new();
+        forged.set_session(session_id ^ 1);
+        let packet = forged.create_packets(&msg.to_vec()).remove(0);
+        second.send_to(&packet.to_bytes(), addr).unwrap();
+        assert!(server.recv_packet().is_none());
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let mut events = Vec::new();
+            let deadline = std::time::Instant::now() + Duration::from_secs(10);
+            while std::time::Instant::now() < deadline {
+                server.recv_packet();
+                events.extend(server.drain_events());
+                if events.len() == 2 {
+                    break;
+                }
+            }
+            events
+        });
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let id = session_key(client.session_id());
+        client.send(&BiWiMessage::builder().field(1, "bye").build()).unwrap();
+        client.close(Duration::from_secs(5)).unwrap();
+
+        let events = server_thread.join().unwrap();
+        assert_eq!(events, vec![ServerEvent::ClientConnected(id.clone()), ServerEvent::ClientDisconnected(id)]);
+    }
+
+    #[test]
+    fn test_large_message_roundtrip() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let deadline = std::time::Instant::now() + Duration::from_secs(10);
+            while std::time::Instant::now() < deadline {
+                if let Some((id, msg)) = server.recv_packet() {
+                    server.send_to(&id, &msg).unwrap();
+                    // Keep servicing ACKs until the echo is delivered
+                    while server.connections.lock().unwrap()[&id].packet_manager.has_pending_acks()
+                        && std::time::Instant::now() < deadline
+                    {
+                        server.recv_packet();
+                    }
+                    return;
+                }
+            }
+        });
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let msg = BiWiMessage::builder()
+            .field(1, crate::encoder::BiWiValue::Binary((0..10_000u32).map(|i| i as u8).collect()))
+            .build();
+        client.send(&msg).unwrap();
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_session() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_psk(b"secret");
+        let addr = server.socket.local_addr().unwrap().to_string();
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *server_running.lock().unwrap() {
+                if let Some((id, msg)) = server.recv_packet() {
+                    server.send_to(&id, &msg).unwrap();
+                }
+            }
+        });
+
+        let client = BiWiUdpClient::connect_with_psk(&addr, b"secret").unwrap();
+        let msg = crate::biwi_msg! { 1 => "sealed", 2 => vec![9u8; 3000] };
+        client.send(&msg).unwrap();
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+
+        let refused = BiWiUdpClient::connect(&addr).err().unwrap();
+        assert_eq!(refused.kind(), io::ErrorKind::ConnectionRefused);
+        let wrong_key = BiWiUdpClient::connect_with_psk(&addr, b"guess").err().unwrap();
+        assert_eq!(wrong_key.kind(), io::ErrorKind::PermissionDenied);
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_field_compression_negotiated() {
+        let config = ServerConfig::default().with_field_compression();
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_config(config);
+        let addr = server.socket.local_addr().unwrap().to_string();
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *server_running.lock().unwrap() {
+                if let Some((id, msg)) = server.recv_packet() {
+                    server.send_to(&id, &msg).unwrap();
+                }
+            }
+        });
+
+        let client = BiWiUdpClient::connect(&addr).unwrap();
+        let msg = crate::biwi_msg! { 1 => "device-7f3a9c", 2 => "sensors/temperature", 3 => 21 };
+        let mut sent = Vec::new();
+        for _ in 0..2 {
+            let before = client.stats().bytes_sent;
+            let receipt = client.send(&msg).unwrap();
+            assert_eq!(receipt.wait_timeout(Duration::from_secs(5)), Some(crate::receipt::SendOutcome::Delivered));
+            assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+            sent.push(client.stats().bytes_sent - before);
+        }
+        // Once its inserts are delivered, the strings go as references
+        assert!(sent[1] < sent[0] / 2, "{:?}", sent);
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_compact_headers_negotiated() {
+        let config = ServerConfig::default().with_compact_headers();
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_config(config);
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 64];
+
+        let mut connect = UdpPacket::connect();
+        connect.flags |= FLAG_COMPACT_HEADERS;
+        raw.send_to(&connect.to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let ack = UdpPacket::from_bytes(&buf[..n]).unwrap();
+        assert_ne!(ack.flags & FLAG_COMPACT_HEADERS, 0);
+
+        let mut pm = PacketManager::new();
+        pm.set_session(ack.session_id().unwrap());
+        pm.set_compact_headers(true);
+        let msg = crate::biwi_msg! { 1 => 7 };
+        let packet = pm.create_packets(&msg.to_vec()).remove(0);
+        let sent = pm.encode(&packet);
+        assert_eq!(sent.len(), 2 + SESSION_TAG_LEN + msg.to_vec().len());
+        raw.send_to(&sent, addr).unwrap();
+        let (id, received) = server.recv_packet().unwrap();
+        assert_eq!(received, msg);
+
+        // The server's ACK and reply come back compact too
+        server.send_to(&id, &msg).unwrap();
+        for expected in [PacketType::Ack, PacketType::Data] {
+            let (n, _) = raw.recv_from(&mut buf).unwrap();
+            assert!(n < PACKET_HEADER_SIZE + msg.to_vec().len());
+            assert_eq!(pm.decode(&buf[..n]).unwrap().packet_type, expected);
+        }
+    }
+
+    #[test]
+    fn test_compact_headers_refused_without_negotiation() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 64];
+
+        let mut connect = UdpPacket::connect();
+        connect.flags |= FLAG_COMPACT_HEADERS;
+        raw.send_to(&connect.to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let ack = UdpPacket::from_bytes(&buf[..n]).unwrap();
+        assert_eq!(ack.flags & FLAG_COMPACT_HEADERS, 0);
+
+        // The server declined, so a client sending compact headers anyway is ignored
+        let mut pm = PacketManager::new();
+        pm.set_session(ack.session_id().unwrap());
+        pm.set_compact_headers(true);
+        let msg = crate::biwi_msg! { 1 => 7 };
+        let packet = pm.create_packets_with_mode(&msg.to_vec(), SendMode::Unreliable).remove(0);
+        raw.send_to(&pm.encode(&packet), addr).unwrap();
+        assert!(server.recv_packet().is_none());
+        raw.send_to(&packet.to_bytes(), addr).unwrap();
+        assert_eq!(server.recv_packet().unwrap().1, msg);
+    }
+
+    #[test]
+    fn test_mtu_discovery_over_loopback() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *server_running.lock().unwrap() {
+                server.recv_packet();
+            }
+        });
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        client.discover_mtu(crate::mtu::DEFAULT_MAX_PROBE_SIZE);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while client.mtu() < crate::mtu::DEFAULT_MAX_PROBE_SIZE - 8 && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Loopback carries far more than an Ethernet frame
+        assert!(client.mtu() > crate::network::MAX_PACKET_SIZE);
+        assert!(client.mtu() <= crate::mtu::DEFAULT_MAX_PROBE_SIZE);
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_ping_rtt_over_loopback() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *server_running.lock().unwrap() {
+                server.recv_packet();
+            }
+        });
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        assert_eq!(client.last_rtt(), None);
+        let rtt = client.ping_rtt(Duration::from_secs(2)).unwrap();
+        assert!(rtt < Duration::from_secs(1));
+        assert_eq!(client.last_rtt(), Some(rtt));
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_outlives_liveness_timeout() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_connection_timeout(Duration::from_millis(300));
+        let addr = server.socket.local_addr().unwrap();
+        let timed_out = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&timed_out);
+        server.on_timeout(move |id| record.lock().unwrap().push(id.clone()));
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *server_running.lock().unwrap() {
+                server.recv_packet();
+            }
+        });
+
+        let alive = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        alive.set_keep_alive(Some(Duration::from_millis(50)));
+        let silent = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        silent.set_keep_alive(None);
+        thread::sleep(Duration::from_millis(800));
+
+        assert_eq!(*timed_out.lock().unwrap(), vec![session_key(silent.session_id())]);
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_reconnects_to_restarted_server() {
+        let serve = |mut server: BiWiUdpServer, running: Arc<Mutex<bool>>| {
+            thread::spawn(move || {
+                while *running.lock().unwrap() {
+                    if let Some((id, msg)) = server.recv_packet() {
+                        let _ = server.send_to(&id, &msg);
+                    }
+                }
+            })
+        };
+        let server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let running = Arc::new(Mutex::new(true));
+        let first = serve(server, Arc::clone(&running));
+
+        let config = crate::client::ClientConfig {
+            auto_reconnect: true,
+            backoff: Duration::from_millis(50),
+            timeout: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap().with_config(config);
+        client.set_keep_alive(Some(Duration::from_millis(50)));
+        let old_session = client.session_id();
+
+        // The server goes away; messages sent while the client notices are held
+        *running.lock().unwrap() = false;
+        first.join().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !client.events().any(|event| matches!(event, crate::client::ClientEvent::Reconnecting { .. })) {
+            assert!(Instant::now() < deadline, "client never noticed the server was gone");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let msg = BiWiMessage::builder().field(1, "still there?").build();
+        client.send(&msg).unwrap();
+
+        *running.lock().unwrap() = true;
+        let second = serve(BiWiUdpServer::new("127.0.0.1", addr.port()).unwrap(), Arc::clone(&running));
+        assert_eq!(client.recv_timeout(Duration::from_secs(10)).unwrap(), msg);
+        assert_ne!(client.session_id(), old_session);
+        assert!(client
+            .events()
+            .any(|event| event == crate::client::ClientEvent::Reconnected { session_id: client.session_id() }));
+
+        *running.lock().unwrap() = false;
+        second.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_events() {
+        use crate::client::ClientEvent;
+
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || BiWiUdpClient::connect(&addr.to_string()).unwrap());
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        let client = connecting.join().unwrap();
+        // Answers the ping sent on connect
+        server.recv_packet();
+
+        // The server stops reading, so nothing it is sent gets ACKed
+        let msg = BiWiMessage::builder().field(1, "unheard").build();
+        let receipt = client.send(&msg).unwrap();
+        let mut events = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !events.contains(&ClientEvent::RetransmitExhausted(msg.clone())) {
+            assert!(Instant::now() < deadline, "retransmits never ran out: {:?}", events);
+            events.extend(client.events());
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.disconnect(&session_key(client.session_id())).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        events.extend(client.events());
+
+        assert_eq!(events[0], ClientEvent::Connected { session_id: client.session_id() });
+        assert!(matches!(events[1], ClientEvent::RttUpdated(_)));
+        assert!(matches!(events[2], ClientEvent::PacketDropped(_)));
+        assert_eq!(events[3..], [ClientEvent::RetransmitExhausted(msg), ClientEvent::Disconnected(DisconnectReason::Closed)]);
+        assert!(!client.is_active());
+        assert_eq!(receipt.outcome(), Some(crate::receipt::SendOutcome::Dropped));
+    }
+
+    #[test]
+    fn test_client_send_backpressure() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || BiWiUdpClient::connect(&addr.to_string()).unwrap());
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        server.recv_packet();
+        let config = crate::client::ClientConfig { send_capacity: 2, high_watermark: 2, low_watermark: 0, ..Default::default() };
+        let client = connecting.join().unwrap().with_config(config);
+        let (tx, watermarks) = std::sync::mpsc::channel();
+        let high = tx.clone();
+        client.on_high_watermark(move |backlog| high.send(("high", backlog)).unwrap());
+        client.on_low_watermark(move |backlog| tx.send(("low", backlog)).unwrap());
+
+        // Nobody ACKs while the server isn't reading
+        let msg = BiWiMessage::builder().field(1, "queued").build();
+        client.send(&msg).unwrap();
+        client.try_send(&msg).unwrap();
+        assert_eq!(client.try_send(&msg).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(watermarks.try_recv().unwrap(), ("high", 2));
+
+        // A blocked send goes through once ACKs drain the queue
+        let running = Arc::new(Mutex::new(true));
+        let serving = Arc::clone(&running);
+        let server_thread = thread::spawn(move || {
+            while *serving.lock().unwrap() {
+                server.recv_packet();
+            }
+        });
+        let receipt = client.send(&msg).unwrap();
+        assert_eq!(watermarks.recv_timeout(Duration::from_secs(5)).unwrap(), ("low", 0));
+        assert_eq!(receipt.wait_timeout(Duration::from_secs(5)), Some(crate::receipt::SendOutcome::Delivered));
+
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_teardown() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let connecting = thread::spawn(move || {
+            (0..2).map(|_| BiWiUdpClient::connect(&addr.to_string()).unwrap()).collect::<Vec<_>>()
+        });
+        while !connecting.is_finished() {
+            server.recv_packet();
+        }
+        let mut clients = connecting.join().unwrap();
+
+        // Closed by the server: what was sent first is still received, then recv fails instead of blocking
+        let id = session_key(clients[0].session_id());
+        let msg = BiWiMessage::builder().field(1, "last words").build();
+        server.send_to(&id, &msg).unwrap();
+        server.disconnect(&id).unwrap();
+        assert_eq!(clients[0].recv().unwrap(), msg);
+        assert_eq!(clients[0].recv().unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        assert!(!clients[0].is_active());
+
+        // Closed locally: the receive thread is woken and joined straight away
+        let started = Instant::now();
+        clients[1].disconnect();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(clients[1].recv_timeout(Duration::from_secs(5)).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn test_poll_and_tick() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+            let mut msg = BiWiMessage::new();
+            msg.set_field(1, crate::encoder::BiWiValue::from("frame"));
+            client.send(&msg).unwrap();
+            client
+        });
+
+        // A game loop: poll and tick each frame until the message shows up
+        let mut events = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !events.iter().any(|e| matches!(e, ServerEvent::Message(..))) && Instant::now() < deadline {
+            events.extend(server.poll(1));
+            server.tick(Instant::now());
+            thread::sleep(Duration::from_millis(1));
+        }
+        let client = client.join().unwrap();
+        let id = session_key(client.session_id());
+        assert_eq!(events[0], ServerEvent::ClientConnected(id.clone()));
+        assert!(matches!(&events[1], ServerEvent::Message(from, msg) if *from == id && msg.get_str(1) == Some("frame")));
+
+        server.ping(&id).unwrap();
+        let mut rtt = None;
+        while rtt.is_none() && Instant::now() < deadline {
+            rtt = server.poll(8).into_iter().find_map(|e| match e {
+                ServerEvent::PingResult(from, rtt) if from == id => Some(rtt),
+                _ => None,
+            });
+        }
+        assert!(rtt.is_some());
+
+        // Deadlines follow the clock passed to tick, not the wall clock
+        server.tick(Instant::now() + CONNECTION_TIMEOUT);
+        assert_eq!(server.poll(8), vec![ServerEvent::ClientTimedOut(id)]);
+    }
+
+    #[cfg(all(feature = "mio", unix))]
+    #[test]
+    fn test_driven_by_mio() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        let mut poll = mio::Poll::new().unwrap();
+        poll.registry().register(&mut server, mio::Token(0), mio::Interest::READABLE).unwrap();
+
+        let client = thread::spawn(move || {
+            let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+            let mut msg = BiWiMessage::new();
+            msg.set_field(1, crate::encoder::BiWiValue::from("readable"));
+            client.send(&msg).unwrap();
+            client
+        });
+
+        let mut mio_events = mio::Events::with_capacity(8);
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !received.iter().any(|e| matches!(e, ServerEvent::Message(..))) && Instant::now() < deadline {
+            let timeout = server.next_tick().saturating_duration_since(Instant::now());
+            poll.poll(&mut mio_events, Some(timeout)).unwrap();
+            if !mio_events.is_empty() {
+                received.extend(server.handle_readable());
+            }
+            if Instant::now() >= server.next_tick() {
+                server.tick(Instant::now());
+            }
+        }
+        let id = session_key(client.join().unwrap().session_id());
+        assert_eq!(received[0], ServerEvent::ClientConnected(id));
+        assert!(matches!(&received[1], ServerEvent::Message(_, msg) if msg.get_str(1) == Some("readable")));
+    }
+
+    #[test]
+    fn test_delayed_acks_are_batched() {
+        let config = ServerConfig::default().with_max_ack_delay(Duration::from_millis(20));
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap().with_config(config);
+        let addr = server.socket.local_addr().unwrap();
+        let raw = UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 64];
+
+        raw.send_to(&UdpPacket::connect().to_bytes(), addr).unwrap();
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let mut pm = PacketManager::new();
+        pm.set_session(UdpPacket::from_bytes(&buf[..n]).unwrap().session_id().unwrap());
+
+        let msg = crate::biwi_msg! { 1 => 7 };
+        for _ in 0..3 {
+            let packet = pm.create_packets(&msg.to_vec()).remove(0);
+            raw.send_to(&pm.encode(&packet), addr).unwrap();
+            assert_eq!(server.recv_packet().unwrap().1, msg);
+        }
+
+        // One ACK for all three, once the delay runs out
+        server.recv_packet();
+        let (n, _) = raw.recv_from(&mut buf).unwrap();
+        let ack = UdpPacket::from_bytes(&buf[..n]).unwrap();
+        assert_eq!((ack.packet_type, ack.ack_number), (PacketType::Ack, 2));
+        assert!(pm.handle_ack_packet(&ack));
+        assert_eq!(pm.pending_ack_count(), 0);
+        raw.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        server.recv_packet();
+        assert!(raw.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_coalesced_messages_share_a_datagram() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let mut received = Vec::new();
+            let deadline = std::time::Instant::now() + Duration::from_secs(10);
+            while received.len() < 3 && std::time::Instant::now() < deadline {
+                received.extend(server.recv_packet().map(|(_, msg)| msg));
+            }
+            received
+        });
+
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        client.set_coalesce_delay(Duration::from_secs(60)).unwrap();
+        let messages: Vec<BiWiMessage> = (0..3).map(|i| BiWiMessage::builder().field(1, i).build()).collect();
+        for msg in &messages {
+            client.send_coalesced(msg, SendMode::Unreliable).unwrap();
+        }
+        client.flush().unwrap();
+
+        assert_eq!(server_thread.join().unwrap(), messages);
+    }
+
+    #[test]
+    fn test_stream_from_client() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&updates);
+        server.on_stream(move |_, update| {
+            sink.lock().unwrap().push((update.data.to_vec(), update.total, update.complete));
+        });
+
+        let server_thread = thread::spawn(move || {
+            let deadline = std::time::Instant::now() + Duration::from_secs(10);
+            while std::time::Instant::now() < deadline {
+                server.recv_packet();
+                if updates.lock().unwrap().last().is_some_and(|u| u.2) {
+                    break;
+                }
+            }
+            let updates = updates.lock().unwrap().clone();
+            updates
+        });
+
+        let payload: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let mut client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        client.set_stream_window(4);
+        client.send_stream(7, payload.len() as u64, &payload[..]).unwrap();
+
+        let updates = server_thread.join().unwrap();
+        let (data, total, complete) = updates.last().unwrap();
+        assert!(*complete);
+        assert_eq!(*total, payload.len() as u64);
+        assert_eq!(data, &payload);
+        // Partial updates only ever grow
+        assert!(updates.windows(2).all(|w| w[0].0.len() <= w[1].0.len()));
+        assert!(updates.len() > 2);
+    }
+
+    #[test]
+    fn test_stream_fails_when_frames_run_out_of_retries() {
+        let mut server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+
+        // Answer the handshake, then stop reading so nothing is ACKed
+        let server_thread = thread::spawn(move || {
+            let deadline = std::time::Instant::now() + Duration::from_millis(500);
+            while std::time::Instant::now() < deadline {
+                server.recv_packet();
+            }
+            server
+        });
+        let client = BiWiUdpClient::connect(&addr.to_string()).unwrap();
+        let _server = server_thread.join().unwrap();
+
+        client.set_retransmit_policy(RetransmitPolicy { max_retries: 1, ..RetransmitPolicy::default() });
+        let payload = [7u8; 100];
+        let err = client.send_stream(7, payload.len() as u64, &payload[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
     }
 }
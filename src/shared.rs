@@ -0,0 +1,92 @@
+//! BiWi Shared Messages
+//! Immutable, reference-counted messages that are encoded exactly once.
+//! Cloning a `SharedMessage` is a pointer copy, so one broadcast payload can be
+//! handed to every connection (or thread) without re-encoding.
+
+use crate::message::BiWiMessage;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Immutable message with its encoded bytes, shared via `Arc`
+#[derive(Clone)]
+pub struct SharedMessage(Arc<SharedInner>);
+
+struct SharedInner {
+    message: BiWiMessage,
+    encoded: Vec<u8>,
+}
+
+impl SharedMessage {
+    /// Freeze a message, encoding it once
+    pub fn new(message: BiWiMessage) -> Self {
+        let encoded = message.to_vec();
+        Self(Arc::new(SharedInner { message, encoded }))
+    }
+
+    /// The frozen message
+    pub fn message(&self) -> &BiWiMessage {
+        &self.0.message
+    }
+
+    /// The encoded bytes, identical for every holder
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0.encoded
+    }
+
+    /// Encoded size in bytes
+    pub fn len(&self) -> usize {
+        self.0.encoded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.encoded.is_empty()
+    }
+
+    /// Check whether two handles share the same allocation
+    pub fn ptr_eq(&self, other: &SharedMessage) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for SharedMessage {
+    type Target = BiWiMessage;
+
+    fn deref(&self) -> &BiWiMessage {
+        &self.0.message
+    }
+}
+
+impl From<BiWiMessage> for SharedMessage {
+    fn from(message: BiWiMessage) -> Self {
+        Self::new(message)
+    }
+}
+
+impl std::fmt::Debug for SharedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedMessage")
+            .field("message", &self.0.message)
+            .field("len", &self.0.encoded.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_message_encodes_once() {
+        let msg = crate::biwi_msg! { 1 => "tick", 2 => 42 };
+        let shared = SharedMessage::new(msg.clone());
+        let copy = shared.clone();
+
+        assert!(shared.ptr_eq(&copy));
+        assert_eq!(copy.as_bytes(), msg.to_vec().as_slice());
+        assert_eq!(copy.as_bytes().as_ptr(), shared.as_bytes().as_ptr());
+        assert_eq!(copy.get_str(1), Some("tick"));
+
+        let handle = std::thread::spawn(move || copy.len());
+        assert_eq!(handle.join().unwrap(), shared.len());
+    }
+}
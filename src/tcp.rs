@@ -0,0 +1,406 @@
+//! BiWi TCP Transport
+//! Reliable, ordered transport for services that don't need UDP's latency profile.
+//! Every message is framed as a big-endian u32 length followed by the encoded message;
+//! each connection gets its own thread running the read/decode loop.
+
+use crate::message::BiWiMessage;
+use crate::server::ConnectionId;
+use crate::transport::{BiWiTransport, StatsCounters, TransportStats};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Frames larger than this are rejected and the connection is closed
+pub const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// Write one length-prefixed frame
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large"))?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Read one length-prefixed frame into `buf`
+pub fn read_frame(reader: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<()> {
+    let len = read_frame_len(reader)?;
+    buf.resize(len, 0);
+    reader.read_exact(buf)
+}
+
+fn read_frame_len(reader: &mut impl Read) -> io::Result<usize> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf)?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+    }
+    Ok(len)
+}
+
+/// Read one length-prefixed frame into `buf` and split it off as a shared buffer,
+/// ready for `BiWiMessage::from_shared`. `buf` keeps any spare capacity for the next frame.
+#[cfg(feature = "bytes")]
+pub fn read_frame_bytes(reader: &mut impl Read, buf: &mut bytes::BytesMut) -> io::Result<bytes::Bytes> {
+    let len = read_frame_len(reader)?;
+    buf.clear();
+    buf.resize(len, 0);
+    reader.read_exact(buf)?;
+    Ok(buf.split().freeze())
+}
+
+/// Connection lifecycle and message events from `BiWiTcpServer`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TcpEvent {
+    Connected(ConnectionId),
+    Message(ConnectionId, BiWiMessage),
+    Disconnected(ConnectionId),
+}
+
+/// BiWi TCP Server
+pub struct BiWiTcpServer {
+    local_addr: SocketAddr,
+    connections: Arc<Mutex<HashMap<ConnectionId, TcpStream>>>,
+    events: Receiver<TcpEvent>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl BiWiTcpServer {
+    /// Bind to `addr` and start accepting connections
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        event!(INFO, addr = %local_addr, "BiWi TCP server listening");
+
+        let (tx, rx) = channel();
+        let server = BiWiTcpServer {
+            local_addr,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            events: rx,
+            running: Arc::new(Mutex::new(true)),
+        };
+
+        let connections = Arc::clone(&server.connections);
+        let running = Arc::clone(&server.running);
+        thread::spawn(move || {
+            while *running.lock().unwrap() {
+                match listener.accept() {
+                    Ok((stream, addr)) => {
+                        if let Err(error) = accept_connection(stream, addr, &connections, &tx) {
+                            event!(DEBUG, peer = %addr, %error, "failed to set up TCP connection");
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(_) => {}
+                }
+            }
+        });
+
+        Ok(server)
+    }
+
+    /// Address the server is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Next event (blocking); `None` once the server has shut down
+    pub fn recv(&self) -> Option<TcpEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Next event (non-blocking)
+    pub fn try_recv(&self) -> Option<TcpEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Next event, waiting at most `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<TcpEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Send a message to a specific client
+    pub fn send_to(&self, client_id: &str, message: &BiWiMessage) -> io::Result<()> {
+        let mut conns = self.connections.lock().unwrap();
+        let stream = conns
+            .get_mut(client_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not found"))?;
+        write_frame(stream, &message.to_vec())
+    }
+
+    /// Broadcast a message to all connected clients (encoded once)
+    pub fn broadcast(&self, message: &BiWiMessage) -> io::Result<()> {
+        let msg_bytes = message.to_vec();
+        let mut conns = self.connections.lock().unwrap();
+        for stream in conns.values_mut() {
+            write_frame(stream, &msg_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Close a client's connection; its `Disconnected` event follows
+    pub fn disconnect(&self, client_id: &str) {
+        if let Some(stream) = self.connections.lock().unwrap().remove(client_id) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Get all connected clients
+    pub fn get_connections(&self) -> Vec<ConnectionId> {
+        self.connections.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Stop accepting and close every connection
+    pub fn shutdown(&self) {
+        *self.running.lock().unwrap() = false;
+        for (_, stream) in self.connections.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for BiWiTcpServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    connections: &Arc<Mutex<HashMap<ConnectionId, TcpStream>>>,
+    tx: &Sender<TcpEvent>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let mut reader = stream.try_clone()?;
+
+    let client_id = addr.to_string();
+    connections.lock().unwrap().insert(client_id.clone(), stream);
+    let _ = tx.send(TcpEvent::Connected(client_id.clone()));
+
+    let connections = Arc::clone(connections);
+    let tx = tx.clone();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        while read_frame(&mut reader, &mut buf).is_ok() {
+            match BiWiMessage::from_buffer(&buf) {
+                Ok(msg) => {
+                    let _ = tx.send(TcpEvent::Message(client_id.clone(), msg));
+                }
+                // A stream can't resync after a bad frame
+                Err(_) => break,
+            }
+        }
+
+        if let Some(stream) = connections.lock().unwrap().remove(&client_id) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let _ = tx.send(TcpEvent::Disconnected(client_id));
+    });
+    Ok(())
+}
+
+/// BiWi TCP Client
+pub struct BiWiTcpClient {
+    stream: Mutex<TcpStream>,
+    peer: SocketAddr,
+    message_rx: Receiver<BiWiMessage>,
+    running: Arc<Mutex<bool>>,
+    stats: Arc<StatsCounters>,
+}
+
+impl BiWiTcpClient {
+    /// Connect to a server
+    pub fn connect(server_addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(server_addr)?;
+        stream.set_nodelay(true)?;
+        let peer = stream.peer_addr()?;
+        let mut reader = stream.try_clone()?;
+
+        let (tx, rx) = channel();
+        let running = Arc::new(Mutex::new(true));
+        let stats = Arc::new(StatsCounters::default());
+
+        let reader_running = Arc::clone(&running);
+        let reader_stats = Arc::clone(&stats);
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            while read_frame(&mut reader, &mut buf).is_ok() {
+                let Ok(msg) = BiWiMessage::from_buffer(&buf) else {
+                    break;
+                };
+                reader_stats.record_received(buf.len());
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+            *reader_running.lock().unwrap() = false;
+        });
+
+        Ok(BiWiTcpClient {
+            stream: Mutex::new(stream),
+            peer,
+            message_rx: rx,
+            running,
+            stats,
+        })
+    }
+
+    /// Send a message to the server
+    pub fn send(&self, message: &BiWiMessage) -> io::Result<()> {
+        let msg_bytes = message.to_vec();
+        write_frame(&mut *self.stream.lock().unwrap(), &msg_bytes)?;
+        self.stats.record_sent(msg_bytes.len());
+        Ok(())
+    }
+
+    /// Try to receive a message (non-blocking)
+    pub fn try_recv(&self) -> Option<BiWiMessage> {
+        self.message_rx.try_recv().ok()
+    }
+
+    /// Receive a message (blocking)
+    pub fn recv(&self) -> io::Result<BiWiMessage> {
+        self.message_rx
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionReset, "Connection closed"))
+    }
+
+    /// Receive with timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<BiWiMessage> {
+        self.message_rx
+            .recv_timeout(timeout)
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Recv timeout"))
+    }
+
+    /// Check if the connection is still open
+    pub fn is_active(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    /// Close the connection
+    pub fn disconnect(&mut self) {
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+        *self.running.lock().unwrap() = false;
+    }
+}
+
+impl BiWiTransport for BiWiTcpClient {
+    fn send_message(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.send(message)
+    }
+
+    fn recv_message(&self, timeout: Option<Duration>) -> io::Result<BiWiMessage> {
+        match timeout {
+            Some(timeout) => self.recv_timeout(timeout),
+            None => self.recv(),
+        }
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.stats.snapshot()
+    }
+}
+
+impl Drop for BiWiTcpClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::BiWiValue;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_frame_roundtrip_and_limit() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, b"abc").unwrap();
+        assert_eq!(wire, [0, 0, 0, 3, b'a', b'b', b'c']);
+
+        let mut buf = Vec::new();
+        read_frame(&mut &wire[..], &mut buf).unwrap();
+        assert_eq!(buf, b"abc");
+
+        let oversized = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes();
+        assert!(read_frame(&mut &oversized[..], &mut buf).is_err());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_shared_frames_decode_without_copying() {
+        let blob = bytes::Bytes::from(vec![7u8; 4096]);
+        let mut msg = BiWiMessage::new();
+        msg.set_field(1, BiWiValue::from(blob.clone()));
+        msg.set_field(2, BiWiValue::from("name"));
+        // Encodes exactly like the copied form
+        let mut copied = BiWiMessage::new();
+        copied.set_field(1, BiWiValue::Binary(blob.to_vec()));
+        copied.set_field(2, BiWiValue::from("name"));
+        assert_eq!(msg.to_vec(), copied.to_vec());
+
+        let mut wire = Vec::new();
+        write_frame(&mut wire, &msg.to_vec()).unwrap();
+        write_frame(&mut wire, &copied.to_vec()).unwrap();
+        let mut reader = &wire[..];
+        let mut buf = bytes::BytesMut::new();
+        let frame = read_frame_bytes(&mut reader, &mut buf).unwrap();
+        let decoded = BiWiMessage::from_shared(&frame).unwrap();
+
+        // The blob points into the frame buffer
+        let Some(BiWiValue::Bytes(data)) = decoded.get_field(1) else {
+            panic!("expected a shared Bytes value");
+        };
+        assert_eq!(data, &blob);
+        let frame_range = frame.as_ptr() as usize..frame.as_ptr() as usize + frame.len();
+        assert!(frame_range.contains(&(data.as_ptr() as usize)));
+        assert_eq!(decoded.get_bytes(1), Some(&blob[..]));
+
+        let second = read_frame_bytes(&mut reader, &mut buf).unwrap();
+        assert_eq!(BiWiMessage::from_buffer(&second).unwrap(), copied);
+    }
+
+    #[test]
+    fn test_tcp_echo_and_lifecycle() {
+        let server = BiWiTcpServer::bind("127.0.0.1:0").unwrap();
+        let mut client = BiWiTcpClient::connect(&server.local_addr().to_string()).unwrap();
+
+        let Some(TcpEvent::Connected(id)) = server.recv_timeout(TIMEOUT) else {
+            panic!("expected connect event");
+        };
+
+        let msg = BiWiMessage::builder()
+            .field(1, "hello")
+            .field(2, BiWiValue::Binary(vec![9; 100_000]))
+            .build();
+        client.send(&msg).unwrap();
+        assert_eq!(server.recv_timeout(TIMEOUT), Some(TcpEvent::Message(id.clone(), msg.clone())));
+
+        server.send_to(&id, &msg).unwrap();
+        assert_eq!(client.recv_timeout(TIMEOUT).unwrap(), msg);
+
+        client.disconnect();
+        assert_eq!(server.recv_timeout(TIMEOUT), Some(TcpEvent::Disconnected(id)));
+        assert!(server.get_connections().is_empty());
+    }
+}
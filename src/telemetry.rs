@@ -0,0 +1,170 @@
+//! BiWi Metrics
+//! With the `metrics` feature, messages, packets and connection health are reported
+//! through the `metrics` facade, so whichever recorder the application installs
+//! (Prometheus, StatsD, ...) picks them up. Without the feature the hooks compile
+//! to nothing.
+//!
+//! Message counters come from `BiWiMessage` encoding and decoding; packet, byte,
+//! retransmission and RTT metrics from the `PacketManager` behind every client and
+//! server connection.
+
+/// Counter: messages encoded
+pub const MESSAGES_ENCODED: &str = "biwi_messages_encoded_total";
+/// Counter: bytes of encoded messages
+pub const ENCODED_BYTES: &str = "biwi_encoded_bytes_total";
+/// Counter: messages decoded
+pub const MESSAGES_DECODED: &str = "biwi_messages_decoded_total";
+/// Counter: bytes of decoded messages
+pub const DECODED_BYTES: &str = "biwi_decoded_bytes_total";
+/// Counter: buffers that failed to decode
+pub const DECODE_ERRORS: &str = "biwi_decode_errors_total";
+/// Counter: packets sent, retransmissions included
+pub const PACKETS_SENT: &str = "biwi_packets_sent_total";
+/// Counter: bytes of packets sent
+pub const PACKET_BYTES_SENT: &str = "biwi_packet_bytes_sent_total";
+/// Counter: packets received and authenticated
+pub const PACKETS_RECEIVED: &str = "biwi_packets_received_total";
+/// Counter: bytes of packets received
+pub const PACKET_BYTES_RECEIVED: &str = "biwi_packet_bytes_received_total";
+/// Counter: packets dropped for failing authentication
+pub const AUTH_FAILURES: &str = "biwi_auth_failures_total";
+/// Counter: reliable packets resent
+pub const RETRANSMISSIONS: &str = "biwi_retransmissions_total";
+/// Histogram: ACK round-trip times, in seconds
+pub const RTT_SECONDS: &str = "biwi_rtt_seconds";
+
+#[cfg(feature = "metrics")]
+mod emit {
+    use super::*;
+    use metrics::{counter, histogram};
+    use std::time::Duration;
+
+    pub(crate) fn message_encoded(bytes: usize) {
+        counter!(MESSAGES_ENCODED).increment(1);
+        counter!(ENCODED_BYTES).increment(bytes as u64);
+    }
+
+    pub(crate) fn message_decoded(bytes: usize) {
+        counter!(MESSAGES_DECODED).increment(1);
+        counter!(DECODED_BYTES).increment(bytes as u64);
+    }
+
+    pub(crate) fn decode_error() {
+        counter!(DECODE_ERRORS).increment(1);
+    }
+
+    pub(crate) fn packet_sent(bytes: usize) {
+        counter!(PACKETS_SENT).increment(1);
+        counter!(PACKET_BYTES_SENT).increment(bytes as u64);
+    }
+
+    pub(crate) fn packet_received(bytes: usize) {
+        counter!(PACKETS_RECEIVED).increment(1);
+        counter!(PACKET_BYTES_RECEIVED).increment(bytes as u64);
+    }
+
+    pub(crate) fn auth_failure() {
+        counter!(AUTH_FAILURES).increment(1);
+    }
+
+    pub(crate) fn retransmissions(count: usize) {
+        counter!(RETRANSMISSIONS).increment(count as u64);
+    }
+
+    pub(crate) fn rtt(rtt: Duration) {
+        histogram!(RTT_SECONDS).record(rtt.as_secs_f64());
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod emit {
+    use std::time::Duration;
+
+    pub(crate) fn message_encoded(_bytes: usize) {}
+    pub(crate) fn message_decoded(_bytes: usize) {}
+    pub(crate) fn decode_error() {}
+    pub(crate) fn packet_sent(_bytes: usize) {}
+    pub(crate) fn packet_received(_bytes: usize) {}
+    pub(crate) fn auth_failure() {}
+    pub(crate) fn retransmissions(_count: usize) {}
+    pub(crate) fn rtt(_rtt: Duration) {}
+}
+
+pub(crate) use emit::*;
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use crate::message::BiWiMessage;
+    use crate::network::{PacketManager, UdpPacket};
+    use metrics::{Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<HashMap<String, Arc<Samples>>>,
+    }
+
+    impl TestRecorder {
+        fn counter(&self, name: &str) -> u64 {
+            self.counters.lock().unwrap().get(name).map_or(0, |c| c.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            Counter::from_arc(Arc::clone(counters.entry(key.name().to_string()).or_default()))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            let mut histograms = self.histograms.lock().unwrap();
+            Histogram::from_arc(Arc::clone(histograms.entry(key.name().to_string()).or_default()))
+        }
+    }
+
+    #[test]
+    fn test_hooks_reach_the_recorder() {
+        let recorder = TestRecorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            let bytes = BiWiMessage::builder().field(1, "hi").build().to_vec();
+            BiWiMessage::from_buffer(&bytes).unwrap();
+            assert!(BiWiMessage::from_buffer(&bytes[..bytes.len() - 1]).is_err());
+
+            let mut pm = PacketManager::new();
+            let mut packet = UdpPacket::connect();
+            let wire = pm.encode(&packet);
+            assert!(pm.open(&mut packet));
+            pm.record_rtt_sample(Duration::from_millis(20));
+
+            assert_eq!(recorder.counter(MESSAGES_ENCODED), 1);
+            assert_eq!(recorder.counter(ENCODED_BYTES), bytes.len() as u64);
+            assert_eq!(recorder.counter(MESSAGES_DECODED), 1);
+            assert_eq!(recorder.counter(DECODE_ERRORS), 1);
+            assert_eq!(recorder.counter(PACKET_BYTES_SENT), wire.len() as u64);
+            assert_eq!(recorder.counter(PACKETS_RECEIVED), 1);
+            assert_eq!(*recorder.histograms.lock().unwrap()[RTT_SECONDS].0.lock().unwrap(), [0.02]);
+        });
+    }
+}
@@ -0,0 +1,455 @@
+//! BiWi Testing Support
+//! Proptest strategies for `BiWiValue`/`BiWiMessage` and roundtrip helpers, plus
+//! `SimulatedNetwork`, an in-memory link with configurable loss, latency, jitter,
+//! duplication and reordering for exercising retransmission and reassembly.
+//! Enable the `testing` feature to fuzz your own schema mappings against the codec.
+
+use crate::decoder::{BiWiDecoder, DecodeResult};
+use crate::encoder::{BiWiEncoder, BiWiValue, SmallString};
+use crate::message::BiWiMessage;
+use crate::network::{ConnectionStats, PacketManager, PacketType, SendMode, UdpPacket, DEFAULT_CHANNEL};
+use crate::transport::{BiWiTransport, StatsCounters, TransportStats};
+use proptest::collection::{hash_map, vec};
+use proptest::prelude::*;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Strategy for leaf (non-container) values. NaN is excluded because it
+/// never compares equal, which would make roundtrip assertions meaningless.
+pub fn arb_leaf() -> impl Strategy<Value = BiWiValue> {
+    prop_oneof![
+        Just(BiWiValue::Null),
+        any::<bool>().prop_map(BiWiValue::Boolean),
+        any::<i32>().prop_map(BiWiValue::Int32),
+        any::<i64>().prop_map(BiWiValue::Int64),
+        any::<f32>().prop_filter("NaN", |f| !f.is_nan()).prop_map(BiWiValue::Float32),
+        any::<f64>().prop_filter("NaN", |f| !f.is_nan()).prop_map(BiWiValue::Float64),
+        "\\PC{0,5}".prop_filter_map("too long", |s| SmallString::new(&s).map(BiWiValue::SmallString)),
+        "\\PC{0,200}".prop_map(BiWiValue::String),
+        vec(any::<u8>(), 0..256).prop_map(BiWiValue::Binary),
+    ]
+}
+
+/// Strategy for arbitrary values, nesting arrays and objects up to `depth` levels
+pub fn arb_value_with_depth(depth: u32) -> impl Strategy<Value = BiWiValue> {
+    arb_leaf().prop_recursive(depth, 64, 8, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..8).prop_map(BiWiValue::Array),
+            hash_map("\\PC{0,12}", inner, 0..8).prop_map(BiWiValue::Object),
+        ]
+    })
+}
+
+/// Strategy for arbitrary values (up to 4 levels of nesting)
+pub fn arb_value() -> impl Strategy<Value = BiWiValue> {
+    arb_value_with_depth(4)
+}
+
+/// Strategy for messages with up to `max_fields` fields across the full u32 ID range
+pub fn arb_message_with(max_fields: usize) -> impl Strategy<Value = BiWiMessage> {
+    hash_map(any::<u32>(), arb_value(), 0..=max_fields).prop_map(|fields| {
+        let mut message = BiWiMessage::with_capacity(fields.len());
+        for (id, value) in fields {
+            message.set_field(id, value);
+        }
+        message
+    })
+}
+
+/// Strategy for messages with up to 16 fields
+pub fn arb_message() -> impl Strategy<Value = BiWiMessage> {
+    arb_message_with(16)
+}
+
+impl Arbitrary for BiWiValue {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<BiWiValue>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        arb_value().boxed()
+    }
+}
+
+impl Arbitrary for BiWiMessage {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<BiWiMessage>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        arb_message().boxed()
+    }
+}
+
+/// Encode and decode a single value
+pub fn roundtrip_value(value: &BiWiValue) -> DecodeResult<BiWiValue> {
+    let mut encoder = BiWiEncoder::new();
+    encoder.encode_value(value);
+    BiWiDecoder::new(encoder.as_slice()).decode_value()
+}
+
+/// Encode and decode a message
+pub fn roundtrip_message(message: &BiWiMessage) -> DecodeResult<BiWiMessage> {
+    BiWiMessage::from_buffer(&message.to_vec())
+}
+
+/// Assert that a message survives encoding unchanged, for use inside `proptest!`
+pub fn assert_roundtrip(message: &BiWiMessage) -> Result<(), TestCaseError> {
+    let decoded = roundtrip_message(message).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(&decoded, message);
+    Ok(())
+}
+
+/// What a `SimulatedNetwork` does to each datagram, in both directions. The default
+/// is a perfect link that delivers everything at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Chance of a datagram being dropped, from 0.0 to 1.0
+    pub loss: f64,
+    /// One-way delay
+    pub latency: Duration,
+    /// Most a datagram's delay differs from `latency`, either way
+    pub jitter: Duration,
+    /// Chance of a datagram arriving twice
+    pub duplicate: f64,
+    /// Chance of a datagram being held back an extra `latency + jitter`, so the
+    /// ones sent after it overtake it
+    pub reorder: f64,
+}
+
+/// What the link has done to the datagrams crossing it so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Datagrams handed to the link, both ways
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// Datagrams that reached the other end, duplicates included
+    pub delivered: u64,
+}
+
+struct Datagram {
+    arrives: Instant,
+    to: usize,
+    bytes: Vec<u8>,
+}
+
+struct Side {
+    pm: PacketManager,
+    inbox: VecDeque<Vec<u8>>,
+}
+
+/// Both ends of a simulated link and the datagrams between them
+struct Link {
+    conditions: NetworkConditions,
+    /// xorshift state; every drop, duplicate, delay and reorder is drawn from it
+    rng: u64,
+    sides: [Side; 2],
+    in_flight: Vec<Datagram>,
+    stats: LinkStats,
+}
+
+impl Link {
+    /// Uniform in [0, 1)
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.random() < probability
+    }
+
+    /// Serialize packets from side `from` and put them on the wire
+    fn send(&mut self, from: usize, packets: Vec<UdpPacket>, now: Instant) {
+        for packet in packets {
+            let bytes = self.sides[from].pm.encode(&packet);
+            self.transmit(1 - from, bytes, now);
+        }
+    }
+
+    fn transmit(&mut self, to: usize, bytes: Vec<u8>, now: Instant) {
+        let conditions = self.conditions;
+        self.stats.sent += 1;
+        if self.chance(conditions.loss) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.chance(conditions.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let offset = conditions.jitter.mul_f64(self.random() * 2.0);
+            let mut delay = (conditions.latency + offset).saturating_sub(conditions.jitter);
+            if self.chance(conditions.reorder) {
+                self.stats.reordered += 1;
+                delay += conditions.latency + conditions.jitter;
+            }
+            self.in_flight.push(Datagram { arrives: now + delay, to, bytes: bytes.clone() });
+        }
+    }
+
+    /// Deliver every datagram due by `now`, earliest first, then retransmit and
+    /// release held packets on both sides the way a client's receive loop does
+    fn pump(&mut self, now: Instant) {
+        while let Some(next) = (0..self.in_flight.len())
+            .filter(|&i| self.in_flight[i].arrives <= now)
+            .min_by_key(|&i| self.in_flight[i].arrives)
+        {
+            let datagram = self.in_flight.remove(next);
+            self.arrive(datagram, now);
+        }
+        for side in 0..2 {
+            let retransmits = self.sides[side].pm.get_retransmit_packets_at(now);
+            self.send(side, retransmits.into_iter().map(|(packet, _)| packet).collect(), now);
+            let released = self.sides[side].pm.flush_reorder_at(now);
+            self.sides[side].collect(released);
+            let acks = self.sides[side].pm.take_due_acks_at(now);
+            self.send(side, acks, now);
+        }
+    }
+
+    fn arrive(&mut self, datagram: Datagram, now: Instant) {
+        self.stats.delivered += 1;
+        let to = datagram.to;
+        let side = &mut self.sides[to];
+        let Ok(mut packet) = side.pm.decode(&datagram.bytes) else {
+            return;
+        };
+        if !side.pm.open(&mut packet) {
+            return;
+        }
+        match packet.packet_type {
+            PacketType::Data => {
+                // ACK duplicates too, in case the first ACK was lost
+                let acks = if packet.send_mode().is_reliable() { side.pm.queue_ack_at(&packet, now) } else { Vec::new() };
+                let delivered = side.pm.deliver(packet);
+                side.collect(delivered);
+                self.send(to, acks, now);
+            }
+            PacketType::Ack => {
+                side.pm.handle_ack_packet(&packet);
+                let released = side.pm.release_paced();
+                self.send(to, released, now);
+            }
+            _ => {}
+        }
+    }
+
+    fn settled(&self) -> bool {
+        self.in_flight.is_empty() && self.sides.iter().all(|side| side.pm.backlog() == 0)
+    }
+}
+
+impl Side {
+    fn collect(&mut self, packets: Vec<UdpPacket>) {
+        for packet in packets {
+            self.inbox.extend(packet.messages().into_iter().map(<[u8]>::to_vec));
+        }
+    }
+}
+
+/// One end of an in-memory link between two `PacketManager`s. Messages are split
+/// into packets, ACKed, retransmitted, reordered and reassembled exactly as over
+/// UDP, but every datagram crosses a simulated link that applies
+/// `NetworkConditions`. Which datagrams are lost, duplicated, delayed or reordered
+/// is drawn from a seeded generator, so a seed that shows a bug can be rerun.
+///
+/// Nothing runs in the background: sending, receiving and `pump` move the link
+/// along, for both ends.
+pub struct SimulatedNetwork {
+    link: Arc<Mutex<Link>>,
+    side: usize,
+    stats: StatsCounters,
+}
+
+impl SimulatedNetwork {
+    /// Two connected ends over a link with `conditions`, each with a default `PacketManager`
+    pub fn pair(conditions: NetworkConditions, seed: u64) -> (Self, Self) {
+        Self::pair_with(conditions, seed, PacketManager::new(), PacketManager::new())
+    }
+
+    /// Two connected ends whose packets are handled by `a` and `b`, for testing other
+    /// retry limits, reorder windows, packet sizes or congestion controllers
+    pub fn pair_with(conditions: NetworkConditions, seed: u64, a: PacketManager, b: PacketManager) -> (Self, Self) {
+        let link = Arc::new(Mutex::new(Link {
+            conditions,
+            // xorshift never leaves zero
+            rng: seed ^ 0x9E37_79B9_7F4A_7C15,
+            sides: [a, b].map(|pm| Side { pm, inbox: VecDeque::new() }),
+            in_flight: Vec::new(),
+            stats: LinkStats::default(),
+        }));
+        let end = |side| Self { link: Arc::clone(&link), side, stats: StatsCounters::default() };
+        (end(0), end(1))
+    }
+
+    /// Change the link's conditions from now on; datagrams already in flight keep their delay
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        self.link.lock().unwrap().conditions = conditions;
+    }
+
+    pub fn send_with_mode(&self, message: &BiWiMessage, mode: SendMode) -> io::Result<()> {
+        let bytes = message.to_vec();
+        let mut link = self.link.lock().unwrap();
+        let pm = &mut link.sides[self.side].pm;
+        pm.check_message_size(bytes.len())?;
+        let packets = pm.create_packets_on(DEFAULT_CHANNEL, &bytes, mode);
+        let packets = pm.pace(packets);
+        link.send(self.side, packets, Instant::now());
+        self.stats.record_sent(bytes.len());
+        Ok(())
+    }
+
+    /// Deliver whatever is due and retransmit whatever has timed out, on both ends
+    pub fn pump(&self) {
+        self.link.lock().unwrap().pump(Instant::now());
+    }
+
+    /// Pump until nothing is in flight and neither end waits on an ACK, or until
+    /// `timeout`; false if the link didn't settle in time
+    pub fn settle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut link = self.link.lock().unwrap();
+            link.pump(Instant::now());
+            if link.settled() {
+                return true;
+            }
+            drop(link);
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Counters for the link, both directions
+    pub fn link_stats(&self) -> LinkStats {
+        self.link.lock().unwrap().stats
+    }
+
+    /// This end's packet counters, retransmissions and RTT estimate
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.link.lock().unwrap().sides[self.side].pm.stats()
+    }
+}
+
+impl BiWiTransport for SimulatedNetwork {
+    fn send_message(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.send_with_mode(message, SendMode::ReliableOrdered)
+    }
+
+    fn recv_message(&self, timeout: Option<Duration>) -> io::Result<BiWiMessage> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let mut link = self.link.lock().unwrap();
+            link.pump(Instant::now());
+            if let Some(bytes) = link.sides[self.side].inbox.pop_front() {
+                drop(link);
+                self.stats.record_received(bytes.len());
+                return BiWiMessage::from_buffer(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            drop(link);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "No message before the timeout"));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// A placeholder address for the other end
+    fn peer(&self) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 2 - self.side as u16))
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.stats.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ByteOrder;
+
+    #[test]
+    fn test_simulated_network_recovers_in_order() {
+        let conditions = NetworkConditions {
+            loss: 0.2,
+            latency: Duration::from_millis(2),
+            jitter: Duration::from_millis(1),
+            duplicate: 0.1,
+            reorder: 0.1,
+        };
+        let mut receiver = PacketManager::with_config(Duration::from_millis(20), 20);
+        receiver.set_reorder_window(Some(Duration::from_secs(5)));
+        let (a, b) = SimulatedNetwork::pair_with(conditions, 7, PacketManager::with_config(Duration::from_millis(20), 20), receiver);
+
+        let big = BiWiMessage::builder().field(2, BiWiValue::Binary(vec![7; 6000])).build();
+        for i in 0..20 {
+            a.send_message(&BiWiMessage::builder().field(1, BiWiValue::Int32(i)).build()).unwrap();
+        }
+        a.send_message(&big).unwrap();
+
+        for i in 0..20 {
+            assert_eq!(b.recv_message(Some(Duration::from_secs(5))).unwrap().get_i32(1), Some(i));
+        }
+        assert_eq!(b.recv_message(Some(Duration::from_secs(5))).unwrap(), big);
+        assert!(a.settle(Duration::from_secs(5)));
+
+        let link = a.link_stats();
+        assert!(link.dropped > 0 && link.duplicated > 0 && link.reordered > 0);
+        assert!(a.connection_stats().retransmissions > 0);
+        assert_eq!(b.stats().messages_received, 21);
+        assert!(b.recv_message(Some(Duration::from_millis(20))).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn value_roundtrip(value in any::<BiWiValue>()) {
+            prop_assert_eq!(roundtrip_value(&value).unwrap(), value);
+        }
+
+        #[test]
+        fn message_roundtrip(message in any::<BiWiMessage>()) {
+            assert_roundtrip(&message)?;
+        }
+
+        #[test]
+        fn sparse_roundtrip(message in arb_message_with(8)) {
+            // Keep IDs close together so the bitmap stays small
+            let mut dense = BiWiMessage::new();
+            for (id, value) in message.fields() {
+                dense.set_field(id % 512, value.clone());
+            }
+            let decoded = BiWiMessage::from_buffer_sparse(&dense.to_vec_sparse()).unwrap();
+            prop_assert_eq!(decoded, dense);
+        }
+
+        #[test]
+        fn little_endian_roundtrip(value in any::<BiWiValue>()) {
+            let mut encoder = BiWiEncoder::new().with_byte_order(ByteOrder::LittleEndian);
+            encoder.encode_value(&value);
+            let decoded = BiWiDecoder::new(encoder.as_slice())
+                .with_byte_order(ByteOrder::LittleEndian)
+                .decode_value()
+                .unwrap();
+            prop_assert_eq!(decoded, value);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in vec(any::<u8>(), 0..512)) {
+            let _ = BiWiMessage::from_buffer(&bytes);
+            let _ = BiWiMessage::from_buffer_sparse(&bytes);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! BiWi Pub/Sub Topics
+//! Clients subscribe to topics by name or by numeric ID with small control messages;
+//! the server records each connection's subscriptions and `publish` encodes a message
+//! once and fans it out to every subscriber. Sessions drop their subscriptions when
+//! they end.
+//!
+//! Control messages are ordinary messages whose envelope has the reserved type
+//! `SUBSCRIBE` or `UNSUBSCRIBE` and whose field 1 names the topic (a string, or an
+//! integer ID). The server consumes them; they never reach the application.
+
+use crate::encoder::BiWiValue;
+use crate::envelope::Envelope;
+use crate::message::BiWiMessage;
+use crate::server::ConnectionId;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Envelope message type of a subscribe request (reserved)
+pub const SUBSCRIBE: u16 = 0xFFFE;
+/// Envelope message type of an unsubscribe request (reserved)
+pub const UNSUBSCRIBE: u16 = 0xFFFF;
+
+/// A topic, named by string or by an ID agreed on out of band (cheaper on the wire)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Name(Arc<str>),
+    Id(u32),
+}
+
+impl From<&str> for Topic {
+    fn from(name: &str) -> Self {
+        Topic::Name(name.into())
+    }
+}
+
+impl From<String> for Topic {
+    fn from(name: String) -> Self {
+        Topic::Name(name.into())
+    }
+}
+
+impl From<u32> for Topic {
+    fn from(id: u32) -> Self {
+        Topic::Id(id)
+    }
+}
+
+impl Topic {
+    /// The control message that subscribes to (or unsubscribes from) this topic
+    pub(crate) fn control(&self, subscribe: bool) -> BiWiMessage {
+        let value = match self {
+            Topic::Name(name) => BiWiValue::from(&**name),
+            Topic::Id(id) => BiWiValue::Int64(*id as i64),
+        };
+        let message_type = if subscribe { SUBSCRIBE } else { UNSUBSCRIBE };
+        BiWiMessage::builder().field(1, value).build().with_envelope(Envelope::new(message_type))
+    }
+
+    /// Read a control message: the topic and whether it subscribes. `None` for any
+    /// other message.
+    pub(crate) fn parse_control(message: &BiWiMessage) -> Option<(Topic, bool)> {
+        let subscribe = match message.message_type()? {
+            SUBSCRIBE => true,
+            UNSUBSCRIBE => false,
+            _ => return None,
+        };
+        if let Some(name) = message.get_str(1) {
+            return Some((Topic::from(name), subscribe));
+        }
+        let topic = match message.get_field(1)? {
+            BiWiValue::Int32(id) => Topic::Id(u32::try_from(*id).ok()?),
+            BiWiValue::Int64(id) => Topic::Id(u32::try_from(*id).ok()?),
+            _ => return None,
+        };
+        Some((topic, subscribe))
+    }
+}
+
+/// A server's subscriptions, by topic
+#[derive(Clone, Default)]
+pub(crate) struct Topics(Arc<Mutex<HashMap<Topic, HashSet<ConnectionId>>>>);
+
+impl Topics {
+    /// Returns false if the connection was already subscribed
+    pub fn subscribe(&self, client_id: &str, topic: Topic) -> bool {
+        self.0.lock().unwrap().entry(topic).or_default().insert(client_id.to_string())
+    }
+
+    /// Returns false if the connection wasn't subscribed
+    pub fn unsubscribe(&self, client_id: &str, topic: &Topic) -> bool {
+        let mut topics = self.0.lock().unwrap();
+        let Some(subscribers) = topics.get_mut(topic) else {
+            return false;
+        };
+        let removed = subscribers.remove(client_id);
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        removed
+    }
+
+    /// Current subscribers of `topic`, in no particular order
+    pub fn subscribers(&self, topic: &Topic) -> Vec<ConnectionId> {
+        self.0.lock().unwrap().get(topic).map_or_else(Vec::new, |s| s.iter().cloned().collect())
+    }
+
+    /// Topics `client_id` is subscribed to, in no particular order
+    pub fn subscriptions(&self, client_id: &str) -> Vec<Topic> {
+        let topics = self.0.lock().unwrap();
+        topics.iter().filter(|(_, s)| s.contains(client_id)).map(|(topic, _)| topic.clone()).collect()
+    }
+
+    /// Drop every subscription of a closed session
+    pub fn leave_all(&self, client_id: &str) {
+        let mut topics = self.0.lock().unwrap();
+        topics.retain(|_, subscribers| {
+            subscribers.remove(client_id);
+            !subscribers.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_messages_and_subscriptions() {
+        for topic in [Topic::from("sensors/temp"), Topic::from(7u32)] {
+            for subscribe in [true, false] {
+                let decoded = BiWiMessage::from_buffer(&topic.control(subscribe).to_vec()).unwrap();
+                assert_eq!(Topic::parse_control(&decoded), Some((topic.clone(), subscribe)));
+            }
+        }
+        assert_eq!(Topic::parse_control(&BiWiMessage::builder().field(1, "x").build()), None);
+
+        let topics = Topics::default();
+        assert!(topics.subscribe("a", "chat".into()));
+        assert!(!topics.subscribe("a", "chat".into()));
+        topics.subscribe("b", "chat".into());
+        topics.subscribe("a", 3u32.into());
+        assert_eq!(topics.subscriptions("b"), vec![Topic::from("chat")]);
+
+        topics.leave_all("a");
+        assert_eq!(topics.subscribers(&"chat".into()), vec!["b".to_string()]);
+        assert!(topics.subscribers(&3u32.into()).is_empty());
+        assert!(topics.unsubscribe("b", &"chat".into()));
+        assert!(!topics.unsubscribe("b", &"chat".into()));
+    }
+}
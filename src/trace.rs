@@ -0,0 +1,152 @@
+//! Tracing hooks. With the `tracing` feature, `event!` and `span!` forward to the
+//! `tracing` crate; without it they only borrow the values they were given, so call
+//! sites read the same either way.
+//!
+//! Levels: `INFO` for sockets opening, `DEBUG` for session lifecycle, handshakes,
+//! retransmits and rejected input, `TRACE` for every datagram received.
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($args:tt)+) => {
+        ::tracing::event!(::tracing::Level::$level, $($args)+)
+    };
+}
+
+/// Borrow every value in a list of `tracing` fields, then the message's format
+/// arguments, without recording anything
+#[cfg(not(feature = "tracing"))]
+macro_rules! borrow_fields {
+    () => {};
+    ($message:literal $(, $arg:expr)* $(,)?) => {
+        let _ = ($(&$arg,)*);
+    };
+    ($name:ident = % $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        borrow_fields!($($($rest)*)?);
+    };
+    ($name:ident = ? $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        borrow_fields!($($($rest)*)?);
+    };
+    ($name:ident = $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        borrow_fields!($($($rest)*)?);
+    };
+    (% $name:ident $(, $($rest:tt)*)?) => {
+        let _ = &$name;
+        borrow_fields!($($($rest)*)?);
+    };
+    (? $name:ident $(, $($rest:tt)*)?) => {
+        let _ = &$name;
+        borrow_fields!($($($rest)*)?);
+    };
+    ($name:ident $(, $($rest:tt)*)?) => {
+        let _ = &$name;
+        borrow_fields!($($($rest)*)?);
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $($args:tt)+) => {{
+        borrow_fields!($($args)+);
+    }};
+}
+
+/// Enter a span for the rest of the enclosing scope: `let _span = span!(..);`
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($level:ident, $($args:tt)+) => {
+        ::tracing::span!(::tracing::Level::$level, $($args)+).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($level:ident, $name:literal $(, $($fields:tt)*)?) => {{
+        borrow_fields!($($($fields)*)?);
+        $crate::trace::NoSpan
+    }};
+}
+
+/// Stands in for an entered span when tracing is off
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::client::BiWiUdpClient;
+    use crate::server::BiWiUdpServer;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tracing::dispatcher::{self, Dispatch};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Keeps the name of every span and the message of every event, in order
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.0.lock().unwrap().push(span.metadata().name().to_string());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            event.record(&mut MessageVisitor(&self.0));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    struct MessageVisitor<'a>(&'a Mutex<Vec<String>>);
+
+    impl Visit for MessageVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.0.lock().unwrap().push(format!("{:?}", value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_handshake_events() {
+        let recorder = Recorder::default();
+        let dispatch = Dispatch::new(recorder.clone());
+        let mut server = dispatcher::with_default(&dispatch, || BiWiUdpServer::new("127.0.0.1", 0).unwrap());
+        let addr = server.local_addr().unwrap();
+
+        let running = Arc::new(Mutex::new(true));
+        let server_running = Arc::clone(&running);
+        let server_dispatch = dispatch.clone();
+        let server_thread = thread::spawn(move || {
+            dispatcher::with_default(&server_dispatch, || {
+                while *server_running.lock().unwrap() {
+                    server.recv_packet();
+                }
+            })
+        });
+        let client = dispatcher::with_default(&dispatch, || BiWiUdpClient::connect(&addr.to_string()).unwrap());
+        *running.lock().unwrap() = false;
+        server_thread.join().unwrap();
+        drop(client);
+
+        let recorded = recorder.0.lock().unwrap().clone();
+        for expected in ["BiWi UDP server listening", "biwi.handshake", "session opened", "BiWi UDP client connected"] {
+            assert!(recorded.iter().any(|r| r == expected), "{expected:?} missing from {recorded:?}");
+        }
+    }
+}
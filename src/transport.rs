@@ -0,0 +1,222 @@
+//! BiWi Transport Abstraction
+//! `BiWiTransport` is the message-level interface shared by the point-to-point
+//! transports (UDP and TCP clients), so higher layers can be written once.
+//! `InProcess` implements it over channels, for tests and for services embedded in
+//! the same process that have no use for a socket.
+
+use crate::message::BiWiMessage;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Message and byte counters for one transport
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// A connection that moves whole messages to and from a single peer
+pub trait BiWiTransport {
+    /// Send a message to the peer
+    fn send_message(&self, message: &BiWiMessage) -> io::Result<()>;
+
+    /// Receive the next message, blocking for at most `timeout` (forever if `None`)
+    fn recv_message(&self, timeout: Option<Duration>) -> io::Result<BiWiMessage>;
+
+    /// Address of the peer
+    fn peer(&self) -> SocketAddr;
+
+    /// Counters since the transport was created
+    fn stats(&self) -> TransportStats;
+}
+
+/// Lock-free counters behind `TransportStats`
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl StatsCounters {
+    pub(crate) fn record_sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self, bytes: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> TransportStats {
+        TransportStats {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// One end of an in-process connection. Messages cross as encoded bytes, exactly as
+/// they would over a socket, and arrive in order; nothing is lost.
+pub struct InProcess {
+    tx: Option<Sender<Vec<u8>>>,
+    rx: Mutex<Receiver<Vec<u8>>>,
+    peer: SocketAddr,
+    stats: StatsCounters,
+}
+
+impl InProcess {
+    /// Two connected ends. Their `peer` addresses are placeholders on the
+    /// unspecified address, ports 1 and 2.
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let end = |tx, rx, peer_port| Self {
+            tx: Some(tx),
+            rx: Mutex::new(rx),
+            peer: SocketAddr::from((Ipv4Addr::UNSPECIFIED, peer_port)),
+            stats: StatsCounters::default(),
+        };
+        (end(a_tx, a_rx, 2), end(b_tx, b_rx, 1))
+    }
+
+    /// Send a message; fails once either end has disconnected
+    pub fn send(&self, message: &BiWiMessage) -> io::Result<()> {
+        let msg_bytes = message.to_vec();
+        let len = msg_bytes.len();
+        self.tx
+            .as_ref()
+            .ok_or_else(closed)?
+            .send(msg_bytes)
+            .map_err(|_| closed())?;
+        self.stats.record_sent(len);
+        Ok(())
+    }
+
+    /// Try to receive a message (non-blocking)
+    pub fn try_recv(&self) -> Option<BiWiMessage> {
+        let msg_bytes = self.rx.lock().unwrap().try_recv().ok()?;
+        self.decode(msg_bytes).ok()
+    }
+
+    /// Receive a message (blocking); fails once the other end has disconnected and
+    /// everything it sent has been read
+    pub fn recv(&self) -> io::Result<BiWiMessage> {
+        let msg_bytes = self.rx.lock().unwrap().recv().map_err(|_| closed())?;
+        self.decode(msg_bytes)
+    }
+
+    /// Receive with timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<BiWiMessage> {
+        let msg_bytes = self.rx.lock().unwrap().recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "Recv timeout"),
+            RecvTimeoutError::Disconnected => closed(),
+        })?;
+        self.decode(msg_bytes)
+    }
+
+    fn decode(&self, msg_bytes: Vec<u8>) -> io::Result<BiWiMessage> {
+        self.stats.record_received(msg_bytes.len());
+        BiWiMessage::from_buffer(&msg_bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Close this end; the other end's `recv` fails once it has read what was sent
+    pub fn disconnect(&mut self) {
+        self.tx = None;
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "Connection closed")
+}
+
+impl BiWiTransport for InProcess {
+    fn send_message(&self, message: &BiWiMessage) -> io::Result<()> {
+        self.send(message)
+    }
+
+    fn recv_message(&self, timeout: Option<Duration>) -> io::Result<BiWiMessage> {
+        match timeout {
+            Some(timeout) => self.recv_timeout(timeout),
+            None => self.recv(),
+        }
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.stats.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::{BiWiTcpClient, BiWiTcpServer, TcpEvent};
+
+    /// Generic code only sees the trait
+    fn echo_once(transport: &impl BiWiTransport, message: &BiWiMessage) -> io::Result<BiWiMessage> {
+        transport.send_message(message)?;
+        transport.recv_message(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_tcp_client_as_transport() {
+        let server = BiWiTcpServer::bind("127.0.0.1:0").unwrap();
+        let client = BiWiTcpClient::connect(&server.local_addr().to_string()).unwrap();
+        assert_eq!(client.peer(), server.local_addr());
+
+        let echo = std::thread::spawn(move || {
+            while let Some(event) = server.recv_timeout(Duration::from_secs(5)) {
+                if let TcpEvent::Message(id, msg) = event {
+                    server.send_to(&id, &msg).unwrap();
+                    return;
+                }
+            }
+        });
+
+        let msg = BiWiMessage::builder().field(1, "over any transport").build();
+        assert_eq!(echo_once(&client, &msg).unwrap(), msg);
+        echo.join().unwrap();
+
+        let stats = client.stats();
+        let size = msg.to_vec().len() as u64;
+        assert_eq!(stats.messages_sent, 1);
+        assert_eq!(stats.messages_received, 1);
+        assert_eq!(stats.bytes_sent, size);
+        assert_eq!(stats.bytes_received, size);
+    }
+
+    #[test]
+    fn test_in_process_pair() {
+        let (client, mut server) = InProcess::pair();
+        let echo = std::thread::spawn(move || {
+            let msg = server.recv().unwrap();
+            server.send(&msg).unwrap();
+            server.disconnect();
+            server
+        });
+
+        let msg = BiWiMessage::builder().field(1, "no sockets").build();
+        assert_eq!(echo_once(&client, &msg).unwrap(), msg);
+        let server = echo.join().unwrap();
+        let size = msg.to_vec().len() as u64;
+        assert_eq!(client.stats().bytes_sent, size);
+        assert_eq!(server.stats().messages_received, 1);
+        assert_eq!(client.recv().unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        drop(server);
+        assert!(client.send(&msg).is_err());
+    }
+}
@@ -18,6 +18,18 @@ pub enum BiWiType {
     ChunkStart = 0x0A,
     ChunkData = 0x0B,
     ChunkEnd = 0x0C,
+    /// Chunk start carrying a transfer ID, so the transfer can be resumed
+    ChunkTransferStart = 0x0D,
+    /// Receiver -> sender: continue a transfer from a chunk index
+    ChunkResume = 0x0E,
+    /// Chunk data followed by a CRC-32 of its data
+    ChunkDataChecked = 0x0F,
+    /// Chunk end followed by a SHA-256 of the whole payload
+    ChunkEndHashed = 0x10,
+    /// Field value taken from an entry of the session's field table
+    FieldRef = 0x11,
+    /// Field value that also becomes an entry of the session's field table
+    FieldInsert = 0x12,
 }
 
 impl BiWiType {
@@ -37,6 +49,12 @@ impl BiWiType {
             0x0A => Some(BiWiType::ChunkStart),
             0x0B => Some(BiWiType::ChunkData),
             0x0C => Some(BiWiType::ChunkEnd),
+            0x0D => Some(BiWiType::ChunkTransferStart),
+            0x0E => Some(BiWiType::ChunkResume),
+            0x0F => Some(BiWiType::ChunkDataChecked),
+            0x10 => Some(BiWiType::ChunkEndHashed),
+            0x11 => Some(BiWiType::FieldRef),
+            0x12 => Some(BiWiType::FieldInsert),
             _ => None,
         }
     }
@@ -57,6 +75,12 @@ impl BiWiType {
             BiWiType::ChunkStart => "CHUNK_START",
             BiWiType::ChunkData => "CHUNK_DATA",
             BiWiType::ChunkEnd => "CHUNK_END",
+            BiWiType::ChunkTransferStart => "CHUNK_TRANSFER_START",
+            BiWiType::ChunkResume => "CHUNK_RESUME",
+            BiWiType::ChunkDataChecked => "CHUNK_DATA_CHECKED",
+            BiWiType::ChunkEndHashed => "CHUNK_END_HASHED",
+            BiWiType::FieldRef => "FIELD_REF",
+            BiWiType::FieldInsert => "FIELD_INSERT",
         }
     }
 
@@ -85,7 +109,13 @@ impl BiWiType {
     pub fn is_streaming_type(&self) -> bool {
         matches!(
             self,
-            BiWiType::ChunkStart | BiWiType::ChunkData | BiWiType::ChunkEnd
+            BiWiType::ChunkStart
+                | BiWiType::ChunkData
+                | BiWiType::ChunkEnd
+                | BiWiType::ChunkTransferStart
+                | BiWiType::ChunkResume
+                | BiWiType::ChunkDataChecked
+                | BiWiType::ChunkEndHashed
         )
     }
 
@@ -108,3 +138,24 @@ impl From<BiWiType> for u8 {
         t as u8
     }
 }
+
+/// Byte order used for fixed-width values (floats and chunk headers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Network byte order, the BiWi default
+    #[default]
+    BigEndian,
+    /// Little-endian, matching the in-memory layout of x86/ARM targets
+    LittleEndian,
+}
+
+impl ByteOrder {
+    /// Byte order of the platform this crate was compiled for
+    pub fn native() -> Self {
+        if cfg!(target_endian = "little") {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        }
+    }
+}
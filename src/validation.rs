@@ -0,0 +1,271 @@
+//! BiWi Message Validation
+//! A lightweight schema (`MessageSpec`) describing required fields, expected
+//! types, numeric ranges and length limits. `BiWiMessage::validate` checks a
+//! message against it and reports every violation, not just the first.
+
+use crate::encoder::BiWiValue;
+use crate::message::{BiWiMessage, FieldFlags};
+use std::collections::BTreeMap;
+
+/// Broad value categories a field may be constrained to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Null,
+    Boolean,
+    /// Int32 or Int64
+    Integer,
+    /// Float32 or Float64
+    Float,
+    /// Any integer or float (`BiWiValue::number` may pick either)
+    Number,
+    /// SmallString or String
+    String,
+    Binary,
+    Array,
+    Object,
+}
+
+impl ValueKind {
+    /// Check whether `value` belongs to this category
+    pub fn matches(self, value: &BiWiValue) -> bool {
+        match (self, value) {
+            (ValueKind::Null, BiWiValue::Null) => true,
+            (ValueKind::Boolean, BiWiValue::Boolean(_)) => true,
+            (ValueKind::Integer, BiWiValue::Int32(_) | BiWiValue::Int64(_)) => true,
+            (ValueKind::Float, BiWiValue::Float32(_) | BiWiValue::Float64(_)) => true,
+            (ValueKind::Number, v) => v.as_f64().is_some(),
+            (ValueKind::String, BiWiValue::SmallString(_) | BiWiValue::String(_)) => true,
+            (ValueKind::Binary, BiWiValue::Binary(_)) => true,
+            #[cfg(feature = "bytes")]
+            (ValueKind::Binary, BiWiValue::Bytes(_)) => true,
+            (
+                ValueKind::Array,
+                BiWiValue::Array(_)
+                | BiWiValue::Int32Array(_)
+                | BiWiValue::Int64Array(_)
+                | BiWiValue::Float32Array(_)
+                | BiWiValue::Float64Array(_),
+            ) => true,
+            (ValueKind::Object, BiWiValue::Object(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Constraints on a single field
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldSpec {
+    pub required: bool,
+    pub kind: Option<ValueKind>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Byte length for strings and binary, element count for arrays and objects
+    pub max_len: Option<usize>,
+    /// Metadata such as `FieldFlags::SENSITIVE`, applied with `BiWiMessage::apply_field_flags`
+    pub flags: FieldFlags,
+}
+
+/// A rule that a message failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    Missing(u32),
+    WrongType { field_id: u32, expected: ValueKind },
+    OutOfRange { field_id: u32, value: f64 },
+    TooLong { field_id: u32, len: usize, max: usize },
+    Unknown(u32),
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Missing(id) => write!(f, "field {} is required", id),
+            Violation::WrongType { field_id, expected } => {
+                write!(f, "field {} should be {:?}", field_id, expected)
+            }
+            Violation::OutOfRange { field_id, value } => {
+                write!(f, "field {} value {} is out of range", field_id, value)
+            }
+            Violation::TooLong { field_id, len, max } => {
+                write!(f, "field {} has length {} (max {})", field_id, len, max)
+            }
+            Violation::Unknown(id) => write!(f, "field {} is not allowed", id),
+        }
+    }
+}
+
+/// Expected shape of a message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageSpec {
+    fields: BTreeMap<u32, FieldSpec>,
+    deny_unknown: bool,
+}
+
+impl MessageSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require a field of the given kind
+    pub fn required(mut self, field_id: u32, kind: ValueKind) -> Self {
+        let spec = self.fields.entry(field_id).or_default();
+        spec.required = true;
+        spec.kind = Some(kind);
+        self
+    }
+
+    /// Allow an optional field, checking its kind when present
+    pub fn optional(mut self, field_id: u32, kind: ValueKind) -> Self {
+        self.fields.entry(field_id).or_default().kind = Some(kind);
+        self
+    }
+
+    /// Constrain a numeric field to `min..=max`
+    pub fn range(mut self, field_id: u32, min: f64, max: f64) -> Self {
+        let spec = self.fields.entry(field_id).or_default();
+        spec.min = Some(min);
+        spec.max = Some(max);
+        self
+    }
+
+    /// Limit the length of a string, binary, array or object field
+    pub fn max_len(mut self, field_id: u32, max_len: usize) -> Self {
+        self.fields.entry(field_id).or_default().max_len = Some(max_len);
+        self
+    }
+
+    /// Mark a field as sensitive (redacted from `Debug` output)
+    pub fn sensitive(mut self, field_id: u32) -> Self {
+        self.fields.entry(field_id).or_default().flags |= FieldFlags::SENSITIVE;
+        self
+    }
+
+    /// Mark a field as deprecated
+    pub fn deprecated(mut self, field_id: u32) -> Self {
+        self.fields.entry(field_id).or_default().flags |= FieldFlags::DEPRECATED;
+        self
+    }
+
+    /// Fields that carry metadata flags
+    pub fn field_flags(&self) -> impl Iterator<Item = (u32, FieldFlags)> + '_ {
+        self.fields
+            .iter()
+            .filter(|(_, spec)| !spec.flags.is_empty())
+            .map(|(id, spec)| (*id, spec.flags))
+    }
+
+    /// Reject fields the spec does not mention
+    pub fn deny_unknown(mut self) -> Self {
+        self.deny_unknown = true;
+        self
+    }
+
+    /// Set the full constraints for one field
+    pub fn field(mut self, field_id: u32, spec: FieldSpec) -> Self {
+        self.fields.insert(field_id, spec);
+        self
+    }
+
+    /// Check `message`, collecting every violation
+    pub fn check(&self, message: &BiWiMessage) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        for (&field_id, spec) in &self.fields {
+            match message.get_field(field_id) {
+                None if spec.required => violations.push(Violation::Missing(field_id)),
+                None => {}
+                Some(value) => Self::check_field(field_id, spec, value, &mut violations),
+            }
+        }
+        if self.deny_unknown {
+            for (field_id, _) in message.iter() {
+                if !self.fields.contains_key(&field_id) {
+                    violations.push(Violation::Unknown(field_id));
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn check_field(field_id: u32, spec: &FieldSpec, value: &BiWiValue, violations: &mut Vec<Violation>) {
+        if let Some(kind) = spec.kind {
+            if !kind.matches(value) {
+                violations.push(Violation::WrongType { field_id, expected: kind });
+                return;
+            }
+        }
+
+        if spec.min.is_some() || spec.max.is_some() {
+            match value.as_f64() {
+                Some(n) if spec.min.is_some_and(|min| n < min) || spec.max.is_some_and(|max| n > max) => {
+                    violations.push(Violation::OutOfRange { field_id, value: n });
+                }
+                // NaN compares false against everything, so reject it explicitly
+                Some(n) if n.is_nan() => violations.push(Violation::OutOfRange { field_id, value: n }),
+                Some(_) => {}
+                None => violations.push(Violation::WrongType {
+                    field_id,
+                    expected: ValueKind::Number,
+                }),
+            }
+        }
+
+        if let Some(max) = spec.max_len {
+            let len = match value {
+                BiWiValue::SmallString(s) => Some(s.as_bytes().len()),
+                BiWiValue::String(s) => Some(s.len()),
+                BiWiValue::Binary(b) => Some(b.len()),
+                #[cfg(feature = "bytes")]
+                BiWiValue::Bytes(b) => Some(b.len()),
+                BiWiValue::Array(items) => Some(items.len()),
+                BiWiValue::Int32Array(items) => Some(items.len()),
+                BiWiValue::Int64Array(items) => Some(items.len()),
+                BiWiValue::Float32Array(items) => Some(items.len()),
+                BiWiValue::Float64Array(items) => Some(items.len()),
+                BiWiValue::Object(map) => Some(map.len()),
+                _ => None,
+            };
+            if let Some(len) = len.filter(|len| *len > max) {
+                violations.push(Violation::TooLong { field_id, len, max });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_reports_all_violations() {
+        let spec = MessageSpec::new()
+            .required(1, ValueKind::String)
+            .max_len(1, 8)
+            .required(2, ValueKind::Number)
+            .range(2, 0.0, 100.0)
+            .required(3, ValueKind::Boolean)
+            .optional(4, ValueKind::Array)
+            .deny_unknown();
+
+        let good = crate::biwi_msg! { 1 => "alice", 2 => 42.5, 3 => true };
+        assert_eq!(good.validate(&spec), Ok(()));
+
+        let bad = crate::biwi_msg! { 1 => "a very long player name", 2 => 101, 4 => "x", 9 => 0 };
+        let violations = bad.validate(&spec).unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                Violation::TooLong { field_id: 1, len: 23, max: 8 },
+                Violation::OutOfRange { field_id: 2, value: 101.0 },
+                Violation::Missing(3),
+                Violation::WrongType { field_id: 4, expected: ValueKind::Array },
+                Violation::Unknown(9),
+            ]
+        );
+        assert_eq!(violations[2].to_string(), "field 3 is required");
+    }
+}
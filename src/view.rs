@@ -0,0 +1,194 @@
+// BiWi Message View
+// Read-only access to an encoded message without decoding it up front.
+// Field offsets are indexed on first access; values are decoded on demand
+// straight from the borrowed buffer.
+
+use crate::decoder::{BiWiDecoder, DecodeError, DecodeResult};
+use crate::encoder::BiWiValue;
+use crate::envelope::Envelope;
+use crate::message::BiWiMessage;
+use crate::reader::Reader;
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Zero-copy view over a buffer produced by `BiWiMessage::to_vec`
+pub struct BiWiMessageView<'a> {
+    buffer: &'a [u8],
+    index: OnceCell<DecodeResult<Index>>,
+}
+
+struct Index {
+    envelope: Option<Envelope>,
+    /// Field ID -> byte range of its encoded value (type byte onwards)
+    fields: BTreeMap<u32, Range<usize>>,
+}
+
+impl<'a> BiWiMessageView<'a> {
+    /// Wrap an encoded message; nothing is read until the first access
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            index: OnceCell::new(),
+        }
+    }
+
+    /// The underlying encoded buffer
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    /// Walk the buffer once, recording where each field's value lives
+    fn index(&self) -> DecodeResult<&Index> {
+        self.index
+            .get_or_init(|| {
+                let mut decoder = BiWiDecoder::new(self.buffer);
+                let envelope = decoder.decode_envelope()?;
+                let mut fields = BTreeMap::new();
+                while decoder.has_more() {
+                    let field_id = decoder.read_field_id()?;
+                    let start = decoder.offset();
+                    decoder.skip_value()?;
+                    // Later duplicates win, matching `BiWiMessage::from_buffer`
+                    fields.insert(field_id, start..decoder.offset());
+                }
+                Ok(Index { envelope, fields })
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Check the whole buffer is well-formed (structure only)
+    pub fn validate(&self) -> DecodeResult<()> {
+        self.index().map(|_| ())
+    }
+
+    /// The message envelope, if any
+    pub fn envelope(&self) -> DecodeResult<Option<Envelope>> {
+        Ok(self.index()?.envelope)
+    }
+
+    /// Encoded bytes of a field's value, starting at its type byte
+    pub fn raw_field(&self, field_id: u32) -> DecodeResult<Option<&'a [u8]>> {
+        let buffer = self.buffer;
+        Ok(self.index()?.fields.get(&field_id).and_then(|range| buffer.get(range.clone())))
+    }
+
+    /// Decode a single field
+    pub fn get_field(&self, field_id: u32) -> DecodeResult<Option<BiWiValue>> {
+        match self.raw_field(field_id)? {
+            Some(raw) => BiWiDecoder::new(raw).decode_value().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Borrow a string field straight from the buffer
+    pub fn get_str(&self, field_id: u32) -> DecodeResult<Option<&'a str>> {
+        let Some(raw) = self.raw_field(field_id)? else {
+            return Ok(None);
+        };
+        let mut reader = Reader::new(raw);
+        let length = match reader.read_u8("type byte")? {
+            0x86 => reader.read_u8("small string length")? as usize,
+            0x06 => reader.read_varint_u32("string length")? as usize,
+            _ => return Ok(None),
+        };
+        let bytes = reader.read_bytes(length, "string content")?;
+        std::str::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| DecodeError::InvalidData("invalid UTF-8"))
+    }
+
+    /// Borrow a binary field straight from the buffer
+    pub fn get_bytes(&self, field_id: u32) -> DecodeResult<Option<&'a [u8]>> {
+        let Some(raw) = self.raw_field(field_id)? else {
+            return Ok(None);
+        };
+        let mut reader = Reader::new(raw);
+        if reader.read_u8("type byte")? != 0x07 {
+            return Ok(None);
+        }
+        let length = reader.read_varint_u32("binary length")? as usize;
+        reader.read_bytes(length, "binary content").map(Some)
+    }
+
+    /// Check if field exists
+    pub fn has_field(&self, field_id: u32) -> DecodeResult<bool> {
+        Ok(self.index()?.fields.contains_key(&field_id))
+    }
+
+    /// Get all field IDs in ascending order
+    pub fn field_ids(&self) -> DecodeResult<Vec<u32>> {
+        Ok(self.index()?.fields.keys().copied().collect())
+    }
+
+    /// Get the number of fields
+    pub fn field_count(&self) -> DecodeResult<usize> {
+        Ok(self.index()?.fields.len())
+    }
+
+    /// Decode every field into an owned message
+    pub fn to_message(&self) -> DecodeResult<BiWiMessage> {
+        BiWiMessage::from_buffer(self.buffer)
+    }
+}
+
+impl std::fmt::Debug for BiWiMessageView<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BiWiMessageView")
+            .field("len", &self.buffer.len())
+            .field("indexed", &self.index.get().is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view_matches_decoded_message() {
+        let msg = crate::biwi_msg! {
+            1 => "short",
+            2 => "a string that does not fit inline",
+            3 => vec![0u8, 1, 2],
+            4 => [1.5, 2.5],
+            40 => 7,
+            1000 => crate::biwi_msg! { 1 => true }.to_vec(),
+        };
+        let buffer = msg.to_vec();
+        let view = BiWiMessageView::new(&buffer);
+
+        assert_eq!(view.field_ids().unwrap(), msg.field_ids());
+        for (field_id, value) in msg.iter() {
+            assert_eq!(view.get_field(field_id).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(view.get_str(1).unwrap(), Some("short"));
+        assert_eq!(view.get_str(2).unwrap(), Some("a string that does not fit inline"));
+        assert_eq!(view.get_str(40).unwrap(), None);
+        assert_eq!(view.get_bytes(3).unwrap(), Some(&[0u8, 1, 2][..]));
+        assert_eq!(view.get_field(99).unwrap(), None);
+        assert_eq!(view.to_message().unwrap(), msg);
+        assert_eq!(view.envelope().unwrap(), None);
+
+        let enveloped = msg.clone().with_type(3).to_vec();
+        let view = BiWiMessageView::new(&enveloped);
+        assert_eq!(view.envelope().unwrap(), Some(Envelope::new(3)));
+        assert_eq!(view.get_str(1).unwrap(), Some("short"));
+    }
+
+    #[test]
+    fn test_view_reports_malformed_buffers() {
+        let buffer = crate::biwi_msg! { 1 => "hello", 2 => 42 }.to_vec();
+        let truncated = &buffer[..buffer.len() - 1];
+        let view = BiWiMessageView::new(truncated);
+        assert!(view.validate().is_err());
+        assert!(view.get_field(1).is_err());
+
+        // Structure is fine but the string is not UTF-8
+        let bad_utf8 = [0x04, 0x86, 0x02, 0xff, 0xfe];
+        let view = BiWiMessageView::new(&bad_utf8);
+        assert!(view.validate().is_ok());
+        assert!(view.get_str(1).is_err());
+    }
+}
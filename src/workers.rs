@@ -0,0 +1,174 @@
+//! BiWi Server Worker Pools
+//! Spreads a UDP server's sessions over worker threads. One dispatcher thread reads
+//! the socket and hands each datagram to the worker owning its session, picked from
+//! the session ID (or, for a handshake, the source address); each worker is a
+//! `BiWiUdpServer` with its own share of the connections, running its own copy of
+//! the handler. Workers issue session IDs from their own shard of the ID space, so a
+//! session's packets always land on the worker that created it.
+
+use crate::handler::{BiWiServerHandler, StopHandle};
+use crate::network::{PacketType, WirePacket};
+use crate::server::{BiWiUdpServer, Shard};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Inbox = Sender<(Vec<u8>, SocketAddr)>;
+
+/// A server running on worker threads; stops when dropped
+pub struct WorkerPool {
+    local_addr: SocketAddr,
+    running: Arc<Mutex<bool>>,
+    workers: Vec<(StopHandle, JoinHandle<()>)>,
+    dispatcher: Option<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn start<H>(server: BiWiUdpServer, count: usize, handler: H) -> io::Result<Self>
+    where
+        H: BiWiServerHandler + Clone + Send + 'static,
+    {
+        if count == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A worker pool needs at least one worker"));
+        }
+        let local_addr = server.socket.local_addr()?;
+
+        // Sessions already open go to the worker that will receive their packets
+        let mut shares: Vec<HashMap<_, _>> = (0..count).map(|_| HashMap::new()).collect();
+        for (id, conn) in server.connections.lock().unwrap().drain() {
+            shares[Shard::index_of(conn.session_id, count as u64) as usize].insert(id, conn);
+        }
+
+        let mut inboxes = Vec::with_capacity(count);
+        let mut workers = Vec::with_capacity(count);
+        for (index, share) in shares.into_iter().enumerate() {
+            let (tx, rx) = channel();
+            let shard = Shard { index: index as u64, count: count as u64 };
+            let mut worker = server.worker(shard, rx, share)?;
+            let stop = worker.stop_handle();
+            let handler = handler.clone();
+            inboxes.push(tx);
+            workers.push((stop, thread::spawn(move || worker.run(handler))));
+        }
+
+        let running = Arc::new(Mutex::new(true));
+        let dispatcher = {
+            let running = Arc::clone(&running);
+            let socket = server.socket;
+            thread::spawn(move || dispatch(socket, inboxes, running))
+        };
+
+        Ok(Self {
+            local_addr,
+            running,
+            workers,
+            dispatcher: Some(dispatcher),
+        })
+    }
+
+    /// Address the server is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Stop the dispatcher and every worker, waiting for their threads to finish
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Workers first, so none is left waiting on a closed inbox
+        for (stop, worker) in self.workers.drain(..) {
+            stop.stop();
+            let _ = worker.join();
+        }
+        *self.running.lock().unwrap() = false;
+        if let Some(dispatcher) = self.dispatcher.take() {
+            let _ = dispatcher.join();
+        }
+    }
+}
+
+/// Read the socket and route each datagram to its worker's inbox
+fn dispatch(socket: UdpSocket, inboxes: Vec<Inbox>, running: Arc<Mutex<bool>>) {
+    let mut buf = vec![0u8; 65536];
+    while *running.lock().unwrap() {
+        // Timeouts just recheck `running`
+        let Ok((n, addr)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        let worker = route(&buf[..n], addr, inboxes.len());
+        let _ = inboxes[worker].send((buf[..n].to_vec(), addr));
+    }
+}
+
+/// Index of the worker that owns a datagram's session. Handshakes carry no session
+/// tag, so they go by source address, which keeps a client's retried `Connect`s on
+/// the worker that answered the first one.
+fn route(data: &[u8], addr: SocketAddr, count: usize) -> usize {
+    let session_id = WirePacket::parse(data).ok().and_then(|WirePacket { packet, .. }| match packet.packet_type {
+        PacketType::Disconnect => packet.session_id(),
+        _ => packet.session_tag(),
+    });
+    match session_id {
+        Some(session_id) => Shard::index_of(session_id, count as u64) as usize,
+        None => {
+            let mut hasher = DefaultHasher::new();
+            addr.hash(&mut hasher);
+            (hasher.finish() % count as u64) as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::BiWiUdpClient;
+    use crate::encoder::BiWiValue;
+    use crate::handler::Connection;
+    use crate::message::BiWiMessage;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl BiWiServerHandler for Echo {
+        fn on_message(&mut self, conn: &Connection<'_>, message: BiWiMessage) {
+            conn.reply(&message).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_workers_share_sessions() {
+        let server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        let pool = server.spawn_workers(3, Echo).unwrap();
+        assert_eq!(pool.worker_count(), 3);
+
+        let clients: Vec<BiWiUdpClient> =
+            (0..6).map(|_| BiWiUdpClient::connect(&pool.local_addr().to_string()).unwrap()).collect();
+        for (i, client) in clients.iter().enumerate() {
+            let mut msg = BiWiMessage::new();
+            msg.set_field(1, BiWiValue::Int32(i as i32));
+            client.send(&msg).unwrap();
+            assert_eq!(client.recv_timeout(Duration::from_secs(5)).unwrap(), msg);
+        }
+        pool.stop();
+    }
+
+    #[test]
+    fn test_worker_pool_needs_a_worker() {
+        let server = BiWiUdpServer::new("127.0.0.1", 0).unwrap();
+        assert_eq!(server.spawn_workers(0, Echo).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+}